@@ -137,12 +137,21 @@ fn accounts_create<'a, 'b>() -> App<'a, 'b> {
             Arg::with_name("round_trip_time")
                 .long("round-trip-time")
                 .takes_value(true),
+            Arg::with_name("min_expiry_window")
+                .long("min-expiry-window")
+                .takes_value(true),
             Arg::with_name("amount_per_minute_limit")
                 .long("amount-per-minute-limit")
                 .takes_value(true),
             Arg::with_name("packets_per_minute_limit")
                 .long("packets-per-minute-limit")
                 .takes_value(true),
+            Arg::with_name("packets_per_second_limit")
+                .long("packets-per-second-limit")
+                .takes_value(true),
+            Arg::with_name("packet_burst_limit")
+                .long("packet-burst-limit")
+                .takes_value(true),
             Arg::with_name("settlement_engine_url")
                 .long("settlement-engine-url")
                 .takes_value(true),
@@ -208,12 +217,21 @@ fn accounts_update<'a, 'b>() -> App<'a, 'b> {
             Arg::with_name("round_trip_time")
                 .long("round-trip-time")
                 .takes_value(true),
+            Arg::with_name("min_expiry_window")
+                .long("min-expiry-window")
+                .takes_value(true),
             Arg::with_name("amount_per_minute_limit")
                 .long("amount-per-minute-limit")
                 .takes_value(true),
             Arg::with_name("packets_per_minute_limit")
                 .long("packets-per-minute-limit")
                 .takes_value(true),
+            Arg::with_name("packets_per_second_limit")
+                .long("packets-per-second-limit")
+                .takes_value(true),
+            Arg::with_name("packet_burst_limit")
+                .long("packet-burst-limit")
+                .takes_value(true),
             Arg::with_name("settlement_engine_url")
                 .long("settlement-engine-url")
                 .takes_value(true),
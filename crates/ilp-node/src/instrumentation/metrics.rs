@@ -4,7 +4,7 @@ use interledger::{
         Account, IlpResult, IncomingRequest, IncomingService, OutgoingRequest, OutgoingService,
     },
 };
-use metrics::{self, labels, recorder, Key};
+use metrics::{self, labels, recorder, Key, Label};
 use std::time::Instant;
 
 pub async fn incoming_metrics<A: Account + CcpRoutingAccount>(
@@ -12,6 +12,7 @@ pub async fn incoming_metrics<A: Account + CcpRoutingAccount>(
     mut next: Box<dyn IncomingService<A> + Send>,
 ) -> IlpResult {
     let labels = labels!(
+        "from_account_id" => request.from.id().to_string(),
         "from_asset_code" => request.from.asset_code().to_string(),
         "from_routing_relation" => request.from.routing_relation().to_string(),
     );
@@ -22,17 +23,7 @@ pub async fn incoming_metrics<A: Account + CcpRoutingAccount>(
     let start_time = Instant::now();
 
     let result = next.handle_request(request).await;
-    if result.is_ok() {
-        recorder().increment_counter(
-            Key::from_name_and_labels("requests.incoming.fulfill", labels.clone()),
-            1,
-        );
-    } else {
-        recorder().increment_counter(
-            Key::from_name_and_labels("requests.incoming.reject", labels.clone()),
-            1,
-        );
-    }
+    record_result("requests.incoming", &result, labels.clone());
 
     recorder().record_histogram(
         Key::from_name_and_labels("requests.incoming.duration", labels),
@@ -46,6 +37,8 @@ pub async fn outgoing_metrics<A: Account + CcpRoutingAccount>(
     mut next: Box<dyn OutgoingService<A> + Send>,
 ) -> IlpResult {
     let labels = labels!(
+        "from_account_id" => request.from.id().to_string(),
+        "to_account_id" => request.to.id().to_string(),
         "from_asset_code" => request.from.asset_code().to_string(),
         "to_asset_code" => request.to.asset_code().to_string(),
         "from_routing_relation" => request.from.routing_relation().to_string(),
@@ -61,17 +54,7 @@ pub async fn outgoing_metrics<A: Account + CcpRoutingAccount>(
     let start_time = Instant::now();
 
     let result = next.send_request(request).await;
-    if result.is_ok() {
-        recorder().increment_counter(
-            Key::from_name_and_labels("requests.outgoing.fulfill", labels.clone()),
-            1,
-        );
-    } else {
-        recorder().increment_counter(
-            Key::from_name_and_labels("requests.outgoing.reject", labels.clone()),
-            1,
-        );
-    }
+    record_result("requests.outgoing", &result, labels.clone());
 
     recorder().record_histogram(
         Key::from_name_and_labels("requests.outgoing.duration", labels),
@@ -80,3 +63,198 @@ pub async fn outgoing_metrics<A: Account + CcpRoutingAccount>(
 
     result
 }
+
+/// Counts the fulfill or reject, labelling rejects with their error code
+fn record_result(prefix: &str, result: &IlpResult, mut labels: Vec<Label>) {
+    match result {
+        Ok(_) => recorder().increment_counter(
+            Key::from_name_and_labels(format!("{}.fulfill", prefix), labels),
+            1,
+        ),
+        Err(reject) => {
+            labels.push(Label::new("error_code", reject.code().to_string()));
+            recorder().increment_counter(
+                Key::from_name_and_labels(format!("{}.reject", prefix), labels),
+                1,
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use interledger::{
+        ccp::RoutingRelation,
+        packet::{Address, ErrorCode, FulfillBuilder, PrepareBuilder, RejectBuilder},
+        service::{incoming_service_fn, outgoing_service_fn, Username},
+    };
+    use metrics_core::{Builder, Drain, Observe};
+    use metrics_runtime::{observers::PrometheusBuilder, Controller, Receiver};
+    use once_cell::sync::Lazy;
+    use std::str::FromStr;
+    use std::time::{Duration, SystemTime};
+    use uuid::Uuid;
+
+    // The recorder is global, so all tests share it and use their own account ids.
+    // Only the counters are checked: the histograms' windows follow the clock of the
+    // receiver's upkeep thread, so their samples depend on the timing of the test
+    static CONTROLLER: Lazy<Controller> = Lazy::new(|| {
+        let receiver = Receiver::builder().build().unwrap();
+        let controller = receiver.controller();
+        metrics::set_boxed_recorder(Box::new(receiver)).unwrap();
+        controller
+    });
+
+    static USERNAME: Lazy<Username> = Lazy::new(|| Username::from_str("alice").unwrap());
+    static ADDRESS: Lazy<Address> = Lazy::new(|| Address::from_str("example.alice").unwrap());
+
+    #[derive(Clone, Debug)]
+    struct TestAccount(Uuid);
+
+    impl Account for TestAccount {
+        fn id(&self) -> Uuid {
+            self.0
+        }
+
+        fn username(&self) -> &Username {
+            &USERNAME
+        }
+
+        fn asset_code(&self) -> &str {
+            "XYZ"
+        }
+
+        fn asset_scale(&self) -> u8 {
+            9
+        }
+
+        fn ilp_address(&self) -> &Address {
+            &ADDRESS
+        }
+    }
+
+    impl CcpRoutingAccount for TestAccount {
+        fn routing_relation(&self) -> RoutingRelation {
+            RoutingRelation::Peer
+        }
+    }
+
+    fn prepare() -> interledger::packet::Prepare {
+        PrepareBuilder {
+            destination: Address::from_str("example.destination").unwrap(),
+            amount: 100,
+            expires_at: SystemTime::now() + Duration::from_secs(30),
+            execution_condition: &[0; 32],
+            data: &[],
+        }
+        .build()
+    }
+
+    fn result(fulfill: bool) -> IlpResult {
+        if fulfill {
+            Ok(FulfillBuilder {
+                fulfillment: &[0; 32],
+                data: &[],
+            }
+            .build())
+        } else {
+            Err(RejectBuilder {
+                code: ErrorCode::T04_INSUFFICIENT_LIQUIDITY,
+                message: &[],
+                triggered_by: None,
+                data: &[],
+            }
+            .build())
+        }
+    }
+
+    /// Renders the registry in the Prometheus exposition format
+    fn scrape() -> String {
+        let mut observer = PrometheusBuilder::default().build();
+        CONTROLLER.observe(&mut observer);
+        observer.drain()
+    }
+
+    /// Returns the value of the metric whose labels contain `label`
+    fn value(output: &str, name: &str, label: &str) -> Option<u64> {
+        output
+            .lines()
+            .find(|line| line.starts_with(&format!("{}{{", name)) && line.contains(label))
+            .and_then(|line| line.rsplit(' ').next())
+            .map(|value| value.parse().unwrap())
+    }
+
+    #[tokio::test]
+    async fn counts_incoming_packets_by_account() {
+        Lazy::force(&CONTROLLER);
+        let account = TestAccount(Uuid::new_v4());
+        let label = format!("from_account_id=\"{}\"", account.0);
+
+        for fulfill in &[true, true, false] {
+            let fulfill = *fulfill;
+            let next = incoming_service_fn(move |_| result(fulfill));
+            incoming_metrics(
+                IncomingRequest {
+                    from: account.clone(),
+                    prepare: prepare(),
+                },
+                Box::new(next),
+            )
+            .await
+            .ok();
+        }
+
+        let output = scrape();
+        assert_eq!(value(&output, "requests_incoming_prepare", &label), Some(3));
+        assert_eq!(value(&output, "requests_incoming_fulfill", &label), Some(2));
+        assert_eq!(
+            value(
+                &output,
+                "requests_incoming_reject",
+                &format!(
+                    "{},from_asset_code=\"XYZ\",from_routing_relation=\"Peer\",error_code=\"T04\"",
+                    label
+                )
+            ),
+            Some(1)
+        );
+    }
+
+    #[tokio::test]
+    async fn counts_outgoing_packets_by_account() {
+        Lazy::force(&CONTROLLER);
+        let from = TestAccount(Uuid::new_v4());
+        let to = TestAccount(Uuid::new_v4());
+        let label = format!("to_account_id=\"{}\"", to.0);
+
+        for fulfill in &[false, true] {
+            let fulfill = *fulfill;
+            let next = outgoing_service_fn(move |_| result(fulfill));
+            outgoing_metrics(
+                OutgoingRequest {
+                    from: from.clone(),
+                    to: to.clone(),
+                    original_amount: 100,
+                    prepare: prepare(),
+                },
+                Box::new(next),
+            )
+            .await
+            .ok();
+        }
+
+        let output = scrape();
+        assert_eq!(value(&output, "requests_outgoing_prepare", &label), Some(2));
+        assert_eq!(value(&output, "requests_outgoing_fulfill", &label), Some(1));
+        assert_eq!(value(&output, "requests_outgoing_reject", &label), Some(1));
+        assert_eq!(
+            value(
+                &output,
+                "requests_outgoing_prepare",
+                &format!("from_account_id=\"{}\"", from.0)
+            ),
+            Some(2)
+        );
+    }
+}
@@ -5,6 +5,7 @@ use std::{net::SocketAddr, sync::Arc, time::Duration};
 use tracing::{error, info};
 use warp::{
     http::{Response, StatusCode},
+    path::FullPath,
     Filter,
 };
 
@@ -13,6 +14,9 @@ use warp::{
 pub struct PrometheusConfig {
     /// IP address and port to host the Prometheus endpoint on.
     pub bind_address: SocketAddr,
+    /// Path of the Prometheus endpoint, e.g. `metrics`. Defaults to the root path.
+    #[serde(default)]
+    pub path: String,
     /// Amount of time, in milliseconds, that the node will collect data points for the
     /// Prometheus histograms. Defaults to 300000ms (5 minutes).
     #[serde(default = "PrometheusConfig::default_histogram_window")]
@@ -63,19 +67,28 @@ pub async fn serve_prometheus(node: InterledgerNode) -> Result<(), ()> {
         Ok(_) => {
             let observer = Arc::new(metrics_runtime::observers::PrometheusBuilder::default());
 
-            let filter = warp::get().and(warp::path::end()).map(move || {
-                let mut observer = observer.build();
-                controller.observe(&mut observer);
-                let prometheus_response = observer.drain();
-                Response::builder()
-                    .status(StatusCode::OK)
-                    .header("Content-Type", "text/plain; version=0.0.4")
-                    .body(prometheus_response)
-            });
+            let path = prometheus.path.trim_matches('/').to_string();
+            let filter = warp::get()
+                .and(warp::path::full())
+                .map(move |full_path: FullPath| {
+                    if full_path.as_str().trim_matches('/') != path {
+                        return Response::builder()
+                            .status(StatusCode::NOT_FOUND)
+                            .body(String::new());
+                    }
+                    let mut observer = observer.build();
+                    controller.observe(&mut observer);
+                    let prometheus_response = observer.drain();
+                    Response::builder()
+                        .status(StatusCode::OK)
+                        .header("Content-Type", "text/plain; version=0.0.4")
+                        .body(prometheus_response)
+                });
 
             info!(target: "interledger-node",
-                "Prometheus metrics server listening on: {}",
-                prometheus.bind_address
+                "Prometheus metrics server listening on: {}/{}",
+                prometheus.bind_address,
+                prometheus.path.trim_start_matches('/')
             );
 
             tokio::spawn(warp::serve(filter).bind(prometheus.bind_address));
@@ -13,13 +13,17 @@ use uuid::Uuid;
 /// Add tracing context for the incoming request.
 /// This adds minimal information for the ERROR log
 /// level and more information for the DEBUG level.
+///
+/// The packet gets a new trace ID here, and every span opened while
+/// the packet is forwarded (including the rate lookup and the settlement
+/// it triggers) is nested under this one, so they all share that ID.
 pub async fn trace_incoming<A: Account>(
     request: IncomingRequest<A>,
     mut next: Box<dyn IncomingService<A> + Send>,
 ) -> IlpResult {
     let request_span = error_span!(target: "interledger-node",
         "incoming",
-        request.id = %Uuid::new_v4(),
+        trace.id = %Uuid::new_v4(),
         prepare.destination = %request.prepare.destination(),
         prepare.amount = request.prepare.amount(),
         from.id = %request.from.id()
@@ -62,9 +66,9 @@ pub async fn trace_forwarding<A: Account>(
     );
     let details_span = debug_span!(parent: &request_span,
         "",
-        to.username = %request.from.username(),
-        to.asset_code = %request.from.asset_code(),
-        to.asset_scale = %request.from.asset_scale(),
+        to.username = %request.to.username(),
+        to.asset_code = %request.to.asset_code(),
+        to.asset_scale = %request.to.asset_scale(),
     );
 
     let span = if details_span.is_none() {
@@ -79,13 +83,16 @@ pub async fn trace_forwarding<A: Account>(
 /// Add tracing context for the outgoing request (created by this node).
 /// This adds minimal information for the ERROR log
 /// level and more information for the DEBUG level.
+///
+/// Packets created by this node do not pass through `trace_incoming`,
+/// so this is where they get their trace ID.
 pub async fn trace_outgoing<A: Account + CcpRoutingAccount>(
     request: OutgoingRequest<A>,
     mut next: Box<dyn OutgoingService<A> + Send>,
 ) -> IlpResult {
     let request_span = error_span!(target: "interledger-node",
         "outgoing",
-        request.id = %Uuid::new_v4(),
+        trace.id = %Uuid::new_v4(),
         prepare.destination = %request.prepare.destination(),
         from.id = %request.from.id(),
         to.id = %request.to.id(),
@@ -96,9 +103,9 @@ pub async fn trace_outgoing<A: Account + CcpRoutingAccount>(
         from.ilp_address = %request.from.ilp_address(),
         from.asset_code = %request.from.asset_code(),
         from.asset_scale = %request.from.asset_scale(),
-        to.username = %request.from.username(),
-        to.asset_code = %request.from.asset_code(),
-        to.asset_scale = %request.from.asset_scale(),
+        to.username = %request.to.username(),
+        to.asset_code = %request.to.asset_code(),
+        to.asset_scale = %request.to.asset_scale(),
     );
 
     // Don't log anything for failed route updates sent to child accounts
@@ -150,3 +157,200 @@ fn trace_response(result: Result<Fulfill, Reject>) -> Result<Fulfill, Reject> {
 
     result
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use interledger::{
+        api::{AccountDetails, NodeStore},
+        packet::{Address, FulfillBuilder, PrepareBuilder},
+        rates::ExchangeRateStore,
+        router::Router,
+        service::outgoing_service_fn,
+        service_util::{BalanceService, ExchangeRateService},
+        store::memory::InMemoryStore,
+    };
+    use serde::Deserialize;
+    use std::collections::HashMap;
+    use std::str::FromStr;
+    use std::sync::{Arc, Mutex};
+    use std::time::SystemTime;
+    use tracing::{
+        field::{Field, Visit},
+        span::{Attributes, Id},
+        Subscriber,
+    };
+    use tracing_subscriber::{layer::Context, prelude::*, registry::LookupSpan, Layer};
+
+    #[derive(Debug)]
+    struct CapturedSpan {
+        name: &'static str,
+        parent: Option<u64>,
+        fields: HashMap<String, String>,
+    }
+
+    impl Visit for CapturedSpan {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            self.fields
+                .insert(field.name().to_string(), format!("{:?}", value));
+        }
+
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.fields
+                .insert(field.name().to_string(), value.to_string());
+        }
+    }
+
+    /// Records every span with its fields and the ID of its parent
+    #[derive(Clone, Default)]
+    struct CaptureSpans(Arc<Mutex<HashMap<u64, CapturedSpan>>>);
+
+    impl<S> Layer<S> for CaptureSpans
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        fn new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+            let mut span = CapturedSpan {
+                name: attrs.metadata().name(),
+                parent: ctx
+                    .span(id)
+                    .and_then(|span| span.parent())
+                    .map(|parent| parent.id().into_u64()),
+                fields: HashMap::new(),
+            };
+            attrs.record(&mut span);
+            self.0.lock().unwrap().insert(id.into_u64(), span);
+        }
+    }
+
+    impl CaptureSpans {
+        /// Returns the ID of the only span with the given name
+        fn find(&self, name: &str) -> u64 {
+            let spans = self.0.lock().unwrap();
+            let found: Vec<u64> = spans
+                .iter()
+                .filter(|(_, span)| span.name == name)
+                .map(|(id, _)| *id)
+                .collect();
+            assert_eq!(found.len(), 1, "expected one {} span", name);
+            found[0]
+        }
+
+        /// Returns the field of the span or, for the fields only recorded at the
+        /// DEBUG level, of its unnamed child span
+        fn field(&self, id: u64, field: &str) -> Option<String> {
+            let spans = self.0.lock().unwrap();
+            spans[&id].fields.get(field).cloned().or_else(|| {
+                spans
+                    .values()
+                    .find(|span| span.name.is_empty() && span.parent == Some(id))
+                    .and_then(|span| span.fields.get(field).cloned())
+            })
+        }
+
+        /// Returns the names of the span's ancestors, the closest one first
+        fn ancestors(&self, id: u64) -> Vec<&'static str> {
+            let spans = self.0.lock().unwrap();
+            let mut names = Vec::new();
+            let mut parent = spans[&id].parent;
+            while let Some(id) = parent {
+                if !spans[&id].name.is_empty() {
+                    names.push(spans[&id].name);
+                }
+                parent = spans[&id].parent;
+            }
+            names
+        }
+    }
+
+    fn account_details(username: &str, asset_code: &str) -> AccountDetails {
+        AccountDetails::deserialize(&serde_json::json!({
+            "username": username,
+            "asset_code": asset_code,
+            "asset_scale": 9,
+            "routing_relation": "Child",
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn nests_spans_of_a_packet_under_its_incoming_span() {
+        let spans = CaptureSpans::default();
+        let _guard = tracing_subscriber::registry()
+            .with(spans.clone())
+            .set_default();
+
+        let store = InMemoryStore::new(Address::from_str("example.node").unwrap());
+        let alice = store
+            .insert_account(account_details("alice", "ABC"))
+            .await
+            .unwrap();
+        store
+            .insert_account(account_details("bob", "XYZ"))
+            .await
+            .unwrap();
+        store
+            .set_exchange_rates(
+                vec![("ABC".to_string(), 1.0), ("XYZ".to_string(), 2.0)]
+                    .into_iter()
+                    .collect(),
+            )
+            .unwrap();
+
+        let outgoing = outgoing_service_fn(|_| {
+            Ok(FulfillBuilder {
+                fulfillment: &[0; 32],
+                data: &[],
+            }
+            .build())
+        });
+        let outgoing = BalanceService::new(store.clone(), None, outgoing);
+        let outgoing = ExchangeRateService::new(0.0, store.clone(), outgoing);
+        let outgoing = outgoing.wrap(trace_forwarding);
+        let mut incoming = Router::new(store.clone(), outgoing).wrap(trace_incoming);
+
+        incoming
+            .handle_request(IncomingRequest {
+                from: alice.clone(),
+                prepare: PrepareBuilder {
+                    destination: Address::from_str("example.node.bob").unwrap(),
+                    amount: 100,
+                    expires_at: SystemTime::now() + std::time::Duration::from_secs(30),
+                    execution_condition: &[0; 32],
+                    data: &[],
+                }
+                .build(),
+            })
+            .await
+            .unwrap();
+
+        let incoming = spans.find("incoming");
+        let trace_id = spans.field(incoming, "trace.id").unwrap();
+        assert!(Uuid::parse_str(&trace_id).is_ok());
+        assert_eq!(
+            spans.field(incoming, "prepare.destination").unwrap(),
+            "example.node.bob"
+        );
+        assert_eq!(spans.field(incoming, "prepare.amount").unwrap(), "100");
+        assert_eq!(
+            spans.field(incoming, "from.id").unwrap(),
+            alice.id().to_string()
+        );
+        assert_eq!(spans.field(incoming, "from.username").unwrap(), "alice");
+        assert_eq!(spans.ancestors(incoming), Vec::<&str>::new());
+
+        let forwarding = spans.find("forwarding");
+        assert_eq!(spans.field(forwarding, "to.username").unwrap(), "bob");
+        assert_eq!(spans.field(forwarding, "to.asset_code").unwrap(), "XYZ");
+        assert_eq!(spans.ancestors(forwarding), vec!["incoming"]);
+
+        let rate_lookup = spans.find("rate_lookup");
+        assert_eq!(spans.field(rate_lookup, "from.asset_code").unwrap(), "ABC");
+        assert_eq!(spans.field(rate_lookup, "to.asset_code").unwrap(), "XYZ");
+        assert_eq!(spans.ancestors(rate_lookup), vec!["forwarding", "incoming"]);
+
+        let settlement = spans.find("settlement");
+        assert_eq!(spans.field(settlement, "outgoing_amount").unwrap(), "50");
+        assert_eq!(spans.ancestors(settlement), vec!["forwarding", "incoming"]);
+    }
+}
@@ -89,11 +89,27 @@ async fn main() {
         }
     }
 
-    node.serve(log_writer.clone()).await.unwrap();
+    let connector = node.serve(log_writer.clone()).await.unwrap();
 
-    // Add a future which is always pending. This will ensure main does not exist
-    // TODO: Is there a better way of doing this?
-    futures::future::pending().await
+    // Run until the process is asked to stop, then let the packets in flight drain
+    shutdown_signal().await;
+    connector.shutdown().await;
+}
+
+/// Resolves once the process receives SIGINT (Ctrl-C) or, on Unix, SIGTERM
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
 }
 
 fn cmdline_configuration(version: &str) -> clap::App<'static, '_> {
@@ -148,10 +164,34 @@ fn cmdline_configuration(version: &str) -> clap::App<'static, '_> {
             .takes_value(true)
             .default_value("")
             .help("Unique prefix that can be used to identify part of the db that this node will use. This can be used to enable multiple nodes to share the same database instance"),
+        Arg::with_name("publish_account_notifications")
+            .long("publish_account_notifications")
+            .takes_value(true)
+            .help("Whether to publish the balance changes and incoming settlements of the accounts through the database, so that the WebSocket subscribers connected to the other nodes sharing it receive them too. Defaults to false."),
         Arg::with_name("http_bind_address")
             .long("http_bind_address")
             .takes_value(true)
             .help("IP address and port to listen for HTTP connections. This is used for both the API and ILP over HTTP packets. ILP over HTTP is a means to transfer ILP packets instead of BTP connections"),
+        Arg::with_name("ilp_over_http_max_body_size")
+            .long("ilp_over_http_max_body_size")
+            .takes_value(true)
+            .help("Max size in bytes of ILP over HTTP request bodies. Larger requests are rejected with 413 Payload Too Large. Defaults to 40000."),
+        Arg::with_name("ilp_over_http_client.pool_max_idle_per_host")
+            .long("ilp_over_http_client.pool_max_idle_per_host")
+            .takes_value(true)
+            .help("Maximum number of idle connections the ILP over HTTP client keeps open to each peer host. Defaults to 32."),
+        Arg::with_name("ilp_over_http_client.pool_idle_timeout")
+            .long("ilp_over_http_client.pool_idle_timeout")
+            .takes_value(true)
+            .help("Time, defined in milliseconds, after which idle ILP over HTTP connections are closed. Defaults to 90000ms (90 seconds)."),
+        Arg::with_name("ilp_over_http_client.tcp_keepalive")
+            .long("ilp_over_http_client.tcp_keepalive")
+            .takes_value(true)
+            .help("Interval, defined in milliseconds, of the TCP keepalive probes sent on ILP over HTTP connections. Defaults to 60000ms (60 seconds)."),
+        Arg::with_name("ilp_over_http_client.timeout")
+            .long("ilp_over_http_client.timeout")
+            .takes_value(true)
+            .help("Timeout, defined in milliseconds, of outgoing ILP over HTTP requests to accounts without their own ilp_over_http_timeout. Defaults to 30000ms (30 seconds)."),
         Arg::with_name("settlement_api_bind_address")
             .long("settlement_api_bind_address")
             .takes_value(true)
@@ -164,6 +204,18 @@ fn cmdline_configuration(version: &str) -> clap::App<'static, '_> {
             .long("route_broadcast_interval")
             .takes_value(true)
             .help("Interval, defined in milliseconds, on which the node will broadcast routing information to other nodes using CCP. Defaults to 30000ms (30 seconds)."),
+        Arg::with_name("route_selection")
+            .long("route_selection")
+            .takes_value(true)
+            .help("How packets for prefixes routed through several accounts are spread across them. Defaults to weighted_random."),
+        Arg::with_name("shutdown_grace_period")
+            .long("shutdown_grace_period")
+            .takes_value(true)
+            .help("Time, defined in milliseconds, the node waits on shutdown for the packets in flight to resolve and the pending settlements to be sent. Defaults to 30000ms (30 seconds)."),
+        Arg::with_name("min_expiry_window")
+            .long("min_expiry_window")
+            .takes_value(true)
+            .help("Minimum time, defined in milliseconds, incoming packets must have left before they expire. Packets expiring sooner are rejected. Accounts can override it with their own min_expiry_window. Defaults to 0 (no minimum)."),
         Arg::with_name("exchange_rate.provider")
             .long("exchange_rate.provider")
             .takes_value(true)
@@ -186,6 +238,10 @@ fn cmdline_configuration(version: &str) -> clap::App<'static, '_> {
             .long("prometheus.bind_address")
             .takes_value(true)
             .help("IP address and port to host the Prometheus endpoint on."),
+        Arg::with_name("prometheus.path")
+            .long("prometheus.path")
+            .takes_value(true)
+            .help("Path of the Prometheus endpoint, e.g. metrics. Defaults to the root path."),
         Arg::with_name("prometheus.histogram_window")
             .long("prometheus.histogram_window")
             .takes_value(true)
@@ -37,29 +37,33 @@ use interledger::{
     btp::{btp_service_as_filter, connect_client, BtpOutgoingService, BtpStore},
     ccp::{CcpRouteManagerBuilder, CcpRoutingAccount, CcpRoutingStore, RoutingRelation},
     errors::*,
-    http::{HttpClientService, HttpServer as IlpOverHttpServer, HttpStore},
+    http::{
+        HttpClientConfig, HttpClientService, HttpServer as IlpOverHttpServer, HttpStore,
+        MAX_PACKET_SIZE,
+    },
     ildcp::IldcpService,
     packet::Address,
     packet::{ErrorCode, RejectBuilder},
-    rates::{ExchangeRateFetcher, ExchangeRateStore},
-    router::{Router, RouterStore},
+    rates::{CachingRateProvider, ExchangeRateFetcher, ExchangeRateStore},
+    router::{RouteSelection, Router, RouterStore},
     service::{
         outgoing_service_fn, Account as AccountTrait, AccountStore, AddressStore, OutgoingRequest,
         Username,
     },
     service_util::{
-        BalanceStore, EchoService, ExchangeRateService, ExpiryShortenerService,
-        MaxPacketAmountService, RateLimitService, RateLimitStore, ValidatorService,
+        BalanceStore, Connector, EchoService, ExchangeRateService, ExpiryShortenerService,
+        MaxPacketAmountService, PacketRateLimitService, RateLimitService, RateLimitStore,
+        ValidatorService,
     },
     settlement::{
         api::{create_settlements_filter, SettlementMessageService},
         core::{
             idempotency::IdempotentStore,
-            types::{LeftoversStore, SettlementStore},
+            types::{LeftoversStore, PendingSettlementStore, SettlementStore},
         },
     },
     store::account::Account,
-    stream::{StreamNotificationsStore, StreamReceiverService},
+    stream::{AccountNotificationsStore, StreamNotificationsStore, StreamReceiverService},
 };
 use num_bigint::BigUint;
 use once_cell::sync::Lazy;
@@ -81,7 +85,9 @@ use warp::{self, Filter};
 #[cfg(feature = "redis")]
 use crate::redis_store::*;
 #[cfg(feature = "balance-tracking")]
-use interledger::service_util::{start_delayed_settlement, BalanceService};
+use interledger::service_util::{
+    resume_pending_settlements, start_delayed_settlement, BalanceService,
+};
 
 #[doc(hidden)]
 pub use interledger::rates::ExchangeRateProvider;
@@ -154,6 +160,16 @@ pub struct ExchangeRateConfig {
     /// that the connector will tolerate before invalidating the exchange rate cache.
     #[serde(default = "ExchangeRateConfig::default_poll_failure_tolerance")]
     pub poll_failure_tolerance: u32,
+    /// Time, defined in milliseconds, for which a rate is used for forwarding packets
+    /// before it is looked up again, so rates set via the HTTP API may take this long to
+    /// apply. Defaults to 60000ms (60 seconds).
+    #[serde(default = "ExchangeRateConfig::default_ttl")]
+    pub ttl: u64,
+    /// Time, defined in milliseconds, for which a rate that could not be looked up again
+    /// is still used for forwarding packets, e.g. after the cache was invalidated because
+    /// the provider kept failing. Defaults to 600000ms (10 minutes).
+    #[serde(default = "ExchangeRateConfig::default_max_staleness")]
+    pub max_staleness: u64,
     /// API to poll for exchange rates. Currently the supported options are:
     /// - [CoinCap](https://docs.coincap.io)
     /// - [CryptoCompare](https://cryptocompare.com) (note this requires an API key)
@@ -178,6 +194,8 @@ impl Default for ExchangeRateConfig {
         Self {
             poll_interval: Self::default_poll_interval(),
             poll_failure_tolerance: Self::default_poll_failure_tolerance(),
+            ttl: Self::default_ttl(),
+            max_staleness: Self::default_max_staleness(),
             provider: Default::default(),
             spread: Self::default_spread(),
         }
@@ -191,6 +209,12 @@ impl ExchangeRateConfig {
     pub(crate) fn default_poll_failure_tolerance() -> u32 {
         5
     }
+    pub(crate) fn default_ttl() -> u64 {
+        60_000
+    }
+    pub(crate) fn default_max_staleness() -> u64 {
+        600_000
+    }
     pub(crate) fn default_spread() -> f64 {
         0.0
     }
@@ -221,10 +245,22 @@ pub struct InterledgerNode {
     /// Database prefix which can be used in case a db instance is shared by multiple nodes
     #[serde(default)]
     pub database_prefix: String,
+    /// Whether to publish the balance changes and incoming settlements of the accounts
+    /// through the database, so that the WebSocket subscribers connected to the other nodes
+    /// sharing it receive them too. Defaults to false.
+    #[serde(default)]
+    pub publish_account_notifications: bool,
     /// IP address and port to listen for HTTP connections
     /// This is used for both the API and ILP over HTTP packets
     #[serde(default = "default_http_bind_address")]
     pub http_bind_address: SocketAddr,
+    /// Max size in bytes of ILP over HTTP request bodies. Larger requests are
+    /// rejected with `413 Payload Too Large`. Defaults to 40000.
+    pub ilp_over_http_max_body_size: Option<u64>,
+    /// Connection pool and timeout settings of the client sending ILP over HTTP
+    /// packets to peers
+    #[serde(default)]
+    pub ilp_over_http_client: HttpClientConfig,
     /// IP address and port to listen for the Settlement Engine API
     #[serde(default = "default_settlement_api_bind_address")]
     pub settlement_api_bind_address: SocketAddr,
@@ -236,6 +272,19 @@ pub struct InterledgerNode {
     /// Interval, defined in milliseconds, on which the node will broadcast routing
     /// information to other nodes using CCP. Defaults to 30000ms (30 seconds).
     pub route_broadcast_interval: Option<u64>,
+    /// How packets for prefixes routed through several accounts are spread across them:
+    /// `weighted_random` (the default) or `round_robin`.
+    #[serde(default)]
+    pub route_selection: RouteSelection,
+    /// Time, defined in milliseconds, the node waits on shutdown for the packets in flight
+    /// to be fulfilled or rejected and the pending settlements to be sent. Defaults to
+    /// 30000ms (30 seconds).
+    pub shutdown_grace_period: Option<u64>,
+    /// Minimum time, defined in milliseconds, incoming packets must have left before they
+    /// expire. Packets expiring sooner are rejected with `R02: Insufficient Timeout`. Accounts
+    /// can override it with their own `min_expiry_window`. Defaults to 0 (no minimum).
+    #[serde(default)]
+    pub min_expiry_window: u32,
     #[serde(default)]
     /// Configuration for calculating exchange rates between various pairs.
     pub exchange_rate: ExchangeRateConfig,
@@ -256,24 +305,19 @@ pub struct InterledgerNode {
 }
 
 impl InterledgerNode {
-    /// Returns a future that runs the Interledger.rs Node.
+    /// Returns a future that runs the Interledger.rs Node. It resolves to the node's
+    /// [`Connector`], which is used to shut it down gracefully.
     ///
     /// If the Prometheus configuration was provided, it will
     /// also run the Prometheus metrics server on the given address.
     // TODO when a BTP connection is made, insert a outgoing HTTP entry into the Store to tell other
     // connector instances to forward packets for that account to us
-    pub async fn serve(self, log_writer: Option<LogWriter>) -> Result<(), ()> {
+    pub async fn serve(self, log_writer: Option<LogWriter>) -> Result<Connector, ()> {
         cfg_if! {
             if #[cfg(feature = "monitoring")] {
-                let f = futures::future::join(serve_prometheus(self.clone()), self.serve_node(log_writer)).then(
-                    |r| async move {
-                        if r.0.is_ok() || r.1.is_ok() {
-                            Ok(())
-                        } else {
-                            Err(())
-                        }
-                    },
-                );
+                // The metrics server failing (e.g. because it is not configured) does not stop the node
+                let f = futures::future::join(serve_prometheus(self.clone()), self.serve_node(log_writer))
+                    .map(|(_, node)| node);
             } else {
                 let f = self.serve_node(log_writer);
             }
@@ -282,7 +326,7 @@ impl InterledgerNode {
         f.await
     }
 
-    async fn serve_node(self, log_writer: Option<LogWriter>) -> Result<(), ()> {
+    async fn serve_node(self, log_writer: Option<LogWriter>) -> Result<Connector, ()> {
         let ilp_address = if let Some(address) = &self.ilp_address {
             address.clone()
         } else {
@@ -317,18 +361,20 @@ impl InterledgerNode {
         store: S,
         ilp_address: Address,
         _log_writer: Option<LogWriter>,
-    ) -> Result<(), ()>
+    ) -> Result<Connector, ()>
     where
         S: NodeStore<Account = Account>
             + AddressStore
             + BtpStore<Account = Account>
             + HttpStore<Account = Account>
             + StreamNotificationsStore<Account = Account>
+            + AccountNotificationsStore
             + BalanceStore
             + SettlementStore<Account = Account>
             + ExchangeRateStore
             + BalanceStore
             + SettlementStore<Account = Account>
+            + PendingSettlementStore
             + RouterStore<Account = Account>
             + CcpRoutingStore<Account = Account>
             + RateLimitStore<Account = Account>
@@ -347,14 +393,22 @@ impl InterledgerNode {
 
         let secret_seed = Bytes::copy_from_slice(&self.secret_seed[..]);
         let http_bind_address = self.http_bind_address;
+        let ilp_over_http_max_body_size =
+            self.ilp_over_http_max_body_size.unwrap_or(MAX_PACKET_SIZE);
+        let ilp_over_http_client = self.ilp_over_http_client.clone();
         let settlement_api_bind_address = self.settlement_api_bind_address;
         let admin_auth_token = self.admin_auth_token.clone();
         let default_spsp_account = self.default_spsp_account.clone();
         let route_broadcast_interval = self.route_broadcast_interval;
+        let route_selection = self.route_selection;
+        let shutdown_grace_period = self.shutdown_grace_period.unwrap_or(30_000);
+        let min_expiry_window = self.min_expiry_window;
         let exchange_rate_provider = self.exchange_rate.provider.clone();
         let exchange_rate_poll_interval = self.exchange_rate.poll_interval;
         let exchange_rate_poll_failure_tolerance = self.exchange_rate.poll_failure_tolerance;
         let exchange_rate_spread = self.exchange_rate.spread;
+        let exchange_rate_ttl = Duration::from_millis(self.exchange_rate.ttl);
+        let exchange_rate_max_staleness = Duration::from_millis(self.exchange_rate.max_staleness);
         #[cfg(feature = "google-pubsub")]
         let google_pubsub = self.google_pubsub.clone();
 
@@ -404,7 +458,8 @@ impl InterledgerNode {
         // The BTP service is both an Incoming and Outgoing one so we pass it first as the Outgoing
         // service to others like the router and then call handle_incoming on it to set up the incoming handler
         let outgoing_service = btp_server_service.clone();
-        let outgoing_service = HttpClientService::new(store.clone(), outgoing_service);
+        let outgoing_service =
+            HttpClientService::with_config(store.clone(), outgoing_service, &ilp_over_http_client);
 
         #[cfg(feature = "monitoring")]
         let outgoing_service = outgoing_service.wrap(outgoing_metrics);
@@ -416,6 +471,10 @@ impl InterledgerNode {
         let outgoing_service =
             StreamReceiverService::new(secret_seed.clone(), store.clone(), outgoing_service);
 
+        // Settlements which were in flight when the node last stopped are sent again
+        #[cfg(feature = "balance-tracking")]
+        resume_pending_settlements(store.clone());
+
         #[cfg(feature = "balance-tracking")]
         let outgoing_service = match self.settle_every {
             Some(seconds) => {
@@ -434,8 +493,15 @@ impl InterledgerNode {
             None => BalanceService::new(store.clone(), None, outgoing_service),
         };
 
-        let outgoing_service =
-            ExchangeRateService::new(exchange_rate_spread, store.clone(), outgoing_service);
+        let rate_provider = CachingRateProvider::new(store.clone())
+            .with_ttl(exchange_rate_ttl)
+            .with_max_staleness(exchange_rate_max_staleness);
+        let outgoing_service = ExchangeRateService::with_provider(
+            exchange_rate_spread,
+            store.clone(),
+            rate_provider,
+            outgoing_service,
+        );
 
         #[cfg(feature = "google-pubsub")]
         let outgoing_service =
@@ -453,7 +519,8 @@ impl InterledgerNode {
         }
 
         // Set up the Router and Routing Manager
-        let incoming_service = Router::new(store.clone(), outgoing_service_fwd);
+        let incoming_service =
+            Router::new(store.clone(), outgoing_service_fwd).with_route_selection(route_selection);
 
         // Add tracing to track the outgoing request details
         #[cfg(feature = "monitoring")]
@@ -475,8 +542,16 @@ impl InterledgerNode {
         let incoming_service = SettlementMessageService::new(incoming_service);
         let incoming_service = IldcpService::new(incoming_service);
         let incoming_service = MaxPacketAmountService::new(store.clone(), incoming_service);
-        let incoming_service = ValidatorService::incoming(store.clone(), incoming_service);
+        let incoming_service = ValidatorService::incoming(store.clone(), incoming_service)
+            .with_min_expiry_window(min_expiry_window);
         let incoming_service = RateLimitService::new(store.clone(), incoming_service);
+        let incoming_service = PacketRateLimitService::new(store.clone(), incoming_service);
+
+        // Tracks the packets in flight so that they can be drained on shutdown
+        let connector = Connector::new(Duration::from_millis(shutdown_grace_period));
+        #[cfg(feature = "balance-tracking")]
+        let connector = connector.with_settlement_flush(store.clone());
+        let incoming_service = connector.incoming(store.clone(), incoming_service);
 
         // Add tracing to track the incoming request details
         #[cfg(feature = "monitoring")]
@@ -552,7 +627,9 @@ impl InterledgerNode {
         // add an API of ILP over HTTP and add rejection handler
         let api = api
             .into_warp_filter()
-            .or(IlpOverHttpServer::new(incoming_service_http, store.clone()).as_filter())
+            .or(IlpOverHttpServer::new(incoming_service_http, store.clone())
+                .with_max_body_size(ilp_over_http_max_body_size)
+                .as_filter())
             .or(btp_service_as_filter(
                 btp_server_service_clone,
                 store.clone(),
@@ -657,7 +734,7 @@ impl InterledgerNode {
             debug!(target: "interledger-node", "Not using exchange rate provider. Rates must be set via the HTTP API");
         }
 
-        Ok(())
+        Ok(connector)
     }
 }
 
@@ -2,6 +2,7 @@
 
 use crate::node::{InterledgerNode, LogWriter};
 use futures::TryFutureExt;
+use interledger::service_util::Connector;
 pub use interledger::{
     api::{AccountDetails, NodeStore},
     packet::Address,
@@ -25,13 +26,14 @@ pub async fn serve_redis_node(
     node: InterledgerNode,
     ilp_address: Address,
     log_writer: Option<LogWriter>,
-) -> Result<(), ()> {
+) -> Result<Connector, ()> {
     let redis_connection_info = node.database_url.clone().into_connection_info().unwrap();
     let redis_addr = redis_connection_info.addr.clone();
     let redis_secret = generate_redis_secret(&node.secret_seed);
     let store = RedisStoreBuilder::new(redis_connection_info, redis_secret)
         .with_db_prefix(node.database_prefix.as_str())
         .node_ilp_address(ilp_address.clone())
+        .publish_account_notifications(node.publish_account_notifications)
         .connect()
         .map_err(move |err| error!(target: "interledger-node", "Error connecting to Redis: {:?} {:?}", redis_addr, err))
         .await?;
@@ -2,18 +2,18 @@
 use async_trait::async_trait;
 use bytes::Bytes;
 use interledger_btp::{BtpAccount, BtpOutgoingService};
-use interledger_ccp::CcpRoutingAccount;
+use interledger_ccp::{CcpRoutingAccount, RoutePolicy, RoutingRelation};
 use interledger_errors::NodeStoreError;
 use interledger_http::{HttpAccount, HttpStore};
 use interledger_packet::Address;
 use interledger_rates::ExchangeRateStore;
-use interledger_router::RouterStore;
+use interledger_router::{RouterStore, WeightedRoute};
 use interledger_service::{
     Account, AccountStore, AddressStore, IncomingService, OutgoingService, Username,
 };
 use interledger_service_util::BalanceStore;
 use interledger_settlement::core::types::{SettlementAccount, SettlementStore};
-use interledger_stream::StreamNotificationsStore;
+use interledger_stream::{AccountNotificationsStore, StreamNotificationsStore};
 use secrecy::SecretString;
 use serde::{de, Deserialize, Serialize};
 use std::{boxed::*, collections::HashMap, fmt::Display, net::SocketAddr, str::FromStr};
@@ -117,10 +117,19 @@ pub trait NodeStore: Clone + Send + Sync + 'static {
         settings: AccountSettings,
     ) -> Result<Self::Account, NodeStoreError>;
 
-    // TODO limit the number of results and page through them
+    /// Checks that the store's backend can currently be reached
+    async fn check_connection(&self) -> Result<(), NodeStoreError>;
+
     /// Gets all stored accounts
     async fn get_all_accounts(&self) -> Result<Vec<Self::Account>, NodeStoreError>;
 
+    /// Gets a page of the accounts matching the query's filters, ordered by username.
+    /// Implementations should scan their usernames in order rather than load every account.
+    async fn query_accounts(
+        &self,
+        query: AccountQuery,
+    ) -> Result<AccountPage<Self::Account>, NodeStoreError>;
+
     /// Sets the static routes for routing
     async fn set_static_routes<R>(&self, routes: R) -> Result<(), NodeStoreError>
     where
@@ -135,6 +144,14 @@ pub trait NodeStore: Clone + Send + Sync + 'static {
         account_id: Uuid,
     ) -> Result<(), NodeStoreError>;
 
+    /// Routes the prefix through several accounts, which the router spreads packets
+    /// across according to their weights. An empty list removes the prefix's multipath route.
+    async fn set_multipath_route(
+        &self,
+        prefix: String,
+        routes: Vec<WeightedRoute>,
+    ) -> Result<(), NodeStoreError>;
+
     /// Sets the default route ("") to be the provided account id
     /// (acts as a catch-all route if all other routes don't match)
     async fn set_default_route(&self, account_id: Uuid) -> Result<(), NodeStoreError>;
@@ -152,6 +169,9 @@ pub trait NodeStore: Clone + Send + Sync + 'static {
         &self,
         asset_code: &str,
     ) -> Result<Option<Url>, NodeStoreError>;
+
+    /// Gets the default settlement engines of every asset code
+    async fn get_settlement_engines(&self) -> Result<HashMap<String, Url>, NodeStoreError>;
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -193,6 +213,108 @@ pub struct AccountSettings {
     /// would pre-fund with the user)
     #[serde(default, deserialize_with = "optional_number_or_string")]
     pub settle_to: Option<u64>,
+    /// Minimum time, defined in milliseconds, between two settlements triggered by the
+    /// balance reaching the `settle_threshold`
+    #[serde(default, deserialize_with = "optional_number_or_string")]
+    pub min_settlement_interval: Option<u64>,
+}
+
+/// Number of accounts in a page of `GET /accounts` when the query doesn't set a limit
+pub const DEFAULT_ACCOUNTS_PAGE_SIZE: usize = 100;
+/// Largest number of accounts in a page of `GET /accounts`
+pub const MAX_ACCOUNTS_PAGE_SIZE: usize = 1000;
+
+/// Filters and position of a page of accounts, which are ordered by username.
+/// Every filter which is set must match.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct AccountQuery {
+    /// Only the accounts of this asset code
+    pub asset_code: Option<String>,
+    /// Only the accounts with this routing relation
+    #[serde(default, deserialize_with = "optional_routing_relation")]
+    pub routing_relation: Option<RoutingRelation>,
+    /// Only the accounts whose username starts with this prefix (case-sensitive)
+    pub username_prefix: Option<String>,
+    /// The `next_cursor` of the previous page: the page starts after this username
+    pub cursor: Option<String>,
+    /// Maximum number of accounts in the page
+    pub limit: Option<usize>,
+}
+
+impl AccountQuery {
+    /// Number of accounts in the page, within 1 and `MAX_ACCOUNTS_PAGE_SIZE`
+    pub fn page_size(&self) -> usize {
+        self.limit
+            .unwrap_or(DEFAULT_ACCOUNTS_PAGE_SIZE)
+            .clamp(1, MAX_ACCOUNTS_PAGE_SIZE)
+    }
+
+    /// The username to start scanning from, and whether it is included, combining the cursor
+    /// and the username prefix. `None` to scan from the first username.
+    pub fn scan_start(&self) -> Option<(&str, bool)> {
+        match (self.cursor.as_deref(), self.username_prefix.as_deref()) {
+            (Some(cursor), Some(prefix)) if cursor < prefix => Some((prefix, true)),
+            (Some(cursor), _) => Some((cursor, false)),
+            (None, Some(prefix)) => Some((prefix, true)),
+            (None, None) => None,
+        }
+    }
+
+    /// Whether the username is still in the range of usernames to scan
+    pub fn in_scan_range(&self, username: &str) -> bool {
+        self.username_prefix
+            .as_deref()
+            .is_none_or(|prefix| username.starts_with(prefix))
+    }
+
+    /// Whether the account passes the asset code and routing relation filters
+    pub fn matches<A: Account + CcpRoutingAccount>(&self, account: &A) -> bool {
+        self.asset_code
+            .as_deref()
+            .is_none_or(|asset_code| account.asset_code() == asset_code)
+            && self
+                .routing_relation
+                .is_none_or(|relation| account.routing_relation() == relation)
+    }
+}
+
+fn optional_routing_relation<'de, D>(deserializer: D) -> Result<Option<RoutingRelation>, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|relation| {
+            RoutingRelation::from_str(&relation)
+                .map_err(|_| de::Error::custom(format!("invalid routing relation: {}", relation)))
+        })
+        .transpose()
+}
+
+/// A page of the accounts matching an [`AccountQuery`]
+#[derive(Debug, Clone, Serialize)]
+pub struct AccountPage<A> {
+    pub accounts: Vec<A>,
+    /// Pass this as the `cursor` of the query to get the next page. `None` on the last page
+    pub next_cursor: Option<String>,
+}
+
+impl<A: Account> AccountPage<A> {
+    /// Builds the page out of the matching accounts found by the scan, which should look
+    /// for one more account than the page size to tell whether there is a next page
+    pub fn new(mut accounts: Vec<A>, page_size: usize) -> Self {
+        let next_cursor = if accounts.len() > page_size {
+            accounts.truncate(page_size);
+            accounts
+                .last()
+                .map(|account| account.username().to_string())
+        } else {
+            None
+        };
+        AccountPage {
+            accounts,
+            next_cursor,
+        }
+    }
 }
 
 /// EncryptedAccountSettings is created by encrypting the incoming and outgoing
@@ -213,6 +335,9 @@ pub struct EncryptedAccountSettings {
     #[serde(default, deserialize_with = "optional_number_or_string")]
     /// The amount which the balance service will attempt to settle down to
     pub settle_to: Option<u64>,
+    #[serde(default, deserialize_with = "optional_number_or_string")]
+    /// Minimum time in milliseconds between two settlements triggered by the settle_threshold
+    pub min_settlement_interval: Option<u64>,
 }
 
 /// The Account type for the RedisStore.
@@ -236,6 +361,10 @@ pub struct AccountDetails {
     pub min_balance: Option<i64>,
     /// The account's ILP over HTTP URL (this is where packets are sent over HTTP from your node)
     pub ilp_over_http_url: Option<String>,
+    /// Timeout, defined in milliseconds, of the ILP over HTTP requests sent to the account.
+    /// Defaults to the node's ILP over HTTP client timeout
+    #[serde(default, deserialize_with = "optional_number_or_string")]
+    pub ilp_over_http_timeout: Option<u64>,
     /// The account's API and incoming ILP over HTTP token.
     /// This must match the ILP over HTTP outgoing token on the peer's node if receiving
     /// packets from that peer
@@ -266,18 +395,38 @@ pub struct AccountDetails {
     /// The amount which the balance service will attempt to settle down to
     #[serde(default, deserialize_with = "optional_number_or_string")]
     pub settle_to: Option<i64>,
+    /// Minimum time, defined in milliseconds, between two settlements triggered by the
+    /// balance reaching the `settle_threshold`. Until it has passed, the balance keeps
+    /// growing and is settled down to `settle_to` once it has
+    #[serde(default, deserialize_with = "optional_number_or_string")]
+    pub min_settlement_interval: Option<u64>,
     /// The routing relation of the account
     pub routing_relation: Option<String>,
     /// The round trip time of the account (should be set depending on how
     /// well the network connectivity of the account and the node is)
     #[serde(default, deserialize_with = "optional_number_or_string")]
     pub round_trip_time: Option<u32>,
+    /// The minimum time, in milliseconds, the account's incoming packets must have left
+    /// before they expire. Defaults to the node's `min_expiry_window`
+    #[serde(default, deserialize_with = "optional_number_or_string")]
+    pub min_expiry_window: Option<u32>,
     /// The maximum amount the account can send per minute
     #[serde(default, deserialize_with = "optional_number_or_string")]
     pub amount_per_minute_limit: Option<u64>,
     /// The limit of packets the account can send per minute
     #[serde(default, deserialize_with = "optional_number_or_string")]
     pub packets_per_minute_limit: Option<u32>,
+    /// The rate, in packets per second, at which the account's token bucket refills.
+    /// Unlike the per minute limits, this is enforced in the node's memory
+    #[serde(default, deserialize_with = "optional_number_or_string")]
+    pub packets_per_second_limit: Option<u32>,
+    /// The number of packets the account can send in a burst on top of its
+    /// `packets_per_second_limit`. Defaults to one second's worth of packets
+    #[serde(default, deserialize_with = "optional_number_or_string")]
+    pub packet_burst_limit: Option<u32>,
+    /// Limits which routes are advertised to the account over CCP
+    #[serde(default)]
+    pub route_policy: Option<RoutePolicy>,
     /// The account's settlement engine URL. If a global engine url is configured
     /// for the account's asset code,  that will be used instead (even if the account is
     /// configured with a specific one)
@@ -311,6 +460,7 @@ where
         + BalanceStore
         + SettlementStore<Account = A>
         + StreamNotificationsStore<Account = A>
+        + AccountNotificationsStore
         + RouterStore
         + ExchangeRateStore,
     I: IncomingService<A> + Clone + Send + Sync + 'static,
@@ -374,8 +524,9 @@ where
         .or(routes::node_settings_api(
             self.admin_api_token,
             self.node_version,
-            self.store,
+            self.store.clone(),
         ))
+        .or(routes::health_api(self.store))
         .boxed()
     }
 
@@ -457,6 +608,39 @@ mod tests {
         );
     }
 
+    #[test]
+    fn account_query_scan_start() {
+        let query = |cursor: Option<&str>, prefix: Option<&str>| AccountQuery {
+            cursor: cursor.map(str::to_owned),
+            username_prefix: prefix.map(str::to_owned),
+            ..AccountQuery::default()
+        };
+        assert_eq!(query(None, None).scan_start(), None);
+        assert_eq!(query(Some("bob"), None).scan_start(), Some(("bob", false)));
+        assert_eq!(query(None, Some("b")).scan_start(), Some(("b", true)));
+        // The cursor only moves the start once it's past the prefix
+        assert_eq!(
+            query(Some("alice"), Some("b")).scan_start(),
+            Some(("b", true))
+        );
+        assert_eq!(
+            query(Some("bob"), Some("b")).scan_start(),
+            Some(("bob", false))
+        );
+
+        assert!(query(None, Some("b")).in_scan_range("bob"));
+        assert!(!query(None, Some("b")).in_scan_range("charlie"));
+        assert_eq!(
+            AccountQuery::default().page_size(),
+            DEFAULT_ACCOUNTS_PAGE_SIZE
+        );
+        let unbounded = AccountQuery {
+            limit: Some(usize::MAX),
+            ..AccountQuery::default()
+        };
+        assert_eq!(unbounded.page_size(), MAX_ACCOUNTS_PAGE_SIZE);
+    }
+
     #[test]
     fn account_settings_deserialization() {
         let settings: AccountSettings = serde_json::from_value(json!({
@@ -464,10 +648,12 @@ mod tests {
             "ilp_over_http_incoming_token": "secret",
             "settle_to": 0,
             "settle_threshold": "1000",
+            "min_settlement_interval": "60000",
         }))
         .unwrap();
         assert_eq!(settings.settle_threshold, Some(1000));
         assert_eq!(settings.settle_to, Some(0));
+        assert_eq!(settings.min_settlement_interval, Some(60000));
         assert_eq!(
             settings.ilp_over_http_url,
             Some("https://example.com/ilp".to_string())
@@ -1,4 +1,4 @@
-use crate::{number_or_string, AccountDetails, AccountSettings, NodeStore};
+use crate::{number_or_string, AccountDetails, AccountQuery, AccountSettings, NodeStore};
 use bytes::Bytes;
 use futures::{Future, FutureExt, StreamExt, TryFutureExt};
 use interledger_btp::{connect_to_service_account, BtpAccount, BtpOutgoingService};
@@ -16,12 +16,15 @@ use interledger_service::{
 use interledger_service_util::BalanceStore;
 use interledger_settlement::core::{types::SettlementAccount, SettlementClient};
 use interledger_spsp::{pay, SpspResponder};
-use interledger_stream::{PaymentNotification, StreamNotificationsStore};
+use interledger_stream::{
+    AccountNotification, AccountNotificationsStore, PaymentNotification, StreamNotificationsStore,
+};
 use secrecy::{ExposeSecret, SecretString};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use std::convert::TryFrom;
 use std::fmt::Debug;
+use std::str::FromStr;
 use tracing::{debug, error};
 use uuid::Uuid;
 use warp::{self, reply::Json, Filter, Rejection};
@@ -72,6 +75,25 @@ const fn get_default_max_slippage() -> f64 {
     0.015
 }
 
+/// Outcome of one of the accounts of a `PUT /accounts` batch
+#[derive(Serialize, Debug)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum BatchOutcome<A> {
+    Created { account: A },
+    Updated { account: A },
+    Failed { error: ApiError },
+}
+
+/// Result of one of the accounts of a `PUT /accounts` batch, reported in the order they were
+/// given so that a partially applied batch can be reconciled
+#[derive(Serialize, Debug)]
+struct BatchItemResult<A> {
+    /// The username of the account, unless the item didn't have a valid one
+    username: Option<Username>,
+    #[serde(flatten)]
+    outcome: BatchOutcome<A>,
+}
+
 #[derive(Deserialize, Debug)]
 struct SpspPayRequest {
     receiver: String,
@@ -103,6 +125,7 @@ where
         + HttpStore<Account = A>
         + BalanceStore
         + StreamNotificationsStore<Account = A>
+        + AccountNotificationsStore
         + ExchangeRateStore
         + RouterStore,
     A: BtpAccount
@@ -229,10 +252,16 @@ where
         .and(warp::path("accounts"))
         .and(warp::path::end())
         .and(admin_only.clone())
+        .and(warp::query::<AccountQuery>())
         .and(with_store.clone())
-        .and_then(|store: S| async move {
-            let accounts = store.get_all_accounts().await?;
-            Ok::<Json, Rejection>(warp::reply::json(&accounts))
+        .and_then(|query: AccountQuery, store: S| async move {
+            // Without any query parameters, list every account like before pagination
+            if query == AccountQuery::default() {
+                let accounts = store.get_all_accounts().await?;
+                return Ok::<Json, Rejection>(warp::reply::json(&accounts));
+            }
+            let page = store.query_accounts(query).await?;
+            Ok::<Json, Rejection>(warp::reply::json(&page))
         });
 
     // PUT /accounts/:username
@@ -263,6 +292,37 @@ where
             }
         });
 
+    // PUT /accounts
+    let btp_clone = btp.clone();
+    let outgoing_handler_clone = outgoing_handler.clone();
+    let put_accounts = warp::put()
+        .and(warp::path("accounts"))
+        .and(warp::path::end())
+        .and(admin_only.clone())
+        .and(deserialize_json())
+        .and(with_store.clone())
+        .and_then(move |items: Vec<serde_json::Value>, store: S| {
+            let outgoing_handler = outgoing_handler_clone.clone();
+            let btp = btp_clone.clone();
+            async move {
+                // Each account is created or updated on its own, so a failed one doesn't
+                // undo the others
+                let mut results = Vec::with_capacity(items.len());
+                for item in items {
+                    let username = item
+                        .get("username")
+                        .and_then(|username| username.as_str())
+                        .and_then(|username| Username::from_str(username).ok());
+                    let outcome =
+                        upsert_account(item, outgoing_handler.clone(), store.clone(), btp.clone())
+                            .await
+                            .unwrap_or_else(|error| BatchOutcome::Failed { error });
+                    results.push(BatchItemResult { username, outcome });
+                }
+                Ok::<Json, Rejection>(warp::reply::json(&results))
+            }
+        });
+
     // GET /accounts/:username
     let get_account = warp::get()
         .and(warp::path("accounts"))
@@ -356,7 +416,7 @@ where
 
     // (Websocket) /accounts/:username/payments/incoming
     let incoming_payment_notifications = warp::path("accounts")
-        .and(admin_or_authorized_user_only)
+        .and(admin_or_authorized_user_only.clone())
         .and(warp::path("payments"))
         .and(warp::path("incoming"))
         .and(warp::path::end())
@@ -370,6 +430,21 @@ where
             })
         });
 
+    // (Websocket) /accounts/:username/notifications
+    let account_notifications = warp::path("accounts")
+        .and(admin_or_authorized_user_only)
+        .and(warp::path("notifications"))
+        .and(warp::path::end())
+        .and(warp::ws())
+        .and(with_store.clone())
+        .map(|id: Uuid, ws: warp::ws::Ws, store: S| {
+            ws.on_upgrade(move |ws: warp::ws::WebSocket| {
+                let (ws_tx, ws_rx) = ws.split();
+                tokio::task::spawn(notify_account(ws_tx, id, store).map(|result| result.unwrap()));
+                consume_msg_drain(ws_rx)
+            })
+        });
+
     // (Websocket) /payments/incoming
     let all_payment_notifications = warp::path("payments")
         .and(admin_only)
@@ -486,12 +561,14 @@ where
         get_spsp_well_known,
         post_accounts,
         get_accounts,
+        put_accounts,
         put_account,
         delete_account,
         get_account,
         get_account_balance,
         put_account_settings,
         incoming_payment_notifications,
+        account_notifications,
         all_payment_notifications,
         post_payments,
     )
@@ -533,6 +610,31 @@ fn notify_user(
         .then(futures::future::ok)
 }
 
+// Similar to notify_user, but streams every notification concerning the account
+// (payments, incoming settlements and balance changes), each tagged with its `type`
+fn notify_account(
+    ws_tx: futures::stream::SplitSink<warp::ws::WebSocket, warp::ws::Message>,
+    id: Uuid,
+    store: impl AccountNotificationsStore,
+) -> impl Future<Output = Result<(), ()>> {
+    let (tx, rx) = futures::channel::mpsc::unbounded::<AccountNotification>();
+    store.add_account_notification_subscription(id, tx);
+
+    let rx = rx.map(|notification: AccountNotification| {
+        debug!("Sending account notification via WS: {:?}", notification);
+        let msg = warp::ws::Message::text(serde_json::to_string(&notification).unwrap());
+        Ok(msg)
+    });
+
+    rx.forward(ws_tx)
+        .map(|result| {
+            if let Err(e) = result {
+                eprintln!("websocket send error: {}", e);
+            }
+        })
+        .then(futures::future::ok)
+}
+
 // Similar to notify_user, but instead of associating an account Uuid with a sender,
 // it only assumes control of the store's all payment notification receiver; its messages
 // are published alongside account-specific notifications and the dedicated thread
@@ -588,10 +690,7 @@ where
         .await?;
 
     let info = IldcpResponse::try_from(fulfill.into_data().freeze()).map_err(|err| {
-        let msg = format!(
-            "Unable to parse ILDCP response from fulfill packet: {:?}",
-            err
-        );
+        let msg = format!("Unable to use ILDCP response from fulfill packet: {}", err);
         error!("{}", msg);
         ApiError::internal_server_error().detail(msg)
     })?;
@@ -633,6 +732,64 @@ where
     Ok(())
 }
 
+/// Creates the account of a `PUT /accounts` batch, or updates it if one with the same
+/// username exists, and connects to its external services like the single account
+/// endpoints do
+async fn upsert_account<O, A, S, B>(
+    item: serde_json::Value,
+    service: O,
+    store: S,
+    btp: BtpOutgoingService<B, A>,
+) -> Result<BatchOutcome<A>, ApiError>
+where
+    O: OutgoingService<A> + Clone + Send + Sync + 'static,
+    A: CcpRoutingAccount + BtpAccount + SettlementAccount + Clone + Send + Sync + 'static,
+    S: NodeStore<Account = A>
+        + AccountStore<Account = A>
+        + AddressStore
+        + BalanceStore
+        + Clone
+        + Send
+        + Sync
+        + 'static,
+    B: OutgoingService<A> + Clone + Send + 'static,
+{
+    let account_details = AccountDetails::deserialize(&item)
+        .map_err(|err| ApiError::bad_request().detail(err.to_string()))?;
+
+    let (account, created) = match store
+        .get_account_id_from_username(&account_details.username)
+        .await
+    {
+        Ok(id) => {
+            // Like PUT /accounts/:username, a new BTP token replaces the existing connection
+            if account_details.ilp_over_btp_incoming_token.is_some() {
+                btp.close_connection(&id);
+            }
+            (store.update_account(id, account_details).await?, false)
+        }
+        Err(AccountStoreError::AccountNotFound(_)) => {
+            (store.insert_account(account_details).await?, true)
+        }
+        Err(err) => return Err(err.into()),
+    };
+
+    connect_to_external_services(service, account.clone(), store, btp)
+        .await
+        .map_err(|rejection| {
+            rejection
+                .find::<ApiError>()
+                .cloned()
+                .unwrap_or_else(ApiError::internal_server_error)
+        })?;
+
+    Ok(if created {
+        BatchOutcome::Created { account }
+    } else {
+        BatchOutcome::Updated { account }
+    })
+}
+
 // Helper function which gets called whenever a new account is added or
 // modified.
 // Performed actions:
@@ -653,7 +810,7 @@ where
     O: OutgoingService<A> + Clone + Send + Sync + 'static,
     A: CcpRoutingAccount + BtpAccount + SettlementAccount + Clone + Send + Sync + 'static,
     S: NodeStore<Account = A> + AddressStore + BalanceStore + Clone + Send + Sync + 'static,
-    B: OutgoingService<A> + Clone + 'static,
+    B: OutgoingService<A> + Clone + Send + 'static,
 {
     // Try to connect to the account's BTP socket if they have
     // one configured
@@ -738,6 +895,75 @@ mod tests {
         assert_eq!(resp.status().as_u16(), 401);
     }
 
+    #[tokio::test]
+    async fn only_admin_can_put_accounts_in_batch() {
+        let api = test_accounts_api();
+        let batch = Some(serde_json::json!([DETAILS.clone()]));
+        let resp = api_call(&api, "PUT", "/accounts", "admin", batch.clone()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+
+        let resp = api_call(&api, "PUT", "/accounts", "wrong", batch).await;
+        assert_eq!(resp.status().as_u16(), 401);
+    }
+
+    #[tokio::test]
+    async fn reports_the_result_of_each_account_in_batch() {
+        let api = test_accounts_api();
+        let batch = serde_json::json!([
+            // alice exists, so the account is updated
+            DETAILS.clone(),
+            {
+                "username": "dave",
+                "asset_code": "XYZ",
+                "asset_scale": 9,
+            },
+            // Missing its asset code
+            {
+                "username": "carol",
+                "asset_scale": 9,
+            },
+            {
+                "username": "not a valid username!",
+                "asset_code": "XYZ",
+                "asset_scale": 9,
+            },
+        ]);
+        let resp = api_call(&api, "PUT", "/accounts", "admin", Some(batch)).await;
+        assert_eq!(resp.status().as_u16(), 200);
+
+        let results: Vec<serde_json::Value> = serde_json::from_slice(resp.body()).unwrap();
+        let outcomes: Vec<_> = results
+            .iter()
+            .map(|result| {
+                (
+                    result["username"].clone(),
+                    result["status"].as_str().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            outcomes,
+            vec![
+                (serde_json::json!("alice"), "updated"),
+                (serde_json::json!("dave"), "created"),
+                (serde_json::json!("carol"), "failed"),
+                (serde_json::Value::Null, "failed"),
+            ]
+        );
+        for applied in &results[..2] {
+            assert!(applied.get("account").is_some());
+            assert!(applied.get("error").is_none());
+        }
+        for failed in &results[2..] {
+            assert_eq!(failed["error"]["status"], 400);
+            assert!(failed.get("account").is_none());
+        }
+        assert!(results[2]["error"]["detail"]
+            .as_str()
+            .unwrap()
+            .contains("asset_code"));
+    }
+
     #[tokio::test]
     async fn only_admin_can_delete_account() {
         let api = test_accounts_api();
@@ -768,6 +994,24 @@ mod tests {
         assert_eq!(resp.status().as_u16(), 401);
     }
 
+    #[tokio::test]
+    async fn pages_accounts_when_queried() {
+        let api = test_accounts_api();
+        let resp = api_call(&api, "GET", "/accounts", "admin", None).await;
+        let accounts: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(accounts.as_array().unwrap().len(), 2);
+
+        let path = "/accounts?limit=1&asset_code=XYZ&routing_relation=peer";
+        let resp = api_call(&api, "GET", path, "admin", None).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let page: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(page["accounts"].as_array().unwrap().len(), 1);
+        assert!(page["next_cursor"].is_null());
+
+        let resp = api_call(&api, "GET", "/accounts?limit=1", "wrong", None).await;
+        assert_eq!(resp.status().as_u16(), 401);
+    }
+
     #[tokio::test]
     async fn only_admin_or_user_can_get_account() {
         let api = test_accounts_api();
@@ -0,0 +1,197 @@
+use crate::{AccountQuery, NodeStore};
+use futures::future::join_all;
+use http::StatusCode;
+use interledger_router::RouterStore;
+use serde::Serialize;
+use std::{collections::HashSet, time::Duration};
+use tracing::warn;
+use url::Url;
+use warp::{self, Filter};
+
+/// How long a settlement engine has to answer the readiness check
+const ENGINE_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Serialize)]
+struct HealthResponse {
+    status: &'static str,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct FailedCheck {
+    subsystem: &'static str,
+    detail: String,
+}
+
+#[derive(Serialize)]
+struct ReadinessResponse {
+    status: &'static str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    failing: Vec<FailedCheck>,
+}
+
+pub fn health_api<S>(
+    store: S,
+) -> impl warp::Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone
+where
+    S: NodeStore + RouterStore,
+{
+    let client = reqwest::Client::builder()
+        .timeout(ENGINE_CHECK_TIMEOUT)
+        .build()
+        .unwrap();
+
+    // GET /health
+    // Answers as long as the node is running
+    let get_health = warp::get()
+        .and(warp::path("health"))
+        .and(warp::path::end())
+        .map(|| warp::reply::json(&HealthResponse { status: "alive" }));
+
+    // GET /ready
+    // Answers with 503 and the failing subsystems if the node cannot handle packets
+    let get_ready = warp::get()
+        .and(warp::path("ready"))
+        .and(warp::path::end())
+        .and(warp::any().map(move || store.clone()))
+        .and(warp::any().map(move || client.clone()))
+        .then(|store: S, client: reqwest::Client| async move {
+            let failing = readiness_failures(&store, &client).await;
+            let (status, code) = if failing.is_empty() {
+                ("ready", StatusCode::OK)
+            } else {
+                ("not_ready", StatusCode::SERVICE_UNAVAILABLE)
+            };
+            warp::reply::with_status(
+                warp::reply::json(&ReadinessResponse { status, failing }),
+                code,
+            )
+        });
+
+    get_health.or(get_ready)
+}
+
+/// Checks the store, then the subsystems whose state is read from it
+async fn readiness_failures<S>(store: &S, client: &reqwest::Client) -> Vec<FailedCheck>
+where
+    S: NodeStore + RouterStore,
+{
+    if let Err(err) = store.check_connection().await {
+        warn!("Store is not reachable: {}", err);
+        return vec![FailedCheck {
+            subsystem: "store",
+            detail: "the store is not reachable".to_string(),
+        }];
+    }
+    // Only look for a single account so that probing doesn't scan the whole store
+    let has_accounts = match store
+        .query_accounts(AccountQuery {
+            limit: Some(1),
+            ..Default::default()
+        })
+        .await
+    {
+        Ok(page) => !page.accounts.is_empty(),
+        Err(err) => {
+            warn!("Unable to load accounts from the store: {}", err);
+            return vec![FailedCheck {
+                subsystem: "store",
+                detail: "the accounts could not be loaded".to_string(),
+            }];
+        }
+    };
+
+    let mut failing = Vec::new();
+    // Every account is routed to, so the routing table is only empty before it is loaded
+    if has_accounts && store.routing_table().is_empty() {
+        failing.push(FailedCheck {
+            subsystem: "routing_table",
+            detail: "the routing table has not been loaded".to_string(),
+        });
+    }
+
+    // Only the node's default engines are checked, since those set on individual
+    // accounts could only be found by loading every account
+    let engines: HashSet<Url> = match store.get_settlement_engines().await {
+        Ok(engines) => engines.into_values().collect(),
+        Err(err) => {
+            warn!(
+                "Unable to load the settlement engines from the store: {}",
+                err
+            );
+            failing.push(FailedCheck {
+                subsystem: "store",
+                detail: "the settlement engines could not be loaded".to_string(),
+            });
+            return failing;
+        }
+    };
+    let unreachable = join_all(engines.iter().map(|url| async move {
+        // Any response means that the engine is up, even if it has nothing at this path
+        match client.get(url.clone()).send().await {
+            Ok(_) => None,
+            Err(err) => {
+                warn!("Settlement engine at {} is not reachable: {}", url, err);
+                Some(url)
+            }
+        }
+    }))
+    .await
+    .into_iter()
+    .flatten()
+    .count();
+    if unreachable > 0 {
+        // The engines' URLs are not listed since this endpoint does not require authentication
+        failing.push(FailedCheck {
+            subsystem: "settlement_engines",
+            detail: format!(
+                "{} of {} settlement engines are not reachable",
+                unreachable,
+                engines.len()
+            ),
+        });
+    }
+
+    failing
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::routes::test_helpers::{api_call, test_health_api};
+    use serde_json::{json, Value};
+
+    #[tokio::test]
+    async fn reports_liveness_without_checking_the_store() {
+        let api = test_health_api(false);
+        let resp = api_call(&api, "GET", "/health", "", None).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        assert_eq!(
+            serde_json::from_slice::<Value>(resp.body()).unwrap(),
+            json!({"status": "alive"})
+        );
+    }
+
+    #[tokio::test]
+    async fn ready_when_every_subsystem_is_up() {
+        let api = test_health_api(true);
+        let resp = api_call(&api, "GET", "/ready", "", None).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        assert_eq!(
+            serde_json::from_slice::<Value>(resp.body()).unwrap(),
+            json!({"status": "ready"})
+        );
+    }
+
+    #[tokio::test]
+    async fn not_ready_when_the_store_is_down() {
+        let api = test_health_api(false);
+        let resp = api_call(&api, "GET", "/ready", "", None).await;
+        assert_eq!(resp.status().as_u16(), 503);
+        assert_eq!(
+            serde_json::from_slice::<Value>(resp.body()).unwrap(),
+            json!({
+                "status": "not_ready",
+                "failing": [{"subsystem": "store", "detail": "the store is not reachable"}],
+            })
+        );
+    }
+}
@@ -1,7 +1,9 @@
 mod accounts;
+mod health;
 mod node_settings;
 
 pub use accounts::accounts_api;
+pub use health::health_api;
 pub use node_settings::node_settings_api;
 
 #[cfg(test)]
@@ -5,7 +5,7 @@ use interledger_errors::*;
 use interledger_http::{deserialize_json, HttpAccount};
 use interledger_packet::Address;
 use interledger_rates::ExchangeRateStore;
-use interledger_router::RouterStore;
+use interledger_router::{RouterStore, WeightedRoute};
 use interledger_service::{Account, AccountStore, AddressStore, Username};
 use interledger_settlement::core::{types::SettlementAccount, SettlementClient};
 use secrecy::{ExposeSecret, SecretString};
@@ -14,7 +14,7 @@ use std::{
     collections::HashMap,
     str::{self, FromStr},
 };
-use tracing::{error, debug};
+use tracing::{debug, error};
 use url::Url;
 use uuid::Uuid;
 use warp::{self, reply::Json, Filter, Rejection};
@@ -176,6 +176,63 @@ where
             }
         });
 
+    // GET /routes/multipath
+    // Response: Map of ILP Address prefix -> Map of Username -> weight
+    let get_multipath_routes = warp::get()
+        .and(warp::path("routes"))
+        .and(warp::path("multipath"))
+        .and(warp::path::end())
+        .and(with_store.clone())
+        .and_then(|store: S| async move {
+            let routes = store.multipath_routing_table();
+            let account_ids: Vec<Uuid> = routes
+                .values()
+                .flatten()
+                .map(|route| route.account_id)
+                .collect();
+            let accounts = store.get_accounts(account_ids).await?;
+            let mut usernames = accounts.iter().map(|a| a.username().to_string());
+            let routes: HashMap<String, HashMap<String, u32>> = routes
+                .iter()
+                .map(|(prefix, routes)| {
+                    let weights = routes
+                        .iter()
+                        .filter_map(|route| Some((usernames.next()?, route.weight)))
+                        .collect();
+                    (prefix.to_string(), weights)
+                })
+                .collect();
+
+            Ok::<Json, Rejection>(warp::reply::json(&routes))
+        });
+
+    // PUT /routes/multipath/:prefix
+    // Body: Map of Username -> weight (an empty map removes the route)
+    let put_multipath_route = warp::put()
+        .and(warp::path("routes"))
+        .and(warp::path("multipath"))
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(admin_only.clone())
+        .and(deserialize_json())
+        .and(with_store.clone())
+        .and_then(
+            |prefix: String, weights: HashMap<String, u32>, store: S| async move {
+                // Convert the usernames to account IDs to set the route in the store
+                let mut routes = Vec::with_capacity(weights.len());
+                for (username, weight) in weights.iter() {
+                    let username = Username::from_str(username)
+                        .map_err(|_| Rejection::from(ApiError::bad_request()))?;
+                    routes.push(WeightedRoute {
+                        account_id: store.get_account_id_from_username(&username).await?,
+                        weight: *weight,
+                    });
+                }
+                store.set_multipath_route(prefix, routes).await?;
+                Ok::<Json, Rejection>(warp::reply::json(&weights))
+            },
+        );
+
     // PUT /settlement/engines
     let put_settlement_engines = warp::put()
         .and(warp::path("settlement"))
@@ -225,6 +282,8 @@ where
         .or(get_routes)
         .or(put_static_routes)
         .or(put_static_route)
+        .or(get_multipath_routes)
+        .or(put_multipath_route)
         .or(put_settlement_engines)
 }
 
@@ -284,6 +343,21 @@ mod tests {
         assert_eq!(resp.status().as_u16(), 401);
     }
 
+    #[tokio::test]
+    async fn only_admin_can_put_multipath_route() {
+        let api = test_node_settings_api();
+        let weights = json!({"alice": 3, "bob": 1});
+        let path = "/routes/multipath/g.node1";
+        let resp = api_call(&api, "PUT", path, "admin", Some(weights.clone())).await;
+        assert_eq!(resp.status().as_u16(), 200);
+
+        let resp = api_call(&api, "PUT", path, "wrong", Some(weights)).await;
+        assert_eq!(resp.status().as_u16(), 401);
+
+        let resp = api_call(&api, "GET", "/routes/multipath", "", None).await;
+        assert_eq!(resp.status().as_u16(), 200);
+    }
+
     #[tokio::test]
     async fn only_admin_can_put_single_static_route() {
         let api = test_node_settings_api();
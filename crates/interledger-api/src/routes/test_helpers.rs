@@ -1,6 +1,6 @@
 use crate::{
-    routes::{accounts_api, node_settings_api},
-    AccountDetails, AccountSettings, NodeStore,
+    routes::{accounts_api, health_api, node_settings_api},
+    AccountDetails, AccountPage, AccountQuery, AccountSettings, NodeStore,
 };
 use async_trait::async_trait;
 use bytes::Bytes;
@@ -12,13 +12,15 @@ use interledger_errors::*;
 use interledger_http::{HttpAccount, HttpStore};
 use interledger_packet::{Address, ErrorCode, FulfillBuilder, RejectBuilder};
 use interledger_rates::ExchangeRateStore;
-use interledger_router::RouterStore;
+use interledger_router::{RouterStore, WeightedRoute};
 use interledger_service::{
     incoming_service_fn, outgoing_service_fn, Account, AccountStore, AddressStore, Username,
 };
 use interledger_service_util::BalanceStore;
 use interledger_settlement::core::types::{SettlementAccount, SettlementEngineDetails};
-use interledger_stream::{PaymentNotification, StreamNotificationsStore};
+use interledger_stream::{
+    AccountNotification, AccountNotificationsStore, PaymentNotification, StreamNotificationsStore,
+};
 use once_cell::sync::Lazy;
 use secrecy::SecretString;
 use serde::{Deserialize, Serialize};
@@ -56,7 +58,8 @@ where
 
 pub fn test_node_settings_api(
 ) -> impl warp::Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
-    node_settings_api("admin".to_owned(), None, TestStore).recover(default_rejection_handler)
+    node_settings_api("admin".to_owned(), None, TestStore::default())
+        .recover(default_rejection_handler)
 }
 
 pub fn test_accounts_api(
@@ -81,7 +84,7 @@ pub fn test_accounts_api(
         Address::from_str("example.alice").unwrap(),
         outgoing.clone(),
     );
-    let store = TestStore;
+    let store = TestStore::default();
     accounts_api(
         Bytes::from("admin"),
         "admin".to_owned(),
@@ -94,13 +97,24 @@ pub fn test_accounts_api(
     .recover(default_rejection_handler)
 }
 
+pub fn test_health_api(
+    store_connected: bool,
+) -> impl warp::Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    health_api(TestStore {
+        disconnected: !store_connected,
+    })
+    .recover(default_rejection_handler)
+}
+
 /*
  * Lots of boilerplate implementations of all necessary traits to launch
  * the crate's APIs in unit tests
  */
 
-#[derive(Clone)]
-struct TestStore;
+#[derive(Clone, Default)]
+struct TestStore {
+    disconnected: bool,
+}
 
 use serde_json::json;
 pub static USERNAME: Lazy<Username> = Lazy::new(|| Username::from_str("alice").unwrap());
@@ -185,12 +199,16 @@ impl AccountStore for TestStore {
         Ok(vec![TestAccount])
     }
 
-    // stub implementation (not used in these tests)
+    // Every username but dave's has an account
     async fn get_account_id_from_username(
         &self,
-        _username: &Username,
+        username: &Username,
     ) -> Result<Uuid, AccountStoreError> {
-        Ok(Uuid::new_v4())
+        if username.as_ref() == "dave" {
+            Err(AccountStoreError::AccountNotFound(username.to_string()))
+        } else {
+            Ok(Uuid::new_v4())
+        }
     }
 }
 
@@ -219,7 +237,9 @@ impl ExchangeRateStore for TestStore {
 
 impl RouterStore for TestStore {
     fn routing_table(&self) -> Arc<HashMap<String, Uuid>> {
-        Arc::new(HashMap::new())
+        let mut table = HashMap::new();
+        table.insert(EXAMPLE_ADDRESS.to_string(), Uuid::new_v4());
+        Arc::new(table)
     }
 }
 
@@ -254,10 +274,27 @@ impl NodeStore for TestStore {
         Ok(TestAccount)
     }
 
+    async fn check_connection(&self) -> Result<(), NodeStoreError> {
+        if self.disconnected {
+            Err(NodeStoreError::Other(Box::new(std::io::Error::from(
+                std::io::ErrorKind::ConnectionRefused,
+            ))))
+        } else {
+            Ok(())
+        }
+    }
+
     async fn get_all_accounts(&self) -> Result<Vec<Self::Account>, NodeStoreError> {
         Ok(vec![TestAccount, TestAccount])
     }
 
+    async fn query_accounts(
+        &self,
+        query: AccountQuery,
+    ) -> Result<AccountPage<Self::Account>, NodeStoreError> {
+        Ok(AccountPage::new(vec![TestAccount], query.page_size()))
+    }
+
     async fn set_static_routes<R>(&self, _routes: R) -> Result<(), NodeStoreError>
     where
         R: IntoIterator<Item = (String, Uuid)> + Send + 'async_trait,
@@ -273,6 +310,14 @@ impl NodeStore for TestStore {
         Ok(())
     }
 
+    async fn set_multipath_route(
+        &self,
+        _prefix: String,
+        _routes: Vec<WeightedRoute>,
+    ) -> Result<(), NodeStoreError> {
+        Ok(())
+    }
+
     async fn set_default_route(&self, _account_id: Uuid) -> Result<(), NodeStoreError> {
         unimplemented!()
     }
@@ -290,6 +335,10 @@ impl NodeStore for TestStore {
     ) -> Result<Option<Url>, NodeStoreError> {
         Ok(None)
     }
+
+    async fn get_settlement_engines(&self) -> Result<HashMap<String, Url>, NodeStoreError> {
+        Ok(HashMap::new())
+    }
 }
 
 #[async_trait]
@@ -329,6 +378,20 @@ impl StreamNotificationsStore for TestStore {
     }
 }
 
+impl AccountNotificationsStore for TestStore {
+    fn add_account_notification_subscription(
+        &self,
+        _id: Uuid,
+        _sender: UnboundedSender<AccountNotification>,
+    ) {
+        unimplemented!()
+    }
+
+    fn publish_account_notification(&self, _id: Uuid, _notification: AccountNotification) {
+        unimplemented!()
+    }
+}
+
 #[async_trait]
 impl BalanceStore for TestStore {
     async fn get_balance(&self, _: Uuid) -> Result<i64, BalanceStoreError> {
@@ -1,17 +1,75 @@
 use super::packet::*;
-use super::service::BtpOutgoingService;
+use super::service::{BtpOutgoingService, LinkState, WeakBtpOutgoingService};
 use super::BtpAccount;
 use futures::{future::join_all, SinkExt, StreamExt, TryFutureExt};
 use interledger_errors::ApiError;
 use interledger_packet::Address;
 use interledger_service::*;
 use rand::random;
+use std::convert::TryFrom;
+use std::time::Duration;
 use thiserror::Error;
 use tokio_tungstenite::connect_async;
 use tokio_tungstenite::tungstenite::Message;
-use tracing::{debug, error};
+use tracing::{debug, error, warn};
 use url::Url;
 
+/// How the client connects again after a BTP connection drops.
+///
+/// The `n`th attempt in a row is made after waiting `base_delay * backoff_multiplier^(n - 1)`,
+/// capped at `max_delay`, with up to `jitter` of that delay randomly taken off so that
+/// peers which lost their links at the same time don't all come back at once.
+/// After `max_attempts` failed attempts the link is given up on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReconnectPolicy {
+    /// Number of failed attempts in a row after which the link is closed
+    pub max_attempts: u32,
+    /// Delay before the first attempt
+    pub base_delay: Duration,
+    /// Longest delay between two attempts
+    pub max_delay: Duration,
+    /// Factor the delay grows by with each failed attempt
+    pub backoff_multiplier: f64,
+    /// Fraction of each delay, between 0 and 1, randomly taken off of it
+    pub jitter: f64,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            max_attempts: u32::MAX,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(60),
+            backoff_multiplier: 2.0,
+            jitter: 0.5,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Time to wait before the given attempt, counting from 1
+    pub fn delay(&self, attempt: u32) -> Duration {
+        self.delay_with_jitter(attempt, random::<f64>())
+    }
+
+    /// Delay with `random`, between 0 and 1, of the jitter applied
+    fn delay_with_jitter(&self, attempt: u32, random: f64) -> Duration {
+        if attempt == 0 {
+            return Duration::from_secs(0);
+        }
+        let exponent = i32::try_from(attempt - 1).unwrap_or(i32::MAX);
+        let backoff =
+            self.base_delay.as_secs_f64() * self.backoff_multiplier.max(1.0).powi(exponent);
+        let capped = backoff.min(self.max_delay.as_secs_f64());
+        let jitter = if self.jitter > 0.0 {
+            self.jitter.min(1.0)
+        } else {
+            0.0
+        };
+        Duration::from_secs_f64(capped * (1.0 - jitter * random))
+    }
+}
+
 /// Create a BtpOutgoingService wrapping BTP connections to the accounts specified.
 /// Calling `handle_incoming` with an `IncomingService` will turn the returned
 /// BtpOutgoingService into a bidirectional handler.
@@ -22,7 +80,7 @@ pub async fn connect_client<A, S>(
     next_outgoing: S,
 ) -> Result<BtpOutgoingService<S, A>, BtpClientError>
 where
-    S: OutgoingService<A> + Clone + 'static,
+    S: OutgoingService<A> + Clone + Send + 'static,
     A: BtpAccount + Send + Sync + 'static,
 {
     let service = BtpOutgoingService::new(ilp_address, next_outgoing);
@@ -64,14 +122,126 @@ impl From<BtpClientError> for warp::Rejection {
 /// 1. Initialize a WebSocket connection at the BTP account's URL
 /// 2. Send a BTP authorization packet to the peer
 /// 3. If successful, consider the BTP connection established and add it to the service
+///
+/// If the connection drops later on, the client connects again following the default
+/// [`ReconnectPolicy`].
 pub async fn connect_to_service_account<O, A>(
     account: A,
     error_on_unavailable: bool,
     service: BtpOutgoingService<O, A>,
 ) -> Result<(), BtpClientError>
 where
-    O: OutgoingService<A> + Clone + 'static,
+    O: OutgoingService<A> + Clone + Send + 'static,
+    A: BtpAccount + Send + Sync + 'static,
+{
+    connect_to_service_account_with_reconnect_policy(
+        account,
+        error_on_unavailable,
+        service,
+        ReconnectPolicy::default(),
+    )
+    .await
+}
+
+/// Same as [`connect_to_service_account`], but reconnects following the given policy
+pub async fn connect_to_service_account_with_reconnect_policy<O, A>(
+    account: A,
+    error_on_unavailable: bool,
+    service: BtpOutgoingService<O, A>,
+    reconnect_policy: ReconnectPolicy,
+) -> Result<(), BtpClientError>
+where
+    O: OutgoingService<A> + Clone + Send + 'static,
+    A: BtpAccount + Send + Sync + 'static,
+{
+    match connect_and_authenticate(&account).await {
+        Ok(connection) => {
+            let closed = service.add_reconnecting_connection(account.clone(), connection);
+            tokio::spawn(reconnect(
+                account,
+                service.downgrade(),
+                closed,
+                reconnect_policy,
+            ));
+            Ok(())
+        }
+        Err(err @ BtpClientError::CannotConnect(..)) => Err(err),
+        Err(err) => {
+            if error_on_unavailable {
+                Err(err)
+            } else {
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Connects to the account again each time its connection drops, until the
+/// link or the service is closed or the policy gives up
+async fn reconnect<O, A>(
+    account: A,
+    service: WeakBtpOutgoingService<O, A>,
+    mut closed: futures::channel::oneshot::Receiver<()>,
+    policy: ReconnectPolicy,
+) where
+    O: OutgoingService<A> + Clone + Send + 'static,
     A: BtpAccount + Send + Sync + 'static,
+{
+    let account_id = account.id();
+    let mut attempt = 0;
+    loop {
+        let _ = (&mut closed).await;
+        loop {
+            match service.upgrade() {
+                Some(service) if service.link_state(&account_id) == LinkState::Reconnecting => {}
+                Some(service) => {
+                    service.mark_closed(&account_id);
+                    return;
+                }
+                None => return,
+            }
+            if attempt >= policy.max_attempts {
+                warn!(
+                    "Giving up on reconnecting to account {} after {} attempts",
+                    account_id, attempt
+                );
+                if let Some(service) = service.upgrade() {
+                    service.mark_closed(&account_id);
+                }
+                return;
+            }
+            attempt += 1;
+            let delay = policy.delay(attempt);
+            debug!(
+                "Reconnecting to account {} in {:?} (attempt {})",
+                account_id, delay, attempt
+            );
+            tokio::time::sleep(delay).await;
+
+            match connect_and_authenticate(&account).await {
+                Ok(connection) => match service.upgrade() {
+                    Some(service) if service.link_state(&account_id) == LinkState::Reconnecting => {
+                        closed = service.add_reconnecting_connection(account.clone(), connection);
+                        attempt = 0;
+                        break;
+                    }
+                    _ => return,
+                },
+                Err(err) => warn!("Error reconnecting to account {}: {}", account_id, err),
+            }
+        }
+    }
+}
+
+/// Opens a WebSocket connection to the account's BTP URL and sends the BTP auth packet over it
+async fn connect_and_authenticate<A>(
+    account: &A,
+) -> Result<
+    impl futures::Stream<Item = Message> + futures::Sink<Message> + Send + 'static,
+    BtpClientError,
+>
+where
+    A: BtpAccount,
 {
     let account_id = account.id();
     let mut url = account
@@ -140,18 +310,40 @@ where
     match result {
         Ok(_) => {
             debug!("Connected to account {}'s server", account.id());
-            let connection = connection.filter_map(|v| async move { v.ok() });
-            service.add_connection(account, connection);
-            Ok(())
+            Ok(connection.filter_map(|v| async move { v.ok() }))
         }
         Err(err) => {
             let msg = format!("Error sending auth packet on connection {}: {}", url, err);
             error!("{}", msg);
-            if error_on_unavailable {
-                Err(BtpClientError::Unavailable(msg))
-            } else {
-                Ok(())
-            }
+            Err(BtpClientError::Unavailable(msg))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> ReconnectPolicy {
+        ReconnectPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(1000),
+            backoff_multiplier: 2.0,
+            jitter: 0.5,
+        }
+    }
+
+    #[test]
+    fn backs_off_exponentially() {
+        let delays: Vec<_> = (1..7)
+            .map(|attempt| policy().delay_with_jitter(attempt, 0.0).as_millis())
+            .collect();
+        assert_eq!(delays, vec![100, 200, 400, 800, 1000, 1000]);
+        assert_eq!(policy().delay_with_jitter(3, 1.0).as_millis(), 200);
+        for _ in 0..100 {
+            let delay = policy().delay(3);
+            assert!(delay >= Duration::from_millis(200) && delay <= Duration::from_millis(400));
         }
     }
 }
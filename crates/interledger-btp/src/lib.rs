@@ -17,9 +17,12 @@ mod server;
 mod service;
 mod wrapped_ws;
 
-pub use self::client::{connect_client, connect_to_service_account};
+pub use self::client::{
+    connect_client, connect_to_service_account, connect_to_service_account_with_reconnect_policy,
+    ReconnectPolicy,
+};
 pub use self::server::btp_service_as_filter; // This is consumed only by the node.
-pub use self::service::{BtpOutgoingService, BtpService};
+pub use self::service::{BtpOutgoingService, BtpService, LinkState};
 
 use interledger_errors::BtpStoreError;
 
@@ -70,9 +73,15 @@ pub mod fuzzing {
 
 #[cfg(test)]
 mod client_server {
+    use super::packet::{BtpPacket, BtpResponse, ContentType, ProtocolData, Serializable};
     use super::*;
-    use interledger_packet::{Address, ErrorCode, FulfillBuilder, PrepareBuilder, RejectBuilder};
+    use bytes::BytesMut;
+    use futures::{SinkExt, StreamExt};
+    use interledger_packet::{
+        Address, ErrorCode, Fulfill, FulfillBuilder, PrepareBuilder, RejectBuilder,
+    };
     use interledger_service::*;
+    use parking_lot::Mutex;
     use socket2::{Domain, Socket, Type};
     use std::str::FromStr;
     use std::{
@@ -81,6 +90,7 @@ mod client_server {
         time::{Duration, SystemTime},
     };
     use uuid::Uuid;
+    use warp::Filter;
 
     use once_cell::sync::Lazy;
 
@@ -326,4 +336,213 @@ mod client_server {
 
         btp_service.close();
     }
+
+    fn test_request(account: &TestAccount) -> OutgoingRequest<TestAccount> {
+        OutgoingRequest {
+            from: account.clone(),
+            to: account.clone(),
+            original_amount: 100,
+            prepare: PrepareBuilder {
+                destination: Address::from_str("example.destination").unwrap(),
+                amount: 100,
+                execution_condition: &[0; 32],
+                expires_at: SystemTime::now() + Duration::from_secs(30),
+                data: b"test data",
+            }
+            .build(),
+        }
+    }
+
+    #[tokio::test]
+    async fn reconnects_after_connection_drops() {
+        let bind_addr = get_open_port();
+
+        // Mock BTP server recording the auth tokens it gets. It drops the first
+        // connection as soon as a Prepare comes in and fulfills Prepares on later ones.
+        let auth_tokens: Arc<Mutex<Vec<Vec<u8>>>> = Arc::new(Mutex::new(Vec::new()));
+        let auth_tokens_clone = auth_tokens.clone();
+        let filter = warp::ws().map(move |ws: warp::ws::Ws| {
+            let auth_tokens = auth_tokens_clone.clone();
+            ws.on_upgrade(move |mut socket| async move {
+                let auth = socket.next().await.unwrap().unwrap();
+                let connection_number = {
+                    let mut auth_tokens = auth_tokens.lock();
+                    match BtpPacket::from_bytes(auth.as_bytes()).unwrap() {
+                        BtpPacket::Message(message) => auth_tokens.push(
+                            message
+                                .protocol_data
+                                .into_iter()
+                                .find(|data| data.protocol_name == "auth_token")
+                                .unwrap()
+                                .data,
+                        ),
+                        _ => panic!("Expected auth message"),
+                    }
+                    auth_tokens.len()
+                };
+                while let Some(Ok(message)) = socket.next().await {
+                    if !message.is_binary() {
+                        continue;
+                    }
+                    if connection_number == 1 {
+                        let _ = socket.close().await;
+                        return;
+                    }
+                    let request_id = match BtpPacket::from_bytes(message.as_bytes()).unwrap() {
+                        BtpPacket::Message(message) => message.request_id,
+                        BtpPacket::Response(response) => response.request_id,
+                        BtpPacket::Error(_) => panic!("Unexpected BTP error"),
+                    };
+                    let fulfill = FulfillBuilder {
+                        fulfillment: &[0; 32],
+                        data: b"reconnected",
+                    }
+                    .build();
+                    let response = BtpResponse {
+                        request_id,
+                        protocol_data: vec![ProtocolData {
+                            protocol_name: "ilp".into(),
+                            content_type: ContentType::ApplicationOctetStream,
+                            data: BytesMut::from(fulfill).to_vec(),
+                        }],
+                    };
+                    socket
+                        .send(warp::ws::Message::binary(response.to_bytes()))
+                        .await
+                        .unwrap();
+                }
+            })
+        });
+        tokio::spawn(warp::serve(filter).bind(bind_addr));
+
+        let account = TestAccount {
+            id: Uuid::new_v4(),
+            ilp_over_btp_url: Some(Url::parse(&format!("btp+ws://{}", bind_addr)).unwrap()),
+            ilp_over_btp_outgoing_token: Some("test_auth_token".to_string()),
+            ilp_over_btp_incoming_token: None,
+        };
+        let addr = Address::from_str("example.address").unwrap();
+        let addr_clone = addr.clone();
+        let service = BtpOutgoingService::new(
+            addr.clone(),
+            outgoing_service_fn(move |_| {
+                Err(RejectBuilder {
+                    code: ErrorCode::F02_UNREACHABLE,
+                    message: &[],
+                    data: &[],
+                    triggered_by: Some(&addr_clone),
+                }
+                .build())
+            }),
+        );
+        connect_to_service_account_with_reconnect_policy(
+            account.clone(),
+            true,
+            service.clone(),
+            ReconnectPolicy {
+                base_delay: Duration::from_millis(10),
+                ..ReconnectPolicy::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(service.link_state(&account.id), LinkState::Connected);
+
+        let mut client = service
+            .clone()
+            .handle_incoming(incoming_service_fn(move |_| {
+                Err(RejectBuilder {
+                    code: ErrorCode::F02_UNREACHABLE,
+                    message: &[],
+                    data: &[],
+                    triggered_by: Some(&addr),
+                }
+                .build())
+            }))
+            .await;
+
+        // The request in flight when the connection drops fails right away
+        let reject = client
+            .send_request(test_request(&account))
+            .await
+            .unwrap_err();
+        assert_eq!(reject.code(), ErrorCode::T01_PEER_UNREACHABLE);
+        assert_ne!(service.link_state(&account.id), LinkState::Closed);
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while service.link_state(&account.id) != LinkState::Connected {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("Client did not reconnect");
+
+        let fulfill: Fulfill = client.send_request(test_request(&account)).await.unwrap();
+        assert_eq!(fulfill.data(), b"reconnected");
+        assert_eq!(
+            *auth_tokens.lock(),
+            vec![b"test_auth_token".to_vec(), b"test_auth_token".to_vec()]
+        );
+
+        service.close();
+        assert_eq!(service.link_state(&account.id), LinkState::Closed);
+    }
+
+    #[tokio::test]
+    async fn times_out_unanswered_requests() {
+        let bind_addr = get_open_port();
+        // Mock BTP server that reads everything and never responds
+        let filter = warp::ws().map(|ws: warp::ws::Ws| {
+            ws.on_upgrade(|mut socket| async move { while socket.next().await.is_some() {} })
+        });
+        tokio::spawn(warp::serve(filter).bind(bind_addr));
+
+        let account = TestAccount {
+            id: Uuid::new_v4(),
+            ilp_over_btp_url: Some(Url::parse(&format!("btp+ws://{}", bind_addr)).unwrap()),
+            ilp_over_btp_outgoing_token: Some("test_auth_token".to_string()),
+            ilp_over_btp_incoming_token: None,
+        };
+        let addr = Address::from_str("example.address").unwrap();
+        let service = BtpOutgoingService::new(
+            addr.clone(),
+            outgoing_service_fn(move |_| {
+                Err(RejectBuilder {
+                    code: ErrorCode::F02_UNREACHABLE,
+                    message: &[],
+                    data: &[],
+                    triggered_by: Some(&addr),
+                }
+                .build())
+            }),
+        )
+        .with_request_timeout(Duration::from_millis(100));
+        connect_to_service_account(account.clone(), true, service.clone())
+            .await
+            .unwrap();
+
+        // A request the caller gave up on is swept up by the next sweep
+        let mut client = service.clone();
+        let abandoned = tokio::time::timeout(
+            Duration::from_millis(20),
+            client.send_request(test_request(&account)),
+        )
+        .await;
+        assert!(abandoned.is_err());
+        assert_eq!(service.pending_request_count(), 1);
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        assert_eq!(service.pending_request_count(), 0);
+
+        let reject = tokio::time::timeout(
+            Duration::from_secs(5),
+            client.send_request(test_request(&account)),
+        )
+        .await
+        .expect("Request did not time out")
+        .unwrap_err();
+        assert_eq!(reject.code(), ErrorCode::R00_TRANSFER_TIMED_OUT);
+        assert_eq!(service.pending_request_count(), 0);
+
+        service.close();
+    }
 }
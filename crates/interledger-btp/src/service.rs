@@ -14,6 +14,7 @@ use once_cell::sync::Lazy;
 use parking_lot::{Mutex, RwLock};
 use rand::random;
 use std::collections::HashMap;
+use std::sync::Weak;
 use std::{convert::TryFrom, iter::IntoIterator, marker::PhantomData, sync::Arc, time::Duration};
 use stream_cancel::{Trigger, Valve};
 use tokio::time;
@@ -27,12 +28,31 @@ static PING: Lazy<Message> = Lazy::new(|| Message::Ping(Vec::with_capacity(0)));
 static PONG: Lazy<Message> = Lazy::new(|| Message::Pong(Vec::with_capacity(0)));
 
 // Return a Reject timeout if the outgoing message future does not complete
-// within this timeout. This will probably happen if the peer closed the websocket
-// with us
-const SEND_MSG_TIMEOUT: Duration = Duration::from_secs(30);
+// within this timeout, unless another one was set with `with_request_timeout`.
+// This will probably happen if the peer closed the websocket with us
+const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
 
 type IlpResultChannel = oneshot::Sender<Result<Fulfill, Reject>>;
 type IncomingRequestBuffer<A> = UnboundedReceiver<(A, u32, Prepare)>;
+type PendingRequests = Arc<Mutex<HashMap<u32, PendingRequest>>>;
+
+/// An outgoing request awaiting a response
+struct PendingRequest {
+    /// The connection the request was sent on
+    connection: UnboundedSender<Message>,
+    response: IlpResultChannel,
+}
+
+/// State of the BTP link to an account
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkState {
+    /// A WebSocket connection to the account is open
+    Connected,
+    /// The connection dropped and the client is trying to connect again
+    Reconnecting,
+    /// There is no connection and none is being attempted
+    Closed,
+}
 
 /// The BtpOutgoingService wraps all BTP/WebSocket connections that come
 /// in on the given address. It implements OutgoingService for sending
@@ -47,7 +67,9 @@ pub struct BtpOutgoingService<O, A: Account> {
     ilp_address: Address,
     /// Outgoing messages for the receiver of the websocket indexed by account uid
     connections: Arc<RwLock<HashMap<Uuid, UnboundedSender<Message>>>>,
-    pending_outgoing: Arc<Mutex<HashMap<u32, IlpResultChannel>>>,
+    link_states: Arc<RwLock<HashMap<Uuid, LinkState>>>,
+    pending_outgoing: PendingRequests,
+    request_timeout: Duration,
     pending_incoming: Arc<Mutex<Option<IncomingRequestBuffer<A>>>>,
     incoming_sender: UnboundedSender<(A, u32, Prepare)>,
     next: O,
@@ -68,7 +90,7 @@ async fn handle_message<A: BtpAccount>(
     message: Message,
     tx_clone: UnboundedSender<Message>,
     account: A,
-    pending_requests: Arc<Mutex<HashMap<u32, IlpResultChannel>>>,
+    pending_requests: PendingRequests,
     incoming_sender: UnboundedSender<(A, u32, Prepare)>,
 ) {
     if message.is_binary() {
@@ -77,8 +99,7 @@ async fn handle_message<A: BtpAccount>(
             Ok((request_id, Packet::Prepare(prepare))) => {
                 debug!(
                     "Got incoming Prepare packet on request ID: {} {:?}",
-                    request_id,
-                    prepare
+                    request_id, prepare
                 );
                 let _ = incoming_sender
                     .unbounded_send((account, request_id, prepare))
@@ -87,8 +108,8 @@ async fn handle_message<A: BtpAccount>(
             // Sends the fulfill/reject to the outgoing service
             Ok((request_id, Packet::Fulfill(fulfill))) => {
                 debug!("Got fulfill response to request id {}", request_id);
-                if let Some(channel) = (*pending_requests.lock()).remove(&request_id) {
-                    let _ = channel.send(Ok(fulfill)).map_err(|fulfill| error!("Error forwarding Fulfill packet back to the Future that sent the Prepare: {:?}", fulfill));
+                if let Some(pending) = (*pending_requests.lock()).remove(&request_id) {
+                    let _ = pending.response.send(Ok(fulfill)).map_err(|fulfill| error!("Error forwarding Fulfill packet back to the Future that sent the Prepare: {:?}", fulfill));
                 } else {
                     warn!(
                         "Got Fulfill packet that does not match an outgoing Prepare we sent: {:?}",
@@ -98,8 +119,8 @@ async fn handle_message<A: BtpAccount>(
             }
             Ok((request_id, Packet::Reject(reject))) => {
                 debug!("Got reject response to request id {}", request_id);
-                if let Some(channel) = (*pending_requests.lock()).remove(&request_id) {
-                    let _ = channel.send(Err(reject)).map_err(|reject| error!("Error forwarding Reject packet back to the Future that sent the Prepare: {:?}", reject));
+                if let Some(pending) = (*pending_requests.lock()).remove(&request_id) {
+                    let _ = pending.response.send(Err(reject)).map_err(|reject| error!("Error forwarding Reject packet back to the Future that sent the Prepare: {:?}", reject));
                 } else {
                     warn!(
                         "Got Reject packet that does not match an outgoing Prepare we sent: {:?}",
@@ -132,7 +153,9 @@ where
         BtpOutgoingService {
             ilp_address,
             connections: Arc::new(RwLock::new(HashMap::new())),
+            link_states: Arc::new(RwLock::new(HashMap::new())),
             pending_outgoing: Arc::new(Mutex::new(HashMap::new())),
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
             pending_incoming: Arc::new(Mutex::new(Some(incoming_receiver))),
             incoming_sender,
             next,
@@ -141,9 +164,26 @@ where
         }
     }
 
+    /// Sets how long outgoing requests wait for the peer to respond before they are
+    /// rejected with `R00_TRANSFER_TIMED_OUT`. Defaults to 30 seconds.
+    pub fn with_request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = timeout;
+        self
+    }
+
+    /// Number of outgoing requests still waiting for a response
+    #[cfg(test)]
+    pub(crate) fn pending_request_count(&self) -> usize {
+        self.pending_outgoing.lock().len()
+    }
+
     /// Deletes the websocket associated with the provided `account_id`
+    /// and stops the client from reconnecting to it
     pub fn close_connection(&self, account_id: &Uuid) {
         self.connections.write().remove(account_id);
+        self.link_states
+            .write()
+            .insert(*account_id, LinkState::Closed);
     }
 
     /// Close all of the open WebSocket connections
@@ -152,6 +192,39 @@ where
     pub fn close(&self) {
         debug!("Closing all WebSocket connections");
         self.close_all_connections.lock().take();
+        for state in self.link_states.write().values_mut() {
+            *state = LinkState::Closed;
+        }
+    }
+
+    /// Returns the state of the BTP link to the given account
+    /// (`Closed` if there has never been one)
+    pub fn link_state(&self, account_id: &Uuid) -> LinkState {
+        self.link_states
+            .read()
+            .get(account_id)
+            .copied()
+            .unwrap_or(LinkState::Closed)
+    }
+
+    /// Marks a link whose connection dropped as closed, unless a new connection took its place
+    pub(crate) fn mark_closed(&self, account_id: &Uuid) {
+        if let Some(state) = self.link_states.write().get_mut(account_id) {
+            if *state == LinkState::Reconnecting {
+                *state = LinkState::Closed;
+            }
+        }
+    }
+
+    /// Returns a handle to this service that does not keep its connections open
+    pub(crate) fn downgrade(&self) -> WeakBtpOutgoingService<O, A> {
+        WeakBtpOutgoingService {
+            service: BtpOutgoingService {
+                close_all_connections: Arc::new(Mutex::new(None)),
+                ..self.clone()
+            },
+            close_all_connections: Arc::downgrade(&self.close_all_connections),
+        }
     }
 
     // Set up a WebSocket connection so that outgoing Prepare packets can be sent to it,
@@ -163,6 +236,27 @@ where
         account: A,
         ws_stream: impl Stream<Item = Message> + Sink<Message> + Send + 'static,
     ) {
+        // Nobody needs to hear about the connection closing, so the receiver is dropped
+        drop(self.spawn_connection(account, ws_stream, LinkState::Closed));
+    }
+
+    // Same as `add_connection`, but the link is marked as `Reconnecting` rather than `Closed`
+    // once the connection drops. The returned receiver resolves at that point, so that the
+    // client can connect again.
+    pub(crate) fn add_reconnecting_connection(
+        &self,
+        account: A,
+        ws_stream: impl Stream<Item = Message> + Sink<Message> + Send + 'static,
+    ) -> oneshot::Receiver<()> {
+        self.spawn_connection(account, ws_stream, LinkState::Reconnecting)
+    }
+
+    fn spawn_connection(
+        &self,
+        account: A,
+        ws_stream: impl Stream<Item = Message> + Sink<Message> + Send + 'static,
+        state_after_close: LinkState,
+    ) -> oneshot::Receiver<()> {
         let account_id = account.id();
         // Set up a channel to forward outgoing packets to the WebSocket connection
         let (client_tx, client_rx) = unbounded();
@@ -201,11 +295,39 @@ where
         // Close connections trigger
         let read = valve.wrap(read); // close when `write_to_ws` calls `drop(connection)`
         let read = self.stream_valve.wrap(read);
+        let (closed_sender, closed_receiver) = oneshot::channel();
+        let connections = self.connections.clone();
+        let link_states = self.link_states.clone();
+        let pending_outgoing = self.pending_outgoing.clone();
+        let ilp_address = self.ilp_address.clone();
+        let connection = client_tx.clone();
         let read_from_ws = read.for_each(handle_message_fn).then(move |_| async move {
             debug!(
                 "Finished reading from WebSocket stream for account: {}",
                 account_id
             );
+            // Stop writing to the socket too, in case it was the peer that went away
+            connection.close_channel();
+            let is_current = {
+                let mut connections = connections.write();
+                let is_current = connections
+                    .get(&account_id)
+                    .map(|current| current.same_receiver(&connection))
+                    .unwrap_or(false);
+                if is_current {
+                    connections.remove(&account_id);
+                }
+                is_current
+            };
+            if is_current {
+                if let Some(state) = link_states.write().get_mut(&account_id) {
+                    if *state == LinkState::Connected {
+                        *state = state_after_close;
+                    }
+                }
+            }
+            fail_pending_requests(&pending_outgoing, &connection, &ilp_address);
+            let _ = closed_sender.send(());
             Ok::<(), ()>(())
         });
         tokio::spawn(read_from_ws);
@@ -229,8 +351,26 @@ where
         });
         tokio::spawn(send_pings);
 
+        // Every request timeout, sweep up the requests on this connection whose
+        // sender went away without waiting for the response
+        let pending_outgoing = self.pending_outgoing.clone();
+        let connection = client_tx.clone();
+        // (`interval` panics on a zero period)
+        let sweep_interval = time::interval(self.request_timeout.max(Duration::from_millis(1)));
+        let sweep_stream = tokio_stream::wrappers::IntervalStream::new(sweep_interval);
+        let repeat_until_service_drops = self.stream_valve.wrap(sweep_stream);
+        let sweep_pending = valve.wrap(repeat_until_service_drops).for_each(move |_| {
+            reap_pending_requests(&pending_outgoing, &connection);
+            future::ready(())
+        });
+        tokio::spawn(sweep_pending);
+
         // Save the sender side of the channel so we have a way to forward outgoing requests to the WebSocket
         self.connections.write().insert(account_id, client_tx);
+        self.link_states
+            .write()
+            .insert(account_id, LinkState::Connected);
+        closed_receiver
     }
 
     /// Convert this BtpOutgoingService into a bidirectional BtpService by adding a handler for incoming requests.
@@ -336,12 +476,18 @@ where
             )) {
                 Ok(_) => {
                     let (sender, receiver) = oneshot::channel();
-                    (*self.pending_outgoing.lock()).insert(request_id, sender);
+                    (*self.pending_outgoing.lock()).insert(
+                        request_id,
+                        PendingRequest {
+                            connection,
+                            response: sender,
+                        },
+                    );
 
                     // Wrap the receiver with a timeout to ensure we do not
                     // wait too long if the other party has disconnected
                     // FIXME: this causes the test case to take 30s
-                    let result = tokio::time::timeout(SEND_MSG_TIMEOUT, receiver).await;
+                    let result = tokio::time::timeout(self.request_timeout, receiver).await;
 
                     let result = match result {
                         Ok(packet) => packet,
@@ -412,6 +558,85 @@ where
     }
 }
 
+/// A handle to a [`BtpOutgoingService`] that does not keep its connections open,
+/// used by the client's reconnection loop
+pub(crate) struct WeakBtpOutgoingService<O, A: Account> {
+    /// The service, with a detached `close_all_connections` trigger
+    service: BtpOutgoingService<O, A>,
+    close_all_connections: Weak<Mutex<Option<Trigger>>>,
+}
+
+impl<O, A> WeakBtpOutgoingService<O, A>
+where
+    O: Clone,
+    A: Account,
+{
+    /// Returns the service unless it was closed or dropped
+    pub(crate) fn upgrade(&self) -> Option<BtpOutgoingService<O, A>> {
+        let close_all_connections = self.close_all_connections.upgrade()?;
+        if close_all_connections.lock().is_none() {
+            return None;
+        }
+        Some(BtpOutgoingService {
+            close_all_connections,
+            ..self.service.clone()
+        })
+    }
+}
+
+/// Removes the pending requests sent on `connection` that match the predicate
+fn take_pending_requests(
+    pending_requests: &PendingRequests,
+    connection: &UnboundedSender<Message>,
+    predicate: impl Fn(&PendingRequest) -> bool,
+) -> Vec<PendingRequest> {
+    let mut pending_requests = pending_requests.lock();
+    let request_ids: Vec<u32> = pending_requests
+        .iter()
+        .filter(|(_, pending)| pending.connection.same_receiver(connection) && predicate(pending))
+        .map(|(request_id, _)| *request_id)
+        .collect();
+    request_ids
+        .into_iter()
+        .filter_map(|request_id| pending_requests.remove(&request_id))
+        .collect()
+}
+
+/// Rejects the requests still waiting for a response on a connection that closed
+fn fail_pending_requests(
+    pending_requests: &PendingRequests,
+    connection: &UnboundedSender<Message>,
+    ilp_address: &Address,
+) {
+    for pending in take_pending_requests(pending_requests, connection, |_| true) {
+        let _ = pending.response.send(Err(RejectBuilder {
+            code: ErrorCode::T01_PEER_UNREACHABLE,
+            message: b"BTP connection closed before the peer responded",
+            triggered_by: Some(ilp_address),
+            data: &[],
+        }
+        .build()));
+    }
+}
+
+/// Removes the requests on a connection that nobody is waiting on anymore, because
+/// the future sending them was dropped before the response came in. Requests that
+/// are still awaited are removed by `send_request` once they time out.
+fn reap_pending_requests(
+    pending_requests: &PendingRequests,
+    connection: &UnboundedSender<Message>,
+) {
+    let orphaned = take_pending_requests(pending_requests, connection, |pending| {
+        pending.response.is_canceled()
+    });
+    if !orphaned.is_empty() {
+        debug!(
+            "Removed {} BTP requests nobody was waiting on",
+            orphaned.len()
+        );
+    }
+}
+
 #[derive(Clone)]
 pub struct BtpService<I, O, A: Account> {
     outgoing: BtpOutgoingService<O, A>,
@@ -432,6 +657,11 @@ where
     pub fn close_connection(&self, account_id: &Uuid) {
         self.outgoing.close_connection(account_id);
     }
+
+    /// Returns the state of the BTP link to the given account
+    pub fn link_state(&self, account_id: &Uuid) -> LinkState {
+        self.outgoing.link_state(account_id)
+    }
 }
 
 #[async_trait]
@@ -19,12 +19,14 @@ use uuid::Uuid;
 #[cfg(test)]
 mod fixtures;
 mod packet;
+mod policy;
 mod routing_table;
 mod server;
 #[cfg(test)]
 mod test_helpers;
 
 pub use packet::{Mode, RouteControlRequest};
+pub use policy::{RouteAction, RoutePolicy, RouteRule};
 pub use server::{CcpRouteManager, CcpRouteManagerBuilder};
 
 use serde::{Deserialize, Serialize};
@@ -103,6 +105,12 @@ pub trait CcpRoutingAccount: Account {
         self.routing_relation() == RoutingRelation::Parent
             || self.routing_relation() == RoutingRelation::Peer
     }
+
+    /// Limits which routes are included in the CCP Route Updates sent to this account.
+    /// All routes are sent to accounts without a policy
+    fn route_policy(&self) -> Option<&RoutePolicy> {
+        None
+    }
 }
 
 // key = Bytes, key should be Address -- TODO
@@ -0,0 +1,142 @@
+use serde::{Deserialize, Serialize};
+
+/// Whether a route matched by a rule is advertised to the account or not
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RouteAction {
+    #[default]
+    Allow,
+    Deny,
+}
+
+/// A rule matching routes by their prefix, their origin or both.
+/// A rule that sets neither matches every route.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RouteRule {
+    pub action: RouteAction,
+    /// Matches routes for this prefix or any prefix under it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+    /// Matches routes that originate at this address or any address under it.
+    /// The origin is the last node in the route's path, i.e. the one that first
+    /// advertised the route, or this node for routes without a path
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+}
+
+/// Decides which routes are advertised to an account in CCP Route Updates,
+/// for example to avoid advertising the routes learned from a provider to
+/// customers. The first rule that matches a route decides whether it is advertised,
+/// and routes that no rule matches are handled with the `default` action.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct RoutePolicy {
+    #[serde(default)]
+    pub rules: Vec<RouteRule>,
+    #[serde(default)]
+    pub default: RouteAction,
+}
+
+impl RoutePolicy {
+    /// Returns whether the route for the given prefix, which originated at the
+    /// given address, may be advertised
+    pub fn allows(&self, prefix: &str, origin: &str) -> bool {
+        let action = self
+            .rules
+            .iter()
+            .find(|rule| {
+                rule.prefix
+                    .as_ref()
+                    .is_none_or(|rule_prefix| is_under(prefix, rule_prefix))
+                    && rule
+                        .origin
+                        .as_ref()
+                        .is_none_or(|rule_origin| is_under(origin, rule_origin))
+            })
+            .map_or(self.default, |rule| rule.action);
+        action == RouteAction::Allow
+    }
+}
+
+/// Checks whether the address is the given prefix or one of its descendants,
+/// comparing whole segments so that `example.alicia` is not under `example.alice`
+fn is_under(address: &str, prefix: &str) -> bool {
+    let prefix = prefix.strip_suffix('.').unwrap_or(prefix);
+    prefix.is_empty()
+        || address
+            .strip_prefix(prefix)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(action: RouteAction, prefix: Option<&str>, origin: Option<&str>) -> RouteRule {
+        RouteRule {
+            action,
+            prefix: prefix.map(String::from),
+            origin: origin.map(String::from),
+        }
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let policy = RoutePolicy {
+            rules: vec![
+                rule(RouteAction::Allow, Some("example.provider.public"), None),
+                rule(RouteAction::Deny, Some("example.provider"), None),
+            ],
+            default: RouteAction::Allow,
+        };
+        assert!(policy.allows("example.provider.public.a", "example.provider"));
+        assert!(!policy.allows("example.provider", "example.provider"));
+        assert!(!policy.allows("example.provider.other", "example.provider"));
+        assert!(policy.allows("example.providers", "example.providers"));
+        assert!(policy.allows("example.other", "example.other"));
+    }
+
+    #[test]
+    fn matches_origin() {
+        let policy = RoutePolicy {
+            rules: vec![rule(RouteAction::Deny, None, Some("example.provider"))],
+            default: RouteAction::Allow,
+        };
+        assert!(!policy.allows("example.a", "example.provider"));
+        assert!(!policy.allows("example.a", "example.provider.sub"));
+        assert!(policy.allows("example.provider", "example.other"));
+    }
+
+    #[test]
+    fn applies_default_action() {
+        let policy = RoutePolicy {
+            rules: vec![rule(
+                RouteAction::Allow,
+                Some("example.a"),
+                Some("example.peer"),
+            )],
+            default: RouteAction::Deny,
+        };
+        assert!(policy.allows("example.a.b", "example.peer"));
+        assert!(!policy.allows("example.a.b", "example.other"));
+        assert!(!policy.allows("example.b", "example.peer"));
+    }
+
+    #[test]
+    fn deserializes_from_json() {
+        let policy: RoutePolicy = serde_json::from_str(
+            r#"{"rules":[{"action":"deny","origin":"example.provider"}],"default":"allow"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            policy,
+            RoutePolicy {
+                rules: vec![rule(RouteAction::Deny, None, Some("example.provider"))],
+                default: RouteAction::Allow,
+            }
+        );
+        assert_eq!(
+            serde_json::from_str::<RoutePolicy>("{}").unwrap(),
+            RoutePolicy::default()
+        );
+    }
+}
@@ -107,6 +107,11 @@ where
         self.prefix_map.resolve(prefix)
     }
 
+    /// All of the routes in the table
+    pub(crate) fn routes(&self) -> impl Iterator<Item = &Route> {
+        self.prefix_map.map.values().map(|(_account, route)| route)
+    }
+
     pub(crate) fn get_simplified_table(&self) -> HashMap<String, A> {
         self.prefix_map
             .map
@@ -120,7 +125,7 @@ where
     pub(crate) fn handle_update_request(
         &mut self,
         account: A,
-        request: RouteUpdateRequest,
+        mut request: RouteUpdateRequest,
     ) -> Result<Vec<String>, String> {
         if self.id != request.routing_table_id {
             debug!(
@@ -151,6 +156,24 @@ where
             return Ok(Vec::new());
         }
 
+        // An update from epoch 0 contains the whole table, so any routes
+        // we have that it doesn't include were withdrawn in the meantime
+        if request.from_epoch_index == 0 {
+            let missing: Vec<String> = self
+                .prefix_map
+                .map
+                .keys()
+                .filter(|prefix| {
+                    !request
+                        .new_routes
+                        .iter()
+                        .any(|route| &route.prefix == *prefix)
+                })
+                .cloned()
+                .collect();
+            request.withdrawn_routes.extend(missing);
+        }
+
         // Update the table with the epoch, new routes, and
         // withdrawn routes received in the route update request
         self.epoch = request.to_epoch_index;
@@ -294,6 +317,37 @@ mod table {
         assert_eq!(updated_routes.len(), 0);
     }
 
+    #[test]
+    fn full_sync_withdraws_routes_it_does_not_include() {
+        let mut table = RoutingTable::new(UPDATE_REQUEST_COMPLEX.routing_table_id);
+        let mut request = UPDATE_REQUEST_COMPLEX.clone();
+        request.from_epoch_index = 0;
+        request.to_epoch_index = 1;
+        table
+            .handle_update_request(ROUTING_ACCOUNT.clone(), request.clone())
+            .unwrap();
+
+        // The whole table, in which prefix1 is gone and prefix5 is new
+        request.from_epoch_index = 0;
+        request.to_epoch_index = 5;
+        request.withdrawn_routes = Vec::new();
+        request.new_routes.remove(0);
+        request.new_routes.push(Route {
+            prefix: "example.prefix5".to_string(),
+            path: vec!["example.prefix5".to_string()],
+            auth: [0; 32],
+            props: Vec::new(),
+        });
+        let mut updated_routes = table
+            .handle_update_request(ROUTING_ACCOUNT.clone(), request)
+            .unwrap();
+        updated_routes.sort();
+        assert_eq!(updated_routes, vec!["example.prefix1", "example.prefix5"]);
+        assert!(table.get_route("example.prefix1").is_none());
+        assert!(table.get_route("example.prefix2").is_some());
+        assert_eq!(table.epoch, 5);
+    }
+
     #[test]
     fn ignores_empty_update() {
         let mut table = RoutingTable::new([0; 16]);
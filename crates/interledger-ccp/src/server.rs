@@ -17,9 +17,8 @@ use interledger_service::{
 use parking_lot::{Mutex, RwLock};
 use ring::digest::{digest, SHA256};
 use std::cmp::Ordering as StdOrdering;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::{
-    cmp::min,
     convert::TryFrom,
     str,
     sync::{
@@ -45,6 +44,7 @@ use once_cell::sync::Lazy;
 const DEFAULT_ROUTE_EXPIRY_TIME: u32 = 30000;
 const DEFAULT_BROADCAST_INTERVAL: u64 = 30000;
 const DUMMY_ROUTING_TABLE_ID: [u8; 16] = [0; 16];
+const DEFAULT_MAX_EPOCHS_KEPT: usize = 1000;
 
 fn hash(preimage: &[u8; 32]) -> [u8; 32] {
     let mut out = [0; 32];
@@ -54,6 +54,56 @@ fn hash(preimage: &[u8; 32]) -> [u8; 32] {
 
 type NewAndWithdrawnRoutes = (Vec<Route>, Vec<String>);
 
+/// The most recent updates to the forwarding table, such that `updates[0]` is the
+/// transition from epoch `first_epoch` to `first_epoch + 1`.
+/// Peers that need updates from before `first_epoch` are sent the whole table instead.
+#[derive(Debug)]
+struct UpdateLog {
+    first_epoch: u32,
+    updates: VecDeque<NewAndWithdrawnRoutes>,
+    max_epochs_kept: usize,
+}
+
+impl UpdateLog {
+    fn new(max_epochs_kept: usize) -> Self {
+        UpdateLog {
+            first_epoch: 0,
+            updates: VecDeque::new(),
+            max_epochs_kept,
+        }
+    }
+
+    /// The epoch the forwarding table is at after the last logged update
+    fn last_epoch(&self) -> u32 {
+        self.first_epoch + self.updates.len() as u32
+    }
+
+    fn push(&mut self, update: NewAndWithdrawnRoutes) {
+        self.updates.push_back(update);
+        while self.updates.len() > self.max_epochs_kept {
+            self.updates.pop_front();
+            self.first_epoch += 1;
+        }
+    }
+
+    /// Returns the updates between the given epochs, or None if some of them
+    /// are no longer (or not yet) logged
+    fn range(
+        &self,
+        from_epoch: u32,
+        to_epoch: u32,
+    ) -> Option<impl Iterator<Item = &NewAndWithdrawnRoutes>> {
+        if from_epoch < self.first_epoch || to_epoch > self.last_epoch() || from_epoch > to_epoch {
+            return None;
+        }
+        Some(
+            self.updates
+                .range((from_epoch - self.first_epoch) as usize..)
+                .take((to_epoch - from_epoch) as usize),
+        )
+    }
+}
+
 /// Builder for [CcpRouteManager](./CcpRouteManager.html)
 /// See documentation on fields for more details.
 pub struct CcpRouteManagerBuilder<I, O, S> {
@@ -68,6 +118,7 @@ pub struct CcpRouteManagerBuilder<I, O, S> {
     store: S,
     ilp_address: Address,
     broadcast_interval: u64,
+    max_epochs_kept: usize,
 }
 
 impl<I, O, S, A> CcpRouteManagerBuilder<I, O, S>
@@ -84,6 +135,7 @@ where
             outgoing,
             store,
             broadcast_interval: DEFAULT_BROADCAST_INTERVAL,
+            max_epochs_kept: DEFAULT_MAX_EPOCHS_KEPT,
         }
     }
 
@@ -98,6 +150,13 @@ where
         self
     }
 
+    /// Set how many epochs of updates to the routing table are kept to send to peers
+    /// incrementally. Peers that fall further behind are sent the whole table.
+    pub fn max_epochs_kept(&mut self, epochs: usize) -> &mut Self {
+        self.max_epochs_kept = epochs;
+        self
+    }
+
     pub fn to_service(&self) -> CcpRouteManager<I, O, S, A> {
         #[allow(clippy::let_and_return)]
         let service = CcpRouteManager {
@@ -106,7 +165,7 @@ where
             outgoing: self.outgoing.clone(),
             store: self.store.clone(),
             forwarding_table: Arc::new(RwLock::new(RoutingTable::default())),
-            forwarding_table_updates: Arc::new(RwLock::new(Vec::new())),
+            forwarding_table_updates: Arc::new(RwLock::new(UpdateLog::new(self.max_epochs_kept))),
             last_epoch_updates_sent_for: Arc::new(AtomicU32::new(0)),
             local_table: Arc::new(RwLock::new(RoutingTable::default())),
             incoming_tables: Arc::new(RwLock::new(HashMap::new())),
@@ -158,8 +217,8 @@ pub struct CcpRouteManager<I, O, S, A: Account> {
     /// It is the same as the local_table with our own address added to the path of each route.
     forwarding_table: Arc<RwLock<RoutingTable<A>>>,
     last_epoch_updates_sent_for: Arc<AtomicU32>,
-    /// These updates are stored such that index 0 is the transition from epoch 0 to epoch 1
-    forwarding_table_updates: Arc<RwLock<Vec<NewAndWithdrawnRoutes>>>,
+    /// The recent updates to the forwarding table, which are sent to peers incrementally
+    forwarding_table_updates: Arc<RwLock<UpdateLog>>,
     /// This is the routing table we have compile from configuration and
     /// broadcasts we have received from our peers. It is saved to the Store so that
     /// the Router services forwards packets according to what it says.
@@ -279,12 +338,24 @@ where
             let (from_epoch_index, to_epoch_index) = {
                 let forwarding_table = self.forwarding_table.read();
                 let to_epoch_index = forwarding_table.epoch();
-                let from_epoch_index =
-                    if control.last_known_routing_table_id != forwarding_table.id() {
-                        0
-                    } else {
-                        min(control.last_known_epoch, to_epoch_index)
-                    };
+                let from_epoch_index = if control.last_known_routing_table_id
+                    != forwarding_table.id()
+                {
+                    0
+                } else if control.last_known_epoch > to_epoch_index {
+                    // They cannot have seen epochs we haven't reached yet,
+                    // so their view of our table is broken and they need all of it
+                    warn!(
+                            "Account {} (id: {}) knows epoch {} of our routing table, which is only at epoch {}. Sending the whole table",
+                            request.from.username(),
+                            request.from.id(),
+                            control.last_known_epoch,
+                            to_epoch_index
+                        );
+                    0
+                } else {
+                    control.last_known_epoch
+                };
                 (from_epoch_index, to_epoch_index)
             };
 
@@ -646,7 +717,7 @@ where
                         .map(|s| s.to_string())
                         .collect(),
                 ));
-                debug_assert_eq!(epoch as usize + 1, forwarding_table_updates.len());
+                debug_assert_eq!(epoch + 1, forwarding_table_updates.last_epoch());
 
                 store.set_routes(local_table.get_simplified_table())
             };
@@ -722,12 +793,19 @@ where
             let mut outgoing = self_clone.outgoing.clone();
             let mut results = Vec::new();
             for account in accounts.into_iter() {
+                let prepare = if account.route_policy().is_some() {
+                    self_clone
+                        .apply_route_policy(&account, route_update_request.clone())
+                        .to_prepare()
+                } else {
+                    prepare.clone()
+                };
                 let res = outgoing
                     .send_request(OutgoingRequest {
                         from: account.clone(),
                         to: account.clone(),
                         original_amount: prepare.amount(),
-                        prepare: prepare.clone(),
+                        prepare,
                     })
                     .await;
                 results.push((account, res));
@@ -781,45 +859,64 @@ where
     }
 
     /// Create a RouteUpdateRequest representing the given range of Forwarding Routing Table epochs.
-    /// If the epoch range is not specified, it will create an update for the last epoch only.
+    /// If the range starts from epoch 0 or is no longer in the update log, the request is a
+    /// full sync instead: it contains the whole table, from epoch 0 to the current one.
     fn create_route_update(
         &self,
         from_epoch_index: u32,
         to_epoch_index: u32,
     ) -> RouteUpdateRequest {
-        let (start, end) = (from_epoch_index as usize, to_epoch_index as usize);
-        let (routing_table_id, current_epoch_index) = {
-            let table = self.forwarding_table.read();
-            (table.id(), table.epoch())
-        };
+        let forwarding_table = self.forwarding_table.read();
+        let (routing_table_id, current_epoch_index) =
+            (forwarding_table.id(), forwarding_table.epoch());
         let forwarding_table_updates = self.forwarding_table_updates.read();
-        let epochs_to_take = end.saturating_sub(start);
 
         // Merge the new routes and withdrawn routes from all of the given epochs
-        let mut new_routes: Vec<Route> = Vec::with_capacity(epochs_to_take);
+        let mut new_routes: Vec<Route> = Vec::new();
         let mut withdrawn_routes: Vec<String> = Vec::new();
 
-        // Include our own prefix if its the first update
-        // TODO this might not be the right place to send our prefix
-        // (the reason we don't include our prefix in the forwarding table
-        // or the updates is that there isn't necessarily an Account that
-        // corresponds to this ILP address)
-        if start == 0 {
-            new_routes.push(Route {
-                prefix: self.ilp_address.read().to_string(),
-                path: Vec::new(),
-                // TODO what should we include here?
-                auth: [0; 32],
-                props: Vec::new(),
-            });
-        }
+        let updates = if from_epoch_index == 0 {
+            None
+        } else {
+            forwarding_table_updates.range(from_epoch_index, to_epoch_index)
+        };
+        let updates = match updates {
+            Some(updates) => updates,
+            None => {
+                if from_epoch_index != 0 {
+                    debug!(
+                        "Epochs {} - {} are no longer in the update log, sending the whole routing table",
+                        from_epoch_index, to_epoch_index
+                    );
+                }
+                // Include our own prefix in full syncs
+                // TODO this might not be the right place to send our prefix
+                // (the reason we don't include our prefix in the forwarding table
+                // or the updates is that there isn't necessarily an Account that
+                // corresponds to this ILP address)
+                new_routes.push(Route {
+                    prefix: self.ilp_address.read().to_string(),
+                    path: Vec::new(),
+                    // TODO what should we include here?
+                    auth: [0; 32],
+                    props: Vec::new(),
+                });
+                new_routes.extend(forwarding_table.routes().cloned());
+                return RouteUpdateRequest {
+                    routing_table_id,
+                    from_epoch_index: 0,
+                    to_epoch_index: current_epoch_index,
+                    current_epoch_index,
+                    new_routes,
+                    withdrawn_routes,
+                    speaker: self.ilp_address.read().clone(),
+                    hold_down_time: DEFAULT_ROUTE_EXPIRY_TIME,
+                };
+            }
+        };
 
         // Iterate through each of the given epochs
-        for (new, withdrawn) in forwarding_table_updates
-            .iter()
-            .skip(start)
-            .take(epochs_to_take)
-        {
+        for (new, withdrawn) in updates {
             for new_route in new {
                 new_routes.push(new_route.clone());
                 // If the route was previously withdrawn, ignore that now since it was added back
@@ -852,12 +949,41 @@ where
         }
     }
 
+    /// Remove the new routes that the account's route policy does not allow us to advertise to it.
+    /// Withdrawn routes are left in, because withdrawing a route the account never got is harmless
+    fn apply_route_policy(
+        &self,
+        account: &A,
+        mut update: RouteUpdateRequest,
+    ) -> RouteUpdateRequest {
+        if let Some(policy) = account.route_policy() {
+            let ilp_address = self.ilp_address.read();
+            update.new_routes.retain(|route| {
+                let origin = route
+                    .path
+                    .last()
+                    .map(String::as_str)
+                    .unwrap_or(&ilp_address as &str);
+                let allowed = policy.allows(&route.prefix, origin);
+                if !allowed {
+                    trace!(
+                        "Route policy of account {} (id: {}) does not allow advertising route: {:?}",
+                        account.username(),
+                        account.id(),
+                        route
+                    );
+                }
+                allowed
+            });
+        }
+        update
+    }
+
     /// Send a Route Update Request to a specific account for the given epoch range.
     /// This is used when the peer has fallen behind and has requested a specific range of updates.
     async fn send_route_update(&self, account: A, from_epoch_index: u32, to_epoch_index: u32) {
-        let prepare = self
-            .create_route_update(from_epoch_index, to_epoch_index)
-            .to_prepare();
+        let update = self.create_route_update(from_epoch_index, to_epoch_index);
+        let prepare = self.apply_route_policy(&account, update).to_prepare();
         let account_id = account.id();
         debug!(
             "Sending individual route update to account: {} for epochs from: {} to: {}",
@@ -1216,6 +1342,32 @@ mod handle_route_control_request {
         assert_eq!(update.new_routes.len(), 3);
     }
 
+    #[tokio::test]
+    async fn sends_whole_table_if_their_epoch_is_ahead_of_ours() {
+        let (mut service, outgoing_requests) = test_service_with_routes();
+        service.update_best_routes(None).await.unwrap();
+        let routing_table_id = service.forwarding_table.read().id();
+        service
+            .handle_request(IncomingRequest {
+                from: ROUTING_ACCOUNT.clone(),
+                prepare: RouteControlRequest {
+                    last_known_routing_table_id: routing_table_id,
+                    mode: Mode::Sync,
+                    last_known_epoch: 7,
+                    features: Vec::new(),
+                }
+                .to_prepare(),
+            })
+            .await
+            .unwrap();
+        let request: &OutgoingRequest<TestAccount> = &outgoing_requests.lock()[0];
+        let update = RouteUpdateRequest::try_from(&request.prepare).unwrap();
+        assert_eq!(update.routing_table_id, routing_table_id);
+        assert_eq!(update.from_epoch_index, 0);
+        assert_eq!(update.to_epoch_index, 1);
+        assert_eq!(update.new_routes.len(), 3);
+    }
+
     #[tokio::test]
     async fn sends_whole_table_if_id_is_different() {
         let (mut service, outgoing_requests) = test_service_with_routes();
@@ -1661,6 +1813,7 @@ mod handle_route_update_request {
 mod create_route_update {
     use super::*;
     use crate::test_helpers::*;
+    use std::str::FromStr;
 
     #[tokio::test]
     async fn heartbeat_message_for_empty_table() {
@@ -1679,7 +1832,7 @@ mod create_route_update {
     async fn includes_the_given_range_of_epochs() {
         let service = test_service();
         (*service.forwarding_table.write()).set_epoch(4);
-        *service.forwarding_table_updates.write() = vec![
+        let updates = vec![
             (
                 vec![Route {
                     prefix: "example.a".to_string(),
@@ -1717,6 +1870,9 @@ mod create_route_update {
                 vec!["example.n".to_string()],
             ),
         ];
+        for update in updates {
+            service.forwarding_table_updates.write().push(update);
+        }
         let update = service.create_route_update(1, 3);
         assert_eq!(update.from_epoch_index, 1);
         assert_eq!(update.to_epoch_index, 3);
@@ -1733,6 +1889,116 @@ mod create_route_update {
         assert!(!new_routes.contains(&"example.m"));
         assert_eq!(update.withdrawn_routes[0], "example.m");
     }
+
+    #[tokio::test]
+    async fn sends_whole_table_for_epochs_no_longer_logged() {
+        let service = test_service();
+        *service.forwarding_table_updates.write() = UpdateLog::new(2);
+        (*service.forwarding_table.write()).set_epoch(4);
+        for prefix in &["example.a", "example.b", "example.c", "example.d"] {
+            service.forwarding_table_updates.write().push((
+                vec![Route {
+                    prefix: prefix.to_string(),
+                    path: vec!["example.x".to_string()],
+                    auth: [1; 32],
+                    props: Vec::new(),
+                }],
+                Vec::new(),
+            ));
+        }
+
+        // Only epochs 2 to 4 are still logged
+        let update = service.create_route_update(2, 4);
+        assert_eq!(update.from_epoch_index, 2);
+        assert_eq!(update.new_routes.len(), 2);
+
+        let update = service.create_route_update(1, 3);
+        assert_eq!(update.from_epoch_index, 0);
+        assert_eq!(update.to_epoch_index, 4);
+        assert!(update.withdrawn_routes.is_empty());
+        assert_eq!(update.new_routes[0].prefix, "example.connector");
+    }
+
+    #[tokio::test]
+    async fn incremental_updates_match_full_sync() {
+        let (service, _outgoing_requests) = test_service_with_routes();
+        let peer = TestAccount::new(Uuid::from_slice(&[10; 16]).unwrap(), "example.peer");
+        service.update_best_routes(None).await.unwrap();
+
+        // Our view of the service's table, as a peer it broadcasts to
+        let mut incremental = RoutingTable::new([0; 16]);
+        let update = service.create_route_update(0, 1);
+        incremental
+            .handle_update_request(peer.clone(), update)
+            .unwrap();
+
+        // Another peer adds two routes and then withdraws one of them
+        let route = |prefix: &str| Route {
+            prefix: prefix.to_string(),
+            path: vec!["example.peer".to_string()],
+            auth: [0; 32],
+            props: Vec::new(),
+        };
+        let peer_updates = vec![
+            (
+                0,
+                1,
+                vec![route("example.remote.a"), route("example.remote.b")],
+                Vec::new(),
+            ),
+            (1, 2, Vec::new(), vec!["example.remote.a".to_string()]),
+        ];
+        for (from_epoch_index, to_epoch_index, new_routes, withdrawn_routes) in peer_updates {
+            service
+                .handle_route_update_request(IncomingRequest {
+                    from: peer.clone(),
+                    prepare: RouteUpdateRequest {
+                        routing_table_id: [1; 16],
+                        current_epoch_index: to_epoch_index,
+                        from_epoch_index,
+                        to_epoch_index,
+                        hold_down_time: 30000,
+                        speaker: Address::from_str("example.peer").unwrap(),
+                        new_routes,
+                        withdrawn_routes,
+                    }
+                    .to_prepare(),
+                })
+                .await
+                .unwrap();
+
+            let from_epoch_index = incremental.epoch();
+            let to_epoch_index = service.forwarding_table.read().epoch();
+            let update = service.create_route_update(from_epoch_index, to_epoch_index);
+            assert_eq!(update.from_epoch_index, from_epoch_index);
+            incremental
+                .handle_update_request(peer.clone(), update)
+                .unwrap();
+        }
+
+        let mut full_sync = RoutingTable::new([0; 16]);
+        let update = service.create_route_update(0, service.forwarding_table.read().epoch());
+        full_sync
+            .handle_update_request(peer.clone(), update)
+            .unwrap();
+
+        let routes = |table: &RoutingTable<TestAccount>| {
+            let mut routes: Vec<(String, Vec<String>)> = table
+                .routes()
+                .map(|route| (route.prefix.clone(), route.path.clone()))
+                .collect();
+            routes.sort();
+            routes
+        };
+        assert_eq!(routes(&incremental), routes(&full_sync));
+        assert_eq!(incremental.epoch(), full_sync.epoch());
+        let prefixes: Vec<String> = routes(&incremental)
+            .into_iter()
+            .map(|(prefix, _path)| prefix)
+            .collect();
+        assert!(prefixes.contains(&"example.remote.b".to_string()));
+        assert!(!prefixes.contains(&"example.remote.a".to_string()));
+    }
 }
 
 #[cfg(test)]
@@ -1740,6 +2006,7 @@ mod send_route_updates {
     use super::*;
     use crate::fixtures::*;
     use crate::test_helpers::*;
+    use crate::{RouteAction, RoutePolicy, RouteRule};
     use interledger_service::*;
     use std::{collections::HashSet, iter::FromIterator, str::FromStr};
 
@@ -1824,6 +2091,84 @@ mod send_route_updates {
         assert!(prefixes.contains(&"example.remote"));
     }
 
+    #[tokio::test]
+    async fn filters_routes_with_each_accounts_route_policy() {
+        let (service, outgoing_requests) = test_service_with_routes();
+        service.update_best_routes(None).await.unwrap();
+        service
+            .handle_route_update_request(IncomingRequest {
+                from: TestAccount::new(Uuid::new_v4(), "example.peer"),
+                prepare: RouteUpdateRequest {
+                    routing_table_id: [0; 16],
+                    current_epoch_index: 1,
+                    from_epoch_index: 0,
+                    to_epoch_index: 1,
+                    hold_down_time: 30000,
+                    speaker: Address::from_str("example.remote").unwrap(),
+                    new_routes: vec![Route {
+                        prefix: "example.remote".to_string(),
+                        path: vec!["example.peer".to_string()],
+                        auth: [0; 32],
+                        props: Vec::new(),
+                    }],
+                    withdrawn_routes: Vec::new(),
+                }
+                .to_prepare(),
+            })
+            .await
+            .unwrap();
+
+        let deny = |prefix: Option<&str>, origin: Option<&str>| RoutePolicy {
+            rules: vec![RouteRule {
+                action: RouteAction::Deny,
+                prefix: prefix.map(String::from),
+                origin: origin.map(String::from),
+            }],
+            default: RouteAction::Allow,
+        };
+        let mut no_configured = TestAccount::new(Uuid::new_v4(), "example.customer.1");
+        no_configured.route_policy = Some(deny(Some("example.configured"), None));
+        let mut no_peer = TestAccount::new(Uuid::new_v4(), "example.customer.2");
+        no_peer.route_policy = Some(deny(None, Some("example.peer")));
+        for account in &[&no_configured, &no_peer] {
+            service
+                .store
+                .routes
+                .lock()
+                .insert(account.ilp_address.to_string(), (*account).clone());
+        }
+
+        service.send_route_updates().await.unwrap();
+        let prefixes_sent_to = |account_id: Uuid| -> Vec<String> {
+            let request = outgoing_requests
+                .lock()
+                .iter()
+                .find(|request| request.to.id() == account_id)
+                .cloned()
+                .unwrap();
+            RouteUpdateRequest::try_from(&request.prepare)
+                .unwrap()
+                .new_routes
+                .into_iter()
+                .map(|route| route.prefix)
+                .collect()
+        };
+
+        let prefixes = prefixes_sent_to(no_configured.id);
+        assert!(!prefixes.contains(&"example.configured.1".to_string()));
+        assert!(prefixes.contains(&"example.remote".to_string()));
+        assert!(prefixes.contains(&"example.connector".to_string()));
+
+        let prefixes = prefixes_sent_to(no_peer.id);
+        assert!(prefixes.contains(&"example.configured.1".to_string()));
+        assert!(!prefixes.contains(&"example.remote".to_string()));
+
+        // Accounts without a policy get every route
+        let prefixes = prefixes_sent_to(Uuid::from_slice(&[1; 16]).unwrap());
+        assert!(prefixes.contains(&"example.configured.1".to_string()));
+        assert!(prefixes.contains(&"example.remote".to_string()));
+    }
+
     #[tokio::test]
     async fn broadcasts_withdrawn_routes() {
         let id10 = Uuid::from_slice(&[10; 16]).unwrap();
@@ -1854,6 +2199,9 @@ mod send_route_updates {
             })
             .await
             .unwrap();
+        service.send_route_updates().await.unwrap();
+        outgoing_requests.lock().clear();
+
         service
             .handle_route_update_request(IncomingRequest {
                 from: TestAccount::new(id10, "example.peer"),
@@ -1874,15 +2222,9 @@ mod send_route_updates {
 
         service.send_route_updates().await.unwrap();
         let update = RouteUpdateRequest::try_from(&outgoing_requests.lock()[0].prepare).unwrap();
-        assert_eq!(update.new_routes.len(), 3);
-        let prefixes: Vec<&str> = update
-            .new_routes
-            .iter()
-            .map(|route| str::from_utf8(route.prefix.as_ref()).unwrap())
-            .collect();
-        assert!(prefixes.contains(&"example.local.1"));
-        assert!(prefixes.contains(&"example.configured.1"));
-        assert!(!prefixes.contains(&"example.remote"));
+        assert_eq!(update.from_epoch_index, 2);
+        assert_eq!(update.to_epoch_index, 3);
+        assert!(update.new_routes.is_empty());
         assert_eq!(update.withdrawn_routes.len(), 1);
         assert_eq!(update.withdrawn_routes[0], "example.remote");
     }
@@ -1902,6 +2244,7 @@ mod send_route_updates {
                     id: id2,
                     ilp_address: Address::from_str("example.connector.other-local").unwrap(),
                     relation: RoutingRelation::Child,
+                    route_policy: None,
                 },
             ),
         ]);
@@ -1990,6 +2333,7 @@ mod send_route_updates {
             id: id2,
             ilp_address: Address::from_str("example.connector.other-local").unwrap(),
             relation: RoutingRelation::Child,
+            route_policy: None,
         };
         let local_routes = HashMap::from_iter(vec![
             (
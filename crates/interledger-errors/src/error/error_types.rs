@@ -63,6 +63,21 @@ pub const INVALID_ILP_PACKET_TYPE: ApiErrorType = ApiErrorType {
     status: StatusCode::BAD_REQUEST,
 };
 
+/// ILP over HTTP request body too large error type (413 Payload Too Large)
+pub const ILP_PACKET_TOO_LARGE_TYPE: ApiErrorType = ApiErrorType {
+    r#type: &ProblemType::InterledgerHttpApi("ilp-over-http/packet-too-large"),
+    title: "Packet Too Large",
+    status: StatusCode::PAYLOAD_TOO_LARGE,
+};
+
+/// ILP over HTTP request without the `application/octet-stream` content type error type
+/// (415 Unsupported Media Type)
+pub const ILP_PACKET_CONTENT_TYPE_TYPE: ApiErrorType = ApiErrorType {
+    r#type: &ProblemType::InterledgerHttpApi("ilp-over-http/unsupported-content-type"),
+    title: "Unsupported Content Type",
+    status: StatusCode::UNSUPPORTED_MEDIA_TYPE,
+};
+
 /// Wrong JSON syntax error type (400 Bad Request)
 pub const JSON_SYNTAX_TYPE: ApiErrorType = ApiErrorType {
     r#type: &ProblemType::InterledgerHttpApi("json-syntax"),
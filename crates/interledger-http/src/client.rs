@@ -9,8 +9,37 @@ use reqwest::{
     Client, ClientBuilder, Response as HttpResponse,
 };
 use secrecy::{ExposeSecret, SecretString};
+use serde::Deserialize;
 use std::{convert::TryFrom, marker::PhantomData, sync::Arc, time::Duration};
-use tracing::{error, debug};
+use tracing::{debug, error};
+
+/// Tuning for the pool of connections the [HttpClientService] keeps open to peers.
+/// Connections to the same peer URL are reused for as long as they stay in the pool.
+/// All durations are defined in milliseconds.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct HttpClientConfig {
+    /// Maximum number of idle connections kept open to each peer host
+    pub pool_max_idle_per_host: usize,
+    /// How long an idle connection is kept in the pool before it is closed
+    pub pool_idle_timeout: u64,
+    /// Interval of the TCP keepalive probes sent on open connections,
+    /// or `None` to not send any
+    pub tcp_keepalive: Option<u64>,
+    /// Timeout of each ILP over HTTP request, for accounts that do not set their own
+    pub timeout: u64,
+}
+
+impl Default for HttpClientConfig {
+    fn default() -> Self {
+        HttpClientConfig {
+            pool_max_idle_per_host: 32,
+            pool_idle_timeout: 90_000,
+            tcp_keepalive: Some(60_000),
+            timeout: 30_000,
+        }
+    }
+}
 
 /// The HttpClientService implements [OutgoingService](../../interledger_service/trait.OutgoingService)
 /// for sending ILP Prepare packets over to the HTTP URL associated with the provided account
@@ -19,7 +48,7 @@ use tracing::{error, debug};
 #[derive(Clone)]
 pub struct HttpClientService<S, O, A> {
     /// An HTTP client configured with a 30 second timeout by default. It is used to send the
-    /// ILP over HTTP messages to the peer
+    /// ILP over HTTP messages to the peer. Clones share its connection pool
     client: Client,
     /// The store used by the client to get the node's ILP Address,
     /// used to populate the `triggered_by` field in Reject packets
@@ -36,8 +65,13 @@ where
     O: OutgoingService<A> + Clone,
     A: HttpAccount,
 {
-    /// Constructs the HttpClientService
+    /// Constructs the HttpClientService with the default [HttpClientConfig]
     pub fn new(store: S, next: O) -> Self {
+        Self::with_config(store, next, &HttpClientConfig::default())
+    }
+
+    /// Constructs the HttpClientService with the given connection pool settings
+    pub fn with_config(store: S, next: O, config: &HttpClientConfig) -> Self {
         let mut headers = HeaderMap::with_capacity(2);
         headers.insert(
             HeaderName::from_static("content-type"),
@@ -45,7 +79,10 @@ where
         );
         let client = ClientBuilder::new()
             .default_headers(headers)
-            .timeout(Duration::from_secs(30))
+            .timeout(Duration::from_millis(config.timeout))
+            .pool_max_idle_per_host(config.pool_max_idle_per_host)
+            .pool_idle_timeout(Duration::from_millis(config.pool_idle_timeout))
+            .tcp_keepalive(config.tcp_keepalive.map(Duration::from_millis))
             .build()
             .unwrap();
 
@@ -82,11 +119,15 @@ where
                 .unwrap_or_else(|| SecretString::new("".to_owned()));
             let header = format!("Bearer {}", token.expose_secret());
             let body = request.prepare.as_ref().to_owned();
-            let resp = self_clone
+            let mut http_request = self_clone
                 .client
                 .post(url.as_ref())
                 .header("authorization", &header)
-                .body(body)
+                .body(body);
+            if let Some(timeout) = request.to.get_http_timeout() {
+                http_request = http_request.timeout(timeout);
+            }
+            let resp = http_request
                 .send()
                 .map_err(move |err| {
                     error!("Error sending HTTP request: {:?}", err);
@@ -171,3 +212,191 @@ async fn parse_packet_from_response(response: HttpResponse, ilp_address: Address
         .build()),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::stream;
+    use interledger_errors::{AddressStoreError, HttpStoreError};
+    use interledger_packet::{FulfillBuilder, PrepareBuilder};
+    use once_cell::sync::Lazy;
+    use std::str::FromStr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::SystemTime;
+    use tokio::net::TcpListener;
+    use url::Url;
+    use uuid::Uuid;
+    use warp::Filter;
+
+    static ILP_ADDRESS: Lazy<Address> = Lazy::new(|| Address::from_str("example.alice").unwrap());
+    static USERNAME: Lazy<Username> = Lazy::new(|| Username::from_str("alice").unwrap());
+
+    /// Serves ILP over HTTP requests with a Fulfill on a local port after the
+    /// given delay, counting the TCP connections it accepts
+    async fn mock_peer(delay: Duration) -> (Url, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!("http://{}/ilp", listener.local_addr().unwrap())).unwrap();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let counter = connections.clone();
+        let incoming = stream::unfold(listener, move |listener| {
+            let counter = counter.clone();
+            async move {
+                let accepted = listener.accept().await.map(|(socket, _)| socket);
+                counter.fetch_add(1, Ordering::SeqCst);
+                Some((accepted, listener))
+            }
+        });
+        let fulfill = warp::post()
+            .and(warp::path("ilp"))
+            .and_then(move || async move {
+                tokio::time::sleep(delay).await;
+                Ok::<_, warp::Rejection>(
+                    BytesMut::from(
+                        FulfillBuilder {
+                            fulfillment: &[0; 32],
+                            data: b"hello",
+                        }
+                        .build(),
+                    )
+                    .to_vec(),
+                )
+            });
+        tokio::spawn(warp::serve(fulfill).run_incoming(incoming));
+        (url, connections)
+    }
+
+    fn request(to: TestAccount) -> OutgoingRequest<TestAccount> {
+        OutgoingRequest {
+            from: TestAccount::default(),
+            to,
+            original_amount: 100,
+            prepare: PrepareBuilder {
+                destination: ILP_ADDRESS.clone(),
+                amount: 100,
+                expires_at: SystemTime::now() + Duration::from_secs(30),
+                execution_condition: &[0; 32],
+                data: &[],
+            }
+            .build(),
+        }
+    }
+
+    fn client(
+    ) -> HttpClientService<TestStore, impl OutgoingService<TestAccount> + Clone, TestAccount> {
+        let next = outgoing_service_fn(|_| -> IlpResult { panic!("should not be called") });
+        HttpClientService::new(TestStore, next)
+    }
+
+    #[tokio::test]
+    async fn reuses_connections_to_the_same_peer() {
+        let (url, connections) = mock_peer(Duration::from_millis(0)).await;
+        let mut service = client();
+        let peer = TestAccount {
+            url: Some(url),
+            timeout: None,
+        };
+
+        for _ in 0..20 {
+            let fulfill = service.send_request(request(peer.clone())).await.unwrap();
+            assert_eq!(fulfill.data(), b"hello");
+        }
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn applies_the_accounts_timeout() {
+        let (url, _) = mock_peer(Duration::from_millis(500)).await;
+        let mut service = client();
+
+        let reject = service
+            .send_request(request(TestAccount {
+                url: Some(url.clone()),
+                timeout: Some(Duration::from_millis(50)),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(reject.code(), ErrorCode::T01_PEER_UNREACHABLE);
+
+        let fulfill = service
+            .send_request(request(TestAccount {
+                url: Some(url),
+                timeout: None,
+            }))
+            .await
+            .unwrap();
+        assert_eq!(fulfill.data(), b"hello");
+    }
+
+    #[derive(Debug, Clone, Default)]
+    struct TestAccount {
+        url: Option<Url>,
+        timeout: Option<Duration>,
+    }
+
+    impl Account for TestAccount {
+        fn id(&self) -> Uuid {
+            Uuid::new_v4()
+        }
+
+        fn username(&self) -> &Username {
+            &USERNAME
+        }
+
+        fn ilp_address(&self) -> &Address {
+            &ILP_ADDRESS
+        }
+
+        fn asset_scale(&self) -> u8 {
+            9
+        }
+
+        fn asset_code(&self) -> &str {
+            "XYZ"
+        }
+    }
+
+    impl HttpAccount for TestAccount {
+        fn get_http_url(&self) -> Option<&Url> {
+            self.url.as_ref()
+        }
+
+        fn get_http_auth_token(&self) -> Option<SecretString> {
+            Some(SecretString::new("password".to_owned()))
+        }
+
+        fn get_http_timeout(&self) -> Option<Duration> {
+            self.timeout
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    struct TestStore;
+
+    #[async_trait]
+    impl AddressStore for TestStore {
+        async fn set_ilp_address(&self, _ilp_address: Address) -> Result<(), AddressStoreError> {
+            Ok(())
+        }
+
+        async fn clear_ilp_address(&self) -> Result<(), AddressStoreError> {
+            Ok(())
+        }
+
+        fn get_ilp_address(&self) -> Address {
+            ILP_ADDRESS.clone()
+        }
+    }
+
+    #[async_trait]
+    impl HttpStore for TestStore {
+        type Account = TestAccount;
+
+        async fn get_account_from_http_auth(
+            &self,
+            username: &Username,
+            _token: &str,
+        ) -> Result<Self::Account, HttpStoreError> {
+            Err(HttpStoreError::Unauthorized(username.to_string()))
+        }
+    }
+}
@@ -9,6 +9,7 @@ use interledger_service::{Account, Username};
 use mime::Mime;
 use secrecy::SecretString;
 use serde::de::DeserializeOwned;
+use std::time::Duration;
 use url::Url;
 use warp::{self, Filter, Rejection};
 
@@ -17,8 +18,8 @@ mod client;
 /// [ILP over HTTP](https://interledger.org/rfcs/0035-ilp-over-http/) API (implemented with [Warp](https://docs.rs/warp/0.2.0/warp/))
 mod server;
 
-pub use self::client::HttpClientService;
-pub use self::server::HttpServer;
+pub use self::client::{HttpClientConfig, HttpClientService};
+pub use self::server::{HttpServer, MAX_PACKET_SIZE};
 
 /// Extension trait for [Account](../interledger_service/trait.Account.html) with [ILP over HTTP](https://interledger.org/rfcs/0035-ilp-over-http/) related information
 pub trait HttpAccount: Account {
@@ -26,6 +27,11 @@ pub trait HttpAccount: Account {
     fn get_http_url(&self) -> Option<&Url>;
     /// Returns the HTTP token which is sent as an HTTP header on each ILP over HTTP request
     fn get_http_auth_token(&self) -> Option<SecretString>;
+    /// Returns the timeout of the ILP over HTTP requests sent to this account,
+    /// if it should differ from the client's
+    fn get_http_timeout(&self) -> Option<Duration> {
+        None
+    }
 }
 
 /// The interface for Stores that can be used with the HttpServerService.
@@ -1,16 +1,18 @@
 use super::HttpStore;
-use bytes::{Bytes, BytesMut};
-use interledger_errors::ApiError;
+use bytes::{Buf, BufMut, BytesMut};
+use futures::{Stream, StreamExt};
+use interledger_errors::{ApiError, ILP_PACKET_CONTENT_TYPE_TYPE, ILP_PACKET_TOO_LARGE_TYPE};
 use interledger_packet::Prepare;
 use interledger_service::Username;
 use interledger_service::{IncomingRequest, IncomingService};
+use mime::Mime;
 use secrecy::{ExposeSecret, SecretString};
 use std::convert::TryFrom;
 use std::net::SocketAddr;
 use tracing::error;
 use warp::{Filter, Rejection};
 
-/// Max message size that is allowed to transfer from a request or a message.
+/// Default max size of an ILP over HTTP request body, which is a single ILP packet
 pub const MAX_PACKET_SIZE: u64 = 40000;
 /// The offset after which the bearer token should be in an ILP over HTTP request
 /// e.g. in `token = "Bearer: MyAuthToken"`, `MyAuthToken` can be taken via token[BEARER_TOKEN_START..]
@@ -24,6 +26,8 @@ pub struct HttpServer<I, S> {
     incoming: I,
     /// A store which implements [`HttpStore`](trait.HttpStore.html)
     store: S,
+    /// Requests with larger bodies are rejected with `413 Payload Too Large`
+    max_body_size: u64,
 }
 
 #[inline]
@@ -49,6 +53,66 @@ where
         .await?)
 }
 
+/// Rejects requests unless their content type is `application/octet-stream`,
+/// which the ILP over HTTP spec requires for packets
+fn octet_stream() -> impl Filter<Extract = (), Error = Rejection> + Copy {
+    warp::header::optional::<String>("content-type")
+        .and_then(|content_type: Option<String>| async move {
+            let mime_type = content_type.and_then(|content_type| content_type.parse::<Mime>().ok());
+            match mime_type {
+                Some(mime_type)
+                    if mime_type.essence_str() == mime::APPLICATION_OCTET_STREAM.essence_str() =>
+                {
+                    Ok(())
+                }
+                _ => Err(Rejection::from(
+                    ApiError::from_api_error_type(&ILP_PACKET_CONTENT_TYPE_TYPE)
+                        .detail("Content-Type must be application/octet-stream"),
+                )),
+            }
+        })
+        .untuple_one()
+}
+
+fn body_too_large(max_body_size: u64) -> Rejection {
+    Rejection::from(
+        ApiError::from_api_error_type(&ILP_PACKET_TOO_LARGE_TYPE)
+            .detail(format!("Body must be at most {} bytes", max_body_size)),
+    )
+}
+
+/// Reads the body into a buffer of at most `max_body_size` bytes. Requests which announce
+/// a larger body are rejected before any of it is read, and bodies sent without a
+/// Content-Length are rejected as soon as they grow past the limit.
+async fn read_body<B>(
+    content_length: Option<u64>,
+    body: impl Stream<Item = Result<B, warp::Error>>,
+    max_body_size: u64,
+) -> Result<BytesMut, Rejection>
+where
+    B: Buf,
+{
+    let content_length = content_length.unwrap_or(0);
+    if content_length > max_body_size {
+        return Err(body_too_large(max_body_size));
+    }
+
+    let mut buffer = BytesMut::with_capacity(content_length as usize);
+    futures::pin_mut!(body);
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk.map_err(|err| {
+            Rejection::from(
+                ApiError::bad_request().detail(format!("Error reading request body: {}", err)),
+            )
+        })?;
+        if (buffer.len() + chunk.remaining()) as u64 > max_body_size {
+            return Err(body_too_large(max_body_size));
+        }
+        buffer.put(chunk);
+    }
+    Ok(buffer)
+}
+
 #[inline]
 /// Implements ILP over HTTP. If account authentication is valid
 /// and the provided packet can be parsed as a
@@ -63,7 +127,7 @@ where
 async fn ilp_over_http<S, I>(
     path_username: Username,
     password: SecretString,
-    body: Bytes,
+    body: BytesMut,
     store: S,
     mut incoming: I,
 ) -> Result<impl warp::Reply, warp::Rejection>
@@ -73,8 +137,7 @@ where
 {
     let account = get_account(store, &path_username, &password).await?;
 
-    let buffer = bytes::BytesMut::from(body.as_ref());
-    if let Ok(prepare) = Prepare::try_from(buffer) {
+    if let Ok(prepare) = Prepare::try_from(body) {
         let result = incoming
             .handle_request(IncomingRequest {
                 from: account,
@@ -104,7 +167,17 @@ where
     S: HttpStore + Clone,
 {
     pub fn new(incoming: I, store: S) -> Self {
-        HttpServer { incoming, store }
+        HttpServer {
+            incoming,
+            store,
+            max_body_size: MAX_PACKET_SIZE,
+        }
+    }
+
+    /// Sets the max size of request bodies, which defaults to [`MAX_PACKET_SIZE`](constant.MAX_PACKET_SIZE.html)
+    pub fn with_max_body_size(mut self, max_body_size: u64) -> Self {
+        self.max_body_size = max_body_size;
+        self
     }
 
     /// Returns a Warp filter which exposes per-account endpoints for [ILP over HTTP](https://interledger.org/rfcs/0035-ilp-over-http/).
@@ -114,16 +187,20 @@ where
     ) -> impl warp::Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
         let store = self.store.clone();
         let incoming = self.incoming.clone();
+        let max_body_size = self.max_body_size;
         let with_store = warp::any().map(move || store.clone());
         let with_incoming = warp::any().map(move || incoming.clone());
+        let body = octet_stream()
+            .and(warp::header::optional::<u64>("content-length"))
+            .and(warp::body::stream())
+            .and_then(move |content_length, body| read_body(content_length, body, max_body_size));
         warp::post()
             .and(warp::path("accounts"))
             .and(warp::path::param::<Username>())
             .and(warp::path("ilp"))
             .and(warp::path::end())
             .and(warp::header::<SecretString>("authorization"))
-            .and(warp::body::content_length_limit(MAX_PACKET_SIZE))
-            .and(warp::body::bytes())
+            .and(body)
             .and(with_store)
             .and(with_incoming)
             .and_then(ilp_over_http)
@@ -141,10 +218,13 @@ mod tests {
     use super::*;
     use crate::HttpAccount;
     use async_trait::async_trait;
+    use bytes::Bytes;
     use bytes::BytesMut;
     use http::Response;
     use interledger_errors::{default_rejection_handler, HttpStoreError};
-    use interledger_packet::{Address, ErrorCode, PrepareBuilder, RejectBuilder};
+    use interledger_packet::{
+        Address, ErrorCode, Fulfill, FulfillBuilder, PrepareBuilder, Reject, RejectBuilder,
+    };
     use interledger_service::{incoming_service_fn, Account};
     use once_cell::sync::Lazy;
     use secrecy::SecretString;
@@ -153,6 +233,7 @@ mod tests {
     use std::time::SystemTime;
     use url::Url;
     use uuid::Uuid;
+    use warp::test::RequestBuilder;
 
     static USERNAME: Lazy<Username> = Lazy::new(|| Username::from_str("alice").unwrap());
     static ILP_ADDRESS: Lazy<Address> = Lazy::new(|| Address::from_str("example.alice").unwrap());
@@ -171,25 +252,128 @@ mod tests {
 
     const AUTH_PASSWORD: &str = "password";
 
-    async fn api_call<F>(
-        api: &F,
+    /// An ILP over HTTP request with valid headers and no body
+    fn ilp_request(
         endpoint: &str, // /ilp or /accounts/:username/ilp
         auth: &str,     // simple bearer or overloaded username+password
-    ) -> Response<Bytes>
-    where
-        F: warp::Filter + 'static,
-        F::Extract: warp::Reply,
-    {
+    ) -> RequestBuilder {
         warp::test::request()
             .method("POST")
             .path(endpoint)
             .header("Authorization", format!("Bearer {}", auth))
-            .header("Content-length", 1000)
+            .header("Content-Type", "application/octet-stream")
+    }
+
+    async fn api_call<F>(api: &F, endpoint: &str, auth: &str) -> Response<Bytes>
+    where
+        F: warp::Filter + 'static,
+        F::Extract: warp::Reply,
+    {
+        ilp_request(endpoint, auth)
+            .header("Content-length", PREPARE_BYTES.len())
             .body(PREPARE_BYTES.clone())
             .reply(api)
             .await
     }
 
+    fn test_api(
+        fulfill: bool,
+    ) -> impl warp::Filter<Extract = (impl warp::Reply,), Error = Rejection> + Clone {
+        let incoming = incoming_service_fn(move |_request| {
+            if fulfill {
+                Ok(FulfillBuilder {
+                    fulfillment: &[0; 32],
+                    data: b"fulfilled",
+                }
+                .build())
+            } else {
+                Err(RejectBuilder {
+                    code: ErrorCode::F02_UNREACHABLE,
+                    message: b"No other incoming handler!",
+                    data: &[],
+                    triggered_by: Some(&ILP_ADDRESS),
+                }
+                .build())
+            }
+        });
+        HttpServer::new(incoming, TestStore)
+            .with_max_body_size(100)
+            .as_filter()
+            .recover(default_rejection_handler)
+    }
+
+    #[tokio::test]
+    async fn responds_with_the_fulfill_or_reject() {
+        let resp = api_call(&test_api(true), "/accounts/alice/ilp", AUTH_PASSWORD).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        assert_eq!(resp.headers()["Content-Type"], "application/octet-stream");
+        let fulfill = Fulfill::try_from(BytesMut::from(resp.body().as_ref())).unwrap();
+        assert_eq!(fulfill.data(), b"fulfilled");
+
+        let resp = api_call(&test_api(false), "/accounts/alice/ilp", AUTH_PASSWORD).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let reject = Reject::try_from(BytesMut::from(resp.body().as_ref())).unwrap();
+        assert_eq!(reject.code(), ErrorCode::F02_UNREACHABLE);
+    }
+
+    #[tokio::test]
+    async fn rejects_oversized_bodies() {
+        let api = test_api(true);
+        let body = vec![0; 101];
+
+        // Announced by the Content-Length
+        let resp = ilp_request("/accounts/alice/ilp", AUTH_PASSWORD)
+            .header("Content-Length", body.len())
+            .body(body.clone())
+            .reply(&api)
+            .await;
+        assert_eq!(resp.status().as_u16(), 413);
+
+        // Only found out while reading the body
+        let resp = ilp_request("/accounts/alice/ilp", AUTH_PASSWORD)
+            .body(body)
+            .reply(&api)
+            .await;
+        assert_eq!(resp.status().as_u16(), 413);
+
+        // Bodies up to the limit are read
+        let resp = ilp_request("/accounts/alice/ilp", AUTH_PASSWORD)
+            .body(vec![0; 100])
+            .reply(&api)
+            .await;
+        assert_eq!(resp.status().as_u16(), 400);
+    }
+
+    #[tokio::test]
+    async fn rejects_other_content_types() {
+        let api = test_api(true);
+        for content_type in &["application/json", "text/plain", "octet-stream"] {
+            let resp = ilp_request("/accounts/alice/ilp", AUTH_PASSWORD)
+                .header("Content-Type", *content_type)
+                .body(PREPARE_BYTES.clone())
+                .reply(&api)
+                .await;
+            assert_eq!(resp.status().as_u16(), 415, "{}", content_type);
+        }
+
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/accounts/alice/ilp")
+            .header("Authorization", format!("Bearer {}", AUTH_PASSWORD))
+            .body(PREPARE_BYTES.clone())
+            .reply(&api)
+            .await;
+        assert_eq!(resp.status().as_u16(), 415);
+
+        // Parameters are allowed
+        let resp = ilp_request("/accounts/alice/ilp", AUTH_PASSWORD)
+            .header("Content-Type", "application/octet-stream; charset=binary")
+            .body(PREPARE_BYTES.clone())
+            .reply(&api)
+            .await;
+        assert_eq!(resp.status().as_u16(), 200);
+    }
+
     #[tokio::test]
     async fn new_api_test() {
         let store = TestStore;
@@ -5,8 +5,10 @@ use std::convert::TryFrom;
 use tracing::{debug, error};
 
 /// Sends an ILDCP Request to the provided service from the provided account
-/// and receives the account's ILP address and asset details
-pub async fn get_ildcp_info<S, A>(service: &mut S, account: A) -> Result<IldcpResponse, ()>
+/// and receives the account's ILP address and asset details.
+/// Responses with an asset scale above 18 or an asset code that is not
+/// printable ASCII are rejected.
+pub async fn get_ildcp_info<S, A>(service: &mut S, account: A) -> Result<IldcpConfig, ()>
 where
     S: IncomingService<A>,
     A: Account,
@@ -21,11 +23,8 @@ where
         .await?;
 
     let response = IldcpResponse::try_from(fulfill.into_data().freeze()).map_err(|err| {
-        error!(
-            "Unable to parse ILDCP response from fulfill packet: {:?}",
-            err
-        );
+        error!("Unable to use ILDCP response from fulfill packet: {}", err);
     })?;
     debug!("Got ILDCP response: {:?}", response);
-    Ok(response)
+    Ok(IldcpConfig::from(&response))
 }
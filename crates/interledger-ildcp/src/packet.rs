@@ -10,6 +10,7 @@ use std::{
     str::FromStr,
     time::{Duration, SystemTime},
 };
+use thiserror::Error;
 
 static PEER_PROTOCOL_FULFILLMENT: [u8; 32] = [0; 32];
 static PEER_PROTOCOL_CONDITION: [u8; 32] = [
@@ -17,6 +18,8 @@ static PEER_PROTOCOL_CONDITION: [u8; 32] = [
     110, 226, 51, 179, 144, 42, 89, 29, 13, 95, 41, 37,
 ];
 const ASSET_SCALE_LEN: usize = 1;
+/// One unit of an asset with a larger scale (10^19 base units) does not fit in an i64 balance
+pub const MAX_ASSET_SCALE: u8 = 18;
 
 static PEER_PROTOCOL_EXPIRY_DURATION: Lazy<Duration> = Lazy::new(|| Duration::from_secs(60));
 static ILDCP_DESTINATION: Lazy<Address> = Lazy::new(|| Address::from_str("peer.config").unwrap());
@@ -55,6 +58,20 @@ impl From<IldcpRequest> for Prepare {
     }
 }
 
+/// Errors from parsing an ILDCP Response or validating the asset details in it
+#[derive(Debug, Error)]
+pub enum IldcpResponseError {
+    #[error("Invalid ILDCP response: {0}")]
+    Parse(#[from] ParseError),
+    #[error(
+        "Invalid ILDCP response: asset scale {0} is not between 0 and {}",
+        MAX_ASSET_SCALE
+    )]
+    InvalidAssetScale(u8),
+    #[error("Invalid ILDCP response: asset code {0:?} is not non-empty printable ASCII")]
+    InvalidAssetCode(String),
+}
+
 /// The response to an ILDCP Request.
 #[derive(Clone, PartialEq, Eq)]
 pub struct IldcpResponse {
@@ -85,22 +102,31 @@ impl From<IldcpResponse> for Fulfill {
 }
 
 impl TryFrom<Bytes> for IldcpResponse {
-    type Error = ParseError;
+    type Error = IldcpResponseError;
 
     fn try_from(buffer: Bytes) -> Result<Self, Self::Error> {
         let mut reader = &buffer[..];
         let buffer_len = reader.len();
 
-        let buf = reader.read_var_octet_string()?;
-        let ilp_address = Address::try_from(buf)?;
+        let buf = reader.read_var_octet_string().map_err(ParseError::from)?;
+        let ilp_address = Address::try_from(buf).map_err(ParseError::from)?;
 
         if reader.remaining() < 1 {
-            return Err(ParseError::Oer(OerError::UnexpectedEof));
+            return Err(ParseError::Oer(OerError::UnexpectedEof).into());
         }
         let asset_scale = reader.get_u8();
+        if asset_scale > MAX_ASSET_SCALE {
+            return Err(IldcpResponseError::InvalidAssetScale(asset_scale));
+        }
 
         let asset_code_offset = buffer_len - reader.len();
-        reader.skip_var_octet_string()?;
+        let asset_code = reader.read_var_octet_string().map_err(ParseError::from)?;
+        // Printable ASCII excludes spaces and control characters
+        if asset_code.is_empty() || !asset_code.iter().all(u8::is_ascii_graphic) {
+            return Err(IldcpResponseError::InvalidAssetCode(
+                String::from_utf8_lossy(asset_code).into_owned(),
+            ));
+        }
 
         Ok(IldcpResponse {
             buffer,
@@ -127,6 +153,26 @@ impl IldcpResponse {
     }
 }
 
+/// The ILP address and asset details a peer has configured for our account,
+/// as received in an ILDCP Response
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IldcpConfig {
+    pub ilp_address: Address,
+    pub asset_code: String,
+    pub asset_scale: u8,
+}
+
+impl From<&IldcpResponse> for IldcpConfig {
+    fn from(response: &IldcpResponse) -> Self {
+        IldcpConfig {
+            ilp_address: response.ilp_address(),
+            // Parsed responses only contain ASCII asset codes
+            asset_code: String::from_utf8_lossy(response.asset_code()).into_owned(),
+            asset_scale: response.asset_scale(),
+        }
+    }
+}
+
 impl fmt::Debug for IldcpResponse {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
@@ -167,3 +213,76 @@ impl<'a> IldcpResponseBuilder<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Serializes a response by hand, so it can contain values the builder would not produce
+    fn response(asset_scale: u8, asset_code: &[u8]) -> Bytes {
+        let address = b"example.alice";
+        let mut buffer = BytesMut::new();
+        buffer.put_var_octet_string(&address[..]);
+        buffer.put_u8(asset_scale);
+        buffer.put_var_octet_string(asset_code);
+        buffer.freeze()
+    }
+
+    #[test]
+    fn parses_valid_response() {
+        let parsed = IldcpResponse::try_from(response(18, b"XRP")).unwrap();
+        assert_eq!(
+            IldcpConfig::from(&parsed),
+            IldcpConfig {
+                ilp_address: Address::from_str("example.alice").unwrap(),
+                asset_code: "XRP".to_string(),
+                asset_scale: 18,
+            }
+        );
+        assert_eq!(
+            IldcpResponse::try_from(response(0, b"USD"))
+                .unwrap()
+                .asset_scale(),
+            0
+        );
+    }
+
+    #[test]
+    fn rejects_asset_scale_out_of_range() {
+        for scale in &[19, 255] {
+            assert!(matches!(
+                IldcpResponse::try_from(response(*scale, b"XYZ")),
+                Err(IldcpResponseError::InvalidAssetScale(s)) if s == *scale
+            ));
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_asset_codes() {
+        for asset_code in &[&b""[..], b"US D", b"USD\n", b"\x00", "€".as_bytes()] {
+            let err = IldcpResponse::try_from(response(9, asset_code)).unwrap_err();
+            assert!(
+                matches!(err, IldcpResponseError::InvalidAssetCode(_)),
+                "{:?} was not rejected: {:?}",
+                asset_code,
+                err
+            );
+        }
+        let err = IldcpResponse::try_from(response(9, b"")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid ILDCP response: asset code \"\" is not non-empty printable ASCII"
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_response() {
+        let full = response(9, b"XYZ");
+        for len in &[0, 5, 14, 15, full.len() - 1] {
+            assert!(matches!(
+                IldcpResponse::try_from(full.slice(..*len)),
+                Err(IldcpResponseError::Parse(_))
+            ));
+        }
+    }
+}
@@ -106,8 +106,23 @@ mod tests {
         assert_eq!(result.data().len(), 19);
 
         let ildpc_info = get_ildcp_info(&mut service, from).await.unwrap();
-        assert_eq!(ildpc_info.ilp_address(), EXAMPLE_ADDRESS.clone());
-        assert_eq!(ildpc_info.asset_code(), b"XYZ");
-        assert_eq!(ildpc_info.asset_scale(), 9);
+        assert_eq!(ildpc_info.ilp_address, EXAMPLE_ADDRESS.clone());
+        assert_eq!(ildpc_info.asset_code, "XYZ");
+        assert_eq!(ildpc_info.asset_scale, 9);
+    }
+
+    #[tokio::test]
+    async fn rejects_response_with_invalid_asset_details() {
+        let mut service = incoming_service_fn(|_| {
+            Ok(Fulfill::from(
+                IldcpResponseBuilder {
+                    ilp_address: &EXAMPLE_ADDRESS,
+                    asset_code: "XYZ",
+                    asset_scale: 19,
+                }
+                .build(),
+            ))
+        });
+        assert!(get_ildcp_info(&mut service, TestAccount).await.is_err());
     }
 }
@@ -7,7 +7,7 @@ use once_cell::sync::Lazy;
 use std::convert::TryFrom;
 
 use ilp::Address;
-use ilp::{ErrorCode, Fulfill, Prepare, Reject};
+use ilp::{ErrorCode, Fulfill, Prepare, PrepareRef, Reject};
 use ilp::{FulfillBuilder, PrepareBuilder, RejectBuilder};
 use interledger_packet as ilp;
 use std::str::FromStr;
@@ -83,6 +83,15 @@ fn benchmark_deserialize(c: &mut Criterion) {
         });
     });
 
+    let prepare_bytes = BytesMut::from(PREPARE.build());
+    c.bench_function("Prepare (deserialize, borrowed)", move |b| {
+        b.iter(|| {
+            let parsed = PrepareRef::try_from(&prepare_bytes[..]).unwrap();
+            assert_eq!(parsed.amount(), PREPARE.amount);
+            assert_eq!(parsed.destination(), &*PREPARE.destination);
+        });
+    });
+
     let fulfill_bytes = BytesMut::from(FULFILL.build());
     c.bench_function("Fulfill (deserialize)", move |b| {
         b.iter(|| {
@@ -22,8 +22,21 @@ pub enum AddressError {
     InvalidLength(usize),
     #[error("Invalid address format")]
     InvalidFormat,
+    #[error("Invalid address scheme: {0:?}")]
+    InvalidScheme(String),
+    #[error("Invalid address character '{}' at position {1}", std::ascii::escape_default(*.0))]
+    InvalidCharacter(u8, usize),
+    #[error("Invalid address: empty segment at position {0}")]
+    EmptySegment(usize),
+    #[error("Invalid address: no segments after the scheme")]
+    SchemeOnly,
 }
 
+/// Allocation schemes an address may start with.
+const SCHEMES: &[&[u8]] = &[
+    b"g", b"private", b"example", b"peer", b"self", b"test", b"test1", b"test2", b"test3", b"local",
+];
+
 // SAFETY: this regex must only match utf-8, as the conversions in Address use unchecked
 // conversions.
 static ADDRESS_PATTERN: Lazy<regex::bytes::Regex> = Lazy::new(|| {
@@ -55,24 +68,76 @@ impl Address {
             return Err(AddressError::InvalidLength(buf.remaining()));
         }
 
-        let (bytes, matches) = if buf.chunk().len() == buf.remaining() {
+        let bytes = if buf.chunk().len() == buf.remaining() {
             // the underlying buffer is not a chained buf
-            (None, ADDRESS_PATTERN.is_match(buf.chunk()))
+            Address::validate(buf.chunk())?;
+            buf.copy_to_bytes(buf.remaining())
         } else {
             // the underlying buffer is multiple slices, copy it now for simplest possible matching
             let bytes = buf.copy_to_bytes(buf.remaining());
-            let matches = ADDRESS_PATTERN.is_match(bytes.as_ref());
-            (Some(bytes), matches)
+            Address::validate(&bytes)?;
+            bytes
         };
 
-        if matches {
-            // it's important that we only call copy_to_bytes once as it advances internal state
-            let bytes = bytes.unwrap_or_else(|| buf.copy_to_bytes(buf.remaining()));
-            Ok(Address(bytes))
+        Ok(Address(bytes))
+    }
+
+    /// Checks that `bytes` hold a valid address without copying them, returning them as a
+    /// string if so.
+    ///
+    /// An address must be at most 1023 bytes, start with one of the allocation schemes (`g`,
+    /// `private`, `example`, `peer`, `self`, `test`, `test1`, `test2`, `test3` or `local`) and
+    /// have one or more segments after it. Segments are separated by `.` and consist of
+    /// `[A-Za-z0-9_~-]`. The error describes the first rule that is broken.
+    pub fn validate(bytes: &[u8]) -> Result<&str, AddressError> {
+        // https://interledger.org/rfcs/0015-ilp-addresses/#address-requirements
+        if bytes.len() > MAX_ADDRESS_LENGTH {
+            return Err(AddressError::InvalidLength(bytes.len()));
+        }
+
+        if ADDRESS_PATTERN.is_match(bytes) {
+            // SAFETY: the pattern only matches utf-8
+            Ok(unsafe { str::from_utf8_unchecked(bytes) })
         } else {
-            Err(AddressError::InvalidFormat)
+            Err(Address::diagnose(bytes))
         }
     }
+
+    /// Finds out why `bytes` didn't match [`ADDRESS_PATTERN`].
+    fn diagnose(bytes: &[u8]) -> AddressError {
+        if bytes.is_empty() {
+            return AddressError::InvalidLength(0);
+        }
+
+        let allowed = |b: u8| b.is_ascii_alphanumeric() || b"_~-.".contains(&b);
+        if let Some(position) = bytes.iter().position(|&b| !allowed(b)) {
+            return AddressError::InvalidCharacter(bytes[position], position);
+        }
+
+        let mut position = 0;
+        for segment in bytes.split(|&b| b == b'.') {
+            if segment.is_empty() {
+                return AddressError::EmptySegment(position);
+            }
+            position += segment.len() + 1;
+        }
+
+        let scheme = bytes.split(|&b| b == b'.').next().unwrap_or_default();
+        if !SCHEMES.contains(&scheme) {
+            return AddressError::InvalidScheme(String::from_utf8_lossy(scheme).into_owned());
+        }
+
+        if scheme.len() == bytes.len() {
+            return AddressError::SchemeOnly;
+        }
+
+        AddressError::InvalidFormat
+    }
+
+    /// Wraps bytes which have already passed [`Address::validate`].
+    pub(crate) fn from_validated(bytes: Bytes) -> Self {
+        Address(bytes)
+    }
 }
 
 impl TryFrom<Bytes> for Address {
@@ -198,8 +263,23 @@ impl<'de> serde::Deserialize<'de> for Address {
     where
         D: serde::Deserializer<'de>,
     {
-        let string = <&str>::deserialize(deserializer)?;
-        Address::from_str(string).map_err(serde::de::Error::custom)
+        struct AddressVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for AddressVisitor {
+            type Value = Address;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("an ILP address")
+            }
+
+            // borrowed and owned strings both end up here, so the address can also be read
+            // from buffered input such as a `serde_json::Value`
+            fn visit_str<E: serde::de::Error>(self, value: &str) -> Result<Address, E> {
+                Address::from_str(value).map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_str(AddressVisitor)
     }
 }
 
@@ -274,6 +354,53 @@ mod test_address {
         assert!(Address::try_from(too_long_address).is_err());
     }
 
+    #[test]
+    fn test_validate() {
+        for address in VALID_ADDRESSES {
+            assert!(Address::validate(address).is_ok());
+        }
+
+        let cases: &[(&[u8], &str)] = &[
+            (b"", "Invalid address length: 0"),
+            (&make_address(1024), "Invalid address length: 1024"),
+            (
+                b"test.alice 123",
+                "Invalid address character ' ' at position 10",
+            ),
+            (
+                b"test.alic\xF0",
+                "Invalid address character '\\xf0' at position 9",
+            ),
+            (b"test.", "Invalid address: empty segment at position 5"),
+            (
+                b".test.alice",
+                "Invalid address: empty segment at position 0",
+            ),
+            (
+                b"test..alice",
+                "Invalid address: empty segment at position 5",
+            ),
+            (b"what.alice", "Invalid address scheme: \"what\""),
+            (b"test4.alice", "Invalid address scheme: \"test4\""),
+            (b"test", "Invalid address: no segments after the scheme"),
+        ];
+        for (address, message) in cases {
+            assert_eq!(
+                Address::validate(address).unwrap_err().to_string(),
+                *message,
+                "address: {:?}",
+                String::from_utf8_lossy(address),
+            );
+        }
+
+        for address in INVALID_ADDRESSES {
+            assert!(!matches!(
+                Address::validate(address),
+                Ok(_) | Err(AddressError::InvalidFormat)
+            ));
+        }
+    }
+
     #[test]
     fn test_deserialize() {
         assert_de_tokens(
@@ -282,7 +409,7 @@ mod test_address {
         );
         assert_de_tokens_error::<Address>(
             &[Token::BorrowedStr("test.alice ")],
-            "Invalid address format",
+            "Invalid address character ' ' at position 10",
         );
     }
 
@@ -0,0 +1,107 @@
+use super::AmountOverflowError;
+use std::convert::TryFrom;
+use std::fmt;
+use std::iter::Sum;
+
+/// An aggregate amount, wide enough to sum any number of packet amounts without overflowing.
+///
+/// Packets carry their amounts as a `u64` on the wire; `Amount` is meant for the accounting
+/// done across many packets. Converting back to a packet amount fails with an
+/// [`AmountOverflowError`] rather than truncating.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Amount(u128);
+
+impl Amount {
+    pub const ZERO: Amount = Amount(0);
+    pub const MAX: Amount = Amount(u128::MAX);
+
+    pub const fn new(value: u128) -> Self {
+        Amount(value)
+    }
+
+    #[inline]
+    pub const fn value(self) -> u128 {
+        self.0
+    }
+
+    #[inline]
+    pub fn checked_add(self, other: impl Into<Amount>) -> Option<Self> {
+        self.0.checked_add(other.into().0).map(Amount)
+    }
+
+    #[inline]
+    pub fn checked_sub(self, other: impl Into<Amount>) -> Option<Self> {
+        self.0.checked_sub(other.into().0).map(Amount)
+    }
+
+    /// Adds, clamping to `Amount::MAX`. That takes more than 2^64 packets of `u64::MAX`.
+    #[inline]
+    pub fn saturating_add(self, other: impl Into<Amount>) -> Self {
+        Amount(self.0.saturating_add(other.into().0))
+    }
+}
+
+impl Sum<u64> for Amount {
+    fn sum<I: Iterator<Item = u64>>(amounts: I) -> Self {
+        amounts.fold(Amount::ZERO, Amount::saturating_add)
+    }
+}
+
+impl From<u64> for Amount {
+    fn from(amount: u64) -> Self {
+        Amount(u128::from(amount))
+    }
+}
+
+impl From<u128> for Amount {
+    fn from(amount: u128) -> Self {
+        Amount(amount)
+    }
+}
+
+impl TryFrom<Amount> for u64 {
+    type Error = AmountOverflowError;
+
+    fn try_from(amount: Amount) -> Result<Self, Self::Error> {
+        u64::try_from(amount.0).map_err(|_| AmountOverflowError(amount.0))
+    }
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0, formatter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sums_past_u64_max() {
+        let total = Amount::from(u64::MAX).checked_add(u64::MAX).unwrap();
+        assert_eq!(total.value(), 2 * u128::from(u64::MAX));
+        assert_eq!(total.checked_sub(u64::MAX), Some(Amount::from(u64::MAX)));
+        assert_eq!(Amount::MAX.checked_add(1u64), None);
+        assert_eq!(Amount::ZERO.checked_sub(1u64), None);
+
+        let sum: Amount = vec![u64::MAX; 3].into_iter().sum();
+        assert_eq!(sum.value(), 3 * u128::from(u64::MAX));
+        assert_eq!(Amount::MAX.saturating_add(u64::MAX), Amount::MAX);
+    }
+
+    #[test]
+    fn converts_to_packet_amount_at_boundary() {
+        assert_eq!(u64::try_from(Amount::from(u64::MAX)).unwrap(), u64::MAX);
+        assert_eq!(u64::try_from(Amount::ZERO).unwrap(), 0);
+
+        let just_over = u128::from(u64::MAX) + 1;
+        let err = u64::try_from(Amount::new(just_over)).unwrap_err();
+        assert_eq!(err, AmountOverflowError(just_over));
+        assert_eq!(
+            err.to_string(),
+            "Amount 18446744073709551616 does not fit in a packet amount"
+        );
+        assert!(u64::try_from(Amount::MAX).is_err());
+    }
+}
@@ -122,9 +122,67 @@ impl fmt::Display for ErrorCode {
     }
 }
 
+#[cfg(any(feature = "serde", test))]
+impl serde::Serialize for ErrorCode {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(
+            str::from_utf8(&self.0[..]).expect("ErrorCode::new accepts only IA5String or ascii"),
+        )
+    }
+}
+
+#[cfg(any(feature = "serde", test))]
+impl<'de> serde::Deserialize<'de> for ErrorCode {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        use std::convert::TryFrom;
+
+        struct ErrorCodeVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for ErrorCodeVisitor {
+            type Value = ErrorCode;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a three character ILP error code")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, value: &str) -> Result<ErrorCode, E> {
+                <[u8; 3]>::try_from(value.as_bytes())
+                    .ok()
+                    .and_then(ErrorCode::new)
+                    .ok_or_else(|| E::custom("error code must be 3 ascii characters"))
+            }
+        }
+
+        deserializer.deserialize_str(ErrorCodeVisitor)
+    }
+}
+
 #[cfg(test)]
 mod test_error_code {
     use super::*;
+    use serde_test::{assert_de_tokens_error, assert_tokens, Token};
+
+    #[test]
+    fn test_serde() {
+        assert_tokens(
+            &ErrorCode::F08_AMOUNT_TOO_LARGE,
+            &[Token::BorrowedStr("F08")],
+        );
+        assert_de_tokens_error::<ErrorCode>(
+            &[Token::BorrowedStr("F0")],
+            "error code must be 3 ascii characters",
+        );
+        assert_de_tokens_error::<ErrorCode>(
+            &[Token::BorrowedStr("F\u{e9}")],
+            "error code must be 3 ascii characters",
+        );
+    }
 
     #[test]
     fn test_class() {
@@ -21,6 +21,11 @@ pub enum ParseError {
     NonRoundtrippableTimestamp,
 }
 
+/// An aggregate [`Amount`](crate::Amount) was too large to be sent in a single packet.
+#[derive(PartialEq, Eq, Debug, thiserror::Error)]
+#[error("Amount {0} does not fit in a packet amount")]
+pub struct AmountOverflowError(pub u128);
+
 #[derive(Debug, thiserror::Error)]
 pub enum PacketTypeError {
     #[error("Unknown packet type")]
@@ -63,6 +68,8 @@ pub enum LengthPrefixError {
     TooLarge,
     #[error("length prefix overflow")]
     UsizeOverflow,
+    #[error("length prefix {0} exceeds the limit of {1}")]
+    ExceedsLimit(usize, usize),
     #[error("variable length prefix with unnecessary multibyte length")]
     LeadingZeros,
     #[cfg(feature = "strict")]
@@ -1,5 +1,25 @@
 use std::fmt;
 
+/// Encodes the bytes as a lowercase hex string.
+pub fn encode(bytes: &[u8]) -> String {
+    format!("{:?}", HexString(bytes))
+}
+
+/// Decodes a hex string of either case, or returns `None` if it isn't one.
+pub fn decode(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() & 1 != 0 {
+        return None;
+    }
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            let high = char::from(pair[0]).to_digit(16)?;
+            let low = char::from(pair[1]).to_digit(16)?;
+            Some((high << 4 | low) as u8)
+        })
+        .collect()
+}
+
 /// Slice as hex string debug formatter, doesn't require allocating a string.
 #[derive(PartialEq, Eq)]
 pub struct HexString<'a>(pub &'a [u8]);
@@ -12,3 +32,18 @@ impl<'a> fmt::Debug for HexString<'a> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrips() {
+        let bytes = [0x00, 0x7f, 0x80, 0xab, 0xff];
+        assert_eq!(encode(&bytes), "007f80abff");
+        assert_eq!(decode("007F80abff").unwrap(), bytes);
+        assert_eq!(decode("").unwrap(), Vec::<u8>::new());
+        assert_eq!(decode("abc"), None);
+        assert_eq!(decode("zz"), None);
+    }
+}
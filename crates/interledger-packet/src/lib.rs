@@ -3,6 +3,7 @@
 //! Interledger packet serialization/deserialization.
 
 mod address;
+mod amount;
 
 mod error;
 mod errors;
@@ -13,11 +14,15 @@ pub mod oer;
 mod packet;
 
 pub use self::address::{Address, AddressError};
+pub use self::amount::Amount;
 pub use self::error::{ErrorClass, ErrorCode};
-pub use self::errors::{OerError, PacketTypeError, ParseError, TrailingBytesError};
+pub use self::errors::{
+    AmountOverflowError, OerError, PacketTypeError, ParseError, TrailingBytesError,
+};
 
 pub use self::packet::MaxPacketAmountDetails;
-pub use self::packet::{Fulfill, Packet, PacketType, Prepare, Reject};
+pub use self::packet::DEFAULT_MAX_CONTENT_LEN;
+pub use self::packet::{Fulfill, Packet, PacketType, Prepare, PrepareRef, Reject};
 pub use self::packet::{FulfillBuilder, PrepareBuilder, RejectBuilder};
 
 #[cfg(any(fuzzing, test))]
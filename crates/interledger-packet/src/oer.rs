@@ -65,6 +65,12 @@ pub trait BufOerExt<'a> {
     fn skip(&mut self, discard_bytes: usize) -> Result<(), OerError>;
     fn skip_var_octet_string(&mut self) -> Result<(), OerError>;
     fn read_var_octet_string_length(&mut self) -> Result<usize, OerError>;
+    /// Like [`BufOerExt::read_var_octet_string_length`], but fails with
+    /// [`LengthPrefixError::ExceedsLimit`] if the length is over `max_len`.
+    fn read_var_octet_string_length_bounded(&mut self, max_len: usize) -> Result<usize, OerError>;
+    /// Like [`BufOerExt::read_var_octet_string`], but fails with
+    /// [`LengthPrefixError::ExceedsLimit`] if the length is over `max_len`.
+    fn read_var_octet_string_bounded(&mut self, max_len: usize) -> Result<&'a [u8], OerError>;
     fn read_var_uint(&mut self) -> Result<u64, OerError>;
 
     /// Decodes a variable length timestamp according to [RFC-0030].
@@ -90,7 +96,12 @@ impl<'a> BufOerExt<'a> for &'a [u8] {
     /// Decodes variable-length octet string.
     #[inline]
     fn read_var_octet_string(&mut self) -> Result<&'a [u8], OerError> {
-        let actual_length = self.read_var_octet_string_length()?;
+        self.read_var_octet_string_bounded(usize::MAX)
+    }
+
+    #[inline]
+    fn read_var_octet_string_bounded(&mut self, max_len: usize) -> Result<&'a [u8], OerError> {
+        let actual_length = self.read_var_octet_string_length_bounded(max_len)?;
         if self.len() < actual_length {
             Err(OerError::UnexpectedEof)
         } else {
@@ -119,6 +130,11 @@ impl<'a> BufOerExt<'a> for &'a [u8] {
     #[doc(hidden)]
     #[inline]
     fn read_var_octet_string_length(&mut self) -> Result<usize, OerError> {
+        self.read_var_octet_string_length_bounded(usize::MAX)
+    }
+
+    #[inline]
+    fn read_var_octet_string_length_bounded(&mut self, max_len: usize) -> Result<usize, OerError> {
         if self.remaining() < 1 {
             return Err(OerError::UnexpectedEof);
         }
@@ -148,10 +164,10 @@ impl<'a> BufOerExt<'a> for &'a [u8] {
                 // it makes no sense for a length to be u64 but usize, and even that is quite a lot
                 let uint = usize::try_from(uint).map_err(|_| LengthPrefixError::UsizeOverflow)?;
 
-                Ok(uint)
+                check_length_limit(uint, max_len)
             }
         } else {
-            Ok(length as usize)
+            check_length_limit(length as usize, max_len)
         }
     }
 
@@ -245,6 +261,14 @@ impl fmt::Display for VariableLengthTimestamp {
     }
 }
 
+fn check_length_limit(length: usize, max_len: usize) -> Result<usize, OerError> {
+    if length > max_len {
+        Err(LengthPrefixError::ExceedsLimit(length, max_len).into())
+    } else {
+        Ok(length)
+    }
+}
+
 fn check_no_leading_zeroes(_size_on_wire: usize, _uint: u64) -> Result<(), LengthPrefixError> {
     #[cfg(feature = "strict")]
     if _size_on_wire != predict_var_uint_size(_uint) as usize {
@@ -443,6 +467,30 @@ mod test_buf_oer_ext {
         }
     }
 
+    #[test]
+    fn read_bounded_var_octet_string() {
+        // A length prefix claiming 4GiB of content followed by almost nothing
+        let huge: &[u8] = &[HIGH_BIT | 4, 0xff, 0xff, 0xff, 0xff, 0x00];
+        assert_eq!(
+            (&huge[..]).read_var_octet_string_bounded(1024).unwrap_err(),
+            OerError::LengthPrefix(LengthPrefixError::ExceedsLimit(0xffff_ffff, 1024))
+        );
+        assert_eq!(
+            (&huge[..]).read_var_octet_string().unwrap_err(),
+            OerError::UnexpectedEof
+        );
+
+        let short: &[u8] = &[0x02, 0xb0, 0xb1];
+        assert_eq!(
+            (&short[..]).read_var_octet_string_bounded(2).unwrap(),
+            &[0xb0, 0xb1]
+        );
+        assert_eq!(
+            (&short[..]).read_var_octet_string_bounded(1).unwrap_err(),
+            OerError::LengthPrefix(LengthPrefixError::ExceedsLimit(2, 1))
+        );
+    }
+
     #[test]
     fn read_var_octet_string_length_buffer_too_small() {
         // The length of the octet string means 1 + 4 bytes of total length, but only at most 1 + 3
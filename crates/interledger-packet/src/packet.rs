@@ -2,7 +2,7 @@ use std::fmt;
 use std::str;
 use std::time::SystemTime;
 
-use bytes::{Buf, BufMut, BytesMut};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use chrono::{DateTime, TimeZone, Utc};
 
 use crate::oer::{self, BufOerExt, MutBufOerExt};
@@ -17,6 +17,15 @@ const CONDITION_LEN: usize = 32;
 const FULFILLMENT_LEN: usize = 32;
 const ERROR_CODE_LEN: usize = 3;
 
+/// Largest packet content the parsers accept unless given another limit: a Reject with the
+/// longest address, message and data [RFC-0027] allows.
+///
+/// [RFC-0027]: https://github.com/interledger/rfcs/blob/master/0027-interledger-protocol-4/0027-interledger-protocol-4.md
+pub const DEFAULT_MAX_CONTENT_LEN: usize = ERROR_CODE_LEN
+    + oer::predict_var_octet_string(1023)
+    + oer::predict_var_octet_string(8191)
+    + oer::predict_var_octet_string(32767);
+
 // NOTE: this is strictly different from the oer::GENERALIZED_TIMESTAMP_FORMAT which has a dot, and
 // is used for much more lenient timestamps with 0-3 fractions.
 static INTERLEDGER_TIMESTAMP_FORMAT: &str = "%Y%m%d%H%M%S%3f";
@@ -59,6 +68,11 @@ impl TryFrom<u8> for PacketType {
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(
+    any(feature = "serde", test),
+    derive(serde::Serialize, serde::Deserialize),
+    serde(tag = "type", rename_all = "snake_case")
+)]
 pub enum Packet {
     Prepare(Prepare),
     Fulfill(Fulfill),
@@ -69,10 +83,21 @@ impl TryFrom<BytesMut> for Packet {
     type Error = ParseError;
 
     fn try_from(buffer: BytesMut) -> Result<Self, Self::Error> {
+        Packet::try_from_with_max_len(buffer, DEFAULT_MAX_CONTENT_LEN)
+    }
+}
+
+impl Packet {
+    /// Parses a packet, failing if its declared content length is over `max_len`.
+    pub fn try_from_with_max_len(buffer: BytesMut, max_len: usize) -> Result<Self, ParseError> {
         match PacketType::try_from(buffer.as_ref())? {
-            PacketType::Prepare => Prepare::try_from(buffer).map(Packet::from),
-            PacketType::Fulfill => Fulfill::try_from(buffer).map(Packet::from),
-            PacketType::Reject => Reject::try_from(buffer).map(Packet::from),
+            PacketType::Prepare => {
+                Prepare::try_from_with_max_len(buffer, max_len).map(Packet::from)
+            }
+            PacketType::Fulfill => {
+                Fulfill::try_from_with_max_len(buffer, max_len).map(Packet::from)
+            }
+            PacketType::Reject => Reject::try_from_with_max_len(buffer, max_len).map(Packet::from),
         }
     }
 }
@@ -124,11 +149,65 @@ pub struct PrepareBuilder<'a> {
     pub data: &'a [u8],
 }
 
+/// A Prepare packet parsed in place, borrowing its fields from the original buffer.
+///
+/// Use it to inspect incoming packets without allocating; [`PrepareRef::to_owned`] makes a
+/// [`Prepare`] once ownership is needed.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct PrepareRef<'a> {
+    buffer: &'a [u8],
+    content_offset: usize,
+    destination: &'a str,
+    amount: u64,
+    expires_at: SystemTime,
+    data_offset: usize,
+}
+
 impl TryFrom<BytesMut> for Prepare {
     type Error = ParseError;
 
     fn try_from(buffer: BytesMut) -> Result<Self, Self::Error> {
-        let (content_offset, mut content) = deserialize_envelope(PacketType::Prepare, &buffer)?;
+        Prepare::try_from_with_max_len(buffer, DEFAULT_MAX_CONTENT_LEN)
+    }
+}
+
+impl Prepare {
+    /// Parses a Prepare, failing if its declared content length is over `max_len`.
+    pub fn try_from_with_max_len(buffer: BytesMut, max_len: usize) -> Result<Self, ParseError> {
+        let prepare = PrepareRef::try_from_with_max_len(&buffer[..], max_len)?;
+        let destination =
+            Address::from_validated(Bytes::copy_from_slice(prepare.destination.as_bytes()));
+        let (content_offset, amount, expires_at, data_offset) = (
+            prepare.content_offset,
+            prepare.amount,
+            prepare.expires_at,
+            prepare.data_offset,
+        );
+
+        Ok(Prepare {
+            buffer,
+            content_offset,
+            destination,
+            amount,
+            expires_at,
+            data_offset,
+        })
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for PrepareRef<'a> {
+    type Error = ParseError;
+
+    fn try_from(buffer: &'a [u8]) -> Result<Self, Self::Error> {
+        PrepareRef::try_from_with_max_len(buffer, DEFAULT_MAX_CONTENT_LEN)
+    }
+}
+
+impl<'a> PrepareRef<'a> {
+    /// Parses a Prepare in place, failing if its declared content length is over `max_len`.
+    pub fn try_from_with_max_len(buffer: &'a [u8], max_len: usize) -> Result<Self, ParseError> {
+        let (content_offset, mut content) =
+            deserialize_envelope(PacketType::Prepare, buffer, max_len)?;
         let content_len = content.len();
 
         const MIN_LEN: usize = AMOUNT_LEN
@@ -185,7 +264,7 @@ impl TryFrom<BytesMut> for Prepare {
         // Skip execution condition.
         content.skip(CONDITION_LEN)?;
 
-        let destination = Address::try_from(content.read_var_octet_string()?)?;
+        let destination = Address::validate(content.read_var_octet_string()?)?;
 
         // Skip the data.
         let data_offset = content_offset + content_len - content.len();
@@ -193,7 +272,7 @@ impl TryFrom<BytesMut> for Prepare {
 
         ensure_no_inner_trailing_bytes(content)?;
 
-        Ok(Prepare {
+        Ok(PrepareRef {
             buffer,
             content_offset,
             destination,
@@ -286,6 +365,78 @@ impl fmt::Debug for Prepare {
     }
 }
 
+impl<'a> PrepareRef<'a> {
+    #[inline]
+    pub fn amount(&self) -> u64 {
+        self.amount
+    }
+
+    #[inline]
+    pub fn expires_at(&self) -> SystemTime {
+        self.expires_at
+    }
+
+    /// The returned value always has a length of 32.
+    #[inline]
+    pub fn execution_condition(&self) -> &'a [u8] {
+        let begin = self.content_offset + AMOUNT_LEN + EXPIRY_LEN;
+        let end = begin + CONDITION_LEN;
+        &self.buffer[begin..end]
+    }
+
+    #[inline]
+    pub fn destination(&self) -> &'a str {
+        self.destination
+    }
+
+    #[inline]
+    pub fn data(&self) -> &'a [u8] {
+        (&self.buffer[self.data_offset..])
+            .peek_var_octet_string()
+            .unwrap()
+    }
+
+    /// Copies the packet into an owned [`Prepare`] without parsing it again.
+    pub fn to_owned(&self) -> Prepare {
+        Prepare {
+            buffer: BytesMut::from(self.buffer),
+            content_offset: self.content_offset,
+            destination: Address::from_validated(Bytes::copy_from_slice(
+                self.destination.as_bytes(),
+            )),
+            amount: self.amount,
+            expires_at: self.expires_at,
+            data_offset: self.data_offset,
+        }
+    }
+}
+
+impl<'a> AsRef<[u8]> for PrepareRef<'a> {
+    #[inline]
+    fn as_ref(&self) -> &[u8] {
+        self.buffer
+    }
+}
+
+impl<'a> fmt::Debug for PrepareRef<'a> {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter
+            .debug_struct("PrepareRef")
+            .field("destination", &self.destination())
+            .field("amount", &self.amount())
+            .field(
+                "expires_at",
+                &DateTime::<Utc>::from(self.expires_at()).to_rfc3339(),
+            )
+            .field(
+                "execution_condition",
+                &HexString(self.execution_condition()),
+            )
+            .field("data_length", &self.data().len())
+            .finish()
+    }
+}
+
 impl<'a> PrepareBuilder<'a> {
     pub fn build(&self) -> Prepare {
         const STATIC_LEN: usize = AMOUNT_LEN + EXPIRY_LEN + CONDITION_LEN;
@@ -340,7 +491,15 @@ impl TryFrom<BytesMut> for Fulfill {
     type Error = ParseError;
 
     fn try_from(buffer: BytesMut) -> Result<Self, Self::Error> {
-        let (content_offset, mut content) = deserialize_envelope(PacketType::Fulfill, &buffer)?;
+        Fulfill::try_from_with_max_len(buffer, DEFAULT_MAX_CONTENT_LEN)
+    }
+}
+
+impl Fulfill {
+    /// Parses a Fulfill, failing if its declared content length is over `max_len`.
+    pub fn try_from_with_max_len(buffer: BytesMut, max_len: usize) -> Result<Self, ParseError> {
+        let (content_offset, mut content) =
+            deserialize_envelope(PacketType::Fulfill, &buffer, max_len)?;
 
         content.skip(FULFILLMENT_LEN)?;
         content.skip_var_octet_string()?;
@@ -442,7 +601,15 @@ impl TryFrom<BytesMut> for Reject {
     type Error = ParseError;
 
     fn try_from(buffer: BytesMut) -> Result<Self, Self::Error> {
-        let (content_offset, mut content) = deserialize_envelope(PacketType::Reject, &buffer)?;
+        Reject::try_from_with_max_len(buffer, DEFAULT_MAX_CONTENT_LEN)
+    }
+}
+
+impl Reject {
+    /// Parses a Reject, failing if its declared content length is over `max_len`.
+    pub fn try_from_with_max_len(buffer: BytesMut, max_len: usize) -> Result<Self, ParseError> {
+        let (content_offset, mut content) =
+            deserialize_envelope(PacketType::Reject, &buffer, max_len)?;
         let content_len = content.len();
 
         const MIN_LEN: usize = ERROR_CODE_LEN + oer::EMPTY_VARLEN_OCTETS_LEN * 3;
@@ -587,6 +754,7 @@ impl<'a> RejectBuilder<'a> {
 fn deserialize_envelope(
     packet_type: PacketType,
     mut reader: &[u8],
+    max_len: usize,
 ) -> Result<(usize, &[u8]), ParseError> {
     if reader.remaining() < PacketType::LEN {
         return Err(OerError::UnexpectedEof.into());
@@ -601,7 +769,7 @@ fn deserialize_envelope(
         // This could probably be determined a better way...
         let mut peek = reader;
         let before = peek.len();
-        peek.read_var_octet_string_length()?;
+        peek.read_var_octet_string_length_bounded(max_len)?;
         before - peek.len()
     };
 
@@ -691,6 +859,137 @@ fn ensure_no_outer_trailing_bytes(reader: &[u8]) -> Result<(), TrailingBytesErro
     }
 }
 
+/// JSON friendly representations of the packets: binary fields are hex encoded and amounts
+/// are strings so that JavaScript consumers don't lose precision. Canonically encoded packets
+/// come back byte-for-byte identical.
+#[cfg(any(feature = "serde", test))]
+mod serde_impls {
+    use super::*;
+    use crate::hex;
+    use chrono::SecondsFormat;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct PrepareJson {
+        amount: String,
+        expires_at: String,
+        execution_condition: String,
+        destination: Address,
+        data: String,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct FulfillJson {
+        fulfillment: String,
+        data: String,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct RejectJson {
+        code: ErrorCode,
+        message: String,
+        triggered_by: Option<Address>,
+        data: String,
+    }
+
+    fn decode_hex<E: Error>(field: &str, value: &str) -> Result<Vec<u8>, E> {
+        hex::decode(value).ok_or_else(|| E::custom(format!("{} must be hex encoded", field)))
+    }
+
+    fn decode_hash<E: Error>(field: &str, value: &str) -> Result<[u8; 32], E> {
+        <[u8; 32]>::try_from(&decode_hex::<E>(field, value)?[..])
+            .map_err(|_| E::custom(format!("{} must be 32 bytes", field)))
+    }
+
+    impl Serialize for Prepare {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            PrepareJson {
+                amount: self.amount().to_string(),
+                expires_at: DateTime::<Utc>::from(self.expires_at())
+                    .to_rfc3339_opts(SecondsFormat::Millis, true),
+                execution_condition: hex::encode(self.execution_condition()),
+                destination: self.destination(),
+                data: hex::encode(self.data()),
+            }
+            .serialize(serializer)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Prepare {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let json = PrepareJson::deserialize(deserializer)?;
+            let amount = json.amount.parse().map_err(D::Error::custom)?;
+            let expires_at =
+                DateTime::parse_from_rfc3339(&json.expires_at).map_err(D::Error::custom)?;
+            let execution_condition =
+                decode_hash("execution_condition", &json.execution_condition)?;
+            let data = decode_hex::<D::Error>("data", &json.data)?;
+            Ok(PrepareBuilder {
+                amount,
+                expires_at: expires_at.into(),
+                execution_condition: &execution_condition,
+                destination: json.destination,
+                data: &data,
+            }
+            .build())
+        }
+    }
+
+    impl Serialize for Fulfill {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            FulfillJson {
+                fulfillment: hex::encode(self.fulfillment()),
+                data: hex::encode(self.data()),
+            }
+            .serialize(serializer)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Fulfill {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let json = FulfillJson::deserialize(deserializer)?;
+            let fulfillment = decode_hash("fulfillment", &json.fulfillment)?;
+            let data = decode_hex::<D::Error>("data", &json.data)?;
+            Ok(FulfillBuilder {
+                fulfillment: &fulfillment,
+                data: &data,
+            }
+            .build())
+        }
+    }
+
+    impl Serialize for Reject {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            RejectJson {
+                code: self.code(),
+                message: hex::encode(self.message()),
+                triggered_by: self.triggered_by(),
+                data: hex::encode(self.data()),
+            }
+            .serialize(serializer)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Reject {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let json = RejectJson::deserialize(deserializer)?;
+            let message = decode_hex::<D::Error>("message", &json.message)?;
+            let data = decode_hex::<D::Error>("data", &json.data)?;
+            Ok(RejectBuilder {
+                code: json.code,
+                message: &message,
+                triggered_by: json.triggered_by.as_ref(),
+                data: &data,
+            }
+            .build())
+        }
+    }
+}
+
 #[cfg(test)]
 mod fuzzed {
     use super::Packet;
@@ -803,6 +1102,7 @@ mod test_packet_type {
 #[cfg(test)]
 mod test_packet {
     use super::*;
+    use crate::errors::LengthPrefixError;
     use crate::fixtures::{FULFILL, PREPARE, REJECT};
     use crate::fixtures::{FULFILL_BYTES, PREPARE_BYTES, REJECT_BYTES};
 
@@ -827,6 +1127,77 @@ mod test_packet {
         assert!(Packet::try_from(BytesMut::from(&[0x99][..])).is_err());
     }
 
+    #[test]
+    fn test_json_roundtrip() {
+        for (packet, bytes) in [
+            (Packet::Prepare(PREPARE.clone()), PREPARE_BYTES),
+            (Packet::Fulfill(FULFILL.clone()), FULFILL_BYTES),
+            (Packet::Reject(REJECT.clone()), REJECT_BYTES),
+        ] {
+            let json = serde_json::to_string(&packet).unwrap();
+            let parsed: Packet = serde_json::from_str(&json).unwrap();
+            assert_eq!(BytesMut::from(parsed), BytesMut::from(bytes), "{}", json);
+        }
+
+        let json = serde_json::to_value(&*PREPARE).unwrap();
+        assert_eq!(json["amount"], "107");
+        assert_eq!(json["expires_at"], "2018-06-07T20:48:42.483Z");
+        assert_eq!(json["destination"], "example.alice");
+        let prepare: Prepare = serde_json::from_value(json).unwrap();
+        assert_eq!(BytesMut::from(prepare), BytesMut::from(PREPARE_BYTES));
+
+        let json = serde_json::to_value(Packet::Reject(REJECT.clone())).unwrap();
+        assert_eq!(json["type"], "reject");
+        assert_eq!(json["code"], "F99");
+    }
+
+    #[test]
+    fn test_json_invalid_fields() {
+        let mut json = serde_json::to_value(&*FULFILL).unwrap();
+        json["fulfillment"] = "abcd".into();
+        let err = serde_json::from_value::<Fulfill>(json.clone()).unwrap_err();
+        assert_eq!(err.to_string(), "fulfillment must be 32 bytes");
+        json["fulfillment"] = "not hex".into();
+        let err = serde_json::from_value::<Fulfill>(json).unwrap_err();
+        assert_eq!(err.to_string(), "fulfillment must be hex encoded");
+
+        let mut json = serde_json::to_value(&*PREPARE).unwrap();
+        json["amount"] = 107.into();
+        assert!(serde_json::from_value::<Prepare>(json).is_err());
+    }
+
+    #[test]
+    fn test_huge_length_prefixes() {
+        for packet_type in [PacketType::Prepare, PacketType::Fulfill, PacketType::Reject] {
+            // content claiming 4GiB with only a few bytes behind it
+            let mut huge = vec![packet_type as u8, 0x84, 0xff, 0xff, 0xff, 0xff];
+            huge.extend_from_slice(&[0; 16]);
+            match Packet::try_from(BytesMut::from(&huge[..])).unwrap_err() {
+                ParseError::Oer(OerError::LengthPrefix(LengthPrefixError::ExceedsLimit(
+                    0xffff_ffff,
+                    DEFAULT_MAX_CONTENT_LEN,
+                ))) => {}
+                other => panic!("unexpected error: {:?}", other),
+            }
+        }
+
+        // a prepare whose data field claims 4GiB inside a small envelope
+        let mut prepare = BytesMut::from(PREPARE_BYTES);
+        let data_offset = prepare.len() - PREPARE.data().len() - 3;
+        prepare.truncate(data_offset);
+        prepare.extend_from_slice(&[0x84, 0xff, 0xff, 0xff, 0xff]);
+        let content_len = prepare.len() - 4;
+        prepare[2..4].copy_from_slice(&(content_len as u16).to_be_bytes());
+        assert!(Prepare::try_from(prepare).is_err());
+
+        // a custom limit applies to well formed packets too
+        assert!(Packet::try_from_with_max_len(BytesMut::from(PREPARE_BYTES), 64).is_err());
+        assert!(
+            Packet::try_from_with_max_len(BytesMut::from(FULFILL_BYTES), FULFILL_BYTES.len())
+                .is_ok()
+        );
+    }
+
     #[test]
     fn test_into_bytes_mut() {
         assert_eq!(
@@ -847,7 +1218,7 @@ mod test_packet {
 #[cfg(test)]
 mod test_prepare {
     use super::*;
-    use crate::fixtures::{self, PREPARE, PREPARE_BUILDER, PREPARE_BYTES};
+    use crate::fixtures::{self, FULFILL_BYTES, PREPARE, PREPARE_BUILDER, PREPARE_BYTES};
 
     #[test]
     fn test_invalid_address() {
@@ -866,6 +1237,50 @@ mod test_prepare {
         }
     }
 
+    #[test]
+    fn test_borrowed_matches_owned() {
+        let corpus = [
+            BytesMut::from(PREPARE_BYTES),
+            BytesMut::from(
+                PrepareBuilder {
+                    amount: u64::MAX,
+                    data: &[],
+                    ..PREPARE_BUILDER.clone()
+                }
+                .build(),
+            ),
+            BytesMut::from(
+                PrepareBuilder {
+                    amount: 0,
+                    destination: Address::try_from(&[b'g', b'.', b'a'][..]).unwrap(),
+                    data: &[0xab; 1024],
+                    ..PREPARE_BUILDER.clone()
+                }
+                .build(),
+            ),
+        ];
+
+        for bytes in corpus.iter() {
+            let owned = Prepare::try_from(bytes.clone()).unwrap();
+            let borrowed = PrepareRef::try_from(&bytes[..]).unwrap();
+            assert_eq!(borrowed.amount(), owned.amount());
+            assert_eq!(borrowed.expires_at(), owned.expires_at());
+            assert_eq!(borrowed.execution_condition(), owned.execution_condition());
+            assert_eq!(borrowed.destination(), &*owned.destination());
+            assert_eq!(borrowed.data(), owned.data());
+            assert_eq!(borrowed.as_ref(), owned.as_ref());
+            assert_eq!(borrowed.to_owned(), owned);
+        }
+    }
+
+    #[test]
+    fn test_borrowed_rejects_invalid() {
+        let mut prep = BytesMut::from(PREPARE_BYTES);
+        prep[67] = 42;
+        assert!(PrepareRef::try_from(&prep[..]).is_err());
+        assert!(PrepareRef::try_from(FULFILL_BYTES).is_err());
+    }
+
     #[test]
     fn test_try_from() {
         assert_eq!(
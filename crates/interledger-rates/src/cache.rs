@@ -0,0 +1,249 @@
+use super::RateProvider;
+use async_trait::async_trait;
+use interledger_errors::ExchangeRateStoreError;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+/// How long a rate is served without asking the provider again
+pub const DEFAULT_RATE_TTL: Duration = Duration::from_secs(60);
+/// How long a rate which could not be refreshed is still served
+pub const DEFAULT_MAX_STALENESS: Duration = Duration::from_secs(600);
+
+/// A rate served by a [`CachingRateProvider`](./struct.CachingRateProvider.html)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CachedRate {
+    pub rate: f64,
+    /// Whether the rate is older than the TTL, i.e. it is being refreshed or
+    /// the last refresh failed
+    pub stale: bool,
+}
+
+struct CacheEntry {
+    rate: f64,
+    fetched_at: Instant,
+    refreshing: bool,
+}
+
+/// Caches the rates of another provider
+///
+/// Rates younger than the TTL are served from the cache. Older rates are still served
+/// while they are refreshed in the background, until they become older than the max
+/// staleness; from then on callers wait for the provider and get its error if it fails.
+pub struct CachingRateProvider<P> {
+    provider: Arc<P>,
+    cache: Arc<Mutex<HashMap<(String, String), CacheEntry>>>,
+    ttl: Duration,
+    max_staleness: Duration,
+}
+
+// Derived Clone would require P: Clone, but only the Arcs are cloned
+impl<P> Clone for CachingRateProvider<P> {
+    fn clone(&self) -> Self {
+        CachingRateProvider {
+            provider: self.provider.clone(),
+            cache: self.cache.clone(),
+            ttl: self.ttl,
+            max_staleness: self.max_staleness,
+        }
+    }
+}
+
+impl<P> CachingRateProvider<P> {
+    pub fn new(provider: P) -> Self {
+        CachingRateProvider {
+            provider: Arc::new(provider),
+            cache: Arc::new(Mutex::new(HashMap::new())),
+            ttl: DEFAULT_RATE_TTL,
+            max_staleness: DEFAULT_MAX_STALENESS,
+        }
+    }
+
+    /// Sets how long a rate is served before it gets refreshed
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Sets how old a rate may become before it is no longer served if refreshing it fails
+    pub fn with_max_staleness(mut self, max_staleness: Duration) -> Self {
+        self.max_staleness = max_staleness;
+        self
+    }
+}
+
+impl<P> CachingRateProvider<P>
+where
+    P: RateProvider + Send + Sync + 'static,
+{
+    /// Returns the rate between the assets along with whether it is stale
+    pub async fn get_cached_rate(
+        &self,
+        from_asset: &str,
+        to_asset: &str,
+    ) -> Result<CachedRate, ExchangeRateStoreError> {
+        let key = (from_asset.to_string(), to_asset.to_string());
+        if let Some(entry) = self.cache.lock().unwrap().get_mut(&key) {
+            let age = entry.fetched_at.elapsed();
+            if age < self.ttl {
+                return Ok(CachedRate {
+                    rate: entry.rate,
+                    stale: false,
+                });
+            }
+            if age < self.max_staleness {
+                if !entry.refreshing {
+                    entry.refreshing = true;
+                    debug!(
+                        "Refreshing exchange rate {}/{} fetched {:?} ago",
+                        from_asset, to_asset, age
+                    );
+                    let this = self.clone();
+                    let key = key.clone();
+                    tokio::spawn(async move { this.refresh(key).await });
+                }
+                return Ok(CachedRate {
+                    rate: entry.rate,
+                    stale: true,
+                });
+            }
+        }
+
+        // There is no rate which may still be served, so wait for the provider
+        self.refresh(key)
+            .await
+            .map(|rate| CachedRate { rate, stale: false })
+    }
+
+    async fn refresh(&self, key: (String, String)) -> Result<f64, ExchangeRateStoreError> {
+        let result = self.provider.get_rate(&key.0, &key.1).await;
+        let mut cache = self.cache.lock().unwrap();
+        match result {
+            Ok(rate) => {
+                cache.insert(
+                    key,
+                    CacheEntry {
+                        rate,
+                        fetched_at: Instant::now(),
+                        refreshing: false,
+                    },
+                );
+            }
+            Err(ref err) => {
+                warn!(
+                    "Failed to refresh exchange rate {}/{}: {}",
+                    key.0, key.1, err
+                );
+                if let Some(entry) = cache.get_mut(&key) {
+                    entry.refreshing = false;
+                }
+            }
+        }
+        result
+    }
+}
+
+#[async_trait]
+impl<P> RateProvider for CachingRateProvider<P>
+where
+    P: RateProvider + Send + Sync + 'static,
+{
+    async fn get_rate(
+        &self,
+        from_asset: &str,
+        to_asset: &str,
+    ) -> Result<f64, ExchangeRateStoreError> {
+        self.get_cached_rate(from_asset, to_asset)
+            .await
+            .map(|cached| cached.rate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    #[derive(Clone, Default)]
+    struct TestProvider {
+        calls: Arc<AtomicUsize>,
+        failing: Arc<AtomicBool>,
+    }
+
+    #[async_trait]
+    impl RateProvider for TestProvider {
+        async fn get_rate(&self, from: &str, to: &str) -> Result<f64, ExchangeRateStoreError> {
+            let calls = self.calls.fetch_add(1, Ordering::SeqCst) + 1;
+            if self.failing.load(Ordering::SeqCst) {
+                Err(ExchangeRateStoreError::PairNotFound {
+                    from: from.to_string(),
+                    to: to.to_string(),
+                })
+            } else {
+                Ok(calls as f64)
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn serves_fresh_rates_from_cache() {
+        let provider = TestProvider::default();
+        let cache = CachingRateProvider::new(provider.clone());
+        for _ in 0..3 {
+            assert_eq!(
+                cache.get_cached_rate("ABC", "XYZ").await.unwrap(),
+                CachedRate {
+                    rate: 1.0,
+                    stale: false
+                }
+            );
+        }
+        assert_eq!(provider.calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn serves_stale_rate_while_refreshing() {
+        let provider = TestProvider::default();
+        let cache = CachingRateProvider::new(provider.clone()).with_ttl(Duration::from_millis(10));
+        cache.get_rate("ABC", "XYZ").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        assert_eq!(
+            cache.get_cached_rate("ABC", "XYZ").await.unwrap(),
+            CachedRate {
+                rate: 1.0,
+                stale: true
+            }
+        );
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert_eq!(
+            cache.get_cached_rate("ABC", "XYZ").await.unwrap(),
+            CachedRate {
+                rate: 2.0,
+                stale: false
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_stale_rate_until_max_staleness() {
+        let provider = TestProvider::default();
+        let cache = CachingRateProvider::new(provider.clone())
+            .with_ttl(Duration::from_millis(10))
+            .with_max_staleness(Duration::from_millis(100));
+        cache.get_rate("ABC", "XYZ").await.unwrap();
+        provider.failing.store(true, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        for _ in 0..3 {
+            assert!(cache.get_cached_rate("ABC", "XYZ").await.unwrap().stale);
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        // Every failed background refresh allows the next request to start another one
+        assert!(provider.calls.load(Ordering::SeqCst) > 2);
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(cache.get_rate("ABC", "XYZ").await.is_err());
+    }
+}
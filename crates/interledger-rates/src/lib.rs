@@ -1,3 +1,4 @@
+use async_trait::async_trait;
 use futures::TryFutureExt;
 use interledger_errors::ExchangeRateStoreError;
 use reqwest::Client;
@@ -13,6 +14,12 @@ mod cryptocompare;
 
 mod coincap;
 
+mod static_provider;
+pub use static_provider::StaticRateProvider;
+
+mod cache;
+pub use cache::{CachedRate, CachingRateProvider, DEFAULT_MAX_STALENESS, DEFAULT_RATE_TTL};
+
 pub trait ExchangeRateStore: Clone {
     // TODO we may want to make this async if/when we use pubsub to broadcast
     // rate changes to different instances of a horizontally-scalable node
@@ -30,6 +37,37 @@ pub trait ExchangeRateStore: Clone {
     fn get_all_exchange_rates(&self) -> Result<HashMap<String, f64>, ExchangeRateStoreError>;
 }
 
+/// A source of exchange rates between pairs of assets
+#[async_trait]
+pub trait RateProvider {
+    /// Returns how many units of `to_asset` a unit of `from_asset` is worth,
+    /// without taking the assets' scales into account
+    async fn get_rate(
+        &self,
+        from_asset: &str,
+        to_asset: &str,
+    ) -> Result<f64, ExchangeRateStoreError>;
+}
+
+/// Stores provide the rates which were last saved in them, either via the HTTP API
+/// or by an [`ExchangeRateFetcher`](./struct.ExchangeRateFetcher.html)
+#[async_trait]
+impl<S> RateProvider for S
+where
+    S: ExchangeRateStore + Send + Sync,
+{
+    async fn get_rate(
+        &self,
+        from_asset: &str,
+        to_asset: &str,
+    ) -> Result<f64, ExchangeRateStoreError> {
+        // Exchange rates are expressed as `base asset / asset`, e.g. if the source asset
+        // is worth 1 USD and the destination asset is worth 10 USD, the rate is 1/10
+        let rates = self.get_exchange_rates(&[from_asset, to_asset])?;
+        Ok(rates[0] / rates[1])
+    }
+}
+
 /// This determines which external API service to poll for exchange rates.
 #[derive(Debug, Clone, Deserialize)]
 pub enum ExchangeRateProvider {
@@ -71,6 +109,9 @@ impl PartialEq<ExchangeRateProvider> for ExchangeRateProvider {
 }
 
 /// Poll exchange rate providers for the current exchange rates
+///
+/// The polled rates are saved in the store, from which the fetcher also serves
+/// them as a [`RateProvider`](./trait.RateProvider.html)
 #[derive(Clone)]
 pub struct ExchangeRateFetcher<S> {
     provider: ExchangeRateProvider,
@@ -166,3 +207,17 @@ where
         }
     }
 }
+
+#[async_trait]
+impl<S> RateProvider for ExchangeRateFetcher<S>
+where
+    S: ExchangeRateStore + Send + Sync,
+{
+    async fn get_rate(
+        &self,
+        from_asset: &str,
+        to_asset: &str,
+    ) -> Result<f64, ExchangeRateStoreError> {
+        self.store.get_rate(from_asset, to_asset).await
+    }
+}
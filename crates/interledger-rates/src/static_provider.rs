@@ -0,0 +1,58 @@
+use super::RateProvider;
+use async_trait::async_trait;
+use interledger_errors::ExchangeRateStoreError;
+use std::collections::HashMap;
+
+/// Provides fixed exchange rates, e.g. for tests or for nodes which only
+/// exchange between assets with a known relative value
+///
+/// Like the rates saved in an [`ExchangeRateStore`](./trait.ExchangeRateStore.html),
+/// each rate is the value of the asset in some common base asset.
+#[derive(Debug, Clone, Default)]
+pub struct StaticRateProvider {
+    rates: HashMap<String, f64>,
+}
+
+impl StaticRateProvider {
+    /// Creates a provider from a map of asset codes to their value in the base asset
+    pub fn new(rates: HashMap<String, f64>) -> Self {
+        StaticRateProvider { rates }
+    }
+}
+
+#[async_trait]
+impl RateProvider for StaticRateProvider {
+    async fn get_rate(
+        &self,
+        from_asset: &str,
+        to_asset: &str,
+    ) -> Result<f64, ExchangeRateStoreError> {
+        match (self.rates.get(from_asset), self.rates.get(to_asset)) {
+            (Some(from_rate), Some(to_rate)) => Ok(from_rate / to_rate),
+            _ => Err(ExchangeRateStoreError::PairNotFound {
+                from: from_asset.to_string(),
+                to: to_asset.to_string(),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn divides_configured_rates() {
+        let provider = StaticRateProvider::new(
+            vec![("ABC".to_string(), 1.0), ("XYZ".to_string(), 4.0)]
+                .into_iter()
+                .collect(),
+        );
+        assert_eq!(provider.get_rate("ABC", "XYZ").await.unwrap(), 0.25);
+        assert_eq!(provider.get_rate("XYZ", "ABC").await.unwrap(), 4.0);
+        assert!(matches!(
+            provider.get_rate("ABC", "DEF").await.unwrap_err(),
+            ExchangeRateStoreError::PairNotFound { .. }
+        ));
+    }
+}
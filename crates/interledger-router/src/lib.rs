@@ -6,6 +6,11 @@
 //!
 //! A routing table could be as simple as a single entry for the empty prefix
 //! ("") that will route all requests to a specific outgoing account.
+//! Otherwise, the route with the longest prefix the destination starts with wins.
+//!
+//! A prefix can also be routed through several accounts, each with a weight.
+//! The router then spreads packets for it across those accounts according to
+//! their weights (see `RouteSelection`).
 //!
 //! Note that the Router is not responsible for building the routing table,
 //! only using the information provided by the store. The routing table in the
@@ -16,9 +21,13 @@ use interledger_service::AccountStore;
 use std::{collections::HashMap, sync::Arc};
 use uuid::Uuid;
 
+mod multipath;
 mod router;
+mod trie;
 
+pub use self::multipath::{RouteSelection, WeightedRoute};
 pub use self::router::Router;
+pub use self::trie::RoutingTrie;
 
 /// A trait for Store implmentations that have ILP routing tables.
 pub trait RouterStore: AccountStore + Clone + Send + Sync + 'static {
@@ -28,4 +37,11 @@ pub trait RouterStore: AccountStore + Clone + Send + Sync + 'static {
     /// This ensures that individual packets can be routed without hitting the underlying store.
     /// An Arc is returned to avoid copying the underlying data while processing each packet.
     fn routing_table(&self) -> Arc<HashMap<String, Uuid>>;
+
+    /// **Synchronously** return the prefixes that are routed through several accounts.
+    /// A prefix in this table replaces the route for the same prefix in the `routing_table`.
+    /// Like the routing table, the same Arc should be returned until the routes change.
+    fn multipath_routing_table(&self) -> Arc<HashMap<String, Vec<WeightedRoute>>> {
+        Arc::new(HashMap::new())
+    }
 }
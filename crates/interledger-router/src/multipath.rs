@@ -0,0 +1,244 @@
+use parking_lot::RwLock;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// One of the accounts a prefix is routed through
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WeightedRoute {
+    pub account_id: Uuid,
+    /// The share of the prefix's packets this account gets, relative to the other
+    /// accounts' weights. A weight of 0 disables the route.
+    pub weight: u32,
+}
+
+/// How the router picks among the accounts a prefix is routed through
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RouteSelection {
+    /// Pick an account at random for every packet, with a probability proportional to its weight
+    #[default]
+    WeightedRandom,
+    /// Take the accounts in turn, sending each as many consecutive packets as its weight
+    RoundRobin,
+}
+
+/// The accounts a prefix in the router's trie is routed through
+#[derive(Debug)]
+pub(crate) struct NextHops {
+    routes: Vec<WeightedRoute>,
+    /// Number of packets routed so far, for round robin selection
+    turn: AtomicU64,
+}
+
+impl NextHops {
+    pub(crate) fn single(account_id: Uuid) -> Self {
+        NextHops {
+            routes: vec![WeightedRoute {
+                account_id,
+                weight: 1,
+            }],
+            turn: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns None if none of the routes has a weight
+    pub(crate) fn weighted(routes: &[WeightedRoute]) -> Option<Self> {
+        let routes: Vec<WeightedRoute> = routes
+            .iter()
+            .filter(|route| route.weight > 0)
+            .copied()
+            .collect();
+        if routes.is_empty() {
+            None
+        } else {
+            Some(NextHops {
+                routes,
+                turn: AtomicU64::new(0),
+            })
+        }
+    }
+
+    pub(crate) fn is_multipath(&self) -> bool {
+        self.routes.len() > 1
+    }
+
+    /// Picks the account for the next packet, skipping the deprioritized ones
+    /// unless all of them are
+    pub(crate) fn select(&self, selection: RouteSelection, deprioritized: &Deprioritized) -> Uuid {
+        if !self.is_multipath() {
+            return self.routes[0].account_id;
+        }
+        let mut candidates: Vec<&WeightedRoute> = self
+            .routes
+            .iter()
+            .filter(|route| !deprioritized.contains(route.account_id))
+            .collect();
+        if candidates.is_empty() {
+            candidates = self.routes.iter().collect();
+        }
+
+        let total: u64 = candidates.iter().map(|route| u64::from(route.weight)).sum();
+        let mut ticket = match selection {
+            RouteSelection::WeightedRandom => rand::thread_rng().gen_range(0, total),
+            RouteSelection::RoundRobin => self.turn.fetch_add(1, Ordering::Relaxed) % total,
+        };
+        for route in &candidates {
+            let weight = u64::from(route.weight);
+            if ticket < weight {
+                return route.account_id;
+            }
+            ticket -= weight;
+        }
+        unreachable!("ticket is always less than the total weight")
+    }
+}
+
+/// Accounts that recently rejected packets with temporary errors, which are
+/// passed over while their cooldown lasts
+#[derive(Debug)]
+pub(crate) struct Deprioritized {
+    cooldown: Duration,
+    until: RwLock<HashMap<Uuid, Instant>>,
+}
+
+impl Deprioritized {
+    pub(crate) fn new(cooldown: Duration) -> Self {
+        Deprioritized {
+            cooldown,
+            until: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub(crate) fn insert(&self, account_id: Uuid) {
+        let now = Instant::now();
+        let mut until = self.until.write();
+        until.retain(|_, until| *until > now);
+        until.insert(account_id, now + self.cooldown);
+    }
+
+    fn contains(&self, account_id: Uuid) -> bool {
+        match self.until.read().get(&account_id) {
+            Some(until) => *until > Instant::now(),
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PACKETS: usize = 10_000;
+
+    fn three_routes() -> (NextHops, [Uuid; 3]) {
+        let ids = [Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4()];
+        let hops = NextHops::weighted(&[
+            WeightedRoute {
+                account_id: ids[0],
+                weight: 1,
+            },
+            WeightedRoute {
+                account_id: ids[1],
+                weight: 3,
+            },
+            WeightedRoute {
+                account_id: ids[2],
+                weight: 6,
+            },
+        ])
+        .unwrap();
+        (hops, ids)
+    }
+
+    /// Returns the share of the packets routed to each account
+    fn distribution(
+        hops: &NextHops,
+        ids: &[Uuid; 3],
+        selection: RouteSelection,
+        deprioritized: &Deprioritized,
+    ) -> [f64; 3] {
+        let mut counts = [0; 3];
+        for _ in 0..PACKETS {
+            let selected = hops.select(selection, deprioritized);
+            counts[ids.iter().position(|id| *id == selected).unwrap()] += 1;
+        }
+        let mut shares = [0.0; 3];
+        for (share, count) in shares.iter_mut().zip(counts.iter()) {
+            *share = f64::from(*count) / PACKETS as f64;
+        }
+        shares
+    }
+
+    fn assert_close(actual: [f64; 3], expected: [f64; 3]) {
+        for (actual, expected) in actual.iter().zip(expected.iter()) {
+            assert!(
+                (actual - expected).abs() < 0.02,
+                "expected {:?}, got {:?}",
+                expected,
+                actual
+            );
+        }
+    }
+
+    #[test]
+    fn weighted_random_follows_the_weights() {
+        let (hops, ids) = three_routes();
+        let deprioritized = Deprioritized::new(Duration::from_secs(60));
+        let shares = distribution(&hops, &ids, RouteSelection::WeightedRandom, &deprioritized);
+        assert_close(shares, [0.1, 0.3, 0.6]);
+    }
+
+    #[test]
+    fn round_robin_follows_the_weights() {
+        let (hops, ids) = three_routes();
+        let deprioritized = Deprioritized::new(Duration::from_secs(60));
+        let shares = distribution(&hops, &ids, RouteSelection::RoundRobin, &deprioritized);
+        assert_eq!(shares, [0.1, 0.3, 0.6]);
+    }
+
+    #[test]
+    fn skips_deprioritized_routes() {
+        let (hops, ids) = three_routes();
+        let deprioritized = Deprioritized::new(Duration::from_secs(60));
+        deprioritized.insert(ids[2]);
+        let shares = distribution(&hops, &ids, RouteSelection::WeightedRandom, &deprioritized);
+        assert_close(shares, [0.25, 0.75, 0.0]);
+
+        // Packets still go out if every route is deprioritized
+        deprioritized.insert(ids[0]);
+        deprioritized.insert(ids[1]);
+        let shares = distribution(&hops, &ids, RouteSelection::RoundRobin, &deprioritized);
+        assert_eq!(shares, [0.1, 0.3, 0.6]);
+    }
+
+    #[test]
+    fn deprioritization_expires() {
+        let (hops, ids) = three_routes();
+        let deprioritized = Deprioritized::new(Duration::from_millis(0));
+        deprioritized.insert(ids[0]);
+        let shares = distribution(&hops, &ids, RouteSelection::RoundRobin, &deprioritized);
+        assert_eq!(shares, [0.1, 0.3, 0.6]);
+    }
+
+    #[test]
+    fn ignores_routes_without_weight() {
+        let id = Uuid::new_v4();
+        let routes = [
+            WeightedRoute {
+                account_id: id,
+                weight: 2,
+            },
+            WeightedRoute {
+                account_id: Uuid::new_v4(),
+                weight: 0,
+            },
+        ];
+        let hops = NextHops::weighted(&routes).unwrap();
+        assert!(!hops.is_multipath());
+        assert!(NextHops::weighted(&routes[1..]).is_none());
+    }
+}
@@ -1,9 +1,28 @@
-use super::RouterStore;
+use super::multipath::{Deprioritized, NextHops};
+use super::{RouteSelection, RouterStore, RoutingTrie, WeightedRoute};
 use async_trait::async_trait;
-use interledger_packet::{ErrorCode, RejectBuilder};
+use interledger_packet::{ErrorClass, ErrorCode, RejectBuilder};
 use interledger_service::*;
+use parking_lot::RwLock;
+use std::collections::HashMap;
 use std::str;
+use std::sync::Arc;
+use std::time::Duration;
 use tracing::{debug, error};
+use uuid::Uuid;
+
+/// How long an account is passed over after rejecting a packet with a temporary error
+const DEFAULT_FAILURE_COOLDOWN: Duration = Duration::from_secs(30);
+
+type RoutingTable = Arc<HashMap<String, Uuid>>;
+type MultipathRoutingTable = Arc<HashMap<String, Vec<WeightedRoute>>>;
+
+/// The store's routing tables along with the trie built from them
+struct CachedTrie {
+    routing_table: RoutingTable,
+    multipath_routing_table: MultipathRoutingTable,
+    trie: Arc<RoutingTrie<NextHops>>,
+}
 
 /// # Interledger Router
 ///
@@ -21,11 +40,23 @@ use tracing::{debug, error};
 ///   - reduce the Prepare packet's expiry
 ///
 /// That is done by OutgoingServices.
+///
+/// Packets are routed to the longest prefix their destination starts with, so the prefix
+/// `example.a` routes `example.alice` unless there is a route for `example.alice` itself.
+/// The trie used for this is rebuilt whenever the store hands out a new routing table.
+///
+/// Prefixes in the store's multipath routing table are spread across their accounts
+/// as configured with `with_route_selection`. An account that rejects a packet with a
+/// temporary (`Txx`) error is passed over for those prefixes until its cooldown ends,
+/// unless all of the prefix's accounts are.
 
 #[derive(Clone)]
 pub struct Router<S, O> {
     store: S,
     next: O,
+    trie: Arc<RwLock<Option<CachedTrie>>>,
+    selection: RouteSelection,
+    deprioritized: Arc<Deprioritized>,
 }
 
 impl<S, O> Router<S, O>
@@ -34,7 +65,71 @@ where
     O: OutgoingService<S::Account>,
 {
     pub fn new(store: S, next: O) -> Self {
-        Router { store, next }
+        Router {
+            store,
+            next,
+            trie: Arc::new(RwLock::new(None)),
+            selection: RouteSelection::default(),
+            deprioritized: Arc::new(Deprioritized::new(DEFAULT_FAILURE_COOLDOWN)),
+        }
+    }
+
+    /// Sets how packets are spread across the accounts of multipath routes
+    pub fn with_route_selection(mut self, selection: RouteSelection) -> Self {
+        self.selection = selection;
+        self
+    }
+
+    /// Sets how long an account is passed over for multipath routes after it
+    /// rejects a packet with a temporary error (defaults to 30 seconds)
+    pub fn with_failure_cooldown(mut self, cooldown: Duration) -> Self {
+        self.deprioritized = Arc::new(Deprioritized::new(cooldown));
+        self
+    }
+}
+
+impl<S, O> Router<S, O> {
+    /// Returns the trie for the given routing tables, building it if either changed
+    /// since the last request
+    fn trie_for(
+        &self,
+        routing_table: &RoutingTable,
+        multipath_routing_table: &MultipathRoutingTable,
+    ) -> Arc<RoutingTrie<NextHops>> {
+        if let Some(cached) = &*self.trie.read() {
+            if Arc::ptr_eq(&cached.routing_table, routing_table)
+                && (Arc::ptr_eq(&cached.multipath_routing_table, multipath_routing_table)
+                    || cached.multipath_routing_table.is_empty()
+                        && multipath_routing_table.is_empty())
+            {
+                return cached.trie.clone();
+            }
+        }
+
+        let mut trie = RoutingTrie::default();
+        for (prefix, account_id) in routing_table.iter() {
+            if !multipath_routing_table.contains_key(prefix) {
+                trie.insert(prefix, NextHops::single(*account_id));
+            }
+        }
+        for (prefix, routes) in multipath_routing_table.iter() {
+            match NextHops::weighted(routes) {
+                Some(next_hops) => trie.insert(prefix, next_hops),
+                // Fall back to the prefix's single route, if any
+                None => {
+                    if let Some(account_id) = routing_table.get(prefix) {
+                        trie.insert(prefix, NextHops::single(*account_id));
+                    }
+                }
+            }
+        }
+        let trie = Arc::new(trie);
+        *self.trie.write() = Some(CachedTrie {
+            routing_table: routing_table.clone(),
+            multipath_routing_table: multipath_routing_table.clone(),
+            trie: trie.clone(),
+        });
+        trie
     }
 }
 
@@ -46,48 +141,32 @@ where
 {
     /// Figures out the next node to pass the received Prepare packet to.
     ///
-    /// Firstly, it checks if there is a direct path for that account and uses that.
-    /// If not it scans through the routing table and checks if the route prefix matches
-    /// the prepare packet's destination or if it's a catch-all address (i.e. empty prefix)
+    /// It looks up the longest route prefix matching the prepare packet's destination
+    /// (which may be the destination itself), falling back to the catch-all route
+    /// (i.e. empty prefix) if there is one. If the prefix is routed through several
+    /// accounts, one of them is picked according to the route selection policy.
     async fn handle_request(&mut self, request: IncomingRequest<S::Account>) -> IlpResult {
         let destination = request.prepare.destination();
         let mut next_hop = None;
+        let mut is_multipath = false;
         let routing_table = self.store.routing_table();
+        let multipath_routing_table = self.store.multipath_routing_table();
         let ilp_address = self.store.get_ilp_address();
 
-        // Check if we have a direct path for that account or if we need to scan
-        // through the routing table
         let dest: &str = &destination;
         debug!("Finding route for address: \"{}\".", destination);
-        if let Some(account_id) = routing_table.get(dest) {
-            debug!(
-                "Found direct route for address: \"{}\". Account: {}",
-                destination, account_id
-            );
-            next_hop = Some(*account_id);
-        } else if !routing_table.is_empty() {
-            let mut matching_prefix = "";
-            let routing_table = self.store.routing_table();
-
-            debug!(
-                "Route not found. Scanning through routing table with Prefix: {:#?}.",
-                routing_table
-            );
-
-            for (prefix, account) in (*routing_table).iter() {
-                // Check if the route prefix matches or is empty (meaning it's a catch-all address)
-                if (prefix.is_empty() || dest.starts_with(prefix.as_str()))
-                    && prefix.len() >= matching_prefix.len()
-                {
-                    next_hop.replace(*account);
-                    matching_prefix = prefix.as_str();
-                }
-            }
-            if let Some(account_id) = next_hop {
+        if !routing_table.is_empty() || !multipath_routing_table.is_empty() {
+            if let Some((matching_prefix, next_hops)) = self
+                .trie_for(&routing_table, &multipath_routing_table)
+                .longest_match(dest)
+            {
+                let account_id = next_hops.select(self.selection, &self.deprioritized);
                 debug!(
                     "Found matching route for address: \"{}\". Prefix: \"{}\", account: {}",
                     destination, matching_prefix, account_id,
                 );
+                next_hop = Some(account_id);
+                is_multipath = next_hops.is_multipath();
             }
         } else {
             error!("Unable to route request because routing table is empty");
@@ -99,7 +178,18 @@ where
                 Ok(mut accounts) => {
                     let request = request.into_outgoing(accounts.remove(0));
                     debug!("Forwarding request: \"{:?}\"", request);
-                    next.send_request(request).await
+                    let result = next.send_request(request).await;
+                    if let Err(ref reject) = result {
+                        if is_multipath && reject.code().class() == ErrorClass::Temporary {
+                            debug!(
+                                "Deprioritizing account {} after it rejected a packet with: {:?}",
+                                account_id,
+                                reject.code()
+                            );
+                            self.deprioritized.insert(account_id);
+                        }
+                    }
+                    result
                 }
                 Err(_) => {
                     error!("No record found for account: {}", account_id);
@@ -188,6 +278,7 @@ mod tests {
     #[derive(Clone)]
     struct TestStore {
         routes: HashMap<String, Uuid>,
+        multipath: MultipathRoutingTable,
     }
 
     #[async_trait]
@@ -231,12 +322,17 @@ mod tests {
         fn routing_table(&self) -> Arc<HashMap<String, Uuid>> {
             Arc::new(self.routes.clone())
         }
+
+        fn multipath_routing_table(&self) -> MultipathRoutingTable {
+            self.multipath.clone()
+        }
     }
 
     #[tokio::test]
     async fn empty_routing_table() {
         let mut router = Router::new(
             TestStore {
+                multipath: Arc::new(HashMap::new()),
                 routes: HashMap::new(),
             },
             outgoing_service_fn(|_| {
@@ -268,6 +364,7 @@ mod tests {
     async fn no_route() {
         let mut router = Router::new(
             TestStore {
+                multipath: Arc::new(HashMap::new()),
                 routes: vec![("example.other".to_string(), Uuid::new_v4())]
                     .into_iter()
                     .collect(),
@@ -301,6 +398,7 @@ mod tests {
     async fn finds_exact_route() {
         let mut router = Router::new(
             TestStore {
+                multipath: Arc::new(HashMap::new()),
                 routes: vec![("example.destination".to_string(), Uuid::new_v4())]
                     .into_iter()
                     .collect(),
@@ -334,6 +432,7 @@ mod tests {
     async fn catch_all_route() {
         let mut router = Router::new(
             TestStore {
+                multipath: Arc::new(HashMap::new()),
                 routes: vec![(String::new(), Uuid::new_v4())].into_iter().collect(),
             },
             outgoing_service_fn(|_| {
@@ -365,6 +464,7 @@ mod tests {
     async fn finds_matching_prefix() {
         let mut router = Router::new(
             TestStore {
+                multipath: Arc::new(HashMap::new()),
                 routes: vec![("example.".to_string(), Uuid::new_v4())]
                     .into_iter()
                     .collect(),
@@ -394,6 +494,138 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn rebuilds_trie_only_when_routing_table_changes() {
+        let router = Router::new(
+            TestStore {
+                multipath: Arc::new(HashMap::new()),
+                routes: HashMap::new(),
+            },
+            outgoing_service_fn(|_: OutgoingRequest<TestAccount>| {
+                Ok(FulfillBuilder {
+                    fulfillment: &[0; 32],
+                    data: &[],
+                }
+                .build())
+            }),
+        );
+        let account_id = Uuid::new_v4();
+        let table = Arc::new(
+            vec![("example.".to_string(), account_id)]
+                .into_iter()
+                .collect(),
+        );
+
+        let multipath = Arc::new(HashMap::new());
+
+        let trie = router.trie_for(&table, &multipath);
+        // Empty multipath routing tables are interchangeable
+        assert!(Arc::ptr_eq(
+            &trie,
+            &router.trie_for(&table, &Arc::new(HashMap::new()))
+        ));
+        let (prefix, next_hops) = trie.longest_match("example.destination").unwrap();
+        assert_eq!(prefix, "example.");
+        assert_eq!(
+            next_hops.select(RouteSelection::RoundRobin, &router.deprioritized),
+            account_id
+        );
+
+        let updated = Arc::new(HashMap::new());
+        let trie = router.trie_for(&updated, &multipath);
+        assert!(trie.longest_match("example.destination").is_none());
+
+        let other_account_id = Uuid::new_v4();
+        let multipath = Arc::new(
+            vec![(
+                "example.".to_string(),
+                vec![WeightedRoute {
+                    account_id: other_account_id,
+                    weight: 1,
+                }],
+            )]
+            .into_iter()
+            .collect(),
+        );
+        let trie = router.trie_for(&table, &multipath);
+        let (_, next_hops) = trie.longest_match("example.destination").unwrap();
+        assert_eq!(
+            next_hops.select(RouteSelection::RoundRobin, &router.deprioritized),
+            other_account_id
+        );
+    }
+
+    #[tokio::test]
+    async fn deprioritizes_accounts_after_temporary_rejects() {
+        let failing = Uuid::new_v4();
+        let working = Uuid::new_v4();
+        let sent: Arc<Mutex<Vec<Uuid>>> = Arc::new(Mutex::new(Vec::new()));
+        let sent_clone = sent.clone();
+        let mut router = Router::new(
+            TestStore {
+                routes: HashMap::new(),
+                multipath: Arc::new(
+                    vec![(
+                        "example.".to_string(),
+                        vec![
+                            WeightedRoute {
+                                account_id: failing,
+                                weight: 1,
+                            },
+                            WeightedRoute {
+                                account_id: working,
+                                weight: 1,
+                            },
+                        ],
+                    )]
+                    .into_iter()
+                    .collect(),
+                ),
+            },
+            outgoing_service_fn(move |request: OutgoingRequest<TestAccount>| {
+                sent_clone.lock().push(request.to.0);
+                if request.to.0 == failing {
+                    Err(RejectBuilder {
+                        code: ErrorCode::T04_INSUFFICIENT_LIQUIDITY,
+                        message: &[],
+                        triggered_by: None,
+                        data: &[],
+                    }
+                    .build())
+                } else {
+                    Ok(FulfillBuilder {
+                        fulfillment: &[0; 32],
+                        data: &[],
+                    }
+                    .build())
+                }
+            }),
+        )
+        .with_route_selection(RouteSelection::RoundRobin);
+
+        let mut rejects = 0;
+        for _ in 0..10 {
+            let result = router
+                .handle_request(IncomingRequest {
+                    from: TestAccount(Uuid::new_v4()),
+                    prepare: PrepareBuilder {
+                        destination: Address::from_str("example.destination").unwrap(),
+                        amount: 100,
+                        execution_condition: &[1; 32],
+                        expires_at: UNIX_EPOCH,
+                        data: &[],
+                    }
+                    .build(),
+                })
+                .await;
+            if result.is_err() {
+                rejects += 1;
+            }
+        }
+        assert_eq!(rejects, 1);
+        assert_eq!(sent.lock().iter().filter(|id| **id == working).count(), 9);
+    }
+
     #[tokio::test]
     async fn finds_longest_matching_prefix() {
         let id0 = Uuid::from_slice(&[0; 16]).unwrap();
@@ -403,6 +635,7 @@ mod tests {
         let to_clone = to.clone();
         let mut router = Router::new(
             TestStore {
+                multipath: Arc::new(HashMap::new()),
                 routes: vec![
                     (String::new(), id0),
                     ("example.destination".to_string(), id2),
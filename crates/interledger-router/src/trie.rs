@@ -0,0 +1,197 @@
+use std::collections::{hash_map::Entry, HashMap};
+use std::mem;
+use uuid::Uuid;
+
+/// A routing table indexed by the bytes of each route's prefix, with runs of bytes
+/// that no other prefix branches off from kept on a single edge, so that looking up
+/// an address walks at most one node per branch along it instead of comparing it
+/// against every route.
+///
+/// A prefix matches every address which starts with it: `example.a` matches
+/// `example.a`, `example.alice` and `example.a.wallet`. The empty prefix is the default
+/// route, used when no other prefix matches.
+///
+/// Each route maps to a `T`, which is the account id to route to unless the caller
+/// needs to keep more than that per prefix.
+#[derive(Debug)]
+pub struct RoutingTrie<T = Uuid> {
+    root: Node<T>,
+}
+
+#[derive(Debug)]
+struct Node<T> {
+    /// The bytes on the edge from the parent to this node
+    label: Vec<u8>,
+    /// The route whose prefix ends at this node, along with the prefix
+    route: Option<(String, T)>,
+    /// The child nodes, by the first byte of their label
+    children: HashMap<u8, Node<T>>,
+}
+
+impl<T> Default for RoutingTrie<T> {
+    fn default() -> Self {
+        RoutingTrie {
+            root: Node::default(),
+        }
+    }
+}
+
+impl<T> Default for Node<T> {
+    fn default() -> Self {
+        Node {
+            label: Vec::new(),
+            route: None,
+            children: HashMap::new(),
+        }
+    }
+}
+
+impl<T: Clone> RoutingTrie<T> {
+    pub fn new(routing_table: &HashMap<String, T>) -> Self {
+        let mut trie = RoutingTrie::default();
+        for (prefix, route) in routing_table {
+            trie.insert(prefix, route.clone());
+        }
+        trie
+    }
+}
+
+impl<T> RoutingTrie<T> {
+    /// Adds a route, replacing the one previously added for the same prefix
+    pub fn insert(&mut self, prefix: &str, route: T) {
+        let mut node = &mut self.root;
+        let mut rest = prefix.as_bytes();
+        while let Some(&first) = rest.first() {
+            let child = match node.children.entry(first) {
+                Entry::Vacant(entry) => {
+                    entry.insert(Node {
+                        label: rest.to_vec(),
+                        route: Some((prefix.to_string(), route)),
+                        children: HashMap::new(),
+                    });
+                    return;
+                }
+                Entry::Occupied(entry) => entry.into_mut(),
+            };
+            let common = child
+                .label
+                .iter()
+                .zip(rest)
+                .take_while(|(a, b)| a == b)
+                .count();
+            if common < child.label.len() {
+                // The prefix ends or branches off within the edge, so split it there
+                let tail = Node {
+                    label: child.label.split_off(common),
+                    route: child.route.take(),
+                    children: mem::take(&mut child.children),
+                };
+                child.children.insert(tail.label[0], tail);
+            }
+            rest = &rest[common..];
+            node = child;
+        }
+        node.route = Some((prefix.to_string(), route));
+    }
+
+    /// Returns the longest prefix matching the address and its route,
+    /// falling back to the default route
+    pub fn longest_match(&self, address: &str) -> Option<(&str, &T)> {
+        let mut node = &self.root;
+        let mut matched = node.route.as_ref();
+        let mut rest = address.as_bytes();
+        while let Some(child) = rest.first().and_then(|first| node.children.get(first)) {
+            if !rest.starts_with(&child.label) {
+                break;
+            }
+            rest = &rest[child.label.len()..];
+            node = child;
+            matched = node.route.as_ref().or(matched);
+        }
+        matched.map(|(prefix, route)| (prefix.as_str(), route))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build(routes: &[(&str, u8)]) -> RoutingTrie {
+        RoutingTrie::new(
+            &routes
+                .iter()
+                .map(|(prefix, id)| (prefix.to_string(), Uuid::from_bytes([*id; 16])))
+                .collect(),
+        )
+    }
+
+    fn lookup(trie: &RoutingTrie, address: &str) -> Option<u8> {
+        trie.longest_match(address)
+            .map(|(_, account_id)| account_id.as_bytes()[0])
+    }
+
+    #[test]
+    fn prefers_the_longest_overlapping_prefix() {
+        let trie = build(&[
+            ("example", 1),
+            ("example.alice", 2),
+            ("example.alice.wallet", 3),
+        ]);
+        assert_eq!(lookup(&trie, "example.alice.wallet.123"), Some(3));
+        assert_eq!(lookup(&trie, "example.alice.other"), Some(2));
+        assert_eq!(lookup(&trie, "example.alice"), Some(2));
+        assert_eq!(lookup(&trie, "example.bob"), Some(1));
+        assert_eq!(lookup(&trie, "test.alice"), None);
+    }
+
+    #[test]
+    fn matches_prefixes_within_segments() {
+        let trie = build(&[("example.a", 1), ("example.alice", 2)]);
+        assert_eq!(lookup(&trie, "example.alicia"), Some(1));
+        assert_eq!(lookup(&trie, "example.alice"), Some(2));
+        assert_eq!(lookup(&trie, "example.alice.wallet"), Some(2));
+        assert_eq!(lookup(&trie, "example.al"), Some(1));
+        assert_eq!(lookup(&trie, "example"), None);
+    }
+
+    #[test]
+    fn splits_edges_in_any_insertion_order() {
+        let routes = [
+            ("example.alice", 1),
+            ("example.alicia", 2),
+            ("example.al", 3),
+            ("example.bob", 4),
+        ];
+        for order in [[0, 1, 2, 3], [3, 2, 1, 0], [2, 0, 3, 1]] {
+            let mut trie = RoutingTrie::default();
+            for i in order {
+                let (prefix, id) = routes[i];
+                trie.insert(prefix, id);
+            }
+            assert_eq!(trie.longest_match("example.alice.x").unwrap().1, &1);
+            assert_eq!(trie.longest_match("example.alicia").unwrap().1, &2);
+            assert_eq!(trie.longest_match("example.alex").unwrap().1, &3);
+            assert_eq!(trie.longest_match("example.bobby").unwrap().1, &4);
+            assert!(trie.longest_match("example.a").is_none());
+        }
+    }
+
+    #[test]
+    fn falls_back_to_the_default_route() {
+        let trie = build(&[("", 1), ("example.alice", 2)]);
+        assert_eq!(lookup(&trie, "example.bob"), Some(1));
+        assert_eq!(lookup(&trie, "test.alice"), Some(1));
+        assert_eq!(lookup(&trie, "example.alice.x"), Some(2));
+    }
+
+    #[test]
+    fn treats_trailing_dots_as_part_of_the_prefix() {
+        let trie = build(&[("example.", 1)]);
+        assert_eq!(trie.longest_match("example.bob").unwrap().0, "example.");
+        assert_eq!(lookup(&trie, "example"), None);
+
+        let trie = build(&[("example.", 1), ("example", 2)]);
+        assert_eq!(lookup(&trie, "example.bob"), Some(1));
+        assert_eq!(lookup(&trie, "examples"), Some(2));
+    }
+}
@@ -4,14 +4,16 @@ use interledger_errors::BalanceStoreError;
 use interledger_packet::{ErrorCode, RejectBuilder};
 use interledger_service::*;
 use interledger_settlement::core::{
-    types::{SettlementAccount, SettlementStore},
+    types::{PendingSettlement, PendingSettlementStore, SettlementAccount, SettlementStore},
     SettlementClient,
 };
+use std::collections::HashSet;
 use std::marker::PhantomData;
 use std::sync::{Arc, Mutex};
 use std::{fmt, time::Duration, time::Instant};
 use tokio::sync::mpsc::error::TrySendError;
-use tracing::{debug, error, info, trace, warn};
+use tracing::{debug, debug_span, error, info, trace, warn};
+use tracing_futures::Instrument;
 use uuid::Uuid;
 
 // TODO: Remove AccountStore dependency, use `AccountId: ToString` as associated type
@@ -68,6 +70,8 @@ pub struct BalanceService<S, O, A> {
     policy: Policy,
     account_type: PhantomData<A>,
     channel_last_fail: Arc<Mutex<Instant>>,
+    /// Accounts whose settlement is checked again once their min settlement interval ends
+    held_back_settlements: Arc<Mutex<HashSet<Uuid>>>,
 }
 
 impl<S, O, A> BalanceService<S, O, A>
@@ -91,6 +95,7 @@ where
             },
             account_type: PhantomData,
             channel_last_fail: Arc::new(Mutex::new(Instant::now())),
+            held_back_settlements: Arc::new(Mutex::new(HashSet::new())),
         }
     }
 }
@@ -98,7 +103,14 @@ where
 #[async_trait]
 impl<S, O, A> OutgoingService<A> for BalanceService<S, O, A>
 where
-    S: AddressStore + BalanceStore + SettlementStore<Account = A> + Clone + Send + Sync + 'static,
+    S: AddressStore
+        + BalanceStore
+        + SettlementStore<Account = A>
+        + PendingSettlementStore
+        + Clone
+        + Send
+        + Sync
+        + 'static,
     O: OutgoingService<A> + Send + Clone + 'static,
     A: SettlementAccount + Send + Sync + 'static,
 {
@@ -176,6 +188,7 @@ where
                         settlement_client,
                         self.policy.clone(),
                         self.channel_last_fail.clone(),
+                        self.held_back_settlements.clone(),
                     );
                 }
 
@@ -218,20 +231,32 @@ fn settle_or_rollback_later<Acct, Store>(
     settlement_client: SettlementClient,
     policy: Policy,
     channel_last_fail: Arc<Mutex<Instant>>,
+    held_back_settlements: Arc<Mutex<HashSet<Uuid>>>,
 ) where
     Acct: SettlementAccount + Send + Sync + 'static,
-    Store: BalanceStore + SettlementStore<Account = Acct> + Send + Sync + 'static,
+    Store: BalanceStore
+        + SettlementStore<Account = Acct>
+        + PendingSettlementStore
+        + Send
+        + Sync
+        + 'static,
 {
-    tokio::spawn(settle_or_rollback_now(
-        incoming_amount,
-        outgoing_amount,
-        store,
-        from_id,
-        to,
-        settlement_client,
-        policy,
-        channel_last_fail,
-    ));
+    // The span is created before spawning so that it is nested under the packet's span
+    let span = debug_span!("settlement", to.id = %to.id(), outgoing_amount);
+    tokio::spawn(
+        settle_or_rollback_now(
+            incoming_amount,
+            outgoing_amount,
+            store,
+            from_id,
+            to,
+            settlement_client,
+            policy,
+            channel_last_fail,
+            held_back_settlements,
+        )
+        .instrument(span),
+    );
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -244,10 +269,16 @@ async fn settle_or_rollback_now<Acct, Store>(
     settlement_client: SettlementClient,
     mut policy: Policy,
     channel_last_fail: Arc<Mutex<Instant>>,
+    held_back_settlements: Arc<Mutex<HashSet<Uuid>>>,
 ) -> Result<(), ()>
 where
     Acct: SettlementAccount + Send + Sync + 'static,
-    Store: BalanceStore + SettlementStore<Account = Acct> + Send + Sync + 'static,
+    Store: BalanceStore
+        + SettlementStore<Account = Acct>
+        + PendingSettlementStore
+        + Send
+        + Sync
+        + 'static,
 {
     let (balance, amount_to_settle) = store
         .update_balances_for_fulfill(to.id(), outgoing_amount)
@@ -277,6 +308,19 @@ where
             // is always older than our settlement period, and rescheduling a timeout whenever it
             // would had been too early to settle.
             policy.settle_later(to.id(), channel_last_fail);
+
+            // The settlement may also have been held back by the min settlement interval, in
+            // which case it is checked again once the interval ends, even if no other packet
+            // is fulfilled by then
+            if let Some(interval) = to.min_settlement_interval() {
+                settle_after_interval(
+                    store,
+                    to,
+                    interval,
+                    settlement_client,
+                    held_back_settlements,
+                );
+            }
         }
         return Ok(());
     }
@@ -287,6 +331,54 @@ where
     settle_or_rollback(store, to, amount_to_settle, settlement_client).await
 }
 
+/// Checks once the interval has passed whether the account is over its settle threshold, and
+/// settles it if so. Only one check per account is pending at a time.
+fn settle_after_interval<Acct, Store>(
+    store: Store,
+    to: Acct,
+    interval: Duration,
+    client: SettlementClient,
+    held_back_settlements: Arc<Mutex<HashSet<Uuid>>>,
+) where
+    Acct: SettlementAccount + Send + Sync + 'static,
+    Store: BalanceStore
+        + SettlementStore<Account = Acct>
+        + PendingSettlementStore
+        + Send
+        + Sync
+        + 'static,
+{
+    let id = to.id();
+    if !held_back_settlements.lock().unwrap().insert(id) {
+        return;
+    }
+
+    tokio::spawn(
+        async move {
+            tokio::time::sleep(interval).await;
+            held_back_settlements.lock().unwrap().remove(&id);
+
+            // A fulfill of 0 only checks the settle threshold again
+            let (balance, amount_to_settle) = store
+                .update_balances_for_fulfill(id, 0)
+                .map_err(|err| {
+                    error!(
+                        "Error checking whether account {} can be settled after its min settlement interval: {}",
+                        id, err
+                    )
+                })
+                .await?;
+            debug!(
+                "Account {} balance after its min settlement interval: {}. Amount that needs to be settled: {}",
+                id, balance, amount_to_settle
+            );
+
+            settle_or_rollback(store, to, amount_to_settle, client).await
+        }
+        .in_current_span(),
+    );
+}
+
 async fn settle_or_rollback<Store, Acct>(
     store: Store,
     to: Acct,
@@ -294,7 +386,7 @@ async fn settle_or_rollback<Store, Acct>(
     client: SettlementClient,
 ) -> Result<(), ()>
 where
-    Store: SettlementStore<Account = Acct> + 'static,
+    Store: SettlementStore<Account = Acct> + PendingSettlementStore + 'static,
     Acct: SettlementAccount + 'static,
 {
     if amount == 0 {
@@ -303,46 +395,23 @@ where
     }
 
     if let Some(engine_details) = to.settlement_engine_details() {
-        let engine_url = engine_details.url;
-        // Note that if this program crashes after changing the balance (in the PROCESS_FULFILL
-        // script) and the send_settlement fails but the program isn't alive to hear that, the
-        // balance will be incorrect. No other instance will know that it was trying to send an
-        // outgoing settlement. We could make this more robust by saving something to the DB about
-        // the outgoing settlement when we change the balance but then we would also need to
-        // prevent a situation where every connector instance is polling the settlement engine for
-        // the status of each outgoing settlement and putting unnecessary load on the settlement
-        // engine.
-
-        let result = client
-            .send_settlement(to.id(), engine_url, amount, to.asset_scale())
-            .await;
-
-        if let Err(client_error) = result {
-            warn!(
-                "Settlement for account {} for {} failed: {}",
-                to.id(),
+        // Save the settlement before sending it so that, if this program stops before hearing
+        // back from the engine, it can be sent again on startup with the same idempotency key.
+        // Note that if this program crashes between changing the balance (in the PROCESS_FULFILL
+        // script) and saving the settlement, the amount to settle is still lost.
+        let settlement =
+            PendingSettlement::new(to.id(), engine_details.url, amount, to.asset_scale());
+        if let Err(e) = store.save_pending_settlement(settlement.clone()).await {
+            error!(
+                "Not settling {} for account {} as the pending settlement could not be saved: {}",
                 amount,
-                client_error
-            );
-
-            store
-                .refund_settlement(to.id(), amount)
-                .map_err(|e| {
-                    error!(
-                        "Refunding account {} after failed settlement failed, amount: {}: {}",
-                        to.id(),
-                        amount,
-                        e
-                    )
-                })
-                .await?;
-        } else {
-            info!(
-                "Settlement for account {} for {} succeeded",
                 to.id(),
-                amount
+                e
             );
+            return refund_settlement(&store, &settlement).await;
         }
+
+        send_or_refund_settlement(&store, &settlement, &client).await?;
     } else {
         debug!("Settlement for account {} for {} failed as the account has no settlement engine details",
             to.id(), amount);
@@ -351,6 +420,95 @@ where
     Ok(())
 }
 
+async fn send_or_refund_settlement<Store>(
+    store: &Store,
+    settlement: &PendingSettlement,
+    client: &SettlementClient,
+) -> Result<(), ()>
+where
+    Store: SettlementStore + PendingSettlementStore,
+{
+    let result = client.send_pending_settlement(settlement).await;
+
+    // Only the task which clears the pending settlement acts on the outcome, so that a
+    // settlement which is also being resumed is never refunded twice
+    let cleared = store
+        .clear_pending_settlement(&settlement.idempotency_key)
+        .map_err(|e| {
+            error!(
+                "Clearing pending settlement {} for account {} failed: {}",
+                settlement.idempotency_key, settlement.account_id, e
+            )
+        })
+        .await?;
+    if !cleared {
+        debug!(
+            "Pending settlement {} for account {} was already cleared",
+            settlement.idempotency_key, settlement.account_id
+        );
+        return Ok(());
+    }
+
+    if let Err(client_error) = result {
+        warn!(
+            "Settlement for account {} for {} failed: {}",
+            settlement.account_id, settlement.amount, client_error
+        );
+        refund_settlement(store, settlement).await
+    } else {
+        info!(
+            "Settlement for account {} for {} succeeded",
+            settlement.account_id, settlement.amount
+        );
+        Ok(())
+    }
+}
+
+async fn refund_settlement<Store>(store: &Store, settlement: &PendingSettlement) -> Result<(), ()>
+where
+    Store: SettlementStore,
+{
+    store
+        .refund_settlement(settlement.account_id, settlement.amount)
+        .map_err(|e| {
+            error!(
+                "Refunding account {} after failed settlement failed, amount: {}: {}",
+                settlement.account_id, settlement.amount, e
+            )
+        })
+        .await
+}
+
+/// Start a background task which sends the settlements that were saved but never confirmed
+/// again, for example because the node stopped while they were in flight. Each settlement keeps
+/// its original idempotency key, so an engine which already accepted it will not settle it twice.
+pub fn resume_pending_settlements<Store>(store: Store) -> tokio::task::JoinHandle<()>
+where
+    Store: SettlementStore + PendingSettlementStore + Send + Sync + 'static,
+{
+    let client = SettlementClient::default();
+    tokio::spawn(async move {
+        let settlements = match store.load_pending_settlements().await {
+            Ok(settlements) => settlements,
+            Err(e) => {
+                error!("Failed to load pending settlements: {}", e);
+                return;
+            }
+        };
+        if settlements.is_empty() {
+            return;
+        }
+
+        info!("Resuming {} pending settlements", settlements.len());
+        futures::future::join_all(
+            settlements
+                .iter()
+                .map(|settlement| send_or_refund_settlement(&store, settlement, &client)),
+        )
+        .await;
+    })
+}
+
 /// Captures the behaviour of either operating in a delayed settlement or threshold-only
 /// environment.
 #[derive(Debug, Clone)]
@@ -487,6 +645,7 @@ where
     St: futures::stream::FusedStream<Item = ManageTimeout> + Send + Sync + 'static + Unpin,
     Store: BalanceStore
         + SettlementStore<Account = Acct>
+        + PendingSettlementStore
         + AccountStore<Account = Acct>
         + Clone
         + Send
@@ -520,6 +679,7 @@ where
     St: futures::stream::FusedStream<Item = ManageTimeout> + Send + Sync + 'static + Unpin,
     Store: BalanceStore
         + SettlementStore<Account = Acct>
+        + PendingSettlementStore
         + AccountStore<Account = Acct>
         + Clone
         + Send
@@ -638,6 +798,7 @@ mod tests {
     use interledger_settlement::core::types::SettlementEngineDetails;
     use once_cell::sync::Lazy;
     use parking_lot::RwLock;
+    use std::collections::HashMap;
     use std::str::FromStr;
     use std::sync::Arc;
     use std::time::Duration;
@@ -662,6 +823,31 @@ mod tests {
         mock.assert();
         assert!(!*store.refunded_settlement.read());
         assert!(!*store.rejected_message.read());
+        assert!(store.pending_settlements.read().is_empty());
+    }
+
+    #[tokio::test]
+    async fn resumes_pending_settlements_with_their_idempotency_key() {
+        let store = TestStore::new(0);
+        let settlement = PendingSettlement::new(
+            Uuid::new_v4(),
+            Url::parse(&mockito::server_url()).unwrap(),
+            100,
+            9,
+        );
+        store
+            .save_pending_settlement(settlement.clone())
+            .await
+            .unwrap();
+        let mock = mockito::mock("POST", mockito::Matcher::Any)
+            .match_header("Idempotency-Key", settlement.idempotency_key.as_str())
+            .create();
+
+        resume_pending_settlements(store.clone()).await.unwrap();
+
+        mock.assert();
+        assert!(!*store.refunded_settlement.read());
+        assert!(store.pending_settlements.read().is_empty());
     }
 
     #[tokio::test]
@@ -737,9 +923,38 @@ mod tests {
         assert!(*store.rejected_message.read());
     }
 
+    #[tokio::test]
+    async fn settles_once_the_min_settlement_interval_ends_without_more_traffic() {
+        let mock = mockito::mock("POST", mockito::Matcher::Any).create();
+        let next = outgoing_service_fn(move |_| {
+            Ok(FulfillBuilder {
+                fulfillment: &[0; 32],
+                data: b"test data",
+            }
+            .build())
+        });
+        let store = TestStore::held_back(1);
+        let mut service = BalanceService::new(store.clone(), None, next);
+        let mut request = TEST_REQUEST.clone();
+        request.to.min_settlement_interval = Some(Duration::from_millis(100));
+        service.send_request(request).await.unwrap();
+
+        // The settlement is held back by the interval...
+        tokio::time::sleep(Duration::from_millis(50u64)).await;
+        assert_eq!(*store.fulfills.read(), 1);
+
+        // ...and sent once it ends, even though no other packet was fulfilled
+        tokio::time::sleep(Duration::from_millis(200u64)).await;
+        assert_eq!(*store.fulfills.read(), 2);
+        mock.assert();
+        assert!(!*store.refunded_settlement.read());
+        assert!(store.pending_settlements.read().is_empty());
+    }
+
     #[derive(Debug, Clone)]
     struct TestAccount {
         pub engine_url: Url,
+        pub min_settlement_interval: Option<Duration>,
     }
 
     static ALICE: Lazy<Username> = Lazy::new(|| Username::from_str("alice").unwrap());
@@ -775,21 +990,39 @@ mod tests {
                 url: self.engine_url.clone(),
             })
         }
+
+        fn min_settlement_interval(&self) -> Option<Duration> {
+            self.min_settlement_interval
+        }
     }
 
     #[derive(Clone)]
     struct TestStore {
         amount_to_settle: u64,
+        /// Whether the next fulfill is held back by the min settlement interval
+        held_back: Arc<RwLock<bool>>,
+        fulfills: Arc<RwLock<usize>>,
         rejected_message: Arc<RwLock<bool>>,
         refunded_settlement: Arc<RwLock<bool>>,
+        pending_settlements: Arc<RwLock<HashMap<String, PendingSettlement>>>,
     }
 
     impl TestStore {
         fn new(amount_to_settle: u64) -> Self {
             TestStore {
                 amount_to_settle,
+                held_back: Arc::new(RwLock::new(false)),
+                fulfills: Arc::new(RwLock::new(0)),
                 rejected_message: Arc::new(RwLock::new(false)),
                 refunded_settlement: Arc::new(RwLock::new(false)),
+                pending_settlements: Arc::new(RwLock::new(HashMap::new())),
+            }
+        }
+
+        fn held_back(amount_to_settle: u64) -> Self {
+            TestStore {
+                held_back: Arc::new(RwLock::new(true)),
+                ..TestStore::new(amount_to_settle)
             }
         }
     }
@@ -828,6 +1061,12 @@ mod tests {
             _: Uuid,
             _: u64,
         ) -> Result<(i64, u64), BalanceStoreError> {
+            *self.fulfills.write() += 1;
+            let mut held_back = self.held_back.write();
+            if *held_back {
+                *held_back = false;
+                return Ok((self.amount_to_settle as i64, 0));
+            }
             Ok((0, self.amount_to_settle))
         }
 
@@ -867,14 +1106,46 @@ mod tests {
         }
     }
 
+    #[async_trait]
+    impl PendingSettlementStore for TestStore {
+        async fn save_pending_settlement(
+            &self,
+            settlement: PendingSettlement,
+        ) -> Result<(), SettlementStoreError> {
+            self.pending_settlements
+                .write()
+                .insert(settlement.idempotency_key.clone(), settlement);
+            Ok(())
+        }
+
+        async fn clear_pending_settlement(
+            &self,
+            idempotency_key: &str,
+        ) -> Result<bool, SettlementStoreError> {
+            Ok(self
+                .pending_settlements
+                .write()
+                .remove(idempotency_key)
+                .is_some())
+        }
+
+        async fn load_pending_settlements(
+            &self,
+        ) -> Result<Vec<PendingSettlement>, SettlementStoreError> {
+            Ok(self.pending_settlements.read().values().cloned().collect())
+        }
+    }
+
     static TEST_REQUEST: Lazy<OutgoingRequest<TestAccount>> = Lazy::new(|| {
         let url = mockito::server_url();
         OutgoingRequest {
             to: TestAccount {
                 engine_url: Url::parse(&url).unwrap(),
+                min_settlement_interval: None,
             },
             from: TestAccount {
                 engine_url: Url::parse(&url).unwrap(),
+                min_settlement_interval: None,
             },
             original_amount: 100,
             prepare: PrepareBuilder {
@@ -0,0 +1,340 @@
+use crate::resume_pending_settlements;
+use async_trait::async_trait;
+use futures::future::BoxFuture;
+use interledger_packet::{ErrorCode, RejectBuilder};
+use interledger_service::{Account, AddressStore, IlpResult, IncomingRequest, IncomingService};
+use interledger_settlement::core::types::{PendingSettlementStore, SettlementStore};
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+use tracing::{info, warn};
+
+type Flush = Arc<dyn Fn() -> BoxFuture<'static, ()> + Send + Sync>;
+
+#[derive(Default)]
+struct DrainState {
+    shutting_down: AtomicBool,
+    in_flight: AtomicUsize,
+    drained: Notify,
+}
+
+/// Decrements the in-flight counter when the prepare it was created for resolves
+/// (or its future is dropped)
+struct InFlight(Arc<DrainState>);
+
+impl InFlight {
+    fn new(state: &Arc<DrainState>) -> Self {
+        state.in_flight.fetch_add(1, Ordering::SeqCst);
+        InFlight(state.clone())
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        if self.0.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.drained.notify_waiters();
+        }
+    }
+}
+
+/// # Connector
+///
+/// Handle used to shut a node down gracefully. Incoming services wrapped with
+/// [`Connector::incoming`] have their prepares tracked; once [`Connector::shutdown`] is
+/// called they reject new prepares with `T02: Peer Busy`, while the ones already in flight
+/// are given the grace period to be fulfilled or rejected.
+#[derive(Clone)]
+pub struct Connector {
+    state: Arc<DrainState>,
+    grace_period: Duration,
+    flush: Option<Flush>,
+}
+
+impl Connector {
+    pub fn new(grace_period: Duration) -> Self {
+        Connector {
+            state: Arc::new(DrainState::default()),
+            grace_period,
+            flush: None,
+        }
+    }
+
+    /// Sends the settlements still pending in the store once the in-flight packets
+    /// have drained, so that they do not wait for the next start of the node
+    pub fn with_settlement_flush<S>(mut self, store: S) -> Self
+    where
+        S: SettlementStore + PendingSettlementStore + Clone + Send + Sync + 'static,
+    {
+        self.flush = Some(Arc::new(move || {
+            let store = store.clone();
+            Box::pin(async move {
+                if let Err(e) = resume_pending_settlements(store).await {
+                    warn!("Flushing pending settlements failed: {}", e);
+                }
+            })
+        }));
+        self
+    }
+
+    /// Wraps the incoming service so that its prepares are tracked by this connector
+    pub fn incoming<I, S, A>(&self, store: S, next: I) -> DrainingService<I, S, A>
+    where
+        I: IncomingService<A>,
+        S: AddressStore,
+        A: Account,
+    {
+        DrainingService {
+            state: self.state.clone(),
+            store,
+            next,
+            account_type: PhantomData,
+        }
+    }
+
+    /// Number of prepares currently being processed
+    pub fn in_flight(&self) -> usize {
+        self.state.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Stops accepting new prepares, waits for the ones in flight to resolve and flushes the
+    /// pending settlements. Resolves once that is done or the grace period elapsed, returning
+    /// `false` in the latter case.
+    pub async fn shutdown(&self) -> bool {
+        self.state.shutting_down.store(true, Ordering::SeqCst);
+        info!(
+            "Shutting down, waiting up to {:?} for {} in-flight packets",
+            self.grace_period,
+            self.in_flight()
+        );
+
+        let drain = async {
+            loop {
+                // Registering before checking the counter ensures a notification sent in
+                // between is not missed
+                let drained = self.state.drained.notified();
+                if self.in_flight() == 0 {
+                    break;
+                }
+                drained.await;
+            }
+            if let Some(ref flush) = self.flush {
+                flush().await;
+            }
+        };
+
+        match tokio::time::timeout(self.grace_period, drain).await {
+            Ok(()) => true,
+            Err(_) => {
+                warn!(
+                    "Grace period elapsed with {} packets still in flight",
+                    self.in_flight()
+                );
+                false
+            }
+        }
+    }
+}
+
+/// # Draining Service
+///
+/// Incoming service created by [`Connector::incoming`]. It rejects prepares with
+/// `T02: Peer Busy` once the connector is shutting down.
+#[derive(Clone)]
+pub struct DrainingService<I, S, A> {
+    state: Arc<DrainState>,
+    store: S,
+    next: I,
+    account_type: PhantomData<A>,
+}
+
+#[async_trait]
+impl<I, S, A> IncomingService<A> for DrainingService<I, S, A>
+where
+    I: IncomingService<A> + Send + Sync,
+    S: AddressStore + Send + Sync,
+    A: Account + Send + Sync,
+{
+    async fn handle_request(&mut self, request: IncomingRequest<A>) -> IlpResult {
+        // Counted before checking the flag so that shutdown cannot see zero packets
+        // in flight while this one is let through
+        let _in_flight = InFlight::new(&self.state);
+        if self.state.shutting_down.load(Ordering::SeqCst) {
+            return Err(RejectBuilder {
+                code: ErrorCode::T02_PEER_BUSY,
+                message: b"Node is shutting down",
+                triggered_by: Some(&self.store.get_ilp_address()),
+                data: &[],
+            }
+            .build());
+        }
+        self.next.handle_request(request).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use interledger_errors::AddressStoreError;
+    use interledger_packet::{Address, FulfillBuilder, PrepareBuilder};
+    use interledger_service::Username;
+    use once_cell::sync::Lazy;
+    use std::str::FromStr;
+    use std::time::SystemTime;
+    use tokio::sync::Semaphore;
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn drains_in_flight_packets_before_resolving() {
+        let connector = Connector::new(Duration::from_secs(5));
+        let next = HeldService::default();
+        let service = connector.incoming(TestStore, next.clone());
+
+        let packets: Vec<_> = (0..3)
+            .map(|_| {
+                let mut service = service.clone();
+                tokio::spawn(async move { service.handle_request(request()).await })
+            })
+            .collect();
+        while connector.in_flight() < 3 {
+            tokio::task::yield_now().await;
+        }
+
+        let shutdown = tokio::spawn({
+            let connector = connector.clone();
+            async move { connector.shutdown().await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!shutdown.is_finished());
+
+        // New prepares are turned away while the others drain
+        let reject = service.clone().handle_request(request()).await.unwrap_err();
+        assert_eq!(reject.code(), ErrorCode::T02_PEER_BUSY);
+        assert_eq!(
+            reject.triggered_by().unwrap(),
+            Address::from_str("example.connector").unwrap()
+        );
+
+        next.release.add_permits(3);
+        assert!(shutdown.await.unwrap());
+        assert_eq!(next.completed.load(Ordering::SeqCst), 3);
+        assert_eq!(connector.in_flight(), 0);
+        for packet in packets {
+            assert!(packet.await.unwrap().is_ok());
+        }
+    }
+
+    #[tokio::test]
+    async fn resolves_immediately_without_packets_in_flight() {
+        let connector = Connector::new(Duration::from_secs(5));
+        assert!(connector.shutdown().await);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn gives_up_after_the_grace_period() {
+        let connector = Connector::new(Duration::from_secs(30));
+        let next = HeldService::default();
+        let mut service = connector.incoming(TestStore, next.clone());
+        let packet = tokio::spawn(async move { service.handle_request(request()).await });
+        while connector.in_flight() < 1 {
+            tokio::task::yield_now().await;
+        }
+
+        let start = tokio::time::Instant::now();
+        assert!(!connector.shutdown().await);
+        assert_eq!(start.elapsed(), Duration::from_secs(30));
+        assert_eq!(connector.in_flight(), 1);
+        packet.abort();
+    }
+
+    fn request() -> IncomingRequest<TestAccount> {
+        IncomingRequest {
+            from: TestAccount,
+            prepare: PrepareBuilder {
+                destination: Address::from_str("example.destination").unwrap(),
+                amount: 100,
+                expires_at: SystemTime::now() + Duration::from_secs(30),
+                execution_condition: &[0; 32],
+                data: &[],
+            }
+            .build(),
+        }
+    }
+
+    /// Holds each prepare until a permit is released for it
+    #[derive(Clone)]
+    struct HeldService {
+        release: Arc<Semaphore>,
+        completed: Arc<AtomicUsize>,
+    }
+
+    impl Default for HeldService {
+        fn default() -> Self {
+            HeldService {
+                release: Arc::new(Semaphore::new(0)),
+                completed: Arc::new(AtomicUsize::new(0)),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl IncomingService<TestAccount> for HeldService {
+        async fn handle_request(&mut self, _: IncomingRequest<TestAccount>) -> IlpResult {
+            self.release.acquire().await.unwrap().forget();
+            self.completed.fetch_add(1, Ordering::SeqCst);
+            Ok(FulfillBuilder {
+                fulfillment: &[0; 32],
+                data: &[],
+            }
+            .build())
+        }
+    }
+
+    static ALICE: Lazy<Username> = Lazy::new(|| Username::from_str("alice").unwrap());
+    static EXAMPLE_ADDRESS: Lazy<Address> =
+        Lazy::new(|| Address::from_str("example.alice").unwrap());
+
+    #[derive(Debug, Clone)]
+    struct TestAccount;
+
+    impl Account for TestAccount {
+        fn id(&self) -> Uuid {
+            Uuid::nil()
+        }
+
+        fn username(&self) -> &Username {
+            &ALICE
+        }
+
+        fn asset_code(&self) -> &str {
+            "XYZ"
+        }
+
+        fn asset_scale(&self) -> u8 {
+            9
+        }
+
+        fn ilp_address(&self) -> &Address {
+            &EXAMPLE_ADDRESS
+        }
+    }
+
+    #[derive(Clone)]
+    struct TestStore;
+
+    #[async_trait]
+    impl AddressStore for TestStore {
+        async fn set_ilp_address(&self, _: Address) -> Result<(), AddressStoreError> {
+            Ok(())
+        }
+
+        async fn clear_ilp_address(&self) -> Result<(), AddressStoreError> {
+            Ok(())
+        }
+
+        fn get_ilp_address(&self) -> Address {
+            Address::from_str("example.connector").unwrap()
+        }
+    }
+}
@@ -1,19 +1,24 @@
 use async_trait::async_trait;
 use interledger_packet::{ErrorCode, RejectBuilder};
-use interledger_rates::ExchangeRateStore;
+use interledger_rates::{ExchangeRateStore, RateProvider};
 use interledger_service::*;
-use interledger_settlement::core::types::{ConversionError, Convert, ConvertDetails};
+use interledger_settlement::core::{
+    scale_amount,
+    types::{ConversionError, Convert, ConvertDetails, Rounding},
+};
 use std::marker::PhantomData;
-use tracing::{error, trace, warn};
+use tracing::{debug_span, error, trace, warn};
+use tracing_futures::Instrument;
 
 /// # Exchange Rates Service
 ///
 /// Responsible for getting the exchange rates for the two assets in the outgoing request (`request.from.asset_code`, `request.to.asset_code`).
-/// Requires a `ExchangeRateStore`
+/// Requires a `RateProvider`, which by default is the `ExchangeRateStore`
 #[derive(Clone)]
-pub struct ExchangeRateService<S, O, A> {
+pub struct ExchangeRateService<S, O, A, P = S> {
     spread: f64,
     store: S,
+    provider: P,
     next: O,
     account_type: PhantomData<A>,
 }
@@ -24,10 +29,31 @@ where
     O: OutgoingService<A>,
     A: Account,
 {
+    /// Uses the rates saved in the store
     pub fn new(spread: f64, store: S, next: O) -> Self {
+        ExchangeRateService {
+            spread,
+            store: store.clone(),
+            provider: store,
+            next,
+            account_type: PhantomData,
+        }
+    }
+}
+
+impl<S, O, A, P> ExchangeRateService<S, O, A, P>
+where
+    S: AddressStore,
+    O: OutgoingService<A>,
+    A: Account,
+    P: RateProvider,
+{
+    /// Uses the rates of the given provider instead of the ones saved in the store
+    pub fn with_provider(spread: f64, store: S, provider: P, next: O) -> Self {
         ExchangeRateService {
             spread,
             store,
+            provider,
             next,
             account_type: PhantomData,
         }
@@ -35,17 +61,18 @@ where
 }
 
 #[async_trait]
-impl<S, O, A> OutgoingService<A> for ExchangeRateService<S, O, A>
+impl<S, O, A, P> OutgoingService<A> for ExchangeRateService<S, O, A, P>
 where
     // TODO can we make these non-'static?
-    S: AddressStore + ExchangeRateStore + Clone + Send + Sync + 'static,
+    S: AddressStore + Clone + Send + Sync + 'static,
     O: OutgoingService<A> + Send + Sync + Clone + 'static,
     A: Account + Send + Sync + 'static,
+    P: RateProvider + Send + Sync + 'static,
 {
     /// On send request:
     /// 1. If the prepare packet's amount is 0, it just forwards
-    /// 1. Retrieves the exchange rate from the store (the store independently is responsible for polling the rates)
-    ///     - return reject if the call to the store fails
+    /// 1. Retrieves the exchange rate from the provider (the store independently is responsible for polling the rates)
+    ///     - return reject if the call to the provider fails
     /// 1. Calculates the exchange rate AND scales it up/down depending on how many decimals each asset requires
     /// 1. Updates the amount in the prepare packet and forwards it
     async fn send_request(&mut self, mut request: OutgoingRequest<A>) -> IlpResult {
@@ -53,15 +80,19 @@ where
         if request.prepare.amount() > 0 {
             let rates: (f64, f64) = if request.from.asset_code() == request.to.asset_code() {
                 (1f64, 1f64)
-            } else if let Ok(rates) = self
-                .store
-                .get_exchange_rates(&[request.from.asset_code(), request.to.asset_code()])
+            } else if let Ok(rate) = self
+                .provider
+                .get_rate(request.from.asset_code(), request.to.asset_code())
+                .instrument(debug_span!(
+                    "rate_lookup",
+                    from.asset_code = request.from.asset_code(),
+                    to.asset_code = request.to.asset_code(),
+                ))
+                .await
             {
-                // Exchange rates are expressed as `base asset / asset`. To calculate the outgoing amount,
-                // we multiply by the incoming asset's rate and divide by the outgoing asset's rate. For example,
-                // if an incoming packet is denominated in an asset worth 1 USD and the outgoing asset is worth
-                // 10 USD, the outgoing amount will be 1/10th of the source amount.
-                (rates[0], rates[1])
+                // The rate is how many units of the outgoing asset a unit of the incoming asset
+                // is worth, so the outgoing amount is the incoming amount multiplied by it
+                (rate, 1f64)
             } else {
                 error!(
                     "No exchange rates available for assets: {}, {}",
@@ -170,13 +201,45 @@ fn calculate_outgoing_amount(
         );
         0.0
     };
-    // Can we overflow here?
-    let outgoing_amount = 1.0f64
-        .normalize_scale(ConvertDetails {
-            from: asset_scale_src,
-            to: asset_scale_dest,
-        })
-        .map(|scale| rate * scale * (input as f64));
+    let unscaled = |input: u64| {
+        1.0f64
+            .normalize_scale(ConvertDetails {
+                from: asset_scale_src,
+                to: asset_scale_dest,
+            })
+            .map(|scale| rate * scale * (input as f64))
+    };
+    // Apply the rate before scaling the amount down, so that the amount is only rounded
+    // once and what gets rounded off is known exactly. Like the spread, it is kept by the
+    // node. Rated amounts past u64::MAX are scaled as floats, like amounts scaled up,
+    // since an f64 cannot hold them to the unit anyway.
+    let rated = rate * (input as f64);
+    let outgoing_amount = if asset_scale_dest < asset_scale_src && rated < u64::MAX as f64 {
+        let scaled = scale_amount(
+            rated as u64,
+            asset_scale_src,
+            asset_scale_dest,
+            Rounding::Floor,
+        )
+        .map_err(|_| OutgoingAmountError::FloatOverflow)?;
+        if scaled.residual > 0 {
+            trace!(
+                "Rounded off {} units (scale {}) scaling the amount down to scale {}",
+                scaled.residual,
+                asset_scale_src,
+                asset_scale_dest
+            );
+        }
+        if scaled.amount == 0 && rated > 0.0 {
+            return Err(OutgoingAmountError::LessThanOne(
+                unscaled(input).unwrap_or_default(),
+            ));
+        }
+        Ok(scaled.amount as f64)
+    } else {
+        // Can we overflow here?
+        unscaled(input)
+    };
 
     match outgoing_amount {
         // Happens when rate == 0 or spread >= 1
@@ -197,13 +260,15 @@ mod tests {
     use super::*;
     use interledger_errors::{AddressStoreError, ExchangeRateStoreError};
     use interledger_packet::{Address, Fulfill, FulfillBuilder, PrepareBuilder, Reject};
+    use interledger_rates::{CachingRateProvider, StaticRateProvider};
     use interledger_service::{outgoing_service_fn, Account};
     use once_cell::sync::Lazy;
     use std::collections::HashMap;
     use std::str::FromStr;
     use std::{
+        sync::atomic::{AtomicBool, Ordering},
         sync::{Arc, Mutex},
-        time::SystemTime,
+        time::{Duration, SystemTime},
     };
     use uuid::Uuid;
 
@@ -337,6 +402,36 @@ mod tests {
         );
     }
 
+    #[test]
+    fn calculates_downscaled_amounts_after_the_rate() {
+        assert_eq!(
+            calculate_outgoing_amount(1999, 0.0, (1.0, 1.0), (3, 0)),
+            Ok(1)
+        );
+        assert_eq!(
+            calculate_outgoing_amount(1999, 0.0, (3.0, 1.0), (3, 0)),
+            Ok(5)
+        );
+        // The rate applies to the whole amount, including what is less than a unit
+        assert_eq!(
+            calculate_outgoing_amount(999, 0.0, (1000.0, 1.0), (3, 0)),
+            Ok(999)
+        );
+        assert_eq!(
+            calculate_outgoing_amount(1, 0.0, (1_000_000.0, 1.0), (3, 0)),
+            Ok(1000)
+        );
+        assert_eq!(
+            calculate_outgoing_amount(999, 0.0, (1.0, 1.0), (3, 0)),
+            Err(OutgoingAmountError::LessThanOne(0.999))
+        );
+        // Scales too far apart for the conversion factor to fit in a u128
+        assert!(matches!(
+            calculate_outgoing_amount(u64::MAX, 0.0, (1.0, 1.0), (255, 0)),
+            Err(OutgoingAmountError::LessThanOne(_))
+        ));
+    }
+
     #[test]
     fn calculates_with_high_asset_scale() {
         assert_eq!(
@@ -347,6 +442,89 @@ mod tests {
 
     // Instantiates an exchange rate service and returns the fulfill/reject
     // packet and the outgoing request after performing an asset conversion
+    #[tokio::test]
+    async fn applies_rates_from_custom_provider() {
+        // The store's rates are 1:1, so any conversion must come from the provider
+        let provider = StaticRateProvider::new(
+            vec![("ABC".to_string(), 3.0), ("XYZ".to_string(), 2.0)]
+                .into_iter()
+                .collect(),
+        );
+        let ret = exchange_rate_with_provider(1_000_000, 1, 1, provider, 0.0).await;
+        assert_eq!(ret.1[0].prepare.amount(), 1_500_000);
+
+        let ret = exchange_rate_with_provider(100, 1, 1, StaticRateProvider::default(), 0.0).await;
+        let reject = ret.0.unwrap_err();
+        assert_eq!(reject.code(), ErrorCode::T00_INTERNAL_ERROR);
+        assert!(ret.1.is_empty());
+    }
+
+    #[tokio::test]
+    async fn queries_provider_for_the_request_assets() {
+        struct PairProvider;
+
+        #[async_trait]
+        impl RateProvider for PairProvider {
+            async fn get_rate(
+                &self,
+                from_asset: &str,
+                to_asset: &str,
+            ) -> Result<f64, ExchangeRateStoreError> {
+                assert_eq!((from_asset, to_asset), ("ABC", "XYZ"));
+                Ok(0.25)
+            }
+        }
+
+        let ret = exchange_rate_with_provider(400, 1, 1, PairProvider, 0.0).await;
+        assert_eq!(ret.1[0].prepare.amount(), 100);
+
+        // The spread and scales are applied on top of the provider's rate
+        let ret = exchange_rate_with_provider(400, 1, 2, PairProvider, 0.01).await;
+        assert_eq!(ret.1[0].prepare.amount(), 990);
+    }
+
+    #[tokio::test]
+    async fn prices_with_stale_cached_rates_until_max_staleness() {
+        #[derive(Clone)]
+        struct FlakyProvider(Arc<AtomicBool>);
+
+        #[async_trait]
+        impl RateProvider for FlakyProvider {
+            async fn get_rate(
+                &self,
+                from_asset: &str,
+                to_asset: &str,
+            ) -> Result<f64, ExchangeRateStoreError> {
+                if self.0.load(Ordering::SeqCst) {
+                    Err(ExchangeRateStoreError::PairNotFound {
+                        from: from_asset.to_string(),
+                        to: to_asset.to_string(),
+                    })
+                } else {
+                    Ok(0.5)
+                }
+            }
+        }
+
+        let upstream_down = Arc::new(AtomicBool::new(false));
+        let provider = CachingRateProvider::new(FlakyProvider(upstream_down.clone()))
+            .with_ttl(Duration::from_millis(10))
+            .with_max_staleness(Duration::from_millis(100));
+
+        let ret = exchange_rate_with_provider(100, 1, 1, provider.clone(), 0.0).await;
+        assert_eq!(ret.1[0].prepare.amount(), 50);
+
+        upstream_down.store(true, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let ret = exchange_rate_with_provider(100, 1, 1, provider.clone(), 0.0).await;
+        assert_eq!(ret.1[0].prepare.amount(), 50);
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let ret = exchange_rate_with_provider(100, 1, 1, provider, 0.0).await;
+        assert_eq!(ret.0.unwrap_err().code(), ErrorCode::T00_INTERNAL_ERROR);
+        assert!(ret.1.is_empty());
+    }
+
     async fn exchange_rate(
         amount: u64,
         scale1: u8,
@@ -365,7 +543,39 @@ mod tests {
             }
             .build())
         });
-        let mut service = test_service(rate1, rate2, spread, outgoing);
+        let service = test_service(rate1, rate2, spread, outgoing);
+        send_prepare(service, amount, scale1, scale2, requests).await
+    }
+
+    async fn exchange_rate_with_provider(
+        amount: u64,
+        scale1: u8,
+        scale2: u8,
+        provider: impl RateProvider + Send + Sync + 'static,
+        spread: f64,
+    ) -> (Result<Fulfill, Reject>, Vec<OutgoingRequest<TestAccount>>) {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let requests_clone = requests.clone();
+        let outgoing = outgoing_service_fn(move |request| {
+            requests_clone.lock().unwrap().push(request);
+            Ok(FulfillBuilder {
+                fulfillment: &[0; 32],
+                data: b"hello!",
+            }
+            .build())
+        });
+        let service =
+            ExchangeRateService::with_provider(spread, test_store(1.0, 1.0), provider, outgoing);
+        send_prepare(service, amount, scale1, scale2, requests).await
+    }
+
+    async fn send_prepare(
+        mut service: impl OutgoingService<TestAccount>,
+        amount: u64,
+        scale1: u8,
+        scale2: u8,
+        requests: Arc<Mutex<Vec<OutgoingRequest<TestAccount>>>>,
+    ) -> (Result<Fulfill, Reject>, Vec<OutgoingRequest<TestAccount>>) {
         let result = service
             .send_request(OutgoingRequest {
                 from: TestAccount::new("ABC".to_owned(), scale1),
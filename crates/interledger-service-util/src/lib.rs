@@ -4,6 +4,8 @@
 
 /// Balance tracking service
 mod balance_service;
+/// Handle which drains the packets in flight when the node shuts down
+mod connector;
 /// Service which implements the echo protocol
 mod echo_service;
 /// Service responsible for setting and fetching dollar denominated exchange rates
@@ -13,20 +15,26 @@ mod exchange_rates_service;
 mod expiry_shortener_service;
 /// Service responsible for capping the amount an account can send in a packet
 mod max_packet_amount_service;
+/// Service responsible for throttling the packets an account can send with an in-memory token bucket
+mod packet_rate_limit_service;
 /// Service responsible for capping the amount of packets and amount in packets an account can send
 mod rate_limit_service;
 /// Service responsible for checking that packets are not expired and that prepare packets' fulfillment conditions
 /// match the fulfillment inside the incoming fulfills
 mod validator_service;
 
-pub use self::balance_service::{start_delayed_settlement, BalanceService, BalanceStore};
+pub use self::balance_service::{
+    resume_pending_settlements, start_delayed_settlement, BalanceService, BalanceStore,
+};
+pub use self::connector::{Connector, DrainingService};
 pub use self::echo_service::EchoService;
 pub use self::exchange_rates_service::ExchangeRateService;
 pub use self::expiry_shortener_service::{
     ExpiryShortenerService, RoundTripTimeAccount, DEFAULT_ROUND_TRIP_TIME,
 };
 pub use self::max_packet_amount_service::{MaxPacketAmountAccount, MaxPacketAmountService};
+pub use self::packet_rate_limit_service::PacketRateLimitService;
 pub use self::rate_limit_service::{
     RateLimitAccount, RateLimitError, RateLimitService, RateLimitStore,
 };
-pub use self::validator_service::ValidatorService;
+pub use self::validator_service::{MinExpiryWindowAccount, ValidatorService};
@@ -38,7 +38,9 @@ where
     A: MaxPacketAmountAccount + Send + Sync + 'static,
 {
     /// On receive request:
-    /// 1. if request.prepare.amount <= request.from.max_packet_amount forward the request, else error
+    /// 1. if request.prepare.amount <= request.from.max_packet_amount forward the request, else
+    ///    reject it with an F08 carrying the received and maximum amounts, so that senders can
+    ///    lower the amount of their following packets
     async fn handle_request(&mut self, request: IncomingRequest<A>) -> IlpResult {
         let ilp_address = self.store.get_ilp_address();
         let max_packet_amount = request.from.max_packet_amount();
@@ -54,7 +56,12 @@ where
                 MaxPacketAmountDetails::new(request.prepare.amount(), max_packet_amount).to_bytes();
             Err(RejectBuilder {
                 code: ErrorCode::F08_AMOUNT_TOO_LARGE,
-                message: &[],
+                message: format!(
+                    "Packet amount {} exceeds the maximum of {}",
+                    request.prepare.amount(),
+                    max_packet_amount
+                )
+                .as_bytes(),
                 triggered_by: Some(&ilp_address),
                 data: &details[..],
             }
@@ -135,6 +142,13 @@ mod tests {
         let mut service = MaxPacketAmountService::new(store.clone(), next);
         let reject = service.handle_request(request).await.unwrap_err();
         assert_eq!(reject.code(), ErrorCode::F08_AMOUNT_TOO_LARGE);
+        assert_eq!(
+            reject.triggered_by().unwrap().to_string(),
+            "example.connector"
+        );
+        let details = MaxPacketAmountDetails::from_bytes(reject.data()).unwrap();
+        assert_eq!(details.amount_received(), 100);
+        assert_eq!(details.max_amount(), 99);
     }
 
     #[derive(Clone)]
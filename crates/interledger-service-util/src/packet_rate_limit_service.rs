@@ -0,0 +1,348 @@
+use crate::RateLimitAccount;
+use async_trait::async_trait;
+use interledger_packet::{ErrorCode, RejectBuilder};
+use interledger_service::{AddressStore, IlpResult, IncomingRequest, IncomingService};
+use parking_lot::RwLock;
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::time::Instant;
+use tracing::warn;
+use uuid::Uuid;
+
+/// Number of independently locked maps the buckets are spread over
+const SHARDS: usize = 16;
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+
+/// A token bucket implemented with the Generic Cell Rate Algorithm: instead of counting
+/// tokens, it tracks the time at which the bucket will be full again, so that taking a
+/// token is a single compare-and-swap.
+struct TokenBucket {
+    packets_per_second: u32,
+    burst: u32,
+    /// Nanoseconds it takes to refill one token
+    interval: u64,
+    /// How far ahead of now `full_at` may be, i.e. the time to refill the whole bucket
+    capacity: u64,
+    start: Instant,
+    /// Nanoseconds after `start` at which the bucket will be full again
+    full_at: AtomicU64,
+}
+
+impl TokenBucket {
+    fn new(packets_per_second: u32, burst: u32) -> Self {
+        let interval = NANOS_PER_SECOND / u64::from(packets_per_second.max(1));
+        TokenBucket {
+            packets_per_second,
+            burst,
+            interval,
+            capacity: interval.saturating_mul(u64::from(burst)),
+            start: Instant::now(),
+            full_at: AtomicU64::new(0),
+        }
+    }
+
+    fn has_limits(&self, packets_per_second: u32, burst: u32) -> bool {
+        self.packets_per_second == packets_per_second && self.burst == burst
+    }
+
+    /// Takes a token if one is available
+    fn try_take(&self) -> bool {
+        if self.packets_per_second == 0 {
+            return false;
+        }
+        let now = Instant::now().duration_since(self.start).as_nanos() as u64;
+        let mut full_at = self.full_at.load(Ordering::Acquire);
+        loop {
+            let next = full_at.max(now).saturating_add(self.interval);
+            if next - now > self.capacity {
+                return false;
+            }
+            match self.full_at.compare_exchange_weak(
+                full_at,
+                next,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => return true,
+                Err(current) => full_at = current,
+            }
+        }
+    }
+}
+
+/// The accounts' token buckets, sharded by account id so that a busy account
+/// only contends with the few others in its shard
+struct TokenBuckets {
+    shards: Vec<RwLock<HashMap<Uuid, TokenBucket>>>,
+}
+
+impl TokenBuckets {
+    fn new() -> Self {
+        TokenBuckets {
+            shards: (0..SHARDS).map(|_| RwLock::new(HashMap::new())).collect(),
+        }
+    }
+
+    /// Takes a token from the account's bucket, (re)creating the bucket if the
+    /// account has none yet or its limits were changed
+    fn try_take(&self, account_id: Uuid, packets_per_second: u32, burst: u32) -> bool {
+        let shard = &self.shards[(account_id.as_u128() % SHARDS as u128) as usize];
+        if let Some(bucket) = shard.read().get(&account_id) {
+            if bucket.has_limits(packets_per_second, burst) {
+                return bucket.try_take();
+            }
+        }
+
+        let mut shard = shard.write();
+        let bucket = shard
+            .entry(account_id)
+            .or_insert_with(|| TokenBucket::new(packets_per_second, burst));
+        if !bucket.has_limits(packets_per_second, burst) {
+            *bucket = TokenBucket::new(packets_per_second, burst);
+        }
+        bucket.try_take()
+    }
+}
+
+/// # Packet Rate Limit Service
+///
+/// Incoming Service which throttles the packets each account can send with a token bucket
+/// kept in memory. The bucket refills at the account's `packets_per_second_limit` and holds
+/// up to its `packet_burst_limit` tokens (one second's worth if no burst is configured).
+/// Packets sent while the bucket is empty are rejected with `T05: Rate Limited`.
+///
+/// The limits are read from the account on every request, so changing them in the store
+/// takes effect on the account's next packet; this resets its bucket.
+/// Accounts without a `packets_per_second_limit` are not throttled.
+#[derive(Clone)]
+pub struct PacketRateLimitService<S, I, A> {
+    store: S,
+    next: I,
+    buckets: Arc<TokenBuckets>,
+    account_type: PhantomData<A>,
+}
+
+impl<S, I, A> PacketRateLimitService<S, I, A>
+where
+    S: AddressStore + Send + Sync,
+    I: IncomingService<A> + Send + Sync,
+    A: RateLimitAccount + Sync,
+{
+    pub fn new(store: S, next: I) -> Self {
+        PacketRateLimitService {
+            store,
+            next,
+            buckets: Arc::new(TokenBuckets::new()),
+            account_type: PhantomData,
+        }
+    }
+}
+
+#[async_trait]
+impl<S, I, A> IncomingService<A> for PacketRateLimitService<S, I, A>
+where
+    S: AddressStore + Send + Sync + 'static,
+    I: IncomingService<A> + Send + Sync + 'static,
+    A: RateLimitAccount + Sync + 'static,
+{
+    async fn handle_request(&mut self, request: IncomingRequest<A>) -> IlpResult {
+        if let Some(packets_per_second) = request.from.packets_per_second_limit() {
+            let burst = request
+                .from
+                .packet_burst_limit()
+                .unwrap_or(packets_per_second);
+            if !self
+                .buckets
+                .try_take(request.from.id(), packets_per_second, burst)
+            {
+                warn!(
+                    "Account {} was rate limited for sending too many packets. Limit is: {} per second with a burst of {}",
+                    request.from.id(),
+                    packets_per_second,
+                    burst
+                );
+                return Err(RejectBuilder {
+                    code: ErrorCode::T05_RATE_LIMITED,
+                    message: &[],
+                    triggered_by: Some(&self.store.get_ilp_address()),
+                    data: &[],
+                }
+                .build());
+            }
+        }
+        self.next.handle_request(request).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use interledger_errors::AddressStoreError;
+    use interledger_packet::{Address, FulfillBuilder, PrepareBuilder};
+    use interledger_service::{incoming_service_fn, Account, Username};
+    use once_cell::sync::Lazy;
+    use std::str::FromStr;
+    use std::time::{Duration, SystemTime};
+
+    #[tokio::test(start_paused = true)]
+    async fn rejects_packets_over_the_burst_until_refilled() {
+        let account = TestAccount::new(Some(10), Some(5));
+        let mut service = test_service();
+
+        assert_eq!(send_packets(&mut service, &account, 8).await, 3);
+
+        // One token is refilled every 100ms
+        tokio::time::advance(Duration::from_millis(250)).await;
+        assert_eq!(send_packets(&mut service, &account, 3).await, 1);
+
+        // The bucket never holds more than the burst
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert_eq!(send_packets(&mut service, &account, 7).await, 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn burst_defaults_to_one_second_of_packets() {
+        let account = TestAccount::new(Some(4), None);
+        let mut service = test_service();
+        assert_eq!(send_packets(&mut service, &account, 6).await, 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn limits_each_account_separately() {
+        let alice = TestAccount::new(Some(1), Some(2));
+        let bob = TestAccount::new(Some(1), Some(2));
+        let mut service = test_service();
+        assert_eq!(send_packets(&mut service, &alice, 3).await, 1);
+        assert_eq!(send_packets(&mut service, &bob, 2).await, 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn applies_updated_limits() {
+        let mut account = TestAccount::new(Some(1), Some(1));
+        let mut service = test_service();
+        assert_eq!(send_packets(&mut service, &account, 2).await, 1);
+
+        account.burst = Some(3);
+        assert_eq!(send_packets(&mut service, &account, 4).await, 1);
+
+        account.packets_per_second = None;
+        assert_eq!(send_packets(&mut service, &account, 10).await, 0);
+    }
+
+    /// Sends the packets at once and returns how many were rate limited
+    async fn send_packets(
+        service: &mut impl IncomingService<TestAccount>,
+        account: &TestAccount,
+        packets: usize,
+    ) -> usize {
+        let mut rejects = 0;
+        for _ in 0..packets {
+            let result = service
+                .handle_request(IncomingRequest {
+                    from: account.clone(),
+                    prepare: PrepareBuilder {
+                        destination: Address::from_str("example.destination").unwrap(),
+                        amount: 100,
+                        expires_at: SystemTime::now() + Duration::from_secs(30),
+                        execution_condition: &[0; 32],
+                        data: &[],
+                    }
+                    .build(),
+                })
+                .await;
+            if let Err(reject) = result {
+                assert_eq!(reject.code(), ErrorCode::T05_RATE_LIMITED);
+                assert_eq!(
+                    reject.triggered_by().unwrap(),
+                    Address::from_str("example.connector").unwrap()
+                );
+                rejects += 1;
+            }
+        }
+        rejects
+    }
+
+    fn test_service() -> impl IncomingService<TestAccount> {
+        let next = incoming_service_fn(|_| {
+            Ok(FulfillBuilder {
+                fulfillment: &[0; 32],
+                data: &[],
+            }
+            .build())
+        });
+        PacketRateLimitService::new(TestStore, next)
+    }
+
+    static ALICE: Lazy<Username> = Lazy::new(|| Username::from_str("alice").unwrap());
+    static EXAMPLE_ADDRESS: Lazy<Address> =
+        Lazy::new(|| Address::from_str("example.alice").unwrap());
+
+    #[derive(Debug, Clone)]
+    struct TestAccount {
+        id: Uuid,
+        packets_per_second: Option<u32>,
+        burst: Option<u32>,
+    }
+
+    impl TestAccount {
+        fn new(packets_per_second: Option<u32>, burst: Option<u32>) -> Self {
+            TestAccount {
+                id: Uuid::new_v4(),
+                packets_per_second,
+                burst,
+            }
+        }
+    }
+
+    impl Account for TestAccount {
+        fn id(&self) -> Uuid {
+            self.id
+        }
+
+        fn username(&self) -> &Username {
+            &ALICE
+        }
+
+        fn asset_code(&self) -> &str {
+            "XYZ"
+        }
+
+        fn asset_scale(&self) -> u8 {
+            9
+        }
+
+        fn ilp_address(&self) -> &Address {
+            &EXAMPLE_ADDRESS
+        }
+    }
+
+    impl RateLimitAccount for TestAccount {
+        fn packets_per_second_limit(&self) -> Option<u32> {
+            self.packets_per_second
+        }
+
+        fn packet_burst_limit(&self) -> Option<u32> {
+            self.burst
+        }
+    }
+
+    #[derive(Clone)]
+    struct TestStore;
+
+    #[async_trait]
+    impl AddressStore for TestStore {
+        async fn set_ilp_address(&self, _: Address) -> Result<(), AddressStoreError> {
+            Ok(())
+        }
+
+        async fn clear_ilp_address(&self) -> Result<(), AddressStoreError> {
+            Ok(())
+        }
+
+        fn get_ilp_address(&self) -> Address {
+            Address::from_str("example.connector").unwrap()
+        }
+    }
+}
@@ -16,6 +16,16 @@ pub trait RateLimitAccount: Account {
     fn amount_per_minute_limit(&self) -> Option<u64> {
         None
     }
+
+    /// The rate, in packets per second, at which this account's token bucket refills
+    fn packets_per_second_limit(&self) -> Option<u32> {
+        None
+    }
+
+    /// The number of packets this account can send in a burst, i.e. the size of its token bucket
+    fn packet_burst_limit(&self) -> Option<u32> {
+        None
+    }
 }
 
 /// Rate limiting related errors
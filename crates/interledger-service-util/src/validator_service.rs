@@ -7,6 +7,16 @@ use std::marker::PhantomData;
 use tokio::time::timeout;
 use tracing::error;
 
+/// An account which can be required to leave more (or less) time before its incoming
+/// packets expire than the [`ValidatorService`](./struct.ValidatorService.html)'s default
+pub trait MinExpiryWindowAccount: Account {
+    /// The minimum time, in milliseconds, the account's incoming packets must have left
+    /// before expiring. Overrides the window of the `ValidatorService` when set
+    fn min_expiry_window(&self) -> Option<u32> {
+        None
+    }
+}
+
 /// # Validator Service
 ///
 /// Incoming or Outgoing Service responsible for rejecting timed out
@@ -16,6 +26,8 @@ use tracing::error;
 pub struct ValidatorService<IO, S, A> {
     store: S,
     next: IO,
+    /// Minimum time, in milliseconds, incoming packets must have left before expiring
+    min_expiry_window: u32,
     account_type: PhantomData<A>,
 }
 
@@ -31,9 +43,17 @@ where
         ValidatorService {
             store,
             next,
+            min_expiry_window: 0,
             account_type: PhantomData,
         }
     }
+
+    /// Also rejects incoming requests which expire in less than this many milliseconds,
+    /// unless the account sets its own window
+    pub fn with_min_expiry_window(mut self, milliseconds: u32) -> Self {
+        self.min_expiry_window = milliseconds;
+        self
+    }
 }
 
 impl<O, S, A> ValidatorService<O, S, A>
@@ -49,6 +69,7 @@ where
         ValidatorService {
             store,
             next,
+            min_expiry_window: 0,
             account_type: PhantomData,
         }
     }
@@ -59,30 +80,59 @@ impl<I, S, A> IncomingService<A> for ValidatorService<I, S, A>
 where
     I: IncomingService<A> + Send + Sync,
     S: AddressStore + Send + Sync,
-    A: Account + Send + Sync,
+    A: MinExpiryWindowAccount + Send + Sync,
 {
     /// On receiving a request:
-    /// 1. If the prepare packet in the request is not expired, forward it, otherwise return a reject
+    /// 1. If the prepare packet in the request is expired, return a reject
+    /// 1. If the prepare packet expires sooner than the account's (or else the service's)
+    ///    minimum expiry window, return a reject
+    /// 1. Otherwise forward it
     async fn handle_request(&mut self, request: IncomingRequest<A>) -> IlpResult {
         let expires_at = DateTime::<Utc>::from(request.prepare.expires_at());
         let now = Utc::now();
-        if expires_at >= now {
-            self.next.handle_request(request).await
-        } else {
+        if expires_at < now {
             error!(
                 "Incoming packet expired {}ms ago at {:?} (time now: {:?})",
                 now.signed_duration_since(expires_at).num_milliseconds(),
                 expires_at.to_rfc3339(),
-                expires_at.to_rfc3339(),
+                now.to_rfc3339(),
             );
-            Err(RejectBuilder {
+            return Err(RejectBuilder {
                 code: ErrorCode::R00_TRANSFER_TIMED_OUT,
                 message: &[],
                 triggered_by: Some(&self.store.get_ilp_address()),
                 data: &[],
             }
-            .build())
+            .build());
+        }
+
+        let min_expiry_window = request
+            .from
+            .min_expiry_window()
+            .unwrap_or(self.min_expiry_window);
+        let time_left = expires_at - now;
+        if time_left < Duration::milliseconds(i64::from(min_expiry_window)) {
+            error!(
+                "Incoming packet from account {} expires in {}ms, less than the minimum of {}ms",
+                request.from.id(),
+                time_left.num_milliseconds(),
+                min_expiry_window,
+            );
+            return Err(RejectBuilder {
+                code: ErrorCode::R02_INSUFFICIENT_TIMEOUT,
+                message: format!(
+                    "Packet expires in {}ms, but at least {}ms are required",
+                    time_left.num_milliseconds(),
+                    min_expiry_window
+                )
+                .as_bytes(),
+                triggered_by: Some(&self.store.get_ilp_address()),
+                data: &[],
+            }
+            .build());
         }
+
+        self.next.handle_request(request).await
     }
 }
 
@@ -208,6 +258,9 @@ impl Account for TestAccount {
     }
 }
 
+#[cfg(test)]
+impl MinExpiryWindowAccount for TestAccount {}
+
 #[cfg(test)]
 #[derive(Clone)]
 struct TestStore;
@@ -318,6 +371,147 @@ mod incoming {
             ErrorCode::R00_TRANSFER_TIMED_OUT
         );
     }
+
+    /// An account with its own minimum expiry window
+    #[derive(Clone, Debug)]
+    struct WindowAccount(Option<u32>);
+
+    impl Account for WindowAccount {
+        fn id(&self) -> Uuid {
+            Uuid::nil()
+        }
+
+        fn username(&self) -> &Username {
+            &ALICE
+        }
+
+        fn asset_code(&self) -> &str {
+            "XYZ"
+        }
+
+        fn asset_scale(&self) -> u8 {
+            9
+        }
+
+        fn ilp_address(&self) -> &Address {
+            &EXAMPLE_ADDRESS
+        }
+    }
+
+    impl MinExpiryWindowAccount for WindowAccount {
+        fn min_expiry_window(&self) -> Option<u32> {
+            self.0
+        }
+    }
+
+    fn prepare(expires_at: SystemTime) -> Prepare {
+        PrepareBuilder {
+            destination: Address::from_str("example.destination").unwrap(),
+            amount: 100,
+            expires_at,
+            execution_condition: &[0; 32],
+            data: &[],
+        }
+        .build()
+    }
+
+    #[tokio::test]
+    async fn rejects_packets_expiring_within_min_expiry_window() {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let requests_clone = requests.clone();
+        let mut validator = ValidatorService::incoming(
+            TestStore,
+            incoming_service_fn(move |request| {
+                requests_clone.lock().unwrap().push(request);
+                Ok(FulfillBuilder {
+                    fulfillment: &[0; 32],
+                    data: &[],
+                }
+                .build())
+            }),
+        )
+        .with_min_expiry_window(5000);
+
+        let expired = validator
+            .handle_request(IncomingRequest {
+                from: TestAccount(Uuid::new_v4()),
+                prepare: prepare(SystemTime::now() - Duration::from_millis(1)),
+            })
+            .await;
+        assert_eq!(
+            expired.unwrap_err().code(),
+            ErrorCode::R00_TRANSFER_TIMED_OUT
+        );
+
+        let too_short = validator
+            .handle_request(IncomingRequest {
+                from: TestAccount(Uuid::new_v4()),
+                prepare: prepare(SystemTime::now() + Duration::from_secs(1)),
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(too_short.code(), ErrorCode::R02_INSUFFICIENT_TIMEOUT);
+        assert_eq!(
+            too_short.triggered_by().unwrap().to_string(),
+            "example.connector"
+        );
+        assert!(requests.lock().unwrap().is_empty());
+
+        let result = validator
+            .handle_request(IncomingRequest {
+                from: TestAccount(Uuid::new_v4()),
+                prepare: prepare(SystemTime::now() + Duration::from_secs(30)),
+            })
+            .await;
+        assert!(result.is_ok());
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn accounts_override_min_expiry_window() {
+        let mut validator = ValidatorService::incoming(
+            TestStore,
+            incoming_service_fn(|_| {
+                Ok(FulfillBuilder {
+                    fulfillment: &[0; 32],
+                    data: &[],
+                }
+                .build())
+            }),
+        )
+        .with_min_expiry_window(5000);
+
+        let result = validator
+            .handle_request(IncomingRequest {
+                from: WindowAccount(Some(500)),
+                prepare: prepare(SystemTime::now() + Duration::from_secs(1)),
+            })
+            .await;
+        assert!(result.is_ok());
+
+        let result = validator
+            .handle_request(IncomingRequest {
+                from: WindowAccount(Some(60_000)),
+                prepare: prepare(SystemTime::now() + Duration::from_secs(30)),
+            })
+            .await;
+        assert_eq!(
+            result.unwrap_err().code(),
+            ErrorCode::R02_INSUFFICIENT_TIMEOUT
+        );
+
+        // Accounts without their own window use the service's
+        let result = validator
+            .handle_request(IncomingRequest {
+                from: WindowAccount(None),
+                prepare: prepare(SystemTime::now() + Duration::from_secs(1)),
+            })
+            .await;
+        assert_eq!(
+            result.unwrap_err().code(),
+            ErrorCode::R02_INSUFFICIENT_TIMEOUT
+        );
+    }
 }
 
 #[cfg(test)]
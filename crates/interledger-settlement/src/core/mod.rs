@@ -19,7 +19,8 @@ pub mod types;
 use num_bigint::BigUint;
 use num_traits::Zero;
 use ring::digest::{digest, SHA256};
-use types::{Convert, ConvertDetails};
+use std::convert::TryFrom;
+use types::{ConversionError, Convert, ConvertDetails, Rounding, ScaledAmount};
 
 /// Converts a number from a precision to another while taking precision loss into account
 ///
@@ -77,9 +78,219 @@ pub fn scale_with_precision_loss(
     }
 }
 
+/// Converts an amount from an asset scale to another, rounding it as requested and
+/// reporting what was lost (or added) by the rounding so that it can be accounted for.
+///
+/// Fails if the scaled amount does not fit in a u64, which includes upscaling a non-zero
+/// amount by more than 38 decimal places. Downscaling by more than that leaves nothing of
+/// any amount whatever the rounding, so all of it is reported as the residual.
+///
+/// # Examples
+/// ```rust
+/// # use interledger_settlement::core::{scale_amount, types::{Rounding, ScaledAmount}};
+/// assert_eq!(
+///     scale_amount(905, 11, 9, Rounding::Floor).unwrap(),
+///     ScaledAmount { amount: 9, residual: 5 }
+/// );
+///
+/// assert_eq!(
+///     scale_amount(905, 11, 9, Rounding::Ceil).unwrap(),
+///     ScaledAmount { amount: 10, residual: -95 }
+/// );
+///
+/// assert_eq!(
+///     scale_amount(1, 6, 9, Rounding::HalfEven).unwrap(),
+///     ScaledAmount { amount: 1000, residual: 0 }
+/// );
+/// ```
+pub fn scale_amount(
+    amount: u64,
+    from_scale: u8,
+    to_scale: u8,
+    rounding: Rounding,
+) -> Result<ScaledAmount, ConversionError> {
+    let amount = u128::from(amount);
+    let scale_diff = u32::from(from_scale.max(to_scale) - from_scale.min(to_scale));
+    let factor = match 10u128.checked_pow(scale_diff) {
+        Some(factor) => factor,
+        None if to_scale < from_scale => {
+            return Ok(ScaledAmount {
+                amount: 0,
+                residual: amount as i128,
+            })
+        }
+        None => return Err(ConversionError),
+    };
+
+    if to_scale >= from_scale {
+        let scaled = amount.checked_mul(factor).ok_or(ConversionError)?;
+        return Ok(ScaledAmount {
+            amount: u64::try_from(scaled).map_err(|_| ConversionError)?,
+            residual: 0,
+        });
+    }
+
+    let (quotient, remainder) = (amount / factor, amount % factor);
+    let round_up = remainder > 0
+        && match rounding {
+            Rounding::Floor => false,
+            Rounding::Ceil => true,
+            // The factor is at least 10 here, so doubling the remainder cannot overflow
+            Rounding::HalfEven => match (remainder * 2).cmp(&factor) {
+                std::cmp::Ordering::Less => false,
+                std::cmp::Ordering::Equal => quotient % 2 == 1,
+                std::cmp::Ordering::Greater => true,
+            },
+        };
+
+    // Both the remainder and what it is short of the factor are less than 10^38,
+    // which fits in an i128
+    let (scaled, residual) = if round_up {
+        (quotient + 1, -((factor - remainder) as i128))
+    } else {
+        (quotient, remainder as i128)
+    };
+    Ok(ScaledAmount {
+        // The quotient is at most u64::MAX / 10, so this cannot fail even after rounding up
+        amount: u64::try_from(scaled).map_err(|_| ConversionError)?,
+        residual,
+    })
+}
+
 /// Returns the 32-bytes SHA256 hash of the provided preimage
 pub fn get_hash_of(preimage: &[u8]) -> [u8; 32] {
     let mut hash = [0; 32];
     hash.copy_from_slice(digest(&SHA256, preimage).as_ref());
     hash
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODES: [Rounding; 3] = [Rounding::Floor, Rounding::Ceil, Rounding::HalfEven];
+
+    fn scaled(amount: u64, residual: i128) -> ScaledAmount {
+        ScaledAmount { amount, residual }
+    }
+
+    #[test]
+    fn upscales_without_residual() {
+        for rounding in MODES.iter() {
+            assert_eq!(
+                scale_amount(123, 6, 9, *rounding).unwrap(),
+                scaled(123_000, 0)
+            );
+            assert_eq!(scale_amount(123, 9, 9, *rounding).unwrap(), scaled(123, 0));
+            assert_eq!(scale_amount(0, 0, 18, *rounding).unwrap(), scaled(0, 0));
+        }
+    }
+
+    #[test]
+    fn downscales_with_remainder() {
+        // 1.234 rounds down for every mode except ceil
+        assert_eq!(
+            scale_amount(1234, 3, 0, Rounding::Floor).unwrap(),
+            scaled(1, 234)
+        );
+        assert_eq!(
+            scale_amount(1234, 3, 0, Rounding::Ceil).unwrap(),
+            scaled(2, -766)
+        );
+        assert_eq!(
+            scale_amount(1234, 3, 0, Rounding::HalfEven).unwrap(),
+            scaled(1, 234)
+        );
+
+        // 1.789 rounds up for every mode except floor
+        assert_eq!(
+            scale_amount(1789, 3, 0, Rounding::Floor).unwrap(),
+            scaled(1, 789)
+        );
+        assert_eq!(
+            scale_amount(1789, 3, 0, Rounding::Ceil).unwrap(),
+            scaled(2, -211)
+        );
+        assert_eq!(
+            scale_amount(1789, 3, 0, Rounding::HalfEven).unwrap(),
+            scaled(2, -211)
+        );
+
+        // Ties go to the even amount
+        assert_eq!(
+            scale_amount(25, 1, 0, Rounding::HalfEven).unwrap(),
+            scaled(2, 5)
+        );
+        assert_eq!(
+            scale_amount(35, 1, 0, Rounding::HalfEven).unwrap(),
+            scaled(4, -5)
+        );
+
+        // Without a remainder nothing is rounded
+        for rounding in MODES.iter() {
+            assert_eq!(scale_amount(1000, 3, 0, *rounding).unwrap(), scaled(1, 0));
+        }
+    }
+
+    #[test]
+    fn downscales_amounts_smaller_than_a_unit() {
+        assert_eq!(
+            scale_amount(4, 1, 0, Rounding::Floor).unwrap(),
+            scaled(0, 4)
+        );
+        assert_eq!(
+            scale_amount(4, 1, 0, Rounding::Ceil).unwrap(),
+            scaled(1, -6)
+        );
+        assert_eq!(
+            scale_amount(4, 1, 0, Rounding::HalfEven).unwrap(),
+            scaled(0, 4)
+        );
+
+        // The largest factor that fits in a u128 is 10^38
+        assert_eq!(
+            scale_amount(u64::MAX, 38, 0, Rounding::Floor).unwrap(),
+            scaled(0, u64::MAX.into())
+        );
+        assert_eq!(
+            scale_amount(u64::MAX, 38, 0, Rounding::Ceil).unwrap(),
+            scaled(1, -(10i128.pow(38) - i128::from(u64::MAX)))
+        );
+        assert_eq!(
+            scale_amount(u64::MAX, 38, 0, Rounding::HalfEven).unwrap(),
+            scaled(0, u64::MAX.into())
+        );
+    }
+
+    #[test]
+    fn reports_overflows() {
+        for rounding in MODES.iter() {
+            // u64::MAX is 18446744073709551615, so it can only be upscaled by rounding it down first
+            assert_eq!(
+                scale_amount(u64::MAX / 10, 0, 1, *rounding).unwrap(),
+                scaled(u64::MAX / 10 * 10, 0)
+            );
+            assert!(scale_amount(u64::MAX / 10 + 1, 0, 1, *rounding).is_err());
+            assert!(scale_amount(1, 0, 20, *rounding).is_err());
+
+            // Downscaling the largest amount does not overflow when rounding up
+            let ScaledAmount { amount, .. } = scale_amount(u64::MAX, 1, 0, *rounding).unwrap();
+            assert!(amount == u64::MAX / 10 || amount == u64::MAX / 10 + 1);
+
+            // The factor between the scales must fit in a u128 when upscaling
+            assert!(scale_amount(0, 0, 39, *rounding).is_err());
+        }
+    }
+
+    #[test]
+    fn downscales_by_more_than_a_u128_factor() {
+        for rounding in MODES.iter() {
+            assert_eq!(
+                scale_amount(u64::MAX, 39, 0, *rounding).unwrap(),
+                scaled(0, u64::MAX.into())
+            );
+            assert_eq!(scale_amount(1, 255, 0, *rounding).unwrap(), scaled(0, 1));
+            assert_eq!(scale_amount(0, 255, 0, *rounding).unwrap(), scaled(0, 0));
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use crate::core::types::Quantity;
+use crate::core::types::{PendingSettlement, Quantity};
 use futures_retry::{ErrorHandler, FutureRetry, RetryPolicy};
 use reqwest::Client;
 use serde_json::json;
@@ -88,8 +88,23 @@ impl SettlementClient {
         amount: u64,
         asset_scale: u8,
     ) -> Response {
+        self.send_pending_settlement(&PendingSettlement::new(id, engine_url, amount, asset_scale))
+            .await
+    }
+
+    /// Sends the settlement to the engine (will retry if it fails), using the settlement's
+    /// idempotency key for every attempt so that retries are not settled twice
+    pub async fn send_pending_settlement(&self, settlement: &PendingSettlement) -> Response {
         FutureRetry::new(
-            move || self.send_settlement_once(id, engine_url.clone(), amount, asset_scale),
+            move || {
+                self.send_settlement_once(
+                    settlement.account_id,
+                    settlement.engine_url.clone(),
+                    settlement.amount,
+                    settlement.asset_scale,
+                    &settlement.idempotency_key,
+                )
+            },
             RequestErrorHandler::new(self.max_retries),
         )
         .await
@@ -123,6 +138,7 @@ impl SettlementClient {
         engine_url: Url,
         amount: u64,
         asset_scale: u8,
+        idempotency_key: &str,
     ) -> Response {
         let mut settlement_engine_url = engine_url;
 
@@ -138,14 +154,11 @@ impl SettlementClient {
             amount, settlement_engine_url
         );
 
-        // Mark the request as idempotent
-        let idempotency_uuid = Uuid::new_v4().to_hyphenated().to_string();
-
         // Make the POST request future
         let response = self
             .client
             .post(settlement_engine_url.as_ref())
-            .header("Idempotency-Key", idempotency_uuid)
+            .header("Idempotency-Key", idempotency_key)
             .json(&json!(Quantity::new(amount, asset_scale)))
             .send()
             .await?;
@@ -258,4 +271,51 @@ mod tests {
         m.assert();
         assert!(ret.is_err());
     }
+
+    #[tokio::test]
+    async fn retries_timed_out_settlement_with_same_idempotency_key() {
+        use std::io::{BufRead, BufReader, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let engine_url = format!("http://{}", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        // The engine never answers the first attempt, so the client times out and retries
+        let engine = std::thread::spawn(move || {
+            let mut unanswered = Vec::new();
+            let mut idempotency_keys = Vec::new();
+            for stream in listener.incoming().take(2) {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    if let Some(key) = line.to_lowercase().strip_prefix("idempotency-key:") {
+                        idempotency_keys.push(key.trim().to_string());
+                    }
+                    line.clear();
+                }
+                if unanswered.is_empty() {
+                    unanswered.push(stream);
+                } else {
+                    stream
+                        .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                        .unwrap();
+                }
+            }
+            idempotency_keys
+        });
+
+        let client = SettlementClient::new(Duration::from_millis(200), 1);
+        let settlement = PendingSettlement::new(Uuid::new_v4(), engine_url, 100, 6);
+        let ret = client.send_pending_settlement(&settlement).await;
+
+        assert!(ret.is_ok());
+        assert_eq!(
+            engine.join().unwrap(),
+            vec![
+                settlement.idempotency_key.clone(),
+                settlement.idempotency_key.clone()
+            ]
+        );
+    }
 }
@@ -1,3 +1,4 @@
+use super::scale_amount;
 use async_trait::async_trait;
 use bytes::Bytes;
 use http::StatusCode;
@@ -11,6 +12,7 @@ use serde::{Deserialize, Serialize};
 use std::fmt;
 use std::ops::{Div, Mul};
 use std::str::FromStr;
+use std::time::Duration;
 use url::Url;
 use uuid::Uuid;
 
@@ -108,6 +110,11 @@ pub trait SettlementAccount: Account {
     fn settlement_engine_details(&self) -> Option<SettlementEngineDetails> {
         None
     }
+
+    /// Minimum time (if any) between two settlements triggered by the account's settle threshold
+    fn min_settlement_interval(&self) -> Option<Duration> {
+        None
+    }
 }
 
 #[async_trait]
@@ -137,6 +144,60 @@ pub trait SettlementStore {
     ) -> Result<(), SettlementStoreError>;
 }
 
+/// An outgoing settlement which was handed to a settlement engine but not yet confirmed
+///
+/// The idempotency key is generated once per settlement and sent on every attempt,
+/// so an engine which already accepted the settlement does not perform it again.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct PendingSettlement {
+    /// The account which is being settled
+    pub account_id: Uuid,
+    /// Base URL of the settlement engine the settlement is sent to
+    pub engine_url: Url,
+    /// Amount to settle, denominated in the account's asset scale
+    pub amount: u64,
+    /// Asset scale of the account
+    pub asset_scale: u8,
+    /// Sent as the `Idempotency-Key` header of every attempt
+    pub idempotency_key: String,
+}
+
+impl PendingSettlement {
+    /// Creates a pending settlement with a fresh idempotency key
+    pub fn new(account_id: Uuid, engine_url: Url, amount: u64, asset_scale: u8) -> Self {
+        PendingSettlement {
+            account_id,
+            engine_url,
+            amount,
+            asset_scale,
+            idempotency_key: Uuid::new_v4().to_hyphenated().to_string(),
+        }
+    }
+}
+
+#[async_trait]
+/// Trait used by the connector to persist outgoing settlements until the engine accepts them,
+/// so that the ones interrupted by a restart can be sent again with the same idempotency key
+pub trait PendingSettlementStore {
+    /// Saves the settlement before it is sent to the engine
+    async fn save_pending_settlement(
+        &self,
+        settlement: PendingSettlement,
+    ) -> Result<(), SettlementStoreError>;
+
+    /// Removes the settlement with the provided idempotency key. Returns whether it was
+    /// still pending, which lets only one of several concurrent callers act on its outcome
+    async fn clear_pending_settlement(
+        &self,
+        idempotency_key: &str,
+    ) -> Result<bool, SettlementStoreError>;
+
+    /// Loads every settlement which was saved but not cleared yet
+    async fn load_pending_settlements(
+        &self,
+    ) -> Result<Vec<PendingSettlement>, SettlementStoreError>;
+}
+
 /// Trait used by the connector and engine to track amounts which should have been
 /// settled but were not due to precision loss
 #[async_trait]
@@ -193,6 +254,27 @@ pub struct ConvertDetails {
     pub to: u8,
 }
 
+/// How an amount is rounded when it is scaled down to fewer decimal places
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rounding {
+    /// Round towards zero
+    Floor,
+    /// Round away from zero
+    Ceil,
+    /// Round to the nearest amount, and to the even one when exactly halfway between two
+    HalfEven,
+}
+
+/// An amount scaled by [`scale_amount`](../fn.scale_amount.html)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScaledAmount {
+    /// The amount in the new scale
+    pub amount: u64,
+    /// The difference between the original amount and the scaled one, in the original scale.
+    /// It is positive when the amount was rounded down and negative when it was rounded up.
+    pub residual: i128,
+}
+
 #[derive(Debug)]
 pub struct ConversionError;
 
@@ -215,21 +297,10 @@ pub trait Convert {
 impl Convert for u64 {
     type Item = u64;
 
+    /// Rounds down when downscaling; use [`scale_amount`](../fn.scale_amount.html) to
+    /// choose the rounding or to get what is lost to it
     fn normalize_scale(&self, details: ConvertDetails) -> Result<Self::Item, ConversionError> {
-        // FIXME: it's a bit sketchy how this was planned to work with i8 substraction overflow,
-        // widened to i32 for now, which might not be right either.
-        let scale_diff = (details.from as i32 - details.to as i32).unsigned_abs();
-        let scale = 10u64.pow(scale_diff);
-        let (res, overflow) = if details.to >= details.from {
-            self.overflowing_mul(scale)
-        } else {
-            self.overflowing_div(scale)
-        };
-        if overflow {
-            Err(ConversionError)
-        } else {
-            Ok(res)
-        }
+        scale_amount(*self, details.from, details.to, Rounding::Floor).map(|scaled| scaled.amount)
     }
 }
 
@@ -1,31 +1,124 @@
 use super::{Error, SpspResponse};
+use async_trait::async_trait;
+use bytes::Bytes;
 use futures::TryFutureExt;
 use interledger_rates::ExchangeRateStore;
 use interledger_service::{Account, IncomingService};
-use interledger_stream::{send_money, StreamDelivery};
-use reqwest::Client;
+use interledger_stream::{send_money, SendMoneyOptions, StreamDelivery};
+use reqwest::{header::LOCATION, redirect::Policy, Client, Url};
 use tracing::{debug, error, trace};
 
+/// Maximum number of redirects followed when querying an SPSP receiver
+const MAX_REDIRECTS: usize = 5;
+
+/// What an SPSP receiver answered to a query
+#[derive(Debug, Clone, PartialEq)]
+pub enum SpspHttpResponse {
+    /// Body of a successful response
+    Body(Bytes),
+    /// The `Location` of a redirect, which may be relative to the URL that was queried
+    Redirect(String),
+}
+
+/// HTTP client used to fetch SPSP responses.
+///
+/// Implemented for [`reqwest::Client`](../reqwest/struct.Client.html), which [`query`](./fn.query.html)
+/// uses by default. Implement it to send the queries through another HTTP stack, for example
+/// to use a proxy, custom TLS roots or timeouts.
+///
+/// The client should hand redirects back rather than follow them, so that
+/// [`query_with_client`](./fn.query_with_client.html) can check where they lead.
+#[async_trait]
+pub trait SpspHttpClient: Send + Sync {
+    /// Send a GET request for an SPSP response to the given URL, with the
+    /// `Accept: application/spsp4+json` header
+    async fn get_spsp(&self, url: &str) -> Result<SpspHttpResponse, Error>;
+}
+
+#[async_trait]
+impl SpspHttpClient for Client {
+    async fn get_spsp(&self, url: &str) -> Result<SpspHttpResponse, Error> {
+        let res = self
+            .get(url)
+            .header("Accept", "application/spsp4+json")
+            .send()
+            .map_err(|err| Error::HttpError(format!("Error querying SPSP receiver: {:?}", err)))
+            .await?;
+
+        if res.status().is_redirection() {
+            let location = res
+                .headers()
+                .get(LOCATION)
+                .and_then(|location| location.to_str().ok())
+                .ok_or_else(|| {
+                    Error::HttpError(format!(
+                        "SPSP receiver redirected with status {} but no valid Location",
+                        res.status()
+                    ))
+                })?;
+            return Ok(SpspHttpResponse::Redirect(location.to_string()));
+        }
+
+        let res = res
+            .error_for_status()
+            .map_err(|err| Error::HttpError(format!("Error querying SPSP receiver: {:?}", err)))?;
+
+        res.bytes()
+            .map_err(|err| Error::HttpError(format!("Error reading SPSP response: {:?}", err)))
+            .map_ok(SpspHttpResponse::Body)
+            .await
+    }
+}
+
+/// The client `query` and `pay` use, which leaves redirects to `query_with_client`
+fn default_client() -> Result<Client, Error> {
+    Client::builder()
+        .redirect(Policy::none())
+        .build()
+        .map_err(|err| Error::HttpError(format!("Error building HTTP client: {:?}", err)))
+}
+
 /// Get an ILP Address and shared secret by the receiver of this payment for this connection
 pub async fn query(server: &str) -> Result<SpspResponse, Error> {
-    let server = payment_pointer_to_url(server);
-    trace!("Querying receiver: {}", server);
-
-    let client = Client::new();
-    let res = client
-        .get(&server)
-        .header("Accept", "application/spsp4+json")
-        .send()
-        .map_err(|err| Error::HttpError(format!("Error querying SPSP receiver: {:?}", err)))
-        .await?;
-
-    let res = res
-        .error_for_status()
-        .map_err(|err| Error::HttpError(format!("Error querying SPSP receiver: {:?}", err)))?;
-
-    res.json::<SpspResponse>()
-        .map_err(|err| Error::InvalidSpspServerResponseError(format!("{:?}", err)))
-        .await
+    query_with_client(&default_client()?, server).await
+}
+
+/// Same as [`query`](./fn.query.html), but sends the request with the given HTTP client.
+///
+/// Follows up to 5 redirects, as long as they keep to the scheme of the original URL.
+pub async fn query_with_client<C: SpspHttpClient + ?Sized>(
+    client: &C,
+    server: &str,
+) -> Result<SpspResponse, Error> {
+    let mut url = payment_pointer_to_url(server)?;
+    let mut visited = vec![url.clone()];
+
+    loop {
+        trace!("Querying receiver: {}", url);
+        let location = match client.get_spsp(url.as_str()).await? {
+            SpspHttpResponse::Body(body) => {
+                return serde_json::from_slice::<SpspResponse>(&body)
+                    .map_err(|err| Error::InvalidSpspServerResponseError(format!("{:?}", err)));
+            }
+            SpspHttpResponse::Redirect(location) => location,
+        };
+
+        let next = url.join(&location).map_err(|err| {
+            Error::InvalidRedirect(url.to_string(), format!("{} ({})", location, err))
+        })?;
+        if next.scheme() != url.scheme() {
+            return Err(Error::InvalidRedirect(url.to_string(), next.to_string()));
+        }
+        if visited.contains(&next) {
+            return Err(Error::RedirectLoop(next.to_string()));
+        }
+        if visited.len() > MAX_REDIRECTS {
+            return Err(Error::TooManyRedirects(MAX_REDIRECTS));
+        }
+        debug!("SPSP receiver at {} redirected to {}", url, next);
+        visited.push(next.clone());
+        url = next;
+    }
 }
 
 /// Query the details of the given Payment Pointer and send a payment using the STREAM protocol.
@@ -44,7 +137,35 @@ where
     A: Account + Send + Sync + 'static,
     S: ExchangeRateStore + Send + Sync + 'static,
 {
-    let spsp = query(receiver).await?;
+    pay_with_client(
+        &default_client()?,
+        service,
+        from_account,
+        store,
+        receiver,
+        source_amount,
+        slippage,
+    )
+    .await
+}
+
+/// Same as [`pay`](./fn.pay.html), but queries the payment details with the given HTTP client
+pub async fn pay_with_client<C, I, A, S>(
+    client: &C,
+    service: I,
+    from_account: A,
+    store: S,
+    receiver: &str,
+    source_amount: u64,
+    slippage: f64,
+) -> Result<StreamDelivery, Error>
+where
+    C: SpspHttpClient + ?Sized,
+    I: IncomingService<A> + Clone + Send + Sync + 'static,
+    A: Account + Send + Sync + 'static,
+    S: ExchangeRateStore + Send + Sync + 'static,
+{
+    let spsp = query_with_client(client, receiver).await?;
     let shared_secret = spsp.shared_secret;
     let addr = spsp.destination_account;
     debug!("Sending SPSP payment to address: {}", addr);
@@ -56,7 +177,10 @@ where
         addr,
         shared_secret,
         source_amount,
-        slippage,
+        SendMoneyOptions {
+            slippage,
+            ..SendMoneyOptions::default()
+        },
     )
     .map_err(move |err| {
         error!("Error sending payment: {:?}", err);
@@ -68,41 +192,241 @@ where
     Ok(receipt)
 }
 
-fn payment_pointer_to_url(payment_pointer: &str) -> String {
-    let mut url: String = if let Some(suffix) = payment_pointer.strip_prefix('$') {
-        let prefix = "https://";
-        let mut url = String::with_capacity(prefix.len() + suffix.len());
-        url.push_str(prefix);
-        url.push_str(suffix);
-        url
-    } else {
-        payment_pointer.to_string()
+/// Expand a payment pointer into the URL of its SPSP receiver: `$example.com` becomes
+/// `https://example.com/.well-known/pay`, while `$example.com/alice` keeps its path and becomes
+/// `https://example.com/alice`. URLs are used as given, except that bare hosts get the
+/// well-known path too.
+fn payment_pointer_to_url(payment_pointer: &str) -> Result<Url, Error> {
+    let url = match payment_pointer.strip_prefix('$') {
+        Some(suffix) => format!("https://{}", suffix),
+        None => payment_pointer.to_string(),
     };
+    let mut url = Url::parse(&url).map_err(|err| {
+        Error::InvalidPaymentPointerError(format!("{} ({})", payment_pointer, err))
+    })?;
+    if url.cannot_be_a_base() || url.host().is_none() {
+        return Err(Error::InvalidPaymentPointerError(
+            payment_pointer.to_string(),
+        ));
+    }
 
-    let num_slashes = url.matches('/').count();
-    if num_slashes == 2 {
-        url.push_str("/.well-known/pay");
-    } else if num_slashes == 1 && url.ends_with('/') {
-        url.push_str(".well-known/pay");
+    if url.path() == "/" {
+        url.set_path("/.well-known/pay");
     }
     trace!(
         "Converted payment pointer: {} to URL: {}",
         payment_pointer,
         url
     );
-    url
+    Ok(url)
+}
+
+#[cfg(test)]
+mod query_with_client {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    const SPSP_RESPONSE: &str = r#"{"destination_account":"example.receiver.abc","shared_secret":"AQIDBAUGBwgJCgsMDQ4PEBESExQVFhcYGRobHB0eHyA="}"#;
+
+    /// Answers with canned responses by URL and keeps the URLs it was asked for
+    struct MockClient {
+        responses: HashMap<&'static str, SpspHttpResponse>,
+        urls: Mutex<Vec<String>>,
+    }
+
+    impl MockClient {
+        fn new(responses: Vec<(&'static str, SpspHttpResponse)>) -> Self {
+            MockClient {
+                responses: responses.into_iter().collect(),
+                urls: Mutex::new(Vec::new()),
+            }
+        }
+
+        fn urls(&self) -> Vec<String> {
+            self.urls.lock().unwrap().clone()
+        }
+    }
+
+    #[async_trait]
+    impl SpspHttpClient for MockClient {
+        async fn get_spsp(&self, url: &str) -> Result<SpspHttpResponse, Error> {
+            self.urls.lock().unwrap().push(url.to_string());
+            self.responses
+                .get(url)
+                .cloned()
+                .ok_or_else(|| Error::HttpError(format!("404 for {}", url)))
+        }
+    }
+
+    fn body(body: &'static str) -> SpspHttpResponse {
+        SpspHttpResponse::Body(Bytes::from_static(body.as_bytes()))
+    }
+
+    fn redirect(location: &str) -> SpspHttpResponse {
+        SpspHttpResponse::Redirect(location.to_string())
+    }
+
+    #[tokio::test]
+    async fn parses_response_from_client() {
+        let client = MockClient::new(vec![(
+            "https://receiver.example/alice",
+            body(SPSP_RESPONSE),
+        )]);
+
+        let response = query_with_client(&client, "$receiver.example/alice")
+            .await
+            .unwrap();
+        assert_eq!(
+            response.destination_account.to_string(),
+            "example.receiver.abc"
+        );
+        assert_eq!(response.shared_secret, (1..=32).collect::<Vec<u8>>());
+        assert_eq!(client.urls(), vec!["https://receiver.example/alice"]);
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_responses() {
+        let client = MockClient::new(vec![(
+            "https://receiver.example/.well-known/pay",
+            body(r#"{"destination_account":"example.receiver.abc"}"#),
+        )]);
+
+        let result = query_with_client(&client, "$receiver.example").await;
+        assert!(matches!(
+            result,
+            Err(Error::InvalidSpspServerResponseError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn follows_redirects() {
+        let client = MockClient::new(vec![
+            (
+                "https://receiver.example/.well-known/pay",
+                redirect("https://wallet.example/users/alice"),
+            ),
+            (
+                "https://wallet.example/users/alice",
+                redirect("/spsp/alice"),
+            ),
+            ("https://wallet.example/spsp/alice", body(SPSP_RESPONSE)),
+        ]);
+
+        let response = query_with_client(&client, "$receiver.example")
+            .await
+            .unwrap();
+        assert_eq!(
+            response.destination_account.to_string(),
+            "example.receiver.abc"
+        );
+        assert_eq!(
+            client.urls(),
+            vec![
+                "https://receiver.example/.well-known/pay",
+                "https://wallet.example/users/alice",
+                "https://wallet.example/spsp/alice",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn detects_redirect_loops() {
+        let client = MockClient::new(vec![
+            ("https://receiver.example/a", redirect("/b")),
+            ("https://receiver.example/b", redirect("/a")),
+        ]);
+
+        let result = query_with_client(&client, "$receiver.example/a").await;
+        assert!(
+            matches!(result, Err(Error::RedirectLoop(url)) if url == "https://receiver.example/a")
+        );
+        assert_eq!(client.urls().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn stops_after_max_redirects() {
+        let client = MockClient::new(vec![
+            ("https://receiver.example/1", redirect("/2")),
+            ("https://receiver.example/2", redirect("/3")),
+            ("https://receiver.example/3", redirect("/4")),
+            ("https://receiver.example/4", redirect("/5")),
+            ("https://receiver.example/5", redirect("/6")),
+            ("https://receiver.example/6", redirect("/7")),
+            ("https://receiver.example/7", body(SPSP_RESPONSE)),
+        ]);
+        let result = query_with_client(&client, "$receiver.example/1").await;
+        assert!(matches!(result, Err(Error::TooManyRedirects(5))));
+        assert_eq!(client.urls().len(), 6);
+
+        // Five hops are fine
+        let result = query_with_client(&client, "$receiver.example/2").await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn rejects_downgrades_to_http() {
+        let client = MockClient::new(vec![(
+            "https://receiver.example/.well-known/pay",
+            redirect("http://receiver.example/.well-known/pay"),
+        )]);
+
+        let result = query_with_client(&client, "$receiver.example").await;
+        assert!(matches!(result, Err(Error::InvalidRedirect(_, to)) if to.starts_with("http:")));
+        assert_eq!(client.urls().len(), 1);
+    }
 }
 
 #[cfg(test)]
 mod payment_pointer {
     use super::*;
 
+    fn url(pointer: &str) -> String {
+        payment_pointer_to_url(pointer).unwrap().to_string()
+    }
+
     #[test]
     fn converts_pointer() {
-        let pointer = "$subdomain.domain.example";
         assert_eq!(
-            payment_pointer_to_url(pointer),
+            url("$subdomain.domain.example"),
             "https://subdomain.domain.example/.well-known/pay"
         );
+        assert_eq!(
+            url("$domain.example/"),
+            "https://domain.example/.well-known/pay"
+        );
+    }
+
+    #[test]
+    fn keeps_pointer_paths() {
+        assert_eq!(url("$domain.example/alice"), "https://domain.example/alice");
+        assert_eq!(
+            url("$domain.example:8080/users/alice"),
+            "https://domain.example:8080/users/alice"
+        );
+    }
+
+    #[test]
+    fn uses_urls_as_given() {
+        assert_eq!(
+            url("http://localhost:7770/accounts/alice/spsp"),
+            "http://localhost:7770/accounts/alice/spsp"
+        );
+        assert_eq!(
+            url("http://localhost:7770"),
+            "http://localhost:7770/.well-known/pay"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_pointers() {
+        assert!(matches!(
+            payment_pointer_to_url("$"),
+            Err(Error::InvalidPaymentPointerError(_))
+        ));
+        assert!(matches!(
+            payment_pointer_to_url("alice"),
+            Err(Error::InvalidPaymentPointerError(_))
+        ));
     }
 }
@@ -6,7 +6,7 @@
 //! authenticate ILP packets sent between them. SPSP uses the STREAM transport protocol for sending money and data over ILP.
 
 use interledger_packet::Address;
-use interledger_stream::Error as StreamError;
+use interledger_stream::StreamError;
 use serde::{Deserialize, Serialize};
 
 /// An SPSP client which can query an SPSP Server's payment pointer and initiate a STREAM payment
@@ -14,7 +14,9 @@ mod client;
 /// An SPSP Server implementing an HTTP Service which generates ILP Addresses and Shared Secrets
 mod server;
 
-pub use client::{pay, query};
+pub use client::{
+    pay, pay_with_client, query, query_with_client, SpspHttpClient, SpspHttpResponse,
+};
 pub use server::SpspResponder;
 
 #[derive(Debug, thiserror::Error)]
@@ -31,6 +33,12 @@ pub enum Error {
     ListenError(String),
     #[error("Invalid Payment Pointer: {0}")]
     InvalidPaymentPointerError(String),
+    #[error("Refusing SPSP redirect from {0} to {1}")]
+    InvalidRedirect(String, String),
+    #[error("SPSP redirect loop at {0}")]
+    RedirectLoop(String),
+    #[error("More than {0} redirects querying SPSP receiver")]
+    TooManyRedirects(usize),
 }
 
 /// An SPSP Response returned by the SPSP server
@@ -22,6 +22,15 @@ impl SpspResponder {
     /// Constructs a new SPSP Responder by receiving an ILP Address and a server **secret**
     pub fn new(ilp_address: Address, server_secret: Bytes) -> Self {
         let connection_generator = ConnectionGenerator::new(server_secret);
+        SpspResponder::with_connection_generator(ilp_address, connection_generator)
+    }
+
+    /// Constructs a new SPSP Responder which generates the connections with the provided
+    /// generator, such as one [drawing from a custom RNG](../interledger_stream/struct.ConnectionGenerator.html#method.with_rng)
+    pub fn with_connection_generator(
+        ilp_address: Address,
+        connection_generator: ConnectionGenerator,
+    ) -> Self {
         SpspResponder {
             ilp_address,
             connection_generator,
@@ -1,13 +1,14 @@
 use super::crypto::{decrypt_token, encrypt_token};
 use interledger_api::AccountDetails;
 use interledger_btp::BtpAccount;
-use interledger_ccp::{CcpRoutingAccount, RoutingRelation};
+use interledger_ccp::{CcpRoutingAccount, RoutePolicy, RoutingRelation};
 use interledger_errors::CreateAccountError;
 use interledger_http::HttpAccount;
 use interledger_packet::Address;
 use interledger_service::{Account as AccountTrait, Username};
 use interledger_service_util::{
-    MaxPacketAmountAccount, RateLimitAccount, RoundTripTimeAccount, DEFAULT_ROUND_TRIP_TIME,
+    MaxPacketAmountAccount, MinExpiryWindowAccount, RateLimitAccount, RoundTripTimeAccount,
+    DEFAULT_ROUND_TRIP_TIME,
 };
 use interledger_settlement::core::types::{SettlementAccount, SettlementEngineDetails};
 use ring::aead;
@@ -15,6 +16,7 @@ use secrecy::{ExposeSecret, SecretBytesMut, SecretString};
 use serde::Serializer;
 use serde::{Deserialize, Serialize};
 use std::str::{self, FromStr};
+use std::time::Duration;
 use tracing::error;
 use url::Url;
 use uuid::Uuid;
@@ -42,6 +44,8 @@ pub struct Account {
     pub(crate) min_balance: Option<i64>,
     /// The account's ILP over HTTP URL (this is where packets are sent over HTTP from your node)
     pub(crate) ilp_over_http_url: Option<Url>,
+    /// Timeout of the ILP over HTTP requests sent to the account, in milliseconds
+    pub(crate) ilp_over_http_timeout: Option<u64>,
     #[serde(serialize_with = "optional_secret_bytes_to_utf8")]
     /// The account's API and incoming ILP over HTTP token.
     /// This must match the ILP over HTTP outgoing token on the peer's node if receiving
@@ -70,15 +74,25 @@ pub struct Account {
     pub(crate) settle_threshold: Option<i64>,
     /// The amount which the balance service will attempt to settle down to
     pub(crate) settle_to: Option<i64>,
+    /// Minimum time in milliseconds between two settlements triggered by the settle_threshold
+    pub(crate) min_settlement_interval: Option<u64>,
     /// The routing relation of the account
     pub(crate) routing_relation: RoutingRelation,
     /// The round trip time of the account (should be set depending on how
     /// well the network connectivity of the account and the node is)
     pub(crate) round_trip_time: u32,
+    /// The minimum time the account's incoming packets must have left before they expire
+    pub(crate) min_expiry_window: Option<u32>,
     /// The limit of packets the account can send per minute
     pub(crate) packets_per_minute_limit: Option<u32>,
     /// The maximum amount the account can send per minute
     pub(crate) amount_per_minute_limit: Option<u64>,
+    /// The rate at which the account's packet token bucket refills
+    pub(crate) packets_per_second_limit: Option<u32>,
+    /// The size of the account's packet token bucket
+    pub(crate) packet_burst_limit: Option<u32>,
+    /// The rules deciding which routes are advertised to the account
+    pub(crate) route_policy: Option<RoutePolicy>,
     /// The account's settlement engine URL. If a global engine url is configured
     /// for the account's asset code,  that will be used instead (even if the account is
     /// configured with a specific one)
@@ -153,6 +167,7 @@ impl Account {
             max_packet_amount: details.max_packet_amount,
             min_balance: details.min_balance,
             ilp_over_http_url,
+            ilp_over_http_timeout: details.ilp_over_http_timeout,
             ilp_over_http_incoming_token: details
                 .ilp_over_http_incoming_token
                 .map(|token| SecretBytesMut::new(token.expose_secret().as_str())),
@@ -167,11 +182,16 @@ impl Account {
                 .ilp_over_btp_outgoing_token
                 .map(|token| SecretBytesMut::new(token.expose_secret().as_str())),
             settle_to: details.settle_to,
+            min_settlement_interval: details.min_settlement_interval,
             settle_threshold: details.settle_threshold,
             routing_relation,
             round_trip_time: details.round_trip_time.unwrap_or(DEFAULT_ROUND_TRIP_TIME),
+            min_expiry_window: details.min_expiry_window,
             packets_per_minute_limit: details.packets_per_minute_limit,
             amount_per_minute_limit: details.amount_per_minute_limit,
+            packets_per_second_limit: details.packets_per_second_limit,
+            packet_burst_limit: details.packet_burst_limit,
+            route_policy: details.route_policy,
             settlement_engine_url,
         })
     }
@@ -296,6 +316,10 @@ impl HttpAccount for Account {
         self.ilp_over_http_url.as_ref()
     }
 
+    fn get_http_timeout(&self) -> Option<Duration> {
+        self.ilp_over_http_timeout.map(Duration::from_millis)
+    }
+
     fn get_http_auth_token(&self) -> Option<SecretString> {
         self.ilp_over_http_outgoing_token.as_ref().map(|s| {
             SecretString::new(
@@ -329,6 +353,10 @@ impl CcpRoutingAccount for Account {
     fn routing_relation(&self) -> RoutingRelation {
         self.routing_relation
     }
+
+    fn route_policy(&self) -> Option<&RoutePolicy> {
+        self.route_policy.as_ref()
+    }
 }
 
 impl RoundTripTimeAccount for Account {
@@ -337,6 +365,12 @@ impl RoundTripTimeAccount for Account {
     }
 }
 
+impl MinExpiryWindowAccount for Account {
+    fn min_expiry_window(&self) -> Option<u32> {
+        self.min_expiry_window
+    }
+}
+
 impl RateLimitAccount for Account {
     fn amount_per_minute_limit(&self) -> Option<u64> {
         self.amount_per_minute_limit
@@ -345,6 +379,14 @@ impl RateLimitAccount for Account {
     fn packets_per_minute_limit(&self) -> Option<u32> {
         self.packets_per_minute_limit
     }
+
+    fn packets_per_second_limit(&self) -> Option<u32> {
+        self.packets_per_second_limit
+    }
+
+    fn packet_burst_limit(&self) -> Option<u32> {
+        self.packet_burst_limit
+    }
 }
 
 impl SettlementAccount for Account {
@@ -353,6 +395,10 @@ impl SettlementAccount for Account {
             .as_ref()
             .map(|url| SettlementEngineDetails { url: url.clone() })
     }
+
+    fn min_settlement_interval(&self) -> Option<Duration> {
+        self.min_settlement_interval.map(Duration::from_millis)
+    }
 }
 
 #[cfg(test)]
@@ -370,6 +416,7 @@ mod test {
         min_balance: Some(-1000),
         // we are Bob and we're using this account to peer with Alice
         ilp_over_http_url: Some("http://example.com/accounts/bob/ilp".to_string()),
+        ilp_over_http_timeout: None,
         ilp_over_http_incoming_token: Some(SecretString::new("incoming_auth_token".to_string())),
         ilp_over_http_outgoing_token: Some(SecretString::new("outgoing_auth_token".to_string())),
         ilp_over_btp_url: Some("btp+ws://example.com/accounts/bob/ilp/btp".to_string()),
@@ -377,10 +424,15 @@ mod test {
         ilp_over_btp_outgoing_token: Some(SecretString::new("outgoing_btp_token".to_string())),
         settle_threshold: Some(0),
         settle_to: Some(-1000),
+        min_settlement_interval: None,
         routing_relation: Some("Peer".to_string()),
         round_trip_time: Some(600),
+        min_expiry_window: None,
         amount_per_minute_limit: None,
         packets_per_minute_limit: None,
+        packets_per_second_limit: None,
+        packet_burst_limit: None,
+        route_policy: None,
         settlement_engine_url: None,
     });
 
@@ -0,0 +1,1207 @@
+// The in-memory store keeps the same data as the Redis store (see the schema at the
+// top of redis/mod.rs), but in maps guarded by locks rather than in Redis keys.
+// Balance updates follow the logic of the Lua scripts in redis/lua.
+use super::account::Account;
+use async_trait::async_trait;
+use futures::channel::mpsc::UnboundedSender;
+use interledger_api::{AccountDetails, AccountPage, AccountQuery, AccountSettings, NodeStore};
+use interledger_btp::BtpStore;
+use interledger_ccp::{CcpRoutingAccount, CcpRoutingStore, RoutingRelation};
+use interledger_errors::*;
+use interledger_http::HttpStore;
+use interledger_packet::Address;
+use interledger_rates::ExchangeRateStore;
+use interledger_router::{RouterStore, WeightedRoute};
+use interledger_service::{Account as AccountTrait, AccountStore, AddressStore, Username};
+use interledger_service_util::BalanceStore;
+use interledger_settlement::core::types::{
+    PendingSettlement, PendingSettlementStore, SettlementStore,
+};
+use interledger_stream::{
+    AccountNotification, AccountNotificationsStore, PaymentNotification, StreamNotificationsStore,
+};
+use once_cell::sync::Lazy;
+use parking_lot::{Mutex, RwLock};
+use secrecy::{ExposeSecret, SecretBytesMut};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::TryFrom;
+use std::ops::Bound;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+use tracing::{debug, error, warn};
+use url::Url;
+use uuid::Uuid;
+
+/// The node's default ILP Address
+static DEFAULT_ILP_ADDRESS: Lazy<Address> = Lazy::new(|| Address::from_str("local.host").unwrap());
+
+/// Errors for balance updates the in-memory store cannot apply
+#[derive(Debug, thiserror::Error)]
+pub enum InMemoryBalanceError {
+    #[error("account `{0}` was not found")]
+    AccountNotFound(Uuid),
+    #[error("Incoming prepare of {amount} would bring account {account_id} under its minimum balance. Current balance: {balance}, min balance: {min_balance}")]
+    MinBalanceExceeded {
+        account_id: Uuid,
+        amount: u64,
+        balance: i64,
+        min_balance: i64,
+    },
+    #[error("Balance of account {0} would overflow")]
+    Overflow(Uuid),
+}
+
+impl From<InMemoryBalanceError> for BalanceStoreError {
+    fn from(src: InMemoryBalanceError) -> Self {
+        BalanceStoreError::Other(Box::new(src))
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Balance {
+    balance: i64,
+    prepaid_amount: i64,
+    /// When the balance was last settled, to space out threshold settlements
+    last_settlement: Option<Instant>,
+}
+
+impl Balance {
+    fn total(&self, account_id: Uuid) -> Result<i64, InMemoryBalanceError> {
+        self.balance
+            .checked_add(self.prepaid_amount)
+            .ok_or(InMemoryBalanceError::Overflow(account_id))
+    }
+}
+
+/// The routes the routing table is built from
+#[derive(Debug, Default)]
+struct RouteSources {
+    /// Routes to the local accounts, replaced by the ones learned over CCP
+    current: HashMap<String, Uuid>,
+    /// Routes configured through the API, which take precedence over the current ones
+    configured: HashMap<String, Uuid>,
+    default_route: Option<Uuid>,
+}
+
+impl RouteSources {
+    fn routing_table(&self) -> HashMap<String, Uuid> {
+        self.current
+            .iter()
+            .map(|(prefix, id)| (prefix.clone(), *id))
+            .chain(self.default_route.map(|id| (String::new(), id)))
+            .chain(
+                self.configured
+                    .iter()
+                    .map(|(prefix, id)| (prefix.clone(), *id)),
+            )
+            .collect()
+    }
+}
+
+/// A Store that keeps everything in memory, for tests and nodes that run in a single
+/// process. Nothing is persisted, so all accounts and balances are lost when it is dropped.
+#[derive(Clone)]
+pub struct InMemoryStore {
+    /// The Store's ILP Address
+    ilp_address: Arc<RwLock<Address>>,
+    /// Whether the ILP Address was assigned by a parent, in which case no other
+    /// parent account can be inserted
+    has_parent_address: Arc<AtomicBool>,
+    accounts: Arc<RwLock<HashMap<Uuid, Account>>>,
+    /// Account IDs by username, ordered so that queries can scan a range of usernames
+    usernames: Arc<RwLock<BTreeMap<String, Uuid>>>,
+    balances: Arc<Mutex<HashMap<Uuid, Balance>>>,
+    /// Idempotency keys of the incoming settlements that were already credited
+    settlement_idempotency_keys: Arc<Mutex<HashSet<String>>>,
+    /// Outgoing settlements not yet confirmed by the engine, by idempotency key
+    pending_settlements: Arc<Mutex<HashMap<String, PendingSettlement>>>,
+    /// Settlement engine URLs by asset code
+    settlement_engines: Arc<RwLock<HashMap<String, Url>>>,
+    exchange_rates: Arc<RwLock<HashMap<String, f64>>>,
+    route_sources: Arc<RwLock<RouteSources>>,
+    /// The routing table built from `route_sources`, kept behind an `Arc` so
+    /// that `routing_table` doesn't need to clone it
+    routes: Arc<RwLock<Arc<HashMap<String, Uuid>>>>,
+    /// Prefixes routed through several accounts
+    multipath_routes: Arc<RwLock<MultipathRoutingTable>>,
+    /// WebSocket senders which publish incoming payment updates
+    subscriptions: Arc<Mutex<HashMap<Uuid, Vec<UnboundedSender<PaymentNotification>>>>>,
+    /// A subscriber to all payment notifications, exposed via a WebSocket
+    payment_publisher: broadcast::Sender<PaymentNotification>,
+    /// WebSocket senders which publish all notifications concerning an account
+    account_subscriptions: Arc<Mutex<HashMap<Uuid, Vec<UnboundedSender<AccountNotification>>>>>,
+}
+
+impl Default for InMemoryStore {
+    fn default() -> Self {
+        InMemoryStore::new(DEFAULT_ILP_ADDRESS.clone())
+    }
+}
+
+impl InMemoryStore {
+    /// Creates an empty store for a node with the given ILP Address
+    pub fn new(node_ilp_address: Address) -> Self {
+        let (payment_publisher, _) = broadcast::channel::<PaymentNotification>(256);
+        InMemoryStore {
+            ilp_address: Arc::new(RwLock::new(node_ilp_address)),
+            has_parent_address: Arc::new(AtomicBool::new(false)),
+            accounts: Arc::new(RwLock::new(HashMap::new())),
+            usernames: Arc::new(RwLock::new(BTreeMap::new())),
+            balances: Arc::new(Mutex::new(HashMap::new())),
+            settlement_idempotency_keys: Arc::new(Mutex::new(HashSet::new())),
+            pending_settlements: Arc::new(Mutex::new(HashMap::new())),
+            settlement_engines: Arc::new(RwLock::new(HashMap::new())),
+            exchange_rates: Arc::new(RwLock::new(HashMap::new())),
+            route_sources: Arc::new(RwLock::new(RouteSources::default())),
+            routes: Arc::new(RwLock::new(Arc::new(HashMap::new()))),
+            multipath_routes: Arc::new(RwLock::new(Arc::new(HashMap::new()))),
+            subscriptions: Arc::new(Mutex::new(HashMap::new())),
+            payment_publisher,
+            account_subscriptions: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Rebuilds the routing table after one of its sources changed
+    fn update_routes(&self) {
+        let routes = self.route_sources.read().routing_table();
+        debug!("Routing table is: {:?}", routes);
+        *self.routes.write() = Arc::new(routes);
+    }
+
+    /// Returns the account as it is handed out by the store: if it does not have a
+    /// settlement engine URL but there is one configured for its currency, it uses that one
+    fn load_account(&self, account: &Account) -> Account {
+        let mut account = account.clone();
+        if account.settlement_engine_url.is_none() {
+            account.settlement_engine_url = self
+                .settlement_engines
+                .read()
+                .get(&account.asset_code)
+                .cloned();
+        }
+        account
+    }
+
+    fn load_account_by_username(&self, username: &Username) -> Option<Account> {
+        let id = *self.usernames.read().get(username.as_ref())?;
+        self.accounts
+            .read()
+            .get(&id)
+            .map(|account| self.load_account(account))
+    }
+
+    fn account_exists(&self, id: Uuid) -> bool {
+        self.accounts.read().contains_key(&id)
+    }
+
+    /// Applies `update` to the account's balance, leaving it unchanged if `update` fails
+    fn update_balance<T>(
+        &self,
+        account_id: Uuid,
+        update: impl FnOnce(&mut Balance) -> Result<T, InMemoryBalanceError>,
+    ) -> Result<T, InMemoryBalanceError> {
+        let mut balances = self.balances.lock();
+        let balance = balances
+            .get_mut(&account_id)
+            .ok_or(InMemoryBalanceError::AccountNotFound(account_id))?;
+        let mut updated = *balance;
+        let result = update(&mut updated)?;
+        *balance = updated;
+        Ok(result)
+    }
+
+    /// Returns the settlement thresholds of the account,
+    /// `(settle_threshold, settle_to, min_settlement_interval)`
+    fn settlement_thresholds(
+        &self,
+        account_id: Uuid,
+    ) -> (Option<i64>, Option<i64>, Option<Duration>) {
+        self.accounts
+            .read()
+            .get(&account_id)
+            .map(|account| {
+                (
+                    account.settle_threshold,
+                    account.settle_to,
+                    account.min_settlement_interval.map(Duration::from_millis),
+                )
+            })
+            .unwrap_or((None, None, None))
+    }
+}
+
+#[async_trait]
+impl AccountStore for InMemoryStore {
+    type Account = Account;
+
+    async fn get_accounts(
+        &self,
+        account_ids: Vec<Uuid>,
+    ) -> Result<Vec<Account>, AccountStoreError> {
+        let accounts: Vec<Account> = {
+            let stored = self.accounts.read();
+            account_ids
+                .iter()
+                .filter_map(|id| stored.get(id))
+                .map(|account| self.load_account(account))
+                .collect()
+        };
+        if accounts.len() == account_ids.len() {
+            Ok(accounts)
+        } else {
+            Err(AccountStoreError::WrongLength {
+                expected: account_ids.len(),
+                actual: accounts.len(),
+            })
+        }
+    }
+
+    async fn get_account_id_from_username(
+        &self,
+        username: &Username,
+    ) -> Result<Uuid, AccountStoreError> {
+        match self.usernames.read().get(username.as_ref()) {
+            Some(id) => Ok(*id),
+            None => {
+                debug!("Username not found: {}", username);
+                Err(AccountStoreError::AccountNotFound(username.to_string()))
+            }
+        }
+    }
+}
+
+impl StreamNotificationsStore for InMemoryStore {
+    type Account = Account;
+
+    fn add_payment_notification_subscription(
+        &self,
+        id: Uuid,
+        sender: UnboundedSender<PaymentNotification>,
+    ) {
+        debug!("Added payment notification listener for {}", id);
+        self.subscriptions
+            .lock()
+            .entry(id)
+            .or_default()
+            .push(sender);
+    }
+
+    fn publish_payment_notification(&self, payment: PaymentNotification) {
+        let account_id = match self.usernames.read().get(payment.to_username.as_ref()) {
+            Some(id) => *id,
+            None => {
+                error!(
+                    "Failed to find account ID corresponding to username: {}",
+                    payment.to_username
+                );
+                return;
+            }
+        };
+        debug!(
+            "Publishing payment notification {:?} for account {}",
+            payment, account_id
+        );
+        if self.payment_publisher.receiver_count() > 0 {
+            if let Err(err) = self.payment_publisher.send(payment.clone()) {
+                error!("Failed to send a node-wide payment notification: {:?}", err);
+            }
+        }
+        if let Some(senders) = self.subscriptions.lock().get_mut(&account_id) {
+            senders.retain(|sender| {
+                if let Err(err) = sender.unbounded_send(payment.clone()) {
+                    debug!("Failed to send message: {}", err);
+                    false
+                } else {
+                    true
+                }
+            });
+        }
+        self.publish_account_notification(account_id, AccountNotification::Payment(payment));
+    }
+
+    fn all_payment_subscription(&self) -> broadcast::Receiver<PaymentNotification> {
+        self.payment_publisher.subscribe()
+    }
+}
+
+impl AccountNotificationsStore for InMemoryStore {
+    fn add_account_notification_subscription(
+        &self,
+        id: Uuid,
+        sender: UnboundedSender<AccountNotification>,
+    ) {
+        debug!("Added account notification listener for {}", id);
+        self.account_subscriptions
+            .lock()
+            .entry(id)
+            .or_default()
+            .push(sender);
+    }
+
+    fn publish_account_notification(&self, account_id: Uuid, notification: AccountNotification) {
+        if let Some(senders) = self.account_subscriptions.lock().get_mut(&account_id) {
+            senders.retain(|sender| {
+                if let Err(err) = sender.unbounded_send(notification.clone()) {
+                    debug!("Failed to send message: {}", err);
+                    false
+                } else {
+                    true
+                }
+            });
+        }
+    }
+}
+
+#[async_trait]
+impl BalanceStore for InMemoryStore {
+    /// Returns the balance **from the account holder's perspective**, meaning the sum of
+    /// the Payable Balance and Pending Outgoing minus the Receivable Balance and the Pending Incoming.
+    async fn get_balance(&self, account_id: Uuid) -> Result<i64, BalanceStoreError> {
+        let balance = self
+            .balances
+            .lock()
+            .get(&account_id)
+            .copied()
+            .ok_or(InMemoryBalanceError::AccountNotFound(account_id))?;
+        Ok(balance.total(account_id)?)
+    }
+
+    async fn update_balances_for_prepare(
+        &self,
+        from_account_id: Uuid,
+        incoming_amount: u64,
+    ) -> Result<(), BalanceStoreError> {
+        // Don't do anything if the amount was 0
+        if incoming_amount == 0 {
+            return Ok(());
+        }
+
+        let min_balance = self
+            .accounts
+            .read()
+            .get(&from_account_id)
+            .and_then(|account| account.min_balance);
+        let balance = self.update_balance(from_account_id, |balance| {
+            // Check that the prepare wouldn't go under the account's minimum balance
+            if let Some(min_balance) = min_balance {
+                let total = i128::from(balance.balance) + i128::from(balance.prepaid_amount);
+                if total - i128::from(incoming_amount) < i128::from(min_balance) {
+                    return Err(InMemoryBalanceError::MinBalanceExceeded {
+                        account_id: from_account_id,
+                        amount: incoming_amount,
+                        balance: balance.balance,
+                        min_balance,
+                    });
+                }
+            }
+
+            // Deduct the amount from the prepaid amount and/or the balance
+            let overflow = || InMemoryBalanceError::Overflow(from_account_id);
+            let amount = i64::try_from(incoming_amount).map_err(|_| overflow())?;
+            if balance.prepaid_amount >= amount {
+                balance.prepaid_amount -= amount;
+            } else if balance.prepaid_amount > 0 {
+                let sub_from_balance = amount - balance.prepaid_amount;
+                balance.prepaid_amount = 0;
+                balance.balance = balance
+                    .balance
+                    .checked_sub(sub_from_balance)
+                    .ok_or_else(overflow)?;
+            } else {
+                balance.balance = balance.balance.checked_sub(amount).ok_or_else(overflow)?;
+            }
+            balance.total(from_account_id)
+        })?;
+
+        debug!(
+            "Processed prepare with incoming amount: {}. Account {} has balance (including prepaid amount): {} ",
+            incoming_amount, from_account_id, balance
+        );
+        self.publish_account_notification(
+            from_account_id,
+            AccountNotification::balance_change(from_account_id, balance),
+        );
+        Ok(())
+    }
+
+    async fn update_balances_for_fulfill(
+        &self,
+        to_account_id: Uuid,
+        outgoing_amount: u64,
+    ) -> Result<(i64, u64), BalanceStoreError> {
+        let (settle_threshold, settle_to, min_interval) = self.settlement_thresholds(to_account_id);
+        let (balance, amount_to_settle) = self.update_balance(to_account_id, |balance| {
+            let overflow = || InMemoryBalanceError::Overflow(to_account_id);
+            let amount = i64::try_from(outgoing_amount).map_err(|_| overflow())?;
+            balance.balance = balance.balance.checked_add(amount).ok_or_else(overflow)?;
+
+            // Settle down to settle_to once the balance reaches the settle_threshold,
+            // updating the balance before the settlement is sent so that the same
+            // balance doesn't get settled twice. Within the min settlement interval
+            // of the last settlement the balance is left to grow instead
+            let mut settle_amount = 0;
+            if let (Some(settle_threshold), Some(settle_to)) = (settle_threshold, settle_to) {
+                let now = Instant::now();
+                let debounced = match (min_interval, balance.last_settlement) {
+                    (Some(min_interval), Some(last)) => now.duration_since(last) < min_interval,
+                    _ => false,
+                };
+                if balance.balance >= settle_threshold && settle_threshold > settle_to && !debounced
+                {
+                    settle_amount = (i128::from(balance.balance) - i128::from(settle_to)) as u64;
+                    balance.balance = settle_to;
+                    balance.last_settlement = Some(now);
+                }
+            }
+            Ok((balance.total(to_account_id)?, settle_amount))
+        })?;
+
+        debug!(
+            "Processed fulfill for account {} for outgoing amount {}. Fulfill call result: {} {}",
+            to_account_id, outgoing_amount, balance, amount_to_settle,
+        );
+        self.publish_account_notification(
+            to_account_id,
+            AccountNotification::balance_change(to_account_id, balance),
+        );
+        Ok((balance, amount_to_settle))
+    }
+
+    async fn update_balances_for_reject(
+        &self,
+        from_account_id: Uuid,
+        incoming_amount: u64,
+    ) -> Result<(), BalanceStoreError> {
+        if incoming_amount == 0 {
+            return Ok(());
+        }
+
+        let balance = self.update_balance(from_account_id, |balance| {
+            let overflow = || InMemoryBalanceError::Overflow(from_account_id);
+            let amount = i64::try_from(incoming_amount).map_err(|_| overflow())?;
+            balance.balance = balance.balance.checked_add(amount).ok_or_else(overflow)?;
+            balance.total(from_account_id)
+        })?;
+
+        debug!(
+            "Processed reject for incoming amount: {}. Account {} has balance (including prepaid amount): {}",
+            incoming_amount, from_account_id, balance
+        );
+        self.publish_account_notification(
+            from_account_id,
+            AccountNotification::balance_change(from_account_id, balance),
+        );
+        Ok(())
+    }
+
+    async fn update_balances_for_delayed_settlement(
+        &self,
+        to_account_id: Uuid,
+    ) -> Result<(i64, u64), BalanceStoreError> {
+        let (settle_threshold, settle_to, _) = self.settlement_thresholds(to_account_id);
+        let (balance, amount_to_settle) = self.update_balance(to_account_id, |balance| {
+            let mut settle_amount = 0;
+            if let (Some(settle_threshold), Some(settle_to)) = (settle_threshold, settle_to) {
+                if settle_threshold > settle_to && balance.balance >= settle_to {
+                    settle_amount = (i128::from(balance.balance) - i128::from(settle_to)) as u64;
+                    balance.balance = settle_to;
+                    if settle_amount > 0 {
+                        balance.last_settlement = Some(Instant::now());
+                    }
+                }
+            }
+            Ok((balance.total(to_account_id)?, settle_amount))
+        })?;
+
+        debug!(
+            "Processed account {} for delayed settlement, balance: {}, to_settle: {}",
+            to_account_id, balance, amount_to_settle
+        );
+        if amount_to_settle > 0 {
+            self.publish_account_notification(
+                to_account_id,
+                AccountNotification::balance_change(to_account_id, balance),
+            );
+        }
+        Ok((balance, amount_to_settle))
+    }
+}
+
+#[async_trait]
+impl SettlementStore for InMemoryStore {
+    type Account = Account;
+
+    async fn update_balance_for_incoming_settlement(
+        &self,
+        account_id: Uuid,
+        amount: u64,
+        idempotency_key: Option<String>,
+    ) -> Result<(), SettlementStoreError> {
+        if let Some(idempotency_key) = idempotency_key {
+            // If the idempotency key has been used, then do not perform any operations
+            if !self
+                .settlement_idempotency_keys
+                .lock()
+                .insert(idempotency_key)
+            {
+                return Ok(());
+            }
+        }
+
+        let balance = self
+            .update_balance(account_id, |balance| {
+                let overflow = || InMemoryBalanceError::Overflow(account_id);
+                let amount = i64::try_from(amount).map_err(|_| overflow())?;
+                // Credit the incoming settlement to the balance and/or prepaid amount,
+                // depending on whether that account currently owes money or not
+                if balance.balance >= 0 {
+                    balance.prepaid_amount = balance
+                        .prepaid_amount
+                        .checked_add(amount)
+                        .ok_or_else(overflow)?;
+                } else if -i128::from(balance.balance) >= i128::from(amount) {
+                    balance.balance += amount;
+                } else {
+                    balance.prepaid_amount = balance
+                        .prepaid_amount
+                        .checked_add(amount + balance.balance)
+                        .ok_or_else(overflow)?;
+                    balance.balance = 0;
+                }
+                balance.total(account_id)
+            })
+            .map_err(|err| SettlementStoreError::Other(Box::new(err)))?;
+
+        debug!(
+            "Processed incoming settlement from account: {} for amount: {}. Balance is now: {}",
+            account_id, amount, balance
+        );
+        self.publish_account_notification(
+            account_id,
+            AccountNotification::incoming_settlement(account_id, amount, balance),
+        );
+        Ok(())
+    }
+
+    async fn refund_settlement(
+        &self,
+        account_id: Uuid,
+        settle_amount: u64,
+    ) -> Result<(), SettlementStoreError> {
+        debug!(
+            "Refunding settlement for account: {} of amount: {}",
+            account_id, settle_amount
+        );
+        let balance = self
+            .update_balance(account_id, |balance| {
+                let overflow = || InMemoryBalanceError::Overflow(account_id);
+                let amount = i64::try_from(settle_amount).map_err(|_| overflow())?;
+                balance.balance = balance.balance.checked_add(amount).ok_or_else(overflow)?;
+                Ok(balance.balance)
+            })
+            .map_err(|err| SettlementStoreError::Other(Box::new(err)))?;
+
+        debug!(
+            "Refunded settlement for account: {} of amount: {}. Balance is now: {}",
+            account_id, settle_amount, balance
+        );
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl PendingSettlementStore for InMemoryStore {
+    async fn save_pending_settlement(
+        &self,
+        settlement: PendingSettlement,
+    ) -> Result<(), SettlementStoreError> {
+        self.pending_settlements
+            .lock()
+            .insert(settlement.idempotency_key.clone(), settlement);
+        Ok(())
+    }
+
+    async fn clear_pending_settlement(
+        &self,
+        idempotency_key: &str,
+    ) -> Result<bool, SettlementStoreError> {
+        Ok(self
+            .pending_settlements
+            .lock()
+            .remove(idempotency_key)
+            .is_some())
+    }
+
+    async fn load_pending_settlements(
+        &self,
+    ) -> Result<Vec<PendingSettlement>, SettlementStoreError> {
+        Ok(self.pending_settlements.lock().values().cloned().collect())
+    }
+}
+
+impl ExchangeRateStore for InMemoryStore {
+    fn get_exchange_rates(&self, asset_codes: &[&str]) -> Result<Vec<f64>, ExchangeRateStoreError> {
+        let rates: Vec<f64> = asset_codes
+            .iter()
+            .filter_map(|code| (*self.exchange_rates.read()).get(*code).cloned())
+            .collect();
+        if rates.len() == asset_codes.len() {
+            Ok(rates)
+        } else {
+            Err(ExchangeRateStoreError::PairNotFound {
+                from: asset_codes[0].to_string(),
+                to: asset_codes[1].to_string(),
+            })
+        }
+    }
+
+    fn get_all_exchange_rates(&self) -> Result<HashMap<String, f64>, ExchangeRateStoreError> {
+        Ok((*self.exchange_rates.read()).clone())
+    }
+
+    fn set_exchange_rates(
+        &self,
+        rates: HashMap<String, f64>,
+    ) -> Result<(), ExchangeRateStoreError> {
+        (*self.exchange_rates.write()) = rates;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl BtpStore for InMemoryStore {
+    type Account = Account;
+
+    async fn get_account_from_btp_auth(
+        &self,
+        username: &Username,
+        token: &str,
+    ) -> Result<Self::Account, BtpStoreError> {
+        let account = match self.load_account_by_username(username) {
+            Some(account) => account,
+            None => {
+                warn!("No account found with BTP token");
+                return Err(BtpStoreError::AccountNotFound(username.to_string()));
+            }
+        };
+        match account.ilp_over_btp_incoming_token {
+            Some(ref t) if t.expose_secret().as_ref() == token.as_bytes() => Ok(account),
+            Some(_) => {
+                debug!(
+                    "Found account {} but BTP auth token was wrong",
+                    account.username
+                );
+                Err(BtpStoreError::Unauthorized(username.to_string()))
+            }
+            None => {
+                debug!(
+                    "Account {} does not have an incoming btp token configured",
+                    account.username
+                );
+                Err(BtpStoreError::Unauthorized(username.to_string()))
+            }
+        }
+    }
+
+    async fn get_btp_outgoing_accounts(&self) -> Result<Vec<Self::Account>, BtpStoreError> {
+        Ok(self
+            .accounts
+            .read()
+            .values()
+            .filter(|account| account.ilp_over_btp_url.is_some())
+            .map(|account| self.load_account(account))
+            .collect())
+    }
+}
+
+#[async_trait]
+impl HttpStore for InMemoryStore {
+    type Account = Account;
+
+    /// Checks if the stored token for the provided account id matches the
+    /// provided token, and if so, returns the account associated with that token
+    async fn get_account_from_http_auth(
+        &self,
+        username: &Username,
+        token: &str,
+    ) -> Result<Self::Account, HttpStoreError> {
+        let account = match self.load_account_by_username(username) {
+            Some(account) => account,
+            None => {
+                warn!("No account found with given HTTP auth");
+                return Err(HttpStoreError::AccountNotFound(username.to_string()));
+            }
+        };
+        match account.ilp_over_http_incoming_token {
+            Some(ref t) if t.expose_secret().as_ref() == token.as_bytes() => Ok(account),
+            _ => Err(HttpStoreError::Unauthorized(username.to_string())),
+        }
+    }
+}
+
+impl RouterStore for InMemoryStore {
+    fn routing_table(&self) -> Arc<HashMap<String, Uuid>> {
+        self.routes.read().clone()
+    }
+
+    fn multipath_routing_table(&self) -> MultipathRoutingTable {
+        self.multipath_routes.read().clone()
+    }
+}
+
+#[async_trait]
+impl NodeStore for InMemoryStore {
+    type Account = Account;
+
+    async fn insert_account(
+        &self,
+        account: AccountDetails,
+    ) -> Result<Self::Account, NodeStoreError> {
+        let id = Uuid::new_v4();
+        let account = Account::try_from(id, account, self.get_ilp_address())
+            .map_err(NodeStoreError::InvalidAccount)?;
+        debug!(
+            "Generated account id for {}: {}",
+            account.username, account.id
+        );
+
+        {
+            let mut accounts = self.accounts.write();
+            let mut usernames = self.usernames.write();
+            // Check that there isn't already an account with values that MUST be unique
+            if usernames.contains_key(account.username.as_ref())
+                || (account.routing_relation == RoutingRelation::Parent
+                    && self.has_parent_address.load(Ordering::SeqCst))
+            {
+                warn!(
+                    "An account already exists with the same {}. Cannot insert account: {:?}",
+                    account.id, account
+                );
+                return Err(NodeStoreError::AccountExists(account.username.to_string()));
+            }
+            usernames.insert(account.username.to_string(), id);
+            accounts.insert(id, account.clone());
+            self.balances.lock().insert(id, Balance::default());
+            self.route_sources
+                .write()
+                .current
+                .insert(account.ilp_address.to_string(), id);
+        }
+        self.update_routes();
+
+        debug!(
+            "Inserted account {} (ILP address: {})",
+            account.id, account.ilp_address
+        );
+        Ok(account)
+    }
+
+    async fn delete_account(&self, id: Uuid) -> Result<Account, NodeStoreError> {
+        let account = {
+            let mut accounts = self.accounts.write();
+            let account = accounts
+                .remove(&id)
+                .ok_or_else(|| NodeStoreError::AccountNotFound(id.to_string()))?;
+            self.usernames.write().remove(account.username.as_ref());
+            self.balances.lock().remove(&id);
+            self.route_sources
+                .write()
+                .current
+                .remove(&account.ilp_address.to_string());
+            account
+        };
+        self.update_routes();
+        debug!("Deleted account {}", account.id);
+        Ok(self.load_account(&account))
+    }
+
+    async fn update_account(
+        &self,
+        id: Uuid,
+        account: AccountDetails,
+    ) -> Result<Self::Account, NodeStoreError> {
+        let account = Account::try_from(id, account, self.get_ilp_address())
+            .map_err(NodeStoreError::InvalidAccount)?;
+
+        {
+            let mut accounts = self.accounts.write();
+            let previous = match accounts.get(&id) {
+                Some(previous) => previous.clone(),
+                None => {
+                    warn!(
+                        "No account exists with ID {}, cannot update account {:?}",
+                        account.id, account
+                    );
+                    return Err(NodeStoreError::AccountNotFound(account.id.to_string()));
+                }
+            };
+            let mut usernames = self.usernames.write();
+            if previous.username != account.username {
+                if usernames.contains_key(account.username.as_ref()) {
+                    return Err(NodeStoreError::AccountExists(account.username.to_string()));
+                }
+                usernames.remove(previous.username.as_ref());
+                usernames.insert(account.username.to_string(), id);
+            }
+            let mut route_sources = self.route_sources.write();
+            route_sources
+                .current
+                .remove(&previous.ilp_address.to_string());
+            route_sources
+                .current
+                .insert(account.ilp_address.to_string(), id);
+            accounts.insert(id, account.clone());
+        }
+        self.update_routes();
+
+        debug!(
+            "Updated account {} (id: {}, ILP address: {})",
+            account.username, account.id, account.ilp_address
+        );
+        Ok(account)
+    }
+
+    async fn modify_account_settings(
+        &self,
+        id: Uuid,
+        settings: AccountSettings,
+    ) -> Result<Self::Account, NodeStoreError> {
+        let mut accounts = self.accounts.write();
+        let account = accounts
+            .get_mut(&id)
+            .ok_or_else(|| NodeStoreError::AccountNotFound(id.to_string()))?;
+
+        // Validate everything before changing anything
+        let ilp_over_btp_url = settings
+            .ilp_over_btp_url
+            .map(|url| Url::parse(&url))
+            .transpose()
+            .map_err(|err| {
+                NodeStoreError::InvalidAccount(CreateAccountError::InvalidBtpUrl(err))
+            })?;
+        let ilp_over_http_url = settings
+            .ilp_over_http_url
+            .map(|url| Url::parse(&url))
+            .transpose()
+            .map_err(|err| {
+                NodeStoreError::InvalidAccount(CreateAccountError::InvalidHttpUrl(err))
+            })?;
+        let settle_to = settings
+            .settle_to
+            .map(i64::try_from)
+            .transpose()
+            .map_err(|_| {
+                NodeStoreError::InvalidAccount(CreateAccountError::ParamTooLarge(
+                    "settle_to".to_owned(),
+                ))
+            })?;
+
+        let to_secret =
+            |token: secrecy::SecretString| SecretBytesMut::new(token.expose_secret().as_str());
+        if let Some(url) = ilp_over_btp_url {
+            account.ilp_over_btp_url = Some(url);
+        }
+        if let Some(url) = ilp_over_http_url {
+            account.ilp_over_http_url = Some(url);
+        }
+        if let Some(token) = settings.ilp_over_btp_outgoing_token {
+            account.ilp_over_btp_outgoing_token = Some(to_secret(token));
+        }
+        if let Some(token) = settings.ilp_over_http_outgoing_token {
+            account.ilp_over_http_outgoing_token = Some(to_secret(token));
+        }
+        if let Some(token) = settings.ilp_over_btp_incoming_token {
+            account.ilp_over_btp_incoming_token = Some(to_secret(token));
+        }
+        if let Some(token) = settings.ilp_over_http_incoming_token {
+            account.ilp_over_http_incoming_token = Some(to_secret(token));
+        }
+        if let Some(settle_threshold) = settings.settle_threshold {
+            account.settle_threshold = Some(settle_threshold);
+        }
+        if let Some(settle_to) = settle_to {
+            account.settle_to = Some(settle_to);
+        }
+        if let Some(interval) = settings.min_settlement_interval {
+            account.min_settlement_interval = Some(interval);
+        }
+
+        Ok(self.load_account(account))
+    }
+
+    async fn check_connection(&self) -> Result<(), NodeStoreError> {
+        Ok(())
+    }
+
+    async fn get_all_accounts(&self) -> Result<Vec<Self::Account>, NodeStoreError> {
+        Ok(self
+            .accounts
+            .read()
+            .values()
+            .map(|account| self.load_account(account))
+            .collect())
+    }
+
+    async fn query_accounts(
+        &self,
+        query: AccountQuery,
+    ) -> Result<AccountPage<Self::Account>, NodeStoreError> {
+        let start = match query.scan_start() {
+            Some((username, true)) => Bound::Included(username),
+            Some((username, false)) => Bound::Excluded(username),
+            None => Bound::Unbounded,
+        };
+        let page_size = query.page_size();
+        let usernames = self.usernames.read();
+        let accounts = self.accounts.read();
+        let matching = usernames
+            .range::<str, _>((start, Bound::Unbounded))
+            .take_while(|(username, _)| query.in_scan_range(username))
+            .filter_map(|(_, id)| accounts.get(id))
+            .filter(|account| query.matches(*account))
+            .take(page_size + 1)
+            .map(|account| self.load_account(account))
+            .collect();
+        Ok(AccountPage::new(matching, page_size))
+    }
+
+    async fn set_static_routes<R>(&self, routes: R) -> Result<(), NodeStoreError>
+    where
+        R: IntoIterator<Item = (String, Uuid)> + Send + 'async_trait,
+    {
+        let routes: HashMap<String, Uuid> = routes.into_iter().collect();
+        if !routes.values().all(|id| self.account_exists(*id)) {
+            error!("Error setting static routes because not all of the given accounts exist");
+            return Err(NodeStoreError::MissingAccounts);
+        }
+        self.route_sources.write().configured = routes;
+        self.update_routes();
+        Ok(())
+    }
+
+    async fn set_static_route(
+        &self,
+        prefix: String,
+        account_id: Uuid,
+    ) -> Result<(), NodeStoreError> {
+        if !self.account_exists(account_id) {
+            error!(
+                "Cannot set static route for prefix: {} because account {} does not exist",
+                prefix, account_id
+            );
+            return Err(NodeStoreError::AccountNotFound(account_id.to_string()));
+        }
+        self.route_sources
+            .write()
+            .configured
+            .insert(prefix, account_id);
+        self.update_routes();
+        Ok(())
+    }
+
+    async fn set_multipath_route(
+        &self,
+        prefix: String,
+        routes: Vec<WeightedRoute>,
+    ) -> Result<(), NodeStoreError> {
+        if !routes
+            .iter()
+            .all(|route| self.account_exists(route.account_id))
+        {
+            error!(
+                "Cannot set multipath route for prefix: {} because not all of the given accounts exist",
+                prefix
+            );
+            return Err(NodeStoreError::MissingAccounts);
+        }
+        let mut multipath_routes = self.multipath_routes.write();
+        let mut updated = HashMap::clone(&multipath_routes);
+        if routes.is_empty() {
+            updated.remove(&prefix);
+        } else {
+            updated.insert(prefix, routes);
+        }
+        debug!("Multipath routing table is: {:?}", updated);
+        *multipath_routes = Arc::new(updated);
+        Ok(())
+    }
+
+    async fn set_default_route(&self, account_id: Uuid) -> Result<(), NodeStoreError> {
+        if !self.account_exists(account_id) {
+            error!(
+                "Cannot set default route because account {} does not exist",
+                account_id
+            );
+            return Err(NodeStoreError::AccountNotFound(account_id.to_string()));
+        }
+        self.route_sources.write().default_route = Some(account_id);
+        debug!("Set default route to account id: {}", account_id);
+        self.update_routes();
+        Ok(())
+    }
+
+    async fn set_settlement_engines(
+        &self,
+        asset_to_url_map: impl IntoIterator<Item = (String, Url)> + Send + 'async_trait,
+    ) -> Result<(), NodeStoreError> {
+        let mut settlement_engines = self.settlement_engines.write();
+        for (asset_code, url) in asset_to_url_map {
+            debug!("Setting settlement engine for {} to {}", asset_code, url);
+            settlement_engines.insert(asset_code, url);
+        }
+        Ok(())
+    }
+
+    async fn get_asset_settlement_engine(
+        &self,
+        asset_code: &str,
+    ) -> Result<Option<Url>, NodeStoreError> {
+        Ok(self.settlement_engines.read().get(asset_code).cloned())
+    }
+
+    async fn get_settlement_engines(&self) -> Result<HashMap<String, Url>, NodeStoreError> {
+        Ok(self.settlement_engines.read().clone())
+    }
+}
+
+#[async_trait]
+impl AddressStore for InMemoryStore {
+    // Updates the ILP address of the store & iterates over all children and
+    // updates their ILP Address to match the new address.
+    async fn set_ilp_address(&self, ilp_address: Address) -> Result<(), AddressStoreError> {
+        debug!("Setting ILP address to: {}", ilp_address);
+        *self.ilp_address.write() = ilp_address.clone();
+        self.has_parent_address.store(true, Ordering::SeqCst);
+
+        let last_segment = ilp_address
+            .segments()
+            .next_back()
+            .expect("address did not have a first segment, this should be impossible");
+        {
+            let mut accounts = self.accounts.write();
+            let mut route_sources = self.route_sources.write();
+            for account in accounts.values_mut() {
+                // Update the address and routes of all children and non-routing accounts.
+                if account.routing_relation() != RoutingRelation::Parent
+                    && account.routing_relation() != RoutingRelation::Peer
+                {
+                    route_sources
+                        .current
+                        .remove(&account.ilp_address.to_string());
+                    // if the username of the account ends with the
+                    // node's address, we're already configured so no
+                    // need to append anything.
+                    account.ilp_address = if last_segment == account.username().to_string() {
+                        ilp_address.clone()
+                    } else {
+                        ilp_address
+                            .with_suffix(account.username().as_bytes())
+                            .unwrap()
+                    };
+                    route_sources
+                        .current
+                        .insert(account.ilp_address.to_string(), account.id);
+                }
+            }
+        }
+        self.update_routes();
+        Ok(())
+    }
+
+    async fn clear_ilp_address(&self) -> Result<(), AddressStoreError> {
+        self.has_parent_address.store(false, Ordering::SeqCst);
+        // overwrite the ilp address with the default value
+        *self.ilp_address.write() = DEFAULT_ILP_ADDRESS.clone();
+        Ok(())
+    }
+
+    fn get_ilp_address(&self) -> Address {
+        self.ilp_address.read().clone()
+    }
+}
+
+type RoutingTable<A> = HashMap<String, A>;
+type MultipathRoutingTable = Arc<HashMap<String, Vec<WeightedRoute>>>;
+
+#[async_trait]
+impl CcpRoutingStore for InMemoryStore {
+    type Account = Account;
+
+    async fn get_accounts_to_send_routes_to(
+        &self,
+        ignore_accounts: Vec<Uuid>,
+    ) -> Result<Vec<Account>, CcpRoutingStoreError> {
+        Ok(self
+            .accounts
+            .read()
+            .values()
+            .filter(|account| {
+                account.should_send_routes() && !ignore_accounts.contains(&account.id)
+            })
+            .map(|account| self.load_account(account))
+            .collect())
+    }
+
+    async fn get_accounts_to_receive_routes_from(
+        &self,
+    ) -> Result<Vec<Account>, CcpRoutingStoreError> {
+        Ok(self
+            .accounts
+            .read()
+            .values()
+            .filter(|account| account.should_receive_routes())
+            .map(|account| self.load_account(account))
+            .collect())
+    }
+
+    async fn get_local_and_configured_routes(
+        &self,
+    ) -> Result<(RoutingTable<Account>, RoutingTable<Account>), CcpRoutingStoreError> {
+        let accounts = self.get_all_accounts().await?;
+        let local_table: HashMap<String, Account> = accounts
+            .iter()
+            .map(|account| (account.ilp_address.to_string(), account.clone()))
+            .collect();
+
+        let account_map: HashMap<Uuid, &Account> = accounts
+            .iter()
+            .map(|account| (account.id, account))
+            .collect();
+        let configured_table: HashMap<String, Account> = self
+            .route_sources
+            .read()
+            .configured
+            .iter()
+            .filter_map(|(prefix, account_id)| {
+                if let Some(account) = account_map.get(account_id) {
+                    Some((prefix.clone(), (*account).clone()))
+                } else {
+                    warn!(
+                        "No account for ID: {}, ignoring configured route for prefix: {}",
+                        account_id, prefix
+                    );
+                    None
+                }
+            })
+            .collect();
+
+        Ok((local_table, configured_table))
+    }
+
+    async fn set_routes(
+        &mut self,
+        routes: impl IntoIterator<Item = (String, Account)> + Send + 'async_trait,
+    ) -> Result<(), CcpRoutingStoreError> {
+        let routes: HashMap<String, Uuid> = routes
+            .into_iter()
+            .map(|(prefix, account)| (prefix, account.id))
+            .collect();
+        debug!("Saved {} routes", routes.len());
+        self.route_sources.write().current = routes;
+        self.update_routes();
+        Ok(())
+    }
+}
@@ -1,23 +1,32 @@
 local accounts_key = ARGV[1]
 local to_account = accounts_key .. ':' .. ARGV[2]
 local to_amount = tonumber(ARGV[3])
+local now = tonumber(ARGV[4])
+
+-- Scripts are not rolled back on errors, so fail before writing to an account
+-- that was deleted while the packet was in flight instead of recreating it
+if redis.call('EXISTS', to_account) == 0 then
+    error('Account ' .. ARGV[2] .. ' does not exist')
+end
 
 local balance = redis.call('HINCRBY', to_account, 'balance', to_amount)
-local prepaid_amount, settle_threshold, settle_to = unpack(redis.call('HMGET', to_account, 'prepaid_amount', 'settle_threshold', 'settle_to'))
+local prepaid_amount, settle_threshold, settle_to, min_settlement_interval, last_settlement = unpack(redis.call('HMGET', to_account, 'prepaid_amount', 'settle_threshold', 'settle_to', 'min_settlement_interval', 'last_settlement'))
 
 -- The logic for trigerring settlement is as follows:
 --  1. settle_threshold must be non-nil (if it's nil, then settlement was perhaps disabled on the account).
 --  2. balance must be greater than settle_threshold (this is the core of the 'should I settle logic')
 --  3. settle_threshold must be greater than settle_to (e.g., settleTo=5, settleThreshold=6)
+--  4. if min_settlement_interval is set, the last settlement must be at least that long ago
+local debounced = min_settlement_interval and last_settlement and (now - tonumber(last_settlement) < tonumber(min_settlement_interval))
 local settle_amount = 0
-if (settle_threshold and settle_to) and (balance >= tonumber(settle_threshold)) and (tonumber(settle_threshold) > tonumber(settle_to)) then
+if (settle_threshold and settle_to) and (balance >= tonumber(settle_threshold)) and (tonumber(settle_threshold) > tonumber(settle_to)) and not debounced then
     settle_amount = balance - tonumber(settle_to)
 
     -- Update the balance _before_ sending the settlement so that we don't accidentally send
     -- multiple settlements for the same balance. If the settlement fails we'll roll back
     -- the balance change by re-adding the amount back to the balance
     balance = settle_to
-    redis.call('HSET', to_account, 'balance', balance)
+    redis.call('HMSET', to_account, 'balance', balance, 'last_settlement', now)
 end
 
 return {balance + prepaid_amount, settle_amount}
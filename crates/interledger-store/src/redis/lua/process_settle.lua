@@ -4,6 +4,7 @@
 -- upon completion the `balance` is at the level of `settle_to`
 local accounts_key = ARGV[1]
 local to_account = accounts_key .. ':' .. ARGV[2]
+local now = tonumber(ARGV[3])
 local balance, prepaid_amount, settle_threshold, settle_to = unpack(redis.call('HMGET', to_account, 'balance', 'prepaid_amount', 'settle_threshold', 'settle_to'))
 local settle_amount = 0
 
@@ -11,6 +12,9 @@ if (settle_threshold and settle_to) and (tonumber(settle_threshold) > tonumber(s
     settle_amount = tonumber(balance) - tonumber(settle_to)
     balance = settle_to
     redis.call('HSET', to_account, 'balance', balance)
+    if settle_amount > 0 then
+        redis.call('HSET', to_account, 'last_settlement', now)
+    end
 end
 
 return {balance + prepaid_amount, settle_amount}
@@ -5,15 +5,21 @@
 //   rates:current          hash        exchange rates
 //   routes:current         hash        dynamic routing table
 //   routes:static          hash        static routing table
+//   routes:multipath       hash        JSON lists of weighted routes by prefix
 //   accounts:<id>          hash        information for each account
 //   accounts               set
 //   usernames              hash
+//   usernames:sorted       zset        every username with score 0, ranged over lexicographically
 //   btp_outgoing
+//   pending_settlements    hash        unconfirmed outgoing settlements by idempotency key
+//   stream_notifications:<id>    channel    STREAM payments received by the account
+//   account_notifications:<id>   channel    balance changes and incoming settlements of the account
 // For interactive exploration of the store,
 // use the redis-cli tool included with your redis install.
 // Within redis-cli:
 //    keys *                list all keys of any type in the store
 //    smembers <key>        list the members of a set
+//    zrange <key> 0 -1     list the members of a sorted set
 //    get <key>             get the value of a key
 //    hgetall <key>         the flattened list of every key/value entry within a hash
 mod reconnect;
@@ -23,16 +29,19 @@ use super::account::{Account, AccountWithEncryptedTokens};
 use super::crypto::{encrypt_token, generate_keys, DecryptionKey, EncryptionKey};
 use async_trait::async_trait;
 use bytes::{Bytes, BytesMut};
-use futures::channel::mpsc::UnboundedSender;
+use futures::channel::mpsc::{self, UnboundedSender};
+use futures::StreamExt;
 use http::StatusCode;
-use interledger_api::{AccountDetails, AccountSettings, EncryptedAccountSettings, NodeStore};
+use interledger_api::{
+    AccountDetails, AccountPage, AccountQuery, AccountSettings, EncryptedAccountSettings, NodeStore,
+};
 use interledger_btp::BtpStore;
 use interledger_ccp::{CcpRoutingAccount, CcpRoutingStore, RoutingRelation};
 use interledger_errors::*;
 use interledger_http::HttpStore;
 use interledger_packet::Address;
 use interledger_rates::ExchangeRateStore;
-use interledger_router::RouterStore;
+use interledger_router::{RouterStore, WeightedRoute};
 use interledger_service::{Account as AccountTrait, AccountStore, AddressStore, Username};
 use interledger_service_util::{
     BalanceStore, RateLimitError, RateLimitStore, DEFAULT_ROUND_TRIP_TIME,
@@ -40,9 +49,14 @@ use interledger_service_util::{
 use interledger_settlement::core::{
     idempotency::{IdempotentData, IdempotentStore},
     scale_with_precision_loss,
-    types::{Convert, ConvertDetails, LeftoversStore, SettlementStore},
+    types::{
+        Convert, ConvertDetails, LeftoversStore, PendingSettlement, PendingSettlementStore,
+        SettlementStore,
+    },
+};
+use interledger_stream::{
+    AccountNotification, AccountNotificationsStore, PaymentNotification, StreamNotificationsStore,
 };
-use interledger_stream::{PaymentNotification, StreamNotificationsStore};
 use num_bigint::BigUint;
 use once_cell::sync::Lazy;
 use parking_lot::{Mutex, RwLock};
@@ -53,7 +67,13 @@ use redis_crate::{
 use redis_crate::{AsyncCommands, Script};
 use secrecy::{ExposeSecret, Secret, SecretBytesMut};
 use serde::{Deserialize, Serialize};
-use std::{borrow::Cow, str, str::FromStr, sync::Arc, time::Duration};
+use std::{
+    borrow::Cow,
+    str,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 use std::{collections::HashMap, fmt::Display};
 use tokio::sync::broadcast;
 use tracing::{debug, error, warn};
@@ -62,20 +82,24 @@ use uuid::Uuid;
 use zeroize::Zeroize;
 
 const DEFAULT_POLL_INTERVAL: u64 = 30000; // 30 seconds
-const ACCOUNT_DETAILS_FIELDS: usize = 21;
+const ACCOUNT_DETAILS_FIELDS: usize = 27;
 const DEFAULT_DB_PREFIX: &str = "";
 
 static PARENT_ILP_KEY: &str = "parent_node_account_address";
 static ROUTES_KEY: &str = "routes:current";
 static STATIC_ROUTES_KEY: &str = "routes:static";
 static DEFAULT_ROUTE_KEY: &str = "routes:default";
+static MULTIPATH_ROUTES_KEY: &str = "routes:multipath";
 static STREAM_NOTIFICATIONS_PREFIX: &str = "stream_notifications:";
+static ACCOUNT_NOTIFICATIONS_PREFIX: &str = "account_notifications:";
 static SETTLEMENT_ENGINES_KEY: &str = "settlement_engines";
 static USERNAMES_KEY: &str = "usernames";
+static USERNAMES_INDEX_KEY: &str = "usernames:sorted";
 static ACCOUNTS_KEY: &str = "accounts";
 static SEND_ROUTES_KEY: &str = "send_routes_to";
 static RECEIVE_ROUTES_FROM_KEY: &str = "receive_routes_from";
 static BPT_OUTGOING: &str = "btp_outgoing";
+static PENDING_SETTLEMENTS_KEY: &str = "pending_settlements";
 
 /// Domain separator for leftover amounts
 fn uncredited_amount_key(prefix: &str, account_id: impl ToString) -> String {
@@ -103,6 +127,15 @@ fn prefixed_key<'a>(prefix: &str, key: &'a str) -> Cow<'a, str> {
     }
 }
 
+/// The current time as milliseconds since the unix epoch, which the balance
+/// scripts use to space out threshold settlements
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
 /// Domain separator for accounts
 fn accounts_key(prefix: &str, account_id: Uuid) -> String {
     prefixed_key(prefix, &format!("accounts:{}", account_id)).into_owned()
@@ -131,7 +164,9 @@ static ACCOUNT_FROM_USERNAME: Lazy<Script> =
 static LOAD_ACCOUNTS: Lazy<Script> =
     Lazy::new(|| Script::new(include_str!("lua/load_accounts.lua")));
 
-/// Lua script which reduces the provided account's balance before sending a Prepare packet
+/// Lua script which reduces the provided account's balance before sending a Prepare packet.
+/// Redis runs each script on its own, so the minimum balance check and the deduction
+/// cannot interleave with other prepares for the account
 static PROCESS_PREPARE: Lazy<Script> =
     Lazy::new(|| Script::new(include_str!("lua/process_prepare.lua")));
 
@@ -162,6 +197,7 @@ pub struct RedisStoreBuilder {
     /// Connector's ILP Address. Used to insert `Child` accounts as
     node_ilp_address: Address,
     db_prefix: String,
+    publish_account_notifications: bool,
 }
 
 impl RedisStoreBuilder {
@@ -173,6 +209,7 @@ impl RedisStoreBuilder {
             poll_interval: DEFAULT_POLL_INTERVAL,
             node_ilp_address: DEFAULT_ILP_ADDRESS.clone(),
             db_prefix: DEFAULT_DB_PREFIX.to_string(),
+            publish_account_notifications: false,
         }
     }
 
@@ -195,6 +232,15 @@ impl RedisStoreBuilder {
         self
     }
 
+    /// Publishes the balance changes and incoming settlements of every account over Redis,
+    /// so that the subscribers connected to the other nodes sharing the database receive them
+    /// too. Otherwise, only the subscribers connected to the node processing the change are
+    /// notified, without going through Redis
+    pub fn publish_account_notifications(&mut self, publish: bool) -> &mut Self {
+        self.publish_account_notifications = publish;
+        self
+    }
+
     /// Connects to the Redis Store
     ///
     /// Specifically
@@ -203,6 +249,7 @@ impl RedisStoreBuilder {
     /// 1. Gets the Node address assigned to us by our parent (if it exists)
     /// 1. Starts polling for routing table updates
     /// 1. Spawns a thread to notify incoming payments over WebSockets
+    /// 1. Spawns a task to publish account notifications over Redis, if enabled
     pub async fn connect(&mut self) -> Result<RedisStore, ()> {
         let redis_info = self.redis_url.clone();
         let (encryption_key, decryption_key) = generate_keys(&self.secret[..]);
@@ -238,15 +285,57 @@ impl RedisStoreBuilder {
             ilp_address
         };
 
+        // Databases written before the sorted username index existed only have
+        // the usernames hash, so make sure every username is indexed
+        let usernames: Vec<String> = connection
+            .hkeys(&*prefixed_key(&self.db_prefix, USERNAMES_KEY))
+            .map_err(|err| error!("Error loading usernames: {:?}", err))
+            .await?;
+        if !usernames.is_empty() {
+            let members: Vec<(u8, String)> = usernames.into_iter().map(|u| (0, u)).collect();
+            connection
+                .zadd_multiple::<_, _, _, ()>(
+                    &*prefixed_key(&self.db_prefix, USERNAMES_INDEX_KEY),
+                    &members,
+                )
+                .map_err(|err| error!("Error indexing usernames: {:?}", err))
+                .await?;
+        }
+
         let (all_payment_publisher, _) = broadcast::channel::<PaymentNotification>(256);
 
+        // A single task publishes the account notifications, in the order they were made
+        let account_notification_publisher = if self.publish_account_notifications {
+            let (sender, mut receiver) = mpsc::unbounded::<(Uuid, AccountNotification)>();
+            let mut connection = connection.clone();
+            let channel_prefix =
+                prefixed_key(&self.db_prefix, ACCOUNT_NOTIFICATIONS_PREFIX).into_owned();
+            tokio::spawn(async move {
+                while let Some((account_id, notification)) = receiver.next().await {
+                    let message = serde_json::to_string(&notification).unwrap();
+                    let _ = redis_crate::cmd("PUBLISH")
+                        .arg(format!("{}{}", channel_prefix, account_id))
+                        .arg(message)
+                        .query_async::<_, ()>(&mut connection)
+                        .map_err(|err| error!("Error publish message to Redis: {:?}", err))
+                        .await;
+                }
+            });
+            Some(sender)
+        } else {
+            None
+        };
+
         let store = RedisStore {
             ilp_address: Arc::new(RwLock::new(node_ilp_address)),
             connection,
             subscriptions: Arc::new(Mutex::new(HashMap::new())),
             payment_publisher: all_payment_publisher,
+            account_subscriptions: Arc::new(Mutex::new(HashMap::new())),
+            account_notification_publisher,
             exchange_rates: Arc::new(RwLock::new(HashMap::new())),
             routes: Arc::new(RwLock::new(Arc::new(HashMap::new()))),
+            multipath_routes: Arc::new(RwLock::new(Arc::new(HashMap::new()))),
             encryption_key: Arc::new(encryption_key),
             decryption_key: Arc::new(decryption_key),
             db_prefix: self.db_prefix.clone(),
@@ -257,6 +346,7 @@ impl RedisStoreBuilder {
         let connection_clone = Arc::downgrade(&store.connection.conn);
         let redis_info = store.connection.redis_info.clone();
         let routing_table = store.routes.clone();
+        let multipath_routing_table = store.multipath_routes.clone();
 
         let db_prefix = self.db_prefix.clone();
         let poll_routes = async move {
@@ -265,12 +355,16 @@ impl RedisStoreBuilder {
             loop {
                 interval.tick().await;
                 if let Some(conn) = connection_clone.upgrade() {
-                    let _ = update_routes(
-                        RedisReconnect {
-                            conn,
-                            redis_info: redis_info.clone(),
-                        },
-                        routing_table.clone(),
+                    let connection = RedisReconnect {
+                        conn,
+                        redis_info: redis_info.clone(),
+                    };
+                    let _ = update_routes(connection.clone(), routing_table.clone(), &db_prefix)
+                        .map_err(|err| error!("{}", err))
+                        .await;
+                    let _ = update_multipath_routes(
+                        connection,
+                        multipath_routing_table.clone(),
                         &db_prefix,
                     )
                     .map_err(|err| error!("{}", err))
@@ -289,19 +383,25 @@ impl RedisStoreBuilder {
         // This currently must be a thread rather than a task due to the redis-rs driver
         // not yet supporting asynchronous subscriptions (see https://github.com/mitsuhiko/redis-rs/issues/183).
         let subscriptions_clone = store.subscriptions.clone();
+        let account_subscriptions_clone = store.account_subscriptions.clone();
         let payment_publisher = store.payment_publisher.clone();
         let db_prefix = prefixed_key(&self.db_prefix, STREAM_NOTIFICATIONS_PREFIX).into_owned();
+        let account_db_prefix =
+            prefixed_key(&self.db_prefix, ACCOUNT_NOTIFICATIONS_PREFIX).into_owned();
 
         // add a oneshot to provide some synchronization on a busy continious integration server
         // between this "thread of execution" and the launched listener.
         let (tx, rx) = tokio::sync::oneshot::channel();
 
         std::thread::spawn(move || {
-            // our notifications will be PUBLISH'd to topics under this prefix
-            let prefix = format!("{}*", &db_prefix);
+            // our notifications will be PUBLISH'd to topics under these prefixes
+            let prefixes = [
+                format!("{}*", &db_prefix),
+                format!("{}*", &account_db_prefix),
+            ];
             tx.send(()).expect("exiting as parent has exited");
             let sub_status =
-                sub_connection.psubscribe::<_, _, Vec<String>>(&[prefix], move |msg| {
+                sub_connection.psubscribe::<_, _, Vec<String>>(&prefixes, move |msg| {
                     let channel_name = msg.get_channel_name();
                     if let Some(suffix) = channel_name.strip_prefix(&db_prefix) {
                         if let Ok(account_id) = Uuid::from_str(suffix) {
@@ -319,18 +419,27 @@ impl RedisStoreBuilder {
                                 }
                             }
                             match subscriptions_clone.lock().get_mut(&account_id) {
-                                  Some(senders) => {
-                                    senders.retain(|sender| {
-                                        if let Err(err) = sender.unbounded_send(message.clone()) {
-                                            debug!("Failed to send message: {}", err);
-                                            false
-                                        } else {
-                                            true
-                                        }
-                                    });
-                                },
+                                Some(senders) => send_to_subscribers(senders, &message),
                                 None => debug!("Ignoring message for account {} because there were no open subscriptions", account_id),
                             }
+                            if let Some(senders) = account_subscriptions_clone.lock().get_mut(&account_id) {
+                                send_to_subscribers(senders, &AccountNotification::Payment(message));
+                            }
+                        } else {
+                            error!("Invalid Uuid in channel name: {}", channel_name);
+                        }
+                    } else if let Some(suffix) = channel_name.strip_prefix(&account_db_prefix) {
+                        if let Ok(account_id) = Uuid::from_str(suffix) {
+                            let message: AccountNotification = match serde_json::from_slice(msg.get_payload_bytes()) {
+                                Ok(s) => s,
+                                Err(e) => {
+                                    error!("Failed to get payload from subscription: {}", e);
+                                    return ControlFlow::Continue;
+                                }
+                            };
+                            if let Some(senders) = account_subscriptions_clone.lock().get_mut(&account_id) {
+                                send_to_subscribers(senders, &message);
+                            }
                         } else {
                             error!("Invalid Uuid in channel name: {}", channel_name);
                         }
@@ -368,6 +477,11 @@ pub struct RedisStore {
     subscriptions: Arc<Mutex<HashMap<Uuid, Vec<UnboundedSender<PaymentNotification>>>>>,
     /// A subscriber to all payment notifications, exposed via a WebSocket
     payment_publisher: broadcast::Sender<PaymentNotification>,
+    /// WebSocket senders which publish all notifications concerning an account
+    account_subscriptions: Arc<Mutex<HashMap<Uuid, Vec<UnboundedSender<AccountNotification>>>>>,
+    /// Queues the account notifications to publish over Redis, if they are shared with the
+    /// other nodes using the database
+    account_notification_publisher: Option<UnboundedSender<(Uuid, AccountNotification)>>,
     exchange_rates: Arc<RwLock<HashMap<String, f64>>>,
     /// The store keeps the routing table in memory so that it can be returned
     /// synchronously while the Router is processing packets.
@@ -376,6 +490,8 @@ pub struct RedisStore {
     /// The inner `Arc<HashMap>` is used so that the `routing_table` method can
     /// return a reference to the routing table without cloning the underlying data.
     routes: Arc<RwLock<Arc<HashMap<String, Uuid>>>>,
+    /// Prefixes routed through several accounts, kept in memory like the routing table
+    multipath_routes: Arc<RwLock<MultipathRoutingTable>>,
     /// Encryption Key so that the no cleartext data are stored
     encryption_key: Arc<Secret<EncryptionKey>>,
     /// Decryption Key to provide cleartext data to users
@@ -441,6 +557,12 @@ impl RedisStore {
             RedisAccountId(account.id),
         )
         .ignore();
+        pipe.zadd(
+            &*prefixed_key(&self.db_prefix, USERNAMES_INDEX_KEY),
+            account.username().as_ref(),
+            0,
+        )
+        .ignore();
 
         // Set balance-related details
         pipe.hset_multiple(&id, &[("balance", 0), ("prepaid_amount", 0)])
@@ -634,6 +756,10 @@ impl RedisStore {
             pipe.hset(&accounts_key, "settle_to", settle_to);
         }
 
+        if let Some(interval) = settings.min_settlement_interval {
+            pipe.hset(&accounts_key, "min_settlement_interval", interval);
+        }
+
         pipe.query_async(&mut self.connection.clone()).await?;
 
         // return the updated account
@@ -679,6 +805,11 @@ impl RedisStore {
             account.username().as_ref(),
         )
         .ignore();
+        pipe.zrem(
+            &*prefixed_key(&self.db_prefix, USERNAMES_INDEX_KEY),
+            account.username().as_ref(),
+        )
+        .ignore();
 
         if account.should_send_routes() {
             pipe.srem(
@@ -839,6 +970,32 @@ impl StreamNotificationsStore for RedisStore {
     }
 }
 
+impl AccountNotificationsStore for RedisStore {
+    fn add_account_notification_subscription(
+        &self,
+        id: Uuid,
+        sender: UnboundedSender<AccountNotification>,
+    ) {
+        debug!("Added account notification listener for {}", id);
+        self.account_subscriptions
+            .lock()
+            .entry(id)
+            .or_default()
+            .push(sender);
+    }
+
+    fn publish_account_notification(&self, account_id: Uuid, notification: AccountNotification) {
+        if let Some(publisher) = &self.account_notification_publisher {
+            // The subscribers of this node receive it back through the psubscribe thread
+            if let Err(err) = publisher.unbounded_send((account_id, notification)) {
+                error!("Failed to queue an account notification: {:?}", err);
+            }
+        } else if let Some(senders) = self.account_subscriptions.lock().get_mut(&account_id) {
+            send_to_subscribers(senders, &notification);
+        }
+    }
+}
+
 #[async_trait]
 impl BalanceStore for RedisStore {
     /// Returns the balance **from the account holder's perspective**, meaning the sum of
@@ -879,6 +1036,10 @@ impl BalanceStore for RedisStore {
             "Processed prepare with incoming amount: {}. Account {} has balance (including prepaid amount): {} ",
             incoming_amount, from_account_id, balance
         );
+        self.publish_account_notification(
+            from_account_id,
+            AccountNotification::balance_change(from_account_id, balance),
+        );
         Ok(())
     }
 
@@ -891,15 +1052,17 @@ impl BalanceStore for RedisStore {
             .arg(&*prefixed_key(&self.db_prefix, ACCOUNTS_KEY))
             .arg(RedisAccountId(to_account_id))
             .arg(outgoing_amount)
+            .arg(unix_millis())
             .invoke_async(&mut self.connection.clone())
             .await?;
 
         debug!(
             "Processed fulfill for account {} for outgoing amount {}. Fulfill call result: {} {}",
+            to_account_id, outgoing_amount, balance, amount_to_settle,
+        );
+        self.publish_account_notification(
             to_account_id,
-            outgoing_amount,
-            balance,
-            amount_to_settle,
+            AccountNotification::balance_change(to_account_id, balance),
         );
         Ok((balance, amount_to_settle))
     }
@@ -924,6 +1087,10 @@ impl BalanceStore for RedisStore {
             "Processed reject for incoming amount: {}. Account {} has balance (including prepaid amount): {}",
             incoming_amount, from_account_id, balance
         );
+        self.publish_account_notification(
+            from_account_id,
+            AccountNotification::balance_change(from_account_id, balance),
+        );
 
         Ok(())
     }
@@ -935,15 +1102,20 @@ impl BalanceStore for RedisStore {
         let (balance, amount_to_settle): (i64, u64) = PROCESS_DELAYED_SETTLEMENT
             .arg(&*prefixed_key(&self.db_prefix, ACCOUNTS_KEY))
             .arg(RedisAccountId(to_account_id))
+            .arg(unix_millis())
             .invoke_async(&mut self.connection.clone())
             .await?;
 
         debug!(
             "Processed account {} for delayed settlement, balance: {}, to_settle: {}",
-            to_account_id,
-            balance,
-            amount_to_settle
+            to_account_id, balance, amount_to_settle
         );
+        if amount_to_settle > 0 {
+            self.publish_account_notification(
+                to_account_id,
+                AccountNotification::balance_change(to_account_id, balance),
+            );
+        }
 
         Ok((balance, amount_to_settle))
     }
@@ -1084,6 +1256,10 @@ impl RouterStore for RedisStore {
     fn routing_table(&self) -> Arc<HashMap<String, Uuid>> {
         self.routes.read().clone()
     }
+
+    fn multipath_routing_table(&self) -> MultipathRoutingTable {
+        self.multipath_routes.read().clone()
+    }
 }
 
 #[async_trait]
@@ -1142,6 +1318,7 @@ impl NodeStore for RedisStore {
         let settings = EncryptedAccountSettings {
             settle_to: settings.settle_to,
             settle_threshold: settings.settle_threshold,
+            min_settlement_interval: settings.min_settlement_interval,
             ilp_over_btp_url: settings.ilp_over_btp_url,
             ilp_over_http_url: settings.ilp_over_http_url,
             ilp_over_btp_incoming_token: settings.ilp_over_btp_incoming_token.map(|token| {
@@ -1178,7 +1355,13 @@ impl NodeStore for RedisStore {
         Ok(account.decrypt_tokens(&self.decryption_key.expose_secret().0))
     }
 
-    // TODO limit the number of results and page through them
+    async fn check_connection(&self) -> Result<(), NodeStoreError> {
+        cmd("PING")
+            .query_async::<_, String>(&mut self.connection.clone())
+            .await?;
+        Ok(())
+    }
+
     async fn get_all_accounts(&self) -> Result<Vec<Self::Account>, NodeStoreError> {
         let mut connection = self.connection.clone();
 
@@ -1203,6 +1386,81 @@ impl NodeStore for RedisStore {
         Ok(accounts)
     }
 
+    async fn query_accounts(
+        &self,
+        query: AccountQuery,
+    ) -> Result<AccountPage<Self::Account>, NodeStoreError> {
+        let mut connection = self.connection.clone();
+        let page_size = query.page_size();
+        let batch_size = page_size + 1;
+
+        // Bounds for ZRANGEBYLEX over the sorted username index
+        let mut min: Vec<u8> = match query.scan_start() {
+            Some((username, inclusive)) => {
+                let mut min = vec![if inclusive { b'[' } else { b'(' }];
+                min.extend_from_slice(username.as_bytes());
+                min
+            }
+            None => b"-".to_vec(),
+        };
+        let max: Vec<u8> = match query.username_prefix {
+            Some(ref prefix) => {
+                let mut max = vec![b'['];
+                max.extend_from_slice(prefix.as_bytes());
+                max.push(0xff);
+                max
+            }
+            None => b"+".to_vec(),
+        };
+
+        let mut accounts = Vec::with_capacity(batch_size);
+        while accounts.len() < batch_size {
+            let usernames: Vec<String> = cmd("ZRANGEBYLEX")
+                .arg(&*prefixed_key(&self.db_prefix, USERNAMES_INDEX_KEY))
+                .arg(&min)
+                .arg(&max)
+                .arg("LIMIT")
+                .arg(0)
+                .arg(batch_size)
+                .query_async(&mut connection)
+                .await?;
+            let last = match usernames.last() {
+                Some(last) => last.clone(),
+                None => break,
+            };
+
+            let ids: Vec<Option<RedisAccountId>> = cmd("HMGET")
+                .arg(&*prefixed_key(&self.db_prefix, USERNAMES_KEY))
+                .arg(&usernames)
+                .query_async(&mut connection)
+                .await?;
+            let mut script = LOAD_ACCOUNTS.prepare_invoke();
+            script.arg(&*prefixed_key(&self.db_prefix, ACCOUNTS_KEY));
+            script.arg(&*prefixed_key(&self.db_prefix, SETTLEMENT_ENGINES_KEY));
+            for id in ids.iter().flatten() {
+                script.arg(id.0.to_string());
+            }
+            let loaded: Vec<AccountWithEncryptedTokens> =
+                script.invoke_async(&mut connection).await?;
+
+            accounts.extend(
+                loaded
+                    .into_iter()
+                    .map(|account| account.decrypt_tokens(&self.decryption_key.expose_secret().0))
+                    .filter(|account| query.matches(account))
+                    .take(batch_size - accounts.len()),
+            );
+
+            if usernames.len() < batch_size {
+                break;
+            }
+            min = vec![b'('];
+            min.extend_from_slice(last.as_bytes());
+        }
+
+        Ok(AccountPage::new(accounts, page_size))
+    }
+
     async fn set_static_routes<R>(&self, routes: R) -> Result<(), NodeStoreError>
     where
         R: IntoIterator<Item = (String, Uuid)> + Send + 'async_trait,
@@ -1273,6 +1531,38 @@ impl NodeStore for RedisStore {
         Ok(())
     }
 
+    async fn set_multipath_route(
+        &self,
+        prefix: String,
+        routes: Vec<WeightedRoute>,
+    ) -> Result<(), NodeStoreError> {
+        let mut connection = self.connection.clone();
+        let mut pipe = redis_crate::pipe();
+        for route in routes.iter() {
+            pipe.exists(accounts_key(&self.db_prefix, route.account_id));
+        }
+        let accounts_exist: Vec<bool> = pipe.query_async(&mut connection).await?;
+        if !accounts_exist.iter().all(|a| *a) {
+            error!(
+                "Cannot set multipath route for prefix: {} because not all of the given accounts exist",
+                prefix
+            );
+            return Err(NodeStoreError::MissingAccounts);
+        }
+
+        let key = prefixed_key(&self.db_prefix, MULTIPATH_ROUTES_KEY);
+        if routes.is_empty() {
+            connection.hdel(&*key, prefix).await?;
+        } else {
+            let routes = serde_json::to_string(&routes)
+                .map_err(|err| NodeStoreError::Other(Box::new(err)))?;
+            connection.hset(&*key, prefix, routes).await?;
+        }
+
+        update_multipath_routes(connection, self.multipath_routes.clone(), &self.db_prefix).await?;
+        Ok(())
+    }
+
     async fn set_default_route(&self, account_id: Uuid) -> Result<(), NodeStoreError> {
         let routing_table = self.routes.clone();
         // TODO replace this with a lua script to do both calls at once
@@ -1345,6 +1635,27 @@ impl NodeStore for RedisStore {
             Ok(None)
         }
     }
+
+    async fn get_settlement_engines(&self) -> Result<HashMap<String, Url>, NodeStoreError> {
+        let engines: HashMap<String, String> = self
+            .connection
+            .clone()
+            .hgetall(&*prefixed_key(&self.db_prefix, SETTLEMENT_ENGINES_KEY))
+            .await?;
+        engines
+            .into_iter()
+            .map(|(asset_code, url)| match Url::parse(url.as_str()) {
+                Ok(url) => Ok((asset_code, url)),
+                Err(err) => {
+                    error!(
+                        "Settlement engine URL loaded from Redis was not a valid URL: {:?}",
+                        err
+                    );
+                    Err(NodeStoreError::InvalidEngineUrl(err.to_string()))
+                }
+            })
+            .collect()
+    }
 }
 
 #[async_trait]
@@ -1444,6 +1755,7 @@ impl AddressStore for RedisStore {
 }
 
 type RoutingTable<A> = HashMap<String, A>;
+type MultipathRoutingTable = Arc<HashMap<String, Vec<WeightedRoute>>>;
 
 #[async_trait]
 impl CcpRoutingStore for RedisStore {
@@ -1709,9 +2021,7 @@ impl IdempotentStore for RedisStore {
 
         debug!(
             "Cached {:?}: {:?}, {:?}",
-            idempotency_key,
-            status_code,
-            data,
+            idempotency_key, status_code, data,
         );
         Ok(())
     }
@@ -1737,9 +2047,11 @@ impl SettlementStore for RedisStore {
             .await?;
         debug!(
             "Processed incoming settlement from account: {} for amount: {}. Balance is now: {}",
+            account_id, amount, balance
+        );
+        self.publish_account_notification(
             account_id,
-            amount,
-            balance
+            AccountNotification::incoming_settlement(account_id, amount, balance),
         );
         Ok(())
     }
@@ -1751,8 +2063,7 @@ impl SettlementStore for RedisStore {
     ) -> Result<(), SettlementStoreError> {
         debug!(
             "Refunding settlement for account: {} of amount: {}",
-            account_id,
-            settle_amount
+            account_id, settle_amount
         );
         let balance: i64 = REFUND_SETTLEMENT
             .arg(&*prefixed_key(&self.db_prefix, ACCOUNTS_KEY))
@@ -1763,12 +2074,66 @@ impl SettlementStore for RedisStore {
 
         debug!(
             "Refunded settlement for account: {} of amount: {}. Balance is now: {}",
-            account_id,
-            settle_amount,
-            balance
+            account_id, settle_amount, balance
+        );
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl PendingSettlementStore for RedisStore {
+    async fn save_pending_settlement(
+        &self,
+        settlement: PendingSettlement,
+    ) -> Result<(), SettlementStoreError> {
+        let serialized = serde_json::to_string(&settlement)
+            .map_err(|err| SettlementStoreError::Other(Box::new(err)))?;
+        self.connection
+            .clone()
+            .hset(
+                &*prefixed_key(&self.db_prefix, PENDING_SETTLEMENTS_KEY),
+                &settlement.idempotency_key,
+                serialized,
+            )
+            .await?;
+        debug!(
+            "Saved pending settlement {} for account {}",
+            settlement.idempotency_key, settlement.account_id
         );
         Ok(())
     }
+
+    async fn clear_pending_settlement(
+        &self,
+        idempotency_key: &str,
+    ) -> Result<bool, SettlementStoreError> {
+        let removed: u64 = self
+            .connection
+            .clone()
+            .hdel(
+                &*prefixed_key(&self.db_prefix, PENDING_SETTLEMENTS_KEY),
+                idempotency_key,
+            )
+            .await?;
+        Ok(removed > 0)
+    }
+
+    async fn load_pending_settlements(
+        &self,
+    ) -> Result<Vec<PendingSettlement>, SettlementStoreError> {
+        let settlements: HashMap<String, String> = self
+            .connection
+            .clone()
+            .hgetall(&*prefixed_key(&self.db_prefix, PENDING_SETTLEMENTS_KEY))
+            .await?;
+        settlements
+            .values()
+            .map(|settlement| {
+                serde_json::from_str(settlement)
+                    .map_err(|err| SettlementStoreError::Other(Box::new(err)))
+            })
+            .collect()
+    }
 }
 
 // TODO: AmountWithScale is re-implemented on Interledger-Settlement. It'd be nice
@@ -1897,8 +2262,7 @@ impl LeftoversStore for RedisStore {
     ) -> Result<(), LeftoversStoreError> {
         debug!(
             "Saving uncredited_settlement_amount {:?} {:?}",
-            account_id,
-            uncredited_settlement_amount
+            account_id, uncredited_settlement_amount
         );
         // We store these amounts as lists of strings
         // because we cannot do BigNumber arithmetic in the store
@@ -1964,6 +2328,18 @@ type RouteVec = Vec<(String, RedisAccountId)>;
 use futures::future::TryFutureExt;
 
 // TODO replace this with pubsub when async pubsub is added upstream: https://github.com/mitsuhiko/redis-rs/issues/183
+/// Sends the message to each subscriber, dropping the ones whose receiver was closed
+fn send_to_subscribers<T: Clone>(senders: &mut Vec<UnboundedSender<T>>, message: &T) {
+    senders.retain(|sender| {
+        if let Err(err) = sender.unbounded_send(message.clone()) {
+            debug!("Failed to send message: {}", err);
+            false
+        } else {
+            true
+        }
+    });
+}
+
 async fn update_routes(
     mut connection: RedisReconnect,
     routing_table: Arc<RwLock<Arc<HashMap<String, Uuid>>>>,
@@ -1977,9 +2353,7 @@ async fn update_routes(
         pipe.query_async(&mut connection).await?;
     debug!(
         "Loaded routes from redis. Static routes: {:?}, default route: {:?}, other routes: {:?}",
-        static_routes,
-        default_route,
-        routes
+        static_routes, default_route, routes
     );
     // If there is a default route set in the db,
     // set the entry for "" in the routing table to route to that account
@@ -1999,6 +2373,29 @@ async fn update_routes(
     Ok(())
 }
 
+async fn update_multipath_routes(
+    mut connection: RedisReconnect,
+    multipath_routing_table: Arc<RwLock<MultipathRoutingTable>>,
+    db_prefix: &str,
+) -> Result<(), RedisError> {
+    let routes: HashMap<String, String> = connection
+        .hgetall(&*prefixed_key(db_prefix, MULTIPATH_ROUTES_KEY))
+        .await?;
+    let routes: HashMap<String, Vec<WeightedRoute>> = routes
+        .into_iter()
+        .filter_map(|(prefix, routes)| match serde_json::from_str(&routes) {
+            Ok(routes) => Some((prefix, routes)),
+            Err(err) => {
+                error!("Ignoring invalid multipath route for {}: {}", prefix, err);
+                None
+            }
+        })
+        .collect();
+    debug!("Multipath routing table is: {:?}", routes);
+    *multipath_routing_table.write() = Arc::new(routes);
+    Ok(())
+}
+
 // Uuid does not implement ToRedisArgs and FromRedisValue.
 // Rust does not allow implementing foreign traits on foreign data types.
 // As a result, we wrap Uuid in a local data type, and implement the necessary
@@ -2074,6 +2471,10 @@ impl ToRedisArgs for &AccountWithEncryptedTokens {
             "ilp_over_http_url".write_redis_args(&mut rv);
             ilp_over_http_url.as_str().write_redis_args(&mut rv);
         }
+        if let Some(timeout) = account.ilp_over_http_timeout {
+            "ilp_over_http_timeout".write_redis_args(&mut rv);
+            timeout.write_redis_args(&mut rv);
+        }
         if let Some(ilp_over_http_incoming_token) = account.ilp_over_http_incoming_token.as_ref() {
             "ilp_over_http_incoming_token".write_redis_args(&mut rv);
             ilp_over_http_incoming_token
@@ -2114,6 +2515,10 @@ impl ToRedisArgs for &AccountWithEncryptedTokens {
             "settle_to".write_redis_args(&mut rv);
             settle_to.write_redis_args(&mut rv);
         }
+        if let Some(interval) = account.min_settlement_interval {
+            "min_settlement_interval".write_redis_args(&mut rv);
+            interval.write_redis_args(&mut rv);
+        }
         if let Some(limit) = account.packets_per_minute_limit {
             "packets_per_minute_limit".write_redis_args(&mut rv);
             limit.write_redis_args(&mut rv);
@@ -2122,6 +2527,25 @@ impl ToRedisArgs for &AccountWithEncryptedTokens {
             "amount_per_minute_limit".write_redis_args(&mut rv);
             limit.write_redis_args(&mut rv);
         }
+        if let Some(window) = account.min_expiry_window {
+            "min_expiry_window".write_redis_args(&mut rv);
+            window.write_redis_args(&mut rv);
+        }
+        if let Some(limit) = account.packets_per_second_limit {
+            "packets_per_second_limit".write_redis_args(&mut rv);
+            limit.write_redis_args(&mut rv);
+        }
+        if let Some(limit) = account.packet_burst_limit {
+            "packet_burst_limit".write_redis_args(&mut rv);
+            limit.write_redis_args(&mut rv);
+        }
+        if let Some(route_policy) = &account.route_policy {
+            "route_policy".write_redis_args(&mut rv);
+            // Serializing plain structs and enums to JSON cannot fail
+            serde_json::to_string(route_policy)
+                .unwrap()
+                .write_redis_args(&mut rv);
+        }
         if let Some(min_balance) = account.min_balance {
             "min_balance".write_redis_args(&mut rv);
             min_balance.write_redis_args(&mut rv);
@@ -2156,6 +2580,14 @@ impl FromRedisValue for AccountWithEncryptedTokens {
         };
         let round_trip_time: Option<u32> = get_value_option("round_trip_time", &hash)?;
         let round_trip_time: u32 = round_trip_time.unwrap_or(DEFAULT_ROUND_TRIP_TIME);
+        let route_policy: Option<String> = get_value_option("route_policy", &hash)?;
+        let route_policy =
+            match route_policy {
+                Some(route_policy) => Some(serde_json::from_str(&route_policy).map_err(|_| {
+                    RedisError::from((ErrorKind::TypeError, "Invalid route policy"))
+                })?),
+                None => None,
+            };
 
         let rid: RedisAccountId = get_value("id", &hash)?;
 
@@ -2167,6 +2599,7 @@ impl FromRedisValue for AccountWithEncryptedTokens {
                 asset_code: get_value("asset_code", &hash)?,
                 asset_scale: get_value("asset_scale", &hash)?,
                 ilp_over_http_url: get_url_option("ilp_over_http_url", &hash)?,
+                ilp_over_http_timeout: get_value_option("ilp_over_http_timeout", &hash)?,
                 ilp_over_http_incoming_token: get_bytes_option(
                     "ilp_over_http_incoming_token",
                     &hash,
@@ -2192,10 +2625,15 @@ impl FromRedisValue for AccountWithEncryptedTokens {
                 min_balance: get_value_option("min_balance", &hash)?,
                 settle_threshold: get_value_option("settle_threshold", &hash)?,
                 settle_to: get_value_option("settle_to", &hash)?,
+                min_settlement_interval: get_value_option("min_settlement_interval", &hash)?,
                 routing_relation,
                 round_trip_time,
+                min_expiry_window: get_value_option("min_expiry_window", &hash)?,
                 packets_per_minute_limit: get_value_option("packets_per_minute_limit", &hash)?,
                 amount_per_minute_limit: get_value_option("amount_per_minute_limit", &hash)?,
+                packets_per_second_limit: get_value_option("packets_per_second_limit", &hash)?,
+                packet_burst_limit: get_value_option("packet_burst_limit", &hash)?,
+                route_policy,
                 settlement_engine_url: get_url_option("settlement_engine_url", &hash)?,
             },
         })
@@ -0,0 +1,348 @@
+use super::{fixtures::*, store_helpers::*};
+
+use interledger_api::{AccountQuery, AccountSettings, NodeStore};
+use interledger_btp::BtpStore;
+use interledger_ccp::{CcpRoutingAccount, RouteAction, RoutePolicy, RouteRule, RoutingRelation};
+use interledger_errors::{AccountStoreError, BtpStoreError, HttpStoreError, NodeStoreError};
+use interledger_http::{HttpAccount, HttpStore};
+use interledger_service::{Account as AccountTrait, AccountStore, Username};
+use interledger_service_util::{MinExpiryWindowAccount, RateLimitAccount};
+use interledger_settlement::core::types::SettlementAccount;
+use secrecy::SecretString;
+use std::str::FromStr;
+use std::time::Duration;
+use url::Url;
+use uuid::Uuid;
+
+#[tokio::test]
+async fn inserts_and_loads_accounts() {
+    let (store, accounts) = test_store().await;
+    assert_eq!(accounts[0].ilp_address().to_string(), "example.node.alice");
+
+    let loaded = store
+        .get_accounts(vec![accounts[1].id(), accounts[0].id()])
+        .await
+        .unwrap();
+    assert_eq!(loaded[0].username().as_ref(), "bob");
+    assert_eq!(loaded[1].username().as_ref(), "alice");
+    assert_eq!(
+        store
+            .get_account_id_from_username(&Username::from_str("bob").unwrap())
+            .await
+            .unwrap(),
+        accounts[1].id()
+    );
+    assert_eq!(store.get_all_accounts().await.unwrap().len(), 2);
+
+    let err = store
+        .get_accounts(vec![accounts[0].id(), Uuid::new_v4()])
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        AccountStoreError::WrongLength {
+            expected: 2,
+            actual: 1
+        }
+    ));
+}
+
+#[tokio::test]
+async fn rejects_duplicate_usernames() {
+    let (store, _accounts) = test_store().await;
+    let err = store
+        .insert_account(account_details("alice"))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, NodeStoreError::AccountExists(_)));
+    assert_eq!(store.get_all_accounts().await.unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn updates_and_deletes_accounts() {
+    let (store, accounts) = test_store().await;
+    let alice_id = accounts[0].id();
+
+    let mut details = account_details("carol");
+    details.asset_code = "abc".to_string();
+    let updated = store.update_account(alice_id, details).await.unwrap();
+    assert_eq!(updated.id(), alice_id);
+    assert_eq!(updated.asset_code(), "ABC");
+    assert!(store
+        .get_account_id_from_username(&Username::from_str("alice").unwrap())
+        .await
+        .is_err());
+    assert_eq!(
+        store
+            .get_account_id_from_username(&Username::from_str("carol").unwrap())
+            .await
+            .unwrap(),
+        alice_id
+    );
+
+    let err = store
+        .update_account(Uuid::new_v4(), account_details("dave"))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, NodeStoreError::AccountNotFound(_)));
+
+    let deleted = store.delete_account(alice_id).await.unwrap();
+    assert_eq!(deleted.username().as_ref(), "carol");
+    assert!(store.get_accounts(vec![alice_id]).await.is_err());
+    assert!(matches!(
+        store.delete_account(alice_id).await.unwrap_err(),
+        NodeStoreError::AccountNotFound(_)
+    ));
+}
+
+#[tokio::test]
+async fn loads_and_updates_packet_rate_limits() {
+    let (store, accounts) = test_store().await;
+    let alice_id = accounts[0].id();
+    assert_eq!(accounts[0].packets_per_second_limit(), None);
+
+    let mut details = account_details("alice");
+    details.packets_per_second_limit = Some(100);
+    details.packet_burst_limit = Some(250);
+    store.update_account(alice_id, details).await.unwrap();
+
+    let alice = store.get_accounts(vec![alice_id]).await.unwrap().remove(0);
+    assert_eq!(alice.packets_per_second_limit(), Some(100));
+    assert_eq!(alice.packet_burst_limit(), Some(250));
+}
+
+#[tokio::test]
+async fn loads_and_updates_min_expiry_windows() {
+    let (store, accounts) = test_store().await;
+    let alice_id = accounts[0].id();
+    assert_eq!(accounts[0].min_expiry_window(), None);
+
+    let mut details = account_details("alice");
+    details.min_expiry_window = Some(2000);
+    store.update_account(alice_id, details).await.unwrap();
+
+    let alice = store.get_accounts(vec![alice_id]).await.unwrap().remove(0);
+    assert_eq!(alice.min_expiry_window(), Some(2000));
+}
+
+#[tokio::test]
+async fn loads_and_updates_route_policies() {
+    let (store, accounts) = test_store().await;
+    let alice_id = accounts[0].id();
+    assert_eq!(accounts[0].route_policy(), None);
+
+    let policy = RoutePolicy {
+        rules: vec![RouteRule {
+            action: RouteAction::Deny,
+            prefix: Some("example.provider".to_string()),
+            origin: None,
+        }],
+        default: RouteAction::Allow,
+    };
+    let mut details = account_details("alice");
+    details.route_policy = Some(policy.clone());
+    store.update_account(alice_id, details).await.unwrap();
+
+    let alice = store.get_accounts(vec![alice_id]).await.unwrap().remove(0);
+    assert_eq!(alice.route_policy(), Some(&policy));
+}
+
+#[tokio::test]
+async fn loads_and_updates_http_timeouts() {
+    let (store, accounts) = test_store().await;
+    let alice_id = accounts[0].id();
+    assert_eq!(accounts[0].get_http_timeout(), None);
+
+    let mut details = account_details("alice");
+    details.ilp_over_http_timeout = Some(5000);
+    store.update_account(alice_id, details).await.unwrap();
+
+    let alice = store.get_accounts(vec![alice_id]).await.unwrap().remove(0);
+    assert_eq!(alice.get_http_timeout(), Some(Duration::from_secs(5)));
+}
+
+#[tokio::test]
+async fn modifies_account_settings() {
+    let (store, accounts) = test_store().await;
+    let alice = Username::from_str("alice").unwrap();
+    store
+        .get_account_from_http_auth(&alice, "alice_http")
+        .await
+        .unwrap();
+    store
+        .get_account_from_btp_auth(&alice, "alice_btp")
+        .await
+        .unwrap();
+
+    let settings = AccountSettings {
+        ilp_over_http_incoming_token: Some(SecretString::new("new_http".to_string())),
+        ilp_over_btp_url: Some("btp+ws://example.com/accounts/alice/ilp/btp".to_string()),
+        settle_threshold: Some(100),
+        settle_to: Some(10),
+        ..Default::default()
+    };
+    store
+        .modify_account_settings(accounts[0].id(), settings)
+        .await
+        .unwrap();
+
+    assert!(matches!(
+        store
+            .get_account_from_http_auth(&alice, "alice_http")
+            .await
+            .unwrap_err(),
+        HttpStoreError::Unauthorized(_)
+    ));
+    store
+        .get_account_from_http_auth(&alice, "new_http")
+        .await
+        .unwrap();
+    assert!(matches!(
+        store
+            .get_account_from_btp_auth(&Username::from_str("nobody").unwrap(), "alice_btp")
+            .await
+            .unwrap_err(),
+        BtpStoreError::AccountNotFound(_)
+    ));
+    let btp_outgoing = store.get_btp_outgoing_accounts().await.unwrap();
+    assert_eq!(btp_outgoing.len(), 1);
+    assert_eq!(btp_outgoing[0].id(), accounts[0].id());
+
+    let err = store
+        .modify_account_settings(
+            accounts[0].id(),
+            AccountSettings {
+                settle_to: Some(u64::MAX),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(err, NodeStoreError::InvalidAccount(_)));
+}
+
+#[tokio::test]
+async fn falls_back_to_configured_settlement_engines() {
+    let (store, accounts) = test_store().await;
+    let url = Url::parse("http://settlement.example").unwrap();
+    store
+        .set_settlement_engines(vec![("XYZ".to_string(), url.clone())])
+        .await
+        .unwrap();
+    assert_eq!(
+        store.get_asset_settlement_engine("XYZ").await.unwrap(),
+        Some(url.clone())
+    );
+    assert_eq!(
+        store.get_asset_settlement_engine("ABC").await.unwrap(),
+        None
+    );
+    assert_eq!(
+        store.get_settlement_engines().await.unwrap(),
+        vec![("XYZ".to_string(), url.clone())].into_iter().collect()
+    );
+
+    let account = store
+        .get_accounts(vec![accounts[0].id()])
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    assert_eq!(account.settlement_engine_details().unwrap().url, url);
+}
+
+#[tokio::test]
+async fn pages_through_accounts_in_username_order() {
+    let (store, _) = test_store().await;
+    for username in &["dave", "carol", "albert"] {
+        store
+            .insert_account(account_details(username))
+            .await
+            .unwrap();
+    }
+
+    let mut query = AccountQuery {
+        limit: Some(2),
+        ..Default::default()
+    };
+    let mut pages = Vec::new();
+    loop {
+        let page = store.query_accounts(query.clone()).await.unwrap();
+        pages.push(
+            page.accounts
+                .iter()
+                .map(|account| account.username().to_string())
+                .collect::<Vec<_>>(),
+        );
+        match page.next_cursor {
+            Some(cursor) => query.cursor = Some(cursor),
+            None => break,
+        }
+    }
+    // Five accounts fill two pages and leave one for the last
+    assert_eq!(
+        pages,
+        vec![vec!["albert", "alice"], vec!["bob", "carol"], vec!["dave"]]
+    );
+
+    // A page that ends exactly on the last account has no next cursor
+    let page = store
+        .query_accounts(AccountQuery {
+            cursor: Some("alice".to_owned()),
+            limit: Some(3),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(page.accounts.len(), 3);
+    assert_eq!(page.next_cursor, None);
+}
+
+#[tokio::test]
+async fn combines_account_filters() {
+    let (store, _) = test_store().await;
+    let mut albert = account_details("albert");
+    albert.routing_relation = Some("Peer".to_owned());
+    store.insert_account(albert).await.unwrap();
+    let mut alex = account_details("alex");
+    alex.asset_code = "ABC".to_owned();
+    store.insert_account(alex).await.unwrap();
+    store
+        .insert_account(account_details("alfred"))
+        .await
+        .unwrap();
+
+    let query = AccountQuery {
+        asset_code: Some("XYZ".to_owned()),
+        routing_relation: Some(RoutingRelation::Child),
+        username_prefix: Some("al".to_owned()),
+        limit: Some(1),
+        ..Default::default()
+    };
+    let page = store.query_accounts(query.clone()).await.unwrap();
+    assert_eq!(page.accounts.len(), 1);
+    assert_eq!(page.accounts[0].username().as_ref(), "alfred");
+    assert_eq!(page.next_cursor, Some("alfred".to_owned()));
+
+    let page = store
+        .query_accounts(AccountQuery {
+            cursor: page.next_cursor,
+            ..query
+        })
+        .await
+        .unwrap();
+    assert_eq!(page.accounts.len(), 1);
+    assert_eq!(page.accounts[0].username().as_ref(), "alice");
+    assert_eq!(page.next_cursor, None);
+
+    // A prefix no username starts with matches nothing
+    let page = store
+        .query_accounts(AccountQuery {
+            username_prefix: Some("zz".to_owned()),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    assert!(page.accounts.is_empty());
+    assert_eq!(page.next_cursor, None);
+}
@@ -0,0 +1,274 @@
+use super::{fixtures::*, store_helpers::*};
+
+use futures::{channel::mpsc::unbounded, future::join_all};
+use interledger_api::{AccountSettings, NodeStore};
+use interledger_service::Account as AccountTrait;
+use interledger_service_util::BalanceStore;
+use interledger_settlement::core::types::{
+    PendingSettlement, PendingSettlementStore, SettlementStore,
+};
+use interledger_stream::{AccountNotification, AccountNotificationsStore};
+use std::time::Duration;
+use url::Url;
+use uuid::Uuid;
+
+#[tokio::test]
+async fn prepare_respects_min_balance() {
+    let (store, accounts) = test_store().await;
+    let alice = accounts[0].id();
+
+    store
+        .update_balances_for_prepare(alice, 6000)
+        .await
+        .unwrap();
+    assert_eq!(store.get_balance(alice).await.unwrap(), -6000);
+
+    // Going below the min balance of -10,000 leaves the balance unchanged
+    assert!(store
+        .update_balances_for_prepare(alice, 5000)
+        .await
+        .is_err());
+    assert_eq!(store.get_balance(alice).await.unwrap(), -6000);
+
+    store.update_balances_for_reject(alice, 6000).await.unwrap();
+    assert_eq!(store.get_balance(alice).await.unwrap(), 0);
+
+    assert!(store
+        .update_balances_for_prepare(Uuid::new_v4(), 100)
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn updated_min_balance_is_a_credit_line() {
+    let (store, accounts) = test_store().await;
+    let alice = accounts[0].id();
+    let mut details = account_details("alice");
+    details.min_balance = Some(-1000);
+    store.update_account(alice, details).await.unwrap();
+
+    store.update_balances_for_prepare(alice, 600).await.unwrap();
+    store.update_balances_for_prepare(alice, 400).await.unwrap();
+    assert_eq!(store.get_balance(alice).await.unwrap(), -1000);
+
+    assert!(store.update_balances_for_prepare(alice, 1).await.is_err());
+    assert_eq!(store.get_balance(alice).await.unwrap(), -1000);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_prepares_never_exceed_min_balance() {
+    let (store, accounts) = test_store().await;
+    let alice = accounts[0].id();
+
+    // 100 prepares of 300 against the min balance of -10,000: only 33 fit
+    let results = join_all((0..100).map(|_| {
+        let store = store.clone();
+        tokio::spawn(async move { store.update_balances_for_prepare(alice, 300).await })
+    }))
+    .await;
+    let accepted = results
+        .into_iter()
+        .filter(|result| result.as_ref().unwrap().is_ok())
+        .count();
+    assert_eq!(accepted, 33);
+    assert_eq!(store.get_balance(alice).await.unwrap(), -9900);
+
+    join_all((0..accepted).map(|_| {
+        let store = store.clone();
+        tokio::spawn(async move { store.update_balances_for_reject(alice, 300).await })
+    }))
+    .await;
+    assert_eq!(store.get_balance(alice).await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn spaces_out_threshold_settlements() {
+    let (store, accounts) = test_store().await;
+    let alice = accounts[0].id();
+    let mut details = account_details("alice");
+    details.settle_threshold = Some(100);
+    details.settle_to = Some(10);
+    details.min_settlement_interval = Some(200);
+    store.update_account(alice, details.clone()).await.unwrap();
+
+    // The first time the threshold is reached the balance is settled right away
+    let fulfill = |amount| store.update_balances_for_fulfill(alice, amount);
+    assert_eq!(fulfill(150).await.unwrap(), (10, 140));
+    // Within the interval the balance keeps growing past the threshold
+    assert_eq!(fulfill(150).await.unwrap(), (160, 0));
+    assert_eq!(fulfill(50).await.unwrap(), (210, 0));
+
+    // The first fulfill after the interval settles it down to settle_to, even an empty one
+    // like the balance service sends once the interval ends...
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    assert_eq!(fulfill(0).await.unwrap(), (10, 200));
+    // ...and starts a new interval
+    assert_eq!(fulfill(100).await.unwrap(), (110, 0));
+
+    // Changes to the interval apply to the next fulfill
+    details.min_settlement_interval = None;
+    store.update_account(alice, details).await.unwrap();
+    assert_eq!(fulfill(100).await.unwrap(), (10, 200));
+
+    // Including those made through the account's settings
+    store
+        .modify_account_settings(
+            alice,
+            AccountSettings {
+                min_settlement_interval: Some(200),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(fulfill(150).await.unwrap(), (160, 0));
+}
+
+#[tokio::test]
+async fn prepare_uses_prepaid_amount_first() {
+    let (store, accounts) = test_store().await;
+    let alice = accounts[0].id();
+
+    store
+        .update_balance_for_incoming_settlement(alice, 100, None)
+        .await
+        .unwrap();
+    assert_eq!(store.get_balance(alice).await.unwrap(), 100);
+
+    store.update_balances_for_prepare(alice, 150).await.unwrap();
+    assert_eq!(store.get_balance(alice).await.unwrap(), -50);
+
+    // Incoming settlements pay off the debt before adding to the prepaid amount
+    store
+        .update_balance_for_incoming_settlement(alice, 80, None)
+        .await
+        .unwrap();
+    assert_eq!(store.get_balance(alice).await.unwrap(), 30);
+}
+
+#[tokio::test]
+async fn incoming_settlements_are_idempotent() {
+    let (store, accounts) = test_store().await;
+    let alice = accounts[0].id();
+
+    for _ in 0..2 {
+        store
+            .update_balance_for_incoming_settlement(alice, 100, Some("key".to_string()))
+            .await
+            .unwrap();
+    }
+    assert_eq!(store.get_balance(alice).await.unwrap(), 100);
+}
+
+#[tokio::test]
+async fn notifies_subscribers_of_incoming_settlements_once() {
+    let (store, accounts) = test_store().await;
+    let alice = accounts[0].id();
+    let (tx, mut notifications) = unbounded();
+    store.add_account_notification_subscription(alice, tx);
+
+    for _ in 0..2 {
+        store
+            .update_balance_for_incoming_settlement(alice, 100, Some("key".to_string()))
+            .await
+            .unwrap();
+    }
+
+    match notifications.try_next().unwrap().unwrap() {
+        AccountNotification::IncomingSettlement {
+            account_id,
+            amount,
+            balance,
+            ..
+        } => {
+            assert_eq!(account_id, alice);
+            assert_eq!(amount, 100);
+            assert_eq!(balance, 100);
+        }
+        other => panic!("Unexpected notification: {:?}", other),
+    }
+    assert!(notifications.try_next().is_err());
+}
+
+#[tokio::test]
+async fn fulfill_settles_above_threshold() {
+    let (store, accounts) = test_store().await;
+    let bob = accounts[1].id();
+    store
+        .modify_account_settings(
+            bob,
+            AccountSettings {
+                settle_threshold: Some(100),
+                settle_to: Some(10),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(
+        store.update_balances_for_fulfill(bob, 50).await.unwrap(),
+        (50, 0)
+    );
+    assert_eq!(
+        store.update_balances_for_fulfill(bob, 70).await.unwrap(),
+        (10, 110)
+    );
+    assert_eq!(store.get_balance(bob).await.unwrap(), 10);
+
+    store.refund_settlement(bob, 110).await.unwrap();
+    assert_eq!(store.get_balance(bob).await.unwrap(), 120);
+    assert_eq!(
+        store
+            .update_balances_for_delayed_settlement(bob)
+            .await
+            .unwrap(),
+        (10, 110)
+    );
+}
+
+#[tokio::test]
+async fn reports_overflow_without_changing_the_balance() {
+    let (store, accounts) = test_store().await;
+    let bob = accounts[1].id();
+
+    store
+        .update_balances_for_fulfill(bob, i64::MAX as u64)
+        .await
+        .unwrap();
+    assert!(store.update_balances_for_fulfill(bob, 1).await.is_err());
+    assert!(store
+        .update_balances_for_fulfill(bob, u64::MAX)
+        .await
+        .is_err());
+    assert_eq!(store.get_balance(bob).await.unwrap(), i64::MAX);
+}
+
+#[tokio::test]
+async fn saves_loads_and_clears_pending_settlements() {
+    let (store, accounts) = test_store().await;
+    let settlement = PendingSettlement::new(
+        accounts[1].id(),
+        Url::parse("http://settlement.example").unwrap(),
+        100,
+        9,
+    );
+    store
+        .save_pending_settlement(settlement.clone())
+        .await
+        .unwrap();
+    assert_eq!(
+        store.load_pending_settlements().await.unwrap(),
+        vec![settlement.clone()]
+    );
+
+    assert!(store
+        .clear_pending_settlement(&settlement.idempotency_key)
+        .await
+        .unwrap());
+    assert!(!store
+        .clear_pending_settlement(&settlement.idempotency_key)
+        .await
+        .unwrap());
+    assert!(store.load_pending_settlements().await.unwrap().is_empty());
+}
@@ -0,0 +1,60 @@
+mod accounts_test;
+mod balances_test;
+mod payment_test;
+mod routing_test;
+
+mod fixtures {
+    use interledger_api::AccountDetails;
+    use interledger_service::Username;
+    use secrecy::SecretString;
+    use std::str::FromStr;
+
+    pub fn account_details(username: &str) -> AccountDetails {
+        AccountDetails {
+            ilp_address: None,
+            username: Username::from_str(username).unwrap(),
+            asset_scale: 9,
+            asset_code: "XYZ".to_string(),
+            max_packet_amount: 1_000_000,
+            min_balance: Some(-10_000),
+            ilp_over_http_url: None,
+            ilp_over_http_timeout: None,
+            ilp_over_http_incoming_token: Some(SecretString::new(format!("{}_http", username))),
+            ilp_over_http_outgoing_token: None,
+            ilp_over_btp_url: None,
+            ilp_over_btp_incoming_token: Some(SecretString::new(format!("{}_btp", username))),
+            ilp_over_btp_outgoing_token: None,
+            settle_threshold: None,
+            settle_to: None,
+            min_settlement_interval: None,
+            routing_relation: Some("Child".to_owned()),
+            round_trip_time: None,
+            min_expiry_window: None,
+            amount_per_minute_limit: None,
+            packets_per_minute_limit: None,
+            packets_per_second_limit: None,
+            packet_burst_limit: None,
+            route_policy: None,
+            settlement_engine_url: None,
+        }
+    }
+}
+
+mod store_helpers {
+    use super::fixtures::account_details;
+    use interledger_api::NodeStore;
+    use interledger_packet::Address;
+    use interledger_store::{account::Account, memory::InMemoryStore};
+    use std::str::FromStr;
+
+    /// Creates a store for the node `example.node` with the accounts `alice` and `bob`
+    pub async fn test_store() -> (InMemoryStore, Vec<Account>) {
+        let store = InMemoryStore::new(Address::from_str("example.node").unwrap());
+        let alice = store
+            .insert_account(account_details("alice"))
+            .await
+            .unwrap();
+        let bob = store.insert_account(account_details("bob")).await.unwrap();
+        (store, vec![alice, bob])
+    }
+}
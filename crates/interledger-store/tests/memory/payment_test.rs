@@ -0,0 +1,185 @@
+use super::store_helpers::*;
+
+use bytes::Bytes;
+use futures::{channel::mpsc::unbounded, StreamExt};
+use interledger_api::NodeApi;
+use interledger_btp::BtpOutgoingService;
+use interledger_packet::{ErrorCode, RejectBuilder};
+use interledger_rates::ExchangeRateStore;
+use interledger_router::Router;
+use interledger_service::{
+    outgoing_service_fn, Account as AccountTrait, AddressStore, IncomingService, OutgoingRequest,
+};
+use interledger_service_util::{BalanceService, BalanceStore};
+use interledger_store::{account::Account, memory::InMemoryStore};
+use interledger_stream::{
+    send_money, AccountNotification, ConnectionGenerator, SendMoneyOptions,
+    StreamNotificationsStore, StreamReceiverService,
+};
+use std::{collections::HashMap, time::Duration};
+
+/// Routes packets between the store's accounts, fulfilling the ones addressed to them with STREAM
+fn local_router(
+    store: InMemoryStore,
+    server_secret: Bytes,
+) -> impl IncomingService<Account> + Clone + Send + Sync + 'static {
+    let receiver = StreamReceiverService::new(
+        server_secret,
+        store.clone(),
+        outgoing_service_fn(|request: OutgoingRequest<Account>| {
+            Err(RejectBuilder {
+                code: ErrorCode::F02_UNREACHABLE,
+                message: b"no outgoing links",
+                triggered_by: Some(request.to.ilp_address()),
+                data: &[],
+            }
+            .build())
+        }),
+    );
+    Router::new(store.clone(), BalanceService::new(store, None, receiver))
+}
+
+#[tokio::test]
+async fn sends_payment_between_local_accounts() {
+    let (store, accounts) = test_store().await;
+    let (alice, bob) = (&accounts[0], &accounts[1]);
+    store
+        .set_exchange_rates(
+            vec![("XYZ".to_string(), 1.0)]
+                .into_iter()
+                .collect::<HashMap<_, _>>(),
+        )
+        .unwrap();
+    let (notification_tx, mut notifications) = unbounded();
+    store.add_payment_notification_subscription(bob.id(), notification_tx);
+
+    let server_secret = Bytes::from(&[0; 32][..]);
+    let router = local_router(store.clone(), server_secret.clone());
+
+    let (destination, shared_secret) =
+        ConnectionGenerator::new(server_secret).generate_address_and_secret(bob.ilp_address());
+    let delivery = send_money(
+        router,
+        alice,
+        store.clone(),
+        destination,
+        shared_secret.to_vec(),
+        5000,
+        SendMoneyOptions {
+            slippage: 0.0,
+            ..SendMoneyOptions::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(delivery.delivered_amount, 5000);
+    assert_eq!(store.get_balance(alice.id()).await.unwrap(), -5000);
+
+    // The receiving side's balance is credited in the background once the fulfill comes back
+    let mut bob_balance = 0;
+    for _ in 0..50 {
+        bob_balance = store.get_balance(bob.id()).await.unwrap();
+        if bob_balance == 5000 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(bob_balance, 5000);
+
+    let notification = notifications.next().await.unwrap();
+    assert_eq!(notification.to_username.as_ref(), "bob");
+    assert_eq!(notification.from_username.as_ref(), "alice");
+}
+
+#[tokio::test]
+async fn streams_account_notifications_over_websocket() {
+    let (store, accounts) = test_store().await;
+    let (alice, bob) = (&accounts[0], &accounts[1]);
+    store
+        .set_exchange_rates(
+            vec![("XYZ".to_string(), 1.0)]
+                .into_iter()
+                .collect::<HashMap<_, _>>(),
+        )
+        .unwrap();
+
+    let server_secret = Bytes::from(&[0; 32][..]);
+    let router = local_router(store.clone(), server_secret.clone());
+    let outgoing = outgoing_service_fn(|_| {
+        Err(RejectBuilder {
+            code: ErrorCode::F02_UNREACHABLE,
+            message: &[],
+            triggered_by: None,
+            data: &[],
+        }
+        .build())
+    });
+    let btp = BtpOutgoingService::new(store.get_ilp_address(), outgoing.clone());
+    let api = NodeApi::new(
+        server_secret.clone(),
+        "admin".to_owned(),
+        store.clone(),
+        router.clone(),
+        outgoing,
+        btp,
+    )
+    .into_warp_filter();
+
+    // Both the account holder and the admin subscribe to bob's notifications
+    let mut subscribers = Vec::new();
+    for token in &["bob_http", "admin"] {
+        let client = warp::test::ws()
+            .path("/accounts/bob/notifications")
+            .header("Authorization", format!("Bearer {}", token))
+            .handshake(api.clone())
+            .await
+            .unwrap();
+        subscribers.push(client);
+    }
+
+    let (destination, shared_secret) =
+        ConnectionGenerator::new(server_secret).generate_address_and_secret(bob.ilp_address());
+    send_money(
+        router,
+        alice,
+        store.clone(),
+        destination,
+        shared_secret.to_vec(),
+        5000,
+        SendMoneyOptions {
+            slippage: 0.0,
+            ..SendMoneyOptions::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    for client in subscribers.iter_mut() {
+        let mut received = 0;
+        let mut balance = 0;
+        while received < 5000 || balance < 5000 {
+            let message = tokio::time::timeout(Duration::from_secs(5), client.recv())
+                .await
+                .expect("timed out waiting for a notification")
+                .unwrap();
+            match serde_json::from_str(message.to_str().unwrap()).unwrap() {
+                AccountNotification::Payment(payment) => {
+                    assert_eq!(payment.to_username.as_ref(), "bob");
+                    assert_eq!(payment.from_username.as_ref(), "alice");
+                    received += payment.amount;
+                }
+                AccountNotification::BalanceChange {
+                    account_id,
+                    balance: new_balance,
+                    ..
+                } => {
+                    assert_eq!(account_id, bob.id());
+                    balance = new_balance;
+                }
+                other => panic!("Unexpected notification: {:?}", other),
+            }
+        }
+        assert_eq!(received, 5000);
+        assert_eq!(balance, 5000);
+    }
+}
@@ -0,0 +1,110 @@
+use super::{fixtures::*, store_helpers::*};
+
+use interledger_api::NodeStore;
+use interledger_packet::Address;
+use interledger_router::{RouterStore, WeightedRoute};
+use interledger_service::{Account as AccountTrait, AccountStore, AddressStore};
+use std::str::FromStr;
+use uuid::Uuid;
+
+#[tokio::test]
+async fn routes_to_local_accounts() {
+    let (store, accounts) = test_store().await;
+    let routes = store.routing_table();
+    assert_eq!(routes.len(), 2);
+    assert_eq!(routes["example.node.alice"], accounts[0].id());
+    assert_eq!(routes["example.node.bob"], accounts[1].id());
+
+    store.delete_account(accounts[0].id()).await.unwrap();
+    assert!(!store.routing_table().contains_key("example.node.alice"));
+}
+
+#[tokio::test]
+async fn configured_routes_take_precedence() {
+    let (store, accounts) = test_store().await;
+    store
+        .set_static_route("example.node.alice".to_string(), accounts[1].id())
+        .await
+        .unwrap();
+    store.set_default_route(accounts[0].id()).await.unwrap();
+
+    let routes = store.routing_table();
+    assert_eq!(routes["example.node.alice"], accounts[1].id());
+    assert_eq!(routes[""], accounts[0].id());
+
+    assert!(store
+        .set_static_route("example.other".to_string(), Uuid::new_v4())
+        .await
+        .is_err());
+    assert!(store
+        .set_static_routes(vec![("example.other".to_string(), Uuid::new_v4())])
+        .await
+        .is_err());
+    assert!(!store.routing_table().contains_key("example.other"));
+}
+
+#[tokio::test]
+async fn sets_and_removes_multipath_routes() {
+    let (store, accounts) = test_store().await;
+    let routes = vec![
+        WeightedRoute {
+            account_id: accounts[0].id(),
+            weight: 3,
+        },
+        WeightedRoute {
+            account_id: accounts[1].id(),
+            weight: 1,
+        },
+    ];
+    store
+        .set_multipath_route("example.remote".to_string(), routes.clone())
+        .await
+        .unwrap();
+    assert_eq!(store.multipath_routing_table()["example.remote"], routes);
+
+    let missing = vec![WeightedRoute {
+        account_id: Uuid::new_v4(),
+        weight: 1,
+    }];
+    assert!(store
+        .set_multipath_route("example.other".to_string(), missing)
+        .await
+        .is_err());
+    assert!(!store
+        .multipath_routing_table()
+        .contains_key("example.other"));
+
+    store
+        .set_multipath_route("example.remote".to_string(), Vec::new())
+        .await
+        .unwrap();
+    assert!(store.multipath_routing_table().is_empty());
+}
+
+#[tokio::test]
+async fn readdresses_accounts_when_the_ilp_address_changes() {
+    let (store, accounts) = test_store().await;
+    let mut parent = account_details("parent");
+    parent.ilp_address = Some(Address::from_str("example.parent").unwrap());
+    parent.routing_relation = Some("Parent".to_owned());
+    store.insert_account(parent).await.unwrap();
+
+    store
+        .set_ilp_address(Address::from_str("example.renamed").unwrap())
+        .await
+        .unwrap();
+    assert_eq!(store.get_ilp_address().to_string(), "example.renamed");
+
+    let loaded = store
+        .get_accounts(vec![accounts[0].id(), accounts[1].id()])
+        .await
+        .unwrap();
+    assert_eq!(loaded[0].ilp_address().to_string(), "example.renamed.alice");
+    assert_eq!(loaded[1].ilp_address().to_string(), "example.renamed.bob");
+    let routes = store.routing_table();
+    assert_eq!(routes["example.renamed.alice"], accounts[0].id());
+    assert!(routes.contains_key("example.parent"));
+
+    store.clear_ilp_address().await.unwrap();
+    assert_eq!(store.get_ilp_address().to_string(), "local.host");
+}
@@ -1,18 +1,19 @@
 use super::{fixtures::*, redis_helpers::*, store_helpers::*};
-use interledger_api::{AccountSettings, NodeStore};
+use interledger_api::{AccountPage, AccountQuery, AccountSettings, NodeStore};
 use interledger_btp::BtpAccount;
-use interledger_ccp::{CcpRoutingAccount, RoutingRelation};
+use interledger_ccp::{CcpRoutingAccount, RouteAction, RoutePolicy, RouteRule, RoutingRelation};
 use interledger_http::HttpAccount;
 use interledger_packet::Address;
 use interledger_service::Account as AccountTrait;
 use interledger_service::{AccountStore, AddressStore, Username};
 use interledger_service_util::BalanceStore;
-use interledger_store::redis::RedisStoreBuilder;
+use interledger_store::{account::Account, redis::RedisStoreBuilder};
 use redis_crate::Client;
 use secrecy::ExposeSecret;
 use secrecy::SecretString;
 use std::default::Default;
 use std::str::FromStr;
+use std::time::Duration;
 use uuid::Uuid;
 
 #[tokio::test]
@@ -196,6 +197,38 @@ async fn update_accounts() {
     assert_eq!(err.to_string(), format!("account `{}` was not found", id));
 }
 
+#[tokio::test]
+async fn saves_and_loads_route_policies() {
+    let (store, _context, accounts) = test_store().await.unwrap();
+    let id = accounts[0].id();
+    let policy = RoutePolicy {
+        rules: vec![RouteRule {
+            action: RouteAction::Deny,
+            prefix: Some("example.provider".to_string()),
+            origin: None,
+        }],
+        default: RouteAction::Allow,
+    };
+    let mut new = ACCOUNT_DETAILS_0.clone();
+    new.route_policy = Some(policy.clone());
+    store.update_account(id, new).await.unwrap();
+
+    let account = store.get_accounts(vec![id]).await.unwrap().remove(0);
+    assert_eq!(account.route_policy(), Some(&policy));
+}
+
+#[tokio::test]
+async fn saves_and_loads_http_timeouts() {
+    let (store, _context, accounts) = test_store().await.unwrap();
+    let id = accounts[0].id();
+    let mut new = ACCOUNT_DETAILS_0.clone();
+    new.ilp_over_http_timeout = Some(5000);
+    store.update_account(id, new).await.unwrap();
+
+    let account = store.get_accounts(vec![id]).await.unwrap().remove(0);
+    assert_eq!(account.get_http_timeout(), Some(Duration::from_secs(5)));
+}
+
 #[tokio::test]
 async fn modify_account_settings_settle_to_overflow() {
     let (store, _context, accounts) = test_store().await.unwrap();
@@ -247,6 +280,7 @@ async fn modify_account_settings() {
         ilp_over_btp_url: Some("http://example.com/accounts/dylan/ilp/btp".to_owned()),
         settle_threshold: Some(-50),
         settle_to: Some(100),
+        min_settlement_interval: Some(1000),
     };
     let account = accounts[0].clone();
 
@@ -346,3 +380,65 @@ async fn errors_for_unknown_accounts() {
         .unwrap_err();
     assert_eq!(err.to_string(), "wrong account length (expected 2, got 0)");
 }
+
+#[tokio::test]
+async fn queries_pages_of_accounts() {
+    let (store, context, _) = test_store().await.unwrap();
+    store
+        .insert_account(ACCOUNT_DETAILS_2.clone())
+        .await
+        .unwrap();
+    let usernames = |page: &AccountPage<Account>| {
+        page.accounts
+            .iter()
+            .map(|account| account.username().to_string())
+            .collect::<Vec<_>>()
+    };
+
+    let page = store
+        .query_accounts(AccountQuery {
+            limit: Some(2),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(usernames(&page), vec!["alice", "bob"]);
+    assert_eq!(page.next_cursor, Some("bob".to_owned()));
+    let page = store
+        .query_accounts(AccountQuery {
+            limit: Some(2),
+            cursor: page.next_cursor,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(usernames(&page), vec!["charlie"]);
+    assert_eq!(page.next_cursor, None);
+
+    let page = store
+        .query_accounts(AccountQuery {
+            asset_code: Some("ABC".to_owned()),
+            routing_relation: Some(RoutingRelation::Child),
+            username_prefix: Some("b".to_owned()),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(usernames(&page), vec!["bob"]);
+    assert_eq!(page.next_cursor, None);
+
+    // Connecting to a database without the sorted username index rebuilds it
+    let client = Client::open(context.get_client_connection_info()).unwrap();
+    let mut connection = client.get_multiplexed_tokio_connection().await.unwrap();
+    let _: redis_crate::Value = redis_crate::cmd("DEL")
+        .arg("usernames:sorted")
+        .query_async(&mut connection)
+        .await
+        .unwrap();
+    let store = RedisStoreBuilder::new(context.get_client_connection_info(), [0; 32])
+        .connect()
+        .await
+        .unwrap();
+    let page = store.query_accounts(AccountQuery::default()).await.unwrap();
+    assert_eq!(usernames(&page), vec!["alice", "bob", "charlie"]);
+}
@@ -1,12 +1,15 @@
 use super::{fixtures::*, store_helpers::*};
 
+use futures::future::join_all;
 use interledger_api::NodeStore;
 use interledger_packet::Address;
 use interledger_service::Account as AccountTrait;
 use interledger_service::{AccountStore, Username};
 use interledger_service_util::BalanceStore;
+use interledger_store::redis::RedisStoreBuilder;
 use redis_crate::AsyncCommands;
 use std::str::FromStr;
+use std::time::Duration;
 use uuid::Uuid;
 
 #[tokio::test]
@@ -297,6 +300,82 @@ async fn enforces_minimum_balance() {
     assert!(err.to_string().contains(&expected));
 }
 
+#[tokio::test]
+async fn allows_going_negative_up_to_minimum_balance() {
+    let (store, _context, accs) = test_store().await.unwrap();
+    let id = accs[0].id();
+    store.update_balances_for_prepare(id, 600).await.unwrap();
+    store.update_balances_for_prepare(id, 400).await.unwrap();
+    assert_eq!(store.get_balance(id).await.unwrap(), -1000);
+
+    assert!(store.update_balances_for_prepare(id, 1).await.is_err());
+    assert_eq!(store.get_balance(id).await.unwrap(), -1000);
+}
+
+#[tokio::test]
+async fn spaces_out_threshold_settlements() {
+    let (store, _context, accs) = test_store().await.unwrap();
+    let id = accs[0].id();
+    let mut details = ACCOUNT_DETAILS_0.clone();
+    details.settle_threshold = Some(100);
+    details.settle_to = Some(10);
+    details.min_settlement_interval = Some(200);
+    store.update_account(id, details).await.unwrap();
+
+    let fulfill = |amount| store.update_balances_for_fulfill(id, amount);
+    assert_eq!(fulfill(150).await.unwrap(), (10, 140));
+    assert_eq!(fulfill(150).await.unwrap(), (160, 0));
+
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    assert_eq!(fulfill(1).await.unwrap(), (10, 151));
+    assert_eq!(fulfill(100).await.unwrap(), (110, 0));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_prepares_never_exceed_minimum_balance() {
+    let (store, context, accs) = test_store().await.unwrap();
+    let id = accs[0].id();
+    // Another node sharing the database, with its own connection
+    let other_store = RedisStoreBuilder::new(context.get_client_connection_info(), [0; 32])
+        .connect()
+        .await
+        .unwrap();
+
+    // 50 prepares of 30 against the min balance of -1000: only 33 fit
+    let results = join_all((0..50).map(|i| {
+        let store = if i % 2 == 0 {
+            store.clone()
+        } else {
+            other_store.clone()
+        };
+        tokio::spawn(async move { store.update_balances_for_prepare(id, 30).await })
+    }))
+    .await;
+    let accepted = results
+        .into_iter()
+        .filter(|result| result.as_ref().unwrap().is_ok())
+        .count();
+    assert_eq!(accepted, 33);
+    assert_eq!(store.get_balance(id).await.unwrap(), -990);
+
+    join_all((0..accepted).map(|_| store.update_balances_for_reject(id, 30))).await;
+    assert_eq!(store.get_balance(id).await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn fulfill_and_reject_do_not_recreate_deleted_accounts() {
+    let (store, context, accs) = test_store().await.unwrap();
+    let id = accs[0].id();
+    store.update_balances_for_prepare(id, 100).await.unwrap();
+    store.delete_account(id).await.unwrap();
+
+    assert!(store.update_balances_for_reject(id, 100).await.is_err());
+    assert!(store.update_balances_for_fulfill(id, 100).await.is_err());
+    let mut connection = context.async_connection().await.unwrap();
+    let exists: bool = connection.exists(format!("accounts:{}", id)).await.unwrap();
+    assert!(!exists);
+}
+
 #[tokio::test]
 // Prepare and Fulfill a packet for 100 units from Account 0 to Account 1
 // Then, Prepare and Fulfill a packet for 80 units from Account 1 to Account 0
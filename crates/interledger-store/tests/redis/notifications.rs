@@ -1,12 +1,15 @@
 use super::{fixtures::*, redis_helpers::*};
-use futures::{FutureExt, StreamExt};
+use futures::{channel::mpsc::unbounded, FutureExt, StreamExt};
 use interledger_api::NodeStore;
 use interledger_packet::Address;
 use interledger_service::Account as AccountTrait;
 use interledger_store::redis::RedisStoreBuilder;
-use interledger_stream::{PaymentNotification, StreamNotificationsStore};
+use interledger_stream::{
+    AccountNotification, AccountNotificationsStore, PaymentNotification, StreamNotificationsStore,
+};
 use std::str::FromStr;
 use tokio_stream::wrappers::BroadcastStream;
+use uuid::Uuid;
 
 #[tokio::test]
 async fn notifications_on_multitenant_config() {
@@ -46,6 +49,9 @@ async fn notifications_on_multitenant_config() {
         timestamp: String::from("2021-04-04T12:11:11.987+00:00"),
         sequence: 2,
         connection_closed: false,
+        close_code: None,
+        close_message: None,
+        source_asset: None,
     };
 
     let second_pmt = PaymentNotification {
@@ -56,6 +62,9 @@ async fn notifications_on_multitenant_config() {
         timestamp: String::from("2021-04-04T12:11:10.987+00:00"),
         sequence: 1,
         connection_closed: false,
+        close_code: None,
+        close_message: None,
+        source_asset: None,
     };
 
     // do the test in a loop since sometimes the psubscribe functionality just isn't ready
@@ -103,3 +112,60 @@ async fn notifications_on_multitenant_config() {
 
     unreachable!("did not complete with retries");
 }
+
+#[tokio::test]
+async fn account_notifications_reach_other_nodes_only_if_published() {
+    let context = TestContext::new();
+    let node = RedisStoreBuilder::new(context.get_client_connection_info(), [0; 32])
+        .connect()
+        .await
+        .unwrap();
+    let publishing_node = RedisStoreBuilder::new(context.get_client_connection_info(), [0; 32])
+        .publish_account_notifications(true)
+        .connect()
+        .await
+        .unwrap();
+    let other_node = RedisStoreBuilder::new(context.get_client_connection_info(), [0; 32])
+        .connect()
+        .await
+        .unwrap();
+    let account_id = Uuid::new_v4();
+    let (tx, mut node_rx) = unbounded();
+    node.add_account_notification_subscription(account_id, tx);
+    let (tx, mut other_node_rx) = unbounded();
+    other_node.add_account_notification_subscription(account_id, tx);
+
+    // Without publishing, only the subscribers of the node are notified, right away
+    node.publish_account_notification(
+        account_id,
+        AccountNotification::balance_change(account_id, 100),
+    );
+    match node_rx.try_next().unwrap().unwrap() {
+        AccountNotification::BalanceChange { balance, .. } => assert_eq!(balance, 100),
+        other => panic!("Unexpected notification: {:?}", other),
+    }
+
+    // do the test in a loop since sometimes the psubscribe functionality just isn't ready
+    for _ in 0..10 {
+        publishing_node.publish_account_notification(
+            account_id,
+            AccountNotification::balance_change(account_id, 200),
+        );
+
+        let deadline = std::time::Duration::from_millis(1000);
+        let read_both = futures::future::join(node_rx.next(), other_node_rx.next());
+        let (msg1, msg2) = match tokio::time::timeout(deadline, read_both).await {
+            Ok(messages) => messages,
+            Err(tokio::time::error::Elapsed { .. }) => continue,
+        };
+        for msg in [msg1, msg2] {
+            match msg.unwrap() {
+                AccountNotification::BalanceChange { balance, .. } => assert_eq!(balance, 200),
+                other => panic!("Unexpected notification: {:?}", other),
+            }
+        }
+        return;
+    }
+
+    unreachable!("did not complete with retries");
+}
@@ -26,6 +26,7 @@ mod fixtures {
         max_packet_amount: 1000,
         min_balance: Some(-1000),
         ilp_over_http_url: Some("http://example.com/accounts/dylan/ilp".to_string()),
+        ilp_over_http_timeout: None,
         ilp_over_http_incoming_token: Some(SecretString::new("incoming_auth_token".to_string())),
         ilp_over_http_outgoing_token: Some(SecretString::new("outgoing_auth_token".to_string())),
         ilp_over_btp_url: Some("btp+ws://example.com/accounts/dylan/ilp/btp".to_string()),
@@ -33,10 +34,15 @@ mod fixtures {
         ilp_over_btp_outgoing_token: Some(SecretString::new("btp_token".to_string())),
         settle_threshold: Some(0),
         settle_to: Some(-1000),
+        min_settlement_interval: None,
         routing_relation: Some("Parent".to_owned()),
         round_trip_time: None,
+        min_expiry_window: None,
         amount_per_minute_limit: Some(1000),
         packets_per_minute_limit: Some(2),
+        packets_per_second_limit: None,
+        packet_burst_limit: None,
+        route_policy: None,
         settlement_engine_url: Some("http://settlement.example".to_string()),
     });
     pub static ACCOUNT_DETAILS_1: Lazy<AccountDetails> = Lazy::new(|| AccountDetails {
@@ -47,6 +53,7 @@ mod fixtures {
         max_packet_amount: 1_000_000,
         min_balance: Some(0),
         ilp_over_http_url: Some("http://example.com/accounts/dylan/ilp".to_string()),
+        ilp_over_http_timeout: None,
         // incoming token has is the account's username concatenated wiht the password
         ilp_over_http_incoming_token: Some(SecretString::new("incoming_auth_token".to_string())),
         ilp_over_http_outgoing_token: Some(SecretString::new("outgoing_auth_token".to_string())),
@@ -55,10 +62,15 @@ mod fixtures {
         ilp_over_btp_outgoing_token: Some(SecretString::new("btp_token".to_string())),
         settle_threshold: Some(0),
         settle_to: Some(-1000),
+        min_settlement_interval: None,
         routing_relation: Some("Child".to_owned()),
         round_trip_time: None,
+        min_expiry_window: None,
         amount_per_minute_limit: Some(1000),
         packets_per_minute_limit: Some(20),
+        packets_per_second_limit: None,
+        packet_burst_limit: None,
+        route_policy: None,
         settlement_engine_url: None,
     });
     pub static ACCOUNT_DETAILS_2: Lazy<AccountDetails> = Lazy::new(|| AccountDetails {
@@ -69,6 +81,7 @@ mod fixtures {
         max_packet_amount: 1000,
         min_balance: Some(0),
         ilp_over_http_url: None,
+        ilp_over_http_timeout: None,
         ilp_over_http_incoming_token: None,
         ilp_over_http_outgoing_token: None,
         ilp_over_btp_url: None,
@@ -76,10 +89,15 @@ mod fixtures {
         ilp_over_btp_outgoing_token: None,
         settle_threshold: Some(0),
         settle_to: None,
+        min_settlement_interval: None,
         routing_relation: None,
         round_trip_time: None,
+        min_expiry_window: None,
         amount_per_minute_limit: None,
         packets_per_minute_limit: None,
+        packets_per_second_limit: None,
+        packet_burst_limit: None,
+        route_policy: None,
         settlement_engine_url: None,
     });
 }
@@ -3,7 +3,7 @@ use super::{fixtures::*, redis_helpers::*, store_helpers::*};
 use interledger_api::{AccountDetails, NodeStore};
 use interledger_ccp::CcpRoutingStore;
 use interledger_packet::Address;
-use interledger_router::RouterStore;
+use interledger_router::{RouterStore, WeightedRoute};
 use interledger_service::{Account as AccountTrait, AddressStore, Username};
 use interledger_store::{account::Account, redis::RedisStoreBuilder};
 use std::str::FromStr;
@@ -40,6 +40,7 @@ async fn polls_for_route_updates() {
             max_packet_amount: 1000,
             min_balance: Some(-1000),
             ilp_over_http_url: None,
+            ilp_over_http_timeout: None,
             ilp_over_http_incoming_token: None,
             ilp_over_http_outgoing_token: None,
             ilp_over_btp_url: None,
@@ -47,10 +48,15 @@ async fn polls_for_route_updates() {
             ilp_over_btp_incoming_token: None,
             settle_threshold: None,
             settle_to: None,
+            min_settlement_interval: None,
             routing_relation: Some("Peer".to_owned()),
             round_trip_time: None,
+            min_expiry_window: None,
             amount_per_minute_limit: None,
             packets_per_minute_limit: None,
+            packets_per_second_limit: None,
+            packet_burst_limit: None,
+            route_policy: None,
             settlement_engine_url: None,
         })
         .await
@@ -229,6 +235,43 @@ async fn static_routes_override_others() {
     assert_eq!(routes.len(), 3);
 }
 
+#[tokio::test]
+async fn saves_and_loads_multipath_routes() {
+    let (store, context, accs) = test_store().await.unwrap();
+    let routes = vec![
+        WeightedRoute {
+            account_id: accs[0].id(),
+            weight: 2,
+        },
+        WeightedRoute {
+            account_id: accs[1].id(),
+            weight: 5,
+        },
+    ];
+    store
+        .set_multipath_route("example.remote".to_string(), routes.clone())
+        .await
+        .unwrap();
+    assert_eq!(store.multipath_routing_table()["example.remote"], routes);
+
+    // Another node sharing the database loads the routes too
+    let other_store = RedisStoreBuilder::new(context.get_client_connection_info(), [0; 32])
+        .connect()
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(
+        other_store.multipath_routing_table()["example.remote"],
+        routes
+    );
+
+    store
+        .set_multipath_route("example.remote".to_string(), Vec::new())
+        .await
+        .unwrap();
+    assert!(store.multipath_routing_table().is_empty());
+}
+
 #[tokio::test]
 async fn default_route() {
     let (store, _context, accs) = test_store().await.unwrap();
@@ -7,7 +7,10 @@ use interledger_service::{Account, AccountStore};
 use interledger_service_util::BalanceStore;
 use interledger_settlement::core::{
     idempotency::{IdempotentData, IdempotentStore},
-    types::{LeftoversStore, SettlementAccount, SettlementStore},
+    types::{
+        LeftoversStore, PendingSettlement, PendingSettlementStore, SettlementAccount,
+        SettlementStore,
+    },
 };
 use num_bigint::BigUint;
 use once_cell::sync::Lazy;
@@ -18,6 +21,36 @@ use uuid::Uuid;
 
 static IDEMPOTENCY_KEY: Lazy<String> = Lazy::new(|| String::from("AJKJNUjM0oyiAN46"));
 
+#[tokio::test]
+async fn saves_loads_and_clears_pending_settlements() {
+    let (store, _context, accs) = test_store().await.unwrap();
+    let settlement = PendingSettlement::new(
+        accs[0].id(),
+        Url::parse("http://settlement.example").unwrap(),
+        100,
+        9,
+    );
+    store
+        .save_pending_settlement(settlement.clone())
+        .await
+        .unwrap();
+    assert_eq!(
+        store.load_pending_settlements().await.unwrap(),
+        vec![settlement.clone()]
+    );
+
+    assert!(store
+        .clear_pending_settlement(&settlement.idempotency_key)
+        .await
+        .unwrap());
+    // Only the first clear reports the settlement as pending
+    assert!(!store
+        .clear_pending_settlement(&settlement.idempotency_key)
+        .await
+        .unwrap());
+    assert!(store.load_pending_settlements().await.unwrap().is_empty());
+}
+
 #[tokio::test]
 async fn saves_gets_clears_uncredited_settlement_amount_properly() {
     let (store, _context, _accs) = test_store().await.unwrap();
@@ -1,13 +1,21 @@
-use super::congestion::CongestionController;
+use super::congestion::{CongestionControl, CongestionController};
 use super::crypto::*;
-use super::error::Error;
+use super::data::{
+    connection_max_offset, connection_window, max_data_frames, DataStream, MAX_DATA_PER_PACKET,
+};
+use super::error::{PaymentProgress, RejectReason, StreamError};
 use super::packet::*;
+use super::probe::probe_rate;
+use super::receipt::Receipt;
+use super::retry::RetryPolicy;
+use super::server::AssetDetails;
 use bytes::Bytes;
 use bytes::BytesMut;
+use futures::future::poll_fn;
 use futures::stream::{FuturesUnordered, StreamExt};
 use interledger_packet::{
-    Address, ErrorClass, ErrorCode as IlpErrorCode, PacketType as IlpPacketType, PrepareBuilder,
-    Reject,
+    hex::HexString, Address, ErrorClass, ErrorCode as IlpErrorCode, PacketType as IlpPacketType,
+    PrepareBuilder, Reject, RejectBuilder,
 };
 use interledger_rates::ExchangeRateStore;
 use interledger_service::*;
@@ -17,13 +25,16 @@ use num::traits::identities::{One, Zero};
 use num::traits::ops::checked::CheckedDiv;
 use num::traits::pow::pow;
 use num::BigInt;
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 use tokio::sync::Mutex;
-use tokio::time::timeout_at;
+use tokio::time::{sleep, sleep_until, timeout_at};
 use tokio::time::{Duration, Instant};
 use tracing::{debug, error, warn};
 
 use std::cmp::{max, min};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
 use std::marker::{Send, Sync};
 use std::str;
 use std::sync::Arc;
@@ -33,14 +44,27 @@ use std::time::SystemTime;
 /// getting into an infinite loop of sending packets and effectively DoSing ourselves
 const MAX_TIME_SINCE_LAST_FULFILL: Duration = Duration::from_secs(30);
 
+/// Expiry of the packets sent before a round trip time was measured
+const DEFAULT_PACKET_EXPIRY: Duration = Duration::from_secs(30);
+/// Shortest expiry of a packet, leaving each connector on the path time to forward it
+const MIN_PACKET_EXPIRY: Duration = Duration::from_secs(5);
+/// Longest expiry of a packet, to bound how long the money in it may be held
+const MAX_PACKET_EXPIRY: Duration = Duration::from_secs(30);
+/// Safety margin added to the smoothed round trip time, as a multiple of it
+const PACKET_EXPIRY_RTT_MARGIN: u32 = 3;
+
 /// Minimum number of packet attempts before defaulting to failure rate
 const FAIL_FAST_MINIMUM_PACKET_ATTEMPTS: u64 = 200;
 
 /// Minimum rate of rejected packets in order to terminate the payment
 const FAIL_FAST_MINIMUM_FAILURE_RATE: f64 = 0.99;
 
+/// Time to wait before sending data again when a packet didn't reach the receiver
+const DATA_RETRY_DELAY: Duration = Duration::from_millis(100);
+
 /// Receipt for STREAM payment to account for how much and what assets were sent & delivered
-#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct StreamDelivery {
     /// Sender's ILP Address
     pub from: Address,
@@ -64,6 +88,77 @@ pub struct StreamDelivery {
     /// Receiver's asset code
     /// Updated after we received a `ConnectionAssetDetails` frame.
     pub destination_asset_code: Option<String>,
+    /// Money sent on each of the connection's streams, by stream id
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub streams: BTreeMap<u64, StreamTotals>,
+    /// Number of Prepare packets sent with money, including the ones that were retried
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub attempts: u64,
+    /// Outcome of every packet, in the order their replies came back. Only recorded by
+    /// [`send_money`](./fn.send_money.html) when asked to in its
+    /// [options](./struct.SendMoneyOptions.html#structfield.record_packet_outcomes)
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub packets: Option<Vec<PacketOutcome>>,
+}
+
+/// What happened to a single Prepare sent for a payment
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PacketOutcome {
+    /// Sequence number of the packet
+    pub sequence: u64,
+    /// Amount sent, in source units
+    pub source_amount: u64,
+    /// Amount delivered to the recipient, in destination units. Zero unless fulfilled
+    pub delivered_amount: u64,
+    /// Whether the packet was fulfilled
+    pub fulfilled: bool,
+    /// Code the packet was rejected with, if it was
+    pub reject_code: Option<IlpErrorCode>,
+}
+
+/// Money sent on a single stream of a STREAM connection, in destination units
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct StreamTotals {
+    /// Amount fulfilled on this stream, split across streams by the shares we sent
+    pub delivered_amount: u64,
+    /// Minimum destination amount of the packets in flight on this stream
+    pub in_flight_amount: u64,
+    /// Most the recipient will accept on this stream, once it has sent a `StreamMaxMoney` frame
+    pub receive_max: Option<u64>,
+    /// Highest total the recipient reported having received on this stream
+    pub total_received: u64,
+    /// Receipt for the highest total the recipient signed on this stream, if the connection
+    /// was set up with receipts. Serialized as hex.
+    #[cfg_attr(feature = "serde", serde(default, with = "hex_receipt"))]
+    pub receipt: Option<Bytes>,
+}
+
+impl StreamTotals {
+    /// Total received on this stream according to the latest receipt.
+    ///
+    /// We don't know the receipt secret, so this is only as trustworthy as the recipient until
+    /// the receipt is [verified](./struct.Receipt.html#method.verify) by whoever holds it.
+    pub fn receipt_total_received(&self) -> Option<u64> {
+        let receipt = Receipt::decode(self.receipt.as_ref()?).ok()?;
+        Some(receipt.total_received)
+    }
+
+    /// Amount the recipient is still willing to receive on this stream on top of what's
+    /// delivered and in flight, or `None` if it hasn't advertised a limit
+    #[inline]
+    fn get_amount_left_to_receive(&self) -> Option<u64> {
+        let received = max(self.total_received, self.delivered_amount);
+        Some(
+            self.receive_max?
+                .saturating_sub(received)
+                .saturating_sub(self.in_flight_amount),
+        )
+    }
 }
 
 impl StreamDelivery {
@@ -79,14 +174,17 @@ impl StreamDelivery {
             destination_asset_scale: None,
             destination_asset_code: None,
             delivered_amount: 0,
+            streams: std::iter::once((1, StreamTotals::default())).collect(),
+            attempts: 0,
+            packets: None,
         }
     }
 }
 
 /// Stream payment mutable state: amounts & assets sent and received, sequence, packet counts, and flow control parameters
 struct StreamPayment {
-    /// The [congestion controller](./../congestion/struct.CongestionController.html) to adjust flow control and the in-flight amount
-    congestion_controller: CongestionController,
+    /// The [congestion controller](./../congestion/trait.CongestionControl.html) to adjust flow control and the in-flight amount
+    congestion_controller: Box<dyn CongestionControl + Send>,
     /// The [StreamDelivery](./struct.StreamDelivery.html) receipt to account for the delivered amounts
     receipt: StreamDelivery,
     /// Do we need to send our source account information to the recipient?
@@ -99,27 +197,37 @@ struct StreamPayment {
     rejected_packets: u64,
     /// Number of rejected packets applied to the fail-fast threshold
     fail_fast_rejects: u64,
+    /// How to back off from temporary rejects
+    retry_policy: RetryPolicy,
+    /// Number of packets rejected with temporary errors since the last fulfill
+    temporary_rejects: u32,
+    /// Code and message of the last temporary reject, if any since the last fulfill
+    last_temporary_reject: Option<(IlpErrorCode, String)>,
+    /// Time the retry policy lets us send the next packet after a temporary reject
+    retry_time: Option<Instant>,
     /// Timestamp when a packet was last fulfilled for this payment
     last_fulfill_time: Instant,
+    /// Timestamp when a packet was last prepared for this payment, if any
+    last_prepare_time: Option<Instant>,
+    /// Code and message the recipient closed the connection with, if it did
+    closed_by_peer: Option<(ErrorCode, String)>,
+    /// Code and message of the last rejected packet, if any
+    last_reject: Option<(IlpErrorCode, String)>,
+    /// Least amount the recipient must get for the whole source amount, if the sender set one
+    min_delivery_amount: Option<u64>,
 }
 
 impl StreamPayment {
     /// Determine amount to load in next Prepare and account for it.
-    /// Return the source packet amount and minimum destination amount
+    /// Return the sequence number the congestion controller gave the packet, the source packet
+    /// amount, minimum destination amount and its split across streams
     #[inline]
-    fn apply_prepare<S: ExchangeRateStore>(&mut self, store: &S, slippage: f64) -> (u64, u64) {
-        // Determine scaled rate with slippage used for enforcing minimum destination amount
-        // and computing its corresponding minimum source amount,
-        // where source_amount * scaled_rate = dest_amount.
-        let rate = get_rate(
-            store,
-            self.receipt.source_asset_scale,
-            &self.receipt.source_asset_code,
-            self.receipt.destination_asset_scale,
-            self.receipt.destination_asset_code.as_deref(),
-            slippage,
-        )
-        .unwrap_or_else(BigRational::zero);
+    fn apply_prepare<S: ExchangeRateStore>(
+        &mut self,
+        store: &S,
+        slippage: f64,
+    ) -> (u64, u64, u64, Vec<(u64, u64)>) {
+        let rate = self.get_rate(store, slippage);
 
         // Margin of error is the minimum difference between our scaled rate and scaled rate of intermediaries.
         // This should probably be much smaller than the slippage we're willing to accept.
@@ -141,13 +249,13 @@ impl StreamPayment {
 
         // Compute the source amount. In reverse order of precedence:
 
-        // (5) Amount left in window for congestion
+        // (6) Amount left in window for congestion
         let mut source_amount = self.congestion_controller.get_amount_left_in_window();
 
-        // (4) Min source amount so rounding errors don't prevent delivery
+        // (5) Min source amount so rounding errors don't prevent delivery
         source_amount = max(source_amount, min_source_amount);
 
-        // (3) Distribute "dust" amount across remaining packets
+        // (4) Distribute "dust" amount across remaining packets
 
         // By chance at end of payment, the amount remaining is too small to deliver.
         // Approximate the remaining number of packets.
@@ -165,29 +273,125 @@ impl StreamPayment {
                 (remaining_amount + (estimated_num_packets - 1)) / estimated_num_packets;
         }
 
-        // (2) Max packet amount allowed by nodes in path
+        // (3) Max packet amount allowed by nodes in path
         source_amount = min(
             source_amount,
             self.congestion_controller.get_max_packet_amount(),
         );
 
+        // (2) Amount the recipient is still willing to receive
+        if let Some(receive_window) = self.get_amount_left_in_receive_window(&rate) {
+            source_amount = min(source_amount, receive_window);
+        }
+
         // (1) Amount available to send, subtracting fulfilled and in-fligth amounts
         source_amount = min(source_amount, self.get_amount_available_to_send());
 
         // Account for the prepare
-        self.congestion_controller.prepare(source_amount);
+        let congestion_sequence = self.congestion_controller.prepare(source_amount);
+        self.last_prepare_time = Some(Instant::now());
+        self.receipt.attempts += 1;
         self.receipt.sent_amount = self.receipt.sent_amount.saturating_add(source_amount);
         self.receipt.in_flight_amount = self.receipt.in_flight_amount.saturating_add(source_amount);
 
-        // Compute the minimum destination amount using the same rate
-        let min_destination_amount = convert(source_amount, rate).unwrap_or(0);
-        (source_amount, min_destination_amount)
+        // Compute the minimum destination amount using the same rate. Each packet must also
+        // deliver its share of the minimum delivery amount, so that the payment as a whole
+        // cannot deliver less if the rate moves.
+        let min_destination_amount = max(
+            convert(source_amount, rate).unwrap_or(0),
+            self.get_min_delivery_share(source_amount),
+        );
+
+        let shares = self.allocate_to_streams(min_destination_amount);
+        for &(stream_id, share) in &shares {
+            let stream = self.receipt.streams.entry(stream_id).or_default();
+            stream.in_flight_amount = stream.in_flight_amount.saturating_add(share);
+        }
+
+        (
+            congestion_sequence,
+            source_amount,
+            min_destination_amount,
+            shares,
+        )
+    }
+
+    /// Part of the minimum delivery amount a packet with the given source amount must deliver,
+    /// rounded up
+    #[inline]
+    fn get_min_delivery_share(&self, source_amount: u64) -> u64 {
+        match self.min_delivery_amount {
+            Some(min_delivery_amount) if self.receipt.source_amount > 0 => {
+                let share = (u128::from(source_amount) * u128::from(min_delivery_amount))
+                    .div_ceil(u128::from(self.receipt.source_amount));
+                u64::try_from(share).unwrap_or(u64::MAX)
+            }
+            _ => 0,
+        }
+    }
+
+    /// Split a packet's destination amount across the streams as (stream id, amount) pairs.
+    /// Streams are filled in order of their ids up to what the recipient is still willing to
+    /// receive on each; anything left over from rounding goes to the last stream.
+    #[inline]
+    fn allocate_to_streams(&self, destination_amount: u64) -> Vec<(u64, u64)> {
+        let mut left = destination_amount;
+        let mut shares = Vec::new();
+        for (&stream_id, stream) in &self.receipt.streams {
+            if left == 0 {
+                break;
+            }
+            let share = stream
+                .get_amount_left_to_receive()
+                .map_or(left, |room| min(room, left));
+            if share > 0 {
+                shares.push((stream_id, share));
+                left -= share;
+            }
+        }
+
+        if let (true, Some(&last)) = (left > 0, self.receipt.streams.keys().next_back()) {
+            match shares.last_mut() {
+                Some((stream_id, share)) if *stream_id == last => *share += left,
+                _ => shares.push((last, left)),
+            }
+        }
+        shares
     }
 
     /// Account for a fulfilled packet and update flow control
     #[inline]
-    fn apply_fulfill(&mut self, source_amount: u64, destination_amount: u64) {
-        self.congestion_controller.fulfill(source_amount);
+    fn apply_fulfill(
+        &mut self,
+        congestion_sequence: u64,
+        source_amount: u64,
+        destination_amount: u64,
+        shares: &[(u64, u64)],
+    ) {
+        self.congestion_controller
+            .fulfill(congestion_sequence, source_amount);
+
+        // Credit the streams in proportion to their shares, the last one taking the remainder
+        let total_shares: u64 = shares.iter().map(|&(_, share)| share).sum();
+        let mut left = destination_amount;
+        for (i, &(stream_id, share)) in shares.iter().enumerate() {
+            let delivered = if i + 1 == shares.len() {
+                left
+            } else {
+                (u128::from(destination_amount) * u128::from(share) / u128::from(total_shares))
+                    as u64
+            };
+            left -= delivered;
+            let stream = self.receipt.streams.entry(stream_id).or_default();
+            stream.in_flight_amount = stream.in_flight_amount.saturating_sub(share);
+            stream.delivered_amount = stream.delivered_amount.saturating_add(delivered);
+        }
+        if shares.is_empty() {
+            if let Some(stream) = self.receipt.streams.values_mut().next() {
+                stream.delivered_amount =
+                    stream.delivered_amount.saturating_add(destination_amount);
+            }
+        }
 
         self.receipt.in_flight_amount = self.receipt.in_flight_amount.saturating_sub(source_amount);
         self.receipt.delivered_amount = self
@@ -197,17 +401,36 @@ impl StreamPayment {
 
         self.last_fulfill_time = Instant::now();
         self.fulfilled_packets += 1;
+        self.temporary_rejects = 0;
+        self.last_temporary_reject = None;
+        self.retry_time = None;
     }
 
     /// Account for a rejected packet and update flow control
     #[inline]
-    fn apply_reject(&mut self, amount: u64, reject: &Reject) {
-        self.congestion_controller.reject(amount, reject);
+    fn apply_reject(
+        &mut self,
+        congestion_sequence: u64,
+        amount: u64,
+        shares: &[(u64, u64)],
+        reject: &Reject,
+    ) {
+        self.congestion_controller
+            .reject(congestion_sequence, amount, reject);
+
+        for &(stream_id, share) in shares {
+            let stream = self.receipt.streams.entry(stream_id).or_default();
+            stream.in_flight_amount = stream.in_flight_amount.saturating_sub(share);
+        }
 
         self.receipt.sent_amount = self.receipt.sent_amount.saturating_sub(amount);
         self.receipt.in_flight_amount = self.receipt.in_flight_amount.saturating_sub(amount);
 
         self.rejected_packets += 1;
+        self.last_reject = Some((
+            reject.code(),
+            String::from_utf8_lossy(reject.message()).into_owned(),
+        ));
 
         // Apply F99, T00, T01 to fail-fast threshold.
         // Other final/relative errors should immediately fail; T02-T99 may be resolved with time.
@@ -220,6 +443,112 @@ impl StreamPayment {
         if apply_to_fail_fast {
             self.fail_fast_rejects += 1;
         }
+
+        // Back off before trying again
+        if reject.code().class() == ErrorClass::Temporary {
+            self.temporary_rejects = self.temporary_rejects.saturating_add(1);
+            self.last_temporary_reject = Some((
+                reject.code(),
+                String::from_utf8_lossy(reject.message()).into_owned(),
+            ));
+            self.retry_time =
+                Instant::now().checked_add(self.retry_policy.delay(self.temporary_rejects));
+        }
+    }
+
+    /// Keep the outcome of a packet, if the caller asked for them
+    #[inline]
+    fn record_packet_outcome(&mut self, outcome: PacketOutcome) {
+        if let Some(packets) = self.receipt.packets.as_mut() {
+            packets.push(outcome);
+        }
+    }
+
+    /// Determine scaled rate with slippage used for enforcing minimum destination amount
+    /// and computing its corresponding minimum source amount,
+    /// where source_amount * scaled_rate = dest_amount. Zero if the rate is unknown.
+    #[inline]
+    fn get_rate<S: ExchangeRateStore>(&self, store: &S, slippage: f64) -> BigRational {
+        get_rate(
+            store,
+            self.receipt.source_asset_scale,
+            &self.receipt.source_asset_code,
+            self.receipt.destination_asset_scale,
+            self.receipt.destination_asset_code.as_deref(),
+            slippage,
+        )
+        .or_else(|| self.get_min_delivery_rate())
+        .unwrap_or_else(BigRational::zero)
+    }
+
+    /// Rate at which the whole source amount delivers exactly the minimum delivery amount, for
+    /// when the store has no rate for the assets
+    #[inline]
+    fn get_min_delivery_rate(&self) -> Option<BigRational> {
+        let min_delivery_amount = self.min_delivery_amount.filter(|&amount| amount > 0)?;
+        if self.receipt.source_amount == 0 {
+            return None;
+        }
+        Some(BigRational::new(
+            BigInt::from(min_delivery_amount),
+            BigInt::from(self.receipt.source_amount),
+        ))
+    }
+
+    /// Save the flow control limit the recipient advertised for one of our streams in a
+    /// `StreamMaxMoney` frame
+    #[inline]
+    fn set_remote_receive_max(&mut self, stream_id: u64, receive_max: u64, total_received: u64) {
+        if let Some(stream) = self.receipt.streams.get_mut(&stream_id) {
+            stream.receive_max = Some(receive_max);
+            stream.total_received = max(stream.total_received, total_received);
+        }
+    }
+
+    /// Stop sending money on a stream the recipient closed
+    #[inline]
+    fn close_remote_stream(&mut self, stream_id: u64) {
+        if let Some(stream) = self.receipt.streams.get_mut(&stream_id) {
+            stream.receive_max = Some(max(stream.total_received, stream.delivered_amount));
+        }
+    }
+
+    /// Keep a receipt the recipient sent for one of our streams if it covers more money than
+    /// the one we have
+    #[inline]
+    fn set_receipt(&mut self, stream_id: u64, receipt: &[u8]) {
+        let total_received = match Receipt::decode(receipt) {
+            Ok(decoded) if decoded.stream_id == stream_id => decoded.total_received,
+            _ => {
+                warn!("Ignoring invalid receipt for stream {}", stream_id);
+                return;
+            }
+        };
+        if let Some(stream) = self.receipt.streams.get_mut(&stream_id) {
+            if stream.receipt_total_received() < Some(total_received) {
+                stream.receipt = Some(Bytes::copy_from_slice(receipt));
+            }
+        }
+    }
+
+    /// Amount in source units the recipient is still willing to receive across all streams on
+    /// top of what's fulfilled and in flight, converted at the minimum acceptable rate. `None`
+    /// if any stream has no advertised limit or the rate is unknown.
+    #[inline]
+    fn get_amount_left_in_receive_window(&self, rate: &BigRational) -> Option<u64> {
+        let window = self
+            .receipt
+            .streams
+            .values()
+            .map(StreamTotals::get_amount_left_to_receive)
+            .try_fold(0u64, |window, left| Some(window.saturating_add(left?)))?;
+        let window = BigRational::from_u64(window)?
+            .checked_div(rate)?
+            .floor()
+            .to_integer()
+            .to_u64()
+            .unwrap_or(u64::MAX);
+        Some(window)
     }
 
     /// Save the recipient's destination asset details for calculating minimum exchange rates
@@ -268,6 +597,28 @@ impl StreamPayment {
             .saturating_sub(self.receipt.sent_amount)
     }
 
+    /// If the congestion controller paces packets or we're backing off from a temporary
+    /// reject, and the next packet isn't due yet, the time it may be sent
+    #[inline]
+    fn next_send_time(&self) -> Option<Instant> {
+        let paced = self
+            .congestion_controller
+            .pacing_interval()
+            .and_then(|interval| self.last_prepare_time?.checked_add(interval));
+        let next = max(paced, self.retry_time)?;
+        if next > Instant::now() {
+            Some(next)
+        } else {
+            None
+        }
+    }
+
+    /// Have too many packets in a row been rejected with temporary errors?
+    #[inline]
+    fn is_out_of_retries(&self) -> bool {
+        self.temporary_rejects >= self.retry_policy.max_attempts
+    }
+
     /// Is as much money as possible in-flight?
     /// (If so, the intended source amount may be fulfilled or in-flight, or the congestion controller
     /// has temporarily limited sending more money)
@@ -277,6 +628,16 @@ impl StreamPayment {
             || self.get_amount_available_to_send() == 0
     }
 
+    /// How far the payment got, to report along with an error
+    fn progress(&self) -> PaymentProgress {
+        PaymentProgress {
+            sent_amount: self.receipt.sent_amount,
+            delivered_amount: self.receipt.delivered_amount,
+            attempts: self.fulfilled_packets + self.rejected_packets,
+            last_reject_code: self.last_reject.as_ref().map(|(code, _)| *code),
+        }
+    }
+
     /// Given we've attempted sending enough packets, does the rate of rejects
     /// that count towards fail-fast indicate the payment is failing?
     #[inline]
@@ -287,6 +648,54 @@ impl StreamPayment {
     }
 }
 
+/// Optional settings of a payment sent with [`send_money`](./fn.send_money.html).
+///
+/// The default allows 1.5% of slippage, sends everything on stream 1, sizes and paces the
+/// packets with an AIMD [`CongestionController`](./struct.CongestionController.html) starting
+/// at a tenth of the source amount, and retries temporary rejects right away.
+pub struct SendMoneyOptions {
+    /// Fraction, between 0 and 1, the exchange rate may get worse than the one from the store
+    /// before the recipient should reject packets
+    pub slippage: f64,
+    /// Sizes and paces the packets instead of the default AIMD controller
+    pub congestion_controller: Option<Box<dyn CongestionControl + Send>>,
+    /// Streams the money is split across. Each packet fills the streams in order of their
+    /// ids, up to the limit the recipient advertises for each in `StreamMaxMoney` frames.
+    /// Without any stream ids, stream 1 is used.
+    pub stream_ids: Vec<u64>,
+    /// How to back off from temporary rejects
+    pub retry_policy: RetryPolicy,
+    /// Records the [outcome](./struct.PacketOutcome.html) of every packet in the returned
+    /// [`StreamDelivery`](./struct.StreamDelivery.html), to help debug partial deliveries
+    pub record_packet_outcomes: bool,
+    /// Fails the payment instead of delivering less than this (in the recipient's units) for
+    /// the whole source amount.
+    ///
+    /// Every packet must deliver its share of that amount, so the recipient rejects the ones
+    /// that would deliver less if the exchange rate gets worse during the payment. The payment
+    /// then stops with a [`StreamError::ExchangeRate`](./enum.StreamError.html) reporting how
+    /// much was sent and delivered until then.
+    pub min_delivery_amount: Option<u64>,
+    /// First [probes](./fn.probe_rate.html) the exchange rate of the path. The payment then
+    /// fails instead of delivering less than the probed rate minus `slippage` would, and the
+    /// default controller's packets start out no larger than the path allows.
+    pub probe_rate: bool,
+}
+
+impl Default for SendMoneyOptions {
+    fn default() -> Self {
+        SendMoneyOptions {
+            slippage: 0.015,
+            congestion_controller: None,
+            stream_ids: vec![1],
+            retry_policy: RetryPolicy::default(),
+            record_packet_outcomes: false,
+            min_delivery_amount: None,
+            probe_rate: false,
+        }
+    }
+}
+
 /// Send the given source amount with packetized Interledger payments using the STREAM transport protocol
 /// Returns the receipt with sent & delivered amounts, asset & account details
 pub async fn send_money<I, A, S>(
@@ -296,15 +705,66 @@ pub async fn send_money<I, A, S>(
     destination_account: Address,
     shared_secret: Vec<u8>,
     source_amount: u64,
-    slippage: f64,
-) -> Result<StreamDelivery, Error>
+    options: SendMoneyOptions,
+) -> Result<StreamDelivery, StreamError>
 where
     I: IncomingService<A> + Clone + Send + Sync + 'static,
     A: Account + Send + Sync + 'static,
     S: ExchangeRateStore + Send + Sync + 'static,
 {
+    let SendMoneyOptions {
+        slippage,
+        congestion_controller,
+        stream_ids,
+        retry_policy,
+        record_packet_outcomes,
+        mut min_delivery_amount,
+        probe_rate: should_probe_rate,
+    } = options;
+
+    let mut max_packet_amount = None;
+    if should_probe_rate {
+        let probe = probe_rate(
+            service.clone(),
+            from_account,
+            destination_account.clone(),
+            &shared_secret,
+            source_amount,
+        )
+        .await?;
+        let probed_min_delivery_amount = probe.min_delivery_amount(source_amount, slippage);
+        debug!(
+            "Probed rate of {} to {}, expecting to deliver at least {}",
+            probe.rate, destination_account, probed_min_delivery_amount
+        );
+        min_delivery_amount = max(min_delivery_amount, Some(probed_min_delivery_amount));
+        max_packet_amount = probe.max_packet_amount;
+    }
+
+    let congestion_controller = congestion_controller.unwrap_or_else(|| {
+        // TODO Make configurable to get money flowing ASAP vs as much as possible per-packet
+        let mut congestion_controller =
+            CongestionController::new(source_amount, source_amount / 10, 2.0);
+        if let Some(max_packet_amount) = max_packet_amount {
+            congestion_controller = congestion_controller.with_max_packet_amount(max_packet_amount);
+        }
+        Box::new(congestion_controller)
+    });
+
     let shared_secret = Bytes::from(shared_secret);
 
+    let mut receipt = StreamDelivery::new(from_account, destination_account, source_amount);
+    if !stream_ids.is_empty() {
+        receipt.streams = stream_ids
+            .iter()
+            .map(|&stream_id| (stream_id, StreamTotals::default()))
+            .collect();
+    }
+    if record_packet_outcomes {
+        receipt.packets = Some(Vec::new());
+    }
+    let destination_account = receipt.to.clone();
+
     let from = from_account.ilp_address();
     if from.scheme() != destination_account.scheme() {
         warn!(
@@ -321,19 +781,22 @@ where
         store,
         slippage,
         payment: Arc::new(Mutex::new(StreamPayment {
-            // TODO Make configurable to get money flowing ASAP vs as much as possible per-packet
-            congestion_controller: CongestionController::new(
-                source_amount,
-                source_amount / 10,
-                2.0,
-            ),
-            receipt: StreamDelivery::new(from_account, destination_account, source_amount),
+            congestion_controller,
+            receipt,
             should_send_source_account: true,
             sequence: 1,
             fulfilled_packets: 0,
             rejected_packets: 0,
             fail_fast_rejects: 0,
+            retry_policy,
+            temporary_rejects: 0,
+            last_temporary_reject: None,
+            retry_time: None,
             last_fulfill_time: Instant::now(),
+            last_prepare_time: None,
+            closed_by_peer: None,
+            last_reject: None,
+            min_delivery_amount,
         })),
     };
 
@@ -341,26 +804,40 @@ where
 
     /// Actions corresponding to the state of the payment
     enum PaymentEvent {
-        /// Send more money: send a packet with the given source amount and minimum destination amount
-        SendMoney((u64, u64)),
+        /// Send more money: send a packet with the given congestion controller sequence number,
+        /// source amount, minimum destination amount and its split across streams
+        SendMoney((u64, u64, u64, Vec<(u64, u64)>)),
         /// Congestion controller limited in-flight amount: wait for pending requests until given deadline
         MaxInFlight(Instant),
+        /// Congestion controller paces packets, or we're backing off from a temporary reject:
+        /// wait until the next one is due
+        Pace(Instant),
+        /// Recipient won't accept any more money: terminate the payment
+        ReceiveMaxExceeded,
         /// Sent full source amount: close the connection and return success
         CloseConnection,
         /// Maximum timeout since last fulfill has elapsed: terminate the payment
         Timeout,
         /// Too many packets are rejected, such as if the exchange rate is too low: terminate the payment
         FailFast,
+        /// Retry policy's maximum attempts were rejected with temporary errors: terminate the payment
+        RetriesExhausted,
+        /// Recipient closed the connection: terminate the payment
+        ClosedByPeer(ErrorCode, String),
     }
 
     loop {
         let event = {
             let mut payment = sender.payment.lock().await;
 
-            if payment.last_fulfill_time.elapsed() >= MAX_TIME_SINCE_LAST_FULFILL {
+            if let Some((code, message)) = payment.closed_by_peer.clone() {
+                PaymentEvent::ClosedByPeer(code, message)
+            } else if payment.last_fulfill_time.elapsed() >= MAX_TIME_SINCE_LAST_FULFILL {
                 PaymentEvent::Timeout
             } else if payment.is_failing() {
                 PaymentEvent::FailFast
+            } else if payment.is_out_of_retries() {
+                PaymentEvent::RetriesExhausted
             } else if payment.is_complete() {
                 PaymentEvent::CloseConnection
             } else if payment.is_max_in_flight() {
@@ -369,16 +846,34 @@ where
                     .checked_add(MAX_TIME_SINCE_LAST_FULFILL)
                     .unwrap();
                 PaymentEvent::MaxInFlight(deadline)
+            } else if payment.get_amount_left_in_receive_window(
+                &payment.get_rate(&sender.store, sender.slippage),
+            ) == Some(0)
+            {
+                if payment.receipt.in_flight_amount == 0 {
+                    PaymentEvent::ReceiveMaxExceeded
+                } else {
+                    // In-flight packets may be rejected, or come back with a larger limit
+                    let deadline = payment
+                        .last_fulfill_time
+                        .checked_add(MAX_TIME_SINCE_LAST_FULFILL)
+                        .unwrap();
+                    PaymentEvent::MaxInFlight(deadline)
+                }
+            } else if let Some(next_send_time) = payment.next_send_time() {
+                PaymentEvent::Pace(next_send_time)
             } else {
                 PaymentEvent::SendMoney(payment.apply_prepare(&sender.store, sender.slippage))
             }
         };
 
         match event {
-            PaymentEvent::SendMoney((source_amount, dest_amount)) => {
+            PaymentEvent::SendMoney((congestion_sequence, source_amount, dest_amount, shares)) => {
                 let mut sender = sender.clone();
                 pending_requests.push(tokio::spawn(async move {
-                    sender.send_money_packet(source_amount, dest_amount).await
+                    sender
+                        .send_money_packet(congestion_sequence, source_amount, dest_amount, shares)
+                        .await
                 }));
             }
             PaymentEvent::MaxInFlight(deadline) => {
@@ -388,15 +883,20 @@ where
 
                 if let Ok(Ok(Err(error))) = result {
                     error!("Send money stopped because of error: {:?}", error);
-                    return Err(error);
+                    return Err(sender.close_with_error(error).await);
                 }
             }
+            PaymentEvent::Pace(next_send_time) => {
+                sleep_until(next_send_time).await;
+            }
             PaymentEvent::CloseConnection => {
                 // Wait for all pending requests to complete before closing the connection
                 pending_requests.map(|_| ()).collect::<()>().await;
 
                 // Try to the tell the recipient the connection is closed
-                sender.try_send_connection_close().await;
+                sender
+                    .try_send_connection_close(ErrorCode::NoError, "")
+                    .await;
 
                 // Return final receipt
                 let payment = sender.payment.lock().await;
@@ -410,17 +910,192 @@ where
             }
             PaymentEvent::Timeout => {
                 // Error if we haven't received a fulfill over a timeout period
-                return Err(Error::Timeout);
+                let progress = sender.payment.lock().await.progress();
+                return Err(StreamError::Timeout { progress });
             }
             PaymentEvent::FailFast => {
-                let payment = sender.payment.lock().await;
-                return Err(Error::PaymentFailFast(
-                    payment.fulfilled_packets,
-                    payment.rejected_packets,
-                ));
+                let error = {
+                    let payment = sender.payment.lock().await;
+                    debug!(
+                        "Too many packets rejected ({} packets fulfilled, {} packets rejected)",
+                        payment.fulfilled_packets, payment.rejected_packets
+                    );
+                    // Only F99, T00 and T01 count towards failing fast, so the last of them
+                    // tells whether the receiver or the path is turning the packets down
+                    let (code, message) = payment
+                        .last_reject
+                        .clone()
+                        .unwrap_or((IlpErrorCode::F99_APPLICATION_ERROR, String::new()));
+                    StreamError::from_reject(code, message, payment.progress())
+                };
+                return Err(sender.close_with_error(error).await);
+            }
+            PaymentEvent::RetriesExhausted => {
+                let error = {
+                    let payment = sender.payment.lock().await;
+                    let (code, message) = payment
+                        .last_temporary_reject
+                        .clone()
+                        .unwrap_or((IlpErrorCode::T00_INTERNAL_ERROR, String::new()));
+                    debug!(
+                        "Giving up after {} packets in a row were rejected",
+                        payment.temporary_rejects
+                    );
+                    StreamError::Unreachable {
+                        code,
+                        message,
+                        progress: payment.progress(),
+                    }
+                };
+                return Err(sender.close_with_error(error).await);
+            }
+            PaymentEvent::ReceiveMaxExceeded => {
+                let error = {
+                    let payment = sender.payment.lock().await;
+                    let receive_max = payment
+                        .receipt
+                        .streams
+                        .values()
+                        .filter_map(|stream| stream.receive_max)
+                        .fold(0, u64::saturating_add);
+                    StreamError::Rejected {
+                        reason: RejectReason::ReceiveMaxExceeded(receive_max),
+                        progress: payment.progress(),
+                    }
+                };
+                return Err(sender.close_with_error(error).await);
+            }
+            PaymentEvent::ClosedByPeer(code, message) => {
+                debug!(
+                    "Recipient closed the connection with {:?}: {}",
+                    code, message
+                );
+                let progress = sender.payment.lock().await.progress();
+                return Err(StreamError::Rejected {
+                    reason: RejectReason::ClosedByPeer(code, message),
+                    progress,
+                });
+            }
+        }
+    }
+}
+
+/// Write and read the bytes of a [`DataStream`](./struct.DataStream.html) over a STREAM
+/// connection, in packets that carry no money.
+///
+/// The receiver can only send data in its replies, so the bytes it writes arrive with the
+/// packets we send. We never send past the windows the receiver advertises with
+/// `StreamMaxData` and `ConnectionMaxData` frames: while they're full, we send empty packets
+/// every so often to learn when the receiver's application has read enough. Returns once the
+/// stream's write half was shut down and the receiver was told, or with an error if the
+/// packets stop getting through.
+pub async fn send_data<I, A>(
+    mut service: I,
+    from_account: &A,
+    destination_account: Address,
+    shared_secret: &[u8],
+    stream: DataStream,
+) -> Result<(), StreamError>
+where
+    I: IncomingService<A>,
+    A: Account,
+{
+    let mut sequence: u64 = 1;
+    let mut last_reply_time = Instant::now();
+    let mut last_reject_code = None;
+    let mut remote_connection_max = None;
+    let streams = std::slice::from_ref(&stream);
+
+    loop {
+        let max_len = MAX_DATA_PER_PACKET.min(connection_window(streams, remote_connection_max));
+        let outgoing = match poll_fn(|cx| stream.poll_outgoing(cx, max_len)).await {
+            Some(outgoing) => outgoing,
+            None => break,
+        };
+        if outgoing.data.is_empty() && outgoing.close.is_none() {
+            // The receiver's window is full, give its application time to read
+            sleep(DATA_RETRY_DELAY).await;
+        }
+        let mut frames = stream.frames(&outgoing);
+        frames.extend(max_data_frames(streams));
+        let prepare_data = StreamPacketBuilder {
+            ilp_packet_type: IlpPacketType::Prepare,
+            prepare_amount: 0,
+            sequence,
+            frames: &frames,
+        }
+        .build()
+        .into_encrypted(shared_secret);
+        let prepare = PrepareBuilder {
+            destination: destination_account.clone(),
+            amount: 0,
+            execution_condition: &generate_condition(shared_secret, &prepare_data),
+            expires_at: SystemTime::now() + Duration::from_secs(30),
+            data: &prepare_data[..],
+        }
+        .build();
+
+        let reply = service
+            .handle_request(IncomingRequest {
+                from: from_account.clone(),
+                prepare,
+            })
+            .await;
+        let reply_data = match &reply {
+            Ok(fulfill) => fulfill.data(),
+            Err(reject) => reject.data(),
+        };
+        if let Err(reject) = &reply {
+            last_reject_code = Some(reject.code());
+        }
+        // Data packets carry no money, so only the attempts count
+        let progress = PaymentProgress {
+            attempts: sequence,
+            last_reject_code,
+            ..PaymentProgress::default()
+        };
+
+        // Whether the receiver fulfilled or rejected the packet, it read the data if it replied
+        match StreamPacket::from_encrypted(shared_secret, BytesMut::from(reply_data)) {
+            Ok(reply_packet) if reply_packet.sequence() == sequence => {
+                stream.handle_frames(reply_packet.frames());
+                stream.sent(&outgoing);
+                remote_connection_max = max(
+                    remote_connection_max,
+                    connection_max_offset(reply_packet.frames()),
+                );
+                last_reply_time = Instant::now();
+                match stream.remote_close() {
+                    Some((ErrorCode::NoError, _)) | None => {}
+                    Some((code, message)) => {
+                        return Err(StreamError::Rejected {
+                            reason: RejectReason::ClosedByPeer(code, message),
+                            progress,
+                        })
+                    }
+                }
+            }
+            _ => {
+                stream.requeue(outgoing);
+                if let Err(reject) = reply {
+                    if reject.code().class() != ErrorClass::Temporary {
+                        return Err(StreamError::from_reject(
+                            reject.code(),
+                            String::from_utf8_lossy(reject.message()).into_owned(),
+                            progress,
+                        ));
+                    }
+                }
+                if last_reply_time.elapsed() >= MAX_TIME_SINCE_LAST_FULFILL {
+                    return Err(StreamError::Timeout { progress });
+                }
+                sleep(DATA_RETRY_DELAY).await;
             }
         }
+        sequence += 1;
     }
+
+    Ok(())
 }
 
 /// Sends and handles all ILP & STREAM packets, encapsulating all payment state
@@ -450,22 +1125,38 @@ where
     #[inline]
     pub async fn send_money_packet(
         &mut self,
+        congestion_sequence: u64,
         source_amount: u64,
         min_destination_amount: u64,
-    ) -> Result<(), Error> {
-        let (prepare, sequence) = {
+        shares: Vec<(u64, u64)>,
+    ) -> Result<(), StreamError> {
+        let (prepare, execution_condition, sequence) = {
             let mut payment = self.payment.lock().await;
 
             // Build the STREAM packet
             let sequence = payment.next_sequence();
-            let mut frames = vec![Frame::StreamMoney(StreamMoneyFrame {
-                stream_id: 1,
-                shares: 1,
-            })];
+            let mut frames: Vec<Frame> = shares
+                .iter()
+                .map(|&(stream_id, shares)| {
+                    Frame::StreamMoney(StreamMoneyFrame { stream_id, shares })
+                })
+                .collect();
+            if frames.is_empty() {
+                // Without a destination amount to split, send it all on the first stream
+                let stream_id = *payment.receipt.streams.keys().next().unwrap_or(&1);
+                frames.push(Frame::StreamMoney(StreamMoneyFrame {
+                    stream_id,
+                    shares: 1,
+                }));
+            }
             if payment.should_send_source_account {
                 frames.push(Frame::ConnectionNewAddress(ConnectionNewAddressFrame {
                     source_account: payment.receipt.from.clone(),
                 }));
+                frames.push(Frame::ConnectionAssetDetails(ConnectionAssetDetailsFrame {
+                    source_asset_code: self.from_account.asset_code(),
+                    source_asset_scale: self.from_account.asset_scale(),
+                }));
             }
             let stream_request_packet = StreamPacketBuilder {
                 ilp_packet_type: IlpPacketType::Prepare,
@@ -491,17 +1182,18 @@ where
             };
 
             // Build the Prepare
+            let expiry = packet_expiry(payment.congestion_controller.smoothed_rtt());
             let prepare = PrepareBuilder {
                 destination: payment.receipt.to.clone(),
                 amount: source_amount,
                 execution_condition: &execution_condition,
-                expires_at: SystemTime::now() + Duration::from_secs(30),
+                expires_at: SystemTime::now() + expiry,
                 // TODO Don't copy the data
                 data: &prepare_data[..],
             }
             .build();
 
-            (prepare, sequence)
+            (prepare, execution_condition, sequence)
         };
 
         // Send it!
@@ -513,6 +1205,27 @@ where
             })
             .await;
 
+        // A fulfillment for any other condition, such as one replayed from an earlier packet,
+        // doesn't prove the recipient got this packet, so the money isn't counted as delivered
+        let reply = match reply {
+            Ok(fulfill) if hash_sha256(fulfill.fulfillment()) != execution_condition => {
+                warn!(
+                    "Fulfillment {:?} of prepare {} did not match its condition {:?}",
+                    HexString(fulfill.fulfillment()),
+                    sequence,
+                    HexString(&execution_condition)
+                );
+                Err(RejectBuilder {
+                    code: IlpErrorCode::F09_INVALID_PEER_RESPONSE,
+                    message: b"Fulfillment did not match condition",
+                    triggered_by: None,
+                    data: &[],
+                }
+                .build())
+            }
+            reply => reply,
+        };
+
         let (packet_type, reply_data) = match &reply {
             Ok(fulfill) => (IlpPacketType::Fulfill, fulfill.data()),
             Err(reject) => (IlpPacketType::Reject, reject.data()),
@@ -524,6 +1237,7 @@ where
         let mut payment = self.payment.lock().await;
 
         // Parse the stream packet and determine the amount the recipient claims they received
+        let mut receiver_replied = false;
         let claimed_amount: u64 = match stream_reply_packet {
             Ok(stream_reply_packet) => {
                 if stream_reply_packet.sequence() != sequence {
@@ -541,16 +1255,45 @@ where
                     warn!("Discarding STREAM packet (received Fulfill, but recipient said they sent a Reject)");
                     0
                 } else {
-                    // Since we decrypted the response, the recipient read the request packet and knows our account
-                    payment.should_send_source_account = false;
+                    // Since we decrypted the response, the recipient read the request packet and knows
+                    // our account, though it only remembers it once it fulfilled a packet carrying it
+                    if packet_type == IlpPacketType::Fulfill {
+                        payment.should_send_source_account = false;
+                    }
+                    receiver_replied = true;
+
+                    // Respect how much more the recipient is willing to receive on each stream,
+                    // keep the receipts it signed for them, and stop if it closed them
+                    for frame in stream_reply_packet.frames() {
+                        match frame {
+                            Frame::StreamMaxMoney(frame) => payment.set_remote_receive_max(
+                                frame.stream_id,
+                                frame.receive_max,
+                                frame.total_received,
+                            ),
+                            Frame::StreamReceipt(frame) => {
+                                payment.set_receipt(frame.stream_id, frame.receipt)
+                            }
+                            Frame::StreamClose(frame) => {
+                                payment.close_remote_stream(frame.stream_id)
+                            }
+                            Frame::ConnectionClose(frame) => {
+                                payment.closed_by_peer =
+                                    Some((frame.code, frame.message.to_string()))
+                            }
+                            _ => {}
+                        }
+                    }
 
                     // Update the destination asset scale & code
                     // https://github.com/interledger/rfcs/pull/551 ensures that this won't change
                     if payment.receipt.destination_asset_scale.is_none() {
                         for frame in stream_reply_packet.frames() {
-                            if let Frame::ConnectionAssetDetails(frame) = frame {
-                                let asset_code = frame.source_asset_code.to_string();
-                                let asset_scale = frame.source_asset_scale;
+                            if let Some(AssetDetails {
+                                asset_code,
+                                asset_scale,
+                            }) = AssetDetails::from_frame(&frame)
+                            {
                                 debug!(
                                     "Setting remote asset details ({} with scale {})",
                                     asset_code, asset_scale
@@ -579,7 +1322,19 @@ where
                 // Even if the data was invalid, since it was fulfilled, we must assume they got at least the minimum
                 let delivered_amount = max(min_destination_amount, claimed_amount);
 
-                payment.apply_fulfill(source_amount, delivered_amount);
+                payment.apply_fulfill(
+                    congestion_sequence,
+                    source_amount,
+                    delivered_amount,
+                    &shares,
+                );
+                payment.record_packet_outcome(PacketOutcome {
+                    sequence,
+                    source_amount,
+                    delivered_amount,
+                    fulfilled: true,
+                    reject_code: None,
+                });
 
                 debug!(
                     "Prepare {} with amount {} was fulfilled ({} left to send)",
@@ -592,7 +1347,14 @@ where
             }
             // Handle ILP Reject
             Err(reject) => {
-                payment.apply_reject(source_amount, &reject);
+                payment.apply_reject(congestion_sequence, source_amount, &shares, &reject);
+                payment.record_packet_outcome(PacketOutcome {
+                    sequence,
+                    source_amount,
+                    delivered_amount: 0,
+                    fulfilled: false,
+                    reject_code: Some(reject.code()),
+                });
 
                 debug!(
                     "Prepare {} with amount {} was rejected with code: {} ({} left to send)",
@@ -605,36 +1367,73 @@ where
                 match (reject.code().class(), reject.code()) {
                     (ErrorClass::Temporary, _) => Ok(()),
                     (_, IlpErrorCode::F08_AMOUNT_TOO_LARGE) => Ok(()),
+                    // The recipient got less than the packet's share of the minimum delivery
+                    // amount: the rate is too poor now, so stop instead of trying it again
+                    (_, IlpErrorCode::F99_APPLICATION_ERROR)
+                        if receiver_replied
+                            && payment.min_delivery_amount.is_some()
+                            && claimed_amount < min_destination_amount =>
+                    {
+                        debug!(
+                            "Recipient got {} for prepare {}, less than the minimum of {}",
+                            claimed_amount, sequence, min_destination_amount
+                        );
+                        Err(StreamError::ExchangeRate {
+                            progress: payment.progress(),
+                        })
+                    }
                     (_, IlpErrorCode::F99_APPLICATION_ERROR) => Ok(()),
                     // R01 is triggered by connector when the amount rounds to 0, so keep retrying
                     // Other Rxx errors such as timeouts are likely terminal
                     (_, IlpErrorCode::R01_INSUFFICIENT_SOURCE_AMOUNT) => Ok(()),
                     // Any other error will stop the rest of the payment
-                    _ => Err(Error::UnexpectedRejection(
+                    _ => Err(StreamError::from_reject(
                         reject.code(),
                         String::from_utf8_lossy(reject.message()).into_owned(),
+                        payment.progress(),
                     )),
                 }
             }
         }
     }
 
+    /// Tell the peer we're giving up on the payment because of the given error, then return it
+    async fn close_with_error(&mut self, error: StreamError) -> StreamError {
+        self.try_send_connection_close(ErrorCode::ApplicationError, &error.to_string())
+            .await;
+        error
+    }
+
     /// Send an unfulfillable Prepare with a ConnectionClose frame to the peer
     /// There's no ACK from the recipient, so we can't confirm it closed
     #[inline]
-    async fn try_send_connection_close(&mut self) {
+    async fn try_send_connection_close(&mut self, code: ErrorCode, message: &str) {
         let prepare = {
             let mut payment = self.payment.lock().await;
             let sequence = payment.next_sequence();
 
+            // Close each of our streams, then the connection itself
+            let mut frames: Vec<Frame> = payment
+                .receipt
+                .streams
+                .keys()
+                .map(|&stream_id| {
+                    Frame::StreamClose(StreamCloseFrame {
+                        stream_id,
+                        code,
+                        message,
+                    })
+                })
+                .collect();
+            frames.push(Frame::ConnectionClose(ConnectionCloseFrame {
+                code,
+                message,
+            }));
             let stream_packet = StreamPacketBuilder {
                 ilp_packet_type: IlpPacketType::Prepare,
                 prepare_amount: 0,
                 sequence,
-                frames: &[Frame::ConnectionClose(ConnectionCloseFrame {
-                    code: ErrorCode::NoError,
-                    message: "",
-                })],
+                frames: &frames,
             }
             .build();
 
@@ -703,6 +1502,18 @@ fn get_rate<S: ExchangeRateStore>(
     Some(rate)
 }
 
+/// Time a packet may stay in flight: the smoothed round trip time plus a safety margin,
+/// within [`MIN_PACKET_EXPIRY`] and [`MAX_PACKET_EXPIRY`]. Without a round trip time
+/// sample yet, the default expiry is used.
+fn packet_expiry(smoothed_rtt: Option<Duration>) -> Duration {
+    match smoothed_rtt {
+        Some(rtt) => rtt
+            .saturating_mul(1 + PACKET_EXPIRY_RTT_MARGIN)
+            .clamp(MIN_PACKET_EXPIRY, MAX_PACKET_EXPIRY),
+        None => DEFAULT_PACKET_EXPIRY,
+    }
+}
+
 /// Convert the given source amount into a destination amount
 /// using the provided rate. Round up for safety.
 ///
@@ -719,12 +1530,39 @@ fn convert(source_amount: u64, rate: BigRational) -> Option<u64> {
     dest_amount.ceil().to_integer().to_u64()
 }
 
+/// (De)serializes receipts as hex strings
+#[cfg(feature = "serde")]
+mod hex_receipt {
+    use bytes::Bytes;
+    use interledger_packet::hex;
+    use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        receipt: &Option<Bytes>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        receipt.as_deref().map(hex::encode).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Bytes>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|receipt| {
+                hex::decode(&receipt)
+                    .map(Bytes::from)
+                    .ok_or_else(|| D::Error::custom("receipt is not valid hex"))
+            })
+            .transpose()
+    }
+}
+
 #[cfg(test)]
 mod send_money_tests {
     use super::*;
     use crate::test_helpers::{TestAccount, TestStore, EXAMPLE_CONNECTOR};
     use async_trait::async_trait;
-    use interledger_packet::{ErrorCode as IlpErrorCode, RejectBuilder};
+    use interledger_packet::{ErrorCode as IlpErrorCode, FulfillBuilder, RejectBuilder};
     use interledger_service::incoming_service_fn;
     use interledger_service_util::MaxPacketAmountService;
     use parking_lot::Mutex;
@@ -765,35 +1603,177 @@ mod send_money_tests {
             Address::from_str("example.destination").unwrap(),
             vec![0; 32],
             100,
-            0.0,
+            SendMoneyOptions {
+                slippage: 0.0,
+                ..SendMoneyOptions::default()
+            },
         )
         .await;
-        assert!(result.is_err());
-        assert_eq!(requests.lock().len(), 1);
+        match result {
+            Err(StreamError::Rejected {
+                reason: RejectReason::Reject(code, message),
+                progress,
+            }) => {
+                assert_eq!(code, IlpErrorCode::F00_BAD_REQUEST);
+                assert_eq!(message, "just some final error");
+                assert_eq!(progress.attempts, 1);
+                assert_eq!(progress.sent_amount, 0);
+            }
+            other => panic!("Expected the payment to be rejected, got {:?}", other),
+        }
+        // The one packet, then the ConnectionClose
+        let requests = requests.lock();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].prepare.amount(), 0);
     }
 
     #[tokio::test]
-    async fn perserveres_past_liquidity_errors() {
-        let destination_address = Address::from_str("example.receiver").unwrap();
-        let account = TestAccount {
-            id: Uuid::new_v4(),
-            asset_code: "XYZ".to_string(),
-            asset_scale: 9,
-            ilp_address: destination_address.clone(),
-            max_packet_amount: Some(10),
-        };
-        let store = TestStore {
-            route: None,
-            price_1: None,
-            price_2: None,
-        };
+    async fn fails_as_unreachable_without_a_path() {
+        let result = send_money_rejected_with(IlpErrorCode::F02_UNREACHABLE).await;
+        match result {
+            Err(StreamError::Unreachable {
+                code,
+                message,
+                progress,
+            }) => {
+                assert_eq!(code, IlpErrorCode::F02_UNREACHABLE);
+                assert_eq!(message, "rejected");
+                assert_eq!(
+                    progress,
+                    PaymentProgress {
+                        sent_amount: 0,
+                        delivered_amount: 0,
+                        attempts: 1,
+                        last_reject_code: Some(IlpErrorCode::F02_UNREACHABLE),
+                    }
+                );
+            }
+            other => panic!("Expected the receiver to be unreachable, got {:?}", other),
+        }
+    }
 
-        let start_time = std::time::Instant::now();
+    #[tokio::test]
+    async fn fails_on_poor_exchange_rates() {
+        // The receiver rejects packets delivering less than the minimum with F99, until
+        // too many were rejected
+        let result = send_money_rejected_with(IlpErrorCode::F99_APPLICATION_ERROR).await;
+        match result {
+            Err(StreamError::ExchangeRate { progress }) => {
+                assert_eq!(progress.attempts, FAIL_FAST_MINIMUM_PACKET_ATTEMPTS);
+                assert_eq!(progress.delivered_amount, 0);
+            }
+            other => panic!("Expected the exchange rate to be too poor, got {:?}", other),
+        }
 
-        let num_requests = Arc::new(AtomicUsize::new(0));
-        let num_requests_clone = num_requests.clone();
+        let result =
+            send_money_rejected_with(IlpErrorCode::F04_INSUFFICIENT_DESTINATION_AMOUNT).await;
+        match result {
+            Err(StreamError::ExchangeRate { progress }) => assert_eq!(progress.attempts, 1),
+            other => panic!("Expected the exchange rate to be too poor, got {:?}", other),
+        }
+    }
 
-        let result = send_money(
+    #[tokio::test(start_paused = true)]
+    async fn times_out_without_replies() {
+        #[derive(Clone)]
+        struct Unresponsive;
+
+        #[async_trait]
+        impl IncomingService<TestAccount> for Unresponsive {
+            async fn handle_request(&mut self, _: IncomingRequest<TestAccount>) -> IlpResult {
+                futures::future::pending().await
+            }
+        }
+
+        let result = send_money(
+            Unresponsive,
+            &test_account(),
+            TestStore {
+                route: None,
+                price_1: None,
+                price_2: None,
+            },
+            Address::from_str("example.destination").unwrap(),
+            vec![0; 32],
+            100,
+            SendMoneyOptions {
+                slippage: 0.0,
+                ..SendMoneyOptions::default()
+            },
+        )
+        .await;
+        match result {
+            Err(StreamError::Timeout { progress }) => {
+                // The packets are still in flight
+                assert!(progress.sent_amount > 0);
+                assert_eq!(progress.attempts, 0);
+                assert_eq!(progress.last_reject_code, None);
+            }
+            other => panic!("Expected the payment to time out, got {:?}", other),
+        }
+    }
+
+    fn test_account() -> TestAccount {
+        TestAccount {
+            id: Uuid::new_v4(),
+            asset_code: "XYZ".to_string(),
+            asset_scale: 9,
+            ilp_address: Address::from_str("example.destination").unwrap(),
+            max_packet_amount: None,
+        }
+    }
+
+    /// Sends a payment through a service rejecting every packet with the given code
+    async fn send_money_rejected_with(code: IlpErrorCode) -> Result<StreamDelivery, StreamError> {
+        send_money(
+            incoming_service_fn(move |_| {
+                Err(RejectBuilder {
+                    code,
+                    message: b"rejected",
+                    triggered_by: Some(&EXAMPLE_CONNECTOR),
+                    data: &[],
+                }
+                .build())
+            }),
+            &test_account(),
+            TestStore {
+                route: None,
+                price_1: None,
+                price_2: None,
+            },
+            Address::from_str("example.destination").unwrap(),
+            vec![0; 32],
+            100,
+            SendMoneyOptions {
+                slippage: 0.0,
+                ..SendMoneyOptions::default()
+            },
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn perserveres_past_liquidity_errors() {
+        let destination_address = Address::from_str("example.receiver").unwrap();
+        let account = TestAccount {
+            id: Uuid::new_v4(),
+            asset_code: "XYZ".to_string(),
+            asset_scale: 9,
+            ilp_address: destination_address.clone(),
+            max_packet_amount: Some(10),
+        };
+        let store = TestStore {
+            route: None,
+            price_1: None,
+            price_2: None,
+        };
+
+        let start_time = std::time::Instant::now();
+
+        let num_requests = Arc::new(AtomicUsize::new(0));
+        let num_requests_clone = num_requests.clone();
+
+        let result = send_money(
             incoming_service_fn(move |_| {
                 if start_time.clone().elapsed() > Duration::from_secs(3) {
                     // Reject with final error to stop STREAM
@@ -820,7 +1800,10 @@ mod send_money_tests {
             destination_address.clone(),
             vec![0; 32],
             50,
-            0.0,
+            SendMoneyOptions {
+                slippage: 0.0,
+                ..SendMoneyOptions::default()
+            },
         )
         .await;
 
@@ -830,6 +1813,298 @@ mod send_money_tests {
         assert!(num_requests.load(Ordering::Relaxed) > 1000);
     }
 
+    /// Rejects the packets `rejects` picks by the order they arrive in with T04, and lets the
+    /// next service have the others
+    #[derive(Clone)]
+    struct FlakyPath<I> {
+        rejects: fn(usize) -> bool,
+        num_requests: Arc<AtomicUsize>,
+        next: I,
+    }
+
+    #[async_trait]
+    impl<I: IncomingService<TestAccount> + Send> IncomingService<TestAccount> for FlakyPath<I> {
+        async fn handle_request(&mut self, request: IncomingRequest<TestAccount>) -> IlpResult {
+            if (self.rejects)(self.num_requests.fetch_add(1, Ordering::Relaxed)) {
+                Err(RejectBuilder {
+                    code: IlpErrorCode::T04_INSUFFICIENT_LIQUIDITY,
+                    message: b"settle up!",
+                    triggered_by: Some(&EXAMPLE_CONNECTOR),
+                    data: &[],
+                }
+                .build())
+            } else {
+                self.next.handle_request(request).await
+            }
+        }
+    }
+
+    /// Replays the fulfillment of the first packet the next service fulfills for all the
+    /// packets it fulfills after it
+    #[derive(Clone)]
+    struct ReplayedFulfillments<I> {
+        first_fulfillment: Arc<Mutex<Option<[u8; 32]>>>,
+        next: I,
+    }
+
+    #[async_trait]
+    impl<I: IncomingService<TestAccount> + Send> IncomingService<TestAccount>
+        for ReplayedFulfillments<I>
+    {
+        async fn handle_request(&mut self, request: IncomingRequest<TestAccount>) -> IlpResult {
+            let fulfill = self.next.handle_request(request).await?;
+            let mut fulfillment = [0; 32];
+            fulfillment.copy_from_slice(fulfill.fulfillment());
+            let fulfillment = *self.first_fulfillment.lock().get_or_insert(fulfillment);
+            Ok(FulfillBuilder {
+                fulfillment: &fulfillment,
+                data: fulfill.data(),
+            }
+            .build())
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_fulfillments_not_matching_the_condition() {
+        let destination_address = Address::from_str("example.receiver").unwrap();
+        let account = TestAccount {
+            id: Uuid::new_v4(),
+            asset_code: "XYZ".to_string(),
+            asset_scale: 9,
+            ilp_address: destination_address.clone(),
+            max_packet_amount: None,
+        };
+        let result = send_money(
+            ReplayedFulfillments {
+                first_fulfillment: Arc::new(Mutex::new(None)),
+                next: limited_receiver(
+                    Bytes::from(vec![0; 32]),
+                    |_, received| received + 40,
+                    Arc::new(Mutex::new(Vec::new())),
+                ),
+            },
+            &account,
+            TestStore {
+                route: None,
+                price_1: None,
+                price_2: None,
+            },
+            destination_address,
+            vec![0; 32],
+            100,
+            SendMoneyOptions {
+                slippage: 0.0,
+                congestion_controller: Some(Box::new(CongestionController::new(100, 10, 2.0))),
+                record_packet_outcomes: true,
+                ..SendMoneyOptions::default()
+            },
+        )
+        .await;
+
+        // The second packet was fulfilled, but the third only got its fulfillment back
+        match result {
+            Err(StreamError::Rejected {
+                reason: RejectReason::Reject(code, _),
+                progress,
+            }) => {
+                assert_eq!(code, IlpErrorCode::F09_INVALID_PEER_RESPONSE);
+                assert_eq!(progress.sent_amount, 40);
+                assert_eq!(progress.delivered_amount, 40);
+            }
+            other => panic!("Expected the payment to be rejected, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn backs_off_from_temporary_rejects() {
+        let destination_address = Address::from_str("example.receiver").unwrap();
+        let account = TestAccount {
+            id: Uuid::new_v4(),
+            asset_code: "XYZ".to_string(),
+            asset_scale: 9,
+            ilp_address: destination_address.clone(),
+            max_packet_amount: None,
+        };
+
+        let num_requests = Arc::new(AtomicUsize::new(0));
+        let service = FlakyPath {
+            rejects: |n| n < 3,
+            num_requests: num_requests.clone(),
+            next: limited_receiver(
+                Bytes::from(vec![0; 32]),
+                |_, _| u64::MAX,
+                Arc::new(Mutex::new(Vec::new())),
+            ),
+        };
+
+        let start = Instant::now();
+        let receipt = send_money(
+            service,
+            &account,
+            TestStore {
+                route: None,
+                price_1: None,
+                price_2: None,
+            },
+            destination_address,
+            vec![0; 32],
+            100,
+            SendMoneyOptions {
+                slippage: 0.0,
+                congestion_controller: Some(Box::new(CongestionController::new(100, 10, 2.0))),
+                retry_policy: RetryPolicy {
+                    max_attempts: 5,
+                    base_delay: Duration::from_millis(20),
+                    max_delay: Duration::from_millis(50),
+                    backoff_multiplier: 2.0,
+                    jitter: 0.5,
+                },
+                ..SendMoneyOptions::default()
+            },
+        )
+        .await
+        .unwrap();
+        let elapsed = start.elapsed();
+
+        assert_eq!(receipt.delivered_amount, 100);
+        assert_eq!(receipt.packets, None);
+        // Every request but the ConnectionClose
+        assert_eq!(
+            receipt.attempts,
+            num_requests.load(Ordering::Relaxed) as u64 - 1
+        );
+        assert!(receipt.attempts >= 4);
+        // Waited 20ms, 40ms and 50ms, less up to half of each for jitter
+        assert!(elapsed >= Duration::from_millis(55), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(1000), "{:?}", elapsed);
+    }
+
+    #[tokio::test]
+    async fn stops_after_max_attempts() {
+        let destination_address = Address::from_str("example.receiver").unwrap();
+        let account = TestAccount {
+            id: Uuid::new_v4(),
+            asset_code: "XYZ".to_string(),
+            asset_scale: 9,
+            ilp_address: destination_address.clone(),
+            max_packet_amount: None,
+        };
+        let amounts = Arc::new(Mutex::new(Vec::new()));
+        let amounts_clone = amounts.clone();
+
+        let result = send_money(
+            incoming_service_fn(move |request: IncomingRequest<TestAccount>| {
+                amounts_clone.lock().push(request.prepare.amount());
+                Err(RejectBuilder {
+                    code: IlpErrorCode::T04_INSUFFICIENT_LIQUIDITY,
+                    message: b"settle up!",
+                    triggered_by: Some(&EXAMPLE_CONNECTOR),
+                    data: &[],
+                }
+                .build())
+            }),
+            &account,
+            TestStore {
+                route: None,
+                price_1: None,
+                price_2: None,
+            },
+            destination_address,
+            vec![0; 32],
+            100,
+            SendMoneyOptions {
+                slippage: 0.0,
+                congestion_controller: Some(Box::new(CongestionController::new(100, 10, 2.0))),
+                retry_policy: RetryPolicy {
+                    max_attempts: 3,
+                    base_delay: Duration::from_millis(1),
+                    ..RetryPolicy::default()
+                },
+                ..SendMoneyOptions::default()
+            },
+        )
+        .await;
+
+        match result {
+            Err(StreamError::Unreachable {
+                code,
+                message,
+                progress,
+            }) => {
+                assert_eq!(code, IlpErrorCode::T04_INSUFFICIENT_LIQUIDITY);
+                assert_eq!(message, "settle up!");
+                assert_eq!(progress.attempts, 3);
+            }
+            other => panic!("Expected to run out of retries, got {:?}", other),
+        }
+        // Three attempts, then the ConnectionClose
+        let amounts = amounts.lock();
+        assert_eq!(amounts.len(), 4);
+        assert_eq!(amounts[3], 0);
+    }
+
+    #[tokio::test]
+    async fn records_packet_outcomes() {
+        let destination_address = Address::from_str("example.receiver").unwrap();
+        let account = TestAccount {
+            id: Uuid::new_v4(),
+            asset_code: "XYZ".to_string(),
+            asset_scale: 9,
+            ilp_address: destination_address.clone(),
+            max_packet_amount: None,
+        };
+        let receipt = send_money(
+            FlakyPath {
+                rejects: |n| n == 2,
+                num_requests: Arc::new(AtomicUsize::new(0)),
+                next: limited_receiver(
+                    Bytes::from(vec![0; 32]),
+                    |_, received| received + 40,
+                    Arc::new(Mutex::new(Vec::new())),
+                ),
+            },
+            &account,
+            TestStore {
+                route: None,
+                price_1: None,
+                price_2: None,
+            },
+            destination_address,
+            vec![0; 32],
+            100,
+            SendMoneyOptions {
+                slippage: 0.0,
+                congestion_controller: Some(Box::new(CongestionController::new(100, 10, 2.0))),
+                record_packet_outcomes: true,
+                ..SendMoneyOptions::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        let outcome =
+            |sequence, source_amount, delivered_amount, reject_code: Option<_>| PacketOutcome {
+                sequence,
+                source_amount,
+                delivered_amount,
+                fulfilled: reject_code.is_none(),
+                reject_code,
+            };
+        // The first packet is unfulfillable until we know the recipient's asset and how much
+        // it takes at a time, then one of the packets is lost on the path
+        assert_eq!(
+            receipt.packets.unwrap(),
+            vec![
+                outcome(1, 100, 0, Some(IlpErrorCode::F99_APPLICATION_ERROR)),
+                outcome(2, 40, 40, None),
+                outcome(3, 40, 0, Some(IlpErrorCode::T04_INSUFFICIENT_LIQUIDITY)),
+                outcome(4, 40, 40, None),
+                outcome(5, 20, 20, None),
+            ]
+        );
+        assert_eq!(receipt.delivered_amount, 100);
+    }
+
     #[tokio::test]
     async fn sends_concurrent_packets() {
         let destination_address = Address::from_str("example.receiver").unwrap();
@@ -905,12 +2180,629 @@ mod send_money_tests {
             destination_address.clone(),
             vec![0; 32],
             50,
-            0.0,
+            SendMoneyOptions {
+                slippage: 0.0,
+                ..SendMoneyOptions::default()
+            },
+        )
+        .await;
+
+        assert!(result.is_err());
+        // Plus the ConnectionClose sent after the first final error
+        assert_eq!(num_requests_in_flight.load(Ordering::Relaxed), 6);
+    }
+
+    #[tokio::test]
+    async fn respects_custom_congestion_control() {
+        /// Allows a fixed amount in flight, no matter what happens to the packets
+        struct FixedWindow {
+            window: u64,
+            in_flight: u64,
+        }
+
+        impl CongestionControl for FixedWindow {
+            fn get_max_packet_amount(&self) -> u64 {
+                u64::MAX
+            }
+
+            fn get_amount_left_in_window(&self) -> u64 {
+                self.window - self.in_flight
+            }
+
+            fn prepare(&mut self, amount: u64) -> u64 {
+                self.in_flight += amount;
+                0
+            }
+
+            fn fulfill(&mut self, _sequence: u64, prepare_amount: u64) {
+                self.in_flight -= prepare_amount;
+            }
+
+            fn reject(&mut self, _sequence: u64, prepare_amount: u64, _reject: &Reject) {
+                self.in_flight -= prepare_amount;
+            }
+        }
+
+        let destination_address = Address::from_str("example.receiver").unwrap();
+        let account = TestAccount {
+            id: Uuid::new_v4(),
+            asset_code: "XYZ".to_string(),
+            asset_scale: 9,
+            ilp_address: destination_address.clone(),
+            max_packet_amount: None,
+        };
+        let amounts = Arc::new(Mutex::new(Vec::new()));
+        let amounts_clone = amounts.clone();
+
+        let result = send_money(
+            incoming_service_fn(move |request| {
+                amounts_clone.lock().push(request.prepare.amount());
+                Err(RejectBuilder {
+                    code: IlpErrorCode::F00_BAD_REQUEST,
+                    message: b"just some final error",
+                    triggered_by: Some(&EXAMPLE_CONNECTOR),
+                    data: &[],
+                }
+                .build())
+            }),
+            &account,
+            TestStore {
+                route: None,
+                price_1: None,
+                price_2: None,
+            },
+            destination_address,
+            vec![0; 32],
+            100,
+            SendMoneyOptions {
+                slippage: 0.0,
+                congestion_controller: Some(Box::new(FixedWindow {
+                    window: 25,
+                    in_flight: 0,
+                })),
+                ..SendMoneyOptions::default()
+            },
+        )
+        .await;
+
+        assert!(result.is_err());
+        // The default controller would have sent the whole 100 at once
+        assert_eq!(*amounts.lock(), vec![25, 0]);
+    }
+
+    #[test]
+    fn computes_packet_expiry_from_the_smoothed_rtt() {
+        assert_eq!(packet_expiry(None), DEFAULT_PACKET_EXPIRY);
+        assert_eq!(
+            packet_expiry(Some(Duration::from_secs(2))),
+            Duration::from_secs(8)
+        );
+        assert_eq!(
+            packet_expiry(Some(Duration::from_millis(1_500))),
+            Duration::from_secs(6)
+        );
+        // Clamped to the bounds
+        assert_eq!(
+            packet_expiry(Some(Duration::from_millis(100))),
+            MIN_PACKET_EXPIRY
+        );
+        assert_eq!(
+            packet_expiry(Some(Duration::from_secs(20))),
+            MAX_PACKET_EXPIRY
+        );
+        assert_eq!(packet_expiry(Some(Duration::MAX)), MAX_PACKET_EXPIRY);
+    }
+
+    #[tokio::test]
+    async fn expires_packets_after_the_smoothed_rtt() {
+        /// Sends everything at once and reports a fixed round trip time
+        struct MeasuredRtt(Option<Duration>);
+
+        impl CongestionControl for MeasuredRtt {
+            fn get_max_packet_amount(&self) -> u64 {
+                u64::MAX
+            }
+
+            fn get_amount_left_in_window(&self) -> u64 {
+                u64::MAX
+            }
+
+            fn prepare(&mut self, _amount: u64) -> u64 {
+                0
+            }
+
+            fn fulfill(&mut self, _sequence: u64, _prepare_amount: u64) {}
+
+            fn reject(&mut self, _sequence: u64, _prepare_amount: u64, _reject: &Reject) {}
+
+            fn smoothed_rtt(&self) -> Option<Duration> {
+                self.0
+            }
+        }
+
+        for (rtt, expiry) in [
+            (None, DEFAULT_PACKET_EXPIRY),
+            (Some(Duration::from_secs(2)), Duration::from_secs(8)),
+            (Some(Duration::from_millis(10)), MIN_PACKET_EXPIRY),
+        ] {
+            let expiries = Arc::new(Mutex::new(Vec::new()));
+            let expiries_clone = expiries.clone();
+            let start = SystemTime::now();
+            let result = send_money(
+                incoming_service_fn(move |request: IncomingRequest<TestAccount>| {
+                    expiries_clone.lock().push(request.prepare.expires_at());
+                    Err(RejectBuilder {
+                        code: IlpErrorCode::F00_BAD_REQUEST,
+                        message: b"just some final error",
+                        triggered_by: Some(&EXAMPLE_CONNECTOR),
+                        data: &[],
+                    }
+                    .build())
+                }),
+                &test_account(),
+                TestStore {
+                    route: None,
+                    price_1: None,
+                    price_2: None,
+                },
+                Address::from_str("example.destination").unwrap(),
+                vec![0; 32],
+                100,
+                SendMoneyOptions {
+                    slippage: 0.0,
+                    congestion_controller: Some(Box::new(MeasuredRtt(rtt))),
+                    ..SendMoneyOptions::default()
+                },
+            )
+            .await;
+            let end = SystemTime::now();
+
+            assert!(result.is_err());
+            // The packet only encodes the expiry to the millisecond
+            let expires_at = expiries.lock()[0];
+            assert!(expires_at + Duration::from_millis(1) >= start + expiry);
+            assert!(expires_at <= end + expiry);
+        }
+    }
+
+    const RECEIPT_NONCE: [u8; 16] = [6; 16];
+    const RECEIPT_SECRET: [u8; 32] = [7; 32];
+
+    /// Receives money like the real recipient, splitting it across streams by their shares and
+    /// signing receipts with `RECEIPT_SECRET`, but advertises a receive max for each stream
+    /// computed from its id and what it has received
+    fn limited_receiver(
+        shared_secret: Bytes,
+        receive_max: fn(u64, u64) -> u64,
+        amounts: Arc<Mutex<Vec<u64>>>,
+    ) -> impl IncomingService<TestAccount> + Clone {
+        let received = Arc::new(Mutex::new(BTreeMap::<u64, u64>::new()));
+        incoming_service_fn(move |request: IncomingRequest<TestAccount>| {
+            let prepare = request.prepare;
+            amounts.lock().push(prepare.amount());
+            let request_packet =
+                StreamPacket::from_encrypted(&shared_secret, BytesMut::from(prepare.data()))
+                    .unwrap();
+            let fulfillment = generate_fulfillment(&shared_secret, prepare.data());
+            let is_fulfillable = hash_sha256(&fulfillment) == prepare.execution_condition();
+
+            let shares: Vec<(u64, u64)> = request_packet
+                .frames()
+                .filter_map(|frame| match frame {
+                    Frame::StreamMoney(frame) => Some((frame.stream_id, frame.shares)),
+                    _ => None,
+                })
+                .collect();
+            let total_shares: u64 = shares.iter().map(|&(_, share)| share).sum();
+
+            let mut received = received.lock();
+            let totals: Vec<(u64, u64)> = shares
+                .iter()
+                .map(|&(stream_id, share)| {
+                    let total_received = received.entry(stream_id).or_default();
+                    if is_fulfillable {
+                        *total_received += prepare.amount() * share / total_shares;
+                    }
+                    (stream_id, *total_received)
+                })
+                .collect();
+            let receipts: Vec<Bytes> = totals
+                .iter()
+                .map(|&(stream_id, total_received)| {
+                    Receipt {
+                        nonce: RECEIPT_NONCE,
+                        stream_id,
+                        total_received,
+                    }
+                    .sign(&RECEIPT_SECRET)
+                })
+                .collect();
+
+            let mut frames = vec![Frame::ConnectionAssetDetails(ConnectionAssetDetailsFrame {
+                source_asset_code: "XYZ",
+                source_asset_scale: 9,
+            })];
+            for (&(stream_id, total_received), receipt) in totals.iter().zip(&receipts) {
+                frames.push(Frame::StreamMaxMoney(StreamMaxMoneyFrame {
+                    stream_id,
+                    receive_max: receive_max(stream_id, total_received),
+                    total_received,
+                }));
+                if is_fulfillable {
+                    frames.push(Frame::StreamReceipt(StreamReceiptFrame {
+                        stream_id,
+                        receipt,
+                    }));
+                }
+            }
+
+            let reply = StreamPacketBuilder {
+                sequence: request_packet.sequence(),
+                ilp_packet_type: if is_fulfillable {
+                    IlpPacketType::Fulfill
+                } else {
+                    IlpPacketType::Reject
+                },
+                prepare_amount: prepare.amount(),
+                frames: &frames,
+            }
+            .build()
+            .into_encrypted(&shared_secret);
+
+            if is_fulfillable {
+                Ok(FulfillBuilder {
+                    fulfillment: &fulfillment,
+                    data: &reply,
+                }
+                .build())
+            } else {
+                Err(RejectBuilder {
+                    code: IlpErrorCode::F99_APPLICATION_ERROR,
+                    message: &[],
+                    triggered_by: None,
+                    data: &reply,
+                }
+                .build())
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn throttles_to_receive_window() {
+        let destination_address = Address::from_str("example.receiver").unwrap();
+        let account = TestAccount {
+            id: Uuid::new_v4(),
+            asset_code: "XYZ".to_string(),
+            asset_scale: 9,
+            ilp_address: destination_address.clone(),
+            max_packet_amount: None,
+        };
+        let amounts = Arc::new(Mutex::new(Vec::new()));
+
+        let receipt = send_money(
+            // Never willing to take more than 10 beyond what it has received
+            limited_receiver(
+                Bytes::from(vec![0; 32]),
+                |_, received| received + 10,
+                amounts.clone(),
+            ),
+            &account,
+            TestStore {
+                route: None,
+                price_1: None,
+                price_2: None,
+            },
+            destination_address,
+            vec![0; 32],
+            100,
+            SendMoneyOptions {
+                slippage: 0.0,
+                ..SendMoneyOptions::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(receipt.delivered_amount, 100);
+        let amounts = amounts.lock();
+        // The first packet learns the asset details and the window, so it can't be fulfilled
+        assert_eq!(amounts[0], 100);
+        assert_eq!(amounts[1..].iter().sum::<u64>(), 100);
+        assert!(amounts[1..].iter().all(|&amount| amount <= 10));
+    }
+
+    #[tokio::test]
+    async fn stops_when_receive_max_is_reached() {
+        let destination_address = Address::from_str("example.receiver").unwrap();
+        let account = TestAccount {
+            id: Uuid::new_v4(),
+            asset_code: "XYZ".to_string(),
+            asset_scale: 9,
+            ilp_address: destination_address.clone(),
+            max_packet_amount: None,
+        };
+        let amounts = Arc::new(Mutex::new(Vec::new()));
+
+        let result = send_money(
+            limited_receiver(Bytes::from(vec![0; 32]), |_, _| 30, amounts.clone()),
+            &account,
+            TestStore {
+                route: None,
+                price_1: None,
+                price_2: None,
+            },
+            destination_address,
+            vec![0; 32],
+            100,
+            SendMoneyOptions {
+                slippage: 0.0,
+                ..SendMoneyOptions::default()
+            },
+        )
+        .await;
+
+        match result {
+            Err(StreamError::Rejected {
+                reason: RejectReason::ReceiveMaxExceeded(30),
+                progress,
+            }) => assert_eq!(progress.delivered_amount, 30),
+            other => panic!("Expected the receive max to be reached, got {:?}", other),
+        }
+        // Then the ConnectionClose
+        assert_eq!(*amounts.lock(), vec![100, 30, 0]);
+    }
+
+    #[tokio::test]
+    async fn overflows_into_streams_with_room() {
+        let destination_address = Address::from_str("example.receiver").unwrap();
+        let account = TestAccount {
+            id: Uuid::new_v4(),
+            asset_code: "XYZ".to_string(),
+            asset_scale: 9,
+            ilp_address: destination_address.clone(),
+            max_packet_amount: None,
+        };
+        let amounts = Arc::new(Mutex::new(Vec::new()));
+
+        let receipt = send_money(
+            // Stream 1 takes at most 20, stream 3 up to 1000
+            limited_receiver(
+                Bytes::from(vec![0; 32]),
+                |stream_id, _| if stream_id == 1 { 20 } else { 1000 },
+                amounts.clone(),
+            ),
+            &account,
+            TestStore {
+                route: None,
+                price_1: None,
+                price_2: None,
+            },
+            destination_address,
+            vec![0; 32],
+            100,
+            SendMoneyOptions {
+                slippage: 0.0,
+                congestion_controller: Some(Box::new(CongestionController::new(100, 10, 2.0))),
+                stream_ids: vec![1, 3],
+                ..SendMoneyOptions::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(receipt.delivered_amount, 100);
+        assert_eq!(receipt.streams.len(), 2);
+        let (first, second) = (&receipt.streams[&1], &receipt.streams[&3]);
+        assert_eq!(first.delivered_amount, 20);
+        assert_eq!(first.receive_max, Some(20));
+        assert_eq!(first.total_received, 20);
+        assert_eq!(second.delivered_amount, 80);
+        assert_eq!(second.total_received, 80);
+        assert_eq!(first.in_flight_amount + second.in_flight_amount, 0);
+    }
+
+    #[tokio::test]
+    async fn returns_latest_receipt() {
+        let destination_address = Address::from_str("example.receiver").unwrap();
+        let account = TestAccount {
+            id: Uuid::new_v4(),
+            asset_code: "XYZ".to_string(),
+            asset_scale: 9,
+            ilp_address: destination_address.clone(),
+            max_packet_amount: None,
+        };
+
+        let receipt = send_money(
+            limited_receiver(
+                Bytes::from(vec![0; 32]),
+                |_, received| received + 40,
+                Arc::new(Mutex::new(Vec::new())),
+            ),
+            &account,
+            TestStore {
+                route: None,
+                price_1: None,
+                price_2: None,
+            },
+            destination_address,
+            vec![0; 32],
+            100,
+            SendMoneyOptions {
+                slippage: 0.0,
+                ..SendMoneyOptions::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        let stream = &receipt.streams[&1];
+        assert_eq!(stream.receipt_total_received(), Some(100));
+        let verified = Receipt::verify(&RECEIPT_SECRET, stream.receipt.as_ref().unwrap()).unwrap();
+        assert_eq!(verified.nonce, RECEIPT_NONCE);
+        assert_eq!(verified.total_received, 100);
+
+        #[cfg(feature = "serde")]
+        {
+            let json = serde_json::to_value(&receipt).unwrap();
+            assert_eq!(
+                json["streams"]["1"]["receipt"].as_str().unwrap().len(),
+                2 * 59
+            );
+            let deserialized: StreamDelivery = serde_json::from_value(json).unwrap();
+            assert_eq!(deserialized, receipt);
+        }
+    }
+
+    #[tokio::test]
+    async fn stops_when_peer_closes_connection() {
+        let destination_address = Address::from_str("example.receiver").unwrap();
+        let account = TestAccount {
+            id: Uuid::new_v4(),
+            asset_code: "XYZ".to_string(),
+            asset_scale: 9,
+            ilp_address: destination_address.clone(),
+            max_packet_amount: None,
+        };
+        let shared_secret = Bytes::from(vec![0; 32]);
+
+        let result = send_money(
+            incoming_service_fn(move |request: IncomingRequest<TestAccount>| {
+                let request_packet = StreamPacket::from_encrypted(
+                    &shared_secret,
+                    BytesMut::from(request.prepare.data()),
+                )
+                .unwrap();
+                let reply = StreamPacketBuilder {
+                    sequence: request_packet.sequence(),
+                    ilp_packet_type: IlpPacketType::Reject,
+                    prepare_amount: 0,
+                    frames: &[Frame::ConnectionClose(ConnectionCloseFrame {
+                        code: ErrorCode::ApplicationError,
+                        message: "Closing shop",
+                    })],
+                }
+                .build()
+                .into_encrypted(&shared_secret);
+                Err(RejectBuilder {
+                    code: IlpErrorCode::F99_APPLICATION_ERROR,
+                    message: &[],
+                    triggered_by: None,
+                    data: &reply,
+                }
+                .build())
+            }),
+            &account,
+            TestStore {
+                route: None,
+                price_1: None,
+                price_2: None,
+            },
+            destination_address,
+            vec![0; 32],
+            100,
+            SendMoneyOptions {
+                slippage: 0.0,
+                ..SendMoneyOptions::default()
+            },
+        )
+        .await;
+
+        match result {
+            Err(StreamError::Rejected {
+                reason: RejectReason::ClosedByPeer(code, message),
+                ..
+            }) => {
+                assert_eq!(code, ErrorCode::ApplicationError);
+                assert_eq!(message, "Closing shop");
+            }
+            other => panic!("Expected the peer to close the connection, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn paces_packets() {
+        /// Lets one packet through at a time, no faster than every 50ms
+        struct Paced {
+            in_flight: u64,
+        }
+
+        impl CongestionControl for Paced {
+            fn get_max_packet_amount(&self) -> u64 {
+                u64::MAX
+            }
+
+            fn get_amount_left_in_window(&self) -> u64 {
+                10 - self.in_flight
+            }
+
+            fn prepare(&mut self, amount: u64) -> u64 {
+                self.in_flight += amount;
+                0
+            }
+
+            fn fulfill(&mut self, _sequence: u64, prepare_amount: u64) {
+                self.in_flight -= prepare_amount;
+            }
+
+            fn reject(&mut self, _sequence: u64, prepare_amount: u64, _reject: &Reject) {
+                self.in_flight -= prepare_amount;
+            }
+
+            fn pacing_interval(&self) -> Option<Duration> {
+                Some(Duration::from_millis(50))
+            }
+        }
+
+        let destination_address = Address::from_str("example.receiver").unwrap();
+        let account = TestAccount {
+            id: Uuid::new_v4(),
+            asset_code: "XYZ".to_string(),
+            asset_scale: 9,
+            ilp_address: destination_address.clone(),
+            max_packet_amount: None,
+        };
+        let request_times = Arc::new(Mutex::new(Vec::new()));
+        let request_times_clone = request_times.clone();
+
+        let result = send_money(
+            incoming_service_fn(move |_| {
+                let mut request_times = request_times_clone.lock();
+                request_times.push(Instant::now());
+                Err(RejectBuilder {
+                    code: if request_times.len() < 4 {
+                        IlpErrorCode::T04_INSUFFICIENT_LIQUIDITY
+                    } else {
+                        IlpErrorCode::F00_BAD_REQUEST
+                    },
+                    message: &[],
+                    triggered_by: Some(&EXAMPLE_CONNECTOR),
+                    data: &[],
+                }
+                .build())
+            }),
+            &account,
+            TestStore {
+                route: None,
+                price_1: None,
+                price_2: None,
+            },
+            destination_address,
+            vec![0; 32],
+            100,
+            SendMoneyOptions {
+                slippage: 0.0,
+                congestion_controller: Some(Box::new(Paced { in_flight: 0 })),
+                ..SendMoneyOptions::default()
+            },
         )
         .await;
 
         assert!(result.is_err());
-        assert_eq!(num_requests_in_flight.load(Ordering::Relaxed), 5);
+        let request_times = request_times.lock();
+        // Including the ConnectionClose after the final error
+        assert_eq!(request_times.len(), 5);
+        // Without pacing, the rejected packets would be retried right away
+        assert!(request_times[3] - request_times[0] >= Duration::from_millis(140));
     }
 
     #[tokio::test]
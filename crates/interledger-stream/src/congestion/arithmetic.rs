@@ -0,0 +1,98 @@
+//! Arithmetic on the controller's amounts.
+//!
+//! Overflows can't happen as long as the controller's invariants hold, e.g. that no more
+//! than `u64::MAX` is ever in flight. By default, a violation panics like any other
+//! arithmetic error in debug builds. With the `defensive` feature, the operations
+//! use checked math instead: a violation logs an error with its operands and the result
+//! is clamped to the nearest safe value, so a latent bug doesn't crash the connector.
+//!
+//! `mul_div` is the exception, since its operands aren't under the controller's control:
+//! it's always checked.
+
+use std::convert::TryFrom;
+use std::time::Duration;
+#[cfg(feature = "defensive")]
+use tracing::error;
+use tracing::warn;
+
+/// `a + b`, clamped to `u64::MAX` on overflow in defensive builds
+#[cfg(feature = "defensive")]
+pub(crate) fn add(a: u64, b: u64, context: &str) -> u64 {
+    a.checked_add(b).unwrap_or_else(|| {
+        error!(
+            "Arithmetic overflow in {}: {} + {}, clamping to {}",
+            context,
+            a,
+            b,
+            u64::MAX
+        );
+        u64::MAX
+    })
+}
+
+#[cfg(not(feature = "defensive"))]
+pub(crate) fn add(a: u64, b: u64, _context: &str) -> u64 {
+    a + b
+}
+
+/// `a + b` for durations, clamped to `Duration::MAX` on overflow in defensive builds
+#[cfg(feature = "defensive")]
+pub(crate) fn add_duration(a: Duration, b: Duration, context: &str) -> Duration {
+    a.checked_add(b).unwrap_or_else(|| {
+        error!(
+            "Arithmetic overflow in {}: {:?} + {:?}, clamping to {:?}",
+            context,
+            a,
+            b,
+            Duration::MAX
+        );
+        Duration::MAX
+    })
+}
+
+#[cfg(not(feature = "defensive"))]
+pub(crate) fn add_duration(a: Duration, b: Duration, _context: &str) -> Duration {
+    a + b
+}
+
+/// `a * b / c`, computed in 128 bits so the product can't overflow. Unlike the other
+/// operations, the operands may come from peers, e.g. the details of an F08 reject, so this
+/// never panics: it returns `None` on division by zero and clamps a quotient too large for
+/// a u64 to `u64::MAX`, logging either case.
+pub(crate) fn mul_div(a: u64, b: u64, c: u64, context: &str) -> Option<u64> {
+    if c == 0 {
+        warn!(
+            "Division by zero in {}: {} * {} / {}, ignoring the result",
+            context, a, b, c
+        );
+        return None;
+    }
+    let quotient = u128::from(a) * u128::from(b) / u128::from(c);
+    Some(u64::try_from(quotient).unwrap_or_else(|_| {
+        warn!(
+            "Arithmetic overflow in {}: {} * {} / {}, clamping to {}",
+            context,
+            a,
+            b,
+            c,
+            u64::MAX
+        );
+        u64::MAX
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mul_div_does_not_overflow_intermediate_product() {
+        assert_eq!(
+            mul_div(u64::MAX, u64::MAX, u64::MAX, "test"),
+            Some(u64::MAX)
+        );
+        assert_eq!(mul_div(u64::MAX, 3, 4, "test"), Some(u64::MAX / 4 * 3 + 2));
+        assert_eq!(mul_div(u64::MAX, u64::MAX, 1, "test"), Some(u64::MAX));
+        assert_eq!(mul_div(10, 20, 0, "test"), None);
+    }
+}
@@ -0,0 +1,95 @@
+//! Compact binary encoding of the congestion event log.
+//!
+//! The log is encoded as a var-uint event count followed by each event: a one byte
+//! event type, the amount as a var-uint and, for rejects, the three byte ILP error code.
+//! Var-uints follow the OER conventions used by ILP packets.
+
+use super::CongestionEvent;
+use crate::error::EventLogError;
+use bytes::{Buf, BufMut};
+use interledger_packet::oer::{BufOerExt, MutBufOerExt};
+use interledger_packet::{ErrorCode, OerError};
+
+const PREPARE: u8 = 1;
+const FULFILL: u8 = 2;
+const REJECT: u8 = 3;
+
+pub(crate) fn encode<'a>(events: impl ExactSizeIterator<Item = &'a CongestionEvent>) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    buffer.put_var_uint(events.len() as u64);
+    for event in events {
+        match *event {
+            CongestionEvent::Prepare { amount } => {
+                buffer.put_u8(PREPARE);
+                buffer.put_var_uint(amount);
+            }
+            CongestionEvent::Fulfill { amount } => {
+                buffer.put_u8(FULFILL);
+                buffer.put_var_uint(amount);
+            }
+            CongestionEvent::Reject { amount, code } => {
+                buffer.put_u8(REJECT);
+                buffer.put_var_uint(amount);
+                buffer.put_slice(&<[u8; 3]>::from(code));
+            }
+        }
+    }
+    buffer
+}
+
+pub(crate) fn decode(mut reader: &[u8]) -> Result<Vec<CongestionEvent>, EventLogError> {
+    let count = reader.read_var_uint()?;
+    // Every event takes at least two bytes, which bounds the allocation for corrupt counts
+    let mut events = Vec::with_capacity(count.min(reader.len() as u64 / 2) as usize);
+    for _ in 0..count {
+        if !reader.has_remaining() {
+            return Err(OerError::UnexpectedEof.into());
+        }
+        let event_type = reader.get_u8();
+        let amount = reader.read_var_uint()?;
+        let event = match event_type {
+            PREPARE => CongestionEvent::Prepare { amount },
+            FULFILL => CongestionEvent::Fulfill { amount },
+            REJECT => {
+                if reader.len() < 3 {
+                    return Err(OerError::UnexpectedEof.into());
+                }
+                let mut code = [0; 3];
+                reader.copy_to_slice(&mut code);
+                let code = ErrorCode::new(code).ok_or(EventLogError::InvalidErrorCode)?;
+                CongestionEvent::Reject { amount, code }
+            }
+            other => return Err(EventLogError::UnknownEventType(other)),
+        };
+        events.push(event);
+    }
+    if reader.has_remaining() {
+        return Err(EventLogError::TrailingBytes);
+    }
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_malformed_logs() {
+        assert!(matches!(
+            decode(&[]),
+            Err(EventLogError::Oer(OerError::UnexpectedEof))
+        ));
+        assert!(matches!(
+            decode(&[1, 1, 9, 1, 1]),
+            Err(EventLogError::UnknownEventType(9))
+        ));
+        assert!(matches!(
+            decode(&[1, 1, REJECT, 1, 1, b'T']),
+            Err(EventLogError::Oer(OerError::UnexpectedEof))
+        ));
+        assert!(matches!(
+            decode(&[1, 0, 0]),
+            Err(EventLogError::TrailingBytes)
+        ));
+    }
+}
@@ -0,0 +1,179 @@
+use super::CongestionController;
+use crate::error::CongestionError;
+use serde::Deserialize;
+
+/// How the window grows before the first congestion signal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum GrowthStrategy {
+    /// Double the window on every fulfill
+    #[default]
+    SlowStart,
+    /// Double the window over each round trip, spread across its fulfills
+    PacedSlowStart,
+}
+
+/// Congestion controller parameters, as read from a section of the node's config file.
+///
+/// Only `start_amount` and `increase_amount` are required, e.g. in JSON:
+///
+/// ```json
+/// { "start_amount": 10000, "increase_amount": 1000, "max_in_flight": 1000000 }
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CongestionConfig {
+    /// Initial window
+    pub start_amount: u64,
+    /// Amount added to the window per fulfill during congestion avoidance
+    pub increase_amount: u64,
+    /// Factor the window is divided by on a congestion signal
+    #[serde(default = "default_decrease_factor")]
+    pub decrease_factor: f64,
+    /// The window never shrinks below this
+    #[serde(default)]
+    pub min_in_flight: u64,
+    /// The window never grows above this
+    #[serde(default = "default_max_in_flight")]
+    pub max_in_flight: u64,
+    /// Fraction of the window the sender may fill
+    #[serde(default = "default_target_utilization")]
+    pub target_utilization: f64,
+    #[serde(default)]
+    pub growth_strategy: GrowthStrategy,
+    /// Window at which slow start ends, if set. See [`CongestionController::with_ssthresh`].
+    #[serde(default)]
+    pub ssthresh: Option<u64>,
+    /// Scales growth and decrease by the asset's volatility, if set.
+    /// See [`CongestionController::with_volatility_scaling`].
+    #[serde(default)]
+    pub volatility_sensitivity: Option<f64>,
+}
+
+fn default_decrease_factor() -> f64 {
+    2.0
+}
+
+fn default_max_in_flight() -> u64 {
+    u64::MAX
+}
+
+fn default_target_utilization() -> f64 {
+    1.0
+}
+
+impl CongestionController {
+    /// Validates the config and builds a controller from it
+    pub fn from_config(config: &CongestionConfig) -> Result<Self, CongestionError> {
+        let mut controller =
+            CongestionController::new(config.start_amount, config.increase_amount, 2.0)
+                .with_window_bounds(config.min_in_flight, config.max_in_flight)?
+                .with_target_utilization(config.target_utilization)?;
+        controller.set_decrease_factor(config.decrease_factor)?;
+        if let Some(ssthresh) = config.ssthresh {
+            controller = controller.with_ssthresh(ssthresh);
+        }
+        if config.growth_strategy == GrowthStrategy::PacedSlowStart {
+            controller = controller.with_paced_slow_start();
+        }
+        if let Some(sensitivity) = config.volatility_sensitivity {
+            if !sensitivity.is_finite() || sensitivity < 0.0 {
+                return Err(CongestionError::InvalidVolatilitySensitivity(sensitivity));
+            }
+            controller = controller.with_volatility_scaling(sensitivity);
+        }
+        Ok(controller)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_controller_from_sample_config() {
+        let config: CongestionConfig = serde_json::from_str(
+            r#"{
+                "start_amount": 10000,
+                "increase_amount": 1000,
+                "decrease_factor": 1.5,
+                "min_in_flight": 500,
+                "max_in_flight": 1000000,
+                "target_utilization": 0.8,
+                "growth_strategy": "paced-slow-start",
+                "ssthresh": 50000,
+                "volatility_sensitivity": 2.0
+            }"#,
+        )
+        .unwrap();
+        let controller = CongestionController::from_config(&config).unwrap();
+
+        assert_eq!(controller.authorized_window(), 10000);
+        assert_eq!(controller.increase_amount(), 1000);
+        assert_eq!(controller.decrease_factor(), 1.5);
+        assert_eq!(controller.target_utilization(), 0.8);
+        assert_eq!(controller.ssthresh(), Some(50000));
+        assert_eq!(
+            controller.active_growth_strategy(),
+            "volatility-scaled paced slow start"
+        );
+        assert_eq!(
+            controller.active_decrease_strategy(),
+            "volatility-scaled multiplicative decrease with floor"
+        );
+    }
+
+    #[test]
+    fn defaults_optional_parameters() {
+        let config: CongestionConfig =
+            serde_json::from_str(r#"{ "start_amount": 10000, "increase_amount": 1000 }"#).unwrap();
+        assert_eq!(config.decrease_factor, 2.0);
+        assert_eq!(config.max_in_flight, u64::MAX);
+
+        let controller = CongestionController::from_config(&config).unwrap();
+        assert_eq!(controller.decrease_factor(), 2.0);
+        assert_eq!(controller.active_growth_strategy(), "slow start");
+        assert_eq!(
+            controller.active_decrease_strategy(),
+            "multiplicative decrease"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_config() {
+        let config: CongestionConfig = serde_json::from_str(
+            r#"{ "start_amount": 10, "increase_amount": 1, "min_in_flight": 20, "max_in_flight": 10 }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            CongestionController::from_config(&config).err(),
+            Some(CongestionError::MinExceedsCap { min: 20, cap: 10 })
+        );
+
+        let config: CongestionConfig = serde_json::from_str(
+            r#"{ "start_amount": 10, "increase_amount": 1, "decrease_factor": 0.5 }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            CongestionController::from_config(&config).err(),
+            Some(CongestionError::InvalidDecreaseFactor(0.5))
+        );
+
+        let config: CongestionConfig = serde_json::from_str(
+            r#"{ "start_amount": 10, "increase_amount": 1, "volatility_sensitivity": -1.0 }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            CongestionController::from_config(&config).err(),
+            Some(CongestionError::InvalidVolatilitySensitivity(-1.0))
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        assert!(serde_json::from_str::<CongestionConfig>(
+            r#"{ "start_amount": 10, "increase_amount": 1, "max_packet_amount": 5 }"#
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,141 @@
+use std::collections::VecDeque;
+
+/// Smooths a time series of samples, such as round trip times or delivery rates,
+/// into a single estimate.
+///
+/// The [congestion controller](./struct.CongestionController.html) uses estimators for its
+/// round trip time and delivery rate, so operators can choose how they are smoothed.
+pub trait Estimator: Send + Sync {
+    /// Adds a sample to the estimate
+    fn observe(&mut self, sample: f64);
+
+    /// The current estimate, or 0.0 before any sample was observed
+    fn value(&self) -> f64;
+
+    /// Clones the estimator, including its state, so controllers using it can be cloned
+    fn clone_box(&self) -> Box<dyn Estimator>;
+}
+
+impl Clone for Box<dyn Estimator> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+/// Exponentially weighted moving average: each sample moves the estimate by `alpha` times
+/// its difference from the estimate. The first sample is taken as is.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ewma {
+    alpha: f64,
+    value: Option<f64>,
+}
+
+impl Ewma {
+    /// Creates an average with the given weight for new samples, clamped to [0.0, 1.0]
+    pub fn new(alpha: f64) -> Self {
+        Ewma {
+            alpha: alpha.clamp(0.0, 1.0),
+            value: None,
+        }
+    }
+}
+
+impl Estimator for Ewma {
+    fn observe(&mut self, sample: f64) {
+        self.value = Some(match self.value {
+            Some(value) => value + self.alpha * (sample - value),
+            None => sample,
+        });
+    }
+
+    fn value(&self) -> f64 {
+        self.value.unwrap_or(0.0)
+    }
+
+    fn clone_box(&self) -> Box<dyn Estimator> {
+        Box::new(self.clone())
+    }
+}
+
+/// Plain average of the most recent samples, which follows changes within a fixed
+/// number of samples and then forgets them entirely
+#[derive(Debug, Clone, PartialEq)]
+pub struct WindowedAverage {
+    samples: VecDeque<f64>,
+    size: usize,
+}
+
+impl WindowedAverage {
+    /// Creates an average over the last `size` samples (at least 1)
+    pub fn new(size: usize) -> Self {
+        WindowedAverage {
+            samples: VecDeque::new(),
+            size: size.max(1),
+        }
+    }
+}
+
+impl Estimator for WindowedAverage {
+    fn observe(&mut self, sample: f64) {
+        if self.samples.len() >= self.size {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    fn value(&self) -> f64 {
+        if self.samples.is_empty() {
+            return 0.0;
+        }
+        self.samples.iter().sum::<f64>() / self.samples.len() as f64
+    }
+
+    fn clone_box(&self) -> Box<dyn Estimator> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ewma_and_windowed_average_respond_differently_to_a_step() {
+        let mut ewma = Ewma::new(0.5);
+        let mut windowed = WindowedAverage::new(4);
+        assert_eq!(ewma.value(), 0.0);
+        assert_eq!(windowed.value(), 0.0);
+
+        let mut ewma_values = Vec::new();
+        let mut windowed_values = Vec::new();
+        for sample in [
+            100.0, 100.0, 100.0, 100.0, 200.0, 200.0, 200.0, 200.0, 200.0,
+        ] {
+            ewma.observe(sample);
+            windowed.observe(sample);
+            ewma_values.push(ewma.value());
+            windowed_values.push(windowed.value());
+        }
+
+        // The EWMA approaches the new level geometrically and never quite reaches it
+        assert_eq!(
+            ewma_values,
+            vec![100.0, 100.0, 100.0, 100.0, 150.0, 175.0, 187.5, 193.75, 196.875]
+        );
+        // The windowed average ramps linearly and fully adopts it after a window of samples
+        assert_eq!(
+            windowed_values,
+            vec![100.0, 100.0, 100.0, 100.0, 125.0, 150.0, 175.0, 200.0, 200.0]
+        );
+    }
+
+    #[test]
+    fn boxed_estimators_clone_their_state() {
+        let mut original: Box<dyn Estimator> = Box::new(WindowedAverage::new(2));
+        original.observe(10.0);
+        let mut clone = original.clone();
+        clone.observe(20.0);
+        assert_eq!(original.value(), 10.0);
+        assert_eq!(clone.value(), 15.0);
+    }
+}
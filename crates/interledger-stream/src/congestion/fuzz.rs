@@ -0,0 +1,191 @@
+//! Entry point for coverage-guided fuzzing of the congestion controller.
+//!
+//! The input is read as a sequence of 12-byte operations, each made of an op code byte,
+//! a big-endian u64 operand and three bytes picking a reject code. A trailing partial
+//! operation is ignored. The controller's invariants are checked after every operation.
+
+use super::{valid_transitions, CongestionController, CongestionState, MockClock};
+use interledger_packet::{ErrorCode, MaxPacketAmountDetails, RejectBuilder};
+use std::convert::TryInto;
+use std::time::Duration;
+
+const OPERATION_LEN: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operation {
+    /// Prepare a packet, as large as the operand but no larger than the sender may send
+    Prepare(u64),
+    /// Fulfill the outstanding packet picked by the operand
+    Fulfill(u64),
+    /// Reject the outstanding packet picked by the operand. F08 rejects report the
+    /// operand as the max amount.
+    Reject(u64, ErrorCode),
+    /// Advance the clock by the operand in milliseconds, up to 10 seconds
+    AdvanceClock(u64),
+    BeginDrain,
+}
+
+fn decode(data: &[u8]) -> impl Iterator<Item = Operation> + '_ {
+    data.chunks_exact(OPERATION_LEN).map(|chunk| {
+        let operand = u64::from_be_bytes(chunk[1..9].try_into().unwrap());
+        match chunk[0] % 5 {
+            0 => Operation::Prepare(operand),
+            1 => Operation::Fulfill(operand),
+            2 => Operation::Reject(operand, error_code(chunk[9], chunk[10], chunk[11])),
+            3 => Operation::AdvanceClock(operand % 10_000),
+            _ => Operation::BeginDrain,
+        }
+    })
+}
+
+/// Maps arbitrary bytes to a code from one of the three ILP error classes
+fn error_code(class: u8, tens: u8, units: u8) -> ErrorCode {
+    let class = [b'F', b'T', b'R'][usize::from(class % 3)];
+    ErrorCode::new([class, b'0' + tens % 10, b'0' + units % 10]).unwrap()
+}
+
+/// Drives a controller through the operations encoded in `data`, panicking if any of
+/// its invariants is violated along the way
+pub fn fuzz_apply(data: &[u8]) {
+    let clock = MockClock::new();
+    let mut controller = CongestionController::new(1000, 100, 2.0).with_clock(clock.clone());
+    // Sequence numbers and amounts of the packets in flight
+    let mut outstanding: Vec<(u64, u64)> = Vec::new();
+
+    for operation in decode(data) {
+        let previous_state = controller.state;
+        match operation {
+            Operation::Prepare(amount) => {
+                let amount = amount
+                    .min(controller.get_amount_left_in_window())
+                    .min(controller.get_max_packet_amount());
+                let draining = controller.state == CongestionState::Draining;
+                let sequence = controller.prepare(amount);
+                if !draining {
+                    outstanding.push((sequence, amount));
+                }
+            }
+            Operation::Fulfill(index) if !outstanding.is_empty() => {
+                let (sequence, amount) =
+                    outstanding.swap_remove(index as usize % outstanding.len());
+                controller.fulfill(sequence, amount);
+            }
+            Operation::Reject(index, code) if !outstanding.is_empty() => {
+                let (sequence, amount) =
+                    outstanding.swap_remove(index as usize % outstanding.len());
+                let details = MaxPacketAmountDetails::new(amount, index).to_bytes();
+                let reject = RejectBuilder {
+                    code,
+                    message: &[],
+                    triggered_by: None,
+                    data: if code == ErrorCode::F08_AMOUNT_TOO_LARGE {
+                        &details
+                    } else {
+                        &[]
+                    },
+                }
+                .build();
+                controller.reject(sequence, amount, &reject);
+            }
+            Operation::AdvanceClock(millis) => clock.advance(Duration::from_millis(millis)),
+            Operation::BeginDrain => controller.begin_drain(),
+            _ => {}
+        }
+        check_invariants(&controller, &outstanding, previous_state);
+    }
+}
+
+fn check_invariants(
+    controller: &CongestionController,
+    outstanding: &[(u64, u64)],
+    previous_state: CongestionState,
+) {
+    assert_eq!(
+        controller.amount_in_flight,
+        outstanding.iter().map(|&(_, amount)| amount).sum::<u64>(),
+        "amount in flight doesn't match the outstanding packets"
+    );
+    assert!(
+        controller.min_in_flight <= controller.max_in_flight
+            && controller.max_in_flight <= controller.max_in_flight_cap,
+        "window {} is outside its bounds [{}, {}]",
+        controller.max_in_flight,
+        controller.min_in_flight,
+        controller.max_in_flight_cap
+    );
+    assert!(
+        controller.get_amount_left_in_window()
+            <= controller
+                .authorized_window()
+                .saturating_sub(controller.amount_in_flight),
+        "more left in the window than it authorizes"
+    );
+    assert!(
+        controller.state == previous_state
+            || valid_transitions(previous_state).contains(&controller.state),
+        "illegal transition from {:?} to {:?}",
+        previous_state,
+        controller.state
+    );
+    if controller.is_drained() {
+        assert!(outstanding.is_empty(), "drained with packets outstanding");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn operation(op: u8, operand: u64, code: &[u8; 3]) -> Vec<u8> {
+        let mut bytes = vec![op];
+        bytes.extend_from_slice(&operand.to_be_bytes());
+        bytes.extend_from_slice(code);
+        bytes
+    }
+
+    #[test]
+    fn decodes_operations() {
+        let mut data = operation(0, 500, b"\0\0\0");
+        data.extend(operation(7, 2, &[1, 0, 4]));
+        data.extend(operation(8, 20_001, b"\0\0\0"));
+        data.extend(&[0, 1, 2]);
+        assert_eq!(
+            decode(&data).collect::<Vec<_>>(),
+            vec![
+                Operation::Prepare(500),
+                Operation::Reject(2, ErrorCode::T04_INSUFFICIENT_LIQUIDITY),
+                Operation::AdvanceClock(1),
+            ]
+        );
+    }
+
+    #[test]
+    fn seed_corpus() {
+        let prepare_fulfill = [operation(0, 500, b"\0\0\0"), operation(1, 0, b"\0\0\0")];
+        let temporary_reject = [
+            operation(0, 2000, b"\0\0\0"),
+            operation(3, 150, b"\0\0\0"),
+            operation(2, 0, &[1, 0, 4]),
+        ];
+        let amount_too_large = [operation(0, 800, b"\0\0\0"), operation(2, 300, &[0, 0, 8])];
+        let drain = [
+            operation(0, 300, b"\0\0\0"),
+            operation(0, 300, b"\0\0\0"),
+            operation(4, 0, b"\0\0\0"),
+            operation(0, 300, b"\0\0\0"),
+            operation(1, 0, b"\0\0\0"),
+            operation(2, 0, &[2, 0, 2]),
+        ];
+        let corpus: Vec<Vec<u8>> = vec![
+            Vec::new(),
+            vec![0; OPERATION_LEN - 1],
+            prepare_fulfill.concat(),
+            temporary_reject.concat(),
+            amount_too_large.concat(),
+            drain.concat(),
+        ];
+        for input in &corpus {
+            fuzz_apply(input);
+        }
+    }
+}
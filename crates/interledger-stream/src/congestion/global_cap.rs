@@ -0,0 +1,351 @@
+use super::{arithmetic, CongestionControl};
+use interledger_packet::Reject;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// Wraps a congestion controller to enforce a ceiling on the total value in flight across
+/// every connection of the node, on top of the wrapped controller's own window.
+///
+/// All the controllers sharing the same counter draw from the same global budget. Once the
+/// cap is reached, the window is empty regardless of how much the wrapped controller allows.
+/// Dropping the controller gives back whatever its packets still in flight reserved.
+pub struct GlobalCapController<C> {
+    inner: C,
+    reservations: GlobalReservations,
+    /// Maximum value allowed in flight across all the controllers sharing the counter
+    global_cap: u64,
+}
+
+/// This controller's share of the global budget, returned to it when dropped
+struct GlobalReservations {
+    /// Value in flight across all the controllers sharing this counter
+    global_in_flight: Arc<AtomicU64>,
+    /// Value reserved by this controller's packets still in flight
+    reserved: u64,
+    /// For the packets prepared through the trait whose global reservation had to be clamped
+    /// to the cap, the part that wasn't reserved, by sequence number
+    shortfalls: HashMap<u64, u64>,
+}
+
+impl<C: CongestionControl> GlobalCapController<C> {
+    pub fn new(inner: C, global_in_flight: Arc<AtomicU64>, global_cap: u64) -> Self {
+        GlobalCapController {
+            inner,
+            reservations: GlobalReservations {
+                global_in_flight,
+                reserved: 0,
+                shortfalls: HashMap::new(),
+            },
+            global_cap,
+        }
+    }
+
+    /// Value currently in flight across all the controllers sharing the counter
+    pub fn global_in_flight(&self) -> u64 {
+        self.reservations.global_in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Prepares the packet if it fits both the wrapped controller's window and the global cap.
+    ///
+    /// The global reservation is made atomically, so controllers racing for the last of the
+    /// budget can't overshoot the cap. Returns the packet's sequence number, or `None`,
+    /// leaving everything untouched, if the packet doesn't fit.
+    pub fn try_prepare(&mut self, amount: u64) -> Option<u64> {
+        if amount > self.inner.get_amount_left_in_window() {
+            return None;
+        }
+        let global_cap = self.global_cap;
+        let reserved = self
+            .reservations
+            .global_in_flight
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |in_flight| {
+                in_flight
+                    .checked_add(amount)
+                    .filter(|total| *total <= global_cap)
+            })
+            .is_ok();
+        if reserved {
+            self.reservations.reserved =
+                arithmetic::add(self.reservations.reserved, amount, "global reservation");
+            Some(self.inner.prepare(amount))
+        } else {
+            debug!(
+                "Global in-flight cap of {} reached, not preparing packet of {}",
+                global_cap, amount
+            );
+            None
+        }
+    }
+
+    /// Returns the wrapped controller, giving back what its packets still in flight reserved
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+}
+
+impl GlobalReservations {
+    /// Gives back what was actually reserved for a resolved packet
+    fn release(&mut self, sequence: u64, amount: u64) {
+        let shortfall = self.shortfalls.remove(&sequence).unwrap_or(0);
+        // Never give back more than this controller reserved, e.g. if a packet is resolved
+        // that was never prepared
+        let amount = amount.saturating_sub(shortfall).min(self.reserved);
+        self.reserved -= amount;
+        self.give_back(amount);
+    }
+
+    fn give_back(&self, amount: u64) {
+        let _ =
+            self.global_in_flight
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |in_flight| {
+                    Some(in_flight.saturating_sub(amount))
+                });
+    }
+}
+
+impl Drop for GlobalReservations {
+    fn drop(&mut self) {
+        if self.reserved > 0 {
+            debug!(
+                "Giving back {} reserved by packets still in flight",
+                self.reserved
+            );
+            self.give_back(self.reserved);
+        }
+    }
+}
+
+impl<C: CongestionControl> CongestionControl for GlobalCapController<C> {
+    fn get_max_packet_amount(&self) -> u64 {
+        self.inner.get_max_packet_amount()
+    }
+
+    fn get_amount_left_in_window(&self) -> u64 {
+        let global_left = self.global_cap.saturating_sub(self.global_in_flight());
+        self.inner.get_amount_left_in_window().min(global_left)
+    }
+
+    /// Reserves the amount globally for callers that already checked the window. If other
+    /// connections used up the budget in the meantime, only what is left below the cap is
+    /// reserved, so the cap is never overshot. Use [`try_prepare`](#method.try_prepare) to
+    /// refuse such packets instead.
+    fn prepare(&mut self, amount: u64) -> u64 {
+        let global_cap = self.global_cap;
+        let mut reserved = 0;
+        let _ = self.reservations.global_in_flight.fetch_update(
+            Ordering::SeqCst,
+            Ordering::SeqCst,
+            |in_flight| {
+                reserved = amount.min(global_cap.saturating_sub(in_flight));
+                Some(in_flight + reserved)
+            },
+        );
+        self.reservations.reserved =
+            arithmetic::add(self.reservations.reserved, reserved, "global reservation");
+        let sequence = self.inner.prepare(amount);
+        if reserved < amount {
+            warn!(
+                "Packet of {} prepared beyond the global in-flight cap of {}, only {} reserved",
+                amount, global_cap, reserved
+            );
+            self.reservations
+                .shortfalls
+                .insert(sequence, amount - reserved);
+        }
+        sequence
+    }
+
+    fn fulfill(&mut self, sequence: u64, prepare_amount: u64) {
+        self.reservations.release(sequence, prepare_amount);
+        self.inner.fulfill(sequence, prepare_amount);
+    }
+
+    fn reject(&mut self, sequence: u64, prepare_amount: u64, reject: &Reject) {
+        self.reservations.release(sequence, prepare_amount);
+        self.inner.reject(sequence, prepare_amount, reject);
+    }
+
+    fn record_rtt_sample(&mut self, rtt: Duration) {
+        self.inner.record_rtt_sample(rtt);
+    }
+
+    fn smoothed_rtt(&self) -> Option<Duration> {
+        self.inner.smoothed_rtt()
+    }
+
+    fn pacing_interval(&self) -> Option<Duration> {
+        self.inner.pacing_interval()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CongestionController;
+    use interledger_packet::{ErrorCode, RejectBuilder};
+    use std::thread;
+
+    #[test]
+    fn empty_window_once_global_cap_is_reached() {
+        let counter = Arc::new(AtomicU64::new(0));
+        let mut first = GlobalCapController::new(
+            CongestionController::new(1000, 10, 2.0),
+            counter.clone(),
+            1500,
+        );
+        let mut second = GlobalCapController::new(
+            CongestionController::new(1000, 10, 2.0),
+            counter.clone(),
+            1500,
+        );
+
+        let first_packet = first.try_prepare(1000).unwrap();
+        assert_eq!(second.get_amount_left_in_window(), 500);
+        assert_eq!(second.try_prepare(600), None);
+        let second_packet = second.try_prepare(500).unwrap();
+        assert_eq!(first.get_amount_left_in_window(), 0);
+        assert_eq!(second.get_amount_left_in_window(), 0);
+
+        first.reject(
+            first_packet,
+            1000,
+            &RejectBuilder {
+                code: ErrorCode::T04_INSUFFICIENT_LIQUIDITY,
+                message: &[],
+                triggered_by: None,
+                data: &[],
+            }
+            .build(),
+        );
+        assert_eq!(counter.load(Ordering::SeqCst), 500);
+        second.fulfill(second_packet, 500);
+        assert_eq!(counter.load(Ordering::SeqCst), 0);
+        // The local windows still apply below the cap
+        assert_eq!(first.get_amount_left_in_window(), 500);
+        assert_eq!(second.get_amount_left_in_window(), 1500);
+    }
+
+    #[test]
+    fn concurrent_controllers_respect_one_global_cap() {
+        const GLOBAL_CAP: u64 = 1000;
+        const PACKET_AMOUNT: u64 = 30;
+
+        let counter = Arc::new(AtomicU64::new(0));
+        let workers: Vec<_> = (0..8)
+            .map(|_| {
+                let mut controller = GlobalCapController::new(
+                    CongestionController::new(GLOBAL_CAP, 1, 2.0),
+                    counter.clone(),
+                    GLOBAL_CAP,
+                );
+                thread::spawn(move || {
+                    let mut in_flight = Vec::new();
+                    let mut prepared = 0;
+                    while prepared < 2000 {
+                        if let Some(sequence) = controller.try_prepare(PACKET_AMOUNT) {
+                            prepared += 1;
+                            in_flight.push(sequence);
+                            assert!(controller.global_in_flight() <= GLOBAL_CAP);
+                        }
+                        // Keep a few packets in flight to contend for the budget
+                        if in_flight.len() > 3 || !in_flight.is_empty() && prepared % 7 == 0 {
+                            controller.fulfill(in_flight.pop().unwrap(), PACKET_AMOUNT);
+                        }
+                    }
+                    for sequence in in_flight {
+                        controller.fulfill(sequence, PACKET_AMOUNT);
+                    }
+                    controller.into_inner()
+                })
+            })
+            .collect();
+
+        for worker in workers {
+            let controller = worker.join().unwrap();
+            assert_eq!(controller.amount_in_flight, 0);
+        }
+        assert_eq!(counter.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn prepare_through_trait_object_never_overshoots_cap() {
+        let counter = Arc::new(AtomicU64::new(0));
+        let mut controllers: Vec<Box<dyn CongestionControl>> = (0..2)
+            .map(|_| {
+                Box::new(GlobalCapController::new(
+                    CongestionController::new(1000, 10, 2.0),
+                    counter.clone(),
+                    1500,
+                )) as Box<dyn CongestionControl>
+            })
+            .collect();
+
+        // Both checked their window before either prepared
+        let amounts: Vec<u64> = controllers
+            .iter()
+            .map(|controller| controller.get_amount_left_in_window())
+            .collect();
+        let sequences: Vec<u64> = controllers
+            .iter_mut()
+            .zip(&amounts)
+            .map(|(controller, amount)| controller.prepare(*amount))
+            .collect();
+        assert_eq!(counter.load(Ordering::SeqCst), 1500);
+
+        controllers[1].fulfill(sequences[1], 1000);
+        assert_eq!(counter.load(Ordering::SeqCst), 1000);
+        controllers[0].fulfill(sequences[0], 1000);
+        assert_eq!(counter.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn shortfalls_follow_their_packets() {
+        let counter = Arc::new(AtomicU64::new(0));
+        let mut controller: Box<dyn CongestionControl> = Box::new(GlobalCapController::new(
+            CongestionController::new(1000, 10, 2.0),
+            counter.clone(),
+            1500,
+        ));
+
+        let full = controller.prepare(1000);
+        // Only 500 was left for the second packet of the same amount
+        let clamped = controller.prepare(1000);
+        assert_eq!(counter.load(Ordering::SeqCst), 1500);
+
+        controller.fulfill(full, 1000);
+        assert_eq!(counter.load(Ordering::SeqCst), 500);
+        controller.fulfill(clamped, 1000);
+        assert_eq!(counter.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn dropping_a_controller_gives_back_its_reservations() {
+        let counter = Arc::new(AtomicU64::new(0));
+        let mut first = GlobalCapController::new(
+            CongestionController::new(1000, 10, 2.0),
+            counter.clone(),
+            1500,
+        );
+        let mut second: Box<dyn CongestionControl> = Box::new(GlobalCapController::new(
+            CongestionController::new(1000, 10, 2.0),
+            counter.clone(),
+            1500,
+        ));
+
+        first.try_prepare(300).unwrap();
+        let fulfilled = first.try_prepare(200).unwrap();
+        first.fulfill(fulfilled, 200);
+        second.prepare(1000);
+        // Only 1000 was left for the second one
+        second.prepare(1000);
+        assert_eq!(counter.load(Ordering::SeqCst), 1500);
+
+        drop(first);
+        assert_eq!(counter.load(Ordering::SeqCst), 1200);
+        drop(second);
+        assert_eq!(counter.load(Ordering::SeqCst), 0);
+    }
+}
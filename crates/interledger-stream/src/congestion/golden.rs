@@ -0,0 +1,124 @@
+//! Regression guard for the AIMD math: a canonical sequence of outcomes is driven
+//! through the controller and the resulting window trajectory is compared against
+//! the checked-in `golden_trajectory.txt`.
+//!
+//! Changes to the control math that alter the trajectory make the test fail.
+//! If the change is intentional, regenerate the golden file with:
+//!
+//! ```text
+//! UPDATE_GOLDEN_TRAJECTORY=1 cargo test -p interledger-stream golden
+//! ```
+use super::*;
+use interledger_packet::RejectBuilder;
+
+const GOLDEN_TRAJECTORY: &str = include_str!("golden_trajectory.txt");
+const UPDATE_ENV_VAR: &str = "UPDATE_GOLDEN_TRAJECTORY";
+
+/// Outcome applied to a packet sized to fill the available window
+#[derive(Clone, Copy)]
+enum Outcome {
+    Fulfill,
+    Reject(ErrorCode),
+    /// F08 reject carrying `MaxPacketAmountDetails` (amount received, max amount)
+    AmountTooLarge(u64, u64),
+}
+
+fn canonical_sequence() -> Vec<Outcome> {
+    use Outcome::*;
+    let mut sequence = vec![Fulfill; 5];
+    sequence.push(Reject(ErrorCode::T04_INSUFFICIENT_LIQUIDITY));
+    sequence.extend(vec![Fulfill; 5]);
+    sequence.push(AmountTooLarge(1000, 100));
+    sequence.extend(vec![Fulfill; 3]);
+    sequence.push(Reject(ErrorCode::T04_INSUFFICIENT_LIQUIDITY));
+    sequence.push(Reject(ErrorCode::T04_INSUFFICIENT_LIQUIDITY));
+    sequence.push(Reject(ErrorCode::T05_RATE_LIMITED));
+    sequence.extend(vec![Fulfill; 4]);
+    sequence.push(Reject(ErrorCode::F08_AMOUNT_TOO_LARGE));
+    sequence.push(Reject(ErrorCode::F99_APPLICATION_ERROR));
+    sequence.extend(vec![Fulfill; 3]);
+    sequence
+}
+
+/// Runs the canonical sequence and returns `max_in_flight` after each outcome
+fn trajectory() -> Vec<u64> {
+    let mut controller = CongestionController::new(1000, 1000, 2.0);
+    canonical_sequence()
+        .into_iter()
+        .map(|outcome| {
+            let amount = min(
+                controller.get_amount_left_in_window(),
+                controller.get_max_packet_amount(),
+            );
+            let sequence = controller.prepare(amount);
+            match outcome {
+                Outcome::Fulfill => controller.fulfill(sequence, amount),
+                Outcome::Reject(code) => controller.reject(
+                    sequence,
+                    amount,
+                    &RejectBuilder {
+                        code,
+                        message: &[],
+                        triggered_by: None,
+                        data: &[],
+                    }
+                    .build(),
+                ),
+                Outcome::AmountTooLarge(received, max_amount) => controller.reject(
+                    sequence,
+                    amount,
+                    &RejectBuilder {
+                        code: ErrorCode::F08_AMOUNT_TOO_LARGE,
+                        message: &[],
+                        triggered_by: None,
+                        data: &MaxPacketAmountDetails::new(received, max_amount).to_bytes(),
+                    }
+                    .build(),
+                ),
+            }
+            controller.max_in_flight
+        })
+        .collect()
+}
+
+fn parse_golden(contents: &str) -> Vec<u64> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            line.parse()
+                .expect("Golden trajectory contains a non-integer line")
+        })
+        .collect()
+}
+
+#[test]
+fn matches_golden_trajectory() {
+    let actual = trajectory();
+
+    if std::env::var_os(UPDATE_ENV_VAR).is_some() {
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/src/congestion/golden_trajectory.txt"
+        );
+        let contents: String = actual.iter().map(|value| format!("{}\n", value)).collect();
+        std::fs::write(path, contents).expect("Unable to write golden trajectory");
+        return;
+    }
+
+    let golden = parse_golden(GOLDEN_TRAJECTORY);
+    assert_eq!(
+        golden.len(),
+        actual.len(),
+        "Golden trajectory length differs; rerun with {}=1 if the change is intentional",
+        UPDATE_ENV_VAR
+    );
+    for (step, (expected, actual)) in golden.iter().zip(actual.iter()).enumerate() {
+        assert_eq!(
+            expected, actual,
+            "Window diverged from the golden trajectory at step {}; rerun with {}=1 if the change is intentional",
+            step, UPDATE_ENV_VAR
+        );
+    }
+}
@@ -0,0 +1,74 @@
+use interledger_packet::Amount;
+use std::fmt::Write;
+
+/// Distribution of the window sizes a [congestion controller](./struct.CongestionController.html)
+/// has gone through, bucketed like an OpenMetrics histogram.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WindowHistogram {
+    /// Inclusive upper bound of each bucket, ascending. An implicit `+Inf` bucket follows.
+    upper_bounds: Vec<u64>,
+    /// Number of observations falling in each bucket (not cumulative), including `+Inf`
+    counts: Vec<u64>,
+    /// Sum of all observations
+    sum: Amount,
+}
+
+impl WindowHistogram {
+    /// Creates an empty histogram with the given bucket upper bounds, which are sorted
+    /// and deduplicated
+    pub fn new(mut upper_bounds: Vec<u64>) -> Self {
+        upper_bounds.sort_unstable();
+        upper_bounds.dedup();
+        let counts = vec![0; upper_bounds.len() + 1];
+        WindowHistogram {
+            upper_bounds,
+            counts,
+            sum: Amount::ZERO,
+        }
+    }
+
+    pub fn observe(&mut self, window: u64) {
+        let bucket = self.upper_bounds.partition_point(|&bound| bound < window);
+        self.counts[bucket] += 1;
+        self.sum = self.sum.saturating_add(window);
+    }
+
+    /// Cumulative counts per bucket, as `(upper bound, count)` pairs.
+    /// The last pair is the `+Inf` bucket, with no upper bound.
+    pub fn buckets(&self) -> Vec<(Option<u64>, u64)> {
+        let bounds = self.upper_bounds.iter().map(|&bound| Some(bound));
+        bounds
+            .chain(std::iter::once(None))
+            .zip(self.counts.iter().scan(0, |total, &count| {
+                *total += count;
+                Some(*total)
+            }))
+            .collect()
+    }
+
+    /// Total number of observations
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Sum of all observations
+    pub fn sum(&self) -> Amount {
+        self.sum
+    }
+
+    /// Renders the histogram as an OpenMetrics metric family with the given name,
+    /// terminated by `# EOF`
+    pub fn to_openmetrics(&self, name: &str) -> String {
+        let mut output = String::new();
+        // Writing to a String can't fail
+        let _ = writeln!(output, "# TYPE {} histogram", name);
+        for (bound, count) in self.buckets() {
+            let le = bound.map_or_else(|| "+Inf".to_string(), |bound| bound.to_string());
+            let _ = writeln!(output, "{}_bucket{{le=\"{}\"}} {}", name, le, count);
+        }
+        let _ = writeln!(output, "{}_sum {}", name, self.sum);
+        let _ = writeln!(output, "{}_count {}", name, self.count());
+        output.push_str("# EOF\n");
+        output
+    }
+}
@@ -0,0 +1,94 @@
+use super::tracking::BoundedQueue;
+
+/// Thresholds debouncing the controller's transitions between slow start and
+/// congestion avoidance, so an isolated reject or a single good round trip
+/// doesn't flip the state back and forth.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hysteresis {
+    /// Number of congestion signals required to leave slow start
+    pub congestion_signals: u64,
+    /// Number of consecutively resolved packets the congestion signals must fall within
+    pub signal_window: u64,
+    /// Number of consecutive round trips without a congestion signal required to re-enter slow start.
+    /// A round trip is counted once a full window's worth of money has been fulfilled.
+    pub clean_round_trips: u64,
+}
+
+impl Hysteresis {
+    /// Whether every threshold can be reached. A zero would leave slow start on the
+    /// first signal, or never, defeating the point of debouncing.
+    pub(crate) fn is_valid(&self) -> bool {
+        self.congestion_signals > 0 && self.signal_window > 0 && self.clean_round_trips > 0
+    }
+}
+
+/// Progress towards the hysteresis thresholds
+#[derive(Debug, Clone)]
+pub(crate) struct HysteresisTracker {
+    config: Hysteresis,
+    /// Number of packets resolved so far
+    resolved_packets: u64,
+    /// Positions (in resolved packets) of the most recent congestion signals
+    signals: BoundedQueue<u64>,
+    /// Amount fulfilled since the current round trip began
+    round_fulfilled: u64,
+    /// Consecutive round trips completed without a congestion signal
+    clean_rounds: u64,
+}
+
+impl HysteresisTracker {
+    pub fn new(config: Hysteresis) -> Self {
+        HysteresisTracker {
+            config,
+            resolved_packets: 0,
+            signals: BoundedQueue::new(config.congestion_signals as usize),
+            round_fulfilled: 0,
+            clean_rounds: 0,
+        }
+    }
+
+    pub fn config(&self) -> Hysteresis {
+        self.config
+    }
+
+    /// Records a congestion signal, returning true if enough signals arrived within
+    /// the signal window to leave slow start
+    pub fn on_congestion_signal(&mut self) -> bool {
+        self.resolved_packets += 1;
+        self.round_fulfilled = 0;
+        self.clean_rounds = 0;
+        self.signals.push(self.resolved_packets);
+
+        if (self.signals.len() as u64) < self.config.congestion_signals {
+            return false;
+        }
+        match self.signals.iter().next() {
+            Some(oldest) => self.resolved_packets - oldest < self.config.signal_window,
+            None => true,
+        }
+    }
+
+    /// Records a resolved packet that didn't signal congestion
+    pub fn on_other_reject(&mut self) {
+        self.resolved_packets += 1;
+    }
+
+    /// Records a fulfilled packet given the current window, returning true once enough
+    /// clean round trips have completed to re-enter slow start
+    pub fn on_fulfill(&mut self, amount: u64, max_in_flight: u64) -> bool {
+        self.resolved_packets += 1;
+        self.round_fulfilled = self.round_fulfilled.saturating_add(amount);
+        if self.round_fulfilled >= max_in_flight {
+            self.round_fulfilled = 0;
+            self.clean_rounds = self.clean_rounds.saturating_add(1);
+        }
+        self.clean_rounds >= self.config.clean_round_trips
+    }
+
+    /// Forgets progress towards either transition, after the state changed
+    pub fn reset(&mut self) {
+        self.signals = BoundedQueue::new(self.config.congestion_signals as usize);
+        self.round_fulfilled = 0;
+        self.clean_rounds = 0;
+    }
+}
@@ -0,0 +1,4157 @@
+use crate::error::{CongestionError, EventLogError};
+use interledger_packet::{Address, ErrorCode, MaxPacketAmountDetails, Reject, RejectBuilder};
+#[cfg(test)]
+use once_cell::sync::Lazy;
+use std::cmp::{max, min};
+use std::convert::TryFrom;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+#[cfg(feature = "congestion-notifications")]
+use tokio::sync::watch;
+use tracing::{debug, error, warn};
+
+/// Amount arithmetic, checked with the `defensive` feature
+mod arithmetic;
+/// Time source for the controller
+mod clock;
+/// Binary encoding of the event log
+mod codec;
+/// Controller parameters deserialized from config files
+#[cfg(feature = "serde")]
+mod config;
+/// Pluggable smoothing of time series
+mod estimator;
+/// Byte-driven entry point for `cargo fuzz`
+#[cfg(any(fuzzing, test))]
+mod fuzz;
+/// Decorator capping the value in flight across every connection of the node
+mod global_cap;
+/// Golden trajectory regression test for the AIMD math
+#[cfg(test)]
+mod golden;
+/// Distribution of window sizes
+mod histogram;
+/// Debounced transitions between slow start and congestion avoidance
+mod hysteresis;
+/// Max packet amounts shared between connections, keyed by address prefix
+mod path_cache;
+/// Final figures of closed connections, averaged per peer
+mod peer_stats;
+/// Replay of recorded round trip times and outcomes
+mod replay;
+/// Smoothed round trip time estimate
+mod rtt;
+/// Controller shared between tasks, with atomic in-flight accounting
+mod shared;
+/// Decorator sending every operation to an async channel
+#[cfg(feature = "congestion-notifications")]
+mod tee;
+/// Bounded bookkeeping shared by the controller's tracking structures
+mod tracking;
+
+pub use clock::{Clock, MockClock, SystemClock};
+#[cfg(feature = "serde")]
+pub use config::{CongestionConfig, GrowthStrategy};
+pub use estimator::{Estimator, Ewma, WindowedAverage};
+#[cfg(fuzzing)]
+pub use fuzz::fuzz_apply;
+pub use global_cap::GlobalCapController;
+pub use histogram::WindowHistogram;
+pub use hysteresis::Hysteresis;
+use hysteresis::HysteresisTracker;
+pub use path_cache::PathCache;
+pub use peer_stats::{PeerAverages, PeerStatsRegistry};
+pub use replay::{replay_trace, TraceEntry, TraceOutcome, TraceResult};
+use rtt::RttEstimator;
+pub use shared::{CongestionSnapshot, SharedCongestionController, WindowReservation};
+#[cfg(feature = "congestion-notifications")]
+pub use tee::TeeController;
+use tracking::BoundedQueue;
+pub use tracking::TrackingLimits;
+
+/// Operations the STREAM sender needs from a congestion controller.
+///
+/// Implemented by the [AIMD controller](./struct.CongestionController.html) and by
+/// decorators layering additional limits on top of another controller.
+pub trait CongestionControl {
+    /// Maximum amount allowed in a single packet
+    fn get_max_packet_amount(&self) -> u64;
+
+    /// Amount that can currently be added in flight
+    fn get_amount_left_in_window(&self) -> u64;
+
+    /// Records a packet of the given amount as in flight, returning the sequence number
+    /// identifying it when it's resolved
+    fn prepare(&mut self, amount: u64) -> u64;
+
+    /// Records the packet with the given sequence number as fulfilled
+    fn fulfill(&mut self, sequence: u64, prepare_amount: u64);
+
+    /// Records the packet with the given sequence number as rejected
+    fn reject(&mut self, sequence: u64, prepare_amount: u64, reject: &Reject);
+
+    /// Feeds a round trip time measured outside of the packets' own resolution.
+    /// Controllers that don't estimate round trip times ignore it.
+    fn record_rtt_sample(&mut self, _rtt: Duration) {}
+
+    /// Smoothed round trip time of the packets, if the controller estimates it and has
+    /// a sample yet
+    fn smoothed_rtt(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Minimum time the sender should leave between two packets, if it should pace them
+    /// rather than send everything the window allows at once
+    fn pacing_interval(&self) -> Option<Duration> {
+        None
+    }
+}
+
+impl CongestionControl for CongestionController {
+    fn get_max_packet_amount(&self) -> u64 {
+        CongestionController::get_max_packet_amount(self)
+    }
+
+    fn get_amount_left_in_window(&self) -> u64 {
+        CongestionController::get_amount_left_in_window(self)
+    }
+
+    fn prepare(&mut self, amount: u64) -> u64 {
+        CongestionController::prepare(self, amount)
+    }
+
+    fn fulfill(&mut self, sequence: u64, prepare_amount: u64) {
+        CongestionController::fulfill(self, sequence, prepare_amount)
+    }
+
+    fn reject(&mut self, sequence: u64, prepare_amount: u64, reject: &Reject) {
+        CongestionController::reject(self, sequence, prepare_amount, reject)
+    }
+
+    fn record_rtt_sample(&mut self, rtt: Duration) {
+        CongestionController::record_rtt_sample(self, rtt)
+    }
+
+    fn smoothed_rtt(&self) -> Option<Duration> {
+        CongestionController::smoothed_rtt(self)
+    }
+
+    fn pacing_interval(&self) -> Option<Duration> {
+        CongestionController::pacing_interval(self)
+    }
+}
+
+/// A basic congestion controller that implements an
+/// Additive Increase, Multiplicative Decrease (AIMD) algorithm.
+///
+/// Future implementations of this will use more advanced congestion
+/// control algorithms.
+#[derive(Clone)]
+pub struct CongestionController {
+    state: CongestionState,
+    /// Amount which is added to `max_in_flight` per fulfill
+    increase_amount: u64,
+    /// Divide `max_in_flight` by this factor per reject with code for insufficient liquidity
+    /// or if there is no `max_packet_amount` specified
+    decrease_factor: f64,
+    /// The maximum amount we are allowed to add in a packet. This gets automatically set if
+    /// we receive a reject packet with a `F08_AMOUNT_TOO_LARGE` error
+    max_packet_amount: Option<u64>,
+    /// The current amount in flight
+    amount_in_flight: u64,
+    /// Amount returned by `credit` that resolving packets may still find missing from the
+    /// amount in flight
+    credited_amount: u64,
+    /// The maximum allowed amount to be in flight
+    max_in_flight: u64,
+    /// The window the controller started with, which it falls back to after an idle period
+    start_amount: u64,
+    /// Idle period after which the window falls back to `start_amount`, if enabled
+    idle_threshold: Option<Duration>,
+    /// Maximum amount the receiver's flow control allows in flight, if advertised
+    receiver_max_in_flight: Option<u64>,
+    /// Fraction of the window the sender may fill, leaving the rest as latency headroom
+    target_utilization: f64,
+    /// Distribution of the window sizes gone through, if enabled
+    window_histogram: Option<WindowHistogram>,
+    /// Floor the window is never cut below
+    min_in_flight: u64,
+    /// Ceiling the window never grows above
+    max_in_flight_cap: u64,
+    /// Bounds applied to the tracking structures below
+    limits: TrackingLimits,
+    /// Index the next prepared packet will get, starting at 1 like STREAM sequence numbers
+    next_sequence: u64,
+    /// Sizes of the recently prepared packets, other than 0-amount ones
+    packet_sizes: WindowedAverage,
+    /// Packets currently in flight, in order of their sequence numbers
+    in_flight_packets: BoundedQueue<InFlightPacket>,
+    /// Sequence numbers of packets reclaimed by `reap`, or ignored while draining, that may
+    /// still be resolved late
+    reaped_packets: BoundedQueue<u64>,
+    /// Age after which `reap` reclaims an unresolved packet, if configured
+    max_packet_age: Option<Duration>,
+    /// Time source for packet timestamps
+    clock: Arc<dyn Clock>,
+    /// Round trip times measured on resolved packets or supplied by the transport
+    rtt: RttEstimator,
+    /// When the window last came within one increase of the floor, if it's still there
+    at_floor_since: Option<Instant>,
+    /// Time spent near the floor in previous stints
+    time_at_floor: Duration,
+    /// Lowest round trip time seen so far
+    baseline_rtt: Option<Duration>,
+    /// Delivery rate when the lowest round trip time was seen, or shortly after
+    baseline_rate: Option<f64>,
+    /// Window at which slow start ends without waiting for a congestion signal, if set
+    ssthresh: Option<u64>,
+    /// Is slow start growth spread over a round trip rather than authorized at once?
+    paced_slow_start: bool,
+    /// The ramp the authorized window is currently following, if pacing
+    pacing_ramp: Option<PacingRamp>,
+    /// How long the sender should hold off after a peer rate limited us, until the next fulfill
+    pacing_delay: Option<Duration>,
+    /// Is the sender currently sending less than the window allows, for lack of demand?
+    app_limited: bool,
+    /// Amount fulfilled since the last delivery rate sample
+    pending_delivery: u64,
+    /// Delivery rates measured between fulfills, excluding app-limited periods
+    delivery_rate: Box<dyn Estimator>,
+    /// Has a delivery rate been measured yet?
+    delivery_rate_sampled: bool,
+    /// When the current delivery rate sample began
+    last_delivery: Option<Instant>,
+    /// Log of the most recent congestion events, oldest first
+    events: BoundedQueue<CongestionEvent>,
+    /// Number of fulfills and rejects in the event log
+    resolved_events: u64,
+    /// Number of rejects in the event log that signaled congestion
+    congested_events: u64,
+    /// Distinct max packet amounts derived from F08 rejects, oldest first
+    f08_limits: BoundedQueue<u64>,
+    /// Debounces state transitions, if configured
+    hysteresis: Option<HysteresisTracker>,
+    /// Smallest fraction of the window a T04 or T05 reject is expected to cut before the
+    /// decrease factor is considered ineffective
+    min_effective_decrease: f64,
+    /// Has the ineffective decrease factor warning already been logged?
+    warned_ineffective_decrease: bool,
+    /// Cache sharing learned max packet amounts with other connections, and the destination
+    /// this connection sends to
+    path_cache: Option<(PathCache, Address)>,
+    /// Outcomes of the last 64 resolved packets, newest in the lowest bit (1 = fulfill)
+    recent_outcomes: u64,
+    /// Number of packets resolved so far, saturating at 64
+    recent_outcome_count: u32,
+    /// Amount fulfilled since the current round trip began
+    round_fulfilled: u64,
+    /// Number of round trips completed, counted once a window's worth has been fulfilled
+    completed_round_trips: u64,
+    /// Window at the end of each of the most recent round trips, oldest first
+    round_trip_windows: BoundedQueue<u64>,
+    /// Window at the first congestion signal, which slow start usually overshot
+    first_signal_window: Option<u64>,
+    /// Window at the second congestion signal, once congestion avoidance recovered
+    stabilized_window: Option<u64>,
+    /// How strongly the volatility hint dampens growth and deepens cuts, if enabled
+    volatility_sensitivity: Option<f64>,
+    /// Latest volatility hint for the asset being sent
+    volatility: f64,
+    /// The congestion level as of the last fulfill or reject
+    congestion_level: CongestionLevel,
+    /// Publishes congestion level transitions to subscribed applications
+    #[cfg(feature = "congestion-notifications")]
+    level_sender: LevelSender,
+}
+
+/// Sender for congestion level transitions, created on the first subscription.
+/// A cloned controller starts without subscribers, so simulations run on a clone
+/// never notify the original's subscribers.
+#[cfg(feature = "congestion-notifications")]
+#[derive(Default)]
+struct LevelSender(Option<watch::Sender<CongestionLevel>>);
+
+#[cfg(feature = "congestion-notifications")]
+impl Clone for LevelSender {
+    fn clone(&self) -> Self {
+        LevelSender(None)
+    }
+}
+
+/// An operation applied to the congestion controller, as recorded in its event log
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CongestionEvent {
+    /// A packet of the given amount was prepared
+    Prepare { amount: u64 },
+    /// A packet of the given amount was fulfilled
+    Fulfill { amount: u64 },
+    /// A packet of the given amount was rejected with the given code
+    Reject { amount: u64, code: ErrorCode },
+}
+
+impl CongestionEvent {
+    /// How the event counts towards the congestion index: whether it resolved a packet,
+    /// and whether it signaled congestion
+    fn counts(&self) -> (u64, u64) {
+        match self {
+            CongestionEvent::Prepare { .. } => (0, 0),
+            CongestionEvent::Fulfill { .. } => (1, 0),
+            CongestionEvent::Reject { code, .. } if is_congestion_signal(*code) => (1, 1),
+            CongestionEvent::Reject { .. } => (1, 0),
+        }
+    }
+}
+
+/// Point-in-time figures of a controller, for logging and debugging slow payments
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CongestionStats {
+    /// The congestion window
+    pub max_in_flight: u64,
+    pub amount_in_flight: u64,
+    pub state: CongestionState,
+    /// Max packet amount learned from F08 rejects, if any
+    pub max_packet_amount: Option<u64>,
+}
+
+/// Linear ramp of the authorized window towards the slow start target over one round trip
+#[derive(Debug, Clone, Copy)]
+struct PacingRamp {
+    from: u64,
+    to: u64,
+    start: Instant,
+    duration: Duration,
+}
+
+impl PacingRamp {
+    fn window_at(&self, now: Instant) -> u64 {
+        let elapsed = now.saturating_duration_since(self.start);
+        if elapsed >= self.duration {
+            return self.to;
+        }
+        let progress = elapsed.as_secs_f64() / self.duration.as_secs_f64();
+        self.from + ((self.to - self.from) as f64 * progress) as u64
+    }
+}
+
+/// A prepared packet that hasn't been fulfilled or rejected yet
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct InFlightPacket {
+    sequence: u64,
+    amount: u64,
+    prepared_at: Instant,
+}
+
+/// Coarse indication of how congested the path is, derived from the
+/// [congestion index](./struct.CongestionController.html#method.congestion_index)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CongestionLevel {
+    /// Fewer than 10% of recently resolved packets signaled congestion
+    Low,
+    /// Between 10% and 30% of recently resolved packets signaled congestion
+    Medium,
+    /// At least 30% of recently resolved packets signaled congestion
+    High,
+}
+
+impl CongestionLevel {
+    fn from_index(index: f64) -> Self {
+        if index < 0.1 {
+            CongestionLevel::Low
+        } else if index < 0.3 {
+            CongestionLevel::Medium
+        } else {
+            CongestionLevel::High
+        }
+    }
+}
+
+/// The phase of the congestion control state machine the controller is in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CongestionState {
+    /// Doubling the window on every fulfill until the first congestion signal
+    SlowStart,
+    /// Growing the window additively and cutting it multiplicatively (AIMD)
+    AvoidCongestion,
+    /// The connection is closing: no new packets, outstanding ones are still accounted for
+    Draining,
+}
+
+/// The states a controller may move to from `state`.
+///
+/// Staying in the same state is always allowed and is not listed. `Draining` is
+/// terminal, since a closing connection never sends again.
+pub fn valid_transitions(state: CongestionState) -> &'static [CongestionState] {
+    use CongestionState::*;
+    match state {
+        SlowStart => &[AvoidCongestion, Draining],
+        AvoidCongestion => &[SlowStart, Draining],
+        Draining => &[],
+    }
+}
+
+impl CongestionController {
+    /// Constructs a new congestion controller
+    pub fn new(start_amount: u64, increase_amount: u64, decrease_factor: f64) -> Self {
+        let limits = TrackingLimits::default();
+        CongestionController {
+            state: CongestionState::SlowStart,
+            increase_amount,
+            decrease_factor,
+            max_packet_amount: None,
+            amount_in_flight: 0,
+            credited_amount: 0,
+            max_in_flight: start_amount,
+            start_amount,
+            idle_threshold: None,
+            min_in_flight: 0,
+            max_in_flight_cap: u64::MAX,
+            receiver_max_in_flight: None,
+            target_utilization: 1.0,
+            window_histogram: None,
+            limits,
+            next_sequence: 1,
+            packet_sizes: WindowedAverage::new(PACKET_SIZE_SAMPLES),
+            in_flight_packets: BoundedQueue::new(limits.max_tracked_packets),
+            reaped_packets: BoundedQueue::new(limits.max_tracked_packets),
+            max_packet_age: None,
+            clock: Arc::new(SystemClock),
+            rtt: RttEstimator::new(limits.max_rtt_samples),
+            baseline_rtt: None,
+            at_floor_since: None,
+            time_at_floor: Duration::from_secs(0),
+            baseline_rate: None,
+            ssthresh: None,
+            paced_slow_start: false,
+            pacing_ramp: None,
+            pacing_delay: None,
+            app_limited: false,
+            pending_delivery: 0,
+            delivery_rate: Box::new(WindowedAverage::new(DEFAULT_RATE_SAMPLES)),
+            delivery_rate_sampled: false,
+            last_delivery: None,
+            events: BoundedQueue::new(limits.max_events),
+            resolved_events: 0,
+            congested_events: 0,
+            f08_limits: BoundedQueue::new(limits.max_f08_limits),
+            hysteresis: None,
+            path_cache: None,
+            recent_outcomes: 0,
+            recent_outcome_count: 0,
+            round_fulfilled: 0,
+            completed_round_trips: 0,
+            round_trip_windows: BoundedQueue::new(CONFIDENCE_ROUND_TRIPS),
+            first_signal_window: None,
+            stabilized_window: None,
+            volatility_sensitivity: None,
+            volatility: 0.0,
+            min_effective_decrease: DEFAULT_MIN_EFFECTIVE_DECREASE,
+            warned_ineffective_decrease: false,
+            congestion_level: CongestionLevel::Low,
+            #[cfg(feature = "congestion-notifications")]
+            level_sender: LevelSender::default(),
+        }
+    }
+
+    /// Bounds the memory used for tracking packets and events, evicting the oldest entries
+    /// once a limit is reached
+    pub fn with_tracking_limits(mut self, limits: TrackingLimits) -> Self {
+        self.limits = limits;
+        self.in_flight_packets.set_limit(limits.max_tracked_packets);
+        self.reaped_packets.set_limit(limits.max_tracked_packets);
+        self.rtt.set_max_samples(limits.max_rtt_samples);
+        self.events.set_limit(limits.max_events);
+        self.resolved_events = 0;
+        self.congested_events = 0;
+        for event in self.events.iter() {
+            let (resolved, congested) = event.counts();
+            self.resolved_events += resolved;
+            self.congested_events += congested;
+        }
+        self.f08_limits.set_limit(limits.max_f08_limits);
+        self
+    }
+
+    /// Requires clusters of congestion signals to leave slow start, and several clean
+    /// round trips to re-enter it, rather than flipping state on the first reject.
+    ///
+    /// Returns an error if any of the thresholds is 0.
+    pub fn with_hysteresis(mut self, hysteresis: Hysteresis) -> Result<Self, CongestionError> {
+        if !hysteresis.is_valid() {
+            return Err(CongestionError::InvalidHysteresis(hysteresis));
+        }
+        self.hysteresis = Some(HysteresisTracker::new(hysteresis));
+        Ok(self)
+    }
+
+    /// The hysteresis thresholds debouncing state transitions, if configured
+    pub fn hysteresis(&self) -> Option<Hysteresis> {
+        self.hysteresis.as_ref().map(HysteresisTracker::config)
+    }
+
+    /// Starts from a max packet amount already known for the path, such as one found by
+    /// [probing its rate](../fn.probe_rate.html)
+    pub fn with_max_packet_amount(mut self, max_packet_amount: u64) -> Self {
+        self.max_packet_amount = Some(min(self.get_max_packet_amount(), max_packet_amount));
+        self
+    }
+
+    /// Shares max packet amounts learned from F08 rejects with other connections through the
+    /// cache, and starts from any limit already known for the destination's address prefix
+    pub fn with_path_cache(mut self, cache: PathCache, destination: Address) -> Self {
+        if let Some(limit) = cache.get(&destination) {
+            debug!(
+                "Using max packet amount of {} learned for a prefix of {}",
+                limit, destination
+            );
+            self.max_packet_amount = Some(min(self.get_max_packet_amount(), limit));
+        }
+        self.path_cache = Some((cache, destination));
+        self
+    }
+
+    /// Starts from the average final window and max packet amount of the recently closed
+    /// connections to the peer, if any were recorded. The window stays within the
+    /// configured bounds and growth still begins in slow start.
+    pub fn prewarm_from(mut self, registry: &PeerStatsRegistry, peer_key: &str) -> Self {
+        if let Some(averages) = registry.averages(peer_key) {
+            debug!(
+                "Pre-warming from {} closed connections to {}: window {}, max packet amount {:?}",
+                averages.connections, peer_key, averages.window, averages.max_packet_amount
+            );
+            self.max_in_flight = averages.window;
+            self.clamp_window();
+            if let Some(limit) = averages.max_packet_amount {
+                self.max_packet_amount = Some(min(self.get_max_packet_amount(), limit));
+            }
+        }
+        self
+    }
+
+    /// Records this connection's final window and learned max packet amount under the
+    /// peer, for new connections to [pre-warm from](#method.prewarm_from). Call it when the
+    /// connection closes.
+    pub fn record_final_stats(&self, registry: &PeerStatsRegistry, peer_key: &str) {
+        registry.record(peer_key, self.max_in_flight, self.max_packet_amount);
+    }
+
+    /// Sets the smallest fraction of the window (e.g. 0.01 for 1%) a T04 or T05 reject is
+    /// expected to cut. If the decrease factor cuts less than this, a warning is logged once,
+    /// since congestion control is then effectively disabled.
+    pub fn with_min_effective_decrease(mut self, fraction: f64) -> Self {
+        self.min_effective_decrease = fraction;
+        self
+    }
+
+    /// Keeps the window between `min_in_flight` and `max_in_flight_cap`, inclusive.
+    ///
+    /// The window is clamped to these bounds after every fulfill and reject, as well as
+    /// right away. Returns an error if the floor exceeds the cap, since the window couldn't
+    /// satisfy both.
+    pub fn with_window_bounds(
+        mut self,
+        min_in_flight: u64,
+        max_in_flight_cap: u64,
+    ) -> Result<Self, CongestionError> {
+        if min_in_flight > max_in_flight_cap {
+            return Err(CongestionError::MinExceedsCap {
+                min: min_in_flight,
+                cap: max_in_flight_cap,
+            });
+        }
+        self.min_in_flight = min_in_flight;
+        self.max_in_flight_cap = max_in_flight_cap;
+        self.clamp_window();
+        Ok(self)
+    }
+
+    /// Only lets the sender fill the given fraction of the window, e.g. 0.8 to keep 20% of
+    /// it free as headroom for latency rather than maximizing throughput. The window itself
+    /// still grows and shrinks as usual. Defaults to 1.0, filling the whole window.
+    ///
+    /// Returns an error unless the target is greater than 0 and at most 1.
+    pub fn with_target_utilization(
+        mut self,
+        target_utilization: f64,
+    ) -> Result<Self, CongestionError> {
+        if target_utilization.is_nan() || target_utilization <= 0.0 || target_utilization > 1.0 {
+            return Err(CongestionError::InvalidTargetUtilization(
+                target_utilization,
+            ));
+        }
+        self.target_utilization = target_utilization;
+        Ok(self)
+    }
+
+    pub fn target_utilization(&self) -> f64 {
+        self.target_utilization
+    }
+
+    /// Records the window size in a histogram with the given bucket upper bounds every time
+    /// the window changes, starting with the current window
+    pub fn with_window_histogram(mut self, upper_bounds: Vec<u64>) -> Self {
+        let mut histogram = WindowHistogram::new(upper_bounds);
+        histogram.observe(self.max_in_flight);
+        self.window_histogram = Some(histogram);
+        self
+    }
+
+    /// The distribution of window sizes, if enabled
+    pub fn window_histogram(&self) -> Option<&WindowHistogram> {
+        self.window_histogram.as_ref()
+    }
+
+    /// Renders the distribution of window sizes in the OpenMetrics text format,
+    /// as the `stream_congestion_window` histogram
+    pub fn window_histogram_openmetrics(&self) -> Option<String> {
+        self.window_histogram
+            .as_ref()
+            .map(|histogram| histogram.to_openmetrics("stream_congestion_window"))
+    }
+
+    /// Lets [`on_idle`](#method.on_idle) reset the window once the connection has been idle
+    /// for at least `threshold`. Without a threshold, idle periods are ignored.
+    pub fn with_idle_threshold(mut self, threshold: Duration) -> Self {
+        self.idle_threshold = Some(threshold);
+        self
+    }
+
+    /// Tells the controller no packets have been in flight for `idle`. If that's at least
+    /// the [idle threshold](#method.with_idle_threshold), the window shrinks back to the
+    /// amount the controller started with and growth restarts in slow start, since the
+    /// path's capacity may have changed in the meantime and a burst at the old window
+    /// could overwhelm it. A window already smaller than the starting amount is kept.
+    pub fn on_idle(&mut self, idle: Duration) {
+        let threshold = match self.idle_threshold {
+            Some(threshold) if idle >= threshold => threshold,
+            _ => return,
+        };
+        if self.state != CongestionState::SlowStart
+            && !valid_transitions(self.state).contains(&CongestionState::SlowStart)
+        {
+            return;
+        }
+        let previous_max_in_flight = self.max_in_flight;
+        self.max_in_flight = min(self.max_in_flight, self.start_amount);
+        self.clamp_window();
+        self.transition(CongestionState::SlowStart);
+        self.pacing_ramp = None;
+        if let Some(hysteresis) = &mut self.hysteresis {
+            hysteresis.reset();
+        }
+        debug!(
+            "Idle for {:?} (threshold {:?}), resetting max in flight from {} to {}",
+            idle, threshold, previous_max_in_flight, self.max_in_flight
+        );
+        self.observe_window(previous_max_in_flight);
+    }
+
+    /// Ends slow start once the window reaches `ssthresh`, switching to additive increase
+    /// rather than doubling until the first congestion signal, which can overshoot far past
+    /// the path's capacity.
+    pub fn with_ssthresh(mut self, ssthresh: u64) -> Self {
+        self.ssthresh = Some(ssthresh);
+        self
+    }
+
+    pub fn ssthresh(&self) -> Option<u64> {
+        self.ssthresh
+    }
+
+    /// Spreads each slow start increase over a round trip: rather than authorizing the
+    /// doubled window at the fulfill, the window available to send ramps up linearly to
+    /// it over one smoothed round trip time, which avoids a burst at the start of each
+    /// round trip. Growth isn't paced until a round trip time has been measured.
+    pub fn with_paced_slow_start(mut self) -> Self {
+        self.paced_slow_start = true;
+        self
+    }
+
+    /// Smooths round trip time samples with the given estimator instead of the
+    /// [RFC 6298](https://tools.ietf.org/html/rfc6298) moving average
+    pub fn with_rtt_estimator(mut self, estimator: Box<dyn Estimator>) -> Self {
+        self.rtt.set_estimator(estimator);
+        self
+    }
+
+    /// Smooths delivery rate samples with the given estimator instead of averaging
+    /// the last 64 samples
+    pub fn with_rate_estimator(mut self, estimator: Box<dyn Estimator>) -> Self {
+        self.delivery_rate = estimator;
+        self.delivery_rate_sampled = false;
+        self
+    }
+
+    /// Uses the given clock to timestamp prepared packets instead of the system clock
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Lets [`reap`](#method.reap) reclaim packets which are still unresolved after the given age
+    pub fn with_max_packet_age(mut self, max_age: Duration) -> Self {
+        self.max_packet_age = Some(max_age);
+        self
+    }
+
+    /// Scales the window adjustments by the asset's volatility, as reported with
+    /// [`set_volatility`](#method.set_volatility).
+    ///
+    /// With a volatility `v`, window growth is divided by `1 + sensitivity * v` and the
+    /// decrease factor is multiplied by it, which limits the value exposed to an exchange
+    /// rate swing while the asset is volatile. A sensitivity of 0 disables the scaling.
+    pub fn with_volatility_scaling(mut self, sensitivity: f64) -> Self {
+        self.volatility_sensitivity = Some(sensitivity.max(0.0));
+        self
+    }
+
+    /// Updates the volatility hint, e.g. the relative standard deviation of recent
+    /// exchange rates. Negative or non-finite values are treated as 0.
+    /// Has no effect unless volatility scaling is enabled.
+    pub fn set_volatility(&mut self, volatility: f64) {
+        self.volatility = if volatility.is_finite() {
+            volatility.max(0.0)
+        } else {
+            0.0
+        };
+    }
+
+    /// The phase of the state machine the controller is currently in
+    pub fn current_state(&self) -> CongestionState {
+        self.state
+    }
+
+    /// The controller's main figures, e.g. to log why the window is small
+    pub fn stats(&self) -> CongestionStats {
+        CongestionStats {
+            max_in_flight: self.max_in_flight,
+            amount_in_flight: self.amount_in_flight,
+            state: self.state,
+            max_packet_amount: self.max_packet_amount,
+        }
+    }
+
+    /// Human-readable name of the rule the window currently grows by on a fulfill
+    pub fn active_growth_strategy(&self) -> &'static str {
+        let volatility_scaled = self.volatility_sensitivity.is_some();
+        match (self.state, self.paced_slow_start, volatility_scaled) {
+            (CongestionState::SlowStart, false, false) => "slow start",
+            (CongestionState::SlowStart, false, true) => "volatility-scaled slow start",
+            (CongestionState::SlowStart, true, false) => "paced slow start",
+            (CongestionState::SlowStart, true, true) => "volatility-scaled paced slow start",
+            (CongestionState::AvoidCongestion, _, false) => "additive increase",
+            (CongestionState::AvoidCongestion, _, true) => "volatility-scaled additive increase",
+            (CongestionState::Draining, _, _) => "none (draining)",
+        }
+    }
+
+    /// Human-readable name of the rule the window is currently cut by on a congestion signal
+    pub fn active_decrease_strategy(&self) -> &'static str {
+        let volatility_scaled = self.volatility_sensitivity.is_some();
+        match (volatility_scaled, self.min_in_flight > 0) {
+            (false, false) => "multiplicative decrease",
+            (false, true) => "multiplicative decrease with floor",
+            (true, false) => "volatility-scaled multiplicative decrease",
+            (true, true) => "volatility-scaled multiplicative decrease with floor",
+        }
+    }
+
+    /// The amount added to the window per fulfill during congestion avoidance
+    pub fn increase_amount(&self) -> u64 {
+        self.increase_amount
+    }
+
+    /// Changes the amount added to the window per fulfill, starting with the next fulfill
+    pub fn set_increase_amount(&mut self, increase_amount: u64) {
+        debug!(
+            "Changing increase amount from {} to {}",
+            self.increase_amount, increase_amount
+        );
+        self.increase_amount = increase_amount;
+    }
+
+    /// The factor the window is divided by on a T04 or T05 reject
+    pub fn decrease_factor(&self) -> f64 {
+        self.decrease_factor
+    }
+
+    /// Changes the factor the window is divided by, starting with the next reject.
+    /// Factors of 1.0 or less (or NaN) would never shrink the window and are refused,
+    /// leaving the current factor in place.
+    pub fn set_decrease_factor(&mut self, decrease_factor: f64) -> Result<(), CongestionError> {
+        if decrease_factor.is_nan() || decrease_factor <= 1.0 {
+            return Err(CongestionError::InvalidDecreaseFactor(decrease_factor));
+        }
+        debug!(
+            "Changing decrease factor from {} to {}",
+            self.decrease_factor, decrease_factor
+        );
+        self.decrease_factor = decrease_factor;
+        Ok(())
+    }
+
+    /// The amount added to the window per fulfill during congestion avoidance,
+    /// after volatility scaling
+    pub fn effective_increase_amount(&self) -> u64 {
+        self.dampen_growth(self.increase_amount)
+    }
+
+    /// The factor the window is divided by on a T04 or T05 reject, after volatility scaling
+    pub fn effective_decrease_factor(&self) -> f64 {
+        self.decrease_factor * self.volatility_scale()
+    }
+
+    /// The limits bounding the controller's tracking structures
+    pub fn tracking_limits(&self) -> TrackingLimits {
+        self.limits
+    }
+
+    /// The most recent congestion events, oldest first
+    pub fn events(&self) -> impl Iterator<Item = &CongestionEvent> {
+        self.events.iter()
+    }
+
+    /// Stops authorizing new packets, e.g. once the connection starts closing.
+    ///
+    /// While draining, the amount left in the window is 0 and prepares are ignored, but
+    /// fulfills and rejects of the packets already in flight are still accounted for
+    /// until the controller [is drained](#method.is_drained). The window no longer grows.
+    pub fn begin_drain(&mut self) {
+        debug!(
+            "Draining congestion controller with {} in flight",
+            self.amount_in_flight
+        );
+        self.transition(CongestionState::Draining);
+        self.pacing_ramp = None;
+    }
+
+    /// True once draining has begun and every outstanding packet has been resolved
+    pub fn is_drained(&self) -> bool {
+        self.state == CongestionState::Draining && self.amount_in_flight == 0
+    }
+
+    /// Total time the window has spent at the [floor](#method.with_window_bounds), or within
+    /// one additive increase of it, including the current stint if it's there now.
+    ///
+    /// A connection that keeps its window pinned to the floor is on a badly congested path.
+    pub fn time_at_floor(&self) -> Duration {
+        match self.at_floor_since {
+            Some(since) => arithmetic::add_duration(
+                self.time_at_floor,
+                self.clock.now().saturating_duration_since(since),
+                "time at floor",
+            ),
+            None => self.time_at_floor,
+        }
+    }
+
+    /// Sequence number the next packet will be given when it's prepared. Every prepare takes
+    /// the next one, including prepares for 0 and ones ignored while draining, so within a
+    /// connection the sequence number identifies a packet when it's resolved and in logs.
+    pub fn next_sequence(&self) -> u64 {
+        self.next_sequence
+    }
+
+    /// Outcomes of the most recently resolved packets as a bitstring, one bit per packet:
+    /// 1 for a fulfill and 0 for a reject. The newest outcome is in the lowest bit, and up
+    /// to 64 outcomes are kept, see [`recent_outcome_count`](#method.recent_outcome_count).
+    ///
+    /// Patterns such as alternating or bursty rejects hint at the kind of impairment on the path.
+    pub fn recent_outcomes(&self) -> u64 {
+        self.recent_outcomes
+    }
+
+    /// Number of valid bits in [`recent_outcomes`](#method.recent_outcomes), at most 64
+    pub fn recent_outcome_count(&self) -> u32 {
+        self.recent_outcome_count
+    }
+
+    /// Serializes the event log to a compact binary blob, e.g. to attach it to a bug report.
+    /// The blob can be parsed back with [`decode_events`](#method.decode_events).
+    pub fn encode_events(&self) -> Vec<u8> {
+        codec::encode(self.events.iter())
+    }
+
+    /// Parses an event log serialized by [`encode_events`](#method.encode_events)
+    pub fn decode_events(bytes: &[u8]) -> Result<Vec<CongestionEvent>, EventLogError> {
+        codec::decode(bytes)
+    }
+
+    /// Fraction of the packets resolved in the event log that were rejected with
+    /// an error signaling congestion, between 0.0 and 1.0
+    pub fn congestion_index(&self) -> f64 {
+        if self.resolved_events == 0 {
+            0.0
+        } else {
+            self.congested_events as f64 / self.resolved_events as f64
+        }
+    }
+
+    /// The congestion level as of the last fulfill or reject
+    pub fn congestion_level(&self) -> CongestionLevel {
+        self.congestion_level
+    }
+
+    /// Subscribes to congestion level transitions. The receiver is only notified
+    /// when the level changes, not on every fulfill or reject.
+    #[cfg(feature = "congestion-notifications")]
+    pub fn subscribe_congestion_level(&mut self) -> watch::Receiver<CongestionLevel> {
+        match &self.level_sender.0 {
+            Some(sender) => sender.subscribe(),
+            None => {
+                let (sender, receiver) = watch::channel(self.congestion_level);
+                self.level_sender = LevelSender(Some(sender));
+                receiver
+            }
+        }
+    }
+
+    /// Number of distinct max packet amounts reported by F08 rejects over the connection's life,
+    /// bounded by the tracking limits. Many distinct values may indicate multiple bottleneck
+    /// hops or a flapping path.
+    pub fn distinct_f08_limit_count(&self) -> usize {
+        self.f08_limits.len()
+    }
+
+    /// Number of in-flight packets currently tracked individually.
+    /// Packets evicted from tracking are still accounted for in the amount in flight.
+    pub fn tracked_packet_count(&self) -> usize {
+        self.in_flight_packets.len()
+    }
+
+    /// Maximium allowed packet amount allowed to send in a packet per F08s
+    pub fn get_max_packet_amount(&self) -> u64 {
+        self.max_packet_amount.unwrap_or(u64::max_value())
+    }
+
+    /// The maximum amount availble to be sent is the maximum amount in flight minus the current amount in flight
+    pub fn get_amount_left_in_window(&self) -> u64 {
+        if self.state == CongestionState::Draining {
+            return 0;
+        }
+        let local_left = self
+            .utilized(self.authorized_window())
+            .saturating_sub(self.amount_in_flight);
+        match self.receiver_max_in_flight {
+            Some(receiver_max) => min(
+                local_left,
+                receiver_max.saturating_sub(self.amount_in_flight),
+            ),
+            None => local_left,
+        }
+    }
+
+    /// The most the sender may have in flight at once: the part of the authorized window
+    /// the target utilization allows, further limited by the receiver's flow control.
+    /// Unlike the amount left in the window, this doesn't depend on what's in flight now.
+    pub fn max_sendable(&self) -> u64 {
+        if self.state == CongestionState::Draining {
+            return 0;
+        }
+        let local = self.utilized(self.authorized_window());
+        match self.receiver_max_in_flight {
+            Some(receiver_max) => min(local, receiver_max),
+            None => local,
+        }
+    }
+
+    /// Average amount of the recently prepared packets, if any were prepared.
+    /// Packets for 0 aren't counted.
+    pub fn average_packet_size(&self) -> Option<u64> {
+        let average = self.packet_sizes.value() as u64;
+        if average > 0 {
+            Some(average)
+        } else {
+            None
+        }
+    }
+
+    /// How many packets of the average size fit in flight at once, so the sender can
+    /// size its pool of concurrent requests. This is at least 1 as long as anything may
+    /// be sent, and 1 until the average packet size is known.
+    pub fn recommended_concurrency(&self) -> u64 {
+        let sendable = self.max_sendable();
+        if sendable == 0 {
+            return 0;
+        }
+        match self.average_packet_size() {
+            Some(average) => max(sendable / average, 1),
+            None => 1,
+        }
+    }
+
+    /// Updates the amount the receiver's flow control allows in flight, converted to source
+    /// units, e.g. from the receive max advertised in its STREAM `MaxMoney` frames.
+    /// `None` removes the limit.
+    ///
+    /// The amount left in the window is the lesser of what the congestion window and the
+    /// receiver allow. The congestion window itself isn't affected.
+    pub fn set_receiver_max_in_flight(&mut self, receiver_max_in_flight: Option<u64>) {
+        self.receiver_max_in_flight = receiver_max_in_flight;
+    }
+
+    pub fn receiver_max_in_flight(&self) -> Option<u64> {
+        self.receiver_max_in_flight
+    }
+
+    /// How long the sender should wait between packets because the peer is rate limiting
+    /// us, if it is. Set by a T05 reject to the smoothed round trip time (or 100ms before one
+    /// was measured), and cleared by the next fulfill.
+    pub fn pacing_delay(&self) -> Option<Duration> {
+        self.pacing_delay
+    }
+
+    /// Minimum gap the sender should leave between packets so a window's worth is spread
+    /// over a round trip instead of sent in one burst: the smoothed round trip time divided
+    /// by the [recommended concurrency](#method.recommended_concurrency). While the peer is
+    /// rate limiting us, the [pacing delay](#method.pacing_delay) applies if it's longer.
+    pub fn pacing_interval(&self) -> Option<Duration> {
+        let spread = match (self.rtt.smoothed(), self.recommended_concurrency()) {
+            (Some(_), 0) | (None, _) => None,
+            (Some(rtt), packets) => Some(rtt / u32::try_from(packets).unwrap_or(u32::MAX)),
+        };
+        match (spread, self.pacing_delay) {
+            (Some(spread), Some(delay)) => Some(max(spread, delay)),
+            (spread, delay) => spread.or(delay),
+        }
+    }
+
+    /// The part of the window currently authorized to be in flight. This is the whole
+    /// window, unless a paced slow start increase is still ramping up.
+    pub fn authorized_window(&self) -> u64 {
+        match &self.pacing_ramp {
+            Some(ramp) => min(ramp.window_at(self.clock.now()), self.max_in_flight),
+            None => self.max_in_flight,
+        }
+    }
+
+    /// The `max_in_flight` the controller would switch to if a reject with the given code
+    /// arrived now, leaving the controller untouched.
+    ///
+    /// The reject is applied to a clone using the same logic as [`reject`](#method.reject),
+    /// so the answer stays accurate as the algorithm evolves. The clone doesn't update
+    /// the path cache or notify congestion level subscribers.
+    pub fn simulate_reject(&self, code: ErrorCode) -> u64 {
+        let mut simulation = self.clone();
+        simulation.path_cache = None;
+        simulation.warned_ineffective_decrease = true;
+        let reject = RejectBuilder {
+            code,
+            message: &[],
+            triggered_by: None,
+            data: &[],
+        }
+        .build();
+        simulation.on_reject(0, &reject);
+        simulation.max_in_flight
+    }
+
+    /// Marks the current underutilization of the window as demand-driven: the application
+    /// has nothing more to send, so the window isn't what limits the sender.
+    ///
+    /// Fulfills don't grow the window while app-limited, since a window the sender isn't
+    /// filling says nothing about how much more the path could carry, and the time spent
+    /// waiting for the application is left out of the [delivery rate](#method.delivery_rate).
+    /// The mark is cleared by the next prepare that fills the window.
+    pub fn mark_app_limited(&mut self) {
+        self.app_limited = true;
+    }
+
+    pub fn is_app_limited(&self) -> bool {
+        self.app_limited
+    }
+
+    /// Estimated amount fulfilled per second, excluding app-limited periods.
+    /// Returns `None` until a delivery has been timed.
+    ///
+    /// Each interval between fulfills yields a rate sample. By default the estimate is the
+    /// average of the last 64 samples, see [`with_rate_estimator`](#method.with_rate_estimator).
+    pub fn delivery_rate(&self) -> Option<f64> {
+        if self.delivery_rate_sampled {
+            Some(self.delivery_rate.value())
+        } else {
+            None
+        }
+    }
+
+    /// Feeds a round trip time measured outside of STREAM, e.g. a BTP keepalive, into the
+    /// same estimate as the round trip times of resolved packets. This keeps the estimate
+    /// fresh while no packets are in flight.
+    pub fn record_rtt_sample(&mut self, rtt: Duration) {
+        self.observe_rtt(rtt);
+    }
+
+    /// Smoothed round trip time, once a sample has been taken
+    pub fn smoothed_rtt(&self) -> Option<Duration> {
+        self.rtt.smoothed()
+    }
+
+    /// Smoothed mean deviation of the round trip time samples
+    pub fn rtt_variance(&self) -> Duration {
+        self.rtt.variance()
+    }
+
+    /// Lowest round trip time among the retained samples
+    pub fn min_rtt(&self) -> Option<Duration> {
+        self.rtt.min()
+    }
+
+    /// True if the path looks bufferbloated: round trip times have gone up significantly
+    /// while the delivery rate stopped growing, meaning the extra money in flight is
+    /// queueing in deep buffers instead of getting through faster.
+    ///
+    /// Specifically, the smoothed RTT is at least 1.5 times the lowest RTT seen, while
+    /// the delivery rate is less than 10% above the rate measured at that lowest RTT.
+    pub fn bufferbloat_detected(&self) -> bool {
+        let (baseline_rtt, baseline_rate) = match (self.baseline_rtt, self.baseline_rate) {
+            (Some(rtt), Some(rate)) if rtt > Duration::from_secs(0) => (rtt, rate),
+            _ => return false,
+        };
+        let (smoothed_rtt, rate) = match (self.rtt.smoothed(), self.delivery_rate()) {
+            (Some(rtt), Some(rate)) => (rtt, rate),
+            _ => return false,
+        };
+        let rtt_inflation = smoothed_rtt.as_secs_f64() / baseline_rtt.as_secs_f64();
+        rtt_inflation >= BUFFERBLOAT_RTT_INFLATION && rate < baseline_rate * BUFFERBLOAT_RATE_GROWTH
+    }
+
+    /// How far slow start overshot the path's capacity: the window at the first congestion
+    /// signal divided by the window at the second one, by which time congestion avoidance
+    /// has recovered to what the path actually sustains.
+    ///
+    /// A ratio well above 1 suggests a smaller initial window would converge faster.
+    /// Returns `None` until two congestion signals have been received.
+    pub fn slow_start_overshoot_ratio(&self) -> Option<f64> {
+        let stabilized = self.stabilized_window?;
+        let overshoot = self.first_signal_window?;
+        Some(overshoot as f64 / stabilized as f64)
+    }
+
+    /// How well-calibrated the window is, between 0.0 ("still calibrating") and 1.0 ("steady").
+    ///
+    /// A round trip completes once a window's worth of money has been fulfilled. The
+    /// confidence is the product of two factors:
+    /// - maturity: completed round trips divided by 8, capped at 1
+    /// - stability: 1 minus the coefficient of variation (standard deviation over mean)
+    ///   of the window at the end of each of the last 8 round trips, floored at 0
+    pub fn window_confidence(&self) -> f64 {
+        let maturity = (self.completed_round_trips as f64 / CONFIDENCE_ROUND_TRIPS as f64).min(1.0);
+        let samples = self.round_trip_windows.len();
+        if samples == 0 {
+            return 0.0;
+        }
+        let mean = self
+            .round_trip_windows
+            .iter()
+            .map(|&w| w as f64)
+            .sum::<f64>()
+            / samples as f64;
+        if mean == 0.0 {
+            return 0.0;
+        }
+        let variance = self
+            .round_trip_windows
+            .iter()
+            .map(|&w| (w as f64 - mean).powi(2))
+            .sum::<f64>()
+            / samples as f64;
+        let stability = (1.0 - variance.sqrt() / mean).max(0.0);
+        maturity * stability
+    }
+
+    /// Ceiling on the delivery rate, in source units per second, given the current window
+    /// and the provided round trip time. Returns `None` if the round trip time is zero.
+    ///
+    /// This is a planning figure derived from the window, not a measured rate.
+    pub fn max_theoretical_throughput(&self, rtt: Duration) -> Option<f64> {
+        let rtt = rtt.as_secs_f64();
+        if rtt > 0.0 {
+            Some(self.max_in_flight as f64 / rtt)
+        } else {
+            None
+        }
+    }
+
+    /// Increments the amount in flight by the provided amount, returning the packet's
+    /// sequence number to resolve it with. While draining, the prepare is ignored and
+    /// resolving the packet later doesn't release anything.
+    pub fn prepare(&mut self, amount: u64) -> u64 {
+        let sequence = self.next_sequence;
+        self.next_sequence = self.next_sequence.saturating_add(1);
+        if self.state == CongestionState::Draining {
+            warn!(
+                "Ignoring prepare {} of {} while draining, the packet must not be sent",
+                sequence, amount
+            );
+            if amount > 0 {
+                self.reaped_packets.push(sequence);
+            }
+            return sequence;
+        }
+        if amount > 0 {
+            self.amount_in_flight = arithmetic::add(self.amount_in_flight, amount, "prepare");
+            self.in_flight_packets.push(InFlightPacket {
+                sequence,
+                amount,
+                prepared_at: self.clock.now(),
+            });
+            self.log_event(CongestionEvent::Prepare { amount });
+            self.packet_sizes.observe(amount as f64);
+            if self.app_limited && self.amount_in_flight >= self.utilized(self.max_in_flight) {
+                // Demand caught up with the window, so deliveries reflect the path again
+                self.app_limited = false;
+                self.last_delivery = Some(self.clock.now());
+            }
+            debug!(
+                "Prepare packet {} of {}, amount in flight is now: {}",
+                sequence, amount, self.amount_in_flight
+            );
+        }
+        sequence
+    }
+
+    /// Decrements the amount in flight by the amount of the packet with the given sequence number.
+    /// Increases the allowed max in flight amount cap
+    pub fn fulfill(&mut self, sequence: u64, prepare_amount: u64) {
+        self.release(sequence, prepare_amount);
+        self.on_fulfill(prepare_amount);
+    }
+
+    /// Decrements the amount in flight by the amount of the packet with the given sequence number
+    /// Decreases the allowed max in flight amount cap
+    pub fn reject(&mut self, sequence: u64, prepare_amount: u64, reject: &Reject) {
+        self.release(sequence, prepare_amount);
+        self.on_reject(prepare_amount, reject);
+    }
+
+    /// Records the fulfill and grows the window, independent of in-flight accounting
+    fn on_fulfill(&mut self, prepare_amount: u64) {
+        let previous_max_in_flight = self.max_in_flight;
+        self.sample_delivery(prepare_amount);
+        self.log_event(CongestionEvent::Fulfill {
+            amount: prepare_amount,
+        });
+        self.record_outcome(true);
+        // Packets are getting through again, so the peer stopped rate limiting us
+        self.pacing_delay = None;
+
+        // Before we know how much we should be sending at a time,
+        // double the window size on every successful packet.
+        // Once we start getting errors, switch to Additive Increase,
+        // Multiplicative Decrease (AIMD) congestion avosequenceance
+        if self.state == CongestionState::Draining {
+            debug!(
+                "Fulfilled packet of {} while draining, amount in flight is now: {}",
+                prepare_amount, self.amount_in_flight
+            );
+        } else if self.app_limited {
+            debug!(
+                "Fulfilled packet of {} while app-limited, holding max in flight at: {}",
+                prepare_amount, self.max_in_flight
+            );
+        } else if self.state == CongestionState::SlowStart {
+            // Double the max in flight (less if the asset is volatile)
+            // but don't exceed the u64 max value
+            self.max_in_flight = self
+                .max_in_flight
+                .saturating_add(self.dampen_growth(self.max_in_flight));
+            debug!(
+                "Fulfilled packet of {}, doubling max in flight to: {}",
+                prepare_amount, self.max_in_flight
+            );
+            if let Some(ssthresh) = self.ssthresh {
+                if self.max_in_flight >= ssthresh {
+                    self.transition(CongestionState::AvoidCongestion);
+                    debug!(
+                        "Max in flight reached the slow start threshold of {}, switching to additive increase",
+                        ssthresh
+                    );
+                }
+            }
+        } else if self.state == CongestionState::AvoidCongestion {
+            // Add to the max in flight but don't exeed the u64 max value
+            self.max_in_flight = self
+                .max_in_flight
+                .saturating_add(self.effective_increase_amount());
+            debug!(
+                "Fulfilled packet of {}, increasing max in flight to: {}",
+                prepare_amount, self.max_in_flight
+            );
+        } else {
+            debug!(
+                "Fulfilled packet of {} in {:?}, holding max in flight at: {}",
+                prepare_amount, self.state, self.max_in_flight
+            );
+        }
+
+        self.clamp_window();
+
+        self.round_fulfilled = self.round_fulfilled.saturating_add(prepare_amount);
+        if self.round_fulfilled >= previous_max_in_flight {
+            self.round_fulfilled = 0;
+            self.completed_round_trips =
+                arithmetic::add(self.completed_round_trips, 1, "completed round trips");
+            self.round_trip_windows.push(self.max_in_flight);
+        }
+
+        if let Some(hysteresis) = &mut self.hysteresis {
+            let clean = hysteresis.on_fulfill(prepare_amount, previous_max_in_flight);
+            if clean && self.state == CongestionState::AvoidCongestion {
+                hysteresis.reset();
+                self.transition(CongestionState::SlowStart);
+                debug!("Path is clean again, re-entering slow start");
+            }
+        }
+
+        self.update_pacing(previous_max_in_flight);
+        self.observe_window(previous_max_in_flight);
+        self.update_congestion_level();
+    }
+
+    /// Records the reject and shrinks the window or max packet amount, independent of
+    /// in-flight accounting
+    fn on_reject(&mut self, prepare_amount: u64, reject: &Reject) {
+        let previous_max_in_flight = self.max_in_flight;
+        self.log_event(CongestionEvent::Reject {
+            amount: prepare_amount,
+            code: reject.code(),
+        });
+        self.record_outcome(false);
+
+        match reject.code() {
+            ErrorCode::T04_INSUFFICIENT_LIQUIDITY => {
+                self.multiplicative_decrease(prepare_amount, reject.code());
+            }
+            ErrorCode::T05_RATE_LIMITED => {
+                self.multiplicative_decrease(prepare_amount, reject.code());
+                let delay = self.rtt.smoothed().unwrap_or(DEFAULT_RATE_LIMIT_DELAY);
+                self.pacing_delay = Some(delay);
+                debug!("Rate limited, hinting to wait {:?} between packets", delay);
+            }
+            ErrorCode::F08_AMOUNT_TOO_LARGE => {
+                if let Ok(details) = MaxPacketAmountDetails::from_bytes(reject.data()) {
+                    if let Some(new_max_packet_amount) = arithmetic::mul_div(
+                        prepare_amount,
+                        details.max_amount(),
+                        details.amount_received(),
+                        "F08 max packet amount",
+                    ) {
+                        if !self.f08_limits.contains(&new_max_packet_amount) {
+                            self.f08_limits.push(new_max_packet_amount);
+                        }
+                        if let Some(max_packet_amount) = self.max_packet_amount {
+                            self.max_packet_amount =
+                                Some(min(max_packet_amount, new_max_packet_amount));
+                        } else {
+                            self.max_packet_amount = Some(new_max_packet_amount);
+                        }
+                        if let Some((cache, destination)) = &self.path_cache {
+                            cache.insert(destination, new_max_packet_amount);
+                        }
+                        debug!(
+                            "Rejected packet with F08 error. Amount received: {}, max amount: {}, setting max packet amount to: {}",
+                            details.amount_received(),
+                            details.max_amount(),
+                            self.get_max_packet_amount()
+                        );
+                    }
+                } else {
+                    warn!("Got F08: Amount Too Large Error without max packet amount details attached");
+                    if let Some(max_packet_amount) = self.max_packet_amount {
+                        self.max_packet_amount =
+                            Some((max_packet_amount as f64 / self.decrease_factor) as u64);
+                    }
+                }
+            }
+            _ => {
+                // No special treatment for other errors
+            }
+        }
+
+        if !is_congestion_signal(reject.code()) {
+            if let Some(hysteresis) = &mut self.hysteresis {
+                hysteresis.on_other_reject();
+            }
+        }
+
+        self.clamp_window();
+
+        self.observe_window(previous_max_in_flight);
+        self.update_congestion_level();
+    }
+
+    /// Cuts the window in response to a congestion signal
+    fn multiplicative_decrease(&mut self, prepare_amount: u64, code: ErrorCode) {
+        self.on_congestion_signal();
+        self.pacing_ramp = None;
+        let previous_max_in_flight = self.max_in_flight;
+        if self.first_signal_window.is_none() {
+            self.first_signal_window = Some(previous_max_in_flight);
+        } else if self.stabilized_window.is_none() {
+            self.stabilized_window = Some(previous_max_in_flight);
+        }
+        self.max_in_flight = max(
+            (self.max_in_flight as f64 / self.effective_decrease_factor()).floor() as u64,
+            1,
+        );
+        self.check_decrease_effectiveness(previous_max_in_flight);
+        debug!("Rejected packet of {} with {} error. Amount in flight is now: {}, decreasing max in flight to: {}", prepare_amount, code, self.amount_in_flight, self.max_in_flight);
+    }
+
+    /// Warns once if a multiplicative decrease barely shrank the window
+    fn check_decrease_effectiveness(&mut self, previous_max_in_flight: u64) {
+        // A window of 1 can't shrink any further, regardless of the factor
+        if self.warned_ineffective_decrease || previous_max_in_flight <= 1 {
+            return;
+        }
+        let decrease =
+            (previous_max_in_flight - self.max_in_flight) as f64 / previous_max_in_flight as f64;
+        if decrease < self.min_effective_decrease {
+            self.warned_ineffective_decrease = true;
+            warn!(
+                "Decrease factor of {} only cut max in flight by {:.4}% (from {} to {}), congestion control is effectively disabled",
+                self.effective_decrease_factor(),
+                decrease * 100.0,
+                previous_max_in_flight,
+                self.max_in_flight
+            );
+        }
+    }
+
+    /// Adds the fulfilled amount and the time since the previous fulfill to the delivery
+    /// rate, unless the interval was app-limited
+    fn sample_delivery(&mut self, amount: u64) {
+        let now = self.clock.now();
+        let last_delivery = match self.last_delivery {
+            Some(last_delivery) if !self.app_limited => last_delivery,
+            _ => {
+                self.pending_delivery = 0;
+                self.last_delivery = Some(now);
+                return;
+            }
+        };
+        self.pending_delivery = self.pending_delivery.saturating_add(amount);
+        let elapsed = now.saturating_duration_since(last_delivery);
+        // Fulfills arriving at the same instant are combined into the next sample
+        if elapsed > Duration::from_secs(0) {
+            self.delivery_rate
+                .observe(self.pending_delivery as f64 / elapsed.as_secs_f64());
+            self.delivery_rate_sampled = true;
+            if self.baseline_rtt.is_some() && self.baseline_rate.is_none() {
+                self.baseline_rate = self.delivery_rate();
+            }
+            self.pending_delivery = 0;
+            self.last_delivery = Some(now);
+        }
+    }
+
+    /// Adds a round trip time sample, noting the delivery rate if it's the lowest yet
+    fn observe_rtt(&mut self, rtt: Duration) {
+        self.rtt.record(rtt);
+        if !matches!(self.baseline_rtt, Some(baseline) if baseline <= rtt) {
+            self.baseline_rtt = Some(rtt);
+            self.baseline_rate = self.delivery_rate();
+        }
+    }
+
+    /// Starts ramping the authorized window towards a grown slow start window, from
+    /// wherever the previous ramp had gotten to
+    fn update_pacing(&mut self, previous_max_in_flight: u64) {
+        if !self.paced_slow_start {
+            return;
+        }
+        let rtt = match self.rtt.smoothed() {
+            Some(rtt) if rtt > Duration::from_secs(0) => rtt,
+            _ => {
+                self.pacing_ramp = None;
+                return;
+            }
+        };
+        if self.state != CongestionState::SlowStart || self.max_in_flight <= previous_max_in_flight
+        {
+            self.pacing_ramp = None;
+            return;
+        }
+        let now = self.clock.now();
+        let from = match &self.pacing_ramp {
+            Some(ramp) => min(ramp.window_at(now), previous_max_in_flight),
+            None => previous_max_in_flight,
+        };
+        self.pacing_ramp = Some(PacingRamp {
+            from,
+            to: self.max_in_flight,
+            start: now,
+            duration: rtt,
+        });
+    }
+
+    /// Appends the event to the log, keeping the counts behind the congestion index in step
+    /// with the events it retains
+    fn log_event(&mut self, event: CongestionEvent) {
+        let (resolved, congested) = event.counts();
+        self.resolved_events += resolved;
+        self.congested_events += congested;
+        if let Some(evicted) = self.events.push(event) {
+            let (resolved, congested) = evicted.counts();
+            self.resolved_events -= resolved;
+            self.congested_events -= congested;
+        }
+    }
+
+    /// Shifts the outcome of a resolved packet into the outcome bitstring
+    fn record_outcome(&mut self, fulfilled: bool) {
+        self.recent_outcomes = (self.recent_outcomes << 1) | u64::from(fulfilled);
+        self.recent_outcome_count = min(self.recent_outcome_count + 1, u64::BITS);
+    }
+
+    /// Adds the window to the histogram if it changed
+    fn observe_window(&mut self, previous_max_in_flight: u64) {
+        if let Some(histogram) = &mut self.window_histogram {
+            if self.max_in_flight != previous_max_in_flight {
+                histogram.observe(self.max_in_flight);
+            }
+        }
+    }
+
+    /// The part of the window the target utilization lets the sender fill
+    fn utilized(&self, window: u64) -> u64 {
+        if self.target_utilization >= 1.0 {
+            window
+        } else {
+            (window as f64 * self.target_utilization) as u64
+        }
+    }
+
+    /// Brings the window back within the configured bounds, and keeps track of how long
+    /// it has been sitting at the floor
+    fn clamp_window(&mut self) {
+        // The floor can't exceed the cap, which is checked when the bounds are set
+        self.max_in_flight = self
+            .max_in_flight
+            .clamp(self.min_in_flight, self.max_in_flight_cap);
+        let near_floor =
+            self.max_in_flight <= self.min_in_flight.saturating_add(self.increase_amount);
+        match (near_floor, self.at_floor_since) {
+            (true, None) => self.at_floor_since = Some(self.clock.now()),
+            (false, Some(since)) => {
+                self.time_at_floor = arithmetic::add_duration(
+                    self.time_at_floor,
+                    self.clock.now().saturating_duration_since(since),
+                    "time at floor",
+                );
+                self.at_floor_since = None;
+            }
+            _ => {}
+        }
+    }
+
+    /// Divisor applied to window growth and multiplier applied to the decrease factor
+    fn volatility_scale(&self) -> f64 {
+        match self.volatility_sensitivity {
+            Some(sensitivity) => 1.0 + sensitivity * self.volatility,
+            None => 1.0,
+        }
+    }
+
+    /// Scales down an increase of the window by the volatility, leaving it exact
+    /// when there's nothing to scale
+    fn dampen_growth(&self, growth: u64) -> u64 {
+        let scale = self.volatility_scale();
+        if scale <= 1.0 {
+            growth
+        } else {
+            (growth as f64 / scale) as u64
+        }
+    }
+
+    /// Moves to `state`, which must be a legal transition from the current state
+    fn transition(&mut self, state: CongestionState) {
+        if state != self.state {
+            debug_assert!(
+                valid_transitions(self.state).contains(&state),
+                "illegal congestion state transition from {:?} to {:?}",
+                self.state,
+                state
+            );
+            self.state = state;
+        }
+    }
+
+    /// Switches to congestion avoidance, once enough signals have clustered if hysteresis is configured
+    fn on_congestion_signal(&mut self) {
+        // The hysteresis counts every signal, whatever the state
+        let clustered = self
+            .hysteresis
+            .as_mut()
+            .map(HysteresisTracker::on_congestion_signal);
+        match clustered {
+            Some(true) if self.state == CongestionState::SlowStart => {
+                if let Some(hysteresis) = &mut self.hysteresis {
+                    hysteresis.reset();
+                }
+                self.transition(CongestionState::AvoidCongestion);
+            }
+            Some(_) => {}
+            None if self.state != CongestionState::Draining => {
+                self.transition(CongestionState::AvoidCongestion)
+            }
+            None => {}
+        }
+    }
+
+    /// Recomputes the congestion level, notifying subscribers if it changed
+    fn update_congestion_level(&mut self) {
+        let level = CongestionLevel::from_index(self.congestion_index());
+        if level != self.congestion_level {
+            debug!(
+                "Congestion level changed from {:?} to {:?}",
+                self.congestion_level, level
+            );
+            self.congestion_level = level;
+            #[cfg(feature = "congestion-notifications")]
+            if let Some(sender) = &self.level_sender.0 {
+                // Subscribers may have gone away, which is fine
+                let _ = sender.send(level);
+            }
+        }
+    }
+
+    /// Removes a resolved packet from the amount in flight, unless `reap` already reclaimed it
+    fn release(&mut self, sequence: u64, amount: u64) {
+        let packet = self
+            .in_flight_packets
+            .remove_sorted(&sequence, |packet| packet.sequence);
+        if let Some(packet) = packet {
+            let rtt = self
+                .clock
+                .now()
+                .saturating_duration_since(packet.prepared_at);
+            self.observe_rtt(rtt);
+            self.take_from_in_flight(packet.amount, "Released");
+            return;
+        }
+        // Untracked packets were either evicted from tracking, and are still part of the
+        // amount in flight, or reaped or ignored, in which case their amount isn't
+        if self
+            .reaped_packets
+            .remove_first(|&reaped| reaped == sequence)
+            .is_none()
+        {
+            self.take_from_in_flight(amount, "Released");
+        }
+    }
+
+    /// Subtracts a packet's amount from the amount in flight, saturating at 0. Only a credit
+    /// that already returned part of the amount explains a shortfall, anything else means
+    /// the packet was resolved twice or with the wrong amount.
+    fn take_from_in_flight(&mut self, amount: u64, action: &str) {
+        if amount > self.amount_in_flight {
+            let shortfall = amount - self.amount_in_flight;
+            if shortfall <= self.credited_amount {
+                self.credited_amount -= shortfall;
+                debug!(
+                    "{} {} with only {} in flight after a credit",
+                    action, amount, self.amount_in_flight
+                );
+            } else {
+                self.credited_amount = 0;
+                error!(
+                    "{} {} with only {} in flight, was a packet resolved twice?",
+                    action, amount, self.amount_in_flight
+                );
+            }
+        }
+        self.amount_in_flight = self.amount_in_flight.saturating_sub(amount);
+    }
+
+    /// Forcibly reclaims the amount of every tracked packet older than the configured
+    /// [max packet age](#method.with_max_packet_age), returning the total amount reclaimed.
+    ///
+    /// This is a safety valve against peers that neither fulfill nor reject packets, which
+    /// would otherwise keep the window full forever. Packets evicted from tracking by the
+    /// [tracking limits](./struct.TrackingLimits.html) can't be reaped. If a reaped packet
+    /// is resolved later on, its amount isn't released a second time.
+    pub fn reap(&mut self, now: Instant) -> u64 {
+        let max_age = match self.max_packet_age {
+            Some(max_age) => max_age,
+            None => return 0,
+        };
+        let expired = self
+            .in_flight_packets
+            .remove_all(|packet| now.saturating_duration_since(packet.prepared_at) >= max_age);
+        let mut reclaimed = 0;
+        for packet in expired {
+            self.take_from_in_flight(packet.amount, "Reaped");
+            self.reaped_packets.push(packet.sequence);
+            reclaimed = arithmetic::add(reclaimed, packet.amount, "reap");
+        }
+        if reclaimed > 0 {
+            warn!(
+                "Reclaimed {} from packets unresolved for more than {:?}, amount in flight is now: {}",
+                reclaimed, max_age, self.amount_in_flight
+            );
+        }
+        reclaimed
+    }
+
+    /// Returns an amount to the window for an out-of-band correction, e.g. a rejected
+    /// packet's value that was later refunded during settlement, without registering a
+    /// fulfill or reject. The amount in flight saturates at 0.
+    pub fn credit(&mut self, amount: u64) {
+        self.credited_amount = self
+            .credited_amount
+            .saturating_add(min(amount, self.amount_in_flight));
+        self.amount_in_flight = self.amount_in_flight.saturating_sub(amount);
+        debug!(
+            "Credited {} back to the window, amount in flight is now: {}",
+            amount, self.amount_in_flight
+        );
+    }
+
+    /// Takes an amount out of the window for an out-of-band correction, e.g. a fulfilled
+    /// packet that was reversed, without registering a prepare. The amount in flight
+    /// saturates at the u64 max value.
+    pub fn debit(&mut self, amount: u64) {
+        self.credited_amount = self.credited_amount.saturating_sub(amount);
+        self.amount_in_flight = self.amount_in_flight.saturating_add(amount);
+        debug!(
+            "Debited {} from the window, amount in flight is now: {}",
+            amount, self.amount_in_flight
+        );
+    }
+
+    #[cfg(test)]
+    fn set_max_packet_amount(&mut self, max_packet_amount: u64) {
+        self.max_packet_amount = Some(max_packet_amount)
+    }
+}
+
+/// Number of completed round trips after which the window estimate is considered mature
+const CONFIDENCE_ROUND_TRIPS: usize = 8;
+
+/// Number of delivery rate samples averaged by default
+const DEFAULT_RATE_SAMPLES: usize = 64;
+
+/// Number of recently prepared packets the average packet size is taken over
+const PACKET_SIZE_SAMPLES: usize = 64;
+
+/// Smoothed RTT, relative to the lowest RTT seen, at which the path is considered to be buffering
+const BUFFERBLOAT_RTT_INFLATION: f64 = 1.5;
+/// Delivery rate growth, relative to the rate at the lowest RTT, below which the rate is considered flat
+const BUFFERBLOAT_RATE_GROWTH: f64 = 1.1;
+
+/// By default, warn if a T04 or T05 reject cuts the window by less than 1%
+const DEFAULT_MIN_EFFECTIVE_DECREASE: f64 = 0.01;
+
+/// Pacing delay hinted after a T05 reject, until a round trip time has been measured
+const DEFAULT_RATE_LIMIT_DELAY: Duration = Duration::from_millis(100);
+
+/// Does the reject code indicate the path is congested?
+fn is_congestion_signal(code: ErrorCode) -> bool {
+    code == ErrorCode::T04_INSUFFICIENT_LIQUIDITY || code == ErrorCode::T05_RATE_LIMITED
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A reject with the given code and no data
+    fn reject_packet(code: ErrorCode) -> Reject {
+        RejectBuilder {
+            code,
+            message: &[],
+            triggered_by: None,
+            data: &[],
+        }
+        .build()
+    }
+
+    /// Prepares and fulfills a packet of the given amount
+    fn fulfill(controller: &mut CongestionController, amount: u64) {
+        let sequence = controller.prepare(amount);
+        controller.fulfill(sequence, amount);
+    }
+
+    /// Prepares a packet of the given amount and rejects it with the given code
+    fn reject(controller: &mut CongestionController, amount: u64, code: ErrorCode) {
+        let sequence = controller.prepare(amount);
+        controller.reject(sequence, amount, &reject_packet(code));
+    }
+
+    fn insufficient_liquidity(controller: &mut CongestionController) {
+        reject(controller, 1, ErrorCode::T04_INSUFFICIENT_LIQUIDITY);
+    }
+
+    fn amount_too_large(received: u64, max: u64) -> Reject {
+        RejectBuilder {
+            code: ErrorCode::F08_AMOUNT_TOO_LARGE,
+            message: &[],
+            triggered_by: None,
+            data: &MaxPacketAmountDetails::new(received, max).to_bytes(),
+        }
+        .build()
+    }
+
+    mod slow_start {
+        use super::*;
+
+        #[test]
+        fn doubles_max_amount_on_fulfill() {
+            let mut controller = CongestionController::new(1000, 1000, 2.0);
+
+            let amount = controller.get_amount_left_in_window();
+            let sequence = controller.prepare(amount);
+            controller.fulfill(sequence, amount);
+            assert_eq!(controller.get_amount_left_in_window(), 2000);
+
+            let amount = controller.get_amount_left_in_window();
+            let sequence = controller.prepare(amount);
+            controller.fulfill(sequence, amount);
+            assert_eq!(controller.get_amount_left_in_window(), 4000);
+
+            let amount = controller.get_amount_left_in_window();
+            let sequence = controller.prepare(amount);
+            controller.fulfill(sequence, amount);
+            assert_eq!(controller.get_amount_left_in_window(), 8000);
+        }
+
+        #[test]
+        fn doesnt_overflow_u64() {
+            let mut controller = CongestionController::new(u64::MAX - 1, 1000, 2.0);
+
+            let amount = controller.get_amount_left_in_window();
+            let sequence = controller.prepare(amount);
+            controller.fulfill(sequence, amount);
+            assert_eq!(controller.get_amount_left_in_window(), u64::max_value());
+        }
+    }
+
+    mod congestion_avoidance {
+        use super::*;
+        use interledger_packet::RejectBuilder;
+        use tracing_test::traced_test;
+
+        static INSUFFICIENT_LIQUIDITY_ERROR: Lazy<Reject> = Lazy::new(|| {
+            RejectBuilder {
+                code: ErrorCode::T04_INSUFFICIENT_LIQUIDITY,
+                message: &[],
+                triggered_by: None,
+                data: &[],
+            }
+            .build()
+        });
+
+        static RATE_LIMITED_ERROR: Lazy<Reject> = Lazy::new(|| {
+            RejectBuilder {
+                code: ErrorCode::T05_RATE_LIMITED,
+                message: &[],
+                triggered_by: None,
+                data: &[],
+            }
+            .build()
+        });
+
+        #[test]
+        fn additive_increase() {
+            let mut controller = CongestionController::new(1000, 1000, 2.0);
+            controller.state = CongestionState::AvoidCongestion;
+            for i in 1..5 {
+                let amount = i * 1000;
+                let sequence = controller.prepare(amount);
+                controller.fulfill(sequence, amount);
+                assert_eq!(controller.get_amount_left_in_window(), 1000 + i * 1000);
+            }
+        }
+
+        #[test]
+        fn multiplicative_decrease() {
+            let mut controller = CongestionController::new(1000, 1000, 2.0);
+            controller.state = CongestionState::AvoidCongestion;
+
+            let amount = controller.get_amount_left_in_window();
+            let sequence = controller.prepare(amount);
+            controller.reject(sequence, amount, &INSUFFICIENT_LIQUIDITY_ERROR);
+            assert_eq!(controller.get_amount_left_in_window(), 500);
+
+            let amount = controller.get_amount_left_in_window();
+            let sequence = controller.prepare(amount);
+            controller.reject(sequence, amount, &INSUFFICIENT_LIQUIDITY_ERROR);
+            assert_eq!(controller.get_amount_left_in_window(), 250);
+        }
+
+        #[test]
+        fn multiplicative_decrease_when_rate_limited() {
+            let mut controller = CongestionController::new(1000, 1000, 2.0);
+            controller.state = CongestionState::AvoidCongestion;
+            assert_eq!(controller.pacing_delay(), None);
+
+            let amount = controller.get_amount_left_in_window();
+            let sequence = controller.prepare(amount);
+            controller.reject(sequence, amount, &RATE_LIMITED_ERROR);
+            assert_eq!(controller.get_amount_left_in_window(), 500);
+            assert!(controller.pacing_delay().is_some());
+
+            let amount = controller.get_amount_left_in_window();
+            let sequence = controller.prepare(amount);
+            controller.reject(sequence, amount, &RATE_LIMITED_ERROR);
+            assert_eq!(controller.get_amount_left_in_window(), 250);
+        }
+
+        #[test]
+        fn rate_limit_pacing_delay() {
+            let clock = MockClock::new();
+            let mut controller =
+                CongestionController::new(1000, 1000, 2.0).with_clock(clock.clone());
+            controller.record_rtt_sample(Duration::from_millis(40));
+
+            // Rate limiting ends slow start like a T04 does
+            let sequence = controller.prepare(100);
+            controller.reject(sequence, 100, &RATE_LIMITED_ERROR);
+            assert!(controller.state == CongestionState::AvoidCongestion);
+            // The zero-length round trip of the rejected packet pulled the average down
+            let delay = controller.pacing_delay().unwrap();
+            assert_eq!(Some(delay), controller.smoothed_rtt());
+            assert!(delay > Duration::from_millis(0) && delay < Duration::from_millis(40));
+
+            let sequence = controller.prepare(100);
+            controller.fulfill(sequence, 100);
+            assert_eq!(controller.pacing_delay(), None);
+        }
+
+        #[test]
+        fn aimd_combined() {
+            let mut controller = CongestionController::new(1000, 1000, 2.0);
+            controller.state = CongestionState::AvoidCongestion;
+
+            let amount = controller.get_amount_left_in_window();
+            let sequence = controller.prepare(amount);
+            controller.fulfill(sequence, amount);
+            assert_eq!(controller.get_amount_left_in_window(), 2000);
+
+            let amount = controller.get_amount_left_in_window();
+            let sequence = controller.prepare(amount);
+            controller.fulfill(sequence, amount);
+            assert_eq!(controller.get_amount_left_in_window(), 3000);
+
+            let amount = controller.get_amount_left_in_window();
+            let sequence = controller.prepare(amount);
+            controller.reject(sequence, amount, &INSUFFICIENT_LIQUIDITY_ERROR);
+            assert_eq!(controller.get_amount_left_in_window(), 1500);
+
+            let amount = controller.get_amount_left_in_window();
+            let sequence = controller.prepare(amount);
+            controller.fulfill(sequence, amount);
+            assert_eq!(controller.get_amount_left_in_window(), 2500);
+        }
+
+        #[test]
+        fn max_packet_amount() {
+            let mut controller = CongestionController::new(1000, 1000, 2.0);
+            assert_eq!(controller.get_amount_left_in_window(), 1000);
+
+            let sequence = controller.prepare(1000);
+            controller.reject(
+                sequence,
+                1000,
+                &RejectBuilder {
+                    code: ErrorCode::F08_AMOUNT_TOO_LARGE,
+                    message: &[],
+                    triggered_by: None,
+                    data: &MaxPacketAmountDetails::new(100, 10).to_bytes(),
+                }
+                .build(),
+            );
+            assert_eq!(controller.get_max_packet_amount(), 100);
+
+            let mut amount = min(
+                controller.get_max_packet_amount(),
+                controller.get_amount_left_in_window(),
+            );
+            let sequence = controller.prepare(amount);
+            controller.reject(
+                sequence,
+                amount,
+                &RejectBuilder {
+                    code: ErrorCode::F08_AMOUNT_TOO_LARGE,
+                    message: &[],
+                    triggered_by: None,
+                    data: &[],
+                }
+                .build(),
+            );
+            // it was decreased by the decrease factor
+            assert_eq!(controller.get_max_packet_amount(), amount / 2);
+
+            amount = min(
+                controller.get_max_packet_amount(),
+                controller.get_amount_left_in_window(),
+            );
+            let sequence = controller.prepare(amount);
+            controller.fulfill(sequence, amount);
+
+            amount = min(
+                controller.get_max_packet_amount(),
+                controller.get_amount_left_in_window(),
+            );
+            assert_eq!(amount, 50);
+        }
+
+        #[test]
+        #[traced_test]
+        fn logs_parsed_max_packet_amount_details() {
+            let mut controller = CongestionController::new(1000, 1000, 2.0);
+
+            let sequence = controller.prepare(1000);
+            controller.reject(
+                sequence,
+                1000,
+                &RejectBuilder {
+                    code: ErrorCode::F08_AMOUNT_TOO_LARGE,
+                    message: &[],
+                    triggered_by: None,
+                    data: &MaxPacketAmountDetails::new(2000, 500).to_bytes(),
+                }
+                .build(),
+            );
+
+            assert_eq!(controller.get_max_packet_amount(), 250);
+            assert!(logs_contain(
+                "Amount received: 2000, max amount: 500, setting max packet amount to: 250"
+            ));
+        }
+
+        #[test]
+        fn max_packet_amount_doesnt_overflow_u64() {
+            let mut controller = CongestionController::new(1000, 1000, 5.0);
+
+            controller.prepare(500);
+            let sequence = controller.prepare(500);
+            controller.reject(sequence, 500, &INSUFFICIENT_LIQUIDITY_ERROR);
+
+            assert_eq!(controller.get_amount_left_in_window(), 0);
+        }
+
+        #[test]
+        fn doesnt_overflow_u64() {
+            let mut controller = CongestionController::new(u64::MAX - 1, 1000, 2.0);
+            controller.state = CongestionState::AvoidCongestion;
+
+            let amount = controller.get_amount_left_in_window();
+            let sequence = controller.prepare(amount);
+            controller.fulfill(sequence, amount);
+            assert_eq!(controller.get_amount_left_in_window(), u64::max_value());
+        }
+    }
+
+    mod tracking_amount_in_flight {
+        use super::*;
+
+        #[test]
+        fn tracking_amount_in_flight() {
+            let mut controller = CongestionController::new(1000, 1000, 2.0);
+            controller.set_max_packet_amount(600);
+            assert_eq!(controller.get_max_packet_amount(), 600);
+
+            controller.prepare(100);
+            let mut max_amount = min(
+                controller.get_amount_left_in_window(),
+                controller.get_max_packet_amount(),
+            );
+            assert_eq!(max_amount, 600);
+
+            controller.prepare(600);
+            max_amount = min(
+                controller.get_amount_left_in_window(),
+                controller.get_max_packet_amount(),
+            );
+            assert_eq!(max_amount, 1000 - 600 - 100);
+        }
+    }
+
+    mod tracking_limits {
+        use super::*;
+        use interledger_packet::RejectBuilder;
+
+        #[test]
+        fn bounds_tracked_packets_and_events() {
+            let limits = TrackingLimits {
+                max_tracked_packets: 4,
+                max_events: 8,
+                max_rtt_samples: 2,
+                max_f08_limits: 2,
+            };
+            let mut controller =
+                CongestionController::new(u64::MAX, 1000, 2.0).with_tracking_limits(limits);
+            assert_eq!(controller.tracking_limits(), limits);
+
+            // Flood the controller with packets that are never resolved
+            for amount in 1..=1000 {
+                controller.prepare(amount);
+            }
+            assert_eq!(controller.tracked_packet_count(), 4);
+            assert_eq!(controller.amount_in_flight, (1..=1000).sum::<u64>());
+
+            let reject = RejectBuilder {
+                code: ErrorCode::T00_INTERNAL_ERROR,
+                message: &[],
+                triggered_by: None,
+                data: &[],
+            }
+            .build();
+            // Sequence numbers start at 1, so each packet's matches its amount
+            controller.fulfill(999, 999);
+            controller.reject(1000, 1000, &reject);
+            assert_eq!(controller.tracked_packet_count(), 2);
+
+            // Only the newest events are retained
+            let events: Vec<CongestionEvent> = controller.events().copied().collect();
+            assert_eq!(events.len(), 8);
+            assert_eq!(
+                &events[4..],
+                &[
+                    CongestionEvent::Prepare { amount: 999 },
+                    CongestionEvent::Prepare { amount: 1000 },
+                    CongestionEvent::Fulfill { amount: 999 },
+                    CongestionEvent::Reject {
+                        amount: 1000,
+                        code: ErrorCode::T00_INTERNAL_ERROR
+                    },
+                ]
+            );
+        }
+    }
+
+    mod congestion_level {
+        use super::*;
+
+        #[test]
+        fn derives_level_from_congestion_index() {
+            let mut controller = CongestionController::new(1000, 1000, 2.0);
+            assert_eq!(controller.congestion_index(), 0.0);
+            assert_eq!(controller.congestion_level(), CongestionLevel::Low);
+
+            for _ in 0..8 {
+                fulfill(&mut controller, 100);
+            }
+            // Rejects that don't signal congestion don't count
+            reject(&mut controller, 100, ErrorCode::F99_APPLICATION_ERROR);
+            assert_eq!(controller.congestion_level(), CongestionLevel::Low);
+
+            reject(&mut controller, 100, ErrorCode::T04_INSUFFICIENT_LIQUIDITY);
+            assert!((controller.congestion_index() - 0.1).abs() < f64::EPSILON);
+            assert_eq!(controller.congestion_level(), CongestionLevel::Medium);
+
+            for _ in 0..4 {
+                reject(&mut controller, 100, ErrorCode::T04_INSUFFICIENT_LIQUIDITY);
+            }
+            assert_eq!(controller.congestion_level(), CongestionLevel::High);
+        }
+
+        #[test]
+        fn index_only_counts_retained_events() {
+            let mut controller =
+                CongestionController::new(1000, 1000, 2.0).with_tracking_limits(TrackingLimits {
+                    max_events: 4,
+                    ..Default::default()
+                });
+            reject(&mut controller, 100, ErrorCode::T04_INSUFFICIENT_LIQUIDITY);
+            assert_eq!(controller.congestion_index(), 1.0);
+
+            // Each fulfill logs a prepare and a fulfill, pushing the reject out of the log
+            fulfill(&mut controller, 100);
+            fulfill(&mut controller, 100);
+            assert_eq!(controller.congestion_index(), 0.0);
+
+            reject(&mut controller, 100, ErrorCode::T05_RATE_LIMITED);
+            assert_eq!(controller.congestion_index(), 0.5);
+            // Shrinking the log recounts what's left
+            controller = controller.with_tracking_limits(TrackingLimits {
+                max_events: 1,
+                ..Default::default()
+            });
+            assert_eq!(controller.congestion_index(), 1.0);
+        }
+
+        #[cfg(feature = "congestion-notifications")]
+        #[tokio::test]
+        async fn notifies_subscribers_on_level_transitions() {
+            let mut controller = CongestionController::new(1000, 1000, 2.0);
+            let mut receiver = controller.subscribe_congestion_level();
+            assert_eq!(*receiver.borrow(), CongestionLevel::Low);
+
+            fulfill(&mut controller, 100);
+            reject(&mut controller, 100, ErrorCode::T04_INSUFFICIENT_LIQUIDITY);
+            receiver.changed().await.unwrap();
+            assert_eq!(*receiver.borrow(), CongestionLevel::High);
+
+            // Staying at the same level doesn't notify
+            reject(&mut controller, 100, ErrorCode::T04_INSUFFICIENT_LIQUIDITY);
+            assert!(
+                tokio::time::timeout(std::time::Duration::from_millis(10), receiver.changed())
+                    .await
+                    .is_err()
+            );
+
+            for _ in 0..20 {
+                fulfill(&mut controller, 100);
+            }
+            receiver.changed().await.unwrap();
+            assert_eq!(*receiver.borrow(), CongestionLevel::Low);
+        }
+    }
+
+    mod f08_limits {
+        use super::*;
+        use interledger_packet::RejectBuilder;
+
+        #[test]
+        fn counts_distinct_limits() {
+            let mut controller = CongestionController::new(u64::MAX, 1000, 2.0);
+            assert_eq!(controller.distinct_f08_limit_count(), 0);
+
+            for (received, max) in [
+                (1000, 500),
+                (1000, 500),
+                (800, 400),
+                (1000, 300),
+                (1000, 300),
+            ] {
+                let sequence = controller.prepare(received);
+                controller.reject(sequence, received, &amount_too_large(received, max));
+            }
+            // F08 without details doesn't report a limit
+            let sequence = controller.prepare(100);
+            controller.reject(
+                sequence,
+                100,
+                &RejectBuilder {
+                    code: ErrorCode::F08_AMOUNT_TOO_LARGE,
+                    message: &[],
+                    triggered_by: None,
+                    data: &[],
+                }
+                .build(),
+            );
+
+            assert_eq!(controller.distinct_f08_limit_count(), 3);
+        }
+
+        #[test]
+        fn bounded_by_tracking_limits() {
+            let mut controller = CongestionController::new(u64::MAX, 1000, 2.0)
+                .with_tracking_limits(TrackingLimits {
+                    max_f08_limits: 2,
+                    ..TrackingLimits::default()
+                });
+
+            for max in 1..=10 {
+                let sequence = controller.prepare(1000);
+                controller.reject(sequence, 1000, &amount_too_large(1000, max * 10));
+            }
+            assert_eq!(controller.distinct_f08_limit_count(), 2);
+        }
+
+        /// Returns the reject of a connector whose incoming account accepts at most
+        /// `max` per packet, for a packet that reached it with `received`
+        async fn connector_reject(received: u64, max: u64) -> Reject {
+            use crate::test_helpers::{TestAccount, TestStore, EXAMPLE_CONNECTOR};
+            use interledger_packet::PrepareBuilder;
+            use interledger_service::{incoming_service_fn, IncomingRequest, IncomingService};
+            use interledger_service_util::MaxPacketAmountService;
+
+            let store = TestStore {
+                route: None,
+                price_1: None,
+                price_2: None,
+            };
+            let next = incoming_service_fn(|_| -> Result<_, Reject> {
+                panic!("packets over the maximum must not be forwarded")
+            });
+            MaxPacketAmountService::new(store, next)
+                .handle_request(IncomingRequest {
+                    from: TestAccount {
+                        id: uuid::Uuid::new_v4(),
+                        ilp_address: EXAMPLE_CONNECTOR.clone(),
+                        asset_code: "XYZ".to_string(),
+                        asset_scale: 9,
+                        max_packet_amount: Some(max),
+                    },
+                    prepare: PrepareBuilder {
+                        destination: EXAMPLE_CONNECTOR.clone(),
+                        amount: received,
+                        expires_at: std::time::SystemTime::now()
+                            + std::time::Duration::from_secs(30),
+                        execution_condition: &[0; 32],
+                        data: &[],
+                    }
+                    .build(),
+                })
+                .await
+                .unwrap_err()
+        }
+
+        #[tokio::test]
+        async fn learns_limit_from_max_packet_amount_service() {
+            let mut controller = CongestionController::new(u64::MAX, 1000, 2.0);
+            let sequence = controller.prepare(1000);
+            controller.reject(sequence, 1000, &connector_reject(1000, 300).await);
+            assert_eq!(controller.get_max_packet_amount(), 300);
+            assert_eq!(controller.distinct_f08_limit_count(), 1);
+        }
+
+        #[tokio::test]
+        async fn scales_limit_from_max_packet_amount_service_to_source_units() {
+            // The packet was worth half as much by the time it reached the connector,
+            // so the sender can send twice the connector's maximum
+            let mut controller = CongestionController::new(u64::MAX, 1000, 2.0);
+            let sequence = controller.prepare(2000);
+            controller.reject(sequence, 2000, &connector_reject(1000, 300).await);
+            assert_eq!(controller.get_max_packet_amount(), 600);
+        }
+    }
+
+    mod hysteresis {
+        use super::*;
+
+        fn hysteresis() -> Hysteresis {
+            Hysteresis {
+                congestion_signals: 3,
+                signal_window: 10,
+                clean_round_trips: 2,
+            }
+        }
+
+        #[test]
+        fn isolated_rejects_dont_leave_slow_start() {
+            let mut controller = CongestionController::new(1000, 1000, 2.0)
+                .with_hysteresis(hysteresis())
+                .unwrap();
+
+            insufficient_liquidity(&mut controller);
+            // The window is still cut, but the state is unchanged
+            assert_eq!(controller.max_in_flight, 500);
+            assert!(controller.state == CongestionState::SlowStart);
+
+            // Signals spread further apart than the signal window don't count as a cluster
+            for _ in 0..3 {
+                for _ in 0..10 {
+                    fulfill(&mut controller, 1);
+                }
+                insufficient_liquidity(&mut controller);
+                assert!(controller.state == CongestionState::SlowStart);
+            }
+        }
+
+        #[test]
+        fn clustered_rejects_leave_slow_start() {
+            let mut controller = CongestionController::new(1000, 1000, 2.0)
+                .with_hysteresis(hysteresis())
+                .unwrap();
+
+            insufficient_liquidity(&mut controller);
+            fulfill(&mut controller, 1);
+            insufficient_liquidity(&mut controller);
+            assert!(controller.state == CongestionState::SlowStart);
+            insufficient_liquidity(&mut controller);
+            assert!(controller.state == CongestionState::AvoidCongestion);
+        }
+
+        #[test]
+        fn clean_round_trips_reenter_slow_start() {
+            let mut controller = CongestionController::new(1000, 1000, 2.0)
+                .with_hysteresis(hysteresis())
+                .unwrap();
+            for _ in 0..3 {
+                insufficient_liquidity(&mut controller);
+            }
+            assert!(controller.state == CongestionState::AvoidCongestion);
+            assert_eq!(controller.max_in_flight, 125);
+
+            // First clean round trip
+            fulfill(&mut controller, 125);
+            assert!(controller.state == CongestionState::AvoidCongestion);
+            // A congestion signal restarts the count
+            insufficient_liquidity(&mut controller);
+            let window = controller.max_in_flight;
+            fulfill(&mut controller, window);
+            assert!(controller.state == CongestionState::AvoidCongestion);
+            let window = controller.max_in_flight;
+            fulfill(&mut controller, window);
+            assert!(controller.state == CongestionState::SlowStart);
+        }
+
+        #[test]
+        fn rejects_unreachable_thresholds() {
+            for invalid in [
+                Hysteresis {
+                    congestion_signals: 0,
+                    ..hysteresis()
+                },
+                Hysteresis {
+                    signal_window: 0,
+                    ..hysteresis()
+                },
+                Hysteresis {
+                    clean_round_trips: 0,
+                    ..hysteresis()
+                },
+            ] {
+                assert_eq!(
+                    CongestionController::new(1000, 1000, 2.0)
+                        .with_hysteresis(invalid)
+                        .err(),
+                    Some(CongestionError::InvalidHysteresis(invalid))
+                );
+            }
+        }
+
+        #[test]
+        fn without_hysteresis_first_reject_leaves_slow_start() {
+            let mut controller = CongestionController::new(1000, 1000, 2.0);
+            assert_eq!(controller.hysteresis(), None);
+            insufficient_liquidity(&mut controller);
+            assert!(controller.state == CongestionState::AvoidCongestion);
+
+            for _ in 0..100 {
+                let window = controller.max_in_flight;
+                fulfill(&mut controller, window);
+            }
+            assert!(controller.state == CongestionState::AvoidCongestion);
+        }
+    }
+
+    mod throughput {
+        use super::*;
+
+        #[test]
+        fn divides_window_by_rtt() {
+            let controller = CongestionController::new(5000, 1000, 2.0);
+            assert_eq!(
+                controller.max_theoretical_throughput(Duration::from_millis(250)),
+                Some(20_000.0)
+            );
+            assert_eq!(
+                controller.max_theoretical_throughput(Duration::from_secs(2)),
+                Some(2500.0)
+            );
+        }
+
+        #[test]
+        fn unknown_without_rtt() {
+            let controller = CongestionController::new(5000, 1000, 2.0);
+            assert_eq!(controller.max_theoretical_throughput(Duration::ZERO), None);
+        }
+    }
+
+    mod path_cache {
+        use super::*;
+        use interledger_packet::RejectBuilder;
+        use std::str::FromStr;
+
+        #[test]
+        fn sibling_destination_benefits_from_learned_limit() {
+            let cache = PathCache::new();
+            let alice = Address::from_str("g.us.bank.alice").unwrap();
+            let bob = Address::from_str("g.us.bank.bob").unwrap();
+
+            let mut to_alice =
+                CongestionController::new(1000, 1000, 2.0).with_path_cache(cache.clone(), alice);
+            assert_eq!(to_alice.get_max_packet_amount(), u64::MAX);
+            let sequence = to_alice.prepare(1000);
+            to_alice.reject(
+                sequence,
+                1000,
+                &RejectBuilder {
+                    code: ErrorCode::F08_AMOUNT_TOO_LARGE,
+                    message: &[],
+                    triggered_by: None,
+                    data: &MaxPacketAmountDetails::new(1000, 300).to_bytes(),
+                }
+                .build(),
+            );
+            assert_eq!(to_alice.get_max_packet_amount(), 300);
+
+            // A new connection to a sibling address starts out with the learned limit
+            let to_bob = CongestionController::new(1000, 1000, 2.0).with_path_cache(cache, bob);
+            assert_eq!(to_bob.get_max_packet_amount(), 300);
+        }
+    }
+
+    mod decrease_diagnostic {
+        use super::*;
+        use tracing_test::traced_test;
+
+        const WARNING: &str = "congestion control is effectively disabled";
+
+        #[test]
+        #[traced_test]
+        fn warns_once_for_near_one_factor() {
+            let mut controller = CongestionController::new(100_000, 1000, 1.001);
+            for _ in 0..5 {
+                insufficient_liquidity(&mut controller);
+            }
+            logs_assert(|lines: &[&str]| {
+                match lines.iter().filter(|line| line.contains(WARNING)).count() {
+                    1 => Ok(()),
+                    n => Err(format!("Expected one warning, got {}", n)),
+                }
+            });
+        }
+
+        #[test]
+        #[traced_test]
+        fn silent_for_effective_factor() {
+            let mut controller = CongestionController::new(100_000, 1000, 2.0);
+            for _ in 0..5 {
+                insufficient_liquidity(&mut controller);
+            }
+            assert!(!logs_contain(WARNING));
+        }
+
+        #[test]
+        #[traced_test]
+        fn threshold_is_configurable() {
+            let mut controller =
+                CongestionController::new(100_000, 1000, 1.2).with_min_effective_decrease(0.5);
+            insufficient_liquidity(&mut controller);
+            assert!(logs_contain(WARNING));
+        }
+    }
+
+    mod event_log_encoding {
+        use super::*;
+        use interledger_packet::RejectBuilder;
+
+        #[test]
+        fn round_trips_mixed_event_log() {
+            let mut controller = CongestionController::new(u64::MAX, 1000, 2.0);
+            let sequences: Vec<u64> = [1, 128, 255, 256, u64::MAX / 4]
+                .iter()
+                .map(|&amount| controller.prepare(amount))
+                .collect();
+            controller.fulfill(sequences[0], 1);
+            for (sequence, amount, code) in [
+                (sequences[2], 255, ErrorCode::T04_INSUFFICIENT_LIQUIDITY),
+                (sequences[3], 256, ErrorCode::F99_APPLICATION_ERROR),
+            ] {
+                let reject = RejectBuilder {
+                    code,
+                    message: &[],
+                    triggered_by: None,
+                    data: &[],
+                }
+                .build();
+                controller.reject(sequence, amount, &reject);
+            }
+            controller.fulfill(sequences[1], 128);
+
+            let encoded = controller.encode_events();
+            let decoded = CongestionController::decode_events(&encoded).unwrap();
+            assert_eq!(decoded, controller.events().copied().collect::<Vec<_>>());
+            assert_eq!(decoded.len(), 9);
+        }
+
+        #[test]
+        fn empty_event_log() {
+            let controller = CongestionController::new(1000, 1000, 2.0);
+            let encoded = controller.encode_events();
+            assert!(CongestionController::decode_events(&encoded)
+                .unwrap()
+                .is_empty());
+        }
+    }
+
+    mod volatility {
+        use super::*;
+
+        fn controller(volatility: f64) -> CongestionController {
+            let mut controller =
+                CongestionController::new(10_000, 1000, 2.0).with_volatility_scaling(4.0);
+            controller.set_volatility(volatility);
+            controller
+        }
+
+        #[test]
+        fn high_volatility_grows_slower() {
+            let mut low = controller(0.0);
+            let mut high = controller(0.25);
+
+            // Slow start grows by half the window instead of doubling it
+            low.fulfill(0, 0);
+            high.fulfill(0, 0);
+            assert_eq!(low.max_in_flight, 20_000);
+            assert_eq!(high.max_in_flight, 15_000);
+
+            low.state = CongestionState::AvoidCongestion;
+            high.state = CongestionState::AvoidCongestion;
+            assert_eq!(low.effective_increase_amount(), 1000);
+            assert_eq!(high.effective_increase_amount(), 500);
+            low.fulfill(0, 0);
+            high.fulfill(0, 0);
+            assert_eq!(low.max_in_flight, 21_000);
+            assert_eq!(high.max_in_flight, 15_500);
+        }
+
+        #[test]
+        fn high_volatility_cuts_sharper() {
+            let mut low = controller(0.0);
+            let mut high = controller(0.25);
+            assert_eq!(low.effective_decrease_factor(), 2.0);
+            assert_eq!(high.effective_decrease_factor(), 4.0);
+
+            insufficient_liquidity(&mut low);
+            insufficient_liquidity(&mut high);
+            assert_eq!(low.max_in_flight, 5000);
+            assert_eq!(high.max_in_flight, 2500);
+        }
+
+        #[test]
+        fn hint_is_ignored_unless_enabled() {
+            let mut controller = CongestionController::new(10_000, 1000, 2.0);
+            controller.set_volatility(1.0);
+            assert_eq!(controller.effective_increase_amount(), 1000);
+            assert_eq!(controller.effective_decrease_factor(), 2.0);
+
+            let mut scaled =
+                CongestionController::new(10_000, 1000, 2.0).with_volatility_scaling(1.0);
+            scaled.set_volatility(f64::NAN);
+            assert_eq!(scaled.effective_decrease_factor(), 2.0);
+        }
+    }
+
+    mod overshoot {
+        use super::*;
+
+        #[test]
+        fn compares_first_signal_window_to_recovered_window() {
+            let mut controller = CongestionController::new(1000, 1000, 2.0);
+            // Slow start: 1000 -> 16000
+            for _ in 0..4 {
+                controller.fulfill(0, 0);
+            }
+            assert_eq!(controller.max_in_flight, 16_000);
+
+            insufficient_liquidity(&mut controller);
+            assert_eq!(controller.max_in_flight, 8000);
+            assert_eq!(controller.slow_start_overshoot_ratio(), None);
+
+            // Congestion avoidance recovers to 10000 before the next signal
+            for _ in 0..2 {
+                controller.fulfill(0, 0);
+            }
+            insufficient_liquidity(&mut controller);
+            assert_eq!(controller.slow_start_overshoot_ratio(), Some(1.6));
+
+            // Later signals don't change the measurement
+            insufficient_liquidity(&mut controller);
+            assert_eq!(controller.slow_start_overshoot_ratio(), Some(1.6));
+        }
+    }
+
+    mod reap {
+        use super::*;
+        use interledger_packet::RejectBuilder;
+        use tracing_test::traced_test;
+
+        #[test]
+        #[traced_test]
+        fn reclaims_unresolved_packets_after_max_age() {
+            let clock = MockClock::new();
+            let mut controller = CongestionController::new(1000, 100, 2.0)
+                .with_clock(clock.clone())
+                .with_max_packet_age(Duration::from_secs(30));
+
+            let first = controller.prepare(400);
+            clock.advance(Duration::from_secs(20));
+            let second = controller.prepare(300);
+            assert_eq!(controller.get_amount_left_in_window(), 300);
+
+            clock.advance(Duration::from_secs(9));
+            assert_eq!(controller.reap(clock.now()), 0);
+            assert!(!logs_contain("Reclaimed"));
+
+            clock.advance(Duration::from_secs(1));
+            assert_eq!(controller.reap(clock.now()), 400);
+            assert_eq!(controller.amount_in_flight, 300);
+            assert_eq!(controller.tracked_packet_count(), 1);
+            assert!(logs_contain("Reclaimed 400"));
+
+            // A late fulfill of the reaped packet doesn't release it twice
+            controller.fulfill(first, 400);
+            assert_eq!(controller.amount_in_flight, 300);
+            controller.fulfill(second, 300);
+            assert_eq!(controller.amount_in_flight, 0);
+        }
+
+        #[test]
+        fn late_reject_of_reaped_packet_does_not_overflow() {
+            let clock = MockClock::new();
+            let mut controller = CongestionController::new(u64::MAX, u64::MAX, 2.0)
+                .with_clock(clock.clone())
+                .with_max_packet_age(Duration::from_secs(30));
+            let sequence = controller.prepare(u64::MAX);
+            clock.advance(Duration::from_secs(30));
+            assert_eq!(controller.reap(clock.now()), u64::MAX);
+
+            // Some of the reclaimed window is in use again when the reaped packet is rejected
+            controller.prepare(10);
+            controller.reject(
+                sequence,
+                u64::MAX,
+                &RejectBuilder {
+                    code: ErrorCode::T04_INSUFFICIENT_LIQUIDITY,
+                    message: &[],
+                    triggered_by: None,
+                    data: &[],
+                }
+                .build(),
+            );
+            assert_eq!(controller.amount_in_flight, 10);
+        }
+
+        #[test]
+        fn does_nothing_without_max_age() {
+            let clock = MockClock::new();
+            let mut controller =
+                CongestionController::new(1000, 100, 2.0).with_clock(clock.clone());
+            controller.prepare(400);
+            clock.advance(Duration::from_secs(3600));
+            assert_eq!(controller.reap(clock.now()), 0);
+            assert_eq!(controller.amount_in_flight, 400);
+        }
+    }
+
+    mod window_confidence {
+        use super::*;
+
+        /// Fulfills a full window's worth of packets, completing one round trip
+        fn round_trip(controller: &mut CongestionController, packet_amount: u64) {
+            let rounds = controller.completed_round_trips;
+            while controller.completed_round_trips == rounds {
+                let sequence = controller.prepare(packet_amount);
+                controller.fulfill(sequence, packet_amount);
+            }
+        }
+
+        #[test]
+        fn fresh_controller_is_calibrating() {
+            let controller = CongestionController::new(1000, 10, 2.0);
+            assert_eq!(controller.window_confidence(), 0.0);
+        }
+
+        #[test]
+        fn long_stable_run_is_steady() {
+            let mut controller = CongestionController::new(10_000, 1, 2.0);
+            controller.state = CongestionState::AvoidCongestion;
+
+            round_trip(&mut controller, 1000);
+            let early = controller.window_confidence();
+            assert!(early <= 0.125, "confidence after one round trip: {}", early);
+
+            for _ in 0..50 {
+                round_trip(&mut controller, 1000);
+            }
+            let steady = controller.window_confidence();
+            assert!(steady > 0.99, "confidence after a stable run: {}", steady);
+        }
+
+        #[test]
+        fn growing_window_is_not_steady() {
+            let mut controller = CongestionController::new(1000, 1, 2.0);
+            // Slow start doubles the window every round trip
+            for _ in 0..CONFIDENCE_ROUND_TRIPS {
+                let window = controller.max_in_flight;
+                round_trip(&mut controller, window);
+            }
+            let confidence = controller.window_confidence();
+            assert!(confidence < 0.5, "confidence in slow start: {}", confidence);
+        }
+    }
+
+    mod window_bounds {
+        use super::*;
+
+        #[test]
+        fn rejects_floor_above_cap() {
+            assert_eq!(
+                CongestionController::new(1000, 10, 2.0)
+                    .with_window_bounds(1001, 1000)
+                    .err(),
+                Some(CongestionError::MinExceedsCap {
+                    min: 1001,
+                    cap: 1000
+                })
+            );
+        }
+
+        #[test]
+        fn window_stays_within_close_bounds() {
+            let mut controller = CongestionController::new(5000, 10, 2.0)
+                .with_window_bounds(990, 1000)
+                .unwrap();
+            // The start amount is clamped right away
+            assert_eq!(controller.max_in_flight, 1000);
+
+            for round in 0..20 {
+                if round % 3 == 0 {
+                    controller.fulfill(0, 0);
+                } else {
+                    insufficient_liquidity(&mut controller);
+                }
+                assert!(
+                    (990..=1000).contains(&controller.max_in_flight),
+                    "window out of bounds: {}",
+                    controller.max_in_flight
+                );
+            }
+            insufficient_liquidity(&mut controller);
+            assert_eq!(controller.max_in_flight, 990);
+            controller.fulfill(0, 0);
+            assert_eq!(controller.max_in_flight, 1000);
+        }
+
+        #[test]
+        fn equal_bounds_pin_the_window() {
+            let mut controller = CongestionController::new(1, 10, 2.0)
+                .with_window_bounds(500, 500)
+                .unwrap();
+            assert_eq!(controller.max_in_flight, 500);
+            insufficient_liquidity(&mut controller);
+            assert_eq!(controller.max_in_flight, 500);
+            controller.fulfill(0, 0);
+            assert_eq!(controller.max_in_flight, 500);
+        }
+    }
+
+    mod simulate_reject {
+        use super::*;
+
+        #[test]
+        fn matches_actual_reject_on_a_clone() {
+            let mut controller = CongestionController::new(1000, 100, 2.0)
+                .with_window_bounds(300, u64::MAX)
+                .unwrap()
+                .with_volatility_scaling(1.0);
+            controller.set_volatility(0.5);
+            controller.fulfill(0, 0);
+            controller.prepare(250);
+
+            for code in [
+                ErrorCode::T04_INSUFFICIENT_LIQUIDITY,
+                ErrorCode::T00_INTERNAL_ERROR,
+                ErrorCode::F99_APPLICATION_ERROR,
+            ] {
+                let simulated = controller.simulate_reject(code);
+                let mut actual = controller.clone();
+                reject(&mut actual, 100, code);
+                assert_eq!(simulated, actual.max_in_flight, "{:?}", code);
+            }
+            // Slow start grew the window by 1000 / 1.5, which is cut by 2.0 * 1.5
+            assert_eq!(controller.max_in_flight, 1666);
+            assert_eq!(
+                controller.simulate_reject(ErrorCode::T04_INSUFFICIENT_LIQUIDITY),
+                555
+            );
+
+            // Cut again, which the window floor limits
+            reject(&mut controller, 100, ErrorCode::T04_INSUFFICIENT_LIQUIDITY);
+            let simulated = controller.simulate_reject(ErrorCode::T04_INSUFFICIENT_LIQUIDITY);
+            let mut actual = controller.clone();
+            reject(&mut actual, 100, ErrorCode::T04_INSUFFICIENT_LIQUIDITY);
+            assert_eq!(simulated, actual.max_in_flight);
+            assert_eq!(simulated, 300);
+        }
+
+        #[test]
+        fn leaves_controller_untouched() {
+            let mut controller = CongestionController::new(1000, 100, 2.0);
+            controller.prepare(100);
+            let events = controller.events().count();
+
+            assert_eq!(
+                controller.simulate_reject(ErrorCode::T04_INSUFFICIENT_LIQUIDITY),
+                500
+            );
+            assert_eq!(controller.max_in_flight, 1000);
+            assert_eq!(controller.amount_in_flight, 100);
+            assert_eq!(controller.events().count(), events);
+            assert!(controller.state == CongestionState::SlowStart);
+        }
+    }
+
+    #[cfg(feature = "defensive")]
+    mod defensive {
+        use super::*;
+        use tracing_test::traced_test;
+
+        #[test]
+        #[traced_test]
+        fn survives_resolving_unprepared_packets() {
+            let mut controller = CongestionController::new(1000, 100, 2.0);
+            controller.prepare(100);
+            // Sequence 0 is never handed out, so this packet was never prepared
+            controller.fulfill(0, 300);
+            assert!(logs_contain("Released 300 with only 100 in flight"));
+            assert_eq!(controller.amount_in_flight, 0);
+            // The controller keeps working afterwards
+            controller.prepare(100);
+            assert_eq!(controller.get_amount_left_in_window(), 1900);
+        }
+
+        #[test]
+        #[traced_test]
+        fn survives_amount_in_flight_overflow() {
+            let mut controller = CongestionController::new(u64::MAX, 100, 2.0);
+            controller.prepare(u64::MAX - 1);
+            controller.prepare(10);
+            assert!(logs_contain("Arithmetic overflow in prepare"));
+            assert_eq!(controller.amount_in_flight, u64::MAX);
+        }
+
+        #[test]
+        #[traced_test]
+        fn survives_reclaiming_more_than_fits_in_a_u64() {
+            let clock = MockClock::new();
+            let mut controller = CongestionController::new(u64::MAX, 100, 2.0)
+                .with_clock(clock.clone())
+                .with_max_packet_age(Duration::from_secs(30));
+            controller.prepare(u64::MAX);
+            controller.prepare(10);
+            clock.advance(Duration::from_secs(30));
+            assert_eq!(controller.reap(clock.now()), u64::MAX);
+            assert!(logs_contain("Arithmetic overflow in reap"));
+            assert_eq!(controller.amount_in_flight, 0);
+        }
+
+        #[test]
+        #[traced_test]
+        fn survives_invalid_f08_details() {
+            let mut controller = CongestionController::new(1000, 100, 2.0);
+
+            // A connector claiming it received nothing
+            let sequence = controller.prepare(100);
+            controller.reject(sequence, 100, &amount_too_large(0, 50));
+            assert!(logs_contain("Division by zero in F08 max packet amount"));
+            assert_eq!(controller.get_max_packet_amount(), u64::MAX);
+
+            let sequence = controller.prepare(u64::MAX / 2);
+            controller.reject(sequence, u64::MAX / 2, &amount_too_large(1, 4));
+            assert!(logs_contain("Arithmetic overflow in F08 max packet amount"));
+            assert_eq!(controller.get_max_packet_amount(), u64::MAX);
+        }
+    }
+
+    mod app_limited {
+        use super::*;
+
+        #[test]
+        fn holds_the_window_until_demand_returns() {
+            let mut limited = CongestionController::new(1000, 100, 2.0);
+            let mut unlimited = CongestionController::new(1000, 100, 2.0);
+            limited.mark_app_limited();
+
+            for controller in [&mut limited, &mut unlimited] {
+                let sequence = controller.prepare(100);
+                controller.fulfill(sequence, 100);
+                controller.state = CongestionState::AvoidCongestion;
+                let sequence = controller.prepare(100);
+                controller.fulfill(sequence, 100);
+            }
+            assert!(limited.is_app_limited());
+            assert_eq!(limited.max_in_flight, 1000);
+            assert_eq!(unlimited.max_in_flight, 2100);
+
+            // Filling the window clears the mark, so the window grows again
+            let sequence = limited.prepare(1000);
+            assert!(!limited.is_app_limited());
+            limited.fulfill(sequence, 1000);
+            assert_eq!(limited.max_in_flight, 1100);
+        }
+
+        #[test]
+        fn idle_time_doesnt_skew_delivery_rate() {
+            let clock = MockClock::new();
+            let mut controller = CongestionController::new(200, 0, 2.0).with_clock(clock.clone());
+            controller.state = CongestionState::AvoidCongestion;
+            assert_eq!(controller.delivery_rate(), None);
+
+            let sequence = controller.prepare(200);
+            controller.fulfill(sequence, 200);
+            for _ in 0..3 {
+                let first = controller.prepare(200);
+                clock.advance(Duration::from_secs(2));
+                controller.fulfill(first, 200);
+            }
+            assert_eq!(controller.delivery_rate(), Some(100.0));
+
+            // The application runs out of money to send for a while
+            controller.mark_app_limited();
+            let second = controller.prepare(50);
+            clock.advance(Duration::from_secs(60));
+            controller.fulfill(second, 50);
+            assert_eq!(controller.delivery_rate(), Some(100.0));
+
+            // Filling the window ends the app-limited period
+            let third = controller.prepare(100);
+            assert!(controller.is_app_limited());
+            let fourth = controller.prepare(100);
+            assert!(!controller.is_app_limited());
+            clock.advance(Duration::from_secs(2));
+            controller.fulfill(third, 100);
+            clock.advance(Duration::from_secs(2));
+            controller.fulfill(fourth, 100);
+            assert_eq!(controller.delivery_rate(), Some(800.0 / 10.0));
+        }
+    }
+
+    mod live_tuning {
+        use super::*;
+
+        #[test]
+        fn new_parameters_apply_to_next_operation() {
+            let mut controller = CongestionController::new(1000, 100, 2.0);
+            controller.state = CongestionState::AvoidCongestion;
+            controller.fulfill(0, 0);
+            assert_eq!(controller.max_in_flight, 1100);
+
+            controller.set_increase_amount(500);
+            assert_eq!(controller.increase_amount(), 500);
+            controller.fulfill(0, 0);
+            assert_eq!(controller.max_in_flight, 1600);
+
+            controller.set_decrease_factor(4.0).unwrap();
+            assert_eq!(controller.decrease_factor(), 4.0);
+            insufficient_liquidity(&mut controller);
+            assert_eq!(controller.max_in_flight, 400);
+        }
+
+        #[test]
+        fn refuses_factors_that_dont_shrink_the_window() {
+            let mut controller = CongestionController::new(1000, 100, 2.0);
+            for factor in [1.0, 0.5, -2.0] {
+                assert_eq!(
+                    controller.set_decrease_factor(factor),
+                    Err(CongestionError::InvalidDecreaseFactor(factor))
+                );
+            }
+            assert!(matches!(
+                controller.set_decrease_factor(f64::NAN),
+                Err(CongestionError::InvalidDecreaseFactor(_))
+            ));
+            assert_eq!(controller.decrease_factor(), 2.0);
+
+            insufficient_liquidity(&mut controller);
+            assert_eq!(controller.max_in_flight, 500);
+        }
+    }
+
+    mod window_histogram {
+        use super::*;
+        use interledger_packet::{Amount, RejectBuilder};
+
+        #[test]
+        fn buckets_window_changes() {
+            let mut controller = CongestionController::new(1000, 1000, 2.0)
+                .with_window_histogram(vec![10_000, 1000, 4000]);
+            // 1000 -> 2000 -> 4000 -> 8000 -> 16000
+            for _ in 0..4 {
+                controller.fulfill(0, 0);
+            }
+            // 16000 -> 8000, then 9000
+            insufficient_liquidity(&mut controller);
+            controller.fulfill(0, 0);
+            // A reject that doesn't change the window isn't observed
+            let sequence = controller.prepare(1);
+            controller.reject(
+                sequence,
+                1,
+                &RejectBuilder {
+                    code: ErrorCode::F99_APPLICATION_ERROR,
+                    message: &[],
+                    triggered_by: None,
+                    data: &[],
+                }
+                .build(),
+            );
+
+            let histogram = controller.window_histogram().unwrap();
+            assert_eq!(
+                histogram.buckets(),
+                vec![
+                    (Some(1000), 1),
+                    (Some(4000), 3),
+                    (Some(10_000), 6),
+                    (None, 7)
+                ]
+            );
+            assert_eq!(histogram.count(), 7);
+            assert_eq!(histogram.sum(), Amount::from(48_000u64));
+
+            assert_eq!(
+                controller.window_histogram_openmetrics().unwrap(),
+                "# TYPE stream_congestion_window histogram\n\
+                 stream_congestion_window_bucket{le=\"1000\"} 1\n\
+                 stream_congestion_window_bucket{le=\"4000\"} 3\n\
+                 stream_congestion_window_bucket{le=\"10000\"} 6\n\
+                 stream_congestion_window_bucket{le=\"+Inf\"} 7\n\
+                 stream_congestion_window_sum 48000\n\
+                 stream_congestion_window_count 7\n\
+                 # EOF\n"
+            );
+        }
+
+        #[test]
+        fn disabled_by_default() {
+            let controller = CongestionController::new(1000, 1000, 2.0);
+            assert!(controller.window_histogram().is_none());
+            assert!(controller.window_histogram_openmetrics().is_none());
+        }
+    }
+
+    mod rtt_samples {
+        use super::*;
+
+        #[test]
+        fn external_samples_update_estimate() {
+            let mut controller = CongestionController::new(1000, 100, 2.0);
+            assert_eq!(controller.smoothed_rtt(), None);
+
+            controller.record_rtt_sample(Duration::from_millis(800));
+            assert_eq!(controller.smoothed_rtt(), Some(Duration::from_millis(800)));
+            controller.record_rtt_sample(Duration::from_millis(400));
+            assert_eq!(controller.smoothed_rtt(), Some(Duration::from_millis(750)));
+            assert_eq!(controller.min_rtt(), Some(Duration::from_millis(400)));
+        }
+
+        #[test]
+        fn shares_estimate_with_packet_measurements() {
+            let clock = MockClock::new();
+            let mut controller =
+                CongestionController::new(1000, 100, 2.0).with_clock(clock.clone());
+
+            let first = controller.prepare(100);
+            clock.advance(Duration::from_millis(800));
+            controller.fulfill(first, 100);
+            assert_eq!(controller.smoothed_rtt(), Some(Duration::from_millis(800)));
+
+            // An idle period with only keepalive samples
+            controller.record_rtt_sample(Duration::from_millis(400));
+            assert_eq!(controller.smoothed_rtt(), Some(Duration::from_millis(750)));
+
+            // Rejects are responses too
+            let second = controller.prepare(100);
+            clock.advance(Duration::from_millis(350));
+            controller.reject(
+                second,
+                100,
+                &interledger_packet::RejectBuilder {
+                    code: ErrorCode::F99_APPLICATION_ERROR,
+                    message: &[],
+                    triggered_by: None,
+                    data: &[],
+                }
+                .build(),
+            );
+            assert_eq!(controller.min_rtt(), Some(Duration::from_millis(350)));
+        }
+    }
+
+    mod paced_slow_start {
+        use super::*;
+
+        #[test]
+        fn ramps_authorized_window_across_a_round_trip() {
+            let clock = MockClock::new();
+            let mut controller = CongestionController::new(1000, 100, 2.0)
+                .with_clock(clock.clone())
+                .with_paced_slow_start();
+            controller.record_rtt_sample(Duration::from_secs(1));
+
+            let sequence = controller.prepare(1000);
+            clock.advance(Duration::from_secs(1));
+            controller.fulfill(sequence, 1000);
+            assert_eq!(controller.max_in_flight, 2000);
+            assert_eq!(controller.smoothed_rtt(), Some(Duration::from_secs(1)));
+
+            // Instead of stepping to 2000 at the fulfill, the window ramps up over the RTT
+            let mut authorized = Vec::new();
+            for _ in 0..5 {
+                authorized.push(controller.get_amount_left_in_window());
+                clock.advance(Duration::from_millis(250));
+            }
+            assert_eq!(authorized, vec![1000, 1250, 1500, 1750, 2000]);
+            clock.advance(Duration::from_secs(1));
+            assert_eq!(controller.get_amount_left_in_window(), 2000);
+        }
+
+        #[test]
+        fn overlapping_increases_continue_from_the_current_ramp() {
+            let clock = MockClock::new();
+            let mut controller = CongestionController::new(1000, 100, 2.0)
+                .with_clock(clock.clone())
+                .with_paced_slow_start();
+            controller.record_rtt_sample(Duration::from_secs(1));
+
+            controller.fulfill(0, 0);
+            clock.advance(Duration::from_millis(500));
+            assert_eq!(controller.authorized_window(), 1500);
+            // The next doubling ramps from 1500 to 4000
+            controller.fulfill(0, 0);
+            assert_eq!(controller.authorized_window(), 1500);
+            clock.advance(Duration::from_millis(500));
+            assert_eq!(controller.authorized_window(), 2750);
+        }
+
+        #[test]
+        fn steps_without_pacing_or_rtt_estimate() {
+            let clock = MockClock::new();
+            let mut unpaced = CongestionController::new(1000, 100, 2.0).with_clock(clock.clone());
+            unpaced.record_rtt_sample(Duration::from_secs(1));
+            unpaced.fulfill(0, 0);
+            assert_eq!(unpaced.get_amount_left_in_window(), 2000);
+
+            let mut no_rtt = CongestionController::new(1000, 100, 2.0)
+                .with_clock(clock)
+                .with_paced_slow_start();
+            no_rtt.fulfill(0, 0);
+            assert_eq!(no_rtt.get_amount_left_in_window(), 2000);
+        }
+    }
+
+    mod strategy_names {
+        use super::*;
+
+        #[test]
+        fn reports_configured_strategies() {
+            let mut controller = CongestionController::new(1000, 100, 2.0);
+            assert_eq!(controller.active_growth_strategy(), "slow start");
+            assert_eq!(
+                controller.active_decrease_strategy(),
+                "multiplicative decrease"
+            );
+            controller.state = CongestionState::AvoidCongestion;
+            assert_eq!(controller.active_growth_strategy(), "additive increase");
+
+            let mut paced = CongestionController::new(1000, 100, 2.0).with_paced_slow_start();
+            assert_eq!(paced.active_growth_strategy(), "paced slow start");
+            // Pacing only applies to slow start
+            paced.state = CongestionState::AvoidCongestion;
+            assert_eq!(paced.active_growth_strategy(), "additive increase");
+
+            let mut scaled = CongestionController::new(1000, 100, 2.0)
+                .with_paced_slow_start()
+                .with_volatility_scaling(1.0)
+                .with_window_bounds(100, u64::MAX)
+                .unwrap();
+            assert_eq!(
+                scaled.active_growth_strategy(),
+                "volatility-scaled paced slow start"
+            );
+            assert_eq!(
+                scaled.active_decrease_strategy(),
+                "volatility-scaled multiplicative decrease with floor"
+            );
+            scaled.state = CongestionState::AvoidCongestion;
+            assert_eq!(
+                scaled.active_growth_strategy(),
+                "volatility-scaled additive increase"
+            );
+
+            let floored = CongestionController::new(1000, 100, 2.0)
+                .with_window_bounds(100, 5000)
+                .unwrap();
+            assert_eq!(
+                floored.active_decrease_strategy(),
+                "multiplicative decrease with floor"
+            );
+        }
+    }
+
+    mod receiver_window {
+        use super::*;
+
+        #[test]
+        fn small_receiver_window_binds_first() {
+            let mut controller = CongestionController::new(10_000, 100, 2.0);
+            controller.set_receiver_max_in_flight(Some(3000));
+            assert_eq!(controller.get_amount_left_in_window(), 3000);
+
+            let sequence = controller.prepare(2000);
+            assert_eq!(controller.get_amount_left_in_window(), 1000);
+            controller.prepare(1000);
+            assert_eq!(controller.get_amount_left_in_window(), 0);
+
+            // Congestion control keeps growing its own window meanwhile
+            controller.fulfill(sequence, 2000);
+            assert_eq!(controller.max_in_flight, 20_000);
+            assert_eq!(controller.get_amount_left_in_window(), 2000);
+
+            controller.set_receiver_max_in_flight(None);
+            assert_eq!(controller.get_amount_left_in_window(), 19_000);
+        }
+
+        #[test]
+        fn congestion_window_binds_when_smaller() {
+            let mut controller = CongestionController::new(1000, 100, 2.0);
+            controller.set_receiver_max_in_flight(Some(5000));
+            controller.prepare(400);
+            assert_eq!(controller.get_amount_left_in_window(), 600);
+        }
+    }
+
+    mod recent_outcomes {
+        use super::*;
+
+        #[test]
+        fn packs_outcomes_newest_first() {
+            let mut controller = CongestionController::new(1000, 100, 2.0);
+            assert_eq!(controller.recent_outcome_count(), 0);
+
+            // Fulfill, fulfill, reject, fulfill, reject, reject, fulfill
+            for fulfilled in [true, true, false, true, false, false, true] {
+                if fulfilled {
+                    controller.fulfill(0, 0);
+                } else {
+                    insufficient_liquidity(&mut controller);
+                }
+            }
+            assert_eq!(controller.recent_outcomes(), 0b1101001);
+            assert_eq!(controller.recent_outcome_count(), 7);
+        }
+
+        #[test]
+        fn slides_over_the_last_64_packets() {
+            let mut controller = CongestionController::new(1000, 100, 2.0);
+            for _ in 0..10 {
+                insufficient_liquidity(&mut controller);
+            }
+            for _ in 0..63 {
+                controller.fulfill(0, 0);
+            }
+            insufficient_liquidity(&mut controller);
+            assert_eq!(controller.recent_outcomes(), u64::MAX << 1);
+            assert_eq!(controller.recent_outcome_count(), 64);
+        }
+    }
+
+    mod draining {
+        use super::*;
+        use interledger_packet::RejectBuilder;
+
+        #[test]
+        fn resolves_outstanding_packets_without_authorizing_new_ones() {
+            let mut controller = CongestionController::new(1000, 100, 2.0);
+            let first = controller.prepare(300);
+            let second = controller.prepare(200);
+            assert!(!controller.is_drained());
+
+            controller.begin_drain();
+            assert_eq!(controller.get_amount_left_in_window(), 0);
+            assert!(!controller.is_drained());
+
+            // New prepares aren't accepted
+            controller.prepare(100);
+            assert_eq!(controller.amount_in_flight, 500);
+
+            controller.fulfill(first, 300);
+            assert_eq!(controller.amount_in_flight, 200);
+            assert_eq!(controller.max_in_flight, 1000);
+            assert_eq!(controller.get_amount_left_in_window(), 0);
+            assert!(!controller.is_drained());
+
+            controller.reject(
+                second,
+                200,
+                &RejectBuilder {
+                    code: ErrorCode::T04_INSUFFICIENT_LIQUIDITY,
+                    message: &[],
+                    triggered_by: None,
+                    data: &[],
+                }
+                .build(),
+            );
+            assert_eq!(controller.amount_in_flight, 0);
+            assert_eq!(controller.get_amount_left_in_window(), 0);
+            assert!(controller.state == CongestionState::Draining);
+            assert!(controller.is_drained());
+        }
+
+        #[test]
+        fn resolving_an_ignored_prepare_releases_nothing() {
+            let mut controller = CongestionController::new(1000, 100, 2.0);
+            let sent = controller.prepare(100);
+            controller.begin_drain();
+
+            // The caller sends the packet anyway
+            let ignored = controller.prepare(50);
+            controller.fulfill(ignored, 50);
+            assert_eq!(controller.amount_in_flight, 100);
+
+            controller.fulfill(sent, 100);
+            assert_eq!(controller.amount_in_flight, 0);
+            assert!(controller.is_drained());
+        }
+
+        #[test]
+        fn idle_controller_drains_immediately() {
+            let mut controller = CongestionController::new(1000, 100, 2.0);
+            assert!(!controller.is_drained());
+            controller.begin_drain();
+            assert!(controller.is_drained());
+        }
+    }
+
+    mod estimators {
+        use super::*;
+
+        /// Fulfills one packet per second, at the given delivery rates
+        fn deliver(clock: &MockClock, controller: &mut CongestionController, rates: &[u64]) {
+            for &rate in rates {
+                let sequence = controller.prepare(rate);
+                clock.advance(Duration::from_secs(1));
+                controller.fulfill(sequence, rate);
+            }
+        }
+
+        #[test]
+        fn controller_uses_configured_estimators() {
+            let ewma_clock = MockClock::new();
+            let mut ewma = CongestionController::new(u64::MAX / 2, 100, 2.0)
+                .with_clock(ewma_clock.clone())
+                .with_rtt_estimator(Box::new(Ewma::new(0.5)))
+                .with_rate_estimator(Box::new(Ewma::new(0.5)));
+            let windowed_clock = MockClock::new();
+            let mut windowed = CongestionController::new(u64::MAX / 2, 100, 2.0)
+                .with_clock(windowed_clock.clone())
+                .with_rtt_estimator(Box::new(WindowedAverage::new(4)))
+                .with_rate_estimator(Box::new(WindowedAverage::new(4)));
+
+            // The first fulfill only starts the clock for rate samples
+            let rates = [0, 100, 100, 100, 100, 500, 500];
+            deliver(&ewma_clock, &mut ewma, &rates);
+            deliver(&windowed_clock, &mut windowed, &rates);
+            // Round trips all took a second
+            assert_eq!(ewma.smoothed_rtt(), Some(Duration::from_secs(1)));
+            assert_eq!(windowed.smoothed_rtt(), Some(Duration::from_secs(1)));
+
+            // After the same step in rates, the EWMA moved 3/4 of the way
+            // while the window still holds half of the old samples
+            assert_eq!(ewma.delivery_rate(), Some(400.0));
+            assert_eq!(windowed.delivery_rate(), Some(300.0));
+
+            deliver(&ewma_clock, &mut ewma, &[500, 500]);
+            deliver(&windowed_clock, &mut windowed, &[500, 500]);
+            assert_eq!(ewma.delivery_rate(), Some(475.0));
+            assert_eq!(windowed.delivery_rate(), Some(500.0));
+        }
+    }
+
+    mod bufferbloat {
+        use super::*;
+
+        /// Sends one packet at a time, so a packet of `amount` delivered over `rtt_ms`
+        /// yields a rate sample of `amount / rtt`
+        fn deliver(
+            clock: &MockClock,
+            controller: &mut CongestionController,
+            rtt_ms: u64,
+            amount: u64,
+        ) {
+            let sequence = controller.prepare(amount);
+            clock.advance(Duration::from_millis(rtt_ms));
+            controller.fulfill(sequence, amount);
+        }
+
+        fn controller(clock: &MockClock) -> CongestionController {
+            CongestionController::new(u64::MAX / 2, 100, 2.0).with_clock(clock.clone())
+        }
+
+        #[test]
+        fn rising_rtt_with_flat_rate() {
+            let clock = MockClock::new();
+            let mut controller = controller(&clock);
+            for step in 0..=20 {
+                let rtt_ms = 100 + step * 20;
+                // The window grows in step with the RTT, but the rate stays at 1000/s
+                deliver(&clock, &mut controller, rtt_ms, rtt_ms);
+                if step < 5 {
+                    assert!(!controller.bufferbloat_detected(), "step {}", step);
+                }
+            }
+            assert!(controller.delivery_rate().unwrap() < 1001.0);
+            assert!(controller.bufferbloat_detected());
+        }
+
+        #[test]
+        fn stable_rtt_with_growing_rate() {
+            let clock = MockClock::new();
+            let mut controller = controller(&clock);
+            for step in 0..=20 {
+                deliver(&clock, &mut controller, 100, 100 + step * 50);
+            }
+            assert!(!controller.bufferbloat_detected());
+        }
+
+        #[test]
+        fn rising_rtt_with_growing_rate() {
+            let clock = MockClock::new();
+            let mut controller = controller(&clock);
+            for step in 0..=20 {
+                let rtt_ms = 100 + step * 20;
+                // Throughput grows faster than the RTT, so the path isn't just queueing
+                deliver(&clock, &mut controller, rtt_ms, rtt_ms * rtt_ms / 100);
+            }
+            assert!(controller.smoothed_rtt().unwrap() >= Duration::from_millis(150));
+            assert!(!controller.bufferbloat_detected());
+        }
+    }
+
+    mod state_machine {
+        use super::*;
+
+        /// Deterministic xorshift generator, so a failing sequence can be replayed
+        struct XorShift(u64);
+
+        impl XorShift {
+            fn next(&mut self) -> u64 {
+                self.0 ^= self.0 << 13;
+                self.0 ^= self.0 >> 7;
+                self.0 ^= self.0 << 17;
+                self.0
+            }
+        }
+
+        #[test]
+        fn starts_in_slow_start() {
+            let controller = CongestionController::new(1000, 10, 2.0);
+            assert_eq!(controller.current_state(), CongestionState::SlowStart);
+        }
+
+        #[test]
+        fn draining_is_terminal() {
+            assert!(valid_transitions(CongestionState::Draining).is_empty());
+            for state in &[CongestionState::SlowStart, CongestionState::AvoidCongestion] {
+                assert!(valid_transitions(*state).contains(&CongestionState::Draining));
+                assert!(!valid_transitions(*state).contains(state));
+            }
+        }
+
+        #[test]
+        fn random_sequence_only_makes_legal_transitions() {
+            let mut rng = XorShift(0x2545_f491_4f6c_dd1d);
+            let mut controller = CongestionController::new(1000, 100, 2.0)
+                .with_hysteresis(Hysteresis {
+                    congestion_signals: 2,
+                    signal_window: 4,
+                    clean_round_trips: 2,
+                })
+                .unwrap();
+            let temporary = reject_packet(ErrorCode::T04_INSUFFICIENT_LIQUIDITY);
+            let final_error = reject_packet(ErrorCode::F99_APPLICATION_ERROR);
+            let mut in_flight = Vec::new();
+            let mut seen = vec![controller.current_state()];
+
+            for step in 0..10_000 {
+                let before = controller.current_state();
+                match rng.next() % 10 {
+                    // A draining connection doesn't send new packets
+                    0..=3 if before != CongestionState::Draining => {
+                        let amount = 1 + rng.next() % 500;
+                        in_flight.push((controller.prepare(amount), amount));
+                    }
+                    4..=6 if !in_flight.is_empty() => {
+                        let (sequence, amount) =
+                            in_flight.swap_remove(rng.next() as usize % in_flight.len());
+                        controller.fulfill(sequence, amount);
+                    }
+                    7 | 8 if !in_flight.is_empty() => {
+                        let (sequence, amount) =
+                            in_flight.swap_remove(rng.next() as usize % in_flight.len());
+                        let reject = if rng.next() & 1 == 0 {
+                            &temporary
+                        } else {
+                            &final_error
+                        };
+                        controller.reject(sequence, amount, reject);
+                    }
+                    9 if step > 9_000 => controller.begin_drain(),
+                    _ => {}
+                }
+                let after = controller.current_state();
+                assert!(
+                    after == before || valid_transitions(before).contains(&after),
+                    "step {}: illegal transition from {:?} to {:?}",
+                    step,
+                    before,
+                    after
+                );
+                if !seen.contains(&after) {
+                    seen.push(after);
+                }
+            }
+
+            // Every state is one the controller actually enters
+            assert_eq!(seen.len(), 3);
+            assert_eq!(controller.current_state(), CongestionState::Draining);
+        }
+    }
+
+    mod credit_debit {
+        use super::*;
+        use tracing_test::traced_test;
+
+        #[test]
+        fn corrects_amount_in_flight() {
+            let mut controller = CongestionController::new(1000, 10, 2.0);
+            controller.prepare(600);
+            controller.credit(200);
+            assert_eq!(controller.amount_in_flight, 400);
+            assert_eq!(controller.get_amount_left_in_window(), 600);
+
+            controller.debit(300);
+            assert_eq!(controller.amount_in_flight, 700);
+            assert_eq!(controller.get_amount_left_in_window(), 300);
+            // Corrections aren't congestion signals
+            assert_eq!(controller.authorized_window(), 1000);
+        }
+
+        #[test]
+        fn saturates() {
+            let mut controller = CongestionController::new(1000, 10, 2.0);
+            controller.prepare(100);
+            controller.credit(500);
+            assert_eq!(controller.amount_in_flight, 0);
+
+            controller.debit(u64::MAX);
+            controller.debit(1);
+            assert_eq!(controller.amount_in_flight, u64::MAX);
+            assert_eq!(controller.get_amount_left_in_window(), 0);
+        }
+
+        #[test]
+        #[traced_test]
+        fn resolving_packets_after_a_credit() {
+            let mut controller = CongestionController::new(1000, 10, 2.0);
+            let rejected = controller.prepare(100);
+            let fulfilled = controller.prepare(100);
+            controller.reject(
+                rejected,
+                100,
+                &RejectBuilder {
+                    code: ErrorCode::T04_INSUFFICIENT_LIQUIDITY,
+                    message: &[],
+                    triggered_by: None,
+                    data: &[],
+                }
+                .build(),
+            );
+            // The rejected packet's value is also credited back out of band
+            controller.credit(100);
+            controller.fulfill(fulfilled, 100);
+            assert_eq!(controller.amount_in_flight, 0);
+
+            let clock = MockClock::new();
+            let mut controller = CongestionController::new(1000, 10, 2.0)
+                .with_clock(clock.clone())
+                .with_max_packet_age(Duration::from_secs(30));
+            controller.prepare(100);
+            controller.credit(100);
+            clock.advance(Duration::from_secs(30));
+            assert_eq!(controller.reap(clock.now()), 100);
+            assert_eq!(controller.amount_in_flight, 0);
+            assert!(!logs_contain("resolved twice"));
+        }
+
+        #[test]
+        #[traced_test]
+        fn logs_packets_resolved_twice() {
+            let mut controller = CongestionController::new(1000, 10, 2.0);
+            let first = controller.prepare(100);
+            let second = controller.prepare(100);
+            controller.credit(50);
+            controller.fulfill(first, 100);
+            controller.fulfill(second, 100);
+            assert!(!logs_contain("resolved twice"));
+            assert_eq!(controller.amount_in_flight, 0);
+
+            // The credit is used up, so the same shortfall now means a double fulfill
+            let sequence = controller.prepare(100);
+            controller.fulfill(sequence, 100);
+            controller.fulfill(sequence, 100);
+            assert!(logs_contain(
+                "Released 100 with only 0 in flight, was a packet resolved twice?"
+            ));
+            assert_eq!(controller.amount_in_flight, 0);
+        }
+    }
+
+    mod next_sequence {
+        use super::*;
+        use tracing_test::traced_test;
+
+        #[test]
+        fn increments_once_per_prepare() {
+            let mut controller = CongestionController::new(1000, 10, 2.0);
+            assert_eq!(controller.next_sequence(), 1);
+
+            let sequence = controller.prepare(100);
+            assert_eq!(controller.next_sequence(), 2);
+            controller.prepare(0);
+            assert_eq!(controller.next_sequence(), 3);
+
+            // Resolving packets doesn't use up an index
+            controller.fulfill(sequence, 100);
+            controller.fulfill(0, 0);
+            assert_eq!(controller.next_sequence(), 3);
+
+            controller.prepare(50);
+            assert_eq!(controller.next_sequence(), 4);
+        }
+
+        #[test]
+        fn ignored_prepares_take_an_index() {
+            let mut controller = CongestionController::new(1000, 10, 2.0);
+            controller.prepare(100);
+            controller.begin_drain();
+            assert_eq!(controller.prepare(100), 2);
+            assert_eq!(controller.next_sequence(), 3);
+        }
+
+        #[test]
+        fn attributes_round_trips_to_packets_of_equal_size() {
+            let clock = MockClock::new();
+            let mut controller = CongestionController::new(1000, 10, 2.0).with_clock(clock.clone());
+            controller.prepare(100);
+            clock.advance(Duration::from_secs(4));
+            let second = controller.prepare(100);
+            clock.advance(Duration::from_secs(1));
+
+            controller.fulfill(second, 100);
+            assert_eq!(controller.smoothed_rtt(), Some(Duration::from_secs(1)));
+            assert_eq!(controller.tracked_packet_count(), 1);
+        }
+
+        #[test]
+        #[traced_test]
+        fn late_fulfill_of_a_reaped_packet_leaves_equal_packets_alone() {
+            let clock = MockClock::new();
+            let mut controller = CongestionController::new(1000, 10, 2.0)
+                .with_clock(clock.clone())
+                .with_max_packet_age(Duration::from_secs(30));
+            let reaped = controller.prepare(100);
+            clock.advance(Duration::from_secs(30));
+            assert_eq!(controller.reap(clock.now()), 100);
+            let live = controller.prepare(100);
+
+            controller.fulfill(reaped, 100);
+            assert_eq!(controller.amount_in_flight, 100);
+            assert_eq!(controller.tracked_packet_count(), 1);
+
+            controller.fulfill(live, 100);
+            assert_eq!(controller.amount_in_flight, 0);
+            assert!(!logs_contain("resolved twice"));
+        }
+    }
+
+    mod prewarm {
+        use super::*;
+
+        #[test]
+        fn seeds_from_closed_connections() {
+            let registry = PeerStatsRegistry::default();
+            for window in &[3000, 5000, 7000] {
+                let mut controller = CongestionController::new(1000, 100, 2.0);
+                controller.max_in_flight = *window;
+                controller.max_packet_amount = Some(window / 10);
+                controller.record_final_stats(&registry, "alice");
+            }
+
+            let controller =
+                CongestionController::new(1000, 100, 2.0).prewarm_from(&registry, "alice");
+            assert_eq!(controller.authorized_window(), 5000);
+            assert_eq!(controller.get_max_packet_amount(), 500);
+            assert_eq!(controller.active_growth_strategy(), "slow start");
+        }
+
+        #[test]
+        fn unknown_peer_starts_from_scratch() {
+            let registry = PeerStatsRegistry::default();
+            CongestionController::new(9000, 100, 2.0).record_final_stats(&registry, "alice");
+
+            let controller =
+                CongestionController::new(1000, 100, 2.0).prewarm_from(&registry, "bob");
+            assert_eq!(controller.authorized_window(), 1000);
+            assert_eq!(controller.get_max_packet_amount(), u64::MAX);
+        }
+
+        #[test]
+        fn respects_window_bounds() {
+            let registry = PeerStatsRegistry::default();
+            CongestionController::new(9000, 100, 2.0).record_final_stats(&registry, "alice");
+
+            let controller = CongestionController::new(1000, 100, 2.0)
+                .with_window_bounds(0, 4000)
+                .unwrap()
+                .prewarm_from(&registry, "alice");
+            assert_eq!(controller.authorized_window(), 4000);
+        }
+    }
+
+    mod time_at_floor {
+        use super::*;
+
+        #[test]
+        fn accumulates_while_near_the_floor() {
+            let clock = MockClock::new();
+            let mut controller = CongestionController::new(4000, 100, 2.0)
+                .with_clock(clock.clone())
+                .with_window_bounds(1000, u64::MAX)
+                .unwrap();
+            let reject = RejectBuilder {
+                code: ErrorCode::T04_INSUFFICIENT_LIQUIDITY,
+                message: &[],
+                triggered_by: None,
+                data: &[],
+            }
+            .build();
+
+            clock.advance(Duration::from_secs(1));
+            let sequence = controller.prepare(1);
+            controller.reject(sequence, 1, &reject);
+            assert_eq!(controller.authorized_window(), 2000);
+            assert_eq!(controller.time_at_floor(), Duration::from_secs(0));
+
+            clock.advance(Duration::from_secs(1));
+            let sequence = controller.prepare(1);
+            controller.reject(sequence, 1, &reject);
+            assert_eq!(controller.authorized_window(), 1000);
+
+            clock.advance(Duration::from_secs(5));
+            assert_eq!(controller.time_at_floor(), Duration::from_secs(5));
+
+            // One increase above the floor still counts
+            let sequence = controller.prepare(1);
+            controller.fulfill(sequence, 1);
+            assert_eq!(controller.authorized_window(), 1100);
+            clock.advance(Duration::from_secs(2));
+
+            let sequence = controller.prepare(1);
+            controller.fulfill(sequence, 1);
+            assert_eq!(controller.authorized_window(), 1200);
+            clock.advance(Duration::from_secs(3));
+            assert_eq!(controller.time_at_floor(), Duration::from_secs(7));
+
+            // Back at the floor, adding to the previous stint
+            let sequence = controller.prepare(1);
+            controller.reject(sequence, 1, &reject);
+            clock.advance(Duration::from_secs(4));
+            assert_eq!(controller.time_at_floor(), Duration::from_secs(11));
+        }
+    }
+
+    mod target_utilization {
+        use super::*;
+
+        #[test]
+        fn leaves_headroom_in_the_window() {
+            let mut controller = CongestionController::new(10_000, 100, 2.0)
+                .with_target_utilization(0.8)
+                .unwrap();
+            assert_eq!(controller.get_amount_left_in_window(), 8000);
+
+            let sequence = controller.prepare(5000);
+            assert_eq!(controller.get_amount_left_in_window(), 3000);
+            controller.prepare(3000);
+            assert_eq!(controller.get_amount_left_in_window(), 0);
+            // The window itself is untouched, 20% of it stays unused
+            assert_eq!(controller.authorized_window(), 10_000);
+
+            controller.fulfill(sequence, 5000);
+            assert_eq!(controller.authorized_window(), 20_000);
+            assert_eq!(controller.get_amount_left_in_window(), 13_000);
+        }
+
+        #[test]
+        fn defaults_to_the_whole_window() {
+            let controller = CongestionController::new(10_000, 100, 2.0);
+            assert_eq!(controller.target_utilization(), 1.0);
+            assert_eq!(controller.get_amount_left_in_window(), 10_000);
+        }
+
+        #[test]
+        fn rejects_invalid_targets() {
+            for target in &[0.0, -0.5, 1.5, f64::NAN] {
+                let result =
+                    CongestionController::new(10_000, 100, 2.0).with_target_utilization(*target);
+                match result {
+                    Err(CongestionError::InvalidTargetUtilization(_)) => {}
+                    _ => panic!("target utilization {} should be rejected", target),
+                }
+            }
+        }
+    }
+
+    mod recommended_concurrency {
+        use super::*;
+
+        #[test]
+        fn divides_the_window_by_the_average_packet_size() {
+            let mut controller = CongestionController::new(10_000, 100, 2.0)
+                .with_window_bounds(0, 10_000)
+                .unwrap();
+            assert_eq!(controller.average_packet_size(), None);
+            assert_eq!(controller.recommended_concurrency(), 1);
+
+            for amount in &[200, 300, 400, 0] {
+                let sequence = controller.prepare(*amount);
+                controller.fulfill(sequence, *amount);
+            }
+            assert_eq!(controller.average_packet_size(), Some(300));
+            assert_eq!(controller.max_sendable(), 10_000);
+            assert_eq!(controller.recommended_concurrency(), 33);
+
+            controller.set_receiver_max_in_flight(Some(1500));
+            assert_eq!(controller.recommended_concurrency(), 5);
+            controller.set_receiver_max_in_flight(Some(100));
+            assert_eq!(controller.recommended_concurrency(), 1);
+        }
+
+        #[test]
+        fn zero_when_nothing_may_be_sent() {
+            let mut controller = CongestionController::new(10_000, 100, 2.0);
+            controller.prepare(500);
+            controller.begin_drain();
+            assert_eq!(controller.max_sendable(), 0);
+            assert_eq!(controller.recommended_concurrency(), 0);
+        }
+    }
+
+    mod f08_overflow {
+        use super::*;
+
+        #[test]
+        fn large_amount_received() {
+            let mut controller = CongestionController::new(u64::MAX, 100, 2.0);
+            let sequence = controller.prepare(u64::MAX - 1);
+            controller.reject(sequence, u64::MAX - 1, &amount_too_large(u64::MAX, 1));
+            assert_eq!(controller.get_max_packet_amount(), 0);
+
+            let mut controller = CongestionController::new(u64::MAX, 100, 2.0);
+            let sequence = controller.prepare(1 << 62);
+            controller.reject(sequence, 1 << 62, &amount_too_large(1 << 61, 1 << 60));
+            // Exact, even though the product is far beyond a u64
+            assert_eq!(controller.get_max_packet_amount(), 1 << 61);
+        }
+
+        #[test]
+        fn clamps_to_u64_max() {
+            let mut controller = CongestionController::new(u64::MAX, 100, 2.0);
+            let sequence = controller.prepare(u64::MAX / 2);
+            controller.reject(sequence, u64::MAX / 2, &amount_too_large(1, u64::MAX));
+            assert_eq!(controller.get_max_packet_amount(), u64::MAX);
+        }
+
+        #[test]
+        fn ignores_zero_amount_received() {
+            let mut controller = CongestionController::new(1000, 100, 2.0);
+            let sequence = controller.prepare(100);
+            controller.reject(sequence, 100, &amount_too_large(0, 50));
+            assert_eq!(controller.get_max_packet_amount(), u64::MAX);
+            assert_eq!(controller.amount_in_flight, 0);
+        }
+    }
+
+    mod stats {
+        use super::*;
+
+        #[test]
+        fn tracks_slow_start_into_congestion_avoidance() {
+            let mut controller = CongestionController::new(1000, 100, 2.0);
+            assert_eq!(
+                controller.stats(),
+                CongestionStats {
+                    max_in_flight: 1000,
+                    amount_in_flight: 0,
+                    state: CongestionState::SlowStart,
+                    max_packet_amount: None,
+                }
+            );
+
+            let sequence = controller.prepare(600);
+            assert_eq!(controller.stats().amount_in_flight, 600);
+            controller.fulfill(sequence, 600);
+            let stats = controller.stats();
+            assert_eq!(stats.max_in_flight, 2000);
+            assert_eq!(stats.state, CongestionState::SlowStart);
+
+            let sequence = controller.prepare(500);
+            controller.reject(
+                sequence,
+                500,
+                &RejectBuilder {
+                    code: ErrorCode::F08_AMOUNT_TOO_LARGE,
+                    message: &[],
+                    triggered_by: None,
+                    data: &MaxPacketAmountDetails::new(500, 300).to_bytes(),
+                }
+                .build(),
+            );
+            assert_eq!(controller.stats().max_packet_amount, Some(300));
+            assert_eq!(controller.stats().state, CongestionState::SlowStart);
+
+            let sequence = controller.prepare(300);
+            controller.reject(
+                sequence,
+                300,
+                &RejectBuilder {
+                    code: ErrorCode::T04_INSUFFICIENT_LIQUIDITY,
+                    message: &[],
+                    triggered_by: None,
+                    data: &[],
+                }
+                .build(),
+            );
+            assert_eq!(
+                controller.stats(),
+                CongestionStats {
+                    max_in_flight: 1000,
+                    amount_in_flight: 0,
+                    state: CongestionState::AvoidCongestion,
+                    max_packet_amount: Some(300),
+                }
+            );
+
+            let sequence = controller.prepare(300);
+            controller.fulfill(sequence, 300);
+            assert_eq!(controller.stats().max_in_flight, 1100);
+            assert_eq!(controller.stats().state, CongestionState::AvoidCongestion);
+        }
+    }
+
+    mod ssthresh {
+        use super::*;
+
+        fn fulfill_window(controller: &mut CongestionController) -> u64 {
+            let amount = controller.get_amount_left_in_window();
+            let sequence = controller.prepare(amount);
+            controller.fulfill(sequence, amount);
+            controller.authorized_window()
+        }
+
+        #[test]
+        fn switches_to_additive_increase_past_threshold() {
+            let mut controller = CongestionController::new(1000, 100, 2.0).with_ssthresh(4000);
+            assert_eq!(fulfill_window(&mut controller), 2000);
+            assert_eq!(controller.current_state(), CongestionState::SlowStart);
+            assert_eq!(fulfill_window(&mut controller), 4000);
+            assert_eq!(controller.current_state(), CongestionState::AvoidCongestion);
+            assert_eq!(fulfill_window(&mut controller), 4100);
+            assert_eq!(fulfill_window(&mut controller), 4200);
+        }
+
+        #[test]
+        fn keeps_doubling_without_threshold() {
+            let mut controller = CongestionController::new(1000, 100, 2.0);
+            for expected in &[2000, 4000, 8000, 16000] {
+                assert_eq!(fulfill_window(&mut controller), *expected);
+            }
+            assert_eq!(controller.current_state(), CongestionState::SlowStart);
+        }
+    }
+
+    mod idle_reset {
+        use super::*;
+
+        fn grown_controller() -> CongestionController {
+            let mut controller = CongestionController::new(1000, 100, 2.0)
+                .with_idle_threshold(Duration::from_secs(10));
+            for _ in 0..3 {
+                let amount = controller.get_amount_left_in_window();
+                let sequence = controller.prepare(amount);
+                controller.fulfill(sequence, amount);
+            }
+            controller.state = CongestionState::AvoidCongestion;
+            assert_eq!(controller.authorized_window(), 8000);
+            controller
+        }
+
+        #[test]
+        fn long_idle_resets_window() {
+            let mut controller = grown_controller();
+            controller.on_idle(Duration::from_secs(30));
+            assert_eq!(controller.authorized_window(), 1000);
+            assert_eq!(controller.current_state(), CongestionState::SlowStart);
+        }
+
+        #[test]
+        fn short_idle_leaves_window() {
+            let mut controller = grown_controller();
+            controller.on_idle(Duration::from_secs(5));
+            assert_eq!(controller.authorized_window(), 8000);
+            assert_eq!(controller.current_state(), CongestionState::AvoidCongestion);
+        }
+
+        #[test]
+        fn ignored_without_threshold() {
+            let mut controller = CongestionController::new(1000, 100, 2.0);
+            controller.max_in_flight = 8000;
+            controller.on_idle(Duration::from_secs(3600));
+            assert_eq!(controller.authorized_window(), 8000);
+        }
+
+        #[test]
+        fn draining_controller_stays_draining() {
+            let mut controller = grown_controller();
+            controller.begin_drain();
+            controller.on_idle(Duration::from_secs(30));
+            assert_eq!(controller.current_state(), CongestionState::Draining);
+        }
+    }
+
+    mod pacing_interval {
+        use super::*;
+
+        #[test]
+        fn ewma_converges() {
+            let mut controller = CongestionController::new(1000, 100, 2.0);
+            controller.record_rtt_sample(Duration::from_millis(800));
+            for _ in 0..60 {
+                controller.record_rtt_sample(Duration::from_millis(200));
+            }
+            let smoothed = controller.smoothed_rtt().unwrap();
+            assert!(
+                smoothed >= Duration::from_millis(200) && smoothed < Duration::from_millis(201)
+            );
+        }
+
+        #[test]
+        fn spreads_window_over_round_trip() {
+            let mut controller = CongestionController::new(1000, 100, 2.0)
+                .with_window_bounds(0, 1000)
+                .unwrap();
+            assert_eq!(controller.pacing_interval(), None);
+
+            controller.record_rtt_sample(Duration::from_millis(100));
+            // Nothing is known about packet sizes yet, so the whole round trip
+            assert_eq!(
+                controller.pacing_interval(),
+                Some(Duration::from_millis(100))
+            );
+
+            let sequence = controller.prepare(250);
+            controller.fulfill(sequence, 250);
+            // Four packets of 250 fit in the window
+            assert_eq!(controller.recommended_concurrency(), 4);
+            let rtt = controller.smoothed_rtt().unwrap();
+            assert_eq!(controller.pacing_interval(), Some(rtt / 4));
+        }
+
+        #[test]
+        fn rate_limit_delay_takes_precedence() {
+            let mut controller = CongestionController::new(1000, 100, 2.0);
+            controller.pacing_delay = Some(Duration::from_secs(1));
+            assert_eq!(controller.pacing_interval(), Some(Duration::from_secs(1)));
+            controller.record_rtt_sample(Duration::from_millis(100));
+            assert_eq!(controller.pacing_interval(), Some(Duration::from_secs(1)));
+        }
+    }
+}
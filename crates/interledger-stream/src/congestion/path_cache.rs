@@ -0,0 +1,174 @@
+use super::tracking::BoundedQueue;
+use interledger_packet::Address;
+use parking_lot::RwLock;
+use std::cmp::min;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Number of prefixes whose limit is remembered
+const DEFAULT_MAX_PREFIXES: usize = 1024;
+
+/// Max packet amounts learned from F08 rejects, shared between connections and
+/// keyed by ILP address prefix.
+///
+/// F08 limits usually come from a connector serving a whole segment of the address space,
+/// so a limit learned while sending to `g.us.bank.alice` is stored under `g.us.bank` and
+/// also applies to `g.us.bank.bob`. Limits are never stored under a bare scheme like `g`,
+/// which would cover the whole address space.
+#[derive(Debug, Clone)]
+pub struct PathCache {
+    limits: Arc<RwLock<Limits>>,
+}
+
+#[derive(Debug)]
+struct Limits {
+    by_prefix: HashMap<String, u64>,
+    /// Prefixes from the least to the most recently updated
+    updated: BoundedQueue<String>,
+}
+
+impl Default for PathCache {
+    fn default() -> Self {
+        PathCache::with_max_prefixes(DEFAULT_MAX_PREFIXES)
+    }
+}
+
+impl PathCache {
+    pub fn new() -> Self {
+        PathCache::default()
+    }
+
+    /// Remembers the limits of at most `max_prefixes` prefixes, forgetting the least
+    /// recently updated one to make room for a new one
+    pub fn with_max_prefixes(max_prefixes: usize) -> Self {
+        PathCache {
+            limits: Arc::new(RwLock::new(Limits {
+                by_prefix: HashMap::new(),
+                updated: BoundedQueue::new(max_prefixes),
+            })),
+        }
+    }
+
+    /// Records the max packet amount for the prefix the destination belongs to
+    /// (every segment except the last). Keeps the lower limit if one is already known.
+    /// Destinations with fewer than three segments are ignored.
+    pub fn insert(&self, destination: &Address, max_packet_amount: u64) {
+        let prefix = match parent_prefix(destination) {
+            Some(prefix) => prefix,
+            None => return,
+        };
+        let mut limits = self.limits.write();
+        let limits = &mut *limits;
+        limits
+            .by_prefix
+            .entry(prefix.clone())
+            .and_modify(|limit| *limit = min(*limit, max_packet_amount))
+            .or_insert(max_packet_amount);
+        limits.updated.remove_first(|updated| *updated == prefix);
+        if let Some(evicted) = limits.updated.push(prefix) {
+            limits.by_prefix.remove(&evicted);
+        }
+    }
+
+    /// Returns the limit stored under the longest prefix covering the destination, if any
+    pub fn get(&self, destination: &Address) -> Option<u64> {
+        let segments: Vec<&str> = destination.segments().collect();
+        let limits = self.limits.read();
+        (1..=segments.len())
+            .rev()
+            .find_map(|len| limits.by_prefix.get(&segments[..len].join(".")).copied())
+    }
+
+    /// Number of prefixes with a known limit
+    pub fn len(&self) -> usize {
+        self.limits.read().by_prefix.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// The destination without its last segment, unless that would only leave the scheme
+fn parent_prefix(destination: &Address) -> Option<String> {
+    let segments: Vec<&str> = destination.segments().collect();
+    if segments.len() < 3 {
+        return None;
+    }
+    Some(segments[..segments.len() - 1].join("."))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn stores_limits_under_parent_prefix() {
+        let cache = PathCache::new();
+        cache.insert(&Address::from_str("g.us.bank.alice").unwrap(), 500);
+        assert_eq!(cache.len(), 1);
+
+        assert_eq!(
+            cache.get(&Address::from_str("g.us.bank.bob").unwrap()),
+            Some(500)
+        );
+        assert_eq!(
+            cache.get(&Address::from_str("g.us.bank.bob.savings").unwrap()),
+            Some(500)
+        );
+        assert_eq!(
+            cache.get(&Address::from_str("g.us.other.carol").unwrap()),
+            None
+        );
+    }
+
+    #[test]
+    fn prefers_longest_prefix_and_lowest_limit() {
+        let cache = PathCache::new();
+        cache.insert(&Address::from_str("g.us.bank").unwrap(), 1000);
+        cache.insert(&Address::from_str("g.us.bank.alice").unwrap(), 500);
+        cache.insert(&Address::from_str("g.us.bank.bob").unwrap(), 700);
+
+        assert_eq!(
+            cache.get(&Address::from_str("g.us.bank.dave").unwrap()),
+            Some(500)
+        );
+        assert_eq!(
+            cache.get(&Address::from_str("g.us.erin").unwrap()),
+            Some(1000)
+        );
+    }
+
+    #[test]
+    fn ignores_two_segment_destinations() {
+        let cache = PathCache::new();
+        cache.insert(&Address::from_str("g.alice").unwrap(), 500);
+        assert!(cache.is_empty());
+        assert_eq!(cache.get(&Address::from_str("g.bob").unwrap()), None);
+    }
+
+    #[test]
+    fn forgets_least_recently_updated_prefix() {
+        let cache = PathCache::with_max_prefixes(2);
+        cache.insert(&Address::from_str("g.us.bank.alice").unwrap(), 500);
+        cache.insert(&Address::from_str("g.eu.bank.bob").unwrap(), 600);
+        // Updating a prefix makes it the most recent one
+        cache.insert(&Address::from_str("g.us.bank.carol").unwrap(), 400);
+        cache.insert(&Address::from_str("g.asia.bank.dave").unwrap(), 700);
+        assert_eq!(cache.len(), 2);
+
+        assert_eq!(
+            cache.get(&Address::from_str("g.us.bank.alice").unwrap()),
+            Some(400)
+        );
+        assert_eq!(
+            cache.get(&Address::from_str("g.eu.bank.bob").unwrap()),
+            None
+        );
+        assert_eq!(
+            cache.get(&Address::from_str("g.asia.bank.dave").unwrap()),
+            Some(700)
+        );
+    }
+}
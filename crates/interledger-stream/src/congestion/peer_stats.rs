@@ -0,0 +1,122 @@
+use super::tracking::BoundedQueue;
+use interledger_packet::Amount;
+use parking_lot::RwLock;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Number of closed connections per peer the averages are taken over
+const DEFAULT_MAX_CONNECTIONS: usize = 16;
+
+/// Final windows and max packet amounts of recently closed connections, shared between
+/// connections and keyed by peer, so new connections to a known peer can start from what
+/// the previous ones learned instead of from scratch.
+#[derive(Debug, Clone)]
+pub struct PeerStatsRegistry {
+    peers: Arc<RwLock<HashMap<String, PeerStats>>>,
+    max_connections: usize,
+}
+
+#[derive(Debug)]
+struct PeerStats {
+    windows: BoundedQueue<u64>,
+    max_packet_amounts: BoundedQueue<u64>,
+}
+
+/// Averages over the recently closed connections to a peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerAverages {
+    /// Average final window
+    pub window: u64,
+    /// Average max packet amount, among the connections that learned one from an F08 reject
+    pub max_packet_amount: Option<u64>,
+    /// Number of connections averaged
+    pub connections: usize,
+}
+
+impl Default for PeerStatsRegistry {
+    fn default() -> Self {
+        PeerStatsRegistry::new(DEFAULT_MAX_CONNECTIONS)
+    }
+}
+
+impl PeerStatsRegistry {
+    /// Averages over the last `max_connections` closed connections to each peer
+    pub fn new(max_connections: usize) -> Self {
+        PeerStatsRegistry {
+            peers: Arc::new(RwLock::new(HashMap::new())),
+            max_connections,
+        }
+    }
+
+    /// Records the final figures of a closed connection to the peer, evicting the oldest
+    /// connection recorded for it once `max_connections` are kept
+    pub fn record(&self, peer_key: &str, window: u64, max_packet_amount: Option<u64>) {
+        let max_connections = self.max_connections;
+        let mut peers = self.peers.write();
+        let stats = peers
+            .entry(peer_key.to_string())
+            .or_insert_with(|| PeerStats {
+                windows: BoundedQueue::new(max_connections),
+                max_packet_amounts: BoundedQueue::new(max_connections),
+            });
+        stats.windows.push(window);
+        if let Some(max_packet_amount) = max_packet_amount {
+            stats.max_packet_amounts.push(max_packet_amount);
+        }
+    }
+
+    /// Averages over the recently closed connections to the peer, if any were recorded
+    pub fn averages(&self, peer_key: &str) -> Option<PeerAverages> {
+        let peers = self.peers.read();
+        let stats = peers.get(peer_key)?;
+        Some(PeerAverages {
+            window: average(&stats.windows)?,
+            max_packet_amount: average(&stats.max_packet_amounts),
+            connections: stats.windows.len(),
+        })
+    }
+
+    /// Number of peers with recorded connections
+    pub fn len(&self) -> usize {
+        self.peers.read().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+fn average(values: &BoundedQueue<u64>) -> Option<u64> {
+    if values.len() == 0 {
+        return None;
+    }
+    let sum: Amount = values.iter().copied().sum();
+    Some((sum.value() / values.len() as u128) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn averages_recent_connections_per_peer() {
+        let registry = PeerStatsRegistry::new(2);
+        registry.record("alice", 1000, None);
+        registry.record("alice", 2000, Some(500));
+        registry.record("alice", 4000, Some(700));
+        registry.record("bob", u64::MAX, None);
+        registry.record("bob", u64::MAX, None);
+
+        assert_eq!(
+            registry.averages("alice"),
+            Some(PeerAverages {
+                window: 3000,
+                max_packet_amount: Some(600),
+                connections: 2,
+            })
+        );
+        assert_eq!(registry.averages("bob").unwrap().window, u64::MAX);
+        assert_eq!(registry.averages("carol"), None);
+        assert_eq!(registry.len(), 2);
+    }
+}
@@ -0,0 +1,139 @@
+use super::CongestionControl;
+use interledger_packet::{ErrorCode, RejectBuilder};
+use std::time::Duration;
+
+/// How a recorded packet was resolved
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceOutcome {
+    Fulfill,
+    Reject(ErrorCode),
+}
+
+/// One packet of a recorded trace
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceEntry {
+    /// Amount of the packet
+    pub amount: u64,
+    /// Round trip time measured for the packet
+    pub rtt: Duration,
+    pub outcome: TraceOutcome,
+}
+
+/// What the controller did over a replayed trace
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct TraceResult {
+    /// Amount left in the window after each entry was resolved, which is the whole
+    /// window since the entries are replayed one at a time
+    pub windows: Vec<u64>,
+    pub fulfilled_packets: u64,
+    pub rejected_packets: u64,
+    pub amount_fulfilled: u64,
+    pub amount_rejected: u64,
+}
+
+impl TraceResult {
+    /// Window after the last entry, if the trace wasn't empty
+    pub fn final_window(&self) -> Option<u64> {
+        self.windows.last().copied()
+    }
+
+    /// Largest window reached over the trace
+    pub fn max_window(&self) -> Option<u64> {
+        self.windows.iter().copied().max()
+    }
+
+    /// Smallest window reached over the trace
+    pub fn min_window(&self) -> Option<u64> {
+        self.windows.iter().copied().min()
+    }
+}
+
+/// Drives the controller through a recorded trace, one packet at a time: each entry is
+/// prepared, its round trip time fed to [`record_rtt_sample`], and then fulfilled or rejected.
+///
+/// The amounts are replayed as recorded, even if they exceed the controller's current
+/// window or max packet amount, so the trajectory reflects the traffic that was really sent.
+/// The controller also times each packet with its own clock, which barely moves during
+/// a replay, so its round trip time estimate mixes the recorded samples with near-zero ones.
+///
+/// [`record_rtt_sample`]: trait.CongestionControl.html#method.record_rtt_sample
+pub fn replay_trace(trace: &[TraceEntry], controller: &mut dyn CongestionControl) -> TraceResult {
+    let mut result = TraceResult {
+        windows: Vec::with_capacity(trace.len()),
+        ..TraceResult::default()
+    };
+    for entry in trace {
+        let sequence = controller.prepare(entry.amount);
+        controller.record_rtt_sample(entry.rtt);
+        match entry.outcome {
+            TraceOutcome::Fulfill => {
+                controller.fulfill(sequence, entry.amount);
+                result.fulfilled_packets += 1;
+                result.amount_fulfilled = result.amount_fulfilled.saturating_add(entry.amount);
+            }
+            TraceOutcome::Reject(code) => {
+                let reject = RejectBuilder {
+                    code,
+                    message: &[],
+                    triggered_by: None,
+                    data: &[],
+                }
+                .build();
+                controller.reject(sequence, entry.amount, &reject);
+                result.rejected_packets += 1;
+                result.amount_rejected = result.amount_rejected.saturating_add(entry.amount);
+            }
+        }
+        result.windows.push(controller.get_amount_left_in_window());
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CongestionController;
+
+    fn entry(amount: u64, rtt_ms: u64, outcome: TraceOutcome) -> TraceEntry {
+        TraceEntry {
+            amount,
+            rtt: Duration::from_millis(rtt_ms),
+            outcome,
+        }
+    }
+
+    #[test]
+    fn replays_window_trajectory() {
+        let trace = [
+            entry(500, 120, TraceOutcome::Fulfill),
+            entry(1000, 110, TraceOutcome::Fulfill),
+            entry(
+                2000,
+                300,
+                TraceOutcome::Reject(ErrorCode::T04_INSUFFICIENT_LIQUIDITY),
+            ),
+            entry(1000, 150, TraceOutcome::Fulfill),
+            entry(1000, 140, TraceOutcome::Fulfill),
+        ];
+        let mut controller = CongestionController::new(1000, 100, 2.0);
+        let result = replay_trace(&trace, &mut controller);
+
+        assert_eq!(result.windows, vec![2000, 4000, 2000, 2100, 2200]);
+        assert_eq!(result.fulfilled_packets, 4);
+        assert_eq!(result.rejected_packets, 1);
+        assert_eq!(result.amount_fulfilled, 3500);
+        assert_eq!(result.amount_rejected, 2000);
+        assert_eq!(result.final_window(), Some(2200));
+        assert_eq!(result.max_window(), Some(4000));
+        assert_eq!(result.min_window(), Some(2000));
+        assert!(controller.smoothed_rtt().is_some());
+    }
+
+    #[test]
+    fn empty_trace() {
+        let mut controller = CongestionController::new(1000, 100, 2.0);
+        let result = replay_trace(&[], &mut controller);
+        assert_eq!(result, TraceResult::default());
+        assert_eq!(result.final_window(), None);
+    }
+}
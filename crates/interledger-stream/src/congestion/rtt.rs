@@ -0,0 +1,145 @@
+use super::estimator::{Estimator, Ewma};
+use super::tracking::BoundedQueue;
+use std::time::Duration;
+
+/// Round trip time estimate. By default the samples are smoothed as described in
+/// [RFC 6298](https://tools.ietf.org/html/rfc6298), but any [estimator](./trait.Estimator.html)
+/// can be used for the smoothed round trip time.
+#[derive(Clone)]
+pub(crate) struct RttEstimator {
+    /// Smoothed round trip time in seconds
+    smoothed: Box<dyn Estimator>,
+    /// Has a sample been taken yet?
+    sampled: bool,
+    /// Smoothed mean deviation of the samples from the smoothed round trip time
+    variance: Duration,
+    /// The most recent samples, oldest first
+    samples: BoundedQueue<Duration>,
+}
+
+impl RttEstimator {
+    pub fn new(max_samples: usize) -> Self {
+        RttEstimator {
+            smoothed: Box::new(Ewma::new(1.0 / 8.0)),
+            sampled: false,
+            variance: Duration::from_secs(0),
+            samples: BoundedQueue::new(max_samples),
+        }
+    }
+
+    /// Replaces the smoothing of the round trip time, discarding the current estimate
+    pub fn set_estimator(&mut self, estimator: Box<dyn Estimator>) {
+        self.smoothed = estimator;
+        self.sampled = false;
+        self.variance = Duration::from_secs(0);
+    }
+
+    pub fn record(&mut self, rtt: Duration) {
+        match self.smoothed() {
+            None => self.variance = rtt / 2,
+            Some(smoothed) => {
+                self.variance = self.variance * 3 / 4 + smoothed.abs_diff(rtt) / 4;
+            }
+        }
+        self.smoothed.observe(rtt.as_secs_f64());
+        self.sampled = true;
+        self.samples.push(rtt);
+    }
+
+    /// The smoothed round trip time. An estimator can produce values that aren't a duration,
+    /// e.g. infinity after overflowing, in which case this falls back to the latest sample.
+    pub fn smoothed(&self) -> Option<Duration> {
+        if !self.sampled {
+            return None;
+        }
+        let value = self.smoothed.value();
+        // Negative values are clamped, but NaN isn't less than 0 so it's still refused
+        let value = if value < 0.0 { 0.0 } else { value };
+        Duration::try_from_secs_f64(value)
+            .ok()
+            .or_else(|| self.samples.iter().last().copied())
+    }
+
+    pub fn variance(&self) -> Duration {
+        self.variance
+    }
+
+    /// Lowest of the retained samples
+    pub fn min(&self) -> Option<Duration> {
+        self.samples.iter().min().copied()
+    }
+
+    pub fn set_max_samples(&mut self, max_samples: usize) {
+        self.samples.set_limit(max_samples);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WindowedAverage;
+
+    /// Estimator stuck at a fixed value
+    #[derive(Clone)]
+    struct Fixed(f64);
+
+    impl Estimator for Fixed {
+        fn observe(&mut self, _sample: f64) {}
+
+        fn value(&self) -> f64 {
+            self.0
+        }
+
+        fn clone_box(&self) -> Box<dyn Estimator> {
+            Box::new(self.clone())
+        }
+    }
+
+    #[test]
+    fn smooths_samples() {
+        let mut estimator = RttEstimator::new(2);
+        assert_eq!(estimator.smoothed(), None);
+
+        estimator.record(Duration::from_millis(800));
+        assert_eq!(estimator.smoothed(), Some(Duration::from_millis(800)));
+        assert_eq!(estimator.variance(), Duration::from_millis(400));
+
+        estimator.record(Duration::from_millis(400));
+        assert_eq!(estimator.smoothed(), Some(Duration::from_millis(750)));
+        assert_eq!(estimator.variance(), Duration::from_millis(400));
+        assert_eq!(estimator.min(), Some(Duration::from_millis(400)));
+
+        // The minimum only considers retained samples
+        estimator.record(Duration::from_millis(600));
+        estimator.record(Duration::from_millis(700));
+        assert_eq!(estimator.min(), Some(Duration::from_millis(600)));
+    }
+
+    #[test]
+    fn uses_configured_estimator() {
+        let mut estimator = RttEstimator::new(2);
+        estimator.set_estimator(Box::new(WindowedAverage::new(2)));
+        estimator.record(Duration::from_millis(800));
+        estimator.record(Duration::from_millis(400));
+        assert_eq!(estimator.smoothed(), Some(Duration::from_millis(600)));
+    }
+
+    #[test]
+    fn falls_back_to_latest_sample_for_invalid_estimates() {
+        for value in [f64::INFINITY, f64::NAN, 1e20] {
+            let mut estimator = RttEstimator::new(2);
+            estimator.set_estimator(Box::new(Fixed(value)));
+            estimator.record(Duration::from_millis(800));
+            estimator.record(Duration::from_millis(400));
+            assert_eq!(estimator.smoothed(), Some(Duration::from_millis(400)));
+        }
+    }
+
+    #[test]
+    fn clamps_negative_estimates_to_zero() {
+        let mut estimator = RttEstimator::new(2);
+        estimator.set_estimator(Box::new(Fixed(-1.0)));
+        estimator.record(Duration::from_millis(800));
+        assert_eq!(estimator.smoothed(), Some(Duration::from_secs(0)));
+    }
+}
@@ -0,0 +1,523 @@
+use super::{Clock, CongestionController};
+use interledger_packet::Reject;
+use parking_lot::RwLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::debug;
+
+/// A [congestion controller](./struct.CongestionController.html) that can be shared between
+/// the task sending packets and the task processing their responses.
+///
+/// Prepares don't take the lock: they reserve their amount with a compare-and-swap on the
+/// atomic amount in flight, against a cached copy of the controller's
+/// [max sendable amount](./struct.CongestionController.html#method.max_sendable). Fulfills and
+/// rejects take the write lock to adjust the window, and refresh the cached copy. Since the
+/// wrapped controller never sees the prepares, it doesn't log them or count their sizes, and
+/// its own amount in flight only catches up at the next fulfill or reject.
+pub struct SharedCongestionController {
+    /// The amount reserved by outstanding window reservations. This is the authoritative
+    /// figure: prepares add to it without the lock, only fulfills and rejects subtract from it.
+    amount_in_flight: AtomicU64,
+    /// The controller's max sendable amount as of the last fulfill, reject or refresh
+    window: AtomicU64,
+    /// Sequence number the next reservation will be given, carried over from the controller
+    next_sequence: AtomicU64,
+    /// Number of fulfills and rejects applied so far, only changed under the write lock
+    version: AtomicU64,
+    /// The controller's clock, to time reservations without the lock
+    clock: Arc<dyn Clock>,
+    /// Window adjustment logic
+    controller: RwLock<CongestionController>,
+}
+
+/// Proof that an amount was reserved in the window, which must be handed back to
+/// the controller by fulfilling or rejecting it.
+///
+/// Dropping a reservation without resolving it leaves its amount in flight.
+#[must_use = "the reservation must be fulfilled or rejected to release its amount"]
+#[derive(Debug, PartialEq, Eq)]
+pub struct WindowReservation {
+    sequence: u64,
+    amount: u64,
+    prepared_at: Instant,
+}
+
+/// Point-in-time view of a [shared controller](./struct.SharedCongestionController.html).
+///
+/// The window figures are read under the read lock, so they always agree with each other and
+/// with `version`. The amount in flight may already include reservations made since.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CongestionSnapshot {
+    /// Number of fulfills and rejects applied when the snapshot was taken
+    pub version: u64,
+    pub amount_in_flight: u64,
+    pub max_in_flight: u64,
+    pub max_packet_amount: u64,
+}
+
+impl WindowReservation {
+    /// The sequence number the controller gave the packet
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// The amount reserved in the window
+    pub fn amount(&self) -> u64 {
+        self.amount
+    }
+}
+
+impl SharedCongestionController {
+    /// Shares the given controller, carrying over its amount in flight
+    pub fn new(controller: CongestionController) -> Self {
+        SharedCongestionController {
+            amount_in_flight: AtomicU64::new(controller.amount_in_flight),
+            window: AtomicU64::new(controller.max_sendable()),
+            next_sequence: AtomicU64::new(controller.next_sequence),
+            version: AtomicU64::new(0),
+            clock: controller.clock.clone(),
+            controller: RwLock::new(controller),
+        }
+    }
+
+    /// Maximium allowed packet amount allowed to send in a packet per F08s
+    pub fn get_max_packet_amount(&self) -> u64 {
+        self.controller.read().get_max_packet_amount()
+    }
+
+    /// The maximum amount availble to be sent is the controller's
+    /// [max sendable amount](./struct.CongestionController.html#method.max_sendable) minus
+    /// the current amount in flight
+    pub fn get_amount_left_in_window(&self) -> u64 {
+        let controller = self.controller.read();
+        controller
+            .max_sendable()
+            .saturating_sub(self.amount_in_flight.load(Ordering::SeqCst))
+    }
+
+    /// The current amount in flight
+    pub fn amount_in_flight(&self) -> u64 {
+        self.amount_in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Reserves the given amount in the window, or returns `None` if it doesn't fit.
+    /// Nothing fits while the controller is draining.
+    pub fn prepare(&self, amount: u64) -> Option<WindowReservation> {
+        let previous = match self.reserve(amount) {
+            Ok(previous) => previous,
+            Err(_) => {
+                // A paced increase keeps growing the window between resolves, so check
+                // the controller before giving up
+                let controller = self.controller.read();
+                self.window
+                    .store(controller.max_sendable(), Ordering::SeqCst);
+                self.reserve(amount).ok()?
+            }
+        };
+        let sequence = self.next_sequence.fetch_add(1, Ordering::SeqCst);
+        debug!(
+            "Reserved {} in the window for packet {}, amount in flight is now: {}",
+            amount,
+            sequence,
+            previous + amount
+        );
+        Some(WindowReservation {
+            sequence,
+            amount,
+            prepared_at: self.clock.now(),
+        })
+    }
+
+    /// Releases the reservation and increases the allowed max in flight amount cap
+    pub fn fulfill(&self, reservation: WindowReservation) {
+        self.resolve(&reservation, |controller| {
+            controller.fulfill(reservation.sequence, reservation.amount)
+        });
+    }
+
+    /// Releases the reservation and decreases the allowed max in flight amount cap
+    pub fn reject(&self, reservation: WindowReservation, reject: &Reject) {
+        self.resolve(&reservation, |controller| {
+            controller.reject(reservation.sequence, reservation.amount, reject)
+        });
+    }
+
+    /// Number of fulfills and rejects applied so far
+    pub fn version(&self) -> u64 {
+        self.version.load(Ordering::SeqCst)
+    }
+
+    /// Reads the controller's main figures, only waiting for a fulfill or reject in progress
+    pub fn snapshot(&self) -> CongestionSnapshot {
+        let controller = self.controller.read();
+        CongestionSnapshot {
+            version: self.version.load(Ordering::SeqCst),
+            amount_in_flight: self.amount_in_flight.load(Ordering::SeqCst),
+            max_in_flight: controller.max_in_flight,
+            max_packet_amount: controller.get_max_packet_amount(),
+        }
+    }
+
+    /// Runs the closure against a consistent view of the controller.
+    ///
+    /// The read lock is held while the closure runs, so no packets can be fulfilled or
+    /// rejected in the meantime and every field read by the closure reflects the same point
+    /// in time. Prepares still go ahead, and aren't part of the controller's amount in flight
+    /// until the next fulfill or reject. Keep the closure short, since it stalls resolves.
+    pub fn with_locked<R>(&self, f: impl FnOnce(&CongestionController) -> R) -> R {
+        f(&self.controller.read())
+    }
+
+    /// Returns the underlying controller
+    pub fn into_inner(self) -> CongestionController {
+        let mut controller = self.controller.into_inner();
+        controller.amount_in_flight = self.amount_in_flight.into_inner();
+        controller.next_sequence = self.next_sequence.into_inner();
+        controller
+    }
+
+    /// Tries to add the amount to the amount in flight without going over the cached
+    /// window, returning the amount in flight before it
+    fn reserve(&self, amount: u64) -> Result<u64, u64> {
+        let window = self.window.load(Ordering::SeqCst);
+        self.amount_in_flight
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |in_flight| {
+                in_flight
+                    .checked_add(amount)
+                    .filter(|&total| total <= window)
+            })
+    }
+
+    /// Runs a fulfill or reject against the controller under the write lock, then takes
+    /// whatever it released off the amount in flight and refreshes the cached window.
+    fn resolve(
+        &self,
+        reservation: &WindowReservation,
+        resolve: impl FnOnce(&mut CongestionController),
+    ) {
+        let mut controller = self.controller.write();
+        let rtt = self
+            .clock
+            .now()
+            .saturating_duration_since(reservation.prepared_at);
+        controller.observe_rtt(rtt);
+        // Only resolves subtract from the atomic and they hold the write lock, so whatever
+        // the controller releases from this figure is still there to take off below
+        let before = self.amount_in_flight.load(Ordering::SeqCst);
+        controller.amount_in_flight = before;
+        resolve(&mut controller);
+        self.amount_in_flight
+            .fetch_sub(before - controller.amount_in_flight, Ordering::SeqCst);
+        self.window
+            .store(controller.max_sendable(), Ordering::SeqCst);
+        self.version.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{CongestionEvent, CongestionState, MockClock, TrackingLimits};
+    use super::*;
+    use interledger_packet::{ErrorCode, RejectBuilder};
+    use std::sync::mpsc;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn reserves_only_what_fits_in_the_window() {
+        let shared = SharedCongestionController::new(CongestionController::new(1000, 1000, 2.0));
+
+        let first = shared.prepare(600).unwrap();
+        assert_eq!(first.amount(), 600);
+        assert_eq!(shared.prepare(500), None);
+        let second = shared.prepare(400).unwrap();
+        assert_eq!(shared.get_amount_left_in_window(), 0);
+
+        shared.fulfill(first);
+        assert_eq!(shared.amount_in_flight(), 400);
+        // Slow start doubled the window
+        assert_eq!(shared.get_amount_left_in_window(), 1600);
+
+        shared.reject(
+            second,
+            &RejectBuilder {
+                code: ErrorCode::T04_INSUFFICIENT_LIQUIDITY,
+                message: &[],
+                triggered_by: None,
+                data: &[],
+            }
+            .build(),
+        );
+        assert_eq!(shared.get_amount_left_in_window(), 1000);
+
+        let controller = shared.into_inner();
+        assert_eq!(controller.amount_in_flight, 0);
+        assert_eq!(controller.max_in_flight, 1000);
+    }
+
+    #[test]
+    fn reserves_while_the_controller_is_locked() {
+        let shared = SharedCongestionController::new(CongestionController::new(1000, 1000, 2.0));
+        let reservation = shared.with_locked(|_| shared.prepare(100)).unwrap();
+        assert_eq!(shared.amount_in_flight(), 100);
+        assert_eq!(shared.version(), 0);
+        shared.fulfill(reservation);
+
+        let controller = shared.into_inner();
+        assert_eq!(controller.amount_in_flight, 0);
+        assert_eq!(controller.next_sequence(), 2);
+    }
+
+    #[test]
+    fn times_round_trips_from_the_reservation() {
+        let clock = MockClock::new();
+        let controller = CongestionController::new(1000, 1000, 2.0).with_clock(clock.clone());
+        let shared = SharedCongestionController::new(controller);
+
+        let reservation = shared.prepare(100).unwrap();
+        clock.advance(Duration::from_secs(1));
+        shared.fulfill(reservation);
+        assert_eq!(
+            shared.with_locked(|controller| controller.smoothed_rtt()),
+            Some(Duration::from_secs(1))
+        );
+    }
+
+    #[test]
+    fn reserves_nothing_while_draining() {
+        let mut controller = CongestionController::new(1000, 1000, 2.0);
+        controller.begin_drain();
+        let shared = SharedCongestionController::new(controller);
+
+        assert_eq!(shared.get_amount_left_in_window(), 0);
+        assert_eq!(shared.prepare(100), None);
+        assert_eq!(shared.amount_in_flight(), 0);
+    }
+
+    #[test]
+    fn reserves_only_the_authorized_part_of_a_paced_increase() {
+        let clock = MockClock::new();
+        let mut controller = CongestionController::new(1000, 100, 2.0)
+            .with_clock(clock.clone())
+            .with_paced_slow_start();
+        controller.record_rtt_sample(Duration::from_secs(1));
+        // Doubles the window, ramping from 1000 to 2000 over a round trip
+        controller.fulfill(0, 0);
+        let shared = SharedCongestionController::new(controller);
+
+        assert_eq!(shared.get_amount_left_in_window(), 1000);
+        assert_eq!(shared.prepare(1500), None);
+        clock.advance(Duration::from_millis(500));
+        assert_eq!(shared.get_amount_left_in_window(), 1500);
+        let reservation = shared.prepare(1500).unwrap();
+        shared.fulfill(reservation);
+    }
+
+    #[test]
+    fn reserves_no_more_than_the_receiver_accepts() {
+        let mut controller = CongestionController::new(1000, 1000, 2.0);
+        controller.set_receiver_max_in_flight(Some(300));
+        let shared = SharedCongestionController::new(controller);
+
+        assert_eq!(shared.get_amount_left_in_window(), 300);
+        let reservation = shared.prepare(300).unwrap();
+        assert_eq!(shared.prepare(1), None);
+        shared.fulfill(reservation);
+    }
+
+    #[test]
+    fn reserves_only_the_target_utilization() {
+        let controller = CongestionController::new(1000, 1000, 2.0)
+            .with_target_utilization(0.8)
+            .unwrap();
+        let shared = SharedCongestionController::new(controller);
+
+        assert_eq!(shared.get_amount_left_in_window(), 800);
+        assert_eq!(shared.prepare(900), None);
+        let reservation = shared.prepare(800).unwrap();
+        shared.fulfill(reservation);
+    }
+
+    #[test]
+    fn locked_view_is_consistent_under_concurrent_use() {
+        const PACKET_AMOUNT: u64 = 10;
+        const START_AMOUNT: u64 = 1000;
+
+        let mut controller =
+            CongestionController::new(START_AMOUNT, 1, 2.0).with_tracking_limits(TrackingLimits {
+                max_events: usize::MAX,
+                ..Default::default()
+            });
+        controller.state = CongestionState::AvoidCongestion;
+        let shared = Arc::new(SharedCongestionController::new(controller));
+
+        let workers: Vec<_> = (0..4)
+            .map(|_| {
+                let shared = shared.clone();
+                thread::spawn(move || {
+                    let mut pending = None;
+                    for _ in 0..2000 {
+                        if let Some(reservation) = shared.prepare(PACKET_AMOUNT) {
+                            // Keep a packet in flight while the next one is prepared
+                            if let Some(previous) = pending.replace(reservation) {
+                                shared.fulfill(previous);
+                            }
+                        }
+                    }
+                    if let Some(last) = pending {
+                        shared.fulfill(last);
+                    }
+                })
+            })
+            .collect();
+
+        for _ in 0..200 {
+            shared.with_locked(|controller| {
+                let fulfills = controller
+                    .events()
+                    .filter(|event| matches!(event, CongestionEvent::Fulfill { .. }))
+                    .count() as u64;
+                // The window reflects exactly the fulfills recorded so far
+                assert_eq!(controller.max_in_flight, START_AMOUNT + fulfills);
+                assert!(controller.amount_in_flight <= controller.max_in_flight);
+                assert_eq!(controller.amount_in_flight % PACKET_AMOUNT, 0);
+                // Packets can be reserved but not released while the view is held
+                let in_flight = shared.amount_in_flight();
+                thread::yield_now();
+                assert!(shared.amount_in_flight() >= in_flight);
+            });
+        }
+
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        shared.with_locked(|controller| assert_eq!(controller.amount_in_flight, 0));
+    }
+
+    #[test]
+    fn resolving_a_mismatched_reservation_does_not_wrap() {
+        let shared = SharedCongestionController::new(CongestionController::new(1000, 1000, 2.0));
+        let reservation = shared.prepare(100).unwrap();
+        shared.fulfill(reservation);
+        // Released twice, or with the wrong amount
+        shared.fulfill(WindowReservation {
+            sequence: 0,
+            amount: 500,
+            prepared_at: Instant::now(),
+        });
+        assert_eq!(shared.amount_in_flight(), 0);
+        assert_eq!(
+            shared.get_amount_left_in_window(),
+            shared.snapshot().max_in_flight
+        );
+    }
+
+    #[test]
+    fn snapshots_agree_with_their_version() {
+        const PACKET_AMOUNT: u64 = 10;
+        const START_AMOUNT: u64 = 1000;
+
+        let mut controller = CongestionController::new(START_AMOUNT, 1, 2.0);
+        controller.state = CongestionState::AvoidCongestion;
+        let shared = Arc::new(SharedCongestionController::new(controller));
+
+        let workers: Vec<_> = (0..4)
+            .map(|_| {
+                let shared = shared.clone();
+                thread::spawn(move || {
+                    for _ in 0..2000 {
+                        if let Some(reservation) = shared.prepare(PACKET_AMOUNT) {
+                            shared.fulfill(reservation);
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        for _ in 0..2000 {
+            let snapshot = shared.snapshot();
+            // Each fulfill grew the window by one unit and bumped the version
+            assert_eq!(snapshot.version, snapshot.max_in_flight - START_AMOUNT);
+            assert_eq!(snapshot.amount_in_flight % PACKET_AMOUNT, 0);
+        }
+
+        for worker in workers {
+            worker.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn separate_prepare_and_resolve_tasks() {
+        const PACKETS: u64 = 10_000;
+        const PACKET_AMOUNT: u64 = 10;
+
+        let mut controller = CongestionController::new(100, 1, 2.0);
+        controller.state = CongestionState::AvoidCongestion;
+        let shared = Arc::new(SharedCongestionController::new(controller));
+        let (sender, receiver) = mpsc::channel::<WindowReservation>();
+
+        let preparer = {
+            let shared = shared.clone();
+            thread::spawn(move || {
+                let mut prepared = 0;
+                while prepared < PACKETS {
+                    if let Some(reservation) = shared.prepare(PACKET_AMOUNT) {
+                        sender.send(reservation).unwrap();
+                        prepared += 1;
+                    } else {
+                        thread::yield_now();
+                    }
+                }
+            })
+        };
+
+        let resolver = {
+            let shared = shared.clone();
+            thread::spawn(move || {
+                for reservation in receiver.iter() {
+                    assert!(shared.amount_in_flight() >= reservation.amount());
+                    shared.fulfill(reservation);
+                }
+            })
+        };
+
+        preparer.join().unwrap();
+        resolver.join().unwrap();
+
+        let controller = Arc::try_unwrap(shared).ok().unwrap().into_inner();
+        assert_eq!(controller.amount_in_flight, 0);
+        // Every fulfill added exactly one unit, regardless of interleaving
+        assert_eq!(controller.max_in_flight, 100 + PACKETS);
+    }
+
+    #[test]
+    fn version_counts_mutations() {
+        let shared = SharedCongestionController::new(CongestionController::new(1000, 1000, 2.0));
+        let before = shared.snapshot();
+        assert_eq!(before.version, 0);
+        assert_eq!(before.max_in_flight, 1000);
+
+        let first = shared.prepare(600).unwrap();
+        let second = shared.prepare(400).unwrap();
+        // Doesn't fit, so nothing changed
+        assert_eq!(shared.prepare(1), None);
+        shared.fulfill(first);
+        shared.reject(
+            second,
+            &RejectBuilder {
+                code: ErrorCode::T04_INSUFFICIENT_LIQUIDITY,
+                message: &[],
+                triggered_by: None,
+                data: &[],
+            }
+            .build(),
+        );
+
+        let after = shared.snapshot();
+        assert_eq!(after.version - before.version, 2);
+        assert_eq!(after.amount_in_flight, 0);
+        assert_eq!(shared.snapshot(), after);
+    }
+}
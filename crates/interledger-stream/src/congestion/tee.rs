@@ -0,0 +1,160 @@
+use super::{CongestionControl, CongestionEvent};
+use interledger_packet::Reject;
+use std::time::Duration;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tracing::trace;
+
+/// Wraps a congestion controller to also send every prepare, fulfill and reject it sees
+/// over a bounded channel, e.g. to ship them to an external telemetry system.
+///
+/// Sending never blocks: if the channel is full or the receiver is gone, the event is
+/// dropped and counted instead, so a slow consumer can't stall the sender.
+pub struct TeeController<C> {
+    inner: C,
+    sink: mpsc::Sender<CongestionEvent>,
+    /// Events that couldn't be sent
+    dropped_events: u64,
+}
+
+impl<C: CongestionControl> TeeController<C> {
+    pub fn new(inner: C, sink: mpsc::Sender<CongestionEvent>) -> Self {
+        TeeController {
+            inner,
+            sink,
+            dropped_events: 0,
+        }
+    }
+
+    /// Number of events dropped because the channel was full or closed
+    pub fn dropped_events(&self) -> u64 {
+        self.dropped_events
+    }
+
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+
+    fn send(&mut self, event: CongestionEvent) {
+        if let Err(err) = self.sink.try_send(event) {
+            self.dropped_events += 1;
+            match err {
+                TrySendError::Full(event) => trace!("Event sink is full, dropping {:?}", event),
+                TrySendError::Closed(event) => trace!("Event sink is closed, dropping {:?}", event),
+            }
+        }
+    }
+}
+
+impl<C: CongestionControl> CongestionControl for TeeController<C> {
+    fn get_max_packet_amount(&self) -> u64 {
+        self.inner.get_max_packet_amount()
+    }
+
+    fn get_amount_left_in_window(&self) -> u64 {
+        self.inner.get_amount_left_in_window()
+    }
+
+    fn prepare(&mut self, amount: u64) -> u64 {
+        let sequence = self.inner.prepare(amount);
+        self.send(CongestionEvent::Prepare { amount });
+        sequence
+    }
+
+    fn fulfill(&mut self, sequence: u64, prepare_amount: u64) {
+        self.inner.fulfill(sequence, prepare_amount);
+        self.send(CongestionEvent::Fulfill {
+            amount: prepare_amount,
+        });
+    }
+
+    fn reject(&mut self, sequence: u64, prepare_amount: u64, reject: &Reject) {
+        self.inner.reject(sequence, prepare_amount, reject);
+        self.send(CongestionEvent::Reject {
+            amount: prepare_amount,
+            code: reject.code(),
+        });
+    }
+
+    fn record_rtt_sample(&mut self, rtt: Duration) {
+        self.inner.record_rtt_sample(rtt);
+    }
+
+    fn smoothed_rtt(&self) -> Option<Duration> {
+        self.inner.smoothed_rtt()
+    }
+
+    fn pacing_interval(&self) -> Option<Duration> {
+        self.inner.pacing_interval()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CongestionController;
+    use interledger_packet::{ErrorCode, RejectBuilder};
+
+    #[tokio::test]
+    async fn sends_events_to_the_sink() {
+        let (sender, mut receiver) = mpsc::channel(8);
+        let mut controller = TeeController::new(CongestionController::new(1000, 10, 2.0), sender);
+        let first = controller.prepare(100);
+        controller.fulfill(first, 100);
+        let second = controller.prepare(200);
+        controller.reject(
+            second,
+            200,
+            &RejectBuilder {
+                code: ErrorCode::T04_INSUFFICIENT_LIQUIDITY,
+                message: &[],
+                triggered_by: None,
+                data: &[],
+            }
+            .build(),
+        );
+
+        assert_eq!(
+            receiver.recv().await,
+            Some(CongestionEvent::Prepare { amount: 100 })
+        );
+        assert_eq!(
+            receiver.recv().await,
+            Some(CongestionEvent::Fulfill { amount: 100 })
+        );
+        assert_eq!(
+            receiver.recv().await,
+            Some(CongestionEvent::Prepare { amount: 200 })
+        );
+        assert_eq!(
+            receiver.recv().await,
+            Some(CongestionEvent::Reject {
+                amount: 200,
+                code: ErrorCode::T04_INSUFFICIENT_LIQUIDITY
+            })
+        );
+        assert_eq!(controller.dropped_events(), 0);
+        assert_eq!(controller.into_inner().get_amount_left_in_window(), 1000);
+    }
+
+    #[tokio::test]
+    async fn drops_events_when_the_sink_is_full() {
+        let (sender, mut receiver) = mpsc::channel(1);
+        let mut controller = TeeController::new(CongestionController::new(1000, 10, 2.0), sender);
+        let first = controller.prepare(100);
+        // Neither of these fit, and must not wait for the receiver
+        controller.fulfill(first, 100);
+        let second = controller.prepare(100);
+        assert_eq!(controller.dropped_events(), 2);
+
+        // The inner controller saw everything
+        assert_eq!(controller.get_amount_left_in_window(), 1900);
+        assert_eq!(
+            receiver.recv().await,
+            Some(CongestionEvent::Prepare { amount: 100 })
+        );
+
+        drop(receiver);
+        controller.fulfill(second, 100);
+        assert_eq!(controller.dropped_events(), 3);
+    }
+}
@@ -0,0 +1,142 @@
+use std::collections::vec_deque::{self, VecDeque};
+
+/// Upper bounds on the per-connection bookkeeping kept by the
+/// [congestion controller](./struct.CongestionController.html).
+///
+/// Each tracking structure evicts its oldest entries once it reaches its limit,
+/// so a connector with many open connections has a predictable memory footprint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrackingLimits {
+    /// Maximum number of in-flight packets tracked individually
+    pub max_tracked_packets: usize,
+    /// Maximum number of entries retained in the congestion event log
+    pub max_events: usize,
+    /// Maximum number of round trip time samples retained
+    pub max_rtt_samples: usize,
+    /// Maximum number of distinct F08 max packet amounts remembered
+    pub max_f08_limits: usize,
+}
+
+impl Default for TrackingLimits {
+    fn default() -> Self {
+        TrackingLimits {
+            max_tracked_packets: 1024,
+            max_events: 256,
+            max_rtt_samples: 64,
+            max_f08_limits: 64,
+        }
+    }
+}
+
+/// FIFO queue holding at most `limit` entries, evicting the oldest entry on overflow
+#[derive(Debug, Clone)]
+pub(crate) struct BoundedQueue<T> {
+    entries: VecDeque<T>,
+    limit: usize,
+}
+
+impl<T> BoundedQueue<T> {
+    pub fn new(limit: usize) -> Self {
+        BoundedQueue {
+            entries: VecDeque::new(),
+            limit,
+        }
+    }
+
+    /// Appends the entry, returning the oldest entry if it had to be evicted to make room
+    pub fn push(&mut self, entry: T) -> Option<T> {
+        if self.limit == 0 {
+            return Some(entry);
+        }
+        let evicted = if self.entries.len() >= self.limit {
+            self.entries.pop_front()
+        } else {
+            None
+        };
+        self.entries.push_back(entry);
+        evicted
+    }
+
+    /// Changes the limit, evicting the oldest entries if the queue no longer fits
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+        while self.entries.len() > limit {
+            self.entries.pop_front();
+        }
+    }
+
+    /// Removes and returns the oldest entry matching the predicate
+    pub fn remove_first(&mut self, predicate: impl Fn(&T) -> bool) -> Option<T> {
+        let index = self.entries.iter().position(predicate)?;
+        self.entries.remove(index)
+    }
+
+    /// Removes and returns the entry with the given key, for queues whose entries were
+    /// pushed in increasing order of their keys
+    pub fn remove_sorted<K: Ord>(&mut self, key: &K, key_of: impl Fn(&T) -> K) -> Option<T> {
+        let index = self
+            .entries
+            .binary_search_by(|entry| key_of(entry).cmp(key))
+            .ok()?;
+        self.entries.remove(index)
+    }
+
+    /// Removes and returns every entry matching the predicate, oldest first
+    pub fn remove_all(&mut self, predicate: impl Fn(&T) -> bool) -> Vec<T> {
+        let (removed, kept): (Vec<T>, Vec<T>) = self.entries.drain(..).partition(predicate);
+        self.entries = kept.into();
+        removed
+    }
+
+    pub fn contains(&self, entry: &T) -> bool
+    where
+        T: PartialEq,
+    {
+        self.entries.contains(entry)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn iter(&self) -> vec_deque::Iter<'_, T> {
+        self.entries.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evicts_oldest_entries() {
+        let mut queue = BoundedQueue::new(3);
+        assert_eq!(queue.push(1), None);
+        assert_eq!(queue.push(2), None);
+        assert_eq!(queue.push(3), None);
+        assert_eq!(queue.push(4), Some(1));
+        assert_eq!(queue.iter().copied().collect::<Vec<_>>(), vec![2, 3, 4]);
+
+        queue.set_limit(1);
+        assert_eq!(queue.iter().copied().collect::<Vec<_>>(), vec![4]);
+    }
+
+    #[test]
+    fn removes_sorted_entries_by_key() {
+        let mut queue = BoundedQueue::new(4);
+        for entry in &[(1, 'a'), (3, 'b'), (4, 'c'), (7, 'd')] {
+            queue.push(*entry);
+        }
+        assert_eq!(queue.remove_sorted(&4, |entry| entry.0), Some((4, 'c')));
+        assert_eq!(queue.remove_sorted(&4, |entry| entry.0), None);
+        assert_eq!(queue.remove_sorted(&2, |entry| entry.0), None);
+        assert_eq!(queue.iter().map(|entry| entry.1).collect::<String>(), "abd");
+    }
+
+    #[test]
+    fn zero_limit_retains_nothing() {
+        let mut queue = BoundedQueue::new(0);
+        assert_eq!(queue.push(1), Some(1));
+        assert_eq!(queue.len(), 0);
+    }
+}
@@ -5,7 +5,7 @@ use ring::rand::{SecureRandom, SystemRandom};
 use ring::{aead, digest, hmac};
 use tracing::error;
 
-const NONCE_LENGTH: usize = 12;
+pub(crate) const NONCE_LENGTH: usize = 12;
 const AUTH_TAG_LENGTH: usize = 16;
 
 /// Protocol specific string for encryption
@@ -57,16 +57,6 @@ pub fn random_condition() -> [u8; 32] {
     condition_slice
 }
 
-/// Returns a random 18 byte number using
-/// [SystemRandom::new()](../../ring/rand/struct.SystemRandom.html#method.new)
-pub fn generate_token() -> [u8; 18] {
-    let mut token: [u8; 18] = [0; 18];
-    SystemRandom::new()
-        .fill(&mut token)
-        .expect("Failed to securely generate a random token!");
-    token
-}
-
 /// Encrypts a plaintext by calling [encrypt_with_nonce](./fn.encrypt_with_nonce.html)
 /// with a random nonce of [`NONCE_LENGTH`](./constant.NONCE_LENGTH.html) generated using
 /// [SystemRandom::new()](../../ring/rand/struct.SystemRandom.html#method.new)
@@ -89,7 +79,7 @@ pub fn encrypt(shared_secret: &[u8], plaintext: BytesMut) -> BytesMut {
 ///
 /// The ciphertext can be decrypted by calling the [`decrypt`](./fn.decrypt.html) function with the
 /// same `shared_secret`.
-fn encrypt_with_nonce(
+pub(crate) fn encrypt_with_nonce(
     shared_secret: &[u8],
     mut plaintext: BytesMut,
     nonce: [u8; NONCE_LENGTH],
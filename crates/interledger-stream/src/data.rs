@@ -0,0 +1,650 @@
+use super::packet::*;
+use bytes::{Bytes, BytesMut};
+use parking_lot::Mutex;
+use std::cmp::max;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Most bytes of a stream we put in a single STREAM packet, leaving room for the other frames
+/// and the encryption overhead within the 32KB of data an ILP packet can carry
+pub const MAX_DATA_PER_PACKET: usize = 16 * 1024;
+
+/// Most bytes the application can write before the writes wait for packets to carry them out
+const MAX_WRITE_BUFFER: usize = 64 * 1024;
+
+/// Most bytes received but not read by the application yet that a stream buffers by default
+pub const DEFAULT_RECEIVE_WINDOW: u64 = 64 * 1024;
+
+/// A chunk of outgoing data, taken from a [`DataStream`](./struct.DataStream.html) to be sent
+/// in a packet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct OutgoingData {
+    /// Position of the chunk in the byte stream
+    pub offset: u64,
+    /// The bytes, which may be empty if the packet only closes the stream
+    pub data: Bytes,
+    /// Code and message to close the stream with, if the packet should also close it
+    pub close: Option<(ErrorCode, String)>,
+}
+
+#[derive(Debug)]
+struct DataState {
+    /// Written by the application but not sent yet
+    outgoing: BytesMut,
+    /// Offset of the first byte of `outgoing`
+    outgoing_offset: u64,
+    /// Offset up to which the other side told us we may send, once it did
+    remote_max_offset: Option<u64>,
+    /// Set once the application shut down its write half
+    write_closed: bool,
+    /// Code and message to close the stream with, if the application aborted it
+    close_error: Option<(ErrorCode, String)>,
+    /// Set once a packet carrying the `StreamClose` frame went through
+    close_sent: bool,
+    /// Bytes received ahead of the ones we're waiting for, by offset
+    pending: BTreeMap<u64, Bytes>,
+    /// Bytes received in order and not read by the application yet
+    readable: BytesMut,
+    /// Offset of the next byte we expect to receive
+    read_offset: u64,
+    /// Most bytes we buffer for the application, in `pending` and `readable` together
+    receive_window: u64,
+    /// Code and message the other side closed the stream with, once it did
+    remote_close: Option<(ErrorCode, String)>,
+    read_waker: Option<Waker>,
+    write_waker: Option<Waker>,
+    /// Wakes whatever sends packets for the stream when there is something to send
+    send_waker: Option<Waker>,
+}
+
+impl DataState {
+    fn new(receive_window: u64) -> Self {
+        DataState {
+            outgoing: BytesMut::new(),
+            outgoing_offset: 0,
+            remote_max_offset: None,
+            write_closed: false,
+            close_error: None,
+            close_sent: false,
+            pending: BTreeMap::new(),
+            readable: BytesMut::new(),
+            read_offset: 0,
+            receive_window,
+            remote_close: None,
+            read_waker: None,
+            write_waker: None,
+            send_waker: None,
+        }
+    }
+
+    fn has_outgoing(&self) -> bool {
+        !self.outgoing.is_empty() || (self.write_closed && !self.close_sent)
+    }
+
+    fn wake_sender(&mut self) {
+        if let Some(waker) = self.send_waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn wake_writer(&mut self) {
+        if let Some(waker) = self.write_waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn wake_reader(&mut self) {
+        if let Some(waker) = self.read_waker.take() {
+            waker.wake();
+        }
+    }
+
+    /// Offset up to which the other side may send: the window starts at the first byte the
+    /// application hasn't read yet
+    fn max_offset(&self) -> u64 {
+        let read = self.read_offset - self.readable.len() as u64;
+        read.saturating_add(self.receive_window)
+    }
+
+    /// Buffer bytes received at the given offset and move every byte that is now in order
+    /// to the readable buffer. Returns `false` if they're new bytes sent after closing the stream,
+    /// or past the window we advertised, in which case the stream is closed with a
+    /// `FlowControlError`.
+    fn receive(&mut self, offset: u64, data: &[u8]) -> bool {
+        let end = offset.saturating_add(data.len() as u64);
+        if end <= self.read_offset || self.pending.contains_key(&offset) {
+            // Retransmission of bytes we already have
+            return true;
+        }
+        if self.remote_close.is_some() {
+            return false;
+        }
+        if end > self.max_offset() {
+            let message = format!(
+                "Received data up to offset {}, but the window ends at {}",
+                end,
+                self.max_offset()
+            );
+            self.close_with_error(ErrorCode::FlowControlError, &message);
+            self.close_remotely(ErrorCode::FlowControlError, &message);
+            return false;
+        }
+        // Overlapping frames are forbidden, so duplicates carry the same bytes
+        self.pending
+            .entry(offset)
+            .or_insert_with(|| Bytes::copy_from_slice(data));
+
+        while let Some(&offset) = self.pending.keys().next() {
+            if offset > self.read_offset {
+                break;
+            }
+            let chunk = self.pending.remove(&offset).unwrap();
+            let skip = (self.read_offset - offset) as usize;
+            if skip < chunk.len() {
+                self.readable.extend_from_slice(&chunk[skip..]);
+                self.read_offset = offset + chunk.len() as u64;
+            }
+        }
+        self.wake_reader();
+        true
+    }
+
+    fn close_with_error(&mut self, code: ErrorCode, message: &str) {
+        self.outgoing.clear();
+        self.write_closed = true;
+        self.close_error = Some((code, message.to_string()));
+        self.wake_sender();
+        self.wake_writer();
+    }
+
+    fn close_remotely(&mut self, code: ErrorCode, message: &str) {
+        if self.remote_close.is_none() {
+            self.remote_close = Some((code, message.to_string()));
+        }
+        self.wake_reader();
+        self.wake_writer();
+    }
+}
+
+/// A single logical byte stream of a STREAM connection, multiplexed with its payments.
+///
+/// The application reads and writes it through `AsyncRead` and `AsyncWrite`, and a clone of
+/// the handle is given to whatever carries the bytes in packets: [`send_data`](./fn.send_data.html)
+/// on the sending side and [`StreamReceiverService::accept_data`](./struct.StreamReceiverService.html#method.accept_data)
+/// on the receiving side. Bytes are numbered with offsets so they're read in the order they
+/// were written even if packets arrive out of order or are retransmitted.
+///
+/// Shutting down the write half closes the stream once every byte written has been sent, and
+/// reads return EOF once the other side closed it and every byte it sent was read. Either
+/// side can also [close it with an error](#method.close_with_error), which fails the other
+/// side's reads and writes, and closing the connection closes all its streams.
+///
+/// The stream buffers at most its [receive window](#method.with_receive_window) of bytes the
+/// application hasn't read, and advertises how far the other side may send with `StreamMaxData`
+/// frames. Data sent past that closes the stream with a `FlowControlError`.
+#[derive(Clone, Debug)]
+pub struct DataStream {
+    stream_id: u64,
+    state: Arc<Mutex<DataState>>,
+}
+
+impl DataStream {
+    pub fn new(stream_id: u64) -> Self {
+        DataStream {
+            stream_id,
+            state: Arc::new(Mutex::new(DataState::new(DEFAULT_RECEIVE_WINDOW))),
+        }
+    }
+
+    /// Buffer at most `receive_window` bytes the application hasn't read, instead of the
+    /// [default](./constant.DEFAULT_RECEIVE_WINDOW.html)
+    pub fn with_receive_window(self, receive_window: u64) -> Self {
+        self.state.lock().receive_window = receive_window;
+        self
+    }
+
+    pub fn stream_id(&self) -> u64 {
+        self.stream_id
+    }
+
+    /// Close the stream right away with an error code and message, dropping whatever was
+    /// written but not sent yet
+    pub fn close_with_error(&self, code: ErrorCode, message: &str) {
+        self.state.lock().close_with_error(code, message);
+    }
+
+    /// Code and message the other side closed the stream with, once it did
+    pub fn remote_close(&self) -> Option<(ErrorCode, String)> {
+        self.state.lock().remote_close.clone()
+    }
+
+    /// Whether both sides closed the stream, so no more packets need to carry it
+    pub(crate) fn is_finished(&self) -> bool {
+        let state = self.state.lock();
+        state.close_sent && state.remote_close.is_some()
+    }
+
+    /// Offset of the next byte to send
+    pub(crate) fn outgoing_offset(&self) -> u64 {
+        self.state.lock().outgoing_offset
+    }
+
+    /// Take the next chunk of at most `max_len` bytes to send, if there is anything to send.
+    ///
+    /// The chunk also stays within the window the other side advertised, so it is empty
+    /// while the window is full: sending it anyway only asks the other side for a new window.
+    pub(crate) fn take_outgoing(&self, max_len: usize) -> Option<OutgoingData> {
+        let mut state = self.state.lock();
+        if !state.has_outgoing() {
+            return None;
+        }
+        let window = state.remote_max_offset.map_or(u64::MAX, |max_offset| {
+            max_offset.saturating_sub(state.outgoing_offset)
+        });
+        let len = state
+            .outgoing
+            .len()
+            .min(max_len)
+            .min(usize::try_from(window).unwrap_or(usize::MAX));
+        let data = state.outgoing.split_to(len).freeze();
+        let offset = state.outgoing_offset;
+        state.outgoing_offset += len as u64;
+        let close = if state.write_closed && state.outgoing.is_empty() {
+            Some(
+                state
+                    .close_error
+                    .clone()
+                    .unwrap_or((ErrorCode::NoError, String::new())),
+            )
+        } else {
+            None
+        };
+        state.wake_writer();
+        Some(OutgoingData {
+            offset,
+            data,
+            close,
+        })
+    }
+
+    /// Wait until there is something to send, or return `None` once the stream is closed
+    pub(crate) fn poll_outgoing(
+        &self,
+        cx: &mut Context<'_>,
+        max_len: usize,
+    ) -> Poll<Option<OutgoingData>> {
+        if let Some(outgoing) = self.take_outgoing(max_len) {
+            return Poll::Ready(Some(outgoing));
+        }
+        let mut state = self.state.lock();
+        if state.close_sent {
+            return Poll::Ready(None);
+        }
+        state.send_waker = Some(cx.waker().clone());
+        // Writes may have raced with the check above
+        if state.has_outgoing() {
+            cx.waker().wake_by_ref();
+        }
+        Poll::Pending
+    }
+
+    /// Record that a chunk taken with `take_outgoing` reached the other side
+    pub(crate) fn sent(&self, outgoing: &OutgoingData) {
+        if outgoing.close.is_some() {
+            let mut state = self.state.lock();
+            state.close_sent = true;
+            state.wake_writer();
+        }
+    }
+
+    /// Put back a chunk taken with `take_outgoing` that didn't reach the other side, so it's
+    /// sent again with the same offset
+    pub(crate) fn requeue(&self, outgoing: OutgoingData) {
+        let mut state = self.state.lock();
+        let mut data = BytesMut::from(&outgoing.data[..]);
+        data.unsplit(state.outgoing.split());
+        state.outgoing = data;
+        state.outgoing_offset = outgoing.offset;
+        state.wake_sender();
+    }
+
+    /// The frames carrying an outgoing chunk on this stream
+    pub(crate) fn frames<'a>(&self, outgoing: &'a OutgoingData) -> Vec<Frame<'a>> {
+        let mut frames = Vec::with_capacity(2);
+        if !outgoing.data.is_empty() {
+            frames.push(Frame::StreamData(StreamDataFrame {
+                stream_id: self.stream_id,
+                offset: outgoing.offset,
+                data: &outgoing.data,
+            }));
+        }
+        if let Some((code, message)) = &outgoing.close {
+            frames.push(Frame::StreamClose(StreamCloseFrame {
+                stream_id: self.stream_id,
+                code: *code,
+                message,
+            }));
+        }
+        frames
+    }
+
+    /// Pick up the data and close frames the other side sent on this stream. Returns `false`
+    /// if it sent new data after closing the stream, or more than the stream's window.
+    pub(crate) fn handle_frames<'a>(&self, frames: impl Iterator<Item = Frame<'a>>) -> bool {
+        let mut state = self.state.lock();
+        let mut accepted = true;
+        for frame in frames {
+            match frame {
+                Frame::StreamData(frame) if frame.stream_id == self.stream_id => {
+                    accepted &= state.receive(frame.offset, frame.data);
+                }
+                Frame::StreamClose(frame) if frame.stream_id == self.stream_id => {
+                    state.close_remotely(frame.code, frame.message);
+                }
+                // Windows only grow, but the frames may arrive out of order
+                Frame::StreamMaxData(frame) if frame.stream_id == self.stream_id => {
+                    state.remote_max_offset = max(state.remote_max_offset, Some(frame.max_offset));
+                }
+                Frame::ConnectionClose(frame) => {
+                    state.close_remotely(frame.code, frame.message);
+                }
+                _ => {}
+            }
+        }
+        accepted
+    }
+}
+
+/// Most bytes the streams may still send together, if the other side advertised a
+/// `ConnectionMaxData` of `max_offset`
+pub(crate) fn connection_window(streams: &[DataStream], max_offset: Option<u64>) -> usize {
+    let sent = streams.iter().fold(0, |sent: u64, stream| {
+        sent.saturating_add(stream.outgoing_offset())
+    });
+    max_offset.map_or(usize::MAX, |max_offset| {
+        usize::try_from(max_offset.saturating_sub(sent)).unwrap_or(usize::MAX)
+    })
+}
+
+/// The highest `ConnectionMaxData` among the frames
+pub(crate) fn connection_max_offset<'a>(frames: impl Iterator<Item = Frame<'a>>) -> Option<u64> {
+    frames
+        .filter_map(|frame| match frame {
+            Frame::ConnectionMaxData(frame) => Some(frame.max_offset),
+            _ => None,
+        })
+        .max()
+}
+
+/// The frames telling the other side how far it may send on each of the streams, and on
+/// all of them together
+pub(crate) fn max_data_frames(streams: &[DataStream]) -> Vec<Frame<'static>> {
+    let mut connection_max_offset: u64 = 0;
+    let mut frames: Vec<_> = streams
+        .iter()
+        .map(|stream| {
+            let max_offset = stream.state.lock().max_offset();
+            connection_max_offset = connection_max_offset.saturating_add(max_offset);
+            Frame::StreamMaxData(StreamMaxDataFrame {
+                stream_id: stream.stream_id,
+                max_offset,
+            })
+        })
+        .collect();
+    if !streams.is_empty() {
+        frames.push(Frame::ConnectionMaxData(ConnectionMaxDataFrame {
+            max_offset: connection_max_offset,
+        }));
+    }
+    frames
+}
+
+impl AsyncRead for DataStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let mut state = self.state.lock();
+        if !state.readable.is_empty() {
+            let len = state.readable.len().min(buf.remaining());
+            buf.put_slice(&state.readable.split_to(len));
+            return Poll::Ready(Ok(()));
+        }
+        match &state.remote_close {
+            // EOF
+            Some((ErrorCode::NoError, _)) if state.pending.is_empty() => {
+                return Poll::Ready(Ok(()));
+            }
+            Some((ErrorCode::NoError, _)) => {
+                return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+            }
+            Some((code, message)) => {
+                return Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::ConnectionAborted,
+                    format!("Stream closed by peer with {:?}: {}", code, message),
+                )));
+            }
+            None => {}
+        }
+        state.read_waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl AsyncWrite for DataStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut state = self.state.lock();
+        let aborted_by_peer =
+            matches!(state.remote_close, Some((code, _)) if code != ErrorCode::NoError);
+        if state.write_closed || aborted_by_peer {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+        let room = MAX_WRITE_BUFFER.saturating_sub(state.outgoing.len());
+        if room == 0 {
+            state.write_waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        let len = buf.len().min(room);
+        state.outgoing.extend_from_slice(&buf[..len]);
+        state.wake_sender();
+        Poll::Ready(Ok(len))
+    }
+
+    /// Wait until every byte written was handed off to a packet
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut state = self.state.lock();
+        if state.outgoing.is_empty() {
+            return Poll::Ready(Ok(()));
+        }
+        state.write_waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+
+    /// Close the stream and wait until the other side was told
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut state = self.state.lock();
+        if state.close_sent {
+            return Poll::Ready(Ok(()));
+        }
+        if !state.write_closed {
+            state.write_closed = true;
+            state.wake_sender();
+        }
+        state.write_waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data_frame(offset: u64, data: &[u8]) -> Frame<'_> {
+        Frame::StreamData(StreamDataFrame {
+            stream_id: 1,
+            offset,
+            data,
+        })
+    }
+
+    fn readable(stream: &DataStream) -> Vec<u8> {
+        stream.state.lock().readable.to_vec()
+    }
+
+    #[test]
+    fn reassembles_out_of_order_data() {
+        let stream = DataStream::new(1);
+        stream.handle_frames(vec![data_frame(5, b"world")].into_iter());
+        assert!(readable(&stream).is_empty());
+
+        stream.handle_frames(vec![data_frame(0, b"hello")].into_iter());
+        assert_eq!(readable(&stream), b"helloworld");
+    }
+
+    #[test]
+    fn ignores_retransmissions_and_other_streams() {
+        let stream = DataStream::new(1);
+        stream.handle_frames(vec![data_frame(0, b"hello"), data_frame(0, b"hello")].into_iter());
+        stream.handle_frames(
+            vec![Frame::StreamData(StreamDataFrame {
+                stream_id: 3,
+                offset: 5,
+                data: b"other",
+            })]
+            .into_iter(),
+        );
+        stream.handle_frames(vec![data_frame(0, b"hello"), data_frame(5, b"!")].into_iter());
+        assert_eq!(readable(&stream), b"hello!");
+    }
+
+    #[test]
+    fn splits_writes_into_chunks() {
+        let stream = DataStream::new(1);
+        stream.state.lock().outgoing.extend_from_slice(&[7; 10]);
+        stream.state.lock().write_closed = true;
+
+        let first = stream.take_outgoing(6).unwrap();
+        assert_eq!((first.offset, first.data.len()), (0, 6));
+        assert_eq!(first.close, None);
+
+        // A lost packet is sent again with the same offset
+        stream.requeue(first);
+        let first = stream.take_outgoing(6).unwrap();
+        assert_eq!(first.offset, 0);
+
+        let last = stream.take_outgoing(6).unwrap();
+        assert_eq!((last.offset, last.data.len()), (6, 4));
+        assert_eq!(last.close, Some((ErrorCode::NoError, String::new())));
+        assert_eq!(stream.frames(&last).len(), 2);
+        stream.sent(&last);
+        assert_eq!(stream.take_outgoing(6), None);
+    }
+
+    #[tokio::test]
+    async fn bounds_unread_data_by_the_receive_window() {
+        use tokio::io::AsyncReadExt;
+
+        let mut stream = DataStream::new(1).with_receive_window(10);
+        assert!(stream.handle_frames(vec![data_frame(4, b"6789")].into_iter()));
+        assert!(stream.handle_frames(vec![data_frame(0, b"0123")].into_iter()));
+        assert_eq!(
+            max_data_frames(std::slice::from_ref(&stream)),
+            vec![
+                Frame::StreamMaxData(StreamMaxDataFrame {
+                    stream_id: 1,
+                    max_offset: 10,
+                }),
+                Frame::ConnectionMaxData(ConnectionMaxDataFrame { max_offset: 10 }),
+            ]
+        );
+
+        // Reading moves the window along
+        let mut buf = [0; 5];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(stream.state.lock().max_offset(), 15);
+        assert!(stream.handle_frames(vec![data_frame(8, b"89abcde")].into_iter()));
+
+        // Past the window, the stream is closed with an error on both sides
+        assert!(!stream.handle_frames(vec![data_frame(15, b"f")].into_iter()));
+        let close = stream.take_outgoing(MAX_DATA_PER_PACKET).unwrap().close;
+        assert_eq!(close.unwrap().0, ErrorCode::FlowControlError);
+        let mut rest = Vec::new();
+        let err = stream.read_to_end(&mut rest).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::ConnectionAborted);
+    }
+
+    #[test]
+    fn sends_within_the_advertised_window() {
+        let stream = DataStream::new(1);
+        stream.state.lock().outgoing.extend_from_slice(&[7; 10]);
+        stream.handle_frames(
+            vec![Frame::StreamMaxData(StreamMaxDataFrame {
+                stream_id: 1,
+                max_offset: 4,
+            })]
+            .into_iter(),
+        );
+
+        let first = stream.take_outgoing(6).unwrap();
+        assert_eq!((first.offset, first.data.len()), (0, 4));
+        // The window is full: only an empty chunk asking for more
+        let probe = stream.take_outgoing(6).unwrap();
+        assert!(probe.data.is_empty() && probe.close.is_none());
+        assert!(stream.frames(&probe).is_empty());
+
+        stream.handle_frames(
+            vec![Frame::StreamMaxData(StreamMaxDataFrame {
+                stream_id: 1,
+                max_offset: 20,
+            })]
+            .into_iter(),
+        );
+        let rest = stream.take_outgoing(6).unwrap();
+        assert_eq!((rest.offset, rest.data.len()), (4, 6));
+
+        let streams = [stream];
+        assert_eq!(connection_window(&streams, Some(12)), 2);
+        assert_eq!(connection_window(&streams, Some(5)), 0);
+        assert_eq!(connection_window(&streams, None), usize::MAX);
+    }
+
+    #[tokio::test]
+    async fn surfaces_remote_close_errors() {
+        use tokio::io::AsyncReadExt;
+
+        let stream = DataStream::new(1);
+        let accepted = stream.handle_frames(
+            vec![
+                data_frame(0, b"hi"),
+                Frame::StreamClose(StreamCloseFrame {
+                    stream_id: 1,
+                    code: ErrorCode::ApplicationError,
+                    message: "bye",
+                }),
+            ]
+            .into_iter(),
+        );
+        assert!(accepted);
+        assert_eq!(
+            stream.remote_close(),
+            Some((ErrorCode::ApplicationError, "bye".to_string()))
+        );
+
+        // Nothing more can be sent on a closed stream
+        assert!(!stream.handle_frames(vec![data_frame(2, b"more")].into_iter()));
+
+        let mut buf = Vec::new();
+        let err = stream.clone().read_to_end(&mut buf).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::ConnectionAborted);
+    }
+}
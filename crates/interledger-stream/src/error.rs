@@ -1,19 +1,166 @@
+use crate::congestion::Hysteresis;
+use crate::packet::ErrorCode as StreamErrorCode;
 use interledger_packet::{
-    AddressError, ErrorCode, OerError, PacketTypeError as IlpPacketTypeError,
+    AddressError, ErrorClass, ErrorCode, OerError, PacketTypeError as IlpPacketTypeError,
 };
+use std::fmt;
 /// Stream Errors
 use std::str::Utf8Error;
 
+/// How far a STREAM payment got before it failed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PaymentProgress {
+    /// Source amount of the packets that were fulfilled or still in flight
+    pub sent_amount: u64,
+    /// Amount the receiver got, in its units
+    pub delivered_amount: u64,
+    /// Number of packets that were fulfilled or rejected
+    pub attempts: u64,
+    /// Code of the last packet that was rejected, if any
+    pub last_reject_code: Option<ErrorCode>,
+}
+
+impl fmt::Display for PaymentProgress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} sent, {} delivered after {} packets",
+            self.sent_amount, self.delivered_amount, self.attempts
+        )?;
+        if let Some(code) = self.last_reject_code {
+            write!(f, ", last rejected with {}", code)?;
+        }
+        Ok(())
+    }
+}
+
+/// Why the receiver turned the payment down
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RejectReason {
+    /// It rejected a packet with a final error
+    Reject(ErrorCode, String),
+    /// It will not accept more than the given amount
+    ReceiveMaxExceeded(u64),
+    /// It closed the connection
+    ClosedByPeer(StreamErrorCode, String),
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RejectReason::Reject(code, message) => {
+                write!(f, "rejected with ErrorCode: {} {:?}", code, message)
+            }
+            RejectReason::ReceiveMaxExceeded(receive_max) => {
+                write!(
+                    f,
+                    "won't accept more than its receive max of {}",
+                    receive_max
+                )
+            }
+            RejectReason::ClosedByPeer(code, message) => {
+                write!(f, "closed the connection with {:?}: {:?}", code, message)
+            }
+        }
+    }
+}
+
+/// Error returned when sending money or data over STREAM fails. Each variant is a class
+/// of failure callers can decide on, e.g. whether it is worth retrying the payment later.
 #[derive(Debug, thiserror::Error)]
-pub enum Error {
-    #[error("Terminating payment since too many packets are rejected ({0} packets fulfilled, {1} packets rejected)")]
-    PaymentFailFast(u64, u64),
-    #[error("Packet was rejected with ErrorCode: {0} {1:?}")]
-    UnexpectedRejection(ErrorCode, String),
-    #[error(
-        "Error maximum time exceeded: Time since last fulfill exceeded the maximum time limit"
-    )]
-    Timeout,
+pub enum StreamError {
+    /// The packets are not getting through to the receiver: they were rejected with a final
+    /// error such as `F02: Unreachable`, or with temporary errors until we gave up
+    #[error("Receiver is unreachable, packets were rejected with ErrorCode: {code} {message:?} ({progress})")]
+    Unreachable {
+        code: ErrorCode,
+        message: String,
+        progress: PaymentProgress,
+    },
+    /// Too many packets are rejected for delivering less than the minimum amount, such as
+    /// if the exchange rate is too poor for the allowed slippage
+    #[error("Exchange rate is too poor, too many packets were rejected ({progress})")]
+    ExchangeRate { progress: PaymentProgress },
+    /// The receiver turned the payment down
+    #[error("Receiver {reason} ({progress})")]
+    Rejected {
+        reason: RejectReason,
+        progress: PaymentProgress,
+    },
+    /// No packet was fulfilled over the maximum time limit
+    #[error("Timed out: time since last fulfill exceeded the maximum time limit ({progress})")]
+    Timeout { progress: PaymentProgress },
+}
+
+impl StreamError {
+    /// Classifies a packet rejected with a final or relative error, or the last of the
+    /// packets that made the payment give up
+    pub(crate) fn from_reject(code: ErrorCode, message: String, progress: PaymentProgress) -> Self {
+        match code {
+            ErrorCode::F04_INSUFFICIENT_DESTINATION_AMOUNT | ErrorCode::F99_APPLICATION_ERROR => {
+                StreamError::ExchangeRate { progress }
+            }
+            code if code == ErrorCode::F02_UNREACHABLE || code.class() != ErrorClass::Final => {
+                StreamError::Unreachable {
+                    code,
+                    message,
+                    progress,
+                }
+            }
+            code => StreamError::Rejected {
+                reason: RejectReason::Reject(code, message),
+                progress,
+            },
+        }
+    }
+
+    /// How far the payment got before it failed
+    pub fn progress(&self) -> &PaymentProgress {
+        match self {
+            StreamError::Unreachable { progress, .. }
+            | StreamError::ExchangeRate { progress }
+            | StreamError::Rejected { progress, .. }
+            | StreamError::Timeout { progress } => progress,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, thiserror::Error)]
+pub enum CongestionError {
+    #[error("Minimum window of {min} exceeds the window cap of {cap}")]
+    MinExceedsCap { min: u64, cap: u64 },
+    #[error("Decrease factor must be greater than 1.0, got {0}")]
+    InvalidDecreaseFactor(f64),
+    #[error("Volatility sensitivity must be a finite, non-negative number, got {0}")]
+    InvalidVolatilitySensitivity(f64),
+    #[error("Target utilization must be greater than 0 and at most 1, got {0}")]
+    InvalidTargetUtilization(f64),
+    #[error("Hysteresis thresholds must all be greater than 0, got {0:?}")]
+    InvalidHysteresis(Hysteresis),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum EventLogError {
+    #[error("Invalid event log: {0}")]
+    Oer(#[from] OerError),
+    #[error("Unknown congestion event type: {0}")]
+    UnknownEventType(u8),
+    #[error("Invalid event log: Reject.ErrorCode was not IA5String")]
+    InvalidErrorCode,
+    #[error("Invalid event log: unexpected trailing bytes")]
+    TrailingBytes,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ReceiptError {
+    #[error("Invalid receipt: {0}")]
+    Oer(#[from] OerError),
+    #[error("Unsupported receipt version: {0}")]
+    UnsupportedVersion(u8),
+    #[error("Invalid receipt: unexpected trailing bytes")]
+    TrailingBytes,
+    #[error("Receipt was not signed with the receipt secret")]
+    InvalidHmac,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -10,19 +10,52 @@ mod client;
 mod congestion;
 /// Cryptographic utilities for generating fulfillments and encrypting/decrypting STREAM packets
 mod crypto;
+/// Byte streams multiplexed with the payments of a STREAM connection
+mod data;
 /// Stream errors
 mod error;
 /// Stream Packet implementation, [as specified in the RFC](https://interledger.org/rfcs/0029-stream/#5-packet-and-frame-specification)
 mod packet;
+/// Exchange rate probing with test packets before a payment
+mod probe;
+/// Receipts signed by the receiver as proof of how much money it received
+mod receipt;
+/// Backoff for packets rejected with temporary errors
+mod retry;
 /// A stream server implementing an [Outgoing Service](../interledger_service/trait.OutgoingService.html) for receiving STREAM payments from peers
 mod server;
 
-pub use client::{send_money, StreamDelivery};
-pub use error::{Error, StreamPacketError};
+pub use client::{
+    send_data, send_money, PacketOutcome, SendMoneyOptions, StreamDelivery, StreamTotals,
+};
+#[cfg(feature = "congestion-notifications")]
+pub use congestion::TeeController;
+pub use congestion::{
+    replay_trace, valid_transitions, Clock, CongestionControl, CongestionController,
+    CongestionEvent, CongestionLevel, CongestionSnapshot, CongestionState, CongestionStats,
+    Estimator, Ewma, GlobalCapController, Hysteresis, MockClock, PathCache, PeerAverages,
+    PeerStatsRegistry, SharedCongestionController, SystemClock, TraceEntry, TraceOutcome,
+    TraceResult, TrackingLimits, WindowHistogram, WindowReservation, WindowedAverage,
+};
+#[cfg(feature = "serde")]
+pub use congestion::{CongestionConfig, GrowthStrategy};
+pub use data::{DataStream, DEFAULT_RECEIVE_WINDOW, MAX_DATA_PER_PACKET};
+pub use error::{
+    CongestionError, EventLogError, PaymentProgress, ReceiptError, RejectReason, StreamError,
+    StreamPacketError,
+};
+pub use packet::ErrorCode as StreamErrorCode;
+pub use probe::{probe_rate, RateProbe};
+pub use receipt::{Receipt, RECEIPT_NONCE_LENGTH, RECEIPT_SECRET_LENGTH, RECEIPT_VERSION};
+pub use retry::RetryPolicy;
 pub use server::{
-    ConnectionGenerator, PaymentNotification, StreamNotificationsStore, StreamReceiverService,
+    AccountNotification, AccountNotificationsStore, AssetDetails, ConnectionGenerator,
+    PaymentNotification, StreamNotificationsStore, StreamReceiverService,
 };
 
+#[cfg(fuzzing)]
+pub use congestion::fuzz_apply;
+
 #[cfg(fuzzing)]
 pub fn fuzz_decrypted_stream_packet(data: &[u8]) {
     let b = bytes::BytesMut::from(data);
@@ -211,8 +244,11 @@ mod send_money_to_receiver {
     use interledger_packet::{ErrorCode, RejectBuilder};
     use interledger_router::Router;
     use interledger_service::outgoing_service_fn;
-    use interledger_service_util::ExchangeRateService;
+    use interledger_service::IncomingService;
+    use interledger_service_util::{ExchangeRateService, MaxPacketAmountService};
     use std::str::FromStr;
+    use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+    use std::sync::Arc;
     use uuid::Uuid;
 
     #[tokio::test]
@@ -268,12 +304,183 @@ mod send_money_to_receiver {
             destination_account,
             shared_secret.to_vec(),
             100,
-            0.0,
+            SendMoneyOptions {
+                slippage: 0.0,
+                ..SendMoneyOptions::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(receipt.delivered_amount, 100);
+    }
+
+    #[tokio::test]
+    async fn stops_at_the_receivers_receive_max() {
+        let server_secret = Bytes::from(&[0; 32][..]);
+        let destination_address = Address::from_str("example.receiver").unwrap();
+        let account = TestAccount {
+            id: Uuid::new_v4(),
+            ilp_address: destination_address.clone(),
+            asset_code: "XYZ".to_string(),
+            asset_scale: 9,
+            max_packet_amount: None,
+        };
+        let store = TestStore {
+            route: Some((destination_address.to_string(), account.clone())),
+            price_1: None,
+            price_2: None,
+        };
+        let connection_generator = ConnectionGenerator::new(server_secret.clone());
+        let server = StreamReceiverService::new(
+            server_secret,
+            DummyStore,
+            outgoing_service_fn(|_| {
+                Err(RejectBuilder {
+                    code: ErrorCode::F02_UNREACHABLE,
+                    message: b"No other outgoing handler",
+                    triggered_by: Some(&EXAMPLE_RECEIVER),
+                    data: &[],
+                }
+                .build())
+            }),
+        );
+        let (destination_account, shared_secret) =
+            connection_generator.generate_address_and_secret(&destination_address);
+        server.set_receive_max(&destination_account, 1, 300);
+
+        let result = send_money(
+            Router::new(store, server.clone()),
+            &account,
+            TestStore {
+                route: None,
+                price_1: None,
+                price_2: None,
+            },
+            destination_account.clone(),
+            shared_secret.to_vec(),
+            1000,
+            SendMoneyOptions {
+                slippage: 0.0,
+                ..SendMoneyOptions::default()
+            },
+        )
+        .await;
+
+        match result {
+            Err(StreamError::Rejected {
+                reason: RejectReason::ReceiveMaxExceeded(300),
+                progress,
+            }) => assert_eq!(progress.delivered_amount, 300),
+            other => panic!("Expected the receive max to be reached, got {:?}", other),
+        }
+        // The sender closed the connection, so the receiver forgot the limit
+        assert_eq!(server.total_received(&destination_account, 1), None);
+    }
+
+    #[tokio::test]
+    async fn exchanges_asset_details_on_connection() {
+        let server_secret = Bytes::from(&[0; 32][..]);
+        let destination_address = Address::from_str("example.receiver").unwrap();
+        let account = TestAccount {
+            id: Uuid::new_v4(),
+            ilp_address: destination_address.clone(),
+            asset_code: "ABC".to_string(),
+            asset_scale: 6,
+            max_packet_amount: None,
+        };
+        let store = TestStore {
+            route: Some((destination_address.to_string(), account)),
+            price_1: Some(1.0),
+            price_2: Some(1.0),
+        };
+        let notifications = RecordingStore::default();
+        let connection_generator = ConnectionGenerator::new(server_secret.clone());
+        let server = StreamReceiverService::new(
+            server_secret,
+            notifications.clone(),
+            outgoing_service_fn(|_| {
+                Err(RejectBuilder {
+                    code: ErrorCode::F02_UNREACHABLE,
+                    message: b"No other outgoing handler",
+                    triggered_by: Some(&EXAMPLE_RECEIVER),
+                    data: &[],
+                }
+                .build())
+            }),
+        );
+        let (destination_account, shared_secret) =
+            connection_generator.generate_address_and_secret(&destination_address);
+
+        let receipt = send_money(
+            Router::new(
+                store.clone(),
+                ExchangeRateService::new(0.0, store.clone(), server.clone()),
+            ),
+            &TestAccount {
+                id: Uuid::new_v4(),
+                asset_code: "XYZ".to_string(),
+                asset_scale: 9,
+                ilp_address: Address::from_str("example.sender").unwrap(),
+                max_packet_amount: None,
+            },
+            store,
+            destination_account.clone(),
+            shared_secret.to_vec(),
+            100_000,
+            SendMoneyOptions {
+                slippage: 0.0,
+                ..SendMoneyOptions::default()
+            },
         )
         .await
         .unwrap();
 
+        // The sender learned the receiver's details...
         assert_eq!(receipt.delivered_amount, 100);
+        assert_eq!(receipt.destination_asset_code.as_deref(), Some("ABC"));
+        assert_eq!(receipt.destination_asset_scale, Some(6));
+
+        // ...and the receiver the sender's, on every notification of the connection
+        let notifications = notifications.0.lock().unwrap();
+        assert!(!notifications.is_empty());
+        assert!(notifications.last().unwrap().connection_closed);
+        for notification in notifications.iter() {
+            assert_eq!(
+                notification.source_asset,
+                Some(AssetDetails {
+                    asset_code: "XYZ".to_string(),
+                    asset_scale: 9,
+                })
+            );
+        }
+        // They're forgotten once the sender closed the connection
+        assert_eq!(server.remote_asset_details(&destination_account), None);
+    }
+
+    /// Keeps the payment notifications published by the receiver
+    #[derive(Clone, Default)]
+    struct RecordingStore(Arc<std::sync::Mutex<Vec<PaymentNotification>>>);
+
+    impl StreamNotificationsStore for RecordingStore {
+        type Account = TestAccount;
+
+        fn add_payment_notification_subscription(
+            &self,
+            _account_id: Uuid,
+            _sender: futures::channel::mpsc::UnboundedSender<PaymentNotification>,
+        ) {
+        }
+
+        fn publish_payment_notification(&self, payment: PaymentNotification) {
+            self.0.lock().unwrap().push(payment);
+        }
+
+        fn all_payment_subscription(
+            &self,
+        ) -> tokio::sync::broadcast::Receiver<PaymentNotification> {
+            tokio::sync::broadcast::channel(1).1
+        }
     }
 
     #[tokio::test]
@@ -332,14 +539,347 @@ mod send_money_to_receiver {
             destination_account,
             shared_secret.to_vec(),
             1000,
-            0.014,
+            SendMoneyOptions {
+                slippage: 0.014,
+                ..SendMoneyOptions::default()
+            },
         )
         .await;
 
         // Connector takes 2% spread, but we're only willing to tolerate 1.4%
         match result {
-            Err(Error::PaymentFailFast(_, _)) => {}
-            _ => panic!("Payment should fail fast due to poor exchange rates"),
+            Err(StreamError::ExchangeRate { progress }) => {
+                assert_eq!(progress.delivered_amount, 0);
+                assert_eq!(
+                    progress.last_reject_code,
+                    Some(ErrorCode::F99_APPLICATION_ERROR)
+                );
+            }
+            other => panic!(
+                "Payment should fail fast due to poor exchange rates, got {:?}",
+                other
+            ),
         }
     }
+
+    #[tokio::test]
+    async fn delivers_at_least_the_minimum_delivery_amount() {
+        let (result, _) = send_with_worsening_rate(None, 900).await;
+        assert!(result.unwrap().delivered_amount >= 900);
+    }
+
+    #[tokio::test]
+    async fn stops_once_the_minimum_delivery_amount_would_be_breached() {
+        // The rate drops to 0.8 after three packets, while at least 0.9 must arrive
+        let (result, fulfilled_amount) = send_with_worsening_rate(Some(3), 900).await;
+        match result {
+            Err(StreamError::ExchangeRate { progress }) => {
+                assert!(fulfilled_amount > 0);
+                assert_eq!(progress.delivered_amount, fulfilled_amount);
+                assert_eq!(
+                    progress.last_reject_code,
+                    Some(ErrorCode::F99_APPLICATION_ERROR)
+                );
+                // Gave up right away instead of retrying until failing fast
+                assert!(progress.attempts < 20);
+            }
+            other => panic!("Payment should stop at the poorer rate, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn probes_the_rate_of_the_path() {
+        let (server, destination_account, shared_secret) = half_rate_path();
+        let probe = probe_rate(
+            server,
+            &TestAccount {
+                id: Uuid::new_v4(),
+                ilp_address: Address::from_str("example.sender").unwrap(),
+                asset_code: "XYZ".to_string(),
+                asset_scale: 9,
+                max_packet_amount: Some(50_000),
+            },
+            destination_account,
+            &shared_secret,
+            1_000_000,
+        )
+        .await
+        .unwrap();
+
+        assert!((probe.rate - 0.5).abs() < 0.001, "rate was {}", probe.rate);
+        assert_eq!(probe.destination_asset_code.as_deref(), Some("ABC"));
+        assert_eq!(probe.destination_asset_scale, Some(9));
+        // The 100_000 packet was too large, so the path's limit was probed instead
+        assert_eq!(probe.max_packet_amount, Some(50_000));
+        assert_eq!(probe.probes, vec![(1_000, 500), (50_000, 25_000)]);
+        assert_eq!(probe.min_delivery_amount(1_000_000, 0.01), 495_000);
+    }
+
+    #[tokio::test]
+    async fn sends_at_the_probed_rate() {
+        let (server, destination_account, shared_secret) = half_rate_path();
+        let receipt = send_money(
+            server,
+            &TestAccount {
+                id: Uuid::new_v4(),
+                ilp_address: Address::from_str("example.sender").unwrap(),
+                asset_code: "XYZ".to_string(),
+                asset_scale: 9,
+                max_packet_amount: Some(50_000),
+            },
+            // Has no rate for the assets
+            TestStore {
+                route: None,
+                price_1: None,
+                price_2: None,
+            },
+            destination_account,
+            shared_secret.to_vec(),
+            1_000_000,
+            SendMoneyOptions {
+                slippage: 0.01,
+                probe_rate: true,
+                ..SendMoneyOptions::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(receipt.delivered_amount, 500_000);
+    }
+
+    /// A receiver of ABC behind a connector which delivers half of each packet and lets
+    /// packets carry as much as the sender's max packet amount
+    fn half_rate_path() -> (
+        impl IncomingService<TestAccount> + Clone + Send + Sync + 'static,
+        Address,
+        [u8; 32],
+    ) {
+        let server_secret = Bytes::from(&[0; 32][..]);
+        let destination_address = Address::from_str("example.receiver").unwrap();
+        let account = TestAccount {
+            id: Uuid::new_v4(),
+            ilp_address: destination_address.clone(),
+            asset_code: "ABC".to_string(),
+            asset_scale: 9,
+            max_packet_amount: None,
+        };
+        let store = TestStore {
+            route: Some((destination_address.to_string(), account)),
+            price_1: None,
+            price_2: None,
+        };
+        let connection_generator = ConnectionGenerator::new(server_secret.clone());
+        let server = StreamReceiverService::new(
+            server_secret,
+            DummyStore,
+            outgoing_service_fn(|_| {
+                Err(RejectBuilder {
+                    code: ErrorCode::F02_UNREACHABLE,
+                    message: b"No other outgoing handler",
+                    triggered_by: Some(&EXAMPLE_RECEIVER),
+                    data: &[],
+                }
+                .build())
+            }),
+        );
+        let server = Router::new(store.clone(), server).wrap(|mut request, mut next| {
+            let amount = request.prepare.amount();
+            request.prepare.set_amount(amount / 2);
+            async move { next.handle_request(request).await }
+        });
+        let server = MaxPacketAmountService::new(store, server);
+
+        let (destination_account, shared_secret) =
+            connection_generator.generate_address_and_secret(&destination_address);
+        (server, destination_account, shared_secret)
+    }
+
+    /// Sends 1000 in packets of up to 100 to a receiver through a connector which delivers
+    /// everything for the given number of packets, then only 80% of each packet. Returns the
+    /// result and the amount the connector passed on at the initial rate.
+    async fn send_with_worsening_rate(
+        full_rate_packets: Option<usize>,
+        min_delivery_amount: u64,
+    ) -> (Result<StreamDelivery, StreamError>, u64) {
+        let server_secret = Bytes::from(&[0; 32][..]);
+        let destination_address = Address::from_str("example.receiver").unwrap();
+        let account = TestAccount {
+            id: Uuid::new_v4(),
+            ilp_address: destination_address.clone(),
+            asset_code: "XYZ".to_string(),
+            asset_scale: 9,
+            max_packet_amount: None,
+        };
+        let store = TestStore {
+            route: Some((destination_address.to_string(), account.clone())),
+            price_1: None,
+            price_2: None,
+        };
+        let connection_generator = ConnectionGenerator::new(server_secret.clone());
+        let server = StreamReceiverService::new(
+            server_secret,
+            DummyStore,
+            outgoing_service_fn(|_| {
+                Err(RejectBuilder {
+                    code: ErrorCode::F02_UNREACHABLE,
+                    message: b"No other outgoing handler",
+                    triggered_by: Some(&EXAMPLE_RECEIVER),
+                    data: &[],
+                }
+                .build())
+            }),
+        );
+
+        let packets = Arc::new(AtomicUsize::new(0));
+        let full_rate_amount = Arc::new(AtomicU64::new(0));
+        let full_rate_amount_clone = full_rate_amount.clone();
+        let server = Router::new(store.clone(), server).wrap(move |mut request, mut next| {
+            let amount = request.prepare.amount();
+            if amount > 0 {
+                let packet = packets.fetch_add(1, Ordering::SeqCst);
+                if full_rate_packets.is_none_or(|packets| packet < packets) {
+                    full_rate_amount_clone.fetch_add(amount, Ordering::SeqCst);
+                } else {
+                    request.prepare.set_amount(amount * 8 / 10);
+                }
+            }
+            async move { next.handle_request(request).await }
+        });
+
+        let server = MaxPacketAmountService::new(store.clone(), server);
+
+        let (destination_account, shared_secret) =
+            connection_generator.generate_address_and_secret(&destination_address);
+
+        // The slippage allows much more than the minimum delivery amount
+        let result = send_money(
+            server,
+            &TestAccount {
+                max_packet_amount: Some(100),
+                ..account
+            },
+            TestStore {
+                route: None,
+                price_1: None,
+                price_2: None,
+            },
+            destination_account,
+            shared_secret.to_vec(),
+            1000,
+            SendMoneyOptions {
+                slippage: 0.5,
+                min_delivery_amount: Some(min_delivery_amount),
+                ..SendMoneyOptions::default()
+            },
+        )
+        .await;
+        (result, full_rate_amount.load(Ordering::SeqCst))
+    }
+}
+
+#[cfg(test)]
+mod send_data_to_receiver {
+    use super::test_helpers::*;
+    use super::*;
+    use async_trait::async_trait;
+    use bytes::Bytes;
+    use interledger_packet::{Address, ErrorCode, RejectBuilder};
+    use interledger_router::Router;
+    use interledger_service::{outgoing_service_fn, IlpResult, IncomingRequest, IncomingService};
+    use std::str::FromStr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use uuid::Uuid;
+
+    /// Counts the packets on their way to the receiver
+    #[derive(Clone)]
+    struct CountPackets<I> {
+        next: I,
+        packets: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl<I: IncomingService<TestAccount> + Send> IncomingService<TestAccount> for CountPackets<I> {
+        async fn handle_request(&mut self, request: IncomingRequest<TestAccount>) -> IlpResult {
+            self.packets.fetch_add(1, Ordering::Relaxed);
+            self.next.handle_request(request).await
+        }
+    }
+
+    #[tokio::test]
+    async fn exchanges_data_in_order() {
+        let server_secret = Bytes::from(&[0; 32][..]);
+        let destination_address = Address::from_str("example.receiver").unwrap();
+        let account = TestAccount {
+            id: Uuid::new_v4(),
+            ilp_address: destination_address.clone(),
+            asset_code: "XYZ".to_string(),
+            asset_scale: 9,
+            max_packet_amount: None,
+        };
+        let store = TestStore {
+            route: Some((destination_address.to_string(), account.clone())),
+            price_1: None,
+            price_2: None,
+        };
+        let connection_generator = ConnectionGenerator::new(server_secret.clone());
+        let server = StreamReceiverService::new(
+            server_secret,
+            DummyStore,
+            outgoing_service_fn(|_| {
+                Err(RejectBuilder {
+                    code: ErrorCode::F02_UNREACHABLE,
+                    message: b"No other outgoing handler",
+                    triggered_by: Some(&EXAMPLE_RECEIVER),
+                    data: &[],
+                }
+                .build())
+            }),
+        );
+        let (destination_account, shared_secret) =
+            connection_generator.generate_address_and_secret(&destination_address);
+
+        // The receiving application answers, then reads everything the sender wrote
+        let mut receiving_stream = server.accept_data(&destination_account, 1);
+        let receiving_app = tokio::spawn(async move {
+            receiving_stream.write_all(b"thanks").await.unwrap();
+            receiving_stream.shutdown().await.unwrap();
+            let mut received = Vec::new();
+            receiving_stream.read_to_end(&mut received).await.unwrap();
+            received
+        });
+
+        let packets = Arc::new(AtomicUsize::new(0));
+        let service = CountPackets {
+            next: Router::new(store, server),
+            packets: packets.clone(),
+        };
+        let mut sending_stream = DataStream::new(1);
+        let sender = {
+            let sending_stream = sending_stream.clone();
+            tokio::spawn(async move {
+                send_data(
+                    service,
+                    &account,
+                    destination_account,
+                    &shared_secret,
+                    sending_stream,
+                )
+                .await
+            })
+        };
+
+        let payload: Vec<u8> = (0..40_000).map(|i| (i % 251) as u8).collect();
+        sending_stream.write_all(&payload).await.unwrap();
+        sending_stream.shutdown().await.unwrap();
+        let mut answer = Vec::new();
+        sending_stream.read_to_end(&mut answer).await.unwrap();
+
+        sender.await.unwrap().unwrap();
+        assert_eq!(receiving_app.await.unwrap(), payload);
+        assert_eq!(answer, b"thanks");
+        // 16KB per packet, the last one also closing the stream
+        assert_eq!(packets.load(Ordering::Relaxed), 3);
+    }
 }
@@ -1,3 +1,5 @@
+#[cfg(test)]
+use super::crypto::{encrypt_with_nonce, NONCE_LENGTH};
 use super::{
     crypto::{decrypt, encrypt},
     StreamPacketError,
@@ -103,6 +105,10 @@ impl<'a> StreamPacketBuilder<'a> {
                     buffer_unencrypted.put_u8(FrameType::StreamDataBlocked as u8);
                     frame.put_contents(&mut contents);
                 }
+                Frame::StreamReceipt(ref frame) => {
+                    buffer_unencrypted.put_u8(FrameType::StreamReceipt as u8);
+                    frame.put_contents(&mut contents);
+                }
                 Frame::Unknown(ref unknown_frame) => {
                     // The frame type u8 was stored and handled by UnknownFrameData
                     buffer_unencrypted.put_u8(unknown_frame.frame_type);
@@ -234,6 +240,16 @@ impl StreamPacket {
         encrypt(shared_secret, self.buffer_unencrypted)
     }
 
+    /// Same as [`into_encrypted`](#method.into_encrypted) with a fixed nonce, for test vectors
+    #[cfg(test)]
+    pub(crate) fn into_encrypted_with_nonce(
+        self,
+        shared_secret: &[u8],
+        nonce: [u8; NONCE_LENGTH],
+    ) -> BytesMut {
+        encrypt_with_nonce(shared_secret, self.buffer_unencrypted, nonce)
+    }
+
     /// The packet's sequence number
     pub fn sequence(&self) -> u64 {
         self.sequence
@@ -322,6 +338,9 @@ impl<'a> FrameIterator<'a> {
             FrameType::StreamDataBlocked => {
                 Frame::StreamDataBlocked(StreamDataBlockedFrame::read_contents(contents)?)
             }
+            FrameType::StreamReceipt => {
+                Frame::StreamReceipt(StreamReceiptFrame::read_contents(contents)?)
+            }
             FrameType::Unknown => {
                 warn!(
                     "Ignoring unknown frame of type {}: {:x?}",
@@ -385,6 +404,7 @@ pub enum Frame<'a> {
     StreamData(StreamDataFrame<'a>),
     StreamMaxData(StreamMaxDataFrame),
     StreamDataBlocked(StreamDataBlockedFrame),
+    StreamReceipt(StreamReceiptFrame<'a>),
     Unknown(UnknownFrameData<'a>),
 }
 
@@ -405,6 +425,7 @@ impl<'a> fmt::Debug for Frame<'a> {
             Frame::StreamData(frame) => write!(f, "{:?}", frame),
             Frame::StreamMaxData(frame) => write!(f, "{:?}", frame),
             Frame::StreamDataBlocked(frame) => write!(f, "{:?}", frame),
+            Frame::StreamReceipt(frame) => write!(f, "{:?}", frame),
             Frame::Unknown(unknown_data) => write!(f, "{:?}", unknown_data),
         }
     }
@@ -428,6 +449,7 @@ pub enum FrameType {
     StreamData = 0x14,
     StreamMaxData = 0x15,
     StreamDataBlocked = 0x16,
+    StreamReceipt = 0x17,
     Unknown,
 }
 
@@ -448,6 +470,7 @@ impl From<u8> for FrameType {
             0x14 => FrameType::StreamData,
             0x15 => FrameType::StreamMaxData,
             0x16 => FrameType::StreamDataBlocked,
+            0x17 => FrameType::StreamReceipt,
             _ => FrameType::Unknown,
         }
     }
@@ -963,6 +986,31 @@ impl<'a> SerializableFrame<'a> for StreamDataBlockedFrame {
     }
 }
 
+/// Proof of the total amount received on a stream, signed by the receiver with a secret
+/// shared with a third-party verifier. See [`Receipt`](../receipt/struct.Receipt.html).
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct StreamReceiptFrame<'a> {
+    /// Identifier of the stream this frame refers to.
+    pub stream_id: u64,
+    /// The serialized, signed receipt
+    pub receipt: &'a [u8],
+}
+
+impl<'a> SerializableFrame<'a> for StreamReceiptFrame<'a> {
+    fn read_contents(mut reader: &'a [u8]) -> Result<Self, StreamPacketError> {
+        let stream_id = reader.read_var_uint()?;
+        let receipt = reader.read_var_octet_string()?;
+        ensure_no_inner_trailing_bytes(reader)?;
+
+        Ok(StreamReceiptFrame { stream_id, receipt })
+    }
+
+    fn put_contents(&self, buf: &mut impl MutBufOerExt) {
+        buf.put_var_uint(self.stream_id);
+        buf.put_var_octet_string(self.receipt);
+    }
+}
+
 /// See: https://github.com/interledger/rfcs/blob/master/0029-stream/0029-stream.md#514-maximum-varuint-size
 fn saturating_read_var_uint<'a>(reader: &mut impl BufOerExt<'a>) -> Result<u64, StreamPacketError> {
     if reader.peek_var_octet_string()?.len() > 8 {
@@ -1274,6 +1322,32 @@ mod serialization {
         );
     }
 
+    #[test]
+    fn it_roundtrips_receipt_frames() {
+        let packet = StreamPacketBuilder {
+            sequence: 1,
+            ilp_packet_type: IlpPacketType::Fulfill,
+            prepare_amount: 99,
+            frames: &[Frame::StreamReceipt(StreamReceiptFrame {
+                stream_id: 1,
+                receipt: &[9; 59],
+            })],
+        }
+        .build();
+        assert_eq!(&packet.buffer_unencrypted[6..11], &[1, 1, 0x17, 62, 1]);
+
+        let parsed =
+            StreamPacket::from_bytes_unencrypted(packet.buffer_unencrypted.clone()).unwrap();
+        assert_eq!(parsed, packet);
+        assert_eq!(
+            parsed.frames().next().unwrap(),
+            Frame::StreamReceipt(StreamReceiptFrame {
+                stream_id: 1,
+                receipt: &[9; 59],
+            })
+        );
+    }
+
     #[test]
     fn it_serializes_to_same_as_javascript() {
         assert_eq!(PACKET.buffer_unencrypted, *SERIALIZED);
@@ -0,0 +1,199 @@
+use super::crypto::random_condition;
+use super::error::{PaymentProgress, StreamError};
+use super::packet::*;
+use super::server::AssetDetails;
+use bytes::BytesMut;
+use interledger_packet::{
+    Address, ErrorClass, ErrorCode as IlpErrorCode, MaxPacketAmountDetails,
+    PacketType as IlpPacketType, PrepareBuilder,
+};
+use interledger_service::{Account, IncomingRequest, IncomingService};
+use std::cmp::min;
+use std::convert::TryFrom;
+use std::time::{Duration, SystemTime};
+use tracing::debug;
+
+/// Source amounts of the test packets, capped at the amount of the payment
+const PROBE_AMOUNTS: [u64; 3] = [1_000, 100_000, 10_000_000];
+
+/// Exchange rate of the path to a STREAM receiver, measured by sending it a few test packets
+/// before the payment.
+///
+/// The test packets are unfulfillable, so no money moves: the receiver rejects each of them,
+/// telling the sender in its reply how much arrived.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RateProbe {
+    /// Amount delivered per unit sent, in the smallest units of both assets
+    pub rate: f64,
+    /// Largest source amount a packet may carry on the path, if a connector told us
+    pub max_packet_amount: Option<u64>,
+    /// The receiver's asset code, if it told us
+    pub destination_asset_code: Option<String>,
+    /// The receiver's asset scale, if it told us
+    pub destination_asset_scale: Option<u8>,
+    /// Source and delivered amounts of the test packets that reached the receiver
+    pub probes: Vec<(u64, u64)>,
+}
+
+impl RateProbe {
+    /// Least amount `source_amount` should deliver at the probed rate, allowing the rate to
+    /// get worse by the `slippage` fraction during the payment
+    pub fn min_delivery_amount(&self, source_amount: u64, slippage: f64) -> u64 {
+        let amount = source_amount as f64 * self.rate * (1.0 - slippage.clamp(0.0, 1.0));
+        // Saturates at u64::MAX
+        amount.floor() as u64
+    }
+}
+
+/// Measure the exchange rate of the path to a STREAM receiver, to set the
+/// [minimum delivery amount](./struct.SendMoneyOptions.html#structfield.min_delivery_amount)
+/// and the size of the packets of a payment of `source_amount` before sending it.
+///
+/// The estimate is based on the largest test packet which reached the receiver, since
+/// rounding distorts it the least. Fails if none of them did.
+pub async fn probe_rate<I, A>(
+    mut service: I,
+    from_account: &A,
+    destination_account: Address,
+    shared_secret: &[u8],
+    source_amount: u64,
+) -> Result<RateProbe, StreamError>
+where
+    I: IncomingService<A>,
+    A: Account,
+{
+    let mut probe = RateProbe::default();
+    let mut last_reject = None;
+    let mut attempts = 0;
+    let mut sequence = 0;
+
+    let mut amounts: Vec<u64> = PROBE_AMOUNTS
+        .iter()
+        .map(|&amount| min(amount, source_amount).max(1))
+        .collect();
+    amounts.dedup();
+
+    let mut next = 0;
+    while let Some(&amount) = amounts.get(next) {
+        next += 1;
+        if probe.max_packet_amount.is_some_and(|max| amount > max) {
+            continue;
+        }
+        sequence += 1;
+        attempts += 1;
+
+        // Only the first packet needs to exchange asset details with the receiver
+        let frames = if sequence == 1 {
+            vec![
+                Frame::ConnectionNewAddress(ConnectionNewAddressFrame {
+                    source_account: from_account.ilp_address().clone(),
+                }),
+                Frame::ConnectionAssetDetails(ConnectionAssetDetailsFrame {
+                    source_asset_code: from_account.asset_code(),
+                    source_asset_scale: from_account.asset_scale(),
+                }),
+            ]
+        } else {
+            Vec::new()
+        };
+        let prepare_data = StreamPacketBuilder {
+            ilp_packet_type: IlpPacketType::Prepare,
+            prepare_amount: 0,
+            sequence,
+            frames: &frames,
+        }
+        .build()
+        .into_encrypted(shared_secret);
+        let prepare = PrepareBuilder {
+            destination: destination_account.clone(),
+            amount,
+            execution_condition: &random_condition(),
+            expires_at: SystemTime::now() + Duration::from_secs(30),
+            data: &prepare_data[..],
+        }
+        .build();
+
+        let reject = match service
+            .handle_request(IncomingRequest {
+                from: from_account.clone(),
+                prepare,
+            })
+            .await
+        {
+            Err(reject) => reject,
+            // Nobody can fulfill it without the preimage of the random condition
+            Ok(_) => continue,
+        };
+
+        match StreamPacket::from_encrypted(shared_secret, BytesMut::from(reject.data())) {
+            Ok(reply) if reply.sequence() == sequence => {
+                for details in reply
+                    .frames()
+                    .filter_map(|frame| AssetDetails::from_frame(&frame))
+                {
+                    probe.destination_asset_code = Some(details.asset_code);
+                    probe.destination_asset_scale = Some(details.asset_scale);
+                }
+                debug!(
+                    "Probe of {} delivered {} to the receiver",
+                    amount,
+                    reply.prepare_amount()
+                );
+                probe.probes.push((amount, reply.prepare_amount()));
+            }
+            _ => {
+                if reject.code() == IlpErrorCode::F08_AMOUNT_TOO_LARGE {
+                    if let Ok(details) = MaxPacketAmountDetails::from_bytes(reject.data()) {
+                        // The limit is in the units of the connector that rejected the packet
+                        let max_packet_amount = u128::from(amount)
+                            * u128::from(details.max_amount())
+                            / u128::from(details.amount_received().max(1));
+                        let max_packet_amount =
+                            u64::try_from(max_packet_amount).unwrap_or(u64::MAX);
+                        if max_packet_amount > 0
+                            && probe
+                                .max_packet_amount
+                                .is_none_or(|max| max_packet_amount < max)
+                        {
+                            // Probe again with as much as the path allows
+                            probe.max_packet_amount = Some(max_packet_amount);
+                            amounts.push(max_packet_amount);
+                        }
+                    }
+                } else if reject.code().class() == ErrorClass::Final {
+                    // The path won't carry any packet to the receiver
+                    last_reject = Some(reject);
+                    break;
+                }
+                last_reject = Some(reject);
+            }
+        }
+    }
+
+    match probe.probes.iter().max_by_key(|(sent, _)| *sent) {
+        Some(&(sent, delivered)) => {
+            probe.rate = delivered as f64 / sent as f64;
+            debug!("Probed exchange rate of {}", probe.rate);
+            Ok(probe)
+        }
+        None => {
+            let progress = PaymentProgress {
+                attempts,
+                last_reject_code: last_reject.as_ref().map(|reject| reject.code()),
+                ..PaymentProgress::default()
+            };
+            Err(match last_reject {
+                Some(reject) => StreamError::from_reject(
+                    reject.code(),
+                    String::from_utf8_lossy(reject.message()).into_owned(),
+                    progress,
+                ),
+                None => StreamError::Unreachable {
+                    code: IlpErrorCode::F02_UNREACHABLE,
+                    message: "No test packet reached the receiver".to_string(),
+                    progress,
+                },
+            })
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use super::crypto::hmac_sha256;
+use super::error::ReceiptError;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use interledger_packet::{
+    oer::{BufOerExt, MutBufOerExt},
+    OerError,
+};
+use ring::hmac;
+
+/// Version of the receipt format produced by [`Receipt::sign`](./struct.Receipt.html#method.sign)
+pub const RECEIPT_VERSION: u8 = 1;
+/// Length of the nonce identifying the connection a receipt was issued for
+pub const RECEIPT_NONCE_LENGTH: usize = 16;
+/// Length of the secret the receiver and the verifier use to authenticate receipts
+pub const RECEIPT_SECRET_LENGTH: usize = 32;
+
+const RECEIPT_HMAC_LENGTH: usize = 32;
+
+/// Proof, signed by the receiver, of the total amount it received on a stream.
+///
+/// The verifier hands the receiver a nonce and a secret when the connection is set up (see
+/// [`ConnectionGenerator::generate_address_and_secret_with_receipts`](./struct.ConnectionGenerator.html#method.generate_address_and_secret_with_receipts)).
+/// The receiver then attaches a receipt to every packet it fulfills, which the sender can pass
+/// on to the verifier without being able to forge one itself.
+///
+/// Serialized as the version, the nonce, the stream id as a VarUInt, the total received as
+/// a UInt64 and the HMAC-SHA256 of all the preceding bytes keyed with the receipt secret.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Receipt {
+    /// Identifies the connection the receipt was issued for
+    pub nonce: [u8; RECEIPT_NONCE_LENGTH],
+    /// Identifier of the stream the money was received on
+    pub stream_id: u64,
+    /// Total amount received on the stream so far, in the receiver's units
+    pub total_received: u64,
+}
+
+impl Receipt {
+    /// Serialize and sign the receipt with the receipt secret
+    pub fn sign(&self, secret: &[u8; RECEIPT_SECRET_LENGTH]) -> Bytes {
+        let mut buf =
+            BytesMut::with_capacity(1 + RECEIPT_NONCE_LENGTH + 9 + 8 + RECEIPT_HMAC_LENGTH);
+        buf.put_u8(RECEIPT_VERSION);
+        buf.put_slice(&self.nonce);
+        buf.put_var_uint(self.stream_id);
+        buf.put_u64(self.total_received);
+        let hmac = hmac_sha256(&secret[..], &buf);
+        buf.put_slice(&hmac);
+        buf.freeze()
+    }
+
+    /// Parse a receipt *without* checking its HMAC.
+    ///
+    /// Useful to senders, which don't know the receipt secret, to keep track of the amount
+    /// the receiver claims to have received. Use [`verify`](#method.verify) before trusting it.
+    pub fn decode(mut reader: &[u8]) -> Result<Self, ReceiptError> {
+        if reader.is_empty() {
+            return Err(ReceiptError::Oer(OerError::UnexpectedEof));
+        }
+        let version = reader.get_u8();
+        if version != RECEIPT_VERSION {
+            return Err(ReceiptError::UnsupportedVersion(version));
+        }
+        if reader.remaining() < RECEIPT_NONCE_LENGTH {
+            return Err(ReceiptError::Oer(OerError::UnexpectedEof));
+        }
+        let mut nonce = [0; RECEIPT_NONCE_LENGTH];
+        reader.copy_to_slice(&mut nonce);
+        let stream_id = reader.read_var_uint()?;
+        if reader.remaining() < 8 + RECEIPT_HMAC_LENGTH {
+            return Err(ReceiptError::Oer(OerError::UnexpectedEof));
+        }
+        let total_received = reader.get_u64();
+        reader.advance(RECEIPT_HMAC_LENGTH);
+        if !reader.is_empty() {
+            return Err(ReceiptError::TrailingBytes);
+        }
+
+        Ok(Receipt {
+            nonce,
+            stream_id,
+            total_received,
+        })
+    }
+
+    /// Parse a receipt and check that it was signed with the given receipt secret
+    pub fn verify(secret: &[u8], receipt: &[u8]) -> Result<Self, ReceiptError> {
+        let decoded = Receipt::decode(receipt)?;
+        let (body, tag) = receipt.split_at(receipt.len() - RECEIPT_HMAC_LENGTH);
+        let key = hmac::Key::new(hmac::HMAC_SHA256, secret);
+        hmac::verify(&key, body, tag).map_err(|_| ReceiptError::InvalidHmac)?;
+        Ok(decoded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: [u8; 32] = [7; 32];
+
+    fn receipt() -> Receipt {
+        Receipt {
+            nonce: [1; 16],
+            stream_id: 1,
+            total_received: 500,
+        }
+    }
+
+    #[test]
+    fn roundtrips() {
+        let signed = receipt().sign(&SECRET);
+        assert_eq!(signed.len(), 59);
+        assert_eq!(Receipt::decode(&signed).unwrap(), receipt());
+        assert_eq!(Receipt::verify(&SECRET, &signed).unwrap(), receipt());
+    }
+
+    #[test]
+    fn rejects_tampered_receipts() {
+        let signed = receipt().sign(&SECRET);
+
+        // Bump the total received
+        let mut tampered = signed.to_vec();
+        tampered[26] += 1;
+        assert_eq!(Receipt::decode(&tampered).unwrap().total_received, 501);
+        assert!(matches!(
+            Receipt::verify(&SECRET, &tampered),
+            Err(ReceiptError::InvalidHmac)
+        ));
+
+        // Flip a bit of the HMAC
+        let mut tampered = signed.to_vec();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(matches!(
+            Receipt::verify(&SECRET, &tampered),
+            Err(ReceiptError::InvalidHmac)
+        ));
+
+        // Signed with another secret
+        assert!(matches!(
+            Receipt::verify(&[8; 32], &signed),
+            Err(ReceiptError::InvalidHmac)
+        ));
+    }
+
+    #[test]
+    fn rejects_malformed_receipts() {
+        let signed = receipt().sign(&SECRET);
+        assert!(matches!(
+            Receipt::verify(&SECRET, &signed[..signed.len() - 1]),
+            Err(ReceiptError::Oer(_))
+        ));
+        assert!(matches!(
+            Receipt::verify(&SECRET, &[signed.as_ref(), &[0]].concat()),
+            Err(ReceiptError::TrailingBytes)
+        ));
+
+        let mut wrong_version = signed.to_vec();
+        wrong_version[0] = 2;
+        assert!(matches!(
+            Receipt::verify(&SECRET, &wrong_version),
+            Err(ReceiptError::UnsupportedVersion(2))
+        ));
+    }
+}
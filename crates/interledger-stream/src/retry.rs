@@ -0,0 +1,133 @@
+use ring::rand::{SecureRandom, SystemRandom};
+use std::convert::TryFrom;
+use std::time::Duration;
+
+/// Further rejects don't grow the delay any more, so the backoff doesn't overflow
+const MAX_BACKOFF_EXPONENT: u32 = 1024;
+
+/// How the STREAM sender backs off when packets are rejected with temporary (`Txx`) errors.
+///
+/// After the `n`th temporary reject in a row, the sender waits
+/// `base_delay * backoff_multiplier^(n - 1)`, capped at `max_delay`, before sending its next
+/// packet, and fails the payment once `max_attempts` packets in a row were rejected. A fulfill
+/// resets the count.
+///
+/// Final (`Fxx`) and relative (`Rxx`) errors are not retried by the policy: they either stop
+/// the payment right away or, like `F08` and `F99`, are part of how STREAM discovers the path
+/// and the recipient's limits.
+///
+/// The default retries immediately and indefinitely, leaving it to the fail-fast and timeout
+/// checks to stop a payment that isn't getting through.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// Number of temporary rejects in a row after which the payment fails
+    pub max_attempts: u32,
+    /// Delay after the first temporary reject
+    pub base_delay: Duration,
+    /// Longest delay between two attempts
+    pub max_delay: Duration,
+    /// Factor the delay grows by with each further temporary reject
+    pub backoff_multiplier: f64,
+    /// Fraction of each delay, between 0 and 1, randomly taken off of it so that senders
+    /// sharing a congested path don't all retry at once
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: u32::MAX,
+            base_delay: Duration::from_secs(0),
+            max_delay: Duration::from_secs(5),
+            backoff_multiplier: 2.0,
+            jitter: 0.0,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Time to wait before the next attempt after the given number of temporary rejects in a row
+    pub fn delay(&self, rejects: u32) -> Duration {
+        let mut random = [0; 4];
+        let fraction = if self.jitter > 0.0 && SystemRandom::new().fill(&mut random).is_ok() {
+            f64::from(u32::from_be_bytes(random)) / f64::from(u32::MAX)
+        } else {
+            0.0
+        };
+        self.delay_with_jitter(rejects, fraction)
+    }
+
+    /// Delay with `random`, between 0 and 1, of the jitter applied
+    fn delay_with_jitter(&self, rejects: u32, random: f64) -> Duration {
+        if rejects == 0 || self.base_delay.is_zero() {
+            return Duration::from_secs(0);
+        }
+        let exponent = i32::try_from((rejects - 1).min(MAX_BACKOFF_EXPONENT)).unwrap_or(i32::MAX);
+        let backoff =
+            self.base_delay.as_secs_f64() * self.backoff_multiplier.max(1.0).powi(exponent);
+        let capped = backoff.min(self.max_delay.as_secs_f64());
+        // Also treats a NaN jitter as no jitter
+        let jitter = if self.jitter > 0.0 {
+            self.jitter.min(1.0)
+        } else {
+            0.0
+        };
+        // Durations too long to represent are capped too
+        Duration::try_from_secs_f64(capped * (1.0 - jitter * random)).unwrap_or(self.max_delay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(1000),
+            backoff_multiplier: 3.0,
+            jitter: 0.5,
+        }
+    }
+
+    #[test]
+    fn grows_delays_up_to_the_max() {
+        let delays: Vec<_> = (0..6)
+            .map(|rejects| policy().delay_with_jitter(rejects, 0.0).as_millis())
+            .collect();
+        assert_eq!(delays, vec![0, 100, 300, 900, 1000, 1000]);
+        assert_eq!(
+            policy().delay_with_jitter(u32::MAX, 0.0),
+            Duration::from_millis(1000)
+        );
+    }
+
+    #[test]
+    fn takes_jitter_off_the_delay() {
+        assert_eq!(policy().delay_with_jitter(2, 1.0).as_millis(), 150);
+        for _ in 0..100 {
+            let delay = policy().delay(2);
+            assert!(delay >= Duration::from_millis(150) && delay <= Duration::from_millis(300));
+        }
+    }
+
+    #[test]
+    fn defaults_to_retrying_immediately() {
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.delay(1), Duration::from_secs(0));
+        assert_eq!(policy.delay(1000), Duration::from_secs(0));
+        assert_eq!(policy.delay(u32::MAX), Duration::from_secs(0));
+    }
+
+    #[test]
+    fn caps_delays_at_a_huge_max_delay() {
+        let policy = RetryPolicy {
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::MAX,
+            ..RetryPolicy::default()
+        };
+        assert_eq!(policy.delay_with_jitter(u32::MAX, 0.0), Duration::MAX);
+        assert_eq!(policy.delay_with_jitter(2, 0.0), Duration::from_secs(2));
+    }
+}
@@ -1,19 +1,30 @@
 use super::crypto::*;
-use super::packet::*;
+use super::data::{
+    connection_max_offset, connection_window, max_data_frames, DataStream, MAX_DATA_PER_PACKET,
+};
+use super::packet::{ErrorCode as StreamErrorCode, *};
+use super::receipt::{Receipt, RECEIPT_NONCE_LENGTH, RECEIPT_SECRET_LENGTH};
 use async_trait::async_trait;
-use bytes::{Bytes, BytesMut};
+use bytes::{BufMut, Bytes, BytesMut};
 use chrono::{DateTime, Utc};
 use futures::channel::mpsc::UnboundedSender;
+use interledger_ildcp::MAX_ASSET_SCALE;
 use interledger_packet::{
-    hex::HexString, Address, ErrorCode, Fulfill, FulfillBuilder, PacketType as IlpPacketType,
-    Prepare, Reject, RejectBuilder,
+    hex::HexString, Address, Amount, ErrorCode, Fulfill, FulfillBuilder,
+    PacketType as IlpPacketType, Prepare, Reject, RejectBuilder,
 };
 use interledger_service::{Account, IlpResult, OutgoingRequest, OutgoingService, Username};
+use parking_lot::Mutex;
+use rand::{rngs::OsRng, CryptoRng, RngCore};
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::marker::PhantomData;
-use std::time::SystemTime;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 use tokio::sync::broadcast;
-use tracing::debug;
+use tracing::{debug, warn};
 use uuid::Uuid;
 
 // Note we are using the same magic bytes as the Javascript
@@ -25,6 +36,34 @@ use uuid::Uuid;
 // this string is.
 const STREAM_SERVER_SECRET_GENERATOR: &[u8] = b"ilp_stream_shared_secret";
 
+/// Length of the random token at the start of the destination account's local part
+const TOKEN_LENGTH: usize = 18;
+
+/// Largest sequence number accepted in a packet. Senders must close the connection before
+/// sending more packets than this, as it isn't safe to encrypt many more with the same key
+const MAX_SEQUENCE: u64 = 1 << 31;
+
+/// Number of sequence numbers up to the highest one received on a connection which are
+/// remembered. Packets with older ones are treated as replays.
+const REPLAY_WINDOW: u64 = 1024;
+
+/// Time after which a connection that delivered no money is forgotten. Well beyond how long
+/// a Prepare may be in flight, so its packets can't be replayed once they are forgotten.
+const REPLAY_WINDOW_IDLE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// A CSPRNG that can be shared between the clones of a [`ConnectionGenerator`]
+trait ConnectionRng: RngCore + CryptoRng + Send {}
+
+impl<R: RngCore + CryptoRng + Send> ConnectionRng for R {}
+
+/// Where the random parts of the connections a [`ConnectionGenerator`] creates come from
+#[derive(Clone)]
+enum Entropy {
+    Rng(Arc<Mutex<dyn ConnectionRng>>),
+    /// Seed the random bytes are derived from, and the number of blocks derived from it so far
+    Seed([u8; 32], Arc<AtomicU64>),
+}
+
 /// A STREAM connection generator that creates `destination_account` and `shared_secret` values
 /// based on a single root secret.
 ///
@@ -33,25 +72,117 @@ const STREAM_SERVER_SECRET_GENERATOR: &[u8] = b"ilp_stream_shared_secret";
 #[derive(Clone)]
 pub struct ConnectionGenerator {
     secret_generator: [u8; 32],
+    entropy: Entropy,
 }
 
 impl ConnectionGenerator {
+    /// Creates a generator which draws the random parts of the connections from the
+    /// operating system's CSPRNG
     pub fn new(server_secret: Bytes) -> Self {
+        ConnectionGenerator::with_rng(server_secret, OsRng)
+    }
+
+    /// Same as [`new`](#method.new), but draws the random parts of the connections from `rng`,
+    /// e.g. for environments that require a specific CSPRNG.
+    ///
+    /// Only the addresses and shared secrets come from `rng`. STREAM packets are still
+    /// encrypted with nonces from ring's `SystemRandom`, and `rand` still links `getrandom`,
+    /// so this doesn't remove the dependency on the operating system's source, e.g. on WASM.
+    ///
+    /// The shared secrets are derived from the RNG's output, so anyone who can predict it can
+    /// decrypt the connections and claim the money sent over them. The RNG must be seeded
+    /// with enough entropy and must not be shared with anything that reveals its output.
+    /// The generator's clones share it.
+    pub fn with_rng<R>(server_secret: Bytes, rng: R) -> Self
+    where
+        R: RngCore + CryptoRng + Send + 'static,
+    {
         assert_eq!(server_secret.len(), 32, "Server secret must be 32 bytes");
 
         let secret = hmac_sha256(&server_secret[..], STREAM_SERVER_SECRET_GENERATOR);
 
         ConnectionGenerator {
             secret_generator: secret,
+            entropy: Entropy::Rng(Arc::new(Mutex::new(rng))),
         }
     }
 
+    /// Same as [`new`](#method.new), but derives the random parts of the connections from
+    /// `seed` instead of the system's CSPRNG, so that a generator created with the same server
+    /// secret and seed always hands out the same sequence of addresses and shared secrets.
+    ///
+    /// Meant for reproducible tests and conformance checks against other implementations.
+    /// Anyone who knows the seed can predict the shared secrets, so never use it in production.
+    pub fn with_seed(server_secret: Bytes, seed: [u8; 32]) -> Self {
+        ConnectionGenerator {
+            entropy: Entropy::Seed(seed, Arc::new(AtomicU64::new(0))),
+            ..ConnectionGenerator::new(server_secret)
+        }
+    }
+
+    /// The next 32 random bytes, or bytes derived from the seed if there is one
+    fn next_random_bytes(&self) -> [u8; 32] {
+        match &self.entropy {
+            Entropy::Rng(rng) => {
+                let mut bytes = [0; 32];
+                rng.lock()
+                    .try_fill_bytes(&mut bytes)
+                    .expect("Failed to securely generate random bytes!");
+                bytes
+            }
+            Entropy::Seed(seed, count) => {
+                let count = count.fetch_add(1, Ordering::Relaxed);
+                hmac_sha256(&seed[..], &count.to_be_bytes())
+            }
+        }
+    }
+
+    fn generate_token(&self) -> [u8; TOKEN_LENGTH] {
+        let mut token = [0; TOKEN_LENGTH];
+        token.copy_from_slice(&self.next_random_bytes()[..TOKEN_LENGTH]);
+        token
+    }
+
     /// Generate the STREAM parameters for the given ILP address and the configured server secret.
     ///
     /// The `destination_account` is generated such that the `shared_secret` can be re-derived
     /// from a Prepare packet's destination and the same server secret.
     pub fn generate_address_and_secret(&self, base_address: &Address) -> (Address, [u8; 32]) {
-        let token = base64::encode_config(generate_token(), base64::URL_SAFE_NO_PAD);
+        self.generate_address_and_secret_from_token(base_address, &self.generate_token())
+    }
+
+    /// Generate the STREAM parameters for a connection whose receiver signs a
+    /// [`Receipt`](./struct.Receipt.html) for every packet it fulfills.
+    ///
+    /// The receipt nonce and secret are encrypted into the `destination_account`, so the
+    /// receiver can recover them with [`rederive_receipt_details`](#method.rederive_receipt_details)
+    /// without storing anything. Whoever verifies the receipts must keep the secret.
+    pub fn generate_address_and_secret_with_receipts(
+        &self,
+        base_address: &Address,
+        receipt_nonce: [u8; RECEIPT_NONCE_LENGTH],
+        receipt_secret: [u8; RECEIPT_SECRET_LENGTH],
+    ) -> (Address, [u8; 32]) {
+        let mut receipt_details =
+            BytesMut::with_capacity(RECEIPT_NONCE_LENGTH + RECEIPT_SECRET_LENGTH);
+        receipt_details.put_slice(&receipt_nonce);
+        receipt_details.put_slice(&receipt_secret);
+
+        let mut token = BytesMut::from(&self.generate_token()[..]);
+        let mut nonce = [0; NONCE_LENGTH];
+        nonce.copy_from_slice(&self.next_random_bytes()[..NONCE_LENGTH]);
+        let encrypted_details =
+            encrypt_with_nonce(&self.secret_generator[..], receipt_details, nonce);
+        token.unsplit(encrypted_details);
+        self.generate_address_and_secret_from_token(base_address, &token)
+    }
+
+    fn generate_address_and_secret_from_token(
+        &self,
+        base_address: &Address,
+        token: &[u8],
+    ) -> (Address, [u8; 32]) {
+        let token = base64::encode_config(token, base64::URL_SAFE_NO_PAD);
         // Note the shared secret is generated from the base64-encoded version of the token,
         // rather than from the unencoded bytes
         let shared_secret = hmac_sha256(&self.secret_generator[..], token.as_bytes());
@@ -82,10 +213,331 @@ impl ConnectionGenerator {
         // rather than decoding the base64 first.
         hmac_sha256(&self.secret_generator[..], local_part.as_bytes())
     }
+
+    /// Recover the receipt nonce and secret from a `destination_account` generated by
+    /// [`generate_address_and_secret_with_receipts`](#method.generate_address_and_secret_with_receipts).
+    ///
+    /// Returns `None` if the connection was set up without receipts.
+    pub fn rederive_receipt_details(
+        &self,
+        destination_account: &Address,
+    ) -> Option<([u8; RECEIPT_NONCE_LENGTH], [u8; RECEIPT_SECRET_LENGTH])> {
+        let local_part = destination_account.segments().next_back()?;
+        let token = base64::decode_config(local_part, base64::URL_SAFE_NO_PAD).ok()?;
+        if token.len() <= TOKEN_LENGTH {
+            return None;
+        }
+        let receipt_details = decrypt(
+            &self.secret_generator[..],
+            BytesMut::from(&token[TOKEN_LENGTH..]),
+        )
+        .ok()?;
+        if receipt_details.len() != RECEIPT_NONCE_LENGTH + RECEIPT_SECRET_LENGTH {
+            return None;
+        }
+
+        let mut nonce = [0; RECEIPT_NONCE_LENGTH];
+        nonce.copy_from_slice(&receipt_details[..RECEIPT_NONCE_LENGTH]);
+        let mut secret = [0; RECEIPT_SECRET_LENGTH];
+        secret.copy_from_slice(&receipt_details[RECEIPT_NONCE_LENGTH..]);
+        Some((nonce, secret))
+    }
+}
+
+/// Receipt nonce of a connection and id of one of its streams
+type ReceiptStream = ([u8; RECEIPT_NONCE_LENGTH], u64);
+
+/// Totals received on each stream of the connections that asked for receipts, along with
+/// when the stream last received money
+#[derive(Default)]
+struct ReceiptTotals(Mutex<HashMap<ReceiptStream, (u64, Instant)>>);
+
+impl ReceiptTotals {
+    /// Adds the amount to the total of the stream and returns the new total. Forgets the
+    /// streams idle for longer than `REPLAY_WINDOW_IDLE_TIMEOUT` whenever a new one receives
+    /// money.
+    fn add(
+        &self,
+        nonce: &[u8; RECEIPT_NONCE_LENGTH],
+        stream_id: u64,
+        amount: u64,
+        now: Instant,
+    ) -> u64 {
+        let mut totals = self.0.lock();
+        let key = (*nonce, stream_id);
+        if !totals.contains_key(&key) {
+            totals.retain(|_, (_, last_seen)| {
+                now.saturating_duration_since(*last_seen) < REPLAY_WINDOW_IDLE_TIMEOUT
+            });
+        }
+        let (total, last_seen) = totals.entry(key).or_insert((0, now));
+        *total = total.saturating_add(amount);
+        *last_seen = now;
+        *total
+    }
+
+    /// Forgets the totals of every stream of the connection
+    fn remove(&self, nonce: &[u8; RECEIPT_NONCE_LENGTH]) {
+        self.0
+            .lock()
+            .retain(|(connection, _), _| connection != nonce);
+    }
+}
+
+/// Byte streams the application accepted, keyed by the shared secret of their connection
+type DataStreams = Mutex<HashMap<[u8; 32], Vec<DataStream>>>;
+
+/// Asset details the senders announced in packets that were fulfilled, keyed by the shared
+/// secret of their connection, along with when the connection last had a packet fulfilled
+#[derive(Default)]
+struct RemoteAssets(Mutex<HashMap<[u8; 32], (AssetDetails, Instant)>>);
+
+impl RemoteAssets {
+    /// Records the details announced on the connection. Forgets the connections idle for
+    /// longer than `REPLAY_WINDOW_IDLE_TIMEOUT` whenever a new one announces its details.
+    fn insert(&self, shared_secret: &[u8; 32], details: AssetDetails, now: Instant) {
+        let mut assets = self.0.lock();
+        if !assets.contains_key(shared_secret) {
+            assets.retain(|_, (_, last_seen)| {
+                now.saturating_duration_since(*last_seen) < REPLAY_WINDOW_IDLE_TIMEOUT
+            });
+        }
+        assets.insert(*shared_secret, (details, now));
+    }
+
+    fn get(&self, shared_secret: &[u8; 32]) -> Option<AssetDetails> {
+        self.0
+            .lock()
+            .get(shared_secret)
+            .map(|(details, _)| details.clone())
+    }
+
+    /// The details announced on the connection, which had another packet fulfilled
+    fn fulfilled(&self, shared_secret: &[u8; 32], now: Instant) -> Option<AssetDetails> {
+        self.0
+            .lock()
+            .get_mut(shared_secret)
+            .map(|(details, last_seen)| {
+                *last_seen = now;
+                details.clone()
+            })
+    }
+
+    fn remove(&self, shared_secret: &[u8; 32]) {
+        self.0.lock().remove(shared_secret);
+    }
+}
+
+/// Most the application lets each limited stream receive and what it received so far, keyed by
+/// the shared secret of their connection and the stream id
+#[derive(Default)]
+struct ReceiveLimits(Mutex<HashMap<([u8; 32], u64), ReceiveLimit>>);
+
+#[derive(Clone, Copy, Debug, Default)]
+struct ReceiveLimit {
+    receive_max: u64,
+    total_received: u64,
+}
+
+impl ReceiveLimits {
+    fn set(&self, shared_secret: &[u8; 32], stream_id: u64, receive_max: u64) {
+        self.0
+            .lock()
+            .entry((*shared_secret, stream_id))
+            .or_default()
+            .receive_max = receive_max;
+    }
+
+    fn get(&self, shared_secret: &[u8; 32], stream_id: u64) -> Option<ReceiveLimit> {
+        self.0.lock().get(&(*shared_secret, stream_id)).copied()
+    }
+
+    /// Credits the limited streams with their amounts, unless one of them would go over its
+    /// limit, in which case nothing is credited and false is returned
+    fn try_receive(&self, shared_secret: &[u8; 32], amounts: &[(u64, u64)]) -> bool {
+        let mut limits = self.0.lock();
+        let fits = amounts.iter().all(|&(stream_id, amount)| {
+            limits
+                .get(&(*shared_secret, stream_id))
+                .is_none_or(|limit| {
+                    limit.total_received.saturating_add(amount) <= limit.receive_max
+                })
+        });
+        if fits {
+            for &(stream_id, amount) in amounts {
+                if let Some(limit) = limits.get_mut(&(*shared_secret, stream_id)) {
+                    limit.total_received += amount;
+                }
+            }
+        }
+        fits
+    }
+
+    fn remove(&self, shared_secret: &[u8; 32]) {
+        self.0
+            .lock()
+            .retain(|(connection, _), _| connection != shared_secret);
+    }
+}
+
+/// Sequence numbers of the packets that delivered money, keyed by the shared secret of their
+/// connection. Packets without money may reuse them, as probes and data streams do.
+#[derive(Default)]
+struct ReceivedSequences(Mutex<HashMap<[u8; 32], ReplayWindow>>);
+
+/// The highest sequence number that delivered money on a connection, and which of the
+/// `REPLAY_WINDOW` ones up to it did
+struct ReplayWindow {
+    highest: u64,
+    /// Bit `sequence % REPLAY_WINDOW` is set if the sequence number is in the window and
+    /// delivered money
+    received: [u64; (REPLAY_WINDOW / 64) as usize],
+    last_seen: Instant,
+}
+
+impl ReceivedSequences {
+    /// Records that the packet with the given sequence number delivered money, returning
+    /// false if one already did or the sequence number is too old to tell. Forgets the
+    /// connections idle for longer than `REPLAY_WINDOW_IDLE_TIMEOUT` whenever a new one
+    /// delivers money.
+    fn insert(&self, shared_secret: &[u8; 32], sequence: u64, now: Instant) -> bool {
+        let mut windows = self.0.lock();
+        if !windows.contains_key(shared_secret) {
+            windows.retain(|_, window| {
+                now.saturating_duration_since(window.last_seen) < REPLAY_WINDOW_IDLE_TIMEOUT
+            });
+        }
+        windows
+            .entry(*shared_secret)
+            .or_insert_with(|| ReplayWindow {
+                highest: 0,
+                received: [0; (REPLAY_WINDOW / 64) as usize],
+                last_seen: now,
+            })
+            .insert(sequence, now)
+    }
+
+    fn remove(&self, shared_secret: &[u8; 32]) {
+        self.0.lock().remove(shared_secret);
+    }
+}
+
+impl ReplayWindow {
+    fn insert(&mut self, sequence: u64, now: Instant) -> bool {
+        self.last_seen = now;
+        if sequence > self.highest {
+            // Sequence numbers that slid into the window haven't been received yet
+            if sequence - self.highest >= REPLAY_WINDOW {
+                self.received = [0; (REPLAY_WINDOW / 64) as usize];
+            } else {
+                for skipped in self.highest + 1..sequence {
+                    self.set(skipped, false);
+                }
+            }
+            self.highest = sequence;
+        } else if self.highest - sequence >= REPLAY_WINDOW || self.get(sequence) {
+            return false;
+        }
+        self.set(sequence, true);
+        true
+    }
+
+    fn get(&self, sequence: u64) -> bool {
+        let bit = sequence % REPLAY_WINDOW;
+        self.received[(bit / 64) as usize] & (1 << (bit % 64)) != 0
+    }
+
+    fn set(&mut self, sequence: u64, received: bool) {
+        let bit = sequence % REPLAY_WINDOW;
+        let word = &mut self.received[(bit / 64) as usize];
+        if received {
+            *word |= 1 << (bit % 64);
+        } else {
+            *word &= !(1 << (bit % 64));
+        }
+    }
+}
+
+/// Asset code and scale an endpoint announced with a `ConnectionAssetDetails` frame
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct AssetDetails {
+    pub asset_code: String,
+    pub asset_scale: u8,
+}
+
+impl AssetDetails {
+    /// The details in the frame, or `None` if it is a different frame or its asset scale is
+    /// out of bounds
+    pub(crate) fn from_frame(frame: &Frame) -> Option<Self> {
+        match frame {
+            Frame::ConnectionAssetDetails(frame) if frame.source_asset_scale > MAX_ASSET_SCALE => {
+                warn!(
+                    "Ignoring remote asset details: asset scale {} is not between 0 and {}",
+                    frame.source_asset_scale, MAX_ASSET_SCALE
+                );
+                None
+            }
+            Frame::ConnectionAssetDetails(frame) => Some(AssetDetails {
+                asset_code: frame.source_asset_code.to_string(),
+                asset_scale: frame.source_asset_scale,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Splits the amount of a packet between the streams by their shares, the last one taking the
+/// remainder. Returns the stream ids with their amounts.
+fn split_amount(amount: u64, shares: &[(u64, u64)]) -> Vec<(u64, u64)> {
+    let total_shares: Amount = shares.iter().map(|&(_, share)| share).sum();
+    let mut left = amount;
+    shares
+        .iter()
+        .enumerate()
+        .map(|(i, &(stream_id, share))| {
+            let received = if i + 1 == shares.len() || total_shares.value() == 0 {
+                left
+            } else {
+                (u128::from(amount) * u128::from(share) / total_shares.value()) as u64
+            };
+            left -= received;
+            (stream_id, received)
+        })
+        .collect()
+}
+
+/// Signs receipts for the packets fulfilled on a connection set up with receipts
+struct ReceiptIssuer<'a> {
+    nonce: [u8; RECEIPT_NONCE_LENGTH],
+    secret: [u8; RECEIPT_SECRET_LENGTH],
+    totals: &'a ReceiptTotals,
+}
+
+impl ReceiptIssuer<'_> {
+    /// Credit the streams with the amounts they received and sign a receipt for the new total
+    /// of each. Returns the stream ids with their totals and receipts.
+    fn issue(&self, amounts: &[(u64, u64)]) -> Vec<(u64, u64, Bytes)> {
+        let now = Instant::now();
+        amounts
+            .iter()
+            .map(|&(stream_id, received)| {
+                let total_received = self.totals.add(&self.nonce, stream_id, received, now);
+                let receipt = Receipt {
+                    nonce: self.nonce,
+                    stream_id,
+                    total_received,
+                }
+                .sign(&self.secret);
+                (stream_id, total_received, receipt)
+            })
+            .collect()
+    }
 }
 
 /// Notification that STREAM fulfilled a packet and received a single Interledger payment, used by Pubsub API consumers
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
 pub struct PaymentNotification {
     /// The username of the account that received the Interledger payment
     pub to_username: Username,
@@ -100,15 +552,94 @@ pub struct PaymentNotification {
     /// The sequence number of the packet
     pub sequence: u64,
     /// Whether or not a packet signifying closing the connection was received.
-    /// In that case, the PaymentNotification will have `amount: 0`
-    /// and `connection_closed: true`.
+    /// Such packets usually carry no money, in which case the PaymentNotification
+    /// will have `amount: 0` and `connection_closed: true`.
     pub connection_closed: bool,
+    /// The [STREAM error code](https://interledger.org/rfcs/0029-stream/#54-error-codes)
+    /// the sender closed the connection with, `1` if it finished normally
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub close_code: Option<u8>,
+    /// The message the sender closed the connection with
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub close_message: Option<String>,
+    /// The sender's asset code and scale, once it announced them on the connection
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub source_asset: Option<AssetDetails>,
+}
+
+impl PaymentNotification {
+    /// Whether the sender closed the connection because something went wrong
+    pub fn closed_with_error(&self) -> bool {
+        matches!(self.close_code, Some(code) if StreamErrorCode::from(code) != StreamErrorCode::NoError)
+    }
+}
+
+/// Notification about a single account, streamed to the subscribers of that account's
+/// notifications. Serialized with a `type` field naming the variant.
+#[derive(Clone, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Deserialize, Serialize),
+    serde(tag = "type", rename_all = "snake_case")
+)]
+pub enum AccountNotification {
+    /// A STREAM payment to the account was fulfilled
+    Payment(PaymentNotification),
+    /// A settlement from the peer was credited to the account
+    IncomingSettlement {
+        account_id: Uuid,
+        /// The amount credited, in the account's asset scale
+        amount: u64,
+        /// The account's balance (including prepaid amount) after the settlement
+        balance: i64,
+        /// The time this notification was fired in RFC3339 format
+        timestamp: String,
+    },
+    /// The account's balance changed, e.g. because a packet was prepared, fulfilled or rejected
+    BalanceChange {
+        account_id: Uuid,
+        /// The account's balance (including prepaid amount) after the change
+        balance: i64,
+        /// The time this notification was fired in RFC3339 format
+        timestamp: String,
+    },
+}
+
+impl AccountNotification {
+    pub fn incoming_settlement(account_id: Uuid, amount: u64, balance: i64) -> Self {
+        AccountNotification::IncomingSettlement {
+            account_id,
+            amount,
+            balance,
+            timestamp: DateTime::<Utc>::from(SystemTime::now()).to_rfc3339(),
+        }
+    }
+
+    pub fn balance_change(account_id: Uuid, balance: i64) -> Self {
+        AccountNotification::BalanceChange {
+            account_id,
+            balance,
+            timestamp: DateTime::<Utc>::from(SystemTime::now()).to_rfc3339(),
+        }
+    }
 }
 
 /// The Ok(ReceiveOk) variant of receive_money(...) return result
 struct ReceiveOk {
     fulfill: Fulfill,
     sequence: u64,
+    connection_close: Option<(StreamErrorCode, String)>,
+    /// Asset details the sender announced in this packet
+    remote_asset: Option<AssetDetails>,
 }
 
 /// The Err(ReceiveErr) variant of receive_money(...) return result
@@ -122,11 +653,17 @@ enum ReceiveErr {
     InvalidPacket,
 
     /// We definitely reject and terminate processing of this transaction.
-    Rejection {
-        reject: Reject,
-        sequence: u64,
-        connection_closed: bool,
-    },
+    Rejection(Box<Rejection>),
+}
+
+/// The rejection in the Err(ReceiveErr::Rejection) variant, boxed so the result stays small
+#[derive(Debug)]
+struct Rejection {
+    reject: Reject,
+    sequence: u64,
+    connection_close: Option<(StreamErrorCode, String)>,
+    /// Asset details the sender announced in this packet
+    remote_asset: Option<AssetDetails>,
 }
 
 /// A trait representing the Publish side of a pub/sub store
@@ -148,18 +685,45 @@ pub trait StreamNotificationsStore {
     fn all_payment_subscription(&self) -> broadcast::Receiver<PaymentNotification>;
 }
 
+/// A store that publishes every notification concerning an account (payments received,
+/// incoming settlements and balance changes) to that account's subscribers.
+///
+/// Payments published with [`StreamNotificationsStore::publish_payment_notification`]
+/// are delivered to these subscribers as [`AccountNotification::Payment`].
+pub trait AccountNotificationsStore {
+    /// *Synchronously* saves the sending side of a subscriber to the account's notifications.
+    /// An account can have any number of subscribers; closed ones are dropped on the next publish.
+    fn add_account_notification_subscription(
+        &self,
+        account_id: Uuid,
+        sender: UnboundedSender<AccountNotification>,
+    );
+
+    /// Publishes the notification to all subscribers of the account
+    fn publish_account_notification(&self, account_id: Uuid, notification: AccountNotification);
+}
+
 /// An OutgoingService that fulfills incoming STREAM packets.
 ///
 /// Note this does **not** maintain STREAM state, but instead fulfills
-/// all incoming packets to collect the money.
-///
-/// This does not currently support handling data sent via STREAM.
+/// all incoming packets to collect the money. The only exceptions are connections
+/// set up with receipts, the byte streams accepted with [`accept_data`](#method.accept_data),
+/// and the streams limited with [`set_receive_max`](#method.set_receive_max). It also remembers
+/// the total received on each stream of the connections that asked for receipts, the asset
+/// details each sender announced in a fulfilled packet, and a window of the latest sequence
+/// numbers of the packets that delivered money, so replayed packets aren't credited twice,
+/// until the connection closes or stops having packets fulfilled for a while.
 #[derive(Clone)]
 pub struct StreamReceiverService<S, O: OutgoingService<A>, A: Account> {
     connection_generator: ConnectionGenerator,
     next: O,
     account_type: PhantomData<A>,
     store: S,
+    receipt_totals: Arc<ReceiptTotals>,
+    data_streams: Arc<DataStreams>,
+    remote_assets: Arc<RemoteAssets>,
+    received_sequences: Arc<ReceivedSequences>,
+    receive_limits: Arc<ReceiveLimits>,
 }
 
 impl<S, O, A> StreamReceiverService<S, O, A>
@@ -175,8 +739,65 @@ where
             next,
             account_type: PhantomData,
             store,
+            receipt_totals: Arc::new(ReceiptTotals::default()),
+            data_streams: Arc::new(Mutex::new(HashMap::new())),
+            remote_assets: Arc::new(RemoteAssets::default()),
+            received_sequences: Arc::new(ReceivedSequences::default()),
+            receive_limits: Arc::new(ReceiveLimits::default()),
         }
     }
+
+    /// Asset code and scale the sender announced on the connection to `destination_account`,
+    /// if it did in a fulfilled packet and hasn't closed or left the connection idle since
+    pub fn remote_asset_details(&self, destination_account: &Address) -> Option<AssetDetails> {
+        let shared_secret = self
+            .connection_generator
+            .rederive_secret(destination_account);
+        self.remote_assets.get(&shared_secret)
+    }
+
+    /// Limit the money the sender may send on one stream of the connection to
+    /// `destination_account` to `receive_max` in total, in our asset. Packets that would take
+    /// the stream over the limit are rejected, and every reply advertises the limit and what the
+    /// stream received so far in a `StreamMaxMoney` frame, so the sender can stay within it.
+    /// Other streams are unlimited. The limit can be raised or lowered while money is
+    /// being sent, and is forgotten when the sender closes the connection.
+    pub fn set_receive_max(&self, destination_account: &Address, stream_id: u64, receive_max: u64) {
+        let shared_secret = self
+            .connection_generator
+            .rederive_secret(destination_account);
+        self.receive_limits
+            .set(&shared_secret, stream_id, receive_max);
+    }
+
+    /// Total the stream of the connection to `destination_account` received, if it was limited
+    /// with [`set_receive_max`](#method.set_receive_max)
+    pub fn total_received(&self, destination_account: &Address, stream_id: u64) -> Option<u64> {
+        let shared_secret = self
+            .connection_generator
+            .rederive_secret(destination_account);
+        self.receive_limits
+            .get(&shared_secret, stream_id)
+            .map(|limit| limit.total_received)
+    }
+
+    /// Read and write the bytes the sender sends on one stream of the connection to
+    /// `destination_account`. Data sent on streams that weren't accepted is dropped.
+    ///
+    /// The receiver can't send packets of its own, so the bytes written to the stream go out
+    /// in the replies to the sender's packets, and are considered delivered once they're in one.
+    pub fn accept_data(&self, destination_account: &Address, stream_id: u64) -> DataStream {
+        let shared_secret = self
+            .connection_generator
+            .rederive_secret(destination_account);
+        let stream = DataStream::new(stream_id);
+        self.data_streams
+            .lock()
+            .entry(shared_secret)
+            .or_default()
+            .push(stream.clone());
+        stream
+    }
 }
 
 #[async_trait]
@@ -200,15 +821,77 @@ where
         // The case where the request is bound for this server
         if dest.starts_with(to_address.as_ref()) {
             let shared_secret = self.connection_generator.rederive_secret(&destination);
+            let receipt_details = self
+                .connection_generator
+                .rederive_receipt_details(&destination);
+            let receipt_nonce = receipt_details.map(|(nonce, _)| nonce);
+            let receipts = receipt_details.map(|(nonce, secret)| ReceiptIssuer {
+                nonce,
+                secret,
+                totals: &self.receipt_totals,
+            });
+            let data_streams = self
+                .data_streams
+                .lock()
+                .get(&shared_secret)
+                .cloned()
+                .unwrap_or_default();
             let response = receive_money(
                 &shared_secret,
                 to_address,
                 request.to.asset_code(),
                 request.to.asset_scale(),
                 &request.prepare,
+                receipts,
+                &data_streams,
+                &self.received_sequences,
+                &self.receive_limits,
             );
+            // Details are only remembered once a packet carrying them was fulfilled, so
+            // packets that are rejected can't make the receiver keep anything
+            let source_asset = match &response {
+                Ok(ReceiveOk {
+                    remote_asset: Some(details),
+                    ..
+                }) => {
+                    self.remote_assets
+                        .insert(&shared_secret, details.clone(), Instant::now());
+                    Some(details.clone())
+                }
+                Ok(_) => self.remote_assets.fulfilled(&shared_secret, Instant::now()),
+                Err(ReceiveErr::Rejection(rejection)) => rejection
+                    .remote_asset
+                    .clone()
+                    .or_else(|| self.remote_assets.get(&shared_secret)),
+                Err(ReceiveErr::InvalidPacket) => None,
+            };
+            if data_streams.iter().any(DataStream::is_finished) {
+                let mut all_data_streams = self.data_streams.lock();
+                if let Some(streams) = all_data_streams.get_mut(&shared_secret) {
+                    streams.retain(|stream| !stream.is_finished());
+                    if streams.is_empty() {
+                        all_data_streams.remove(&shared_secret);
+                    }
+                }
+            }
             match response {
-                Ok(ReceiveOk { fulfill, sequence }) => {
+                Ok(ReceiveOk {
+                    fulfill,
+                    sequence,
+                    connection_close,
+                    ..
+                }) => {
+                    if connection_close.is_some() {
+                        self.remote_assets.remove(&shared_secret);
+                        self.received_sequences.remove(&shared_secret);
+                        self.receive_limits.remove(&shared_secret);
+                        if let Some(nonce) = receipt_nonce {
+                            self.receipt_totals.remove(&nonce);
+                        }
+                    }
+                    let (close_code, close_message) = connection_close
+                        .map(|(code, message)| (u8::from(code), message))
+                        .unzip();
                     self.store
                         .publish_payment_notification(PaymentNotification {
                             to_username,
@@ -217,7 +900,10 @@ where
                             destination,
                             timestamp: DateTime::<Utc>::from(SystemTime::now()).to_rfc3339(),
                             sequence,
-                            connection_closed: false,
+                            connection_closed: close_code.is_some(),
+                            close_code,
+                            close_message,
+                            source_asset,
                         });
                     Ok(fulfill)
                 }
@@ -233,12 +919,20 @@ where
                     // </historical_comment>
                     self.next.send_request(request).await
                 }
-                Err(ReceiveErr::Rejection {
-                    reject,
-                    sequence,
-                    connection_closed,
-                }) => {
-                    if connection_closed {
+                Err(ReceiveErr::Rejection(rejection)) => {
+                    let Rejection {
+                        reject,
+                        sequence,
+                        connection_close,
+                        ..
+                    } = *rejection;
+                    if let Some((code, message)) = connection_close {
+                        self.remote_assets.remove(&shared_secret);
+                        self.received_sequences.remove(&shared_secret);
+                        self.receive_limits.remove(&shared_secret);
+                        if let Some(nonce) = receipt_nonce {
+                            self.receipt_totals.remove(&nonce);
+                        }
                         self.store
                             .publish_payment_notification(PaymentNotification {
                                 to_username,
@@ -248,6 +942,9 @@ where
                                 timestamp: DateTime::<Utc>::from(SystemTime::now()).to_rfc3339(),
                                 sequence,
                                 connection_closed: true,
+                                close_code: Some(code.into()),
+                                close_message: Some(message),
+                                source_asset,
                             });
                     }
 
@@ -260,8 +957,7 @@ where
     }
 }
 
-// TODO send asset code and scale back to sender also
-#[allow(clippy::cognitive_complexity)]
+#[allow(clippy::cognitive_complexity, clippy::too_many_arguments)]
 fn receive_money(
     shared_secret: &[u8; 32],
     // Our node's ILP Address ( we are the receiver, so we should return that
@@ -270,6 +966,10 @@ fn receive_money(
     asset_code: &str,
     asset_scale: u8,
     prepare: &Prepare,
+    receipts: Option<ReceiptIssuer>,
+    data_streams: &[DataStream],
+    received_sequences: &ReceivedSequences,
+    receive_limits: &ReceiveLimits,
 ) -> Result<ReceiveOk, ReceiveErr> {
     // Generate fulfillment
     let fulfillment = generate_fulfillment(&shared_secret[..], prepare.data());
@@ -285,19 +985,94 @@ fn receive_money(
     let stream_packet = StreamPacket::from_encrypted(shared_secret, copied_data)
         .map_err(|_| ReceiveErr::InvalidPacket)?;
 
+    // Pass the data on to the accepted streams and reply with what the application wrote,
+    // splitting the room in the reply and the sender's connection window between them
+    let mut data_accepted = true;
+    let window = connection_window(data_streams, connection_max_offset(stream_packet.frames()));
+    let max_data_len = MAX_DATA_PER_PACKET.min(window) / data_streams.len().max(1);
+    let outgoing_data: Vec<_> = data_streams
+        .iter()
+        .filter_map(|stream| {
+            data_accepted &= stream.handle_frames(stream_packet.frames());
+            let outgoing = stream.take_outgoing(max_data_len)?;
+            stream.sent(&outgoing);
+            Some((stream, outgoing))
+        })
+        .collect();
+    if !data_accepted {
+        debug!("Sender sent data on a stream it closed");
+    }
+
+    let sequence = stream_packet.sequence();
+    let is_sequence_in_range = sequence > 0 && sequence <= MAX_SEQUENCE;
+    if !is_sequence_in_range {
+        debug!(
+            "Sequence {} is not between 1 and {}",
+            sequence, MAX_SEQUENCE
+        );
+    }
+
+    let is_fulfilled = is_fulfillable
+        && is_sequence_in_range
+        && prepare_amount >= stream_packet.prepare_amount()
+        && data_accepted;
+
+    // Money is only credited once per sequence number, so replaying a fulfilled packet
+    // doesn't get it counted or signed for again
+    let is_replayed = is_fulfilled
+        && prepare_amount > 0
+        && !received_sequences.insert(shared_secret, sequence, Instant::now());
+    if is_replayed {
+        debug!("Packet with sequence {} was already fulfilled", sequence);
+    }
+    let is_fulfilled = is_fulfilled && !is_replayed;
+
+    // Split the money between the streams it was sent on, and stay within their limits
+    let shares: Vec<(u64, u64)> = stream_packet
+        .frames()
+        .filter_map(|frame| match frame {
+            Frame::StreamMoney(frame) => Some((frame.stream_id, frame.shares)),
+            _ => None,
+        })
+        .collect();
+    let amounts = split_amount(prepare_amount, &shares);
+    let is_over_limit =
+        is_fulfilled && prepare_amount > 0 && !receive_limits.try_receive(shared_secret, &amounts);
+    if is_over_limit {
+        debug!(
+            "Packet with sequence {} would exceed the receive max of a stream",
+            sequence
+        );
+    }
+    let is_fulfilled = is_fulfilled && !is_over_limit;
+
+    // Sign receipts for the new totals of the streams the money was sent on
+    let receipts = match receipts {
+        Some(issuer) if is_fulfilled => issuer.issue(&amounts),
+        _ => Vec::new(),
+    };
+
     let mut response_frames: Vec<Frame> = Vec::new();
-    let mut connection_closed = false;
+    let mut connection_close = None;
+    let mut remote_asset = None;
 
     // Handle STREAM frames
-    // TODO reject if they send data?
     for frame in stream_packet.frames() {
-        // Tell the sender the stream can handle lots of money
+        // Tell the sender how much more the stream can take. Unless the application limited
+        // the stream, it can handle lots of money and we only know what it received if we're
+        // signing receipts for it.
         if let Frame::StreamMoney(ref frame) = frame {
+            let limit = receive_limits.get(shared_secret, frame.stream_id);
+            let total_received = receipts
+                .iter()
+                .find(|(stream_id, ..)| *stream_id == frame.stream_id)
+                .map(|&(_, total_received, _)| total_received)
+                .or_else(|| limit.map(|limit| limit.total_received))
+                .unwrap_or(0);
             response_frames.push(Frame::StreamMaxMoney(StreamMaxMoneyFrame {
                 stream_id: frame.stream_id,
-                // TODO will returning zero here cause problems?
-                total_received: 0,
-                receive_max: u64::max_value(),
+                total_received,
+                receive_max: limit.map_or(u64::MAX, |limit| limit.receive_max),
             }));
         }
 
@@ -312,16 +1087,33 @@ fn receive_money(
             }));
         }
 
+        // The sender announces its own asset details along with its address
+        if let Some(details) = AssetDetails::from_frame(&frame) {
+            remote_asset = Some(details);
+        }
+
         // The last packet contains the ConnectionClose frame;
         // if this is the case, return this information to the caller
         // to be included in the payment notification
-        if let Frame::ConnectionClose(_) = frame {
-            connection_closed = true;
+        if let Frame::ConnectionClose(frame) = frame {
+            connection_close = Some((frame.code, frame.message.to_string()));
         }
     }
 
+    for (stream, outgoing) in &outgoing_data {
+        response_frames.extend(stream.frames(outgoing));
+    }
+    response_frames.extend(max_data_frames(data_streams));
+
+    for (stream_id, _, receipt) in &receipts {
+        response_frames.push(Frame::StreamReceipt(StreamReceiptFrame {
+            stream_id: *stream_id,
+            receipt,
+        }));
+    }
+
     // Return Fulfill or Reject Packet
-    if is_fulfillable && prepare_amount >= stream_packet.prepare_amount() {
+    if is_fulfilled {
         let response_packet = StreamPacketBuilder {
             sequence: stream_packet.sequence(),
             ilp_packet_type: IlpPacketType::Fulfill,
@@ -344,6 +1136,8 @@ fn receive_money(
         Ok(ReceiveOk {
             fulfill,
             sequence: stream_packet.sequence(),
+            connection_close,
+            remote_asset,
         })
     } else {
         let response_packet = StreamPacketBuilder {
@@ -374,17 +1168,19 @@ fn receive_money(
             data: &encrypted_response[..],
         }
         .build();
-        Err(ReceiveErr::Rejection {
+        Err(ReceiveErr::Rejection(Box::new(Rejection {
             reject,
             sequence: stream_packet.sequence(),
-            connection_closed,
-        })
+            connection_close,
+            remote_asset,
+        })))
     }
 }
 
 #[cfg(test)]
 mod connection_generator {
     use super::*;
+    use hex_literal::hex;
     use std::str::FromStr;
 
     #[test]
@@ -405,6 +1201,158 @@ mod connection_generator {
             shared_secret
         );
     }
+
+    #[test]
+    fn encrypts_receipt_details_into_address() {
+        let receiver_address = Address::from_str("example.receiver").unwrap();
+        let connection_generator = ConnectionGenerator::new(Bytes::from(&[9; 32][..]));
+        let (destination_account, shared_secret) = connection_generator
+            .generate_address_and_secret_with_receipts(&receiver_address, [1; 16], [2; 32]);
+
+        assert_eq!(
+            connection_generator.rederive_secret(&destination_account),
+            shared_secret
+        );
+        assert_eq!(
+            connection_generator.rederive_receipt_details(&destination_account),
+            Some(([1; 16], [2; 32]))
+        );
+
+        // Only the generator that created the address can read them
+        let other_generator = ConnectionGenerator::new(Bytes::from(&[8; 32][..]));
+        assert_eq!(
+            other_generator.rederive_receipt_details(&destination_account),
+            None
+        );
+
+        let (without_receipts, _) =
+            connection_generator.generate_address_and_secret(&receiver_address);
+        assert_eq!(
+            connection_generator.rederive_receipt_details(&without_receipts),
+            None
+        );
+    }
+
+    #[test]
+    fn reproduces_connections_from_a_seed() {
+        let receiver_address = Address::from_str("example.receiver").unwrap();
+        let server_secret = Bytes::from(&[1; 32][..]);
+        let first = ConnectionGenerator::with_seed(server_secret.clone(), [2; 32]);
+        let second = ConnectionGenerator::with_seed(server_secret.clone(), [2; 32]);
+
+        let (address, shared_secret) = first.generate_address_and_secret(&receiver_address);
+        assert_eq!(
+            (address.clone(), shared_secret),
+            second.generate_address_and_secret(&receiver_address)
+        );
+        assert_eq!(first.rederive_secret(&address), shared_secret);
+        assert_ne!(
+            first.generate_address_and_secret(&receiver_address),
+            (address.clone(), shared_secret)
+        );
+        assert_eq!(
+            address.to_string(),
+            "example.receiver.IMbLmkvM90HJbN4s7vRT3AAz"
+        );
+        assert_eq!(
+            shared_secret,
+            hex!("ad8c30f6979dcb7a71b512fdd0b9d70b695c35b3785ad85cc1326da7d8c98a39")
+        );
+
+        let packet = StreamPacketBuilder {
+            ilp_packet_type: IlpPacketType::Prepare,
+            prepare_amount: 100,
+            sequence: 1,
+            frames: &[Frame::StreamMoney(StreamMoneyFrame {
+                stream_id: 1,
+                shares: 1,
+            })],
+        }
+        .build();
+        let data = packet.into_encrypted_with_nonce(&shared_secret, [3; 12]);
+        let fulfillment = generate_fulfillment(&shared_secret, &data);
+        assert_eq!(
+            fulfillment,
+            hex!("9eafef40ed3ea30488d6f65a5a2035077a9b3437adbe77c1a05eb03cb01612c8")
+        );
+        assert_eq!(
+            generate_condition(&shared_secret, &data),
+            hex!("e4c7e597e014d5e588085ee3d0cfd74d574129fec64a27073c5584515e68424d")
+        );
+
+        let with_receipts = |generator: ConnectionGenerator| {
+            generator.generate_address_and_secret_with_receipts(&receiver_address, [4; 16], [5; 32])
+        };
+        assert_eq!(
+            with_receipts(ConnectionGenerator::with_seed(
+                server_secret.clone(),
+                [2; 32]
+            )),
+            with_receipts(ConnectionGenerator::with_seed(
+                server_secret.clone(),
+                [2; 32]
+            ))
+        );
+
+        // Without a seed, the connections are random
+        let random = ConnectionGenerator::new(server_secret);
+        assert_ne!(
+            random.generate_address_and_secret(&receiver_address),
+            random.generate_address_and_secret(&receiver_address)
+        );
+    }
+
+    /// Fills everything with the same byte
+    struct RepeatingRng(u8);
+
+    impl RngCore for RepeatingRng {
+        fn next_u32(&mut self) -> u32 {
+            u32::from_le_bytes([self.0; 4])
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            u64::from_le_bytes([self.0; 8])
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            dest.iter_mut().for_each(|byte| *byte = self.0);
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+            self.fill_bytes(dest);
+            Ok(())
+        }
+    }
+
+    impl CryptoRng for RepeatingRng {}
+
+    #[test]
+    fn derives_connections_from_the_given_rng() {
+        let receiver_address = Address::from_str("example.receiver").unwrap();
+        let server_secret = Bytes::from(&[1; 32][..]);
+        let generator = ConnectionGenerator::with_rng(server_secret.clone(), RepeatingRng(7));
+
+        let (address, shared_secret) = generator.generate_address_and_secret(&receiver_address);
+        let token = base64::encode_config([7; TOKEN_LENGTH], base64::URL_SAFE_NO_PAD);
+        assert_eq!(
+            address,
+            receiver_address.with_suffix(token.as_bytes()).unwrap()
+        );
+        assert_eq!(
+            shared_secret,
+            hmac_sha256(&generator.secret_generator, token.as_bytes())
+        );
+        assert_eq!(
+            generator.generate_address_and_secret(&receiver_address),
+            (address, shared_secret)
+        );
+
+        let other = ConnectionGenerator::with_rng(server_secret, RepeatingRng(8));
+        assert_ne!(
+            other.generate_address_and_secret(&receiver_address).1,
+            shared_secret
+        );
+    }
 }
 
 #[cfg(test)]
@@ -452,10 +1400,83 @@ mod receiving_money {
         .build();
 
         let shared_secret = connection_generator.rederive_secret(&prepare.destination());
-        let result = receive_money(&shared_secret, &ilp_address, "ABC", 9, &prepare);
+        let result = receive_money(
+            &shared_secret,
+            &ilp_address,
+            "ABC",
+            9,
+            &prepare,
+            None,
+            &[],
+            &ReceivedSequences::default(),
+            &ReceiveLimits::default(),
+        );
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn signs_receipts_for_running_totals() {
+        let ilp_address = Address::from_str("example.destination").unwrap();
+        let connection_generator = ConnectionGenerator::new(Bytes::from(&[1; 32][..]));
+        let (destination_account, shared_secret) = connection_generator
+            .generate_address_and_secret_with_receipts(&ilp_address, [3; 16], [4; 32]);
+        let (nonce, secret) = connection_generator
+            .rederive_receipt_details(&destination_account)
+            .unwrap();
+        let totals = ReceiptTotals::default();
+
+        for expected_total in &[100, 200] {
+            let data = test_stream_packet().into_encrypted(&shared_secret[..]);
+            let prepare = PrepareBuilder {
+                destination: destination_account.clone(),
+                amount: 100,
+                expires_at: UNIX_EPOCH,
+                data: &data[..],
+                execution_condition: &generate_condition(&shared_secret[..], &data),
+            }
+            .build();
+            let issuer = ReceiptIssuer {
+                nonce,
+                secret,
+                totals: &totals,
+            };
+
+            let fulfill = receive_money(
+                &shared_secret,
+                &ilp_address,
+                "ABC",
+                9,
+                &prepare,
+                Some(issuer),
+                &[],
+                &ReceivedSequences::default(),
+                &ReceiveLimits::default(),
+            )
+            .unwrap()
+            .fulfill;
+            let reply =
+                StreamPacket::from_encrypted(&shared_secret, BytesMut::from(fulfill.data()))
+                    .unwrap();
+            let receipt = reply
+                .frames()
+                .find_map(|frame| match frame {
+                    Frame::StreamReceipt(frame) => Some(Bytes::copy_from_slice(frame.receipt)),
+                    _ => None,
+                })
+                .unwrap();
+            let total_received = reply.frames().find_map(|frame| match frame {
+                Frame::StreamMaxMoney(frame) => Some(frame.total_received),
+                _ => None,
+            });
+            assert_eq!(total_received, Some(*expected_total));
+
+            let receipt = Receipt::verify(&[4; 32], &receipt).unwrap();
+            assert_eq!(receipt.nonce, [3; 16]);
+            assert_eq!(receipt.stream_id, 1);
+            assert_eq!(receipt.total_received, *expected_total);
+        }
+    }
+
     #[test]
     fn fulfills_valid_packet_without_connection_tag() {
         let ilp_address = Address::from_str("example.destination").unwrap();
@@ -477,10 +1498,291 @@ mod receiving_money {
         .build();
 
         let shared_secret = connection_generator.rederive_secret(&prepare.destination());
-        let result = receive_money(&shared_secret, &ilp_address, "ABC", 9, &prepare);
+        let result = receive_money(
+            &shared_secret,
+            &ilp_address,
+            "ABC",
+            9,
+            &prepare,
+            None,
+            &[],
+            &ReceivedSequences::default(),
+            &ReceiveLimits::default(),
+        );
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn rejects_replayed_sequence_numbers() {
+        let ilp_address = Address::from_str("example.destination").unwrap();
+        let connection_generator = ConnectionGenerator::new(Bytes::from(&[1; 32][..]));
+        let (destination_account, shared_secret) =
+            connection_generator.generate_address_and_secret(&ilp_address);
+        let received_sequences = ReceivedSequences::default();
+
+        let receive = |sequence: u64, amount: u64| {
+            let data = StreamPacketBuilder {
+                ilp_packet_type: IlpPacketType::Prepare,
+                prepare_amount: 0,
+                sequence,
+                frames: &[Frame::StreamMoney(StreamMoneyFrame {
+                    stream_id: 1,
+                    shares: 1,
+                })],
+            }
+            .build()
+            .into_encrypted(&shared_secret[..]);
+            let prepare = PrepareBuilder {
+                destination: destination_account.clone(),
+                amount,
+                expires_at: UNIX_EPOCH,
+                data: &data[..],
+                execution_condition: &generate_condition(&shared_secret[..], &data),
+            }
+            .build();
+            receive_money(
+                &shared_secret,
+                &ilp_address,
+                "ABC",
+                9,
+                &prepare,
+                None,
+                &[],
+                &received_sequences,
+                &ReceiveLimits::default(),
+            )
+            .map(|ok| ok.sequence)
+            .map_err(|err| match err {
+                ReceiveErr::Rejection(rejection) => rejection.reject.code(),
+                ReceiveErr::InvalidPacket => panic!("Packet should have been valid"),
+            })
+        };
+
+        assert_eq!(receive(1, 100), Ok(1));
+        assert_eq!(receive(1, 100), Err(ErrorCode::F99_APPLICATION_ERROR));
+        assert_eq!(receive(2, 100), Ok(2));
+        // Packets without money, like the ones carrying only data, may reuse them
+        assert_eq!(receive(2, 0), Ok(2));
+    }
+
+    #[test]
+    fn stays_within_the_receive_max() {
+        let ilp_address = Address::from_str("example.destination").unwrap();
+        let connection_generator = ConnectionGenerator::new(Bytes::from(&[1; 32][..]));
+        let (destination_account, shared_secret) =
+            connection_generator.generate_address_and_secret(&ilp_address);
+        let received_sequences = ReceivedSequences::default();
+        let receive_limits = ReceiveLimits::default();
+        receive_limits.set(&shared_secret, 1, 250);
+
+        // Returns whether the packet was fulfilled, and the total received and receive max
+        // the reply advertised
+        let receive = |sequence: u64, amount: u64| {
+            let data = StreamPacketBuilder {
+                ilp_packet_type: IlpPacketType::Prepare,
+                prepare_amount: 0,
+                sequence,
+                frames: &[Frame::StreamMoney(StreamMoneyFrame {
+                    stream_id: 1,
+                    shares: 1,
+                })],
+            }
+            .build()
+            .into_encrypted(&shared_secret[..]);
+            let prepare = PrepareBuilder {
+                destination: destination_account.clone(),
+                amount,
+                expires_at: UNIX_EPOCH,
+                data: &data[..],
+                execution_condition: &generate_condition(&shared_secret[..], &data),
+            }
+            .build();
+            let (fulfilled, data) = match receive_money(
+                &shared_secret,
+                &ilp_address,
+                "ABC",
+                9,
+                &prepare,
+                None,
+                &[],
+                &received_sequences,
+                &receive_limits,
+            ) {
+                Ok(ok) => (true, BytesMut::from(ok.fulfill.data())),
+                Err(ReceiveErr::Rejection(rejection)) => {
+                    (false, BytesMut::from(rejection.reject.data()))
+                }
+                Err(ReceiveErr::InvalidPacket) => panic!("Packet should have been valid"),
+            };
+            let reply = StreamPacket::from_encrypted(&shared_secret, data).unwrap();
+            let max_money = reply
+                .frames()
+                .find_map(|frame| match frame {
+                    Frame::StreamMaxMoney(frame) => Some((frame.total_received, frame.receive_max)),
+                    _ => None,
+                })
+                .unwrap();
+            (fulfilled, max_money)
+        };
+
+        assert_eq!(receive(1, 100), (true, (100, 250)));
+        assert_eq!(receive(2, 200), (false, (100, 250)));
+        assert_eq!(receive(3, 150), (true, (250, 250)));
+        // Packets without money still go through
+        assert_eq!(receive(4, 0), (true, (250, 250)));
+
+        // Raising the limit makes room again
+        receive_limits.set(&shared_secret, 1, 300);
+        assert_eq!(receive(5, 50), (true, (300, 300)));
+        assert_eq!(receive(6, 1), (false, (300, 300)));
+    }
+
+    #[test]
+    fn splits_money_between_streams_by_their_shares() {
+        assert_eq!(
+            split_amount(100, &[(1, 1), (2, 2), (3, 0)]),
+            vec![(1, 33), (2, 66), (3, 1)]
+        );
+        assert_eq!(split_amount(100, &[(1, 0), (2, 0)]), vec![(1, 100), (2, 0)]);
+        assert_eq!(split_amount(100, &[]), vec![]);
+    }
+
+    #[test]
+    fn remembers_a_bounded_window_of_sequence_numbers() {
+        let received_sequences = ReceivedSequences::default();
+        let now = Instant::now();
+
+        for sequence in 1..=5000 {
+            assert!(received_sequences.insert(&[1; 32], sequence, now));
+        }
+        // Within the window, received
+        assert!(!received_sequences.insert(&[1; 32], 5000, now));
+        assert!(!received_sequences.insert(&[1; 32], 4000, now));
+        // Too old to tell
+        assert!(!received_sequences.insert(&[1; 32], 1, now));
+
+        // Out of order, skipping some
+        assert!(received_sequences.insert(&[1; 32], 5010, now));
+        assert!(received_sequences.insert(&[1; 32], 5005, now));
+        assert!(!received_sequences.insert(&[1; 32], 5005, now));
+        // Far ahead, so the whole window is new
+        assert!(received_sequences.insert(&[1; 32], 10_000, now));
+        assert!(received_sequences.insert(&[1; 32], 10_000 - REPLAY_WINDOW + 1, now));
+        assert!(!received_sequences.insert(&[1; 32], 10_000 - REPLAY_WINDOW, now));
+    }
+
+    #[test]
+    fn forgets_idle_connections() {
+        let received_sequences = ReceivedSequences::default();
+        let start = Instant::now();
+
+        for connection in 0..100 {
+            received_sequences.insert(&[connection; 32], 1, start);
+        }
+        received_sequences.insert(&[0; 32], 2, start + REPLAY_WINDOW_IDLE_TIMEOUT / 2);
+        assert_eq!(received_sequences.0.lock().len(), 100);
+
+        // Starting a connection forgets the others that were idle for too long
+        received_sequences.insert(&[200; 32], 1, start + REPLAY_WINDOW_IDLE_TIMEOUT);
+        let windows = received_sequences.0.lock();
+        assert_eq!(windows.len(), 2);
+        assert!(windows.contains_key(&[0; 32]));
+        assert!(windows.contains_key(&[200; 32]));
+    }
+
+    #[test]
+    fn forgets_the_asset_details_of_idle_connections() {
+        let remote_assets = RemoteAssets::default();
+        let details = AssetDetails {
+            asset_code: "XYZ".to_string(),
+            asset_scale: 9,
+        };
+        let start = Instant::now();
+
+        for connection in 0..100 {
+            remote_assets.insert(&[connection; 32], details.clone(), start);
+        }
+        assert_eq!(
+            remote_assets.fulfilled(&[0; 32], start + REPLAY_WINDOW_IDLE_TIMEOUT / 2),
+            Some(details.clone())
+        );
+
+        // A new connection announcing its details forgets the others that were idle for too long
+        remote_assets.insert(&[200; 32], details, start + REPLAY_WINDOW_IDLE_TIMEOUT);
+        let assets = remote_assets.0.lock();
+        assert_eq!(assets.len(), 2);
+        assert!(assets.contains_key(&[0; 32]));
+        assert!(assets.contains_key(&[200; 32]));
+    }
+
+    #[test]
+    fn forgets_the_receipt_totals_of_idle_streams() {
+        let totals = ReceiptTotals::default();
+        let start = Instant::now();
+
+        for connection in 0..100 {
+            totals.add(&[connection; 16], 1, 100, start);
+        }
+        assert_eq!(
+            totals.add(&[0; 16], 1, 100, start + REPLAY_WINDOW_IDLE_TIMEOUT / 2),
+            200
+        );
+
+        // A new stream receiving money forgets the others that were idle for too long
+        totals.add(&[200; 16], 1, 100, start + REPLAY_WINDOW_IDLE_TIMEOUT);
+        let totals = totals.0.lock();
+        assert_eq!(totals.len(), 2);
+        assert!(totals.contains_key(&([0; 16], 1)));
+        assert!(totals.contains_key(&([200; 16], 1)));
+    }
+
+    #[test]
+    fn rejects_sequence_numbers_out_of_range() {
+        let ilp_address = Address::from_str("example.destination").unwrap();
+        let connection_generator = ConnectionGenerator::new(Bytes::from(&[1; 32][..]));
+        let (destination_account, shared_secret) =
+            connection_generator.generate_address_and_secret(&ilp_address);
+
+        for &(sequence, in_range) in &[
+            (0, false),
+            (1, true),
+            (MAX_SEQUENCE, true),
+            (MAX_SEQUENCE + 1, false),
+        ] {
+            let data = StreamPacketBuilder {
+                ilp_packet_type: IlpPacketType::Prepare,
+                prepare_amount: 0,
+                sequence,
+                frames: &[Frame::StreamMoney(StreamMoneyFrame {
+                    stream_id: 1,
+                    shares: 1,
+                })],
+            }
+            .build()
+            .into_encrypted(&shared_secret[..]);
+            let prepare = PrepareBuilder {
+                destination: destination_account.clone(),
+                amount: 100,
+                expires_at: UNIX_EPOCH,
+                data: &data[..],
+                execution_condition: &generate_condition(&shared_secret[..], &data),
+            }
+            .build();
+            let result = receive_money(
+                &shared_secret,
+                &ilp_address,
+                "ABC",
+                9,
+                &prepare,
+                None,
+                &[],
+                &ReceivedSequences::default(),
+                &ReceiveLimits::default(),
+            );
+            assert_eq!(result.is_ok(), in_range, "sequence {}", sequence);
+        }
+    }
+
     #[test]
     fn rejects_modified_data() {
         let ilp_address = Address::from_str("example.destination").unwrap();
@@ -503,7 +1805,17 @@ mod receiving_money {
         .build();
 
         let shared_secret = connection_generator.rederive_secret(&prepare.destination());
-        let result = receive_money(&shared_secret, &ilp_address, "ABC", 9, &prepare);
+        let result = receive_money(
+            &shared_secret,
+            &ilp_address,
+            "ABC",
+            9,
+            &prepare,
+            None,
+            &[],
+            &ReceivedSequences::default(),
+            &ReceiveLimits::default(),
+        );
         assert!(result.is_err());
     }
 
@@ -539,10 +1851,268 @@ mod receiving_money {
         .build();
 
         let shared_secret = connection_generator.rederive_secret(&prepare.destination());
-        let result = receive_money(&shared_secret, &ilp_address, "ABC", 9, &prepare);
+        let result = receive_money(
+            &shared_secret,
+            &ilp_address,
+            "ABC",
+            9,
+            &prepare,
+            None,
+            &[],
+            &ReceivedSequences::default(),
+            &ReceiveLimits::default(),
+        );
         assert!(result.is_err());
     }
 
+    #[test]
+    fn returns_connection_close_on_fulfilled_packet() {
+        let ilp_address = Address::from_str("example.destination").unwrap();
+        let connection_generator = ConnectionGenerator::new(Bytes::from(&[1; 32][..]));
+        let (destination_account, shared_secret) =
+            connection_generator.generate_address_and_secret(&ilp_address);
+
+        let data = StreamPacketBuilder {
+            ilp_packet_type: IlpPacketType::Prepare,
+            prepare_amount: 0,
+            sequence: 3,
+            frames: &[
+                Frame::StreamMoney(StreamMoneyFrame {
+                    stream_id: 1,
+                    shares: 1,
+                }),
+                Frame::StreamClose(StreamCloseFrame {
+                    stream_id: 1,
+                    code: StreamErrorCode::NoError,
+                    message: "",
+                }),
+                Frame::ConnectionClose(ConnectionCloseFrame {
+                    code: StreamErrorCode::NoError,
+                    message: "",
+                }),
+            ],
+        }
+        .build()
+        .into_encrypted(&shared_secret[..]);
+        let prepare = PrepareBuilder {
+            destination: destination_account,
+            amount: 100,
+            expires_at: UNIX_EPOCH,
+            data: &data[..],
+            execution_condition: &generate_condition(&shared_secret[..], &data),
+        }
+        .build();
+
+        let result = receive_money(
+            &shared_secret,
+            &ilp_address,
+            "ABC",
+            9,
+            &prepare,
+            None,
+            &[],
+            &ReceivedSequences::default(),
+            &ReceiveLimits::default(),
+        );
+        let ok = result.unwrap();
+        assert_eq!(ok.sequence, 3);
+        assert_eq!(
+            ok.connection_close,
+            Some((StreamErrorCode::NoError, String::new()))
+        );
+    }
+
+    #[test]
+    fn records_the_senders_asset_details_within_bounds() {
+        let ilp_address = Address::from_str("example.destination").unwrap();
+        let connection_generator = ConnectionGenerator::new(Bytes::from(&[1; 32][..]));
+        let (destination_account, shared_secret) =
+            connection_generator.generate_address_and_secret(&ilp_address);
+
+        let receive_with_scale = |source_asset_scale| {
+            let data = StreamPacketBuilder {
+                ilp_packet_type: IlpPacketType::Prepare,
+                prepare_amount: 0,
+                sequence: 1,
+                frames: &[
+                    Frame::ConnectionNewAddress(ConnectionNewAddressFrame {
+                        source_account: Address::from_str("example.sender").unwrap(),
+                    }),
+                    Frame::ConnectionAssetDetails(ConnectionAssetDetailsFrame {
+                        source_asset_code: "XYZ",
+                        source_asset_scale,
+                    }),
+                ],
+            }
+            .build()
+            .into_encrypted(&shared_secret[..]);
+            let prepare = PrepareBuilder {
+                destination: destination_account.clone(),
+                amount: 100,
+                expires_at: UNIX_EPOCH,
+                data: &data[..],
+                execution_condition: &generate_condition(&shared_secret[..], &data),
+            }
+            .build();
+            let ok = receive_money(
+                &shared_secret,
+                &ilp_address,
+                "ABC",
+                6,
+                &prepare,
+                None,
+                &[],
+                &ReceivedSequences::default(),
+                &ReceiveLimits::default(),
+            )
+            .unwrap();
+
+            // Our own details go back to the sender either way
+            let reply =
+                StreamPacket::from_encrypted(&shared_secret[..], BytesMut::from(ok.fulfill.data()))
+                    .unwrap();
+            assert!(reply.frames().any(|frame| frame
+                == Frame::ConnectionAssetDetails(ConnectionAssetDetailsFrame {
+                    source_asset_code: "ABC",
+                    source_asset_scale: 6,
+                })));
+            ok.remote_asset
+        };
+
+        assert_eq!(
+            receive_with_scale(18),
+            Some(AssetDetails {
+                asset_code: "XYZ".to_string(),
+                asset_scale: 18,
+            })
+        );
+        assert_eq!(receive_with_scale(19), None);
+    }
+
+    #[test]
+    fn returns_connection_close_with_application_error() {
+        let ilp_address = Address::from_str("example.destination").unwrap();
+        let connection_generator = ConnectionGenerator::new(Bytes::from(&[1; 32][..]));
+        let (destination_account, shared_secret) =
+            connection_generator.generate_address_and_secret(&ilp_address);
+
+        // Close packets are sent with an unfulfillable condition
+        let data = StreamPacketBuilder {
+            ilp_packet_type: IlpPacketType::Prepare,
+            prepare_amount: 0,
+            sequence: 2,
+            frames: &[Frame::ConnectionClose(ConnectionCloseFrame {
+                code: StreamErrorCode::ApplicationError,
+                message: "Out of stock",
+            })],
+        }
+        .build()
+        .into_encrypted(&shared_secret[..]);
+        let prepare = PrepareBuilder {
+            destination: destination_account,
+            amount: 0,
+            expires_at: UNIX_EPOCH,
+            data: &data[..],
+            execution_condition: &[0; 32],
+        }
+        .build();
+
+        let result = receive_money(
+            &shared_secret,
+            &ilp_address,
+            "ABC",
+            9,
+            &prepare,
+            None,
+            &[],
+            &ReceivedSequences::default(),
+            &ReceiveLimits::default(),
+        );
+        match result {
+            Err(ReceiveErr::Rejection(rejection)) => {
+                assert_eq!(rejection.sequence, 2);
+                assert_eq!(
+                    rejection.connection_close,
+                    Some((
+                        StreamErrorCode::ApplicationError,
+                        "Out of stock".to_string()
+                    ))
+                );
+            }
+            other => panic!("Expected a rejection, got {:?}", other.map(|ok| ok.fulfill)),
+        }
+    }
+
+    #[test]
+    fn rejects_data_after_stream_close() {
+        let ilp_address = Address::from_str("example.destination").unwrap();
+        let connection_generator = ConnectionGenerator::new(Bytes::from(&[1; 32][..]));
+        let (destination_account, shared_secret) =
+            connection_generator.generate_address_and_secret(&ilp_address);
+        let stream = DataStream::new(1);
+
+        let receive = |sequence: u64, frames: &[Frame]| {
+            let data = StreamPacketBuilder {
+                ilp_packet_type: IlpPacketType::Prepare,
+                prepare_amount: 0,
+                sequence,
+                frames,
+            }
+            .build()
+            .into_encrypted(&shared_secret[..]);
+            let prepare = PrepareBuilder {
+                destination: destination_account.clone(),
+                amount: 0,
+                expires_at: UNIX_EPOCH,
+                data: &data[..],
+                execution_condition: &generate_condition(&shared_secret[..], &data),
+            }
+            .build();
+            receive_money(
+                &shared_secret,
+                &ilp_address,
+                "ABC",
+                9,
+                &prepare,
+                None,
+                std::slice::from_ref(&stream),
+                &ReceivedSequences::default(),
+                &ReceiveLimits::default(),
+            )
+        };
+
+        let result = receive(
+            1,
+            &[
+                Frame::StreamData(StreamDataFrame {
+                    stream_id: 1,
+                    offset: 0,
+                    data: b"hello",
+                }),
+                Frame::StreamClose(StreamCloseFrame {
+                    stream_id: 1,
+                    code: StreamErrorCode::NoError,
+                    message: "",
+                }),
+            ],
+        );
+        assert!(result.is_ok());
+        assert_eq!(
+            stream.remote_close(),
+            Some((StreamErrorCode::NoError, String::new()))
+        );
+
+        let result = receive(
+            2,
+            &[Frame::StreamData(StreamDataFrame {
+                stream_id: 1,
+                offset: 5,
+                data: b"more",
+            })],
+        );
+        assert!(matches!(result, Err(ReceiveErr::Rejection(_))));
+    }
+
     #[test]
     fn fulfills_packets_sent_to_javascript_receiver() {
         // This was created by the JS ilp-protocol-stream library
@@ -557,9 +2127,19 @@ mod receiving_money {
             &hex!("b7d09d2e16e6f83c55b60e42fcd7c2b8ed49624a1df73c59b383dbe2e8690309")[..],
             "did not regenerate the same shared secret",
         );
-        let fulfill = receive_money(&shared_secret, &ilp_address, "ABC", 9, &prepare)
-            .expect("Receiver should be able to generate the fulfillment")
-            .fulfill;
+        let fulfill = receive_money(
+            &shared_secret,
+            &ilp_address,
+            "ABC",
+            9,
+            &prepare,
+            None,
+            &[],
+            &ReceivedSequences::default(),
+            &ReceiveLimits::default(),
+        )
+        .expect("Receiver should be able to generate the fulfillment")
+        .fulfill;
         assert_eq!(
             &hash_sha256(fulfill.fulfillment())[..],
             &condition[..],
@@ -752,4 +2332,132 @@ mod stream_receiver_service {
             Address::from_str("example.other-receiver").unwrap(),
         );
     }
+
+    #[tokio::test]
+    async fn remembers_asset_details_from_fulfilled_packets_only() {
+        let ilp_address = Address::from_str("example.destination").unwrap();
+        let server_secret = Bytes::from(&[1; 32][..]);
+        let connection_generator = ConnectionGenerator::new(server_secret.clone());
+        let (destination_account, shared_secret) =
+            connection_generator.generate_address_and_secret(&ilp_address);
+        let mut service = StreamReceiverService::new(
+            server_secret,
+            DummyStore,
+            outgoing_service_fn(|_: OutgoingRequest<TestAccount>| -> IlpResult {
+                panic!("shouldn't get here")
+            }),
+        );
+        let account = TestAccount {
+            id: Uuid::new_v4(),
+            ilp_address: ilp_address.clone(),
+            asset_code: "XYZ".to_string(),
+            asset_scale: 9,
+            max_packet_amount: None,
+        };
+
+        for (sequence, fulfillable) in [(1, false), (2, true)] {
+            let data = StreamPacketBuilder {
+                ilp_packet_type: IlpPacketType::Prepare,
+                prepare_amount: 0,
+                sequence,
+                frames: &[Frame::ConnectionAssetDetails(ConnectionAssetDetailsFrame {
+                    source_asset_code: "ABC",
+                    source_asset_scale: 6,
+                })],
+            }
+            .build()
+            .into_encrypted(&shared_secret[..]);
+            let execution_condition = if fulfillable {
+                generate_condition(&shared_secret[..], &data)
+            } else {
+                random_condition()
+            };
+            let prepare = PrepareBuilder {
+                destination: destination_account.clone(),
+                amount: 100,
+                expires_at: UNIX_EPOCH,
+                data: &data[..],
+                execution_condition: &execution_condition,
+            }
+            .build();
+            let result = service
+                .send_request(OutgoingRequest {
+                    from: account.clone(),
+                    to: account.clone(),
+                    original_amount: prepare.amount(),
+                    prepare,
+                })
+                .await;
+            assert_eq!(result.is_ok(), fulfillable);
+            assert_eq!(
+                service.remote_asset_details(&destination_account).is_some(),
+                fulfillable
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn forgets_receipt_totals_when_the_connection_closes() {
+        let ilp_address = Address::from_str("example.destination").unwrap();
+        let server_secret = Bytes::from(&[1; 32][..]);
+        let connection_generator = ConnectionGenerator::new(server_secret.clone());
+        let (destination_account, shared_secret) = connection_generator
+            .generate_address_and_secret_with_receipts(&ilp_address, [3; 16], [4; 32]);
+        let mut service = StreamReceiverService::new(
+            server_secret,
+            DummyStore,
+            outgoing_service_fn(|_: OutgoingRequest<TestAccount>| -> IlpResult {
+                panic!("shouldn't get here")
+            }),
+        );
+        let account = TestAccount {
+            id: Uuid::new_v4(),
+            ilp_address: ilp_address.clone(),
+            asset_code: "XYZ".to_string(),
+            asset_scale: 9,
+            max_packet_amount: None,
+        };
+
+        for (sequence, close) in [(1, false), (2, true)] {
+            let money = Frame::StreamMoney(StreamMoneyFrame {
+                stream_id: 1,
+                shares: 1,
+            });
+            let connection_close = Frame::ConnectionClose(ConnectionCloseFrame {
+                code: StreamErrorCode::NoError,
+                message: "",
+            });
+            let frames = if close {
+                vec![money, connection_close]
+            } else {
+                vec![money]
+            };
+            let data = StreamPacketBuilder {
+                ilp_packet_type: IlpPacketType::Prepare,
+                prepare_amount: 0,
+                sequence,
+                frames: &frames,
+            }
+            .build()
+            .into_encrypted(&shared_secret[..]);
+            let prepare = PrepareBuilder {
+                destination: destination_account.clone(),
+                amount: 100,
+                expires_at: UNIX_EPOCH,
+                data: &data[..],
+                execution_condition: &generate_condition(&shared_secret[..], &data),
+            }
+            .build();
+            let result = service
+                .send_request(OutgoingRequest {
+                    from: account.clone(),
+                    to: account.clone(),
+                    original_amount: prepare.amount(),
+                    prepare,
+                })
+                .await;
+            assert!(result.is_ok());
+            assert_eq!(service.receipt_totals.0.lock().is_empty(), close);
+        }
+    }
 }